@@ -14,11 +14,29 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::node::messages::Message;
+use crate::config::{Config, ConfigReload};
+use crate::node::audit::AuditReport;
+use crate::node::block_found::BlockFoundHandler;
+use crate::node::diagnostics::Diagnostics;
+use crate::node::events::SequencedEvent;
+use crate::node::health::HealthStatus;
+use crate::node::messages::{InventoryMessage, Message};
+use crate::node::metrics::MetricsSnapshot;
+use crate::node::peer_stats::{NetworkQuality, PeerBreakdown, PeerInfo};
+use crate::node::share_subscriptions::ShareFilter;
+use crate::shares::add_share::AddShareOutcome;
+use crate::shares::chain::dag::DagSnapshot;
+use crate::shares::chain::payout::{PayoutProof, PayoutReport};
+use crate::shares::chain::snapshot::SnapshotError;
+use crate::shares::chain::{ChainStats, PurgeReport, Reorg, ShareStatus};
 use crate::shares::miner_message::MinerWorkbase;
-use crate::shares::ShareBlock;
+use crate::shares::store::{ShareProvenance, StoreBenchmark, WorkbaseOutcome, WorkbaseRange};
+use crate::shares::{ShareBlock, ShareBlockHash};
 use std::error::Error;
-use tokio::sync::oneshot;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
 
 /// Commands for communication between node handle and actor
 /// We allow large enum variants because we want to avoid heap allocations for these frequently used messages
@@ -40,16 +58,137 @@ pub enum Command {
     ),
     /// Command to get a list of connected peers
     GetPeers(oneshot::Sender<Vec<libp2p::PeerId>>),
+    /// Command to get the stats and supported protocols of a connected peer
+    GetPeerInfo(libp2p::PeerId, oneshot::Sender<Option<PeerInfo>>),
+    /// Command to get the most recent inventory a connected peer sent us
+    GetPeerInventory(libp2p::PeerId, oneshot::Sender<Option<InventoryMessage>>),
+    /// Command to get a summary of peer latencies and dial failures
+    GetNetworkQuality(oneshot::Sender<NetworkQuality>),
+    /// Command to count connected peers by origin: dial_peers, mdns, the DHT, inbound or relay
+    GetPeerBreakdown(oneshot::Sender<PeerBreakdown>),
+    /// Command to set the priority of a peer, higher priority peers are preferred for sync and kept in the gossip mesh
+    SetPeerPriority(libp2p::PeerId, u8, oneshot::Sender<()>),
+    /// Command to disconnect a peer and refuse its connections until it is unbanned, false if already banned
+    BanPeer(libp2p::PeerId, oneshot::Sender<bool>),
+    /// Command to accept connections from a banned peer again, false if it was not banned
+    UnbanPeer(libp2p::PeerId, oneshot::Sender<bool>),
+    /// Command to get whether the node keeps up with its callers
+    GetHealth(oneshot::Sender<HealthStatus>),
+    /// Command to get the time since the chain last accepted a share, None if it hasn't accepted one yet
+    TimeSinceLastShare(oneshot::Sender<Option<Duration>>),
+    /// Command to get a copy of the node's metrics, including gossip propagation latency
+    GetMetrics(oneshot::Sender<MetricsSnapshot>),
+    /// Command to get the node's metrics, peer count and chain height and work in the Prometheus text format
+    GetPrometheusMetrics(oneshot::Sender<String>),
+    /// Command to look up the peers closest to a target in the DHT
+    FindClosestPeers(libp2p::PeerId, oneshot::Sender<Vec<libp2p::PeerId>>),
+    /// Command to bootstrap the DHT, re-dialing the dial peers if the bootstrap can't start
+    BootstrapKademlia(oneshot::Sender<()>),
+    /// Command to publish a signed announcement to the pool
+    PublishAnnouncement(
+        String,
+        Vec<u8>,
+        oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    ),
+    /// Command to subscribe to events published by the node
+    SubscribeEvents(oneshot::Sender<broadcast::Receiver<SequencedEvent>>),
+    /// Command to subscribe to the reorgs the main chain goes through, without the other node events
+    SubscribeReorgs(oneshot::Sender<broadcast::Receiver<Reorg>>),
+    /// Command to register a handler called with every share that solves a bitcoin block
+    RegisterBlockFoundHandler(Arc<dyn BlockFoundHandler>, oneshot::Sender<()>),
+    /// Command to subscribe to accepted shares matching a filter
+    SubscribeShares(ShareFilter, oneshot::Sender<mpsc::Receiver<ShareBlock>>),
+    /// Command to get the config the node is running with, after reloads, with secrets redacted
+    GetEffectiveConfig(oneshot::Sender<Config>),
+    /// Command to apply the hot reloadable fields of a newly loaded config
+    ReloadConfig(Config, oneshot::Sender<ConfigReload>),
+    /// Command to start reindexing the store, fails if a reindex is already running
+    StartReindex(oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>),
+    /// Command to cancel the running reindex, responds with false if none was running
+    CancelReindex(oneshot::Sender<bool>),
+    /// Command to reconnect orphan shares whose parent has since been stored, responds with how many were connected
+    DrainOrphans(oneshot::Sender<usize>),
+    /// Command to get the ancestors missing to connect an orphan share, to request them from peers
+    GetMissingAncestors(ShareBlockHash, oneshot::Sender<Vec<ShareBlockHash>>),
+    /// Command to snapshot peers, chain stats, orphans, sync sessions, banned peers and metrics in one call
+    Diagnostics(oneshot::Sender<Diagnostics>),
+    /// Command to validate every stored share against the current rules, responds with the shares that fail
+    AuditChain(oneshot::Sender<AuditReport>),
     /// Command to shutdown node
     Shutdown(oneshot::Sender<()>),
-    /// Command to add share to the chain
-    AddShare(
-        ShareBlock,
+    /// Command to validate and add a locally produced share to the chain
+    AddShare(ShareBlock, oneshot::Sender<AddShareOutcome>),
+    /// Command to add a locally produced share, skipping gossip when the flag is set.
+    /// Used to benchmark store writes and validation without broadcasting synthetic shares.
+    AddShareLocal(ShareBlock, bool, oneshot::Sender<AddShareOutcome>),
+    /// Command to add a batch of locally produced shares, with an outcome for each share in input order
+    AddShareBatch(Vec<ShareBlock>, oneshot::Sender<Vec<AddShareOutcome>>),
+    /// Command to get the blockhashes of all shares attributed to a miner payout address
+    GetSharesByMiner(bitcoin::Address, oneshot::Sender<Vec<ShareBlockHash>>),
+    /// Command to get where a share first reached this node, local or the peer that delivered it
+    GetShareProvenance(ShareBlockHash, oneshot::Sender<Option<ShareProvenance>>),
+    /// Command to get where a share stands in the chain, for learning the outcome of a share after submitting it
+    GetShareStatus(ShareBlockHash, oneshot::Sender<ShareStatus>),
+    /// Command to get the chain tip and whether it has been stable long enough to pay out on
+    GetChainStats(oneshot::Sender<Result<ChainStats, Box<dyn Error + Send + Sync>>>),
+    /// Command to get the shares at the most recent heights and their parent and uncle links
+    GetDagSnapshot(u32, oneshot::Sender<DagSnapshot>),
+    /// Command to get the path between two shares through their closest common ancestor, None if they have none
+    GetPath(
+        ShareBlockHash,
+        ShareBlockHash,
+        oneshot::Sender<Option<Vec<ShareBlockHash>>>,
+    ),
+    /// Command to get the proof of which shares the reward for a block solved by a share is split over
+    GetInclusionProof(ShareBlockHash, oneshot::Sender<Option<PayoutProof>>),
+    /// Command to check an inclusion proof's signature and that its shares match the ones in our store
+    VerifyInclusionProof(PayoutProof, oneshot::Sender<Result<(), String>>),
+    /// Command to split a block reward in satoshis between the miners of a window of the most recent main chain shares
+    ComputePayouts(usize, u64, oneshot::Sender<PayoutReport>),
+    /// Command to estimate the pool hashrate in hashes per second from the shares found in a recent window
+    EstimateHashrate(Duration, oneshot::Sender<f64>),
+    /// Command to count the shares found in each of a number of the most recent time buckets of a length,
+    /// responds with the start of each bucket in seconds since epoch and its count, oldest first
+    GetShareHistogram(Duration, usize, oneshot::Sender<Vec<(u64, u64)>>),
+    /// Command to replace the chain with the chain snapshot in a file, if it has more work
+    LoadSnapshot(PathBuf, oneshot::Sender<Result<(), SnapshotError>>),
+    /// Command to flush, close and reopen the store while the swarm keeps running
+    ReopenStore(oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>),
+    /// Command to forcibly set the chain tip to a stored leaf share, refused unless unsafe operations are allowed
+    SetTip(
+        ShareBlockHash,
         oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
     ),
-    /// Command to store workbase in the node's database
+    /// Command to compact the whole store, responds with its disk usage in bytes before and after
+    CompactStore(oneshot::Sender<Result<(u64, u64), Box<dyn Error + Send + Sync>>>),
+    /// Command to write and read back a number of synthetic entries through the store, responds with the
+    /// throughput and latency percentiles
+    BenchmarkStore(
+        usize,
+        oneshot::Sender<Result<StoreBenchmark, Box<dyn Error + Send + Sync>>>,
+    ),
+    /// Command to delete the shares a peer delivered, keeping and reporting the ones the chain depends on
+    PurgePeerShares(
+        libp2p::PeerId,
+        oneshot::Sender<Result<PurgeReport, Box<dyn Error + Send + Sync>>>,
+    ),
+    /// Command to list stored workbases in a height or time range, skipping an offset and returning at most a limit
+    ListWorkbases(
+        WorkbaseRange,
+        usize,
+        usize,
+        oneshot::Sender<Vec<MinerWorkbase>>,
+    ),
+    /// Command to replace the log filter, an invalid filter is rejected and the current one kept
+    SetLogLevel(
+        String,
+        oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+    ),
+    /// Command to get the log filter currently applied, None if logging was set up without a reload handle
+    GetLogLevel(oneshot::Sender<Option<String>>),
+    /// Command to store workbase in the node's database, responds with whether it was new or already stored
     StoreWorkbase(
         MinerWorkbase,
-        oneshot::Sender<Result<(), Box<dyn Error + Send + Sync>>>,
+        oneshot::Sender<Result<WorkbaseOutcome, Box<dyn Error + Send + Sync>>>,
     ),
 }
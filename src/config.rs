@@ -14,39 +14,247 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::node::gossip_conformance::GossipAnomalyAction;
+use crate::shares::chain::payout::PayoutPolicy;
+use crate::shares::chain::Checkpoint;
 use bitcoin::PublicKey;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
 use serde::Deserialize;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::Path;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct NetworkConfig {
+    /// Multiaddr to listen on, e.g. /ip4/0.0.0.0/tcp/6884
+    /// A wildcard address, /ip4/0.0.0.0 or /ip6/::, listens on the wildcard of every enabled address family.
     pub listen_address: String,
+    /// Listen on IPv4 when listen_address is a wildcard
+    pub enable_ipv4: bool,
+    /// Listen on IPv6 when listen_address is a wildcard
+    pub enable_ipv6: bool,
+    /// Multiaddrs of peers to dial on startup
     pub dial_peers: Vec<String>,
+    /// Discover peers on the local network using mdns
     pub enable_mdns: bool,
+    /// Fail startup instead of warning when dial_peers is empty and mdns is disabled, leaving the node
+    /// no way to find peers on its own
+    pub require_discovery: bool,
     pub max_pending_incoming: u32,
     pub max_pending_outgoing: u32,
     pub max_established_incoming: u32,
     pub max_established_outgoing: u32,
     pub max_established_per_peer: u32,
+    /// Disable Nagle's algorithm on peer connections, so small share messages are sent without delay
+    pub tcp_nodelay: bool,
+    /// Incoming connections queued by the OS on each listening socket before they are accepted
+    pub tcp_listen_backlog: u32,
     pub max_workbase_per_second: u32,
     pub max_userworkbase_per_second: u32,
     pub max_miningshare_per_second: u32,
     pub max_inventory_per_second: u32,
     pub max_transaction_per_second: u32,
     pub rate_limit_window_secs: u64,
+    /// Peers with average ping round trip time above this are reported as slow
+    pub latency_threshold_ms: u64,
+    /// Warn when our clock is off from the median peer clock by more than this, 0 disables the warning
+    pub max_clock_skew_ms: u64,
+    /// Publish locally added shares to the share topic once they are accepted
+    pub auto_gossip: bool,
+    /// Sync, serve and relay the chain without producing shares: shares from ckpool are not received
+    /// and shares added through the node handle are rejected
+    pub observer: bool,
+    /// Stop the node if its event loop doesn't go round for this long, 0 disables the watchdog.
+    /// The loop's timers make it go round every second when idle, so this should be a few seconds at least.
+    pub watchdog_timeout_secs: u64,
+    /// Command responses the caller stopped waiting for that are tolerated in a minute, more and the node warns
+    /// it is overloaded and reports degraded health. 0 never reports degraded health
+    pub max_dropped_responses_per_minute: u32,
+    /// Connected peers needed before the node reports itself healthy and ready for traffic, 0 requires none
+    pub min_healthy_peers: u32,
+    /// Report the node's health as stale once no share has been accepted for this long, a sign miners
+    /// disconnected or the node is partitioned. 0 never reports it stale
+    pub max_share_gap_secs: u64,
+    /// Re-dial dial_peers and re-bootstrap kademlia after no peer is connected for this long, counting from startup
+    /// until the first peer connects. 0 disables recovery
+    pub isolation_grace_period_secs: u64,
+    /// Buffer share gossip that fails to publish for this long after startup, publishing it once a peer
+    /// joins the share topic, so a fresh node doesn't lose its first shares. 0 disables buffering
+    pub gossip_startup_buffer_secs: u64,
+    /// Disconnect peers that send nothing but pings and pongs for this long, freeing their connection slot.
+    /// Peers we connected to from dial_peers are never disconnected. 0 disables eviction
+    pub peer_idle_evict_secs: u64,
+    /// Peers we sync shares from at the same time, other peers with more work wait for a session to end
+    pub max_sync_sessions: u32,
+    /// Listen and external addresses advertised to peers in identify, public addresses first, then relay
+    /// addresses, then the rest. 0 advertises none, peers only learn the address they connected to
+    pub max_advertised_addresses: u32,
+    /// Gossiped shares validated at the same time, each on its own task. Shares are still added to the
+    /// chain one at a time, in the order they arrived
+    pub share_validation_concurrency: u32,
+    /// Give up checking the proof of work of a gossiped share after this many milliseconds, rejecting it and
+    /// disconnecting the peer that sent it, so a pathological share can't hold back the shares behind it.
+    /// Adding a share to the chain is given as long, without blaming the peer. 0 disables the timeout
+    pub message_processing_timeout: u64,
+    /// Drop gossiped shares building on a share more than this many shares behind our chain tip
+    pub max_gossip_lag: u32,
+    /// Don't sync chain pages or request shares an inventory advertises more than this many shares below our chain
+    /// tip, bounding the work a peer serving a backlog of ancient shares can make us do. 0 syncs every share
+    pub sync_min_height_offset: u32,
+    /// Operator public keys whose signed announcements we accept
+    pub trusted_operator_keys: Vec<PublicKey>,
+    /// Stamp gossiped local shares with their origin time and record propagation latency of received ones.
+    /// All nodes on the network need this enabled, as nodes without it can't decode timed shares.
+    pub measure_propagation_latency: bool,
+    /// Requests from a single peer handled at once, further requests get a Busy response until one finishes
+    pub max_inflight_requests_per_peer: u32,
+    /// Round trip representative messages through serialization on startup, failing startup on a mismatch
+    pub serialization_self_test: bool,
+    /// Enable dangerous operator commands meant for testing and emergency recovery, like forcibly setting the chain tip
+    pub allow_unsafe_ops: bool,
+    /// Agent version advertised to peers in identify, to tell deployments apart (defaults to DEFAULT_AGENT_VERSION)
+    #[serde(default = "default_agent_version")]
+    pub agent_version: String,
+    /// Peer ids we connect with, including peers discovered with mdns. Empty allows every peer.
+    pub allowed_peers: Vec<String>,
+    /// What to do with gossip forwarded on a topic the peer is not subscribed to or the pool doesn't use:
+    /// "log", "drop" the message or "disconnect" and ban the peer
+    pub gossip_anomaly_action: GossipAnomalyAction,
+    /// Drop our own gossip messages when peers echo them back, instead of handling them again.
+    /// Echoes are never counted as received gossip or as activity of the peer that echoed them
+    pub drop_gossip_echoes: bool,
+    /// Seed for all of the node's internal randomness, including its peer id, so multi node tests are reproducible.
+    /// Unset in production, where randomness comes from OS entropy
+    #[serde(default)]
+    pub test_seed: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Identify agent version advertised when none is configured, the crate name and version
+pub const DEFAULT_AGENT_VERSION: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+fn default_agent_version() -> String {
+    DEFAULT_AGENT_VERSION.to_string()
+}
+
+impl NetworkConfig {
+    /// The addresses to listen on. A wildcard listen address is replaced by the wildcard of each
+    /// enabled address family, keeping the rest of the address. Other addresses are used as given.
+    pub fn listen_multiaddrs(&self) -> Result<Vec<Multiaddr>, libp2p::multiaddr::Error> {
+        let addr: Multiaddr = self.listen_address.parse()?;
+        let mut protocols = addr.iter();
+        let is_wildcard = match protocols.next() {
+            Some(Protocol::Ip4(ip)) => ip.is_unspecified(),
+            Some(Protocol::Ip6(ip)) => ip.is_unspecified(),
+            _ => false,
+        };
+        if !is_wildcard {
+            return Ok(vec![addr]);
+        }
+        let rest: Vec<Protocol> = protocols.collect();
+        let mut addrs = Vec::new();
+        if self.enable_ipv4 {
+            addrs.push(
+                std::iter::once(Protocol::Ip4(Ipv4Addr::UNSPECIFIED))
+                    .chain(rest.iter().cloned())
+                    .collect(),
+            );
+        }
+        if self.enable_ipv6 {
+            addrs.push(
+                std::iter::once(Protocol::Ip6(Ipv6Addr::UNSPECIFIED))
+                    .chain(rest.iter().cloned())
+                    .collect(),
+            );
+        }
+        Ok(addrs)
+    }
+
+    /// Whether the node can find peers on its own, from dial_peers or mdns. Kademlia bootstraps from
+    /// dial_peers, so without either the node only ever connects to peers that dial it.
+    pub fn has_discovery_path(&self) -> bool {
+        !self.dial_peers.is_empty() || self.enable_mdns
+    }
+}
+
+/// Gossipsub settings for publishing to peers outside our mesh.
+/// Publishing to more peers propagates shares faster, at the cost of sending each share more often.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct GossipsubConfig {
+    /// Publish our own messages to every peer subscribed to the topic, not only our mesh peers.
+    /// Speeds up propagation of our shares a lot in small networks, but each share we publish is sent
+    /// once per subscribed peer, so bandwidth grows with the number of peers.
+    pub flood_publish: bool,
+    /// How long we keep the fanout peers for a topic we publish to without being subscribed, must be at least 1
+    pub fanout_ttl_secs: u64,
+}
+
+/// Retry schedule shared by everything that retries a failing connection, e.g. recovering from
+/// isolation, re-dialing after a failed bootstrap, retrying a sync or reconnecting to ckpool.
+/// See utils::backoff::Backoff.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct BackoffConfig {
+    /// Delay before the first retry, must be at least 1
+    pub base_millis: u64,
+    /// Longest delay between retries, must be at least base_millis
+    pub cap_millis: u64,
+    /// Factor the delay grows by after each retry, must be at least 1
+    pub multiplier: f64,
+    /// Each delay is shortened by a random fraction of up to this much of itself, between 0 and 1
+    pub jitter: f64,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct StoreConfig {
+    /// Path of the RocksDB directory, its parent directory must exist
     pub path: String,
+    /// Prune the oldest shares once the store uses more than this many bytes on disk, 0 disables pruning
+    pub max_disk_bytes: u64,
+    /// Pruning stops once the store uses less than this many bytes, must be below max_disk_bytes
+    pub prune_low_water_bytes: u64,
+    /// How often the store's disk usage is checked when pruning is enabled
+    pub prune_check_interval_secs: u64,
+    /// Compact the whole store this often, e.g. 86400 for nightly. 0 disables scheduled compaction
+    pub compaction_interval_secs: u64,
+    /// Evict the oldest workbases once more than this many are stored, keeping the ones stored shares were
+    /// mined on. 0 keeps every workbase
+    pub max_workbases: usize,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ChainConfig {
+    /// Number of side branch tips tracked besides the main chain tip, the lowest work branches are dropped first
+    pub max_side_branches: usize,
+    /// How shares in the payout window are weighted, "pplns" or "equal"
+    pub payout_policy: PayoutPolicy,
+    /// Number of main chain shares, counting back from the tip, that share a reward
+    pub payout_window: usize,
+    /// Maximum number of main chain shares a reorg may replace, deeper reorgs are refused. 0 for no limit
+    pub max_reorg_depth: usize,
+    /// Seconds over which reorgs are counted to decide if the chain tip is stable enough to pay out on
+    pub stable_tip_window_secs: u64,
+    /// Maximum number of reorgs in the stability window for the chain tip to be stable
+    pub stable_tip_max_reorgs: usize,
+    /// Seconds the chain tip must have stayed unchanged for it to be stable, 0 doesn't wait
+    pub stable_tip_min_unchanged_secs: u64,
+    /// Number of previous shares whose timestamps set the expected difficulty of a new share.
+    /// Shares that don't match the expected difficulty are rejected. 0 disables retargeting
+    pub retarget_window: usize,
+    /// Seconds between shares the retargeted difficulty aims for
+    pub target_share_interval_secs: u64,
+    /// Shares the chain must pass through at given heights. Conflicting shares are rejected
+    /// and reorgs can't rewrite history below the highest checkpoint reached
+    #[serde(default)]
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct CkPoolConfig {
     pub host: String,
     pub port: u16,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct MinerConfig {
     pub pubkey: PublicKey,
 }
@@ -61,7 +269,7 @@ where
     bitcoin::Network::from_core_arg(&s).map_err(serde::de::Error::custom)
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct BitcoinConfig {
     #[serde(deserialize_with = "deserialize_network")]
@@ -71,7 +279,7 @@ pub struct BitcoinConfig {
     pub password: String,
 }
 
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
 pub struct LoggingConfig {
     /// Log to file if specified
     pub file: Option<String>,
@@ -91,11 +299,54 @@ fn default_log_level() -> String {
     "info".to_string()
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Placeholder for secret values in a redacted config
+pub const REDACTED: &str = "<redacted>";
+
+/// A single problem found while validating a config
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ConfigProblem {
+    #[error("network.listen_address {0} is not a valid multiaddr")]
+    InvalidListenAddress(String),
+    #[error("network.listen_address {0} is for an address family that is not enabled")]
+    ListenFamilyDisabled(String),
+    #[error("network.enable_ipv4 and network.enable_ipv6 can't both be false")]
+    NoAddressFamily,
+    #[error("network.dial_peers entry {0} is not a valid multiaddr")]
+    InvalidDialPeer(String),
+    #[error("network.allowed_peers entry {0} is not a valid peer id")]
+    InvalidAllowedPeer(String),
+    #[error("store.path {0} is in a directory that does not exist")]
+    MissingStoreParent(String),
+    #[error("{0}")]
+    InconsistentLimits(String),
+}
+
+/// Error returned when a config file can't be loaded or is invalid
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to load config: {0}")]
+    Load(#[from] config::ConfigError),
+    #[error("Invalid config: {}", .0.iter().map(|p| p.to_string()).collect::<Vec<_>>().join("; "))]
+    Invalid(Vec<ConfigProblem>),
+}
+
+/// Outcome of reloading the config, naming the fields that changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigReload {
+    /// Changed fields that are now in effect
+    pub applied: Vec<&'static str>,
+    /// Changed fields that keep their current value until the node is restarted
+    pub requires_restart: Vec<&'static str>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct Config {
     pub network: NetworkConfig,
+    pub gossipsub: GossipsubConfig,
+    pub backoff: BackoffConfig,
     pub store: StoreConfig,
+    pub chain: ChainConfig,
     pub ckpool: CkPoolConfig,
     pub miner: MinerConfig,
     pub bitcoin: BitcoinConfig,
@@ -112,11 +363,262 @@ impl Config {
             .try_deserialize()
     }
 
+    /// Load config from a TOML file and validate it, reporting every problem found
+    /// Environment variables with the P2POOL prefix override values from the file, as with load.
+    pub fn from_toml_path(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let config: Self = config::Config::builder()
+            .add_source(config::File::from(path.as_ref()).format(config::FileFormat::Toml))
+            .add_source(config::Environment::with_prefix("P2POOL").separator("_"))
+            .build()?
+            .try_deserialize()?;
+        config.validate().map_err(ConfigError::Invalid)?;
+        Ok(config)
+    }
+
+    /// Check the values that deserialize fine but would stop the node from working
+    pub fn validate(&self) -> Result<(), Vec<ConfigProblem>> {
+        let mut problems = Vec::new();
+        let network = &self.network;
+
+        match network.listen_address.parse::<Multiaddr>() {
+            Ok(addr) => {
+                let family_enabled = match addr.iter().next() {
+                    Some(Protocol::Ip4(ip)) => ip.is_unspecified() || network.enable_ipv4,
+                    Some(Protocol::Ip6(ip)) => ip.is_unspecified() || network.enable_ipv6,
+                    _ => true,
+                };
+                if !family_enabled {
+                    problems.push(ConfigProblem::ListenFamilyDisabled(
+                        network.listen_address.clone(),
+                    ));
+                }
+            }
+            Err(_) => problems.push(ConfigProblem::InvalidListenAddress(
+                network.listen_address.clone(),
+            )),
+        }
+        if !network.enable_ipv4 && !network.enable_ipv6 {
+            problems.push(ConfigProblem::NoAddressFamily);
+        }
+        for peer in &network.dial_peers {
+            if peer.parse::<Multiaddr>().is_err() {
+                problems.push(ConfigProblem::InvalidDialPeer(peer.clone()));
+            }
+        }
+        for peer in &network.allowed_peers {
+            if peer.parse::<PeerId>().is_err() {
+                problems.push(ConfigProblem::InvalidAllowedPeer(peer.clone()));
+            }
+        }
+
+        // A bare file name is created in the working directory, which always exists
+        if let Some(parent) = Path::new(&self.store.path).parent() {
+            if !parent.as_os_str().is_empty() && !parent.is_dir() {
+                problems.push(ConfigProblem::MissingStoreParent(self.store.path.clone()));
+            }
+        }
+
+        if network.max_established_per_peer == 0 {
+            problems.push(ConfigProblem::InconsistentLimits(
+                "network.max_established_per_peer must be at least 1".to_string(),
+            ));
+        }
+        if network.max_established_per_peer
+            > network.max_established_incoming + network.max_established_outgoing
+        {
+            problems.push(ConfigProblem::InconsistentLimits(format!(
+                "network.max_established_per_peer {} is more than the total established connections allowed {}",
+                network.max_established_per_peer,
+                network.max_established_incoming + network.max_established_outgoing
+            )));
+        }
+        if network.rate_limit_window_secs == 0 {
+            problems.push(ConfigProblem::InconsistentLimits(
+                "network.rate_limit_window_secs must be at least 1".to_string(),
+            ));
+        }
+        if network.max_sync_sessions == 0 {
+            problems.push(ConfigProblem::InconsistentLimits(
+                "network.max_sync_sessions must be at least 1".to_string(),
+            ));
+        }
+        if network.share_validation_concurrency == 0 {
+            problems.push(ConfigProblem::InconsistentLimits(
+                "network.share_validation_concurrency must be at least 1".to_string(),
+            ));
+        }
+        if network.max_inflight_requests_per_peer == 0 {
+            problems.push(ConfigProblem::InconsistentLimits(
+                "network.max_inflight_requests_per_peer must be at least 1".to_string(),
+            ));
+        }
+        if self.gossipsub.fanout_ttl_secs == 0 {
+            problems.push(ConfigProblem::InconsistentLimits(
+                "gossipsub.fanout_ttl_secs must be at least 1".to_string(),
+            ));
+        }
+        let backoff = &self.backoff;
+        if backoff.base_millis == 0 {
+            problems.push(ConfigProblem::InconsistentLimits(
+                "backoff.base_millis must be at least 1".to_string(),
+            ));
+        }
+        if backoff.cap_millis < backoff.base_millis {
+            problems.push(ConfigProblem::InconsistentLimits(format!(
+                "backoff.cap_millis {} must be at least backoff.base_millis {}",
+                backoff.cap_millis, backoff.base_millis
+            )));
+        }
+        if !(1.0..).contains(&backoff.multiplier) {
+            problems.push(ConfigProblem::InconsistentLimits(format!(
+                "backoff.multiplier {} must be at least 1",
+                backoff.multiplier
+            )));
+        }
+        if !(0.0..=1.0).contains(&backoff.jitter) {
+            problems.push(ConfigProblem::InconsistentLimits(format!(
+                "backoff.jitter {} must be between 0 and 1",
+                backoff.jitter
+            )));
+        }
+        let store = &self.store;
+        if store.max_disk_bytes > 0 {
+            if store.prune_low_water_bytes >= store.max_disk_bytes {
+                problems.push(ConfigProblem::InconsistentLimits(format!(
+                    "store.prune_low_water_bytes {} must be less than store.max_disk_bytes {}",
+                    store.prune_low_water_bytes, store.max_disk_bytes
+                )));
+            }
+            if store.prune_check_interval_secs == 0 {
+                problems.push(ConfigProblem::InconsistentLimits(
+                    "store.prune_check_interval_secs must be at least 1 when pruning is enabled"
+                        .to_string(),
+                ));
+            }
+        }
+        let chain = &self.chain;
+        if chain.retarget_window > 0 && chain.target_share_interval_secs == 0 {
+            problems.push(ConfigProblem::InconsistentLimits(
+                "chain.target_share_interval_secs must be at least 1 when retargeting is enabled"
+                    .to_string(),
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Apply the fields of a newly loaded config that are safe to change while the node runs.
+    /// Fields used to build the swarm, the store or the logger at startup are reported as requiring a restart.
+    pub fn reload(&mut self, new: &Config) -> ConfigReload {
+        let mut reload = ConfigReload::default();
+
+        macro_rules! hot {
+            ($($field:ident),*) => {$(
+                if self.network.$field != new.network.$field {
+                    self.network.$field = new.network.$field.clone();
+                    reload.applied.push(concat!("network.", stringify!($field)));
+                }
+            )*};
+        }
+        macro_rules! cold {
+            ($($field:ident).+) => {
+                if self.$($field).+ != new.$($field).+ {
+                    reload.requires_restart.push(stringify!($($field).+));
+                }
+            };
+        }
+
+        hot!(
+            dial_peers,
+            max_workbase_per_second,
+            max_userworkbase_per_second,
+            max_miningshare_per_second,
+            max_inventory_per_second,
+            max_transaction_per_second,
+            latency_threshold_ms,
+            max_dropped_responses_per_minute,
+            min_healthy_peers,
+            max_share_gap_secs,
+            max_clock_skew_ms,
+            isolation_grace_period_secs,
+            peer_idle_evict_secs,
+            auto_gossip,
+            max_gossip_lag,
+            sync_min_height_offset,
+            trusted_operator_keys,
+            measure_propagation_latency,
+            max_inflight_requests_per_peer,
+            allowed_peers,
+            gossip_anomaly_action,
+            drop_gossip_echoes
+        );
+        cold!(network.listen_address);
+        cold!(network.enable_ipv4);
+        cold!(network.enable_ipv6);
+        cold!(network.enable_mdns);
+        cold!(network.require_discovery);
+        cold!(network.max_pending_incoming);
+        cold!(network.max_pending_outgoing);
+        cold!(network.max_established_incoming);
+        cold!(network.max_established_outgoing);
+        cold!(network.max_established_per_peer);
+        cold!(network.tcp_nodelay);
+        cold!(network.tcp_listen_backlog);
+        cold!(network.rate_limit_window_secs);
+        cold!(network.watchdog_timeout_secs);
+        cold!(network.max_sync_sessions);
+        cold!(network.max_advertised_addresses);
+        cold!(network.share_validation_concurrency);
+        cold!(network.message_processing_timeout);
+        cold!(network.serialization_self_test);
+        cold!(network.allow_unsafe_ops);
+        cold!(network.gossip_startup_buffer_secs);
+        cold!(network.agent_version);
+        cold!(network.observer);
+        cold!(network.test_seed);
+        cold!(gossipsub);
+        cold!(backoff);
+        cold!(store);
+        cold!(chain);
+        cold!(ckpool);
+        cold!(miner);
+        cold!(bitcoin);
+        // The log level is applied through the logger's reload handle, the outputs are set up once at startup
+        if self.logging.level != new.logging.level {
+            self.logging.level = new.logging.level.clone();
+            reload.applied.push("logging.level");
+        }
+        cold!(logging.file);
+        cold!(logging.console);
+        reload
+    }
+
+    /// A copy of the config that is safe to show, with secrets replaced by REDACTED
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        config.bitcoin.password = REDACTED.to_string();
+        config
+    }
+
     pub fn with_listen_address(mut self, listen_address: String) -> Self {
         self.network.listen_address = listen_address;
         self
     }
 
+    pub fn with_enable_ipv4(mut self, enable_ipv4: bool) -> Self {
+        self.network.enable_ipv4 = enable_ipv4;
+        self
+    }
+
+    pub fn with_enable_ipv6(mut self, enable_ipv6: bool) -> Self {
+        self.network.enable_ipv6 = enable_ipv6;
+        self
+    }
+
     pub fn with_dial_peers(mut self, dial_peers: Vec<String>) -> Self {
         self.network.dial_peers = dial_peers;
         self
@@ -152,11 +654,248 @@ impl Config {
         self
     }
 
+    pub fn with_require_discovery(mut self, require_discovery: bool) -> Self {
+        self.network.require_discovery = require_discovery;
+        self
+    }
+
+    pub fn with_tcp_nodelay(mut self, tcp_nodelay: bool) -> Self {
+        self.network.tcp_nodelay = tcp_nodelay;
+        self
+    }
+
+    pub fn with_tcp_listen_backlog(mut self, tcp_listen_backlog: u32) -> Self {
+        self.network.tcp_listen_backlog = tcp_listen_backlog;
+        self
+    }
+
+    pub fn with_max_clock_skew_ms(mut self, max_clock_skew_ms: u64) -> Self {
+        self.network.max_clock_skew_ms = max_clock_skew_ms;
+        self
+    }
+
+    pub fn with_auto_gossip(mut self, auto_gossip: bool) -> Self {
+        self.network.auto_gossip = auto_gossip;
+        self
+    }
+
+    pub fn with_observer(mut self, observer: bool) -> Self {
+        self.network.observer = observer;
+        self
+    }
+
+    pub fn with_watchdog_timeout_secs(mut self, watchdog_timeout_secs: u64) -> Self {
+        self.network.watchdog_timeout_secs = watchdog_timeout_secs;
+        self
+    }
+
+    pub fn with_max_dropped_responses_per_minute(
+        mut self,
+        max_dropped_responses_per_minute: u32,
+    ) -> Self {
+        self.network.max_dropped_responses_per_minute = max_dropped_responses_per_minute;
+        self
+    }
+
+    pub fn with_min_healthy_peers(mut self, min_healthy_peers: u32) -> Self {
+        self.network.min_healthy_peers = min_healthy_peers;
+        self
+    }
+
+    pub fn with_max_share_gap_secs(mut self, max_share_gap_secs: u64) -> Self {
+        self.network.max_share_gap_secs = max_share_gap_secs;
+        self
+    }
+
+    pub fn with_max_sync_sessions(mut self, max_sync_sessions: u32) -> Self {
+        self.network.max_sync_sessions = max_sync_sessions;
+        self
+    }
+
+    pub fn with_share_validation_concurrency(mut self, share_validation_concurrency: u32) -> Self {
+        self.network.share_validation_concurrency = share_validation_concurrency;
+        self
+    }
+
+    pub fn with_message_processing_timeout(mut self, message_processing_timeout: u64) -> Self {
+        self.network.message_processing_timeout = message_processing_timeout;
+        self
+    }
+
+    pub fn with_max_advertised_addresses(mut self, max_advertised_addresses: u32) -> Self {
+        self.network.max_advertised_addresses = max_advertised_addresses;
+        self
+    }
+
+    pub fn with_isolation_grace_period_secs(mut self, isolation_grace_period_secs: u64) -> Self {
+        self.network.isolation_grace_period_secs = isolation_grace_period_secs;
+        self
+    }
+
+    pub fn with_peer_idle_evict_secs(mut self, peer_idle_evict_secs: u64) -> Self {
+        self.network.peer_idle_evict_secs = peer_idle_evict_secs;
+        self
+    }
+
+    pub fn with_gossip_startup_buffer_secs(mut self, gossip_startup_buffer_secs: u64) -> Self {
+        self.network.gossip_startup_buffer_secs = gossip_startup_buffer_secs;
+        self
+    }
+
+    pub fn with_max_gossip_lag(mut self, max_gossip_lag: u32) -> Self {
+        self.network.max_gossip_lag = max_gossip_lag;
+        self
+    }
+
+    pub fn with_sync_min_height_offset(mut self, sync_min_height_offset: u32) -> Self {
+        self.network.sync_min_height_offset = sync_min_height_offset;
+        self
+    }
+
+    pub fn with_trusted_operator_keys(mut self, trusted_operator_keys: Vec<PublicKey>) -> Self {
+        self.network.trusted_operator_keys = trusted_operator_keys;
+        self
+    }
+
+    pub fn with_agent_version(mut self, agent_version: String) -> Self {
+        self.network.agent_version = agent_version;
+        self
+    }
+
+    pub fn with_test_seed(mut self, test_seed: u64) -> Self {
+        self.network.test_seed = Some(test_seed);
+        self
+    }
+
+    pub fn with_allowed_peers(mut self, allowed_peers: Vec<String>) -> Self {
+        self.network.allowed_peers = allowed_peers;
+        self
+    }
+
+    pub fn with_measure_propagation_latency(mut self, measure_propagation_latency: bool) -> Self {
+        self.network.measure_propagation_latency = measure_propagation_latency;
+        self
+    }
+
+    pub fn with_max_inflight_requests_per_peer(
+        mut self,
+        max_inflight_requests_per_peer: u32,
+    ) -> Self {
+        self.network.max_inflight_requests_per_peer = max_inflight_requests_per_peer;
+        self
+    }
+
+    pub fn with_serialization_self_test(mut self, serialization_self_test: bool) -> Self {
+        self.network.serialization_self_test = serialization_self_test;
+        self
+    }
+
+    pub fn with_allow_unsafe_ops(mut self, allow_unsafe_ops: bool) -> Self {
+        self.network.allow_unsafe_ops = allow_unsafe_ops;
+        self
+    }
+
+    pub fn with_gossip_anomaly_action(
+        mut self,
+        gossip_anomaly_action: GossipAnomalyAction,
+    ) -> Self {
+        self.network.gossip_anomaly_action = gossip_anomaly_action;
+        self
+    }
+
+    pub fn with_drop_gossip_echoes(mut self, drop_gossip_echoes: bool) -> Self {
+        self.network.drop_gossip_echoes = drop_gossip_echoes;
+        self
+    }
+
+    pub fn with_gossipsub_flood_publish(mut self, flood_publish: bool) -> Self {
+        self.gossipsub.flood_publish = flood_publish;
+        self
+    }
+
+    pub fn with_gossipsub_fanout_ttl_secs(mut self, fanout_ttl_secs: u64) -> Self {
+        self.gossipsub.fanout_ttl_secs = fanout_ttl_secs;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: BackoffConfig) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
     pub fn with_store_path(mut self, store_path: String) -> Self {
         self.store.path = store_path;
         self
     }
 
+    pub fn with_max_disk_bytes(mut self, max_disk_bytes: u64) -> Self {
+        self.store.max_disk_bytes = max_disk_bytes;
+        self
+    }
+
+    pub fn with_prune_low_water_bytes(mut self, prune_low_water_bytes: u64) -> Self {
+        self.store.prune_low_water_bytes = prune_low_water_bytes;
+        self
+    }
+
+    pub fn with_prune_check_interval_secs(mut self, prune_check_interval_secs: u64) -> Self {
+        self.store.prune_check_interval_secs = prune_check_interval_secs;
+        self
+    }
+
+    pub fn with_compaction_interval_secs(mut self, compaction_interval_secs: u64) -> Self {
+        self.store.compaction_interval_secs = compaction_interval_secs;
+        self
+    }
+
+    pub fn with_max_workbases(mut self, max_workbases: usize) -> Self {
+        self.store.max_workbases = max_workbases;
+        self
+    }
+
+    pub fn with_max_side_branches(mut self, max_side_branches: usize) -> Self {
+        self.chain.max_side_branches = max_side_branches;
+        self
+    }
+
+    pub fn with_max_reorg_depth(mut self, max_reorg_depth: usize) -> Self {
+        self.chain.max_reorg_depth = max_reorg_depth;
+        self
+    }
+
+    pub fn with_stable_tip(
+        mut self,
+        window_secs: u64,
+        max_reorgs: usize,
+        min_unchanged_secs: u64,
+    ) -> Self {
+        self.chain.stable_tip_window_secs = window_secs;
+        self.chain.stable_tip_max_reorgs = max_reorgs;
+        self.chain.stable_tip_min_unchanged_secs = min_unchanged_secs;
+        self
+    }
+
+    pub fn with_checkpoints(mut self, checkpoints: Vec<Checkpoint>) -> Self {
+        self.chain.checkpoints = checkpoints;
+        self
+    }
+
+    pub fn with_payout_policy(mut self, payout_policy: PayoutPolicy) -> Self {
+        self.chain.payout_policy = payout_policy;
+        self
+    }
+
+    pub fn with_payout_window(mut self, payout_window: usize) -> Self {
+        self.chain.payout_window = payout_window;
+        self
+    }
+
+    pub fn with_retargeting(mut self, window: usize, target_share_interval_secs: u64) -> Self {
+        self.chain.retarget_window = window;
+        self.chain.target_share_interval_secs = target_share_interval_secs;
+        self
+    }
+
     pub fn with_ckpool_host(mut self, ckpool_host: String) -> Self {
         self.ckpool.host = ckpool_host;
         self
@@ -194,6 +933,11 @@ mod tests {
 
     #[test]
     fn test_config_builder() {
+        let allowed_peer = PeerId::random().to_string();
+        let checkpoint = Checkpoint {
+            height: 10,
+            blockhash: "0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5".into(),
+        };
         let config = Config::load("./config.toml").unwrap();
         let config = config
             .with_listen_address("127.0.0.1:8080".to_string())
@@ -202,12 +946,60 @@ mod tests {
                 "peer2.example.com".to_string(),
             ])
             .with_enable_mdns(true)
+            .with_require_discovery(true)
             .with_max_pending_incoming(10)
             .with_max_pending_outgoing(10)
             .with_max_established_incoming(50)
             .with_max_established_outgoing(50)
             .with_max_established_per_peer(1)
+            .with_tcp_nodelay(true)
+            .with_tcp_listen_backlog(256)
+            .with_max_clock_skew_ms(5_000)
+            .with_auto_gossip(true)
+            .with_observer(true)
+            .with_watchdog_timeout_secs(300)
+            .with_max_dropped_responses_per_minute(5)
+            .with_min_healthy_peers(3)
+            .with_max_share_gap_secs(900)
+            .with_isolation_grace_period_secs(45)
+            .with_gossip_startup_buffer_secs(20)
+            .with_peer_idle_evict_secs(900)
+            .with_max_sync_sessions(3)
+            .with_max_advertised_addresses(5)
+            .with_share_validation_concurrency(2)
+            .with_message_processing_timeout(2_000)
+            .with_max_gossip_lag(20)
+            .with_sync_min_height_offset(500)
+            .with_measure_propagation_latency(true)
+            .with_max_inflight_requests_per_peer(2)
+            .with_serialization_self_test(false)
+            .with_allow_unsafe_ops(true)
+            .with_agent_version("p2poolv2/eu-west".to_string())
+            .with_test_seed(7)
+            .with_allowed_peers(vec![allowed_peer.clone()])
+            .with_gossip_anomaly_action(GossipAnomalyAction::Disconnect)
+            .with_drop_gossip_echoes(false)
+            .with_gossipsub_flood_publish(false)
+            .with_gossipsub_fanout_ttl_secs(120)
+            .with_backoff(BackoffConfig {
+                base_millis: 500,
+                cap_millis: 30_000,
+                multiplier: 1.5,
+                jitter: 0.25,
+            })
             .with_store_path("/tmp/store".to_string())
+            .with_max_disk_bytes(1_000_000)
+            .with_prune_low_water_bytes(800_000)
+            .with_prune_check_interval_secs(30)
+            .with_compaction_interval_secs(3600)
+            .with_max_workbases(500)
+            .with_max_side_branches(8)
+            .with_max_reorg_depth(50)
+            .with_stable_tip(300, 2, 30)
+            .with_checkpoints(vec![checkpoint.clone()])
+            .with_payout_policy(PayoutPolicy::Equal)
+            .with_payout_window(500)
+            .with_retargeting(20, 15)
             .with_ckpool_host("ckpool.example.com".to_string())
             .with_ckpool_port(3333)
             .with_miner_pubkey(
@@ -222,7 +1014,37 @@ mod tests {
             config.network.dial_peers,
             vec!["peer1.example.com", "peer2.example.com"]
         );
+        assert_eq!(config.network.agent_version, "p2poolv2/eu-west");
+        assert_eq!(config.network.test_seed, Some(7));
+        assert_eq!(config.network.allowed_peers, vec![allowed_peer]);
+        assert_eq!(
+            config.network.gossip_anomaly_action,
+            GossipAnomalyAction::Disconnect
+        );
+        assert!(config.network.require_discovery);
+        assert!(!config.network.drop_gossip_echoes);
+        assert!(!config.gossipsub.flood_publish);
+        assert_eq!(config.gossipsub.fanout_ttl_secs, 120);
+        assert_eq!(config.backoff.base_millis, 500);
+        assert_eq!(config.backoff.cap_millis, 30_000);
+        assert_eq!(config.backoff.multiplier, 1.5);
+        assert_eq!(config.backoff.jitter, 0.25);
         assert_eq!(config.store.path, "/tmp/store");
+        assert_eq!(config.store.max_disk_bytes, 1_000_000);
+        assert_eq!(config.store.prune_low_water_bytes, 800_000);
+        assert_eq!(config.store.prune_check_interval_secs, 30);
+        assert_eq!(config.store.compaction_interval_secs, 3600);
+        assert_eq!(config.store.max_workbases, 500);
+        assert_eq!(config.chain.max_side_branches, 8);
+        assert_eq!(config.chain.max_reorg_depth, 50);
+        assert_eq!(config.chain.stable_tip_window_secs, 300);
+        assert_eq!(config.chain.stable_tip_max_reorgs, 2);
+        assert_eq!(config.chain.stable_tip_min_unchanged_secs, 30);
+        assert_eq!(config.chain.checkpoints, vec![checkpoint]);
+        assert_eq!(config.chain.payout_policy, PayoutPolicy::Equal);
+        assert_eq!(config.chain.payout_window, 500);
+        assert_eq!(config.chain.retarget_window, 20);
+        assert_eq!(config.chain.target_share_interval_secs, 15);
         assert_eq!(config.ckpool.host, "ckpool.example.com");
         assert_eq!(config.ckpool.port, 3333);
         assert_eq!(
@@ -238,6 +1060,329 @@ mod tests {
         assert_eq!(config.network.max_established_incoming, 50);
         assert_eq!(config.network.max_established_outgoing, 50);
         assert_eq!(config.network.max_established_per_peer, 1);
+        assert!(config.network.tcp_nodelay);
+        assert_eq!(config.network.tcp_listen_backlog, 256);
+        assert_eq!(config.network.max_clock_skew_ms, 5_000);
+        assert!(config.network.auto_gossip);
+        assert!(config.network.observer);
+        assert_eq!(config.network.watchdog_timeout_secs, 300);
+        assert_eq!(config.network.max_dropped_responses_per_minute, 5);
+        assert_eq!(config.network.min_healthy_peers, 3);
+        assert_eq!(config.network.max_share_gap_secs, 900);
+        assert_eq!(config.network.isolation_grace_period_secs, 45);
+        assert_eq!(config.network.gossip_startup_buffer_secs, 20);
+        assert_eq!(config.network.peer_idle_evict_secs, 900);
+        assert_eq!(config.network.max_sync_sessions, 3);
+        assert_eq!(config.network.max_advertised_addresses, 5);
+        assert_eq!(config.network.share_validation_concurrency, 2);
+        assert_eq!(config.network.message_processing_timeout, 2_000);
+        assert_eq!(config.network.max_gossip_lag, 20);
+        assert_eq!(config.network.sync_min_height_offset, 500);
+        assert!(config.network.measure_propagation_latency);
+        assert_eq!(config.network.max_inflight_requests_per_peer, 2);
+        assert!(!config.network.serialization_self_test);
+        assert!(config.network.allow_unsafe_ops);
+    }
+
+    /// Write the sample config to a temp dir with some lines replaced, returning the dir and file path
+    fn write_config(replacements: &[(&str, &str)]) -> (tempfile::TempDir, std::path::PathBuf) {
+        let mut contents = std::fs::read_to_string("./config.toml").unwrap();
+        for (from, to) in replacements {
+            assert!(contents.contains(from), "sample config has no {}", from);
+            contents = contents.replace(from, to);
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, contents).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn test_from_toml_path_valid_config() {
+        let (_dir, path) = write_config(&[]);
+        let config = Config::from_toml_path(&path).unwrap();
+        assert_eq!(config.network.listen_address, "/ip4/0.0.0.0/tcp/6884");
+        // The sample config doesn't set an agent version or test seed, so the crate version is advertised
+        // and randomness comes from OS entropy
+        assert_eq!(config.network.agent_version, DEFAULT_AGENT_VERSION);
+        assert_eq!(config.network.test_seed, None);
+    }
+
+    #[test]
+    fn test_from_toml_path_reports_every_problem() {
+        let (_dir, path) = write_config(&[
+            (
+                "listen_address = \"/ip4/0.0.0.0/tcp/6884\"",
+                "listen_address = \"0.0.0.0:6884\"",
+            ),
+            (
+                "dial_peers = []",
+                "dial_peers = [\"/ip4/127.0.0.1/tcp/6885\", \"not-an-address\"]",
+            ),
+            ("allowed_peers = []", "allowed_peers = [\"not-a-peer-id\"]"),
+            (
+                "path = \"./store.db\"",
+                "path = \"/nonexistent-p2pool-dir/store.db\"",
+            ),
+        ]);
+        match Config::from_toml_path(&path) {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(
+                problems,
+                vec![
+                    ConfigProblem::InvalidListenAddress("0.0.0.0:6884".to_string()),
+                    ConfigProblem::InvalidDialPeer("not-an-address".to_string()),
+                    ConfigProblem::InvalidAllowedPeer("not-a-peer-id".to_string()),
+                    ConfigProblem::MissingStoreParent(
+                        "/nonexistent-p2pool-dir/store.db".to_string()
+                    ),
+                ]
+            ),
+            other => panic!("Expected invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_toml_path_inconsistent_limits() {
+        let (_dir, path) = write_config(&[
+            (
+                "max_established_incoming = 50",
+                "max_established_incoming = 1",
+            ),
+            (
+                "max_established_outgoing = 50",
+                "max_established_outgoing = 1",
+            ),
+            (
+                "max_established_per_peer = 1",
+                "max_established_per_peer = 3",
+            ),
+            ("rate_limit_window_secs = 1", "rate_limit_window_secs = 0"),
+            ("max_sync_sessions = 4", "max_sync_sessions = 0"),
+            (
+                "share_validation_concurrency = 4",
+                "share_validation_concurrency = 0",
+            ),
+            (
+                "max_inflight_requests_per_peer = 8",
+                "max_inflight_requests_per_peer = 0",
+            ),
+            ("fanout_ttl_secs = 60", "fanout_ttl_secs = 0"),
+            ("cap_millis = 60000", "cap_millis = 10"),
+            ("jitter = 0.5", "jitter = 1.5"),
+            ("max_disk_bytes = 0", "max_disk_bytes = 1000"),
+            ("prune_low_water_bytes = 0", "prune_low_water_bytes = 1000"),
+            (
+                "prune_check_interval_secs = 60",
+                "prune_check_interval_secs = 0",
+            ),
+            ("retarget_window = 0", "retarget_window = 10"),
+            (
+                "target_share_interval_secs = 10",
+                "target_share_interval_secs = 0",
+            ),
+        ]);
+        match Config::from_toml_path(&path) {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(
+                problems,
+                vec![
+                    ConfigProblem::InconsistentLimits(
+                        "network.max_established_per_peer 3 is more than the total established connections allowed 2".to_string()
+                    ),
+                    ConfigProblem::InconsistentLimits(
+                        "network.rate_limit_window_secs must be at least 1".to_string()
+                    ),
+                    ConfigProblem::InconsistentLimits(
+                        "network.max_sync_sessions must be at least 1".to_string()
+                    ),
+                    ConfigProblem::InconsistentLimits(
+                        "network.share_validation_concurrency must be at least 1".to_string()
+                    ),
+                    ConfigProblem::InconsistentLimits(
+                        "network.max_inflight_requests_per_peer must be at least 1".to_string()
+                    ),
+                    ConfigProblem::InconsistentLimits(
+                        "gossipsub.fanout_ttl_secs must be at least 1".to_string()
+                    ),
+                    ConfigProblem::InconsistentLimits(
+                        "backoff.cap_millis 10 must be at least backoff.base_millis 1000"
+                            .to_string()
+                    ),
+                    ConfigProblem::InconsistentLimits(
+                        "backoff.jitter 1.5 must be between 0 and 1".to_string()
+                    ),
+                    ConfigProblem::InconsistentLimits(
+                        "store.prune_low_water_bytes 1000 must be less than store.max_disk_bytes 1000".to_string()
+                    ),
+                    ConfigProblem::InconsistentLimits(
+                        "store.prune_check_interval_secs must be at least 1 when pruning is enabled".to_string()
+                    ),
+                    ConfigProblem::InconsistentLimits(
+                        "chain.target_share_interval_secs must be at least 1 when retargeting is enabled".to_string()
+                    ),
+                ]
+            ),
+            other => panic!("Expected invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_toml_path_zero_connections_per_peer() {
+        let (_dir, path) = write_config(&[(
+            "max_established_per_peer = 1",
+            "max_established_per_peer = 0",
+        )]);
+        let err = Config::from_toml_path(&path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid config: network.max_established_per_peer must be at least 1"
+        );
+    }
+
+    #[test]
+    fn test_listen_multiaddrs_expands_wildcard_for_enabled_families() {
+        let config = Config::load("./config.toml").unwrap();
+        let listen = |address: &str, ipv4: bool, ipv6: bool| {
+            config
+                .clone()
+                .with_listen_address(address.to_string())
+                .with_enable_ipv4(ipv4)
+                .with_enable_ipv6(ipv6)
+                .network
+                .listen_multiaddrs()
+                .unwrap()
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            listen("/ip4/0.0.0.0/tcp/6884", true, true),
+            vec!["/ip4/0.0.0.0/tcp/6884", "/ip6/::/tcp/6884"]
+        );
+        assert_eq!(
+            listen("/ip6/::/tcp/6884", true, false),
+            vec!["/ip4/0.0.0.0/tcp/6884"]
+        );
+        assert_eq!(
+            listen("/ip4/0.0.0.0/tcp/6884", false, true),
+            vec!["/ip6/::/tcp/6884"]
+        );
+        // Specific addresses are listened on as given
+        assert_eq!(
+            listen("/ip6/::1/tcp/6884", true, true),
+            vec!["/ip6/::1/tcp/6884"]
+        );
+    }
+
+    #[test]
+    fn test_has_discovery_path_needs_dial_peers_or_mdns() {
+        let config = Config::load("./config.toml").unwrap();
+        let isolated = config
+            .clone()
+            .with_dial_peers(vec![])
+            .with_enable_mdns(false);
+        assert!(!isolated.network.has_discovery_path());
+
+        assert!(isolated
+            .clone()
+            .with_enable_mdns(true)
+            .network
+            .has_discovery_path());
+        assert!(isolated
+            .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6884".to_string()])
+            .network
+            .has_discovery_path());
+    }
+
+    #[test]
+    fn test_validate_address_families() {
+        let config = Config::load("./config.toml").unwrap();
+
+        let no_family = config
+            .clone()
+            .with_enable_ipv4(false)
+            .with_enable_ipv6(false);
+        assert_eq!(
+            no_family.validate(),
+            Err(vec![ConfigProblem::NoAddressFamily])
+        );
+
+        let disabled_family = config
+            .with_listen_address("/ip6/::1/tcp/6884".to_string())
+            .with_enable_ipv6(false);
+        assert_eq!(
+            disabled_family.validate(),
+            Err(vec![ConfigProblem::ListenFamilyDisabled(
+                "/ip6/::1/tcp/6884".to_string()
+            )])
+        );
+    }
+
+    #[test]
+    fn test_from_toml_path_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = Config::from_toml_path(dir.path().join("missing.toml"));
+        assert!(matches!(result, Err(ConfigError::Load(_))));
+    }
+
+    #[test]
+    fn test_reload_applies_hot_fields_and_reports_restart_fields() {
+        let mut config = Config::load("./config.toml").unwrap();
+        let new = config
+            .clone()
+            .with_max_gossip_lag(3)
+            .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/7000".to_string()])
+            .with_listen_address("/ip4/0.0.0.0/tcp/7001".to_string())
+            .with_store_path("/tmp/other-store".to_string());
+
+        let reload = config.reload(&new);
+
+        assert_eq!(
+            reload.applied,
+            vec!["network.dial_peers", "network.max_gossip_lag"]
+        );
+        assert_eq!(
+            reload.requires_restart,
+            vec!["network.listen_address", "store"]
+        );
+        assert_eq!(config.network.max_gossip_lag, 3);
+        assert_eq!(config.network.dial_peers, vec!["/ip4/127.0.0.1/tcp/7000"]);
+        assert_eq!(config.network.listen_address, "/ip4/0.0.0.0/tcp/6884");
+        assert_eq!(config.store.path, "./store.db");
+    }
+
+    #[test]
+    fn test_reload_applies_log_level_and_reports_log_outputs() {
+        let mut config = Config::load("./config.toml").unwrap();
+        let mut new = config.clone();
+        new.logging.level = "info,p2poolv2=trace".to_string();
+        new.logging.file = Some("/tmp/p2pool.log".to_string());
+
+        let reload = config.reload(&new);
+
+        assert_eq!(reload.applied, vec!["logging.level"]);
+        assert_eq!(reload.requires_restart, vec!["logging.file"]);
+        assert_eq!(config.logging.level, "info,p2poolv2=trace");
+        assert_eq!(config.logging.file, None);
+    }
+
+    #[test]
+    fn test_reload_unchanged_config_is_empty() {
+        let mut config = Config::load("./config.toml").unwrap();
+        let new = config.clone();
+        assert_eq!(config.reload(&new), ConfigReload::default());
+    }
+
+    #[test]
+    fn test_redacted_config_hides_bitcoin_password() {
+        let config = Config::load("./config.toml")
+            .unwrap()
+            .with_bitcoin_password("hunter2".to_string())
+            .with_max_gossip_lag(7);
+        let redacted = config.redacted();
+        assert_eq!(redacted.bitcoin.password, REDACTED);
+        assert_eq!(redacted.bitcoin.username, config.bitcoin.username);
+        assert_eq!(redacted.network, config.network);
     }
 
     #[test]
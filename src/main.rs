@@ -14,10 +14,13 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::shares::genesis::GENESIS_PUBLIC_KEY;
 use crate::shares::ShareBlock;
+use crate::utils::log_level::LogLevelHandle;
 use clap::Parser;
 use std::error::Error;
 use std::fs::File;
+use tokio::signal::unix::{signal, SignalKind};
 use tracing::{debug, info};
 use tracing_subscriber::field::debug;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Registry};
@@ -52,15 +55,17 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
     // Load configuration
-    let config = config::Config::load(&args.config)?;
+    let config = config::Config::from_toml_path(&args.config)?;
 
     // Configure logging based on config
-    setup_logging(&config.logging)?;
+    let log_level = setup_logging(&config.logging)?;
 
-    let chain_handle = ChainHandle::new(config.store.path.clone());
-    let public_key = "02ac493f2130ca56cb5c3a559860cef9a84f90b5a85dfe4ec6e6067eeee17f4d2d"
-        .parse::<PublicKey>()
-        .unwrap();
+    let chain_handle = ChainHandle::new_with_config(
+        config.store.clone(),
+        config.chain.clone(),
+        config.bitcoin.network,
+    );
+    let public_key = GENESIS_PUBLIC_KEY.parse::<PublicKey>().unwrap();
     let genesis = ShareBlock::build_genesis_for_network(public_key, config.bitcoin.network);
     if chain_handle
         .get_share(genesis.cached_blockhash.unwrap())
@@ -72,8 +77,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
             std::process::exit(1);
         }
     }
-    if let Ok((_node_handle, stopping_rx)) = NodeHandle::new(config, chain_handle).await {
+    if let Ok((node_handle, stopping_rx)) =
+        NodeHandle::new_with_log_level(config, chain_handle, log_level).await
+    {
         info!("Node started");
+        reload_config_on_sighup(args.config, node_handle)?;
         stopping_rx.await?;
         info!("Node stopped");
     } else {
@@ -82,11 +90,42 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Reload the config file on SIGHUP, applying the values the node can change while running
+fn reload_config_on_sighup(
+    config_path: String,
+    node_handle: NodeHandle,
+) -> Result<(), Box<dyn Error>> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            let config = match config::Config::from_toml_path(&config_path) {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Failed to reload config from {}: {}", config_path, e);
+                    continue;
+                }
+            };
+            match node_handle.reload_config(config).await {
+                Ok(reload) => info!(
+                    "Config reloaded, applied {:?}, requires restart {:?}",
+                    reload.applied, reload.requires_restart
+                ),
+                Err(e) => error!("Failed to reload config: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
 /// Sets up logging according to the logging configuration
-fn setup_logging(logging_config: &config::LoggingConfig) -> Result<(), Box<dyn Error>> {
+/// Returns a handle to change the log filter while the node runs.
+fn setup_logging(logging_config: &config::LoggingConfig) -> Result<LogLevelHandle, Box<dyn Error>> {
     debug!("Setting up logging with config: {:?}", logging_config);
-    let filter =
-        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&logging_config.level));
+    let level = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(level) if EnvFilter::try_new(&level).is_ok() => level,
+        _ => logging_config.level.clone(),
+    };
+    let (filter, log_level) = LogLevelHandle::new(&level).map_err(|e| e.to_string())?;
 
     let registry = Registry::default().with(filter);
 
@@ -114,5 +153,5 @@ fn setup_logging(logging_config: &config::LoggingConfig) -> Result<(), Box<dyn E
     }
 
     info!("Logging initialized");
-    Ok(())
+    Ok(log_level)
 }
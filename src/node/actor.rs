@@ -15,16 +15,41 @@
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
 use crate::command::Command;
-use crate::config::Config;
-use crate::node::Node;
+use crate::config::{Config, ConfigReload};
+use crate::node::audit::{run_audit, AuditReport};
+use crate::node::block_found::BlockFoundHandler;
+use crate::node::diagnostics::Diagnostics;
+use crate::node::events::SequencedEvent;
+use crate::node::health::HealthStatus;
+use crate::node::messages::{InventoryMessage, Message};
+use crate::node::metrics::{MetricsSnapshot, NodeGauges};
+use crate::node::peer_stats::{NetworkQuality, PeerBreakdown, PeerInfo, PING_INTERVAL};
+use crate::node::share_subscriptions::ShareFilter;
+use crate::node::watchdog::{supervise, Watchdog};
 use crate::node::SwarmSend;
+use crate::node::{load_snapshot, Node, ISOLATION_CHECK_INTERVAL};
+use crate::shares::add_share::{
+    add_local_share_batch, add_local_share_with_gossip, AddShareError, AddShareOutcome,
+};
 #[mockall_double::double]
 use crate::shares::chain::actor::ChainHandle;
+use crate::shares::chain::dag::DagSnapshot;
+use crate::shares::chain::payout::{PayoutProof, PayoutReport};
+use crate::shares::chain::{ChainStats, PurgeReport, Reorg, ShareStatus};
 use crate::shares::miner_message::MinerWorkbase;
-use crate::shares::ShareBlock;
+use crate::shares::store::{ShareProvenance, StoreBenchmark, WorkbaseOutcome, WorkbaseRange};
+use crate::shares::{ShareBlock, ShareBlockHash};
+use crate::utils::clock::{Clock, SystemClock};
+use crate::utils::log_level::LogLevelHandle;
+use crate::utils::time_provider::TimeProvider;
+use futures::stream::{self, BoxStream};
 use libp2p::futures::StreamExt;
+use rust_decimal::prelude::ToPrimitive;
 use std::error::Error;
-use tokio::sync::{mpsc, oneshot};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tracing::{debug, error, info};
 
 /// NodeHandle provides an interface to interact with a Node running in a separate task
@@ -41,57 +66,785 @@ impl NodeHandle {
     pub async fn new(
         config: Config,
         chain_handle: ChainHandle,
+    ) -> Result<(Self, oneshot::Receiver<()>), Box<dyn Error + Send + Sync>> {
+        Self::start(config, chain_handle, None, Arc::new(SystemClock))
+    }
+
+    /// Create a new Node that can change the log filter at runtime through log_level
+    pub async fn new_with_log_level(
+        config: Config,
+        chain_handle: ChainHandle,
+        log_level: LogLevelHandle,
+    ) -> Result<(Self, oneshot::Receiver<()>), Box<dyn Error + Send + Sync>> {
+        Self::start(config, chain_handle, Some(log_level), Arc::new(SystemClock))
+    }
+
+    /// Create a new Node reading the time from clock, so tests can advance time with a MockClock
+    pub async fn new_with_clock(
+        config: Config,
+        chain_handle: ChainHandle,
+        clock: Arc<dyn Clock>,
+    ) -> Result<(Self, oneshot::Receiver<()>), Box<dyn Error + Send + Sync>> {
+        Self::start(config, chain_handle, None, clock)
+    }
+
+    fn start(
+        config: Config,
+        chain_handle: ChainHandle,
+        log_level: Option<LogLevelHandle>,
+        clock: Arc<dyn Clock>,
     ) -> Result<(Self, oneshot::Receiver<()>), Box<dyn Error + Send + Sync>> {
         let (command_tx, command_rx) = mpsc::channel::<Command>(32);
-        let (node_actor, stopping_rx) = NodeActor::new(config, chain_handle, command_rx).unwrap();
+        let node_actor = NodeActor::new(config, chain_handle, log_level, clock, command_rx)
+            .map_err(|e| e.to_string())?;
+
+        let (stopping_tx, stopping_rx) = oneshot::channel();
+        let watchdog = node_actor.watchdog.clone();
+        tokio::spawn(async move {
+            supervise(watchdog, node_actor.run()).await;
+            if stopping_tx.send(()).is_err() {
+                debug!("Node stopped with nobody waiting for it");
+            }
+        });
+        Ok((Self { command_tx }, stopping_rx))
+    }
+
+    /// Get a list of connected peers
+    pub async fn get_peers(&self) -> Result<Vec<libp2p::PeerId>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::GetPeers(tx)).await?;
+        match rx.await {
+            Ok(peers) => Ok(peers),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Send a request message directly to a connected peer
+    pub async fn send_to_peer(
+        &self,
+        peer_id: libp2p::PeerId,
+        message: Message,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::SendToPeer(peer_id, message, tx))
+            .await?;
+        rx.await?
+    }
+
+    /// Publish an announcement signed by an operator key to the pool
+    /// Receiving nodes only surface it if the signer is one of their trusted operator keys.
+    pub async fn publish_announcement(
+        &self,
+        payload: String,
+        signature: Vec<u8>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::PublishAnnouncement(payload, signature, tx))
+            .await?;
+        match rx.await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Subscribe to events published by the node, such as trusted announcements.
+    /// Events carry a sequence number increasing by one per event, a larger step means events were missed.
+    pub async fn subscribe_events(
+        &self,
+    ) -> Result<broadcast::Receiver<SequencedEvent>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::SubscribeEvents(tx)).await?;
+        match rx.await {
+            Ok(event_rx) => Ok(event_rx),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Subscribe to the reorgs the main chain goes through, with the old and new tip, the orphaned and promoted
+    /// shares and the miners whose credits change. The same reorgs are published to event subscribers as
+    /// NodeEvent::Reorg, this stream spares accounting services from filtering every other event.
+    pub async fn subscribe_reorgs(
+        &self,
+    ) -> Result<broadcast::Receiver<Reorg>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::SubscribeReorgs(tx)).await?;
+        match rx.await {
+            Ok(reorg_rx) => Ok(reorg_rx),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Register a handler called with the block candidate whenever a share added to the chain meets the
+    /// bitcoin network target, e.g. to submit the block to bitcoind. Found blocks are also published to
+    /// event subscribers as NodeEvent::BlockFound.
+    pub async fn register_block_found_handler(
+        &self,
+        handler: Arc<dyn BlockFoundHandler>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::RegisterBlockFoundHandler(handler, tx))
+            .await?;
+        match rx.await {
+            Ok(()) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Stream the shares accepted to the chain that match filter
+    /// Shares are filtered by the node before they are sent, so subscribers only interested in a
+    /// few miners don't receive every share.
+    pub async fn subscribe_shares(
+        &self,
+        filter: ShareFilter,
+    ) -> Result<BoxStream<'static, ShareBlock>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::SubscribeShares(filter, tx))
+            .await?;
+        match rx.await {
+            Ok(share_rx) => Ok(stream::unfold(share_rx, |mut share_rx| async move {
+                share_rx.recv().await.map(|share| (share, share_rx))
+            })
+            .boxed()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the stats and supported protocols of a connected peer, None if not connected
+    pub async fn get_peer_info(
+        &self,
+        peer_id: libp2p::PeerId,
+    ) -> Result<Option<PeerInfo>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::GetPeerInfo(peer_id, tx))
+            .await?;
+        match rx.await {
+            Ok(peer_info) => Ok(peer_info),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the most recent inventory a connected peer sent us, to see if it is behind or on a fork
+    pub async fn get_peer_inventory(
+        &self,
+        peer_id: libp2p::PeerId,
+    ) -> Result<Option<InventoryMessage>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::GetPeerInventory(peer_id, tx))
+            .await?;
+        match rx.await {
+            Ok(inventory) => Ok(inventory),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Apply the fields of a newly loaded config that can change without a restart.
+    /// Returns the changed fields that were applied and those that need a restart to take effect.
+    pub async fn reload_config(
+        &self,
+        config: Config,
+    ) -> Result<ConfigReload, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::ReloadConfig(config, tx))
+            .await?;
+        match rx.await {
+            Ok(reload) => Ok(reload),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Start reindexing the store in the background
+    /// Progress is published to event subscribers as NodeEvent::ReindexProgress.
+    pub async fn start_reindex(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::StartReindex(tx)).await?;
+        match rx.await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Cancel the running reindex, returns false if no reindex was running
+    pub async fn cancel_reindex(&self) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::CancelReindex(tx)).await?;
+        match rx.await {
+            Ok(cancelled) => Ok(cancelled),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Connect orphan shares whose parent has since been stored, returns how many were connected
+    pub async fn drain_orphans(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::DrainOrphans(tx)).await?;
+        match rx.await {
+            Ok(connected) => Ok(connected),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the ancestors missing to connect an orphan share, walking back through the orphans it builds on to
+    /// the deepest parent not in the store. Empty if the share is not an orphan or nothing is missing.
+    pub async fn get_missing_ancestors(
+        &self,
+        blockhash: ShareBlockHash,
+    ) -> Result<Vec<ShareBlockHash>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::GetMissingAncestors(blockhash, tx))
+            .await?;
+        match rx.await {
+            Ok(missing) => Ok(missing),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Snapshot peers, chain stats, orphans, sync sessions, banned peers and metrics in one call
+    pub async fn diagnostics(&self) -> Result<Diagnostics, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::Diagnostics(tx)).await?;
+        match rx.await {
+            Ok(diagnostics) => Ok(diagnostics),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Validate every stored share against the current validation rules and report the shares that fail.
+    /// The chain is not changed. Progress is published to event subscribers as NodeEvent::AuditProgress.
+    pub async fn audit_chain(&self) -> Result<AuditReport, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::AuditChain(tx)).await?;
+        match rx.await {
+            Ok(report) => Ok(report),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get a copy of the node's metrics
+    pub async fn get_metrics(&self) -> Result<MetricsSnapshot, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::GetMetrics(tx)).await?;
+        match rx.await {
+            Ok(metrics) => Ok(metrics),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the node's metrics with its peer count, chain height and chain work in the Prometheus
+    /// text exposition format, so they can be served from an HTTP endpoint as they are
+    pub async fn prometheus_metrics(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::GetPrometheusMetrics(tx))
+            .await?;
+        match rx.await {
+            Ok(text) => Ok(text),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the time since the chain last accepted a share, from a miner, a peer or sync.
+    /// None if no share has been accepted since the node started.
+    pub async fn time_since_last_share(
+        &self,
+    ) -> Result<Option<Duration>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::TimeSinceLastShare(tx))
+            .await?;
+        match rx.await {
+            Ok(elapsed) => Ok(elapsed),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get whether the node keeps up with its callers. The node is degraded while more command responses
+    /// than network.max_dropped_responses_per_minute were dropped in the last minute because callers stopped waiting,
+    /// stale once no share was accepted for network.max_share_gap_secs and under connected while fewer than
+    /// network.min_healthy_peers are connected.
+    pub async fn get_health(&self) -> Result<HealthStatus, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::GetHealth(tx)).await?;
+        match rx.await {
+            Ok(health) => Ok(health),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get a summary of peer ping round trip times and recent dial failures
+    pub async fn get_network_quality(
+        &self,
+    ) -> Result<NetworkQuality, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::GetNetworkQuality(tx)).await?;
+        match rx.await {
+            Ok(quality) => Ok(quality),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the number of connected peers by how we came to be connected to them
+    pub async fn get_peer_breakdown(&self) -> Result<PeerBreakdown, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::GetPeerBreakdown(tx)).await?;
+        match rx.await {
+            Ok(breakdown) => Ok(breakdown),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Set the priority of a peer, 0 by default. Higher priority peers are preferred as sync sources,
+    /// never evicted for being idle and the last pruned from the gossipsub mesh.
+    pub async fn set_peer_priority(
+        &self,
+        peer_id: libp2p::PeerId,
+        priority: u8,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::SetPeerPriority(peer_id, priority, tx))
+            .await?;
+        match rx.await {
+            Ok(()) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Disconnect a peer and refuse its connections until it is unbanned. Returns false if it was already banned.
+    pub async fn ban_peer(
+        &self,
+        peer_id: libp2p::PeerId,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::BanPeer(peer_id, tx)).await?;
+        match rx.await {
+            Ok(banned) => Ok(banned),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Accept connections from a banned peer again. Returns false if it was not banned.
+    pub async fn unban_peer(
+        &self,
+        peer_id: libp2p::PeerId,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::UnbanPeer(peer_id, tx))
+            .await?;
+        match rx.await {
+            Ok(unbanned) => Ok(unbanned),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Shutdown the node
+    pub async fn shutdown(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::Shutdown(tx)).await?;
+        match rx.await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Look up the peers closest to target in the DHT, for debugging peer discovery
+    pub async fn find_closest_peers(
+        &self,
+        target: libp2p::PeerId,
+    ) -> Result<Vec<libp2p::PeerId>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::FindClosestPeers(target, tx))
+            .await?;
+        match rx.await {
+            Ok(peers) => Ok(peers),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Bootstrap the DHT. With no peer in the routing table the dial peers are re-dialed instead,
+    /// published as a BootstrapFailed event.
+    pub async fn bootstrap_kademlia(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::BootstrapKademlia(tx)).await?;
+        match rx.await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Validate and add a locally produced share to the chain, returning what happened to it.
+    /// Observer nodes reject every local share.
+    pub async fn add_share(
+        &self,
+        share: ShareBlock,
+    ) -> Result<AddShareOutcome, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::AddShare(share, tx)).await?;
+        match rx.await {
+            Ok(outcome) => Ok(outcome),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Add a locally produced share, without gossiping it if suppress_gossip is set.
+    /// Lets benchmarks push synthetic shares through validation and the store without broadcasting them.
+    pub async fn add_share_local(
+        &self,
+        share: ShareBlock,
+        suppress_gossip: bool,
+    ) -> Result<AddShareOutcome, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::AddShareLocal(share, suppress_gossip, tx))
+            .await?;
+        match rx.await {
+            Ok(outcome) => Ok(outcome),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Add a batch of locally produced shares, returning the outcome for each share in input order
+    pub async fn add_share_batch(
+        &self,
+        shares: Vec<ShareBlock>,
+    ) -> Result<Vec<AddShareOutcome>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::AddShareBatch(shares, tx))
+            .await?;
+        match rx.await {
+            Ok(outcomes) => Ok(outcomes),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the blockhashes of all shares attributed to a miner payout address
+    pub async fn get_shares_by_miner(
+        &self,
+        address: bitcoin::Address,
+    ) -> Result<Vec<ShareBlockHash>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::GetSharesByMiner(address, tx))
+            .await?;
+        match rx.await {
+            Ok(blockhashes) => Ok(blockhashes),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get where a share first reached this node, None if unknown or it arrived through sync
+    pub async fn get_share_provenance(
+        &self,
+        blockhash: ShareBlockHash,
+    ) -> Result<Option<ShareProvenance>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::GetShareProvenance(blockhash, tx))
+            .await?;
+        match rx.await {
+            Ok(provenance) => Ok(provenance),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get where a share stands in the chain: main chain, side branch, uncle, orphan or unknown.
+    /// Lets a frontend that lost the response to a submitted share learn what happened to it later.
+    pub async fn get_share_status(
+        &self,
+        blockhash: ShareBlockHash,
+    ) -> Result<ShareStatus, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::GetShareStatus(blockhash, tx))
+            .await?;
+        match rx.await {
+            Ok(status) => Ok(status),
+            Err(e) => Err(e.into()),
+        }
+    }
 
-        tokio::spawn(async move {
-            node_actor.run().await;
-        });
-        Ok((Self { command_tx }, stopping_rx))
+    /// Get the chain tip, its height and work, and whether it is stable, i.e. the chain didn't reorg
+    /// more than the configured number of times recently. Payouts should wait for a stable tip.
+    pub async fn get_chain_stats(&self) -> Result<ChainStats, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::GetChainStats(tx)).await?;
+        match rx.await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        }
     }
 
-    /// Get a list of connected peers
-    pub async fn get_peers(&self) -> Result<Vec<libp2p::PeerId>, Box<dyn Error + Send + Sync>> {
+    /// Get the path from one share back to its closest common ancestor with another share and forward to it,
+    /// for reorg analysis and payout audits. None if either share is unknown or they have no common ancestor.
+    pub async fn get_path(
+        &self,
+        from: ShareBlockHash,
+        to: ShareBlockHash,
+    ) -> Result<Option<Vec<ShareBlockHash>>, Box<dyn Error + Send + Sync>> {
         let (tx, rx) = oneshot::channel();
-        self.command_tx.send(Command::GetPeers(tx)).await?;
+        self.command_tx.send(Command::GetPath(from, to, tx)).await?;
         match rx.await {
-            Ok(peers) => Ok(peers),
+            Ok(path) => Ok(path),
             Err(e) => Err(e.into()),
         }
     }
 
-    /// Shutdown the node
-    pub async fn shutdown(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    /// Get the shares at the most recent `depth` heights, including side branches, and the links between them
+    pub async fn get_dag_snapshot(
+        &self,
+        depth: u32,
+    ) -> Result<DagSnapshot, Box<dyn Error + Send + Sync>> {
         let (tx, rx) = oneshot::channel();
-        self.command_tx.send(Command::Shutdown(tx)).await?;
+        self.command_tx
+            .send(Command::GetDagSnapshot(depth, tx))
+            .await?;
         match rx.await {
-            Ok(_) => Ok(()),
+            Ok(snapshot) => Ok(snapshot),
             Err(e) => Err(e.into()),
         }
     }
 
-    /// Add share to the chain
-    pub async fn add_share(&self, share: ShareBlock) -> Result<(), Box<dyn Error + Send + Sync>> {
+    /// Get the proof of the shares the reward for the bitcoin block solved by share block_hash is split over,
+    /// ordered from the solving share back with their cumulative work. None if the share is unknown.
+    pub async fn get_inclusion_proof(
+        &self,
+        block_hash: ShareBlockHash,
+    ) -> Result<Option<PayoutProof>, Box<dyn Error + Send + Sync>> {
         let (tx, rx) = oneshot::channel();
-        self.command_tx.send(Command::AddShare(share, tx)).await?;
+        self.command_tx
+            .send(Command::GetInclusionProof(block_hash, tx))
+            .await?;
         match rx.await {
-            Ok(_) => Ok(()),
+            Ok(proof) => Ok(proof),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Check an inclusion proof, from this or another node, is validly signed and its shares, miners and work
+    /// match the shares in our store. Errors with the reason the proof was rejected.
+    pub async fn verify_inclusion_proof(
+        &self,
+        proof: PayoutProof,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::VerifyInclusionProof(proof, tx))
+            .await?;
+        match rx.await {
+            Ok(result) => result.map_err(|e| e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Split total_reward, in satoshis, between the miners of the window most recent main chain shares under
+    /// the configured payout policy. The report has each miner's address, share count, weight and reward.
+    pub async fn compute_payouts(
+        &self,
+        window: usize,
+        total_reward: u64,
+    ) -> Result<PayoutReport, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::ComputePayouts(window, total_reward, tx))
+            .await?;
+        match rx.await {
+            Ok(report) => Ok(report),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Estimate the pool hashrate in hashes per second, from the difficulty of the shares found in the
+    /// last window divided by its length
+    pub async fn estimate_hashrate(
+        &self,
+        window: Duration,
+    ) -> Result<f64, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::EstimateHashrate(window, tx))
+            .await?;
+        match rx.await {
+            Ok(hashrate) => Ok(hashrate),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Count the shares found in each of the count most recent buckets of the given length, oldest first
+    /// Each bucket is returned with its start in seconds since epoch.
+    pub async fn get_share_histogram(
+        &self,
+        bucket: Duration,
+        count: usize,
+    ) -> Result<Vec<(u64, u64)>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::GetShareHistogram(bucket, count, tx))
+            .await?;
+        match rx.await {
+            Ok(histogram) => Ok(histogram),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replace the chain with the chain snapshot in the file at path
+    /// The snapshot is rejected if it is invalid, for another genesis, or has no more work than our chain.
+    /// Rejections are returned as a SnapshotError.
+    pub async fn load_snapshot(&self, path: PathBuf) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::LoadSnapshot(path, tx))
+            .await?;
+        match rx.await {
+            Ok(result) => result.map_err(|e| e.into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// List stored workbases in a height or time range, ordered by height or time.
+    /// Skips the first offset workbases in the range and returns at most limit, to page through the range.
+    pub async fn list_workbases(
+        &self,
+        range: WorkbaseRange,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<MinerWorkbase>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::ListWorkbases(range, offset, limit, tx))
+            .await?;
+        match rx.await {
+            Ok(workbases) => Ok(workbases),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Flush, close and reopen the store at the same path, deriving the chain tip again from it.
+    /// A lighter way to recover from a bad RocksDB state than restarting the node.
+    pub async fn reopen_store(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::ReopenStore(tx)).await?;
+        match rx.await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Forcibly set the chain tip to a stored leaf share, for testing reorg handling and emergency recovery.
+    /// Refused unless network.allow_unsafe_ops is set, or if the share is not in the store or has children.
+    pub async fn set_tip(
+        &self,
+        blockhash: ShareBlockHash,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::SetTip(blockhash, tx)).await?;
+        match rx.await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Compact the whole store, returning its disk usage in bytes before and after compaction.
+    /// The node keeps handling events while the store compacts.
+    pub async fn compact_store(&self) -> Result<(u64, u64), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::CompactStore(tx)).await?;
+        match rx.await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Benchmark the disk through the store by writing and reading back ops synthetic entries, for capacity
+    /// planning. The entries are deleted afterwards. The node keeps handling events while the benchmark runs.
+    pub async fn benchmark_store(
+        &self,
+        ops: usize,
+    ) -> Result<StoreBenchmark, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::BenchmarkStore(ops, tx))
+            .await?;
+        match rx.await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Delete the shares that first reached us from a peer, e.g. one found feeding us bad data.
+    /// Shares other shares build on, shares referenced as uncles and the chain tip can't be deleted
+    /// without disconnecting the chain, they are kept and listed in the report.
+    pub async fn purge_peer_shares(
+        &self,
+        peer_id: libp2p::PeerId,
+    ) -> Result<PurgeReport, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::PurgePeerShares(peer_id, tx))
+            .await?;
+        match rx.await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replace the log filter, e.g. "debug" or "info,p2poolv2::node=trace".
+    /// An invalid filter returns an error and leaves the current filter in place.
+    pub async fn set_log_level(&self, level: String) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::SetLogLevel(level, tx))
+            .await?;
+        match rx.await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the config the node is running with, including env overrides, defaults and hot reloads.
+    /// Secrets such as the bitcoin RPC password are redacted.
+    pub async fn get_effective_config(&self) -> Result<Config, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(Command::GetEffectiveConfig(tx))
+            .await?;
+        match rx.await {
+            Ok(config) => Ok(config),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the log filter currently applied, None if the node can't change it at runtime
+    pub async fn get_log_level(&self) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx.send(Command::GetLogLevel(tx)).await?;
+        match rx.await {
+            Ok(level) => Ok(level),
             Err(e) => Err(e.into()),
         }
     }
 
-    /// Store workbase in the node's database
+    /// Store workbase in the node's database.
+    /// An identical workbase already stored is reported as existing, a conflicting one is an error.
     pub async fn add_workbase(
         &self,
         workbase: MinerWorkbase,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ) -> Result<WorkbaseOutcome, Box<dyn Error + Send + Sync>> {
         let (tx, rx) = oneshot::channel();
         self.command_tx
             .send(Command::StoreWorkbase(workbase, tx))
             .await?;
         match rx.await {
-            Ok(_) => Ok(()),
+            Ok(result) => result,
             Err(e) => Err(e.into()),
         }
     }
@@ -100,19 +853,66 @@ impl NodeHandle {
 #[cfg(test)]
 use mockall::mock;
 
-#[cfg(test)]
-use crate::node::messages::Message;
-
 #[cfg(test)]
 mock! {
     pub NodeHandle {
         pub async fn new(config: Config, chain_handle: ChainHandle) -> Result<(Self, oneshot::Receiver<()>), Box<dyn Error>>;
+        pub async fn new_with_log_level(config: Config, chain_handle: ChainHandle, log_level: LogLevelHandle) -> Result<(Self, oneshot::Receiver<()>), Box<dyn Error>>;
+        pub async fn new_with_clock(config: Config, chain_handle: ChainHandle, clock: Arc<dyn Clock>) -> Result<(Self, oneshot::Receiver<()>), Box<dyn Error>>;
         pub async fn get_peers(&self) -> Result<Vec<libp2p::PeerId>, Box<dyn Error>>;
+        pub async fn publish_announcement(&self, payload: String, signature: Vec<u8>) -> Result<(), Box<dyn Error>>;
+        pub async fn subscribe_events(&self) -> Result<broadcast::Receiver<SequencedEvent>, Box<dyn Error>>;
+        pub async fn subscribe_reorgs(&self) -> Result<broadcast::Receiver<Reorg>, Box<dyn Error>>;
+        pub async fn register_block_found_handler(&self, handler: Arc<dyn BlockFoundHandler>) -> Result<(), Box<dyn Error>>;
+        pub async fn subscribe_shares(&self, filter: ShareFilter) -> Result<BoxStream<'static, ShareBlock>, Box<dyn Error>>;
+        pub async fn get_peer_info(&self, peer_id: libp2p::PeerId) -> Result<Option<PeerInfo>, Box<dyn Error>>;
+        pub async fn get_peer_inventory(&self, peer_id: libp2p::PeerId) -> Result<Option<InventoryMessage>, Box<dyn Error>>;
+        pub async fn reload_config(&self, config: Config) -> Result<ConfigReload, Box<dyn Error>>;
+        pub async fn start_reindex(&self) -> Result<(), Box<dyn Error>>;
+        pub async fn cancel_reindex(&self) -> Result<bool, Box<dyn Error>>;
+        pub async fn drain_orphans(&self) -> Result<usize, Box<dyn Error>>;
+        pub async fn get_missing_ancestors(&self, blockhash: ShareBlockHash) -> Result<Vec<ShareBlockHash>, Box<dyn Error>>;
+        pub async fn diagnostics(&self) -> Result<Diagnostics, Box<dyn Error>>;
+        pub async fn audit_chain(&self) -> Result<AuditReport, Box<dyn Error>>;
+        pub async fn get_metrics(&self) -> Result<MetricsSnapshot, Box<dyn Error>>;
+        pub async fn prometheus_metrics(&self) -> Result<String, Box<dyn Error>>;
+        pub async fn get_health(&self) -> Result<HealthStatus, Box<dyn Error>>;
+        pub async fn time_since_last_share(&self) -> Result<Option<Duration>, Box<dyn Error>>;
+        pub async fn get_network_quality(&self) -> Result<NetworkQuality, Box<dyn Error>>;
+        pub async fn get_peer_breakdown(&self) -> Result<PeerBreakdown, Box<dyn Error>>;
+        pub async fn set_peer_priority(&self, peer_id: libp2p::PeerId, priority: u8) -> Result<(), Box<dyn Error>>;
+        pub async fn ban_peer(&self, peer_id: libp2p::PeerId) -> Result<bool, Box<dyn Error>>;
+        pub async fn unban_peer(&self, peer_id: libp2p::PeerId) -> Result<bool, Box<dyn Error>>;
         pub async fn shutdown(&self) -> Result<(), Box<dyn Error>>;
         pub async fn send_gossip(&self, message: Message) -> Result<(), Box<dyn Error>>;
         pub async fn send_to_peer(&self, peer_id: libp2p::PeerId, message: Message) -> Result<(), Box<dyn Error>>;
-        pub async fn add_share(&self, share: ShareBlock) -> Result<(), Box<dyn Error>>;
-        pub async fn add_workbase(&self, workbase: MinerWorkbase) -> Result<(), Box<dyn Error>>;
+        pub async fn find_closest_peers(&self, target: libp2p::PeerId) -> Result<Vec<libp2p::PeerId>, Box<dyn Error>>;
+        pub async fn bootstrap_kademlia(&self) -> Result<(), Box<dyn Error>>;
+        pub async fn add_share(&self, share: ShareBlock) -> Result<AddShareOutcome, Box<dyn Error>>;
+        pub async fn add_share_local(&self, share: ShareBlock, suppress_gossip: bool) -> Result<AddShareOutcome, Box<dyn Error>>;
+        pub async fn add_share_batch(&self, shares: Vec<ShareBlock>) -> Result<Vec<AddShareOutcome>, Box<dyn Error>>;
+        pub async fn get_shares_by_miner(&self, address: bitcoin::Address) -> Result<Vec<ShareBlockHash>, Box<dyn Error>>;
+        pub async fn get_share_provenance(&self, blockhash: ShareBlockHash) -> Result<Option<ShareProvenance>, Box<dyn Error>>;
+        pub async fn get_share_status(&self, blockhash: ShareBlockHash) -> Result<ShareStatus, Box<dyn Error>>;
+        pub async fn get_chain_stats(&self) -> Result<ChainStats, Box<dyn Error>>;
+        pub async fn get_dag_snapshot(&self, depth: u32) -> Result<DagSnapshot, Box<dyn Error>>;
+        pub async fn get_path(&self, from: ShareBlockHash, to: ShareBlockHash) -> Result<Option<Vec<ShareBlockHash>>, Box<dyn Error>>;
+        pub async fn get_inclusion_proof(&self, block_hash: ShareBlockHash) -> Result<Option<PayoutProof>, Box<dyn Error>>;
+        pub async fn verify_inclusion_proof(&self, proof: PayoutProof) -> Result<(), Box<dyn Error>>;
+        pub async fn compute_payouts(&self, window: usize, total_reward: u64) -> Result<PayoutReport, Box<dyn Error>>;
+        pub async fn estimate_hashrate(&self, window: Duration) -> Result<f64, Box<dyn Error>>;
+        pub async fn get_share_histogram(&self, bucket: Duration, count: usize) -> Result<Vec<(u64, u64)>, Box<dyn Error>>;
+        pub async fn load_snapshot(&self, path: PathBuf) -> Result<(), Box<dyn Error>>;
+        pub async fn list_workbases(&self, range: WorkbaseRange, offset: usize, limit: usize) -> Result<Vec<MinerWorkbase>, Box<dyn Error>>;
+        pub async fn reopen_store(&self) -> Result<(), Box<dyn Error>>;
+        pub async fn set_tip(&self, blockhash: ShareBlockHash) -> Result<(), Box<dyn Error>>;
+        pub async fn compact_store(&self) -> Result<(u64, u64), Box<dyn Error>>;
+        pub async fn benchmark_store(&self, ops: usize) -> Result<StoreBenchmark, Box<dyn Error>>;
+        pub async fn purge_peer_shares(&self, peer_id: libp2p::PeerId) -> Result<PurgeReport, Box<dyn Error>>;
+        pub async fn set_log_level(&self, level: String) -> Result<(), Box<dyn Error>>;
+        pub async fn get_log_level(&self) -> Result<Option<String>, Box<dyn Error>>;
+        pub async fn get_effective_config(&self) -> Result<Config, Box<dyn Error>>;
+        pub async fn add_workbase(&self, workbase: MinerWorkbase) -> Result<WorkbaseOutcome, Box<dyn Error>>;
     }
 
     // Provide a clone implementation for NodeHandle mock double
@@ -127,35 +927,46 @@ mock! {
 struct NodeActor {
     node: Node,
     command_rx: mpsc::Receiver<Command>,
-    stopping_tx: oneshot::Sender<()>,
+    watchdog: Watchdog,
 }
 
 impl NodeActor {
     fn new(
         config: Config,
         chain_handle: ChainHandle,
+        log_level: Option<LogLevelHandle>,
+        clock: Arc<dyn Clock>,
         command_rx: mpsc::Receiver<Command>,
-    ) -> Result<(Self, oneshot::Receiver<()>), Box<dyn Error>> {
-        let node = Node::new(&config, chain_handle)?;
-        let (stopping_tx, stopping_rx) = oneshot::channel();
-        Ok((
-            Self {
-                node,
-                command_rx,
-                stopping_tx,
-            },
-            stopping_rx,
-        ))
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut node = Node::new(&config, chain_handle, clock.clone())?;
+        node.log_level = log_level;
+        Ok(Self {
+            node,
+            command_rx,
+            watchdog: Watchdog::new(config.network.watchdog_timeout_secs, clock),
+        })
     }
 
     async fn run(mut self) {
+        let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+        let mut isolation_interval = tokio::time::interval(ISOLATION_CHECK_INTERVAL);
         loop {
+            self.watchdog.beat();
             tokio::select! {
+                _ = ping_interval.tick() => {
+                    self.node.ping_peers();
+                },
+                _ = isolation_interval.tick() => {
+                    self.node.check_isolation();
+                    self.node.check_retries();
+                    self.node.expire_gossip_startup_buffer();
+                    self.node.evict_idle_peers();
+                },
                 buf = self.node.swarm_rx.recv() => {
                     match buf {
                         Some(SwarmSend::Gossip(message)) => {
-                            let buf = message.cbor_serialize().unwrap();
-                            if let Err(e) = self.node.swarm.behaviour_mut().gossipsub.publish(self.node.share_topic.clone(), buf) {
+                            let buf = self.node.stamp_gossip_message(message).cbor_serialize().unwrap();
+                            if let Err(e) = self.node.gossip_share(buf) {
                                 error!("Error publishing share: {}", e);
                             }
                         }
@@ -169,11 +980,16 @@ impl NodeActor {
                         }
                         None => {
                             info!("Stopping node actor on channel close");
-                            self.stopping_tx.send(()).unwrap();
                             return;
                         }
                     }
                 },
+                share = self.node.accepted_share_rx.recv() => {
+                    self.node.publish_accepted_share(share);
+                },
+                Some(peer_id) = self.node.timed_out_rx.recv() => {
+                    self.node.record_timed_out_message(peer_id);
+                },
                 event = self.node.swarm.select_next_some() => {
                     if let Err(e) = self.node.handle_swarm_event(event).await {
                         error!("Error handling swarm event: {}", e);
@@ -183,10 +999,184 @@ impl NodeActor {
                     match command {
                         Some(Command::GetPeers(tx)) => {
                             let peers = self.node.swarm.connected_peers().cloned().collect::<Vec<_>>();
-                            tx.send(peers).unwrap();
+                            if tx.send(peers).is_err() {
+                                error!("Failed to send peers response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::PublishAnnouncement(payload, signature, tx)) => {
+                            let result = self.node.publish_announcement(payload, signature).map_err(|e| {
+                                error!("Error publishing announcement: {}", e);
+                                "Error publishing announcement".into()
+                            });
+                            if tx.send(result).is_err() {
+                                error!("Failed to send publish announcement response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::SubscribeEvents(tx)) => {
+                            if tx.send(self.node.subscribe_events()).is_err() {
+                                error!("Failed to send event subscription");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::SubscribeReorgs(tx)) => {
+                            if tx.send(self.node.subscribe_reorgs()).is_err() {
+                                error!("Failed to send reorg subscription");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::RegisterBlockFoundHandler(handler, tx)) => {
+                            self.node.register_block_found_handler(handler);
+                            if tx.send(()).is_err() {
+                                error!("Failed to send block found handler registration response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::SubscribeShares(filter, tx)) => {
+                            if tx.send(self.node.subscribe_shares(filter)).is_err() {
+                                error!("Failed to send share subscription");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetPeerInfo(peer_id, tx)) => {
+                            if tx.send(self.node.peer_info(&peer_id)).is_err() {
+                                error!("Failed to send peer info response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetPeerInventory(peer_id, tx)) => {
+                            if tx.send(self.node.peer_inventory(&peer_id)).is_err() {
+                                error!("Failed to send peer inventory response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::ReloadConfig(config, tx)) => {
+                            if tx.send(self.node.reload_config(config)).is_err() {
+                                error!("Failed to send config reload response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::StartReindex(tx)) => {
+                            if tx.send(self.node.start_reindex()).is_err() {
+                                error!("Failed to send start reindex response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::CancelReindex(tx)) => {
+                            if tx.send(self.node.cancel_reindex()).is_err() {
+                                error!("Failed to send cancel reindex response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::DrainOrphans(tx)) => {
+                            let connected = self.node.chain_handle.drain_orphans().await;
+                            if tx.send(connected).is_err() {
+                                error!("Failed to send drain orphans response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetMissingAncestors(blockhash, tx)) => {
+                            let missing = self.node.chain_handle.get_missing_ancestors(blockhash).await;
+                            if tx.send(missing).is_err() {
+                                error!("Failed to send missing ancestors response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::Diagnostics(tx)) => {
+                            let chain = self.node.chain_handle.get_chain_stats().await.ok();
+                            let orphans = self.node.chain_handle.get_orphan_summary().await;
+                            if tx.send(self.node.diagnostics(chain, orphans)).is_err() {
+                                error!("Failed to send diagnostics response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::AuditChain(tx)) => {
+                            // Auditing validates every stored share, the event loop carries on while it runs
+                            let chain_handle = self.node.chain_handle.clone();
+                            let event_tx = self.node.event_tx.clone();
+                            let clock = self.node.clock.clone();
+                            tokio::spawn(async move {
+                                let report = run_audit(chain_handle, event_tx, clock).await;
+                                if tx.send(report).is_err() {
+                                    error!("Failed to send audit report");
+                                }
+                            });
+                        },
+                        Some(Command::GetMetrics(tx)) => {
+                            if tx.send(self.node.metrics()).is_err() {
+                                error!("Failed to send metrics response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetPrometheusMetrics(tx)) => {
+                            let gauges = NodeGauges {
+                                peers: self.node.connected_peers().len(),
+                                chain_height: self.node.chain_handle.get_tip_height().await,
+                                chain_work: self.node.chain_handle.get_total_difficulty().await.to_f64().unwrap_or_default(),
+                            };
+                            if tx.send(self.node.metrics().to_prometheus(&gauges)).is_err() {
+                                error!("Failed to send prometheus metrics response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::TimeSinceLastShare(tx)) => {
+                            if tx.send(self.node.time_since_last_share()).is_err() {
+                                error!("Failed to send time since last share response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetHealth(tx)) => {
+                            let health = self.node.health();
+                            if tx.send(health).is_err() {
+                                error!("Failed to send health response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetNetworkQuality(tx)) => {
+                            if tx.send(self.node.network_quality()).is_err() {
+                                error!("Failed to send network quality response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetPeerBreakdown(tx)) => {
+                            if tx.send(self.node.peer_breakdown()).is_err() {
+                                error!("Failed to send peer breakdown response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::SetPeerPriority(peer_id, priority, tx)) => {
+                            self.node.set_peer_priority(peer_id, priority);
+                            if tx.send(()).is_err() {
+                                error!("Failed to send set peer priority response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::BanPeer(peer_id, tx)) => {
+                            let banned = self.node.ban_peer(peer_id, "banned by operator".to_string());
+                            if tx.send(banned).is_err() {
+                                error!("Failed to send ban peer response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::UnbanPeer(peer_id, tx)) => {
+                            if tx.send(self.node.unban_peer(&peer_id)).is_err() {
+                                error!("Failed to send unban peer response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::FindClosestPeers(target, tx)) => {
+                            self.node.find_closest_peers(target, tx);
+                        },
+                        Some(Command::BootstrapKademlia(tx)) => {
+                            self.node.bootstrap_kademlia();
+                            if tx.send(()).is_err() {
+                                error!("Failed to send bootstrap response");
+                                self.node.record_dropped_response();
+                            }
                         },
                         Some(Command::SendGossip(buf, tx)) => {
-                            match self.node.swarm.behaviour_mut().gossipsub.publish(self.node.share_topic.clone(), buf) {
+                            match self.node.gossip_share(buf) {
                                 Err(e) => error!("Error publishing share: {}", e),
                                 Ok(_) => tx.send(Ok(())).unwrap(),
                             }
@@ -206,26 +1196,252 @@ impl NodeActor {
                             return;
                         },
                         Some(Command::AddShare(share, tx)) => {
-                            match self.node.chain_handle.add_share(share).await {
-                                Ok(_) => tx.send(Ok(())).unwrap(),
-                                Err(e) => {
-                                    error!("Error adding share to chain: {}", e);
-                                    tx.send(Err("Error adding share to chain".into())).unwrap()
-                                },
+                            let outcome = if self.node.config.network.observer {
+                                AddShareOutcome::Rejected(AddShareError::Observer)
+                            } else {
+                                let gossip = self.node.config.network.auto_gossip;
+                                add_local_share_with_gossip(share, &self.node.chain_handle, &self.node.clock, gossip, &self.node.swarm_tx).await
+                            };
+                            if tx.send(outcome).is_err() {
+                                error!("Failed to send add share outcome");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::AddShareLocal(share, suppress_gossip, tx)) => {
+                            let outcome = if self.node.config.network.observer {
+                                AddShareOutcome::Rejected(AddShareError::Observer)
+                            } else {
+                                let gossip = self.node.config.network.auto_gossip && !suppress_gossip;
+                                add_local_share_with_gossip(share, &self.node.chain_handle, &self.node.clock, gossip, &self.node.swarm_tx).await
+                            };
+                            if tx.send(outcome).is_err() {
+                                error!("Failed to send add share local outcome");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::AddShareBatch(shares, tx)) if self.node.config.network.observer => {
+                            let outcomes = vec![AddShareOutcome::Rejected(AddShareError::Observer); shares.len()];
+                            if tx.send(outcomes).is_err() {
+                                error!("Failed to send add share batch outcomes");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::AddShareBatch(shares, tx)) => {
+                            let outcomes = add_local_share_batch(shares.clone(), &self.node.chain_handle, &self.node.clock).await;
+                            if self.node.config.network.auto_gossip {
+                                // Published here rather than queued on swarm_tx, which only this loop drains, so
+                                // batches larger than the channel are gossiped in full
+                                for (outcome, share) in outcomes.iter().zip(shares) {
+                                    if matches!(outcome, AddShareOutcome::AcceptedMain | AddShareOutcome::AcceptedUncle) {
+                                        let buf = self.node.stamp_gossip_message(Message::MiningShare(share)).cbor_serialize().unwrap();
+                                        if let Err(e) = self.node.gossip_share(buf) {
+                                            error!("Error publishing share from batch: {}", e);
+                                        }
+                                    }
+                                }
+                            }
+                            if tx.send(outcomes).is_err() {
+                                error!("Failed to send add share batch outcomes");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetSharesByMiner(address, tx)) => {
+                            let blockhashes = self.node.chain_handle.get_shares_by_miner(address).await;
+                            if tx.send(blockhashes).is_err() {
+                                error!("Failed to send shares by miner response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetShareProvenance(blockhash, tx)) => {
+                            let provenance = self.node.chain_handle.get_share_provenance(blockhash).await;
+                            if tx.send(provenance).is_err() {
+                                error!("Failed to send share provenance response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetShareStatus(blockhash, tx)) => {
+                            let status = self.node.chain_handle.get_share_status(blockhash).await;
+                            if tx.send(status).is_err() {
+                                error!("Failed to send share status response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetChainStats(tx)) => {
+                            let result = self.node.chain_handle.get_chain_stats().await;
+                            if tx.send(result).is_err() {
+                                error!("Failed to send chain stats response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetPath(from, to, tx)) => {
+                            let path = self.node.chain_handle.get_path(from, to).await;
+                            if tx.send(path).is_err() {
+                                error!("Failed to send path response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetDagSnapshot(depth, tx)) => {
+                            let snapshot = self.node.chain_handle.get_dag_snapshot(depth).await;
+                            if tx.send(snapshot).is_err() {
+                                error!("Failed to send dag snapshot response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetInclusionProof(block_hash, tx)) => {
+                            let proof = self.node.chain_handle.inclusion_proof(block_hash).await.and_then(|mut proof| {
+                                match proof.sign(&self.node.id_keys) {
+                                    Ok(()) => Some(proof),
+                                    Err(e) => {
+                                        error!("Failed to sign inclusion proof: {}", e);
+                                        None
+                                    }
+                                }
+                            });
+                            if tx.send(proof).is_err() {
+                                error!("Failed to send inclusion proof response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::VerifyInclusionProof(proof, tx)) => {
+                            let result = self.node.chain_handle.verify_inclusion_proof(proof).await;
+                            if tx.send(result).is_err() {
+                                error!("Failed to send inclusion proof verification response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::ComputePayouts(window, total_reward, tx)) => {
+                            let report = self.node.chain_handle.payout_report(window, total_reward).await;
+                            if tx.send(report).is_err() {
+                                error!("Failed to send payout report response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::EstimateHashrate(window, tx)) => {
+                            let now = self.node.clock.seconds_since_epoch();
+                            let hashrate = self.node.chain_handle.estimate_hashrate(window, now).await;
+                            if tx.send(hashrate).is_err() {
+                                error!("Failed to send hashrate estimate response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetShareHistogram(bucket, count, tx)) => {
+                            let now = self.node.clock.seconds_since_epoch();
+                            let histogram = self.node.chain_handle.share_histogram(bucket, count, now).await;
+                            if tx.send(histogram).is_err() {
+                                error!("Failed to send share histogram response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::LoadSnapshot(path, tx)) => {
+                            let result = load_snapshot(path, &self.node.chain_handle).await;
+                            if let Err(e) = &result {
+                                error!("Failed to load chain snapshot: {}", e);
+                            }
+                            if tx.send(result).is_err() {
+                                error!("Failed to send load snapshot response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::ListWorkbases(range, offset, limit, tx)) => {
+                            let workbases = self.node.chain_handle.list_workbases(range, offset, limit).await;
+                            if tx.send(workbases).is_err() {
+                                error!("Failed to send list workbases response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::SetTip(blockhash, tx)) => {
+                            let result = if self.node.config.network.allow_unsafe_ops {
+                                self.node.chain_handle.set_tip(blockhash).await
+                            } else {
+                                Err("Setting the tip is an unsafe operation, enable network.allow_unsafe_ops to allow it".into())
                             };
+                            if let Err(e) = &result {
+                                error!("Failed to set tip: {}", e);
+                            }
+                            if tx.send(result).is_err() {
+                                error!("Failed to send set tip response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::ReopenStore(tx)) => {
+                            let result = self.node.chain_handle.reopen_store().await;
+                            if let Err(e) = &result {
+                                error!("Failed to reopen store: {}", e);
+                            }
+                            if tx.send(result).is_err() {
+                                error!("Failed to send reopen store response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::CompactStore(tx)) => {
+                            // Compaction can take a while, the event loop carries on while it runs
+                            let chain_handle = self.node.chain_handle.clone();
+                            tokio::spawn(async move {
+                                let result = chain_handle.compact_store().await;
+                                if let Err(e) = &result {
+                                    error!("Failed to compact store: {}", e);
+                                }
+                                if tx.send(result).is_err() {
+                                    error!("Failed to send compact store response");
+                                }
+                            });
+                        },
+                        Some(Command::BenchmarkStore(ops, tx)) => {
+                            // The benchmark can take a while, the event loop carries on while it runs
+                            let chain_handle = self.node.chain_handle.clone();
+                            tokio::spawn(async move {
+                                let result = chain_handle.benchmark_store(ops).await;
+                                if let Err(e) = &result {
+                                    error!("Failed to benchmark store: {}", e);
+                                }
+                                if tx.send(result).is_err() {
+                                    error!("Failed to send benchmark store response");
+                                }
+                            });
+                        },
+                        Some(Command::PurgePeerShares(peer_id, tx)) => {
+                            let result = self.node.chain_handle.purge_peer_shares(peer_id).await;
+                            if let Err(e) = &result {
+                                error!("Failed to purge shares from peer {}: {}", peer_id, e);
+                            }
+                            if tx.send(result).is_err() {
+                                error!("Failed to send purge peer shares response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::SetLogLevel(level, tx)) => {
+                            let result = self.node.set_log_level(&level);
+                            if let Err(e) = &result {
+                                error!("Failed to set log level to {}: {}", level, e);
+                            }
+                            if tx.send(result).is_err() {
+                                error!("Failed to send set log level response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetEffectiveConfig(tx)) => {
+                            if tx.send(self.node.config().redacted()).is_err() {
+                                error!("Failed to send effective config response");
+                                self.node.record_dropped_response();
+                            }
+                        },
+                        Some(Command::GetLogLevel(tx)) => {
+                            if tx.send(self.node.log_level()).is_err() {
+                                error!("Failed to send log level response");
+                                self.node.record_dropped_response();
+                            }
                         },
                         Some(Command::StoreWorkbase(workbase, tx)) => {
                             match self.node.chain_handle.add_workbase(workbase).await {
-                                Ok(_) => tx.send(Ok(())).unwrap(),
+                                Ok(outcome) => tx.send(Ok(outcome)).unwrap(),
                                 Err(e) => {
                                     error!("Error storing workbase: {}", e);
-                                    tx.send(Err("Error storing workbase".into())).unwrap()
+                                    tx.send(Err(format!("Error storing workbase: {}", e).into())).unwrap()
                                 },
                             };
                         },
                         None => {
                             info!("Stopping node actor on channel close");
-                            self.stopping_tx.send(()).unwrap();
                             return;
                         }
                     }
@@ -0,0 +1,64 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use libp2p::{Multiaddr, PeerId};
+
+/// Whether a peer is on the allow-list of peer ids. An empty allow-list allows every peer.
+pub fn is_allowed(allowed_peers: &[String], peer_id: &PeerId) -> bool {
+    allowed_peers.is_empty()
+        || allowed_peers
+            .iter()
+            .any(|allowed| allowed.parse::<PeerId>().ok().as_ref() == Some(peer_id))
+}
+
+/// The discovered peers we may dial, dropping peers that are not on a non empty allow-list
+pub fn allowed_discoveries(
+    allowed_peers: &[String],
+    discovered: impl IntoIterator<Item = (PeerId, Multiaddr)>,
+) -> Vec<(PeerId, Multiaddr)> {
+    discovered
+        .into_iter()
+        .filter(|(peer_id, _)| is_allowed(allowed_peers, peer_id))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_allow_list_allows_every_peer() {
+        assert!(is_allowed(&[], &PeerId::random()));
+    }
+
+    #[test]
+    fn test_mdns_discoveries_are_filtered_by_allow_list() {
+        let allowed = PeerId::random();
+        let disallowed = PeerId::random();
+        let allowed_addr: Multiaddr = "/ip4/192.168.1.10/tcp/6884".parse().unwrap();
+        let disallowed_addr: Multiaddr = "/ip4/192.168.1.11/tcp/6884".parse().unwrap();
+        let discovered = vec![
+            (allowed, allowed_addr.clone()),
+            (disallowed, disallowed_addr.clone()),
+        ];
+
+        let to_dial = allowed_discoveries(&[allowed.to_string()], discovered.clone());
+        assert_eq!(to_dial, vec![(allowed, allowed_addr)]);
+
+        // Without an allow-list every discovered peer is dialed
+        assert_eq!(allowed_discoveries(&[], discovered.clone()), discovered);
+    }
+}
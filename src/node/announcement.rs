@@ -0,0 +1,137 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::events::{EventSender, NodeEvent};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{ecdsa::Signature, Message as SecpMessage, Secp256k1, SecretKey};
+use bitcoin::PublicKey;
+use tracing::{info, warn};
+
+/// Gossipsub topic operator announcements are published on, kept apart from shares
+pub const ANNOUNCEMENT_TOPIC: &str = "announcement";
+
+/// Digest of the payload that operators sign
+fn payload_digest(payload: &str) -> SecpMessage {
+    SecpMessage::from_digest(sha256::Hash::hash(payload.as_bytes()).to_byte_array())
+}
+
+/// Sign an announcement payload with an operator key, returning the DER encoded signature
+pub fn sign_announcement(payload: &str, secret_key: &SecretKey) -> Vec<u8> {
+    let secp = Secp256k1::signing_only();
+    secp.sign_ecdsa(&payload_digest(payload), secret_key)
+        .serialize_der()
+        .to_vec()
+}
+
+/// Find the trusted operator key that signed the payload, None if the signature is invalid or untrusted
+pub fn verify_announcement(
+    payload: &str,
+    signature: &[u8],
+    trusted_keys: &[PublicKey],
+) -> Option<PublicKey> {
+    let signature = Signature::from_der(signature).ok()?;
+    let secp = Secp256k1::verification_only();
+    let digest = payload_digest(payload);
+    trusted_keys
+        .iter()
+        .find(|key| secp.verify_ecdsa(&digest, &signature, &key.inner).is_ok())
+        .copied()
+}
+
+/// Handle an announcement received on the announcement topic
+/// Announcements signed by a trusted operator are sent to event subscribers, all others are dropped.
+pub fn handle_announcement(
+    payload: String,
+    signature: &[u8],
+    trusted_keys: &[PublicKey],
+    event_tx: &EventSender,
+) {
+    match verify_announcement(&payload, signature, trusted_keys) {
+        Some(signer) => {
+            info!("Received announcement signed by {}: {}", signer, payload);
+            event_tx.send(NodeEvent::Announcement { payload, signer });
+        }
+        None => warn!("Dropping announcement without a trusted signature"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operator_key(byte: u8) -> (SecretKey, PublicKey) {
+        let secret_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+        let public_key = PublicKey::new(secret_key.public_key(&Secp256k1::signing_only()));
+        (secret_key, public_key)
+    }
+
+    #[test]
+    fn test_sign_and_verify_announcement() {
+        let (secret_key, public_key) = operator_key(1);
+        let (_, other_key) = operator_key(2);
+        let payload = "mandatory upgrade at block 1000";
+
+        let signature = sign_announcement(payload, &secret_key);
+        assert_eq!(
+            verify_announcement(payload, &signature, &[other_key, public_key]),
+            Some(public_key)
+        );
+    }
+
+    #[test]
+    fn test_verify_announcement_rejects_untrusted_tampered_and_unsigned() {
+        let (secret_key, public_key) = operator_key(1);
+        let (_, other_key) = operator_key(2);
+        let payload = "mandatory upgrade at block 1000";
+        let signature = sign_announcement(payload, &secret_key);
+
+        assert_eq!(verify_announcement(payload, &signature, &[other_key]), None);
+        assert_eq!(
+            verify_announcement("mandatory upgrade at block 2000", &signature, &[public_key]),
+            None
+        );
+        assert_eq!(verify_announcement(payload, &[], &[public_key]), None);
+        assert_eq!(verify_announcement(payload, &signature, &[]), None);
+    }
+
+    #[test]
+    fn test_handle_announcement_only_surfaces_trusted_announcements() {
+        let (secret_key, public_key) = operator_key(1);
+        let (untrusted_secret_key, _) = operator_key(2);
+        let event_tx = EventSender::new(4);
+        let mut event_rx = event_tx.subscribe();
+
+        let untrusted = sign_announcement("ignore me", &untrusted_secret_key);
+        handle_announcement(
+            "ignore me".to_string(),
+            &untrusted,
+            &[public_key],
+            &event_tx,
+        );
+
+        let trusted = sign_announcement("upgrade", &secret_key);
+        handle_announcement("upgrade".to_string(), &trusted, &[public_key], &event_tx);
+
+        match event_rx.try_recv().map(|sequenced| sequenced.event) {
+            Ok(NodeEvent::Announcement { payload, signer }) => {
+                assert_eq!(payload, "upgrade");
+                assert_eq!(signer, public_key);
+            }
+            other => panic!("Expected trusted announcement, got {:?}", other),
+        }
+        assert!(event_rx.try_recv().is_err());
+    }
+}
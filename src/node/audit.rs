@@ -0,0 +1,218 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::events::{EventSender, NodeEvent};
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use crate::shares::validation::{self, MAX_TIME_DIFF};
+use crate::shares::{ShareBlock, ShareBlockHash};
+use crate::utils::time_provider::TimeProvider;
+use tracing::{info, warn};
+
+/// A stored share that fails the current validation rules
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditFailure {
+    pub blockhash: ShareBlockHash,
+    pub height: u32,
+    pub reason: String,
+}
+
+/// Result of replaying the stored shares through validation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuditReport {
+    /// Number of shares validated
+    pub audited: usize,
+    /// Shares that failed validation, in height order
+    pub failures: Vec<AuditFailure>,
+}
+
+/// A stored share's timestamp was checked to be recent when it arrived, all an audit can check is that it is not
+/// further in the future than validation allows
+fn validate_not_from_future(share: &ShareBlock, now: u64) -> Result<(), String> {
+    let ntime = share.header.miner_share.ntime.to_consensus_u32() as u64;
+    if ntime > now + MAX_TIME_DIFF {
+        return Err(format!(
+            "Share timestamp {} is in the future, more than {} seconds after {}",
+            ntime, MAX_TIME_DIFF, now
+        ));
+    }
+    Ok(())
+}
+
+/// Validate every stored share against the current validation rules, from genesis up to the chain tip.
+/// Only reads from the chain, failing shares are reported and stay in the chain.
+/// A share can't be expected to still be recent, so timestamps are only checked not to be in the future of
+/// time_provider, the node's clock. Shares without a parent are the roots of the chain and are not validated.
+/// Progress is published after every height.
+pub async fn run_audit(
+    chain_handle: ChainHandle,
+    event_tx: EventSender,
+    time_provider: impl TimeProvider,
+) -> AuditReport {
+    let total = match chain_handle.get_tip_height().await {
+        Some(tip_height) => tip_height + 1,
+        None => 0,
+    };
+    info!("Starting audit of {} heights", total);
+    let mut report = AuditReport::default();
+    for height in 0..total {
+        let mut shares: Vec<_> = chain_handle
+            .get_shares_at_height(height)
+            .await
+            .into_iter()
+            .collect();
+        // Shares at a height come from a map, sort them so the report is stable
+        shares.sort_by_key(|(blockhash, _)| blockhash.to_string());
+        for (blockhash, share) in shares {
+            if share.header.prev_share_blockhash.is_none() {
+                continue;
+            }
+            report.audited += 1;
+            let result = match validate_not_from_future(&share, time_provider.seconds_since_epoch())
+            {
+                Ok(()) => validation::validate_contents(&share, &chain_handle)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            };
+            if let Err(reason) = result {
+                warn!(
+                    "Share {} at height {} fails validation: {}",
+                    blockhash, height, reason
+                );
+                report.failures.push(AuditFailure {
+                    blockhash,
+                    height,
+                    reason,
+                });
+            }
+        }
+        event_tx.send(NodeEvent::AuditProgress {
+            done: height + 1,
+            total,
+        });
+    }
+    info!(
+        "Audit of {} shares finished, {} fail validation",
+        report.audited,
+        report.failures.len()
+    );
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::events::EVENT_CHANNEL_CAPACITY;
+    use crate::shares::validation::pow_cache::PowCacheHandle;
+    use crate::test_utils::{valid_share_block, TestBlockBuilder};
+    use crate::utils::time_provider::TestTimeProvider;
+    use std::collections::HashMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[tokio::test]
+    async fn test_run_audit_reports_shares_failing_current_rules() {
+        let (mut valid, workbase, user_workbase, _) = valid_share_block();
+
+        let root = TestBlockBuilder::new()
+            .blockhash("0000000000000000000000000000000000000000000000000000000000000001")
+            .build();
+        let root_hash = root.cached_blockhash.unwrap();
+
+        // A share built from a valid miner share passes
+        valid.header.prev_share_blockhash = Some(root_hash);
+        valid.compute_blockhash();
+        let valid_hash = valid.cached_blockhash.unwrap();
+
+        // A share whose workbase isn't stored fails
+        let invalid = TestBlockBuilder::new()
+            .blockhash("0000000000000000000000000000000000000000000000000000000000000003")
+            .prev_share_blockhash(root_hash)
+            .workinfoid(42)
+            .build();
+        let invalid_hash = invalid.cached_blockhash.unwrap();
+
+        // A share from further in the future than validation allows fails
+        let now = 1_735_000_000;
+        let mut future = valid.clone();
+        future.header.miner_share.ntime =
+            bitcoin::absolute::Time::from_consensus(now as u32 + 3_600).unwrap();
+        future.compute_blockhash();
+        let future_hash = future.cached_blockhash.unwrap();
+
+        let mut chain_handle = ChainHandle::default();
+        chain_handle.expect_get_tip_height().returning(|| Some(1));
+        let root_clone = root.clone();
+        chain_handle
+            .expect_get_shares_at_height()
+            .returning(move |height| match height {
+                0 => HashMap::from([(root_hash, root_clone.clone())]),
+                _ => HashMap::from([
+                    (valid_hash, valid.clone()),
+                    (invalid_hash, invalid.clone()),
+                    (future_hash, future.clone()),
+                ]),
+            });
+        chain_handle
+            .expect_get_share()
+            .returning(move |blockhash| (blockhash == root_hash).then(|| root.clone()));
+        chain_handle
+            .expect_get_workbase()
+            .returning(move |workinfoid| {
+                (workinfoid == workbase.workinfoid).then(|| workbase.clone())
+            });
+        chain_handle
+            .expect_get_user_workbase()
+            .returning(move |workinfoid| {
+                (workinfoid == user_workbase.workinfoid).then(|| user_workbase.clone())
+            });
+        chain_handle
+            .expect_pow_cache()
+            .returning(PowCacheHandle::default);
+        chain_handle.expect_add_share().never();
+
+        let event_tx = EventSender::new(EVENT_CHANNEL_CAPACITY);
+        let mut event_rx = event_tx.subscribe();
+
+        let time_provider = TestTimeProvider(UNIX_EPOCH + Duration::from_secs(now));
+        let report = run_audit(chain_handle, event_tx, time_provider).await;
+
+        assert_eq!(report.audited, 3);
+        assert_eq!(report.failures.len(), 2);
+        assert!(report.failures.iter().all(|failure| failure.height == 1));
+        let reason = |blockhash| {
+            report
+                .failures
+                .iter()
+                .find(|failure| failure.blockhash == blockhash)
+                .map(|failure| failure.reason.clone())
+                .unwrap()
+        };
+        assert!(reason(invalid_hash).contains("Missing workbase"));
+        assert!(reason(future_hash).contains("in the future"));
+
+        let progress: Vec<NodeEvent> = std::iter::from_fn(|| event_rx.try_recv().ok())
+            .map(|sequenced| sequenced.event)
+            .collect();
+        assert_eq!(
+            progress,
+            vec![
+                NodeEvent::AuditProgress { done: 1, total: 2 },
+                NodeEvent::AuditProgress { done: 2, total: 2 },
+            ]
+        );
+    }
+}
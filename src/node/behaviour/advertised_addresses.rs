@@ -0,0 +1,334 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use libp2p::core::transport::ListenerId;
+use libp2p::core::Endpoint;
+use libp2p::identify;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::behaviour::{
+    ExpiredListenAddr, ExternalAddrConfirmed, ExternalAddrExpired, NewListenAddr,
+};
+use libp2p::swarm::{
+    ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::task::{Context, Poll};
+
+/// Where an address we could advertise came from, so it is withdrawn from identify the way it was added
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AddressSource {
+    Listen(ListenerId),
+    External,
+}
+
+/// Identify behaviour advertising at most max_addresses of our listen and external addresses.
+/// A node listening on many interfaces or relay circuits would otherwise send all of them to every peer,
+/// bloating identify messages and filling peers' routing tables with addresses they can't reach.
+/// Public addresses are preferred, then relay addresses, then the rest, keeping the order we learnt them in.
+pub struct CappedIdentify {
+    inner: identify::Behaviour,
+    max_addresses: usize,
+    /// Every listen and external address, in the order the swarm reported them
+    candidates: Vec<(AddressSource, Multiaddr)>,
+    /// The candidates the inner behaviour has been told about
+    advertised: Vec<(AddressSource, Multiaddr)>,
+}
+
+impl CappedIdentify {
+    pub fn new(inner: identify::Behaviour, max_addresses: usize) -> Self {
+        Self {
+            inner,
+            max_addresses,
+            candidates: Vec::new(),
+            advertised: Vec::new(),
+        }
+    }
+
+    /// The addresses currently advertised to peers
+    pub fn advertised_addresses(&self) -> Vec<Multiaddr> {
+        self.advertised
+            .iter()
+            .map(|(_, addr)| addr.clone())
+            .collect()
+    }
+
+    /// Recompute the advertised addresses and tell the inner behaviour what changed
+    fn update_advertised(&mut self) {
+        let selected = prioritize_addresses(
+            self.candidates
+                .iter()
+                .map(|(_, addr)| addr.clone())
+                .collect(),
+            self.max_addresses,
+        );
+        let mut advertised = Vec::new();
+        for addr in selected {
+            if let Some(candidate) = self.candidates.iter().find(|(_, a)| *a == addr) {
+                advertised.push(candidate.clone());
+            }
+        }
+
+        for (source, addr) in &self.advertised {
+            if !advertised.contains(&(*source, addr.clone())) {
+                match source {
+                    AddressSource::Listen(listener_id) => {
+                        self.inner
+                            .on_swarm_event(FromSwarm::ExpiredListenAddr(ExpiredListenAddr {
+                                listener_id: *listener_id,
+                                addr,
+                            }))
+                    }
+                    AddressSource::External => self.inner.on_swarm_event(
+                        FromSwarm::ExternalAddrExpired(ExternalAddrExpired { addr }),
+                    ),
+                }
+            }
+        }
+        for (source, addr) in &advertised {
+            if !self.advertised.contains(&(*source, addr.clone())) {
+                match source {
+                    AddressSource::Listen(listener_id) => {
+                        self.inner
+                            .on_swarm_event(FromSwarm::NewListenAddr(NewListenAddr {
+                                listener_id: *listener_id,
+                                addr,
+                            }))
+                    }
+                    AddressSource::External => self.inner.on_swarm_event(
+                        FromSwarm::ExternalAddrConfirmed(ExternalAddrConfirmed { addr }),
+                    ),
+                }
+            }
+        }
+        self.advertised = advertised;
+    }
+}
+
+impl NetworkBehaviour for CappedIdentify {
+    type ConnectionHandler = <identify::Behaviour as NetworkBehaviour>::ConnectionHandler;
+    type ToSwarm = identify::Event;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner
+            .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::NewListenAddr(NewListenAddr { listener_id, addr }) => {
+                let candidate = (AddressSource::Listen(listener_id), addr.clone());
+                if !self.candidates.contains(&candidate) {
+                    self.candidates.push(candidate);
+                }
+            }
+            FromSwarm::ExpiredListenAddr(ExpiredListenAddr { listener_id, addr }) => {
+                self.candidates.retain(|(source, a)| {
+                    *source != AddressSource::Listen(listener_id) || a != addr
+                });
+            }
+            FromSwarm::ExternalAddrConfirmed(ExternalAddrConfirmed { addr }) => {
+                let candidate = (AddressSource::External, addr.clone());
+                if !self.candidates.contains(&candidate) {
+                    self.candidates.push(candidate);
+                }
+            }
+            FromSwarm::ExternalAddrExpired(ExternalAddrExpired { addr }) => {
+                self.candidates
+                    .retain(|(source, a)| *source != AddressSource::External || a != addr);
+            }
+            _ => {
+                self.inner.on_swarm_event(event);
+                return;
+            }
+        }
+        self.update_advertised();
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        self.inner.poll(cx)
+    }
+}
+
+/// Pick at most max_addresses addresses to advertise: public ones first, then relay circuits, then the rest.
+/// Addresses keep their relative order within each group and duplicates are advertised once.
+pub fn prioritize_addresses(addresses: Vec<Multiaddr>, max_addresses: usize) -> Vec<Multiaddr> {
+    let mut unique: Vec<Multiaddr> = Vec::new();
+    for addr in addresses {
+        if !unique.contains(&addr) {
+            unique.push(addr);
+        }
+    }
+    // Stable sort keeps the order addresses were learnt in within each group
+    unique.sort_by_key(address_rank);
+    unique.truncate(max_addresses);
+    unique
+}
+
+/// 0 for public addresses, 1 for relay circuits and 2 for anything else, such as private or loopback addresses
+fn address_rank(addr: &Multiaddr) -> u8 {
+    if addr
+        .iter()
+        .any(|protocol| matches!(protocol, Protocol::P2pCircuit))
+    {
+        return 1;
+    }
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) if is_public_ipv4(&ip) => 0,
+        Some(Protocol::Ip6(ip)) if is_public_ipv6(&ip) => 0,
+        Some(Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_)) => 0,
+        _ => 2,
+    }
+}
+
+fn is_public_ipv4(ip: &Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    // 100.64.0.0/10 is shared address space used behind carrier grade NAT
+    let shared = octets[0] == 100 && (octets[1] & 0xc0) == 64;
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || shared)
+}
+
+fn is_public_ipv6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    // fc00::/7 is unique local and fe80::/10 is link local
+    let unique_local = (first & 0xfe00) == 0xfc00;
+    let link_local = (first & 0xffc0) == 0xfe80;
+    !(ip.is_loopback() || ip.is_unspecified() || unique_local || link_local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    #[test]
+    fn test_only_prioritized_listen_addresses_are_advertised() {
+        let local_key = Keypair::generate_ed25519();
+        let mut identify = CappedIdentify::new(
+            identify::Behaviour::new(identify::Config::new(
+                "/p2pool/1.0.0".to_string(),
+                local_key.public(),
+            )),
+            3,
+        );
+        let addresses: Vec<Multiaddr> = [
+            "/ip4/127.0.0.1/tcp/6884",
+            "/ip4/192.168.1.10/tcp/6884",
+            "/ip4/10.0.0.5/tcp/6884",
+            "/ip4/203.0.113.7/tcp/6884",
+            "/ip4/198.51.100.1/tcp/4001/p2p-circuit",
+            "/ip4/8.8.4.4/tcp/6884",
+            "/ip6/::1/tcp/6884",
+            "/ip6/2001:4860::8888/tcp/6884",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+        let listener_id = ListenerId::next();
+        for addr in &addresses {
+            identify.on_swarm_event(FromSwarm::NewListenAddr(NewListenAddr {
+                listener_id,
+                addr,
+            }));
+        }
+
+        // 203.0.113.0/24 is a documentation range, so only two addresses are public, then the relay circuit
+        let public1: Multiaddr = "/ip4/8.8.4.4/tcp/6884".parse().unwrap();
+        let public2: Multiaddr = "/ip6/2001:4860::8888/tcp/6884".parse().unwrap();
+        let relay: Multiaddr = "/ip4/198.51.100.1/tcp/4001/p2p-circuit".parse().unwrap();
+        assert_eq!(
+            identify.advertised_addresses(),
+            vec![public1.clone(), public2.clone(), relay.clone()]
+        );
+
+        // When a public address goes away, the best remaining private address takes its place
+        identify.on_swarm_event(FromSwarm::ExpiredListenAddr(ExpiredListenAddr {
+            listener_id,
+            addr: &public1,
+        }));
+        let loopback: Multiaddr = "/ip4/127.0.0.1/tcp/6884".parse().unwrap();
+        assert_eq!(
+            identify.advertised_addresses(),
+            vec![public2, relay, loopback]
+        );
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use libp2p::core::Endpoint;
+use libp2p::swarm::{
+    dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashSet;
+use std::task::{Context, Poll};
+use tracing::debug;
+
+/// Why the gate refused a connection
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum GateError {
+    #[error("peer {0} is banned")]
+    Banned(PeerId),
+    #[error("peer {0} is not on the allow-list")]
+    NotAllowed(PeerId),
+}
+
+/// Refuses connections to and from banned peers and peers missing from a non empty allow-list.
+/// Inbound connections are refused as soon as the peer is authenticated, before the connection is
+/// established and any protocol handlers are set up. Dials to a known peer are refused before dialing.
+#[derive(Debug, Default)]
+pub struct ConnectionGate {
+    allowed_peers: HashSet<PeerId>,
+    banned_peers: HashSet<PeerId>,
+}
+
+impl ConnectionGate {
+    /// Create a gate for the allow-list of peer ids, entries that are not peer ids are ignored
+    pub fn new(allowed_peers: &[String]) -> Self {
+        let mut gate = Self::default();
+        gate.set_allowed_peers(allowed_peers);
+        gate
+    }
+
+    /// Replace the allow-list, an empty allow-list allows every peer that isn't banned
+    pub fn set_allowed_peers(&mut self, allowed_peers: &[String]) {
+        self.allowed_peers = allowed_peers
+            .iter()
+            .filter_map(|peer| peer.parse().ok())
+            .collect();
+    }
+
+    /// Refuse future connections with a peer, returns false if it was already banned
+    pub fn ban(&mut self, peer_id: PeerId) -> bool {
+        self.banned_peers.insert(peer_id)
+    }
+
+    /// Allow connections with a banned peer again, returns false if it was not banned
+    pub fn unban(&mut self, peer_id: &PeerId) -> bool {
+        self.banned_peers.remove(peer_id)
+    }
+
+    /// The peers refused for being banned
+    pub fn banned_peers(&self) -> Vec<PeerId> {
+        self.banned_peers.iter().copied().collect()
+    }
+
+    /// Check a peer against the ban list and then the allow-list
+    pub fn check(&self, peer_id: &PeerId) -> Result<(), GateError> {
+        if self.banned_peers.contains(peer_id) {
+            return Err(GateError::Banned(*peer_id));
+        }
+        if !self.allowed_peers.is_empty() && !self.allowed_peers.contains(peer_id) {
+            return Err(GateError::NotAllowed(*peer_id));
+        }
+        Ok(())
+    }
+
+    fn gate(&self, connection_id: ConnectionId, peer_id: &PeerId) -> Result<(), ConnectionDenied> {
+        self.check(peer_id).map_err(|e| {
+            debug!("Refusing connection {connection_id}: {e}");
+            ConnectionDenied::new(e)
+        })
+    }
+}
+
+impl NetworkBehaviour for ConnectionGate {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = void::Void;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        _remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.gate(connection_id, &peer)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        _addresses: &[Multiaddr],
+        _effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        // Dials to an address without a peer id are checked once the peer is authenticated
+        if let Some(peer) = maybe_peer {
+            self.gate(connection_id, &peer)?;
+        }
+        Ok(vec![])
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _addr: &Multiaddr,
+        _role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.gate(connection_id, &peer)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, _event: FromSwarm) {}
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        void::unreachable(event)
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_banned_peer_is_rejected_before_connection_is_established() {
+        let mut gate = ConnectionGate::new(&[]);
+        let banned = PeerId::random();
+        let other = PeerId::random();
+        let local_addr: Multiaddr = "/ip4/127.0.0.1/tcp/6884".parse().unwrap();
+        let remote_addr: Multiaddr = "/ip4/127.0.0.1/tcp/40000".parse().unwrap();
+        assert!(gate.ban(banned));
+
+        let denied = gate
+            .handle_established_inbound_connection(
+                ConnectionId::new_unchecked(1),
+                banned,
+                &local_addr,
+                &remote_addr,
+            )
+            .err()
+            .expect("banned peer is refused");
+        assert_eq!(
+            denied.downcast::<GateError>().unwrap(),
+            GateError::Banned(banned)
+        );
+        // We don't dial a banned peer at all
+        assert!(gate
+            .handle_pending_outbound_connection(
+                ConnectionId::new_unchecked(2),
+                Some(banned),
+                &[remote_addr.clone()],
+                Endpoint::Dialer,
+            )
+            .is_err());
+        assert!(gate
+            .handle_established_inbound_connection(
+                ConnectionId::new_unchecked(3),
+                other,
+                &local_addr,
+                &remote_addr,
+            )
+            .is_ok());
+
+        assert!(gate.unban(&banned));
+        assert!(gate
+            .handle_established_inbound_connection(
+                ConnectionId::new_unchecked(4),
+                banned,
+                &local_addr,
+                &remote_addr,
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_peers_missing_from_allow_list_are_rejected() {
+        let allowed = PeerId::random();
+        let mut gate = ConnectionGate::new(&[allowed.to_string()]);
+        let stranger = PeerId::random();
+        assert_eq!(gate.check(&allowed), Ok(()));
+        assert_eq!(gate.check(&stranger), Err(GateError::NotAllowed(stranger)));
+
+        // Clearing the allow-list on a config reload allows every peer again
+        gate.set_allowed_peers(&[]);
+        assert_eq!(gate.check(&stranger), Ok(()));
+    }
+}
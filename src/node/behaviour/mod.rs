@@ -14,9 +14,14 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod advertised_addresses;
+pub mod connection_gate;
 pub mod request_response;
 use crate::config::Config;
 use crate::node::messages::Message;
+use crate::shares::ShareBlockHash;
+use advertised_addresses::CappedIdentify;
+use connection_gate::ConnectionGate;
 use libp2p::connection_limits;
 use libp2p::request_response::ProtocolSupport;
 use libp2p::swarm::behaviour::toggle::Toggle;
@@ -33,13 +38,18 @@ use request_response::{RequestResponseBehaviour, RequestResponseEvent};
 use std::error::Error;
 use void;
 
+/// Identify protocol version, peers append the genesis hash of the share chain they are on
+pub const PROTOCOL_VERSION: &str = "/p2pool/1.0.0";
+
 // Combine the behaviors we want to use
 #[derive(NetworkBehaviour)]
 #[behaviour(to_swarm = "P2PoolBehaviourEvent")]
 pub struct P2PoolBehaviour {
+    /// First, so banned and disallowed peers are refused before the other behaviours handle the connection
+    pub gate: ConnectionGate,
     pub gossipsub: gossipsub::Behaviour,
     pub kademlia: kad::Behaviour<MemoryStore>,
-    pub identify: identify::Behaviour,
+    pub identify: CappedIdentify,
     pub mdns: Toggle<MdnsTokio>,
     pub request_response: RequestResponseBehaviour<Message, Message>,
     pub limits: connection_limits::Behaviour,
@@ -64,31 +74,53 @@ pub enum P2PoolBehaviourEvent {
 #[allow(dead_code)]
 
 impl P2PoolBehaviour {
-    pub fn new(local_key: &Keypair, config: &Config) -> Result<Self, Box<dyn Error>> {
+    pub fn new(
+        local_key: &Keypair,
+        config: &Config,
+        genesis_hash: ShareBlockHash,
+    ) -> Result<Self, Box<dyn Error>> {
         // Initialize gossipsub
         let gossipsub_config = gossipsub::ConfigBuilder::default()
             .heartbeat_interval(std::time::Duration::from_secs(HEARTBEAT_INTERVAL))
             .validation_mode(gossipsub::ValidationMode::Strict)
+            .flood_publish(config.gossipsub.flood_publish)
+            .fanout_ttl(std::time::Duration::from_secs(
+                config.gossipsub.fanout_ttl_secs,
+            ))
             .build()
             .expect("Valid config");
 
-        let gossipsub_behaviour = gossipsub::Behaviour::new(
+        let mut gossipsub_behaviour = gossipsub::Behaviour::new(
             gossipsub::MessageAuthenticity::Signed(local_key.clone()),
             gossipsub_config,
         )?;
+        // Peer scores decide which peers the mesh keeps when it prunes, peer priorities are set as application scores
+        gossipsub_behaviour.with_peer_score(
+            gossipsub::PeerScoreParams::default(),
+            gossipsub::PeerScoreThresholds::default(),
+        )?;
 
         // Initialize Kademlia
         let store = MemoryStore::new(local_key.public().to_peer_id());
         let mut kad_config = kad::Config::default();
         kad_config.set_query_timeout(tokio::time::Duration::from_secs(60));
 
-        let kademlia_behaviour =
+        let mut kademlia_behaviour =
             kad::Behaviour::with_config(local_key.public().to_peer_id(), store, kad_config);
-
-        let identify_behaviour = identify::Behaviour::new(identify::Config::new(
-            "/p2pool/1.0.0".to_string(),
-            local_key.public(),
-        ));
+        // Nodes listen on a configured address, so answer DHT queries without waiting
+        // for an external address to be confirmed
+        kademlia_behaviour.set_mode(Some(kad::Mode::Server));
+
+        let identify_behaviour = CappedIdentify::new(
+            identify::Behaviour::new(
+                identify::Config::new(
+                    format!("{}/{}", PROTOCOL_VERSION, genesis_hash),
+                    local_key.public(),
+                )
+                .with_agent_version(config.network.agent_version.clone()),
+            ),
+            config.network.max_advertised_addresses as usize,
+        );
 
         // Initialize MDNS only if enabled in config
         let mdns_behaviour = if config.network.enable_mdns {
@@ -109,6 +141,7 @@ impl P2PoolBehaviour {
         let limits = connection_limits::Behaviour::new(limits_config);
 
         let behaviour = P2PoolBehaviour {
+            gate: ConnectionGate::new(&config.network.allowed_peers),
             gossipsub: gossipsub_behaviour,
             kademlia: kademlia_behaviour,
             identify: identify_behaviour,
@@ -168,7 +201,7 @@ impl From<RequestResponseEvent<Message, Message>> for P2PoolBehaviourEvent {
     }
 }
 
-// Provide From for the void (unreachable) type for connection_limits behaviour
+// Provide From for the void (unreachable) type for the connection_limits and gate behaviours
 impl From<void::Void> for P2PoolBehaviourEvent {
     fn from(void: void::Void) -> Self {
         // Since void::Void is uninhabited (can never be constructed),
@@ -176,3 +209,23 @@ impl From<void::Void> for P2PoolBehaviourEvent {
         match void {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shares::ShareBlock;
+
+    #[tokio::test]
+    async fn test_behaviour_builds_with_flood_publish() {
+        let local_key = Keypair::generate_ed25519();
+        let genesis_hash = ShareBlock::genesis_hash_for_network(bitcoin::Network::Signet);
+        for flood_publish in [true, false] {
+            let config = Config::load("./config.toml")
+                .unwrap()
+                .with_enable_mdns(false)
+                .with_gossipsub_flood_publish(flood_publish)
+                .with_gossipsub_fanout_ttl_secs(30);
+            assert!(P2PoolBehaviour::new(&local_key, &config, genesis_hash).is_ok());
+        }
+    }
+}
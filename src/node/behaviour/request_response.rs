@@ -21,13 +21,18 @@ use libp2p::request_response::{Codec, OutboundFailure};
 use serde::{de::DeserializeOwned, Serialize};
 use std::io;
 
+/// Name of our request-response protocol. The version is bumped when a message changes incompatibly, so peers
+/// on the old format fail protocol negotiation instead of failing to decode each other's messages.
+/// 1.1.0 made Pong carry the responder's clock, Pong { nonce, time_millis } in place of Pong(nonce).
+pub const REQUEST_RESPONSE_PROTOCOL: &str = "/p2pool/1.1.0";
+
 // Protocol name for our request-response protocol
 #[derive(Debug, Clone)]
 pub struct P2PoolRequestResponseProtocol(String);
 
 impl P2PoolRequestResponseProtocol {
     pub fn new() -> Self {
-        Self(format!("/p2pool/1.0.0"))
+        Self(REQUEST_RESPONSE_PROTOCOL.to_string())
     }
 }
 
@@ -0,0 +1,137 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::events::{EventSender, NodeEvent};
+use crate::shares::chain::BlockCandidate;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Called when a share added to the chain solves a bitcoin block, e.g. to submit the block to bitcoind.
+/// Handlers are called from a task forwarding chain events, so they should hand off slow work.
+pub trait BlockFoundHandler: fmt::Debug + Send + Sync {
+    fn block_found(&self, candidate: &BlockCandidate);
+}
+
+/// The block found handlers registered on the node, shared with the task that calls them
+#[derive(Debug, Clone, Default)]
+pub struct BlockFoundHandlers {
+    handlers: Arc<Mutex<Vec<Arc<dyn BlockFoundHandler>>>>,
+}
+
+impl BlockFoundHandlers {
+    pub fn register(&self, handler: Arc<dyn BlockFoundHandler>) {
+        self.handlers.lock().unwrap().push(handler);
+    }
+
+    /// Call every registered handler with the candidate, in the order they were registered
+    pub fn notify(&self, candidate: &BlockCandidate) {
+        let handlers = self.handlers.lock().unwrap().clone();
+        for handler in handlers {
+            handler.block_found(candidate);
+        }
+    }
+}
+
+/// Call the block found handlers and publish a node event for every share that solves a bitcoin block
+pub fn forward_blocks_found(
+    mut block_found_rx: broadcast::Receiver<BlockCandidate>,
+    handlers: BlockFoundHandlers,
+    event_tx: EventSender,
+) {
+    tokio::spawn(async move {
+        loop {
+            match block_found_rx.recv().await {
+                Ok(candidate) => {
+                    info!(
+                        "Share {} found bitcoin block {}",
+                        candidate.share_blockhash,
+                        candidate.block.block_hash()
+                    );
+                    handlers.notify(&candidate);
+                    event_tx.send(NodeEvent::BlockFound(Box::new(candidate)));
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Missed {} found blocks from the chain", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::events::EVENT_CHANNEL_CAPACITY;
+    use crate::shares::miner_message::builders::build_bitcoin_block;
+    use crate::test_utils::valid_share_block;
+    use std::time::Duration;
+
+    #[derive(Debug, Default)]
+    struct RecordingHandler {
+        found: Mutex<Vec<bitcoin::BlockHash>>,
+    }
+
+    impl BlockFoundHandler for RecordingHandler {
+        fn block_found(&self, candidate: &BlockCandidate) {
+            self.found
+                .lock()
+                .unwrap()
+                .push(candidate.block.block_hash());
+        }
+    }
+
+    fn block_candidate() -> BlockCandidate {
+        let (share, workbase, user_workbase, miner_share) = valid_share_block();
+        let block = build_bitcoin_block(&workbase, &user_workbase, &miner_share).unwrap();
+        BlockCandidate {
+            share_blockhash: share.cached_blockhash.unwrap(),
+            share,
+            workbase,
+            coinbase: block.txdata[0].clone(),
+            block,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_found_block_calls_handlers_and_publishes_event() {
+        let (block_found_tx, block_found_rx) = broadcast::channel(4);
+        let handlers = BlockFoundHandlers::default();
+        let handler = Arc::new(RecordingHandler::default());
+        handlers.register(handler.clone());
+        let event_tx = EventSender::new(EVENT_CHANNEL_CAPACITY);
+        let mut event_rx = event_tx.subscribe();
+        forward_blocks_found(block_found_rx, handlers, event_tx);
+
+        let candidate = block_candidate();
+        block_found_tx.send(candidate.clone()).unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(1), event_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event.event,
+            NodeEvent::BlockFound(Box::new(candidate.clone()))
+        );
+        assert_eq!(
+            *handler.found.lock().unwrap(),
+            vec![candidate.block.block_hash()]
+        );
+    }
+}
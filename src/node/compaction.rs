@@ -0,0 +1,66 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{error, info};
+
+/// Compact the whole store once every interval, starting one interval from now
+/// Compactions run one after another, so a slow compaction delays the next one instead of overlapping it.
+pub async fn run_scheduled_compaction(chain_handle: ChainHandle, interval: Duration) {
+    let mut compaction_interval = tokio::time::interval_at(Instant::now() + interval, interval);
+    info!("Compacting the store every {:?}", interval);
+    loop {
+        compaction_interval.tick().await;
+        compact_store(&chain_handle).await;
+    }
+}
+
+/// Compact the whole store and log how much disk space it freed
+pub async fn compact_store(chain_handle: &ChainHandle) {
+    match chain_handle.compact_store().await {
+        Ok((bytes_before, bytes_after)) => info!(
+            "Compacted store, disk usage went from {} to {} bytes",
+            bytes_before, bytes_after
+        ),
+        Err(e) => error!("Failed to compact store: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_compaction_runs_once_per_interval() {
+        let mut chain_handle = ChainHandle::default();
+        chain_handle
+            .expect_compact_store()
+            .times(2)
+            .returning(|| Ok((2000, 1500)));
+
+        let compaction = tokio::spawn(run_scheduled_compaction(
+            chain_handle,
+            Duration::from_secs(60),
+        ));
+        // Nothing is compacted at startup, then once per interval
+        tokio::time::sleep(Duration::from_secs(150)).await;
+        compaction.abort();
+        let _ = compaction.await;
+    }
+}
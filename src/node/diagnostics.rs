@@ -0,0 +1,204 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::behaviour::connection_gate::ConnectionGate;
+use crate::node::messages::InventoryMessage;
+use crate::node::metrics::MetricsSnapshot;
+use crate::node::peer_stats::{PeerInfo, PeerStats};
+use crate::node::sync_sessions::SyncSessions;
+use crate::shares::chain::{ChainCursor, ChainStats, OrphanSummary};
+use crate::utils::clock::Clock;
+use libp2p::PeerId;
+use serde::Serialize;
+use std::time::{Instant, UNIX_EPOCH};
+
+/// A connected peer's stats, as reported in diagnostics
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PeerDiagnostics {
+    pub peer_id: String,
+    /// Average ping round trip time, None until the peer answered a ping
+    pub average_rtt_ms: Option<u64>,
+    /// Fraction of our requests to the peer that got a response, None until one completed
+    pub success_rate: Option<f64>,
+    pub protocols: Vec<String>,
+    pub agent_version: Option<String>,
+    pub last_inventory: Option<InventoryMessage>,
+    pub responses_received: u64,
+    pub outbound_failures: u64,
+    pub inbound_failures: u64,
+    pub clock_offset_ms: Option<i64>,
+    /// Seconds since the peer connected or last sent us a message other than a ping or pong
+    pub idle_secs: Option<u64>,
+}
+
+impl PeerDiagnostics {
+    pub fn new(peer_id: &PeerId, info: &PeerInfo, now: Instant) -> Self {
+        Self {
+            peer_id: peer_id.to_string(),
+            average_rtt_ms: info.average_rtt().map(|rtt| rtt.as_millis() as u64),
+            success_rate: info.success_rate(),
+            protocols: info.protocols.clone(),
+            agent_version: info.agent_version.clone(),
+            last_inventory: info.last_inventory.clone(),
+            responses_received: info.responses_received,
+            outbound_failures: info.outbound_failures,
+            inbound_failures: info.inbound_failures,
+            clock_offset_ms: info.clock_offset_ms,
+            idle_secs: info
+                .last_activity
+                .map(|last_activity| now.saturating_duration_since(last_activity).as_secs()),
+        }
+    }
+}
+
+/// A running sync session, as reported in diagnostics
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyncSessionDiagnostics {
+    pub peer_id: String,
+    /// Where the chain page last requested from the peer starts, None until a page was requested
+    pub cursor: Option<ChainCursor>,
+}
+
+/// A point in time view of the node's state for incident response, gathered in one call instead of
+/// one call per section. Peers and identifiers are sorted so snapshots can be diffed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostics {
+    /// Seconds since the unix epoch when the snapshot was taken
+    pub taken_at: u64,
+    pub peers: Vec<PeerDiagnostics>,
+    /// The chain tip and its stability, None if the chain didn't respond
+    pub chain: Option<ChainStats>,
+    pub orphans: OrphanSummary,
+    pub sync_sessions: Vec<SyncSessionDiagnostics>,
+    pub banned_peers: Vec<String>,
+    pub metrics: MetricsSnapshot,
+}
+
+impl Diagnostics {
+    pub fn new(
+        peer_stats: &PeerStats,
+        sync_sessions: &SyncSessions,
+        gate: &ConnectionGate,
+        metrics: MetricsSnapshot,
+        chain: Option<ChainStats>,
+        orphans: OrphanSummary,
+        clock: &dyn Clock,
+    ) -> Self {
+        let now = clock.instant();
+        let mut peers: Vec<PeerDiagnostics> = peer_stats
+            .peers()
+            .map(|(peer_id, info)| PeerDiagnostics::new(peer_id, info, now))
+            .collect();
+        peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        let mut sync_sessions: Vec<SyncSessionDiagnostics> = sync_sessions
+            .sessions()
+            .into_iter()
+            .map(|(peer_id, cursor)| SyncSessionDiagnostics {
+                peer_id: peer_id.to_string(),
+                cursor,
+            })
+            .collect();
+        sync_sessions.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        let mut banned_peers: Vec<String> = gate
+            .banned_peers()
+            .iter()
+            .map(|peer_id| peer_id.to_string())
+            .collect();
+        banned_peers.sort();
+        Self {
+            taken_at: clock
+                .system_time()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            peers,
+            chain,
+            orphans,
+            sync_sessions,
+            banned_peers,
+            metrics,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::clock::SystemClock;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_diagnostics_contains_every_section() {
+        let peer = PeerId::random();
+        let syncing = PeerId::random();
+        let banned = PeerId::random();
+        let mut peer_stats = PeerStats::new();
+        peer_stats.add_peer(peer, Instant::now());
+        peer_stats.record_rtt(peer, Duration::from_millis(40));
+        let sync_sessions = SyncSessions::new(2);
+        assert!(sync_sessions.start(syncing).await);
+        let cursor = ChainCursor {
+            height: 7,
+            offset: 0,
+        };
+        sync_sessions.set_cursor(&syncing, cursor);
+        let mut gate = ConnectionGate::new(&[]);
+        gate.ban(banned);
+        let orphans = OrphanSummary {
+            count: 1,
+            connectable: 0,
+            missing_parents: vec![
+                "0000000000000000000000000000000000000000000000000000000000000009".into(),
+            ],
+        };
+
+        let diagnostics = Diagnostics::new(
+            &peer_stats,
+            &sync_sessions,
+            &gate,
+            MetricsSnapshot::default(),
+            None,
+            orphans.clone(),
+            &SystemClock,
+        );
+
+        assert_eq!(diagnostics.peers.len(), 1);
+        assert_eq!(diagnostics.peers[0].peer_id, peer.to_string());
+        assert_eq!(diagnostics.peers[0].average_rtt_ms, Some(40));
+        assert_eq!(
+            diagnostics.sync_sessions,
+            vec![SyncSessionDiagnostics {
+                peer_id: syncing.to_string(),
+                cursor: Some(cursor),
+            }]
+        );
+        assert_eq!(diagnostics.banned_peers, vec![banned.to_string()]);
+        assert_eq!(diagnostics.orphans, orphans);
+
+        let json = serde_json::to_value(&diagnostics).unwrap();
+        for section in [
+            "taken_at",
+            "peers",
+            "chain",
+            "orphans",
+            "sync_sessions",
+            "banned_peers",
+            "metrics",
+        ] {
+            assert!(json.get(section).is_some(), "missing section {section}");
+        }
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::shares::chain::{BlockCandidate, DeepReorg, Equivocation, Reorg};
+use bitcoin::PublicKey;
+use libp2p::Multiaddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Number of events buffered for each subscriber before slow subscribers start missing events
+pub const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Events the node publishes to subscribers of NodeHandle::subscribe_events
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeEvent {
+    /// An announcement signed by one of the trusted operator keys
+    Announcement { payload: String, signer: PublicKey },
+    /// A miner produced conflicting shares on the same parent
+    Equivocation(Equivocation),
+    /// The main chain reorged, with the shares orphaned and promoted and the miners whose credits change
+    Reorg(Reorg),
+    /// A share added to the chain meets the bitcoin network target, with the block it solved
+    BlockFound(Box<BlockCandidate>),
+    /// The chain refused a reorg replacing more main chain shares than chain.max_reorg_depth
+    DeepReorgRejected(DeepReorg),
+    /// A reindex finished `done` of the `total` heights it is reindexing
+    ReindexProgress { done: u32, total: u32 },
+    /// An audit of the stored shares finished `done` of the `total` heights it is validating
+    AuditProgress { done: u32, total: u32 },
+    /// No peer was connected for network.isolation_grace_period_secs.
+    /// The node re-dialed `dialed` of its dial peers and re-bootstrapped kademlia.
+    Isolated { dialed: usize },
+    /// A kademlia bootstrap failed, or couldn't start with no peer in the routing table.
+    /// The node re-dialed `dialed` of its dial peers so the routing table can fill again.
+    BootstrapFailed { dialed: usize },
+    /// A listener closed, with the error that closed it if any.
+    /// listening is false when it was the last open listener.
+    ListenerClosed {
+        addresses: Vec<Multiaddr>,
+        error: Option<String>,
+        listening: bool,
+    },
+}
+
+/// A node event with its sequence number. The node numbers events from 0 in the order it publishes them,
+/// so a subscriber that sees a sequence number more than one past the last one it saw has missed events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequencedEvent {
+    pub sequence: u64,
+    pub event: NodeEvent,
+}
+
+/// Publishes node events to subscribers, giving each event the next sequence number.
+/// Clones share the counter, so events from every task publishing for the node are numbered in one sequence.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    tx: broadcast::Sender<SequencedEvent>,
+    next_sequence: Arc<Mutex<u64>>,
+}
+
+impl EventSender {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self {
+            tx,
+            next_sequence: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Publish an event to current subscribers. The counter is held while sending,
+    /// so subscribers receive events in sequence order.
+    pub fn send(&self, event: NodeEvent) {
+        let mut next_sequence = self.next_sequence.lock().unwrap();
+        // Sending fails only when there are no subscribers, the event still uses up its sequence number
+        let _ = self.tx.send(SequencedEvent {
+            sequence: *next_sequence,
+            event,
+        });
+        *next_sequence += 1;
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SequencedEvent> {
+        self.tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(done: u32) -> NodeEvent {
+        NodeEvent::ReindexProgress { done, total: 10 }
+    }
+
+    #[test]
+    fn test_sequence_numbers_increase_by_one() {
+        let event_tx = EventSender::new(EVENT_CHANNEL_CAPACITY);
+        let mut event_rx = event_tx.subscribe();
+        let clone = event_tx.clone();
+
+        event_tx.send(progress(1));
+        clone.send(progress(2));
+        event_tx.send(progress(3));
+
+        let sequences: Vec<u64> = std::iter::from_fn(|| event_rx.try_recv().ok())
+            .map(|event| event.sequence)
+            .collect();
+        assert_eq!(sequences, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_lagged_subscriber_sees_gap_in_sequence_numbers() {
+        let event_tx = EventSender::new(2);
+        let mut event_rx = event_tx.subscribe();
+
+        event_tx.send(progress(1));
+        let first = event_rx.try_recv().unwrap();
+        assert_eq!(first.sequence, 0);
+
+        // The subscriber falls behind by more than the channel capacity
+        for done in 2..=6 {
+            event_tx.send(progress(done));
+        }
+        assert!(matches!(
+            event_rx.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(3))
+        ));
+
+        let next = event_rx.try_recv().unwrap();
+        assert_eq!(next.sequence, 4);
+        assert_eq!(next.event, progress(5));
+        assert_eq!(
+            next.sequence - first.sequence - 1,
+            3,
+            "missed events 1 to 3"
+        );
+    }
+}
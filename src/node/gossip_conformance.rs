@@ -0,0 +1,148 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use libp2p::gossipsub::TopicHash;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// What the node does with gossip that breaks the protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GossipAnomalyAction {
+    /// Log the anomaly and handle the message as usual
+    #[default]
+    Log,
+    /// Log the anomaly and drop the message
+    Drop,
+    /// Log the anomaly, drop the message and disconnect and ban the peer that sent it
+    Disconnect,
+}
+
+/// Gossip a well behaved peer would not send us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GossipAnomaly {
+    /// The message is on a topic the pool does not use
+    UnexpectedTopic(TopicHash),
+    /// The peer forwarded a message on a topic it never told us it is subscribed to
+    NotSubscribed(TopicHash),
+}
+
+impl std::fmt::Display for GossipAnomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GossipAnomaly::UnexpectedTopic(topic) => {
+                write!(f, "message on unexpected topic {topic}")
+            }
+            GossipAnomaly::NotSubscribed(topic) => {
+                write!(f, "message on topic {topic} the peer is not subscribed to")
+            }
+        }
+    }
+}
+
+/// The topics each connected peer told us it is subscribed to, from gossipsub Subscribed and Unsubscribed events
+#[derive(Debug, Default)]
+pub struct TopicSubscriptions {
+    peers: HashMap<PeerId, HashSet<TopicHash>>,
+}
+
+impl TopicSubscriptions {
+    pub fn subscribed(&mut self, peer_id: PeerId, topic: TopicHash) {
+        self.peers.entry(peer_id).or_default().insert(topic);
+    }
+
+    pub fn unsubscribed(&mut self, peer_id: &PeerId, topic: &TopicHash) {
+        if let Some(topics) = self.peers.get_mut(peer_id) {
+            topics.remove(topic);
+        }
+    }
+
+    /// Forget a peer once its last connection closes
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+    }
+
+    pub fn is_subscribed(&self, peer_id: &PeerId, topic: &TopicHash) -> bool {
+        self.peers
+            .get(peer_id)
+            .is_some_and(|topics| topics.contains(topic))
+    }
+
+    /// Check a message forwarded by propagation_source is on one of our topics, and one the peer subscribed to
+    pub fn check_message(
+        &self,
+        propagation_source: &PeerId,
+        topic: &TopicHash,
+        expected_topics: &[TopicHash],
+    ) -> Option<GossipAnomaly> {
+        if !expected_topics.contains(topic) {
+            return Some(GossipAnomaly::UnexpectedTopic(topic.clone()));
+        }
+        if !self.is_subscribed(propagation_source, topic) {
+            return Some(GossipAnomaly::NotSubscribed(topic.clone()));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_from_peer_that_never_subscribed_is_flagged() {
+        let share_topic = TopicHash::from_raw("share");
+        let expected_topics = [share_topic.clone()];
+        let mut subscriptions = TopicSubscriptions::default();
+        let subscriber = PeerId::random();
+        let stranger = PeerId::random();
+        subscriptions.subscribed(subscriber, share_topic.clone());
+
+        assert_eq!(
+            subscriptions.check_message(&subscriber, &share_topic, &expected_topics),
+            None
+        );
+        assert_eq!(
+            subscriptions.check_message(&stranger, &share_topic, &expected_topics),
+            Some(GossipAnomaly::NotSubscribed(share_topic.clone()))
+        );
+
+        // Unsubscribing or disconnecting makes the peer's messages anomalous again
+        subscriptions.unsubscribed(&subscriber, &share_topic);
+        assert_eq!(
+            subscriptions.check_message(&subscriber, &share_topic, &expected_topics),
+            Some(GossipAnomaly::NotSubscribed(share_topic.clone()))
+        );
+        subscriptions.subscribed(subscriber, share_topic.clone());
+        subscriptions.remove_peer(&subscriber);
+        assert!(!subscriptions.is_subscribed(&subscriber, &share_topic));
+    }
+
+    #[test]
+    fn test_message_on_unexpected_topic_is_flagged() {
+        let share_topic = TopicHash::from_raw("share");
+        let other_topic = TopicHash::from_raw("other");
+        let mut subscriptions = TopicSubscriptions::default();
+        let peer_id = PeerId::random();
+        subscriptions.subscribed(peer_id, other_topic.clone());
+
+        assert_eq!(
+            subscriptions.check_message(&peer_id, &other_topic, &[share_topic]),
+            Some(GossipAnomaly::UnexpectedTopic(other_topic))
+        );
+    }
+}
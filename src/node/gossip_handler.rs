@@ -14,20 +14,46 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::node::p2p_message_handlers::receivers::share_blocks::handle_share_block;
+use crate::node::metrics::Metrics;
+use crate::node::p2p_message_handlers::receivers::handle_workbase_update;
 use crate::node::Message;
 #[mockall_double::double]
 use crate::shares::chain::actor::ChainHandle;
-use crate::utils::time_provider::SystemTimeProvider;
+use crate::shares::store::ShareProvenance;
+use crate::shares::ShareBlock;
 use libp2p::{gossipsub, PeerId};
 use std::error::Error;
 use tracing::{debug, error, info};
 
+/// Errors from the stages of handling a gossiped message
+#[derive(Debug, thiserror::Error)]
+pub enum GossipError {
+    #[error("Failed to decode gossip message: {0}")]
+    Decode(String),
+    #[error("Error adding share to chain: {0}")]
+    AddShare(String),
+}
+
+/// Outcome of validating a share received over gossip
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShareValidation {
+    /// The share can be added to the chain
+    Valid,
+    /// The share builds on a share too far behind our chain tip, so it was not validated
+    Stale,
+    /// The share failed validation for the given reason
+    Invalid(String),
+}
+
 /// Handle gossipsub events, these are events that are generated by the gossipsub protocol
-/// We need to handle all events that can be gossiped. Currently, we gossip:
+/// Currently, we gossip:
 /// 1. Workbase(MinerWorkbase)
-/// 2. UserWorkbase(UserWorkbase)
-/// 3. MiningShare(ShareBlock)
+/// 2. WorkbaseUpdate, the coinbase changes since a workbase
+/// 3. UserWorkbase(UserWorkbase)
+/// 4. MiningShare(ShareBlock) and TimedMiningShare
+///
+/// Messages are decoded with decode_message. Shares don't come through here, the node queues them with
+/// queue_share_validation and they are added to the chain with apply_share once validated.
 pub async fn handle_gossipsub_event(
     event: gossipsub::Event,
     chain_handle: ChainHandle,
@@ -39,7 +65,13 @@ pub async fn handle_gossipsub_event(
             message_id: _,
             message,
         } => {
-            let message = Message::cbor_deserialize(&message.data).unwrap();
+            let message = match decode_message(&message.data) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("{} from peer: {}", e, propagation_source);
+                    return Err("Failed to decode gossip message".into());
+                }
+            };
             if let Err(e) = handle_gossip_message(message, chain_handle, propagation_source).await {
                 error!("Failed to handle gossip message: {}", e);
                 return Err("Failed to handle gossip message".into());
@@ -53,6 +85,59 @@ pub async fn handle_gossipsub_event(
     }
 }
 
+/// Whether a gossip message was published by us and echoed back by a peer, going by its signed source
+pub fn is_self_echo(message: &gossipsub::Message, local_peer_id: &PeerId) -> bool {
+    message.source.as_ref() == Some(local_peer_id)
+}
+
+/// Count a received gossip message in metrics, unless it is our own message echoed back.
+/// Returns false for echoes, so the caller can keep them out of the sending peer's stats too.
+pub fn record_received_gossip(
+    message: &gossipsub::Message,
+    local_peer_id: &PeerId,
+    metrics: &Metrics,
+) -> bool {
+    if is_self_echo(message, local_peer_id) {
+        return false;
+    }
+    metrics.record_gossip_received(&message.topic.to_string(), message.data.len());
+    true
+}
+
+/// Decode the payload of a gossipsub message
+pub fn decode_message(data: &[u8]) -> Result<Message, GossipError> {
+    Message::cbor_deserialize(data).map_err(|e| GossipError::Decode(e.to_string()))
+}
+
+/// Check if a share builds on a share too far behind our chain tip to be worth validating
+/// Shares building on a share we don't have are not stale, as we can't tell how far behind they are.
+pub async fn is_stale_share(
+    share: &ShareBlock,
+    chain_handle: &ChainHandle,
+    max_gossip_lag: u32,
+) -> bool {
+    let prev_share_blockhash = match share.header.prev_share_blockhash {
+        Some(prev_share_blockhash) => prev_share_blockhash,
+        None => return false,
+    };
+    match chain_handle.get_depth(prev_share_blockhash).await {
+        Some(depth) => depth > max_gossip_lag as usize,
+        None => false,
+    }
+}
+
+/// Add a validated share to the chain, recording the peer that delivered it
+pub async fn apply_share(
+    share: ShareBlock,
+    peer_id: PeerId,
+    chain_handle: &ChainHandle,
+) -> Result<(), GossipError> {
+    chain_handle
+        .add_share_with_provenance(share, ShareProvenance::Peer(peer_id))
+        .await
+        .map_err(|e| GossipError::AddShare(e.to_string()))
+}
+
 async fn handle_gossip_message(
     message: Message,
     chain_handle: ChainHandle,
@@ -71,6 +156,13 @@ async fn handle_gossip_message(
             }
             Ok(())
         }
+        Message::WorkbaseUpdate {
+            base_workinfoid,
+            coinbase_delta,
+        } => {
+            handle_workbase_update(base_workinfoid, coinbase_delta, &chain_handle).await?;
+            Ok(())
+        }
         Message::UserWorkbase(user_workbase) => {
             info!("Handling user workbase: {:?}", user_workbase);
             if let Err(e) = chain_handle.add_user_workbase(user_workbase).await {
@@ -79,15 +171,6 @@ async fn handle_gossip_message(
             }
             Ok(())
         }
-        Message::MiningShare(mining_share) => {
-            info!("Handling mining share: {:?}", mining_share);
-            let time_provider = SystemTimeProvider {};
-            if let Err(e) = handle_share_block(mining_share, chain_handle, &time_provider).await {
-                error!("Failed to add share: {}", e);
-                return Err(format!("Failed to add share, Error: {}", e).into());
-            }
-            Ok(())
-        }
         _ => {
             // Quietly skip all other Message types
             Ok(())
@@ -99,6 +182,8 @@ async fn handle_gossip_message(
 mod tests {
     use super::*;
     use crate::shares::miner_message::{CkPoolMessage, MinerWorkbase, UserWorkbase};
+    use crate::shares::store::WorkbaseOutcome;
+    use crate::shares::ShareBlockHash;
     use crate::test_utils::TestBlockBuilder;
     use libp2p::gossipsub::{MessageId, TopicHash};
     use libp2p::PeerId;
@@ -114,7 +199,7 @@ mod tests {
             .expect_add_workbase()
             .with(mockall::predicate::eq(workbase.clone()))
             .times(1)
-            .returning(|_| Ok(()));
+            .returning(|_| Ok(WorkbaseOutcome::Added));
 
         let message = Message::Workbase(workbase).cbor_serialize().unwrap();
 
@@ -178,7 +263,7 @@ mod tests {
             .expect_add_workbase()
             .with(mockall::predicate::eq(workbase.clone()))
             .times(1)
-            .returning(|_| Ok(()));
+            .returning(|_| Ok(WorkbaseOutcome::Added));
 
         let result =
             handle_gossip_message(Message::Workbase(workbase), mock_chain, PeerId::random()).await;
@@ -268,26 +353,120 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_decode_message() {
+        let json_str = include_str!("../../tests/test_data/simple_miner_workbase.json");
+        let workbase: MinerWorkbase = serde_json::from_str(json_str).unwrap();
+        let data = Message::Workbase(workbase.clone())
+            .cbor_serialize()
+            .unwrap();
+        match decode_message(&data) {
+            Ok(Message::Workbase(decoded)) => assert_eq!(decoded, workbase),
+            other => panic!("Expected a workbase, got {:?}", other),
+        }
+
+        assert!(matches!(
+            decode_message(&[0xff, 0x00, 0x01]),
+            Err(GossipError::Decode(_))
+        ));
+    }
+
     #[tokio::test]
-    async fn test_handle_gossip_message_mining_share_calls_handle_share_block_but_returns_error_with_validation_error(
-    ) {
-        let mock_chain = ChainHandle::default();
+    async fn test_handle_gossip_event_with_undecodable_message_returns_error() {
+        let event = gossipsub::Event::Message {
+            propagation_source: PeerId::random(),
+            message_id: MessageId::new(b"test"),
+            message: gossipsub::Message {
+                source: Some(PeerId::random()),
+                data: vec![0xff, 0x00, 0x01],
+                sequence_number: Some(0),
+                topic: TopicHash::from_raw("share"),
+            },
+        };
 
-        let share_block = TestBlockBuilder::new()
-            .blockhash("00".repeat(32).as_str())
-            .prev_share_blockhash("00".repeat(32).as_str().into())
-            .build();
+        let result = handle_gossipsub_event(event, ChainHandle::default()).await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Failed to decode gossip message"
+        );
+    }
 
-        let result = handle_gossip_message(
-            Message::MiningShare(share_block),
-            mock_chain,
-            PeerId::random(),
-        )
-        .await;
-        assert!(result.is_err());
+    #[tokio::test]
+    async fn test_apply_share() {
+        let mut mock_chain = ChainHandle::default();
+        let peer_id = PeerId::random();
+        let share_block = TestBlockBuilder::new().build();
+
+        mock_chain
+            .expect_add_share_with_provenance()
+            .with(
+                mockall::predicate::eq(share_block.clone()),
+                mockall::predicate::eq(ShareProvenance::Peer(peer_id)),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+        assert!(apply_share(share_block, peer_id, &mock_chain).await.is_ok());
+
+        let mut mock_chain = ChainHandle::default();
+        mock_chain
+            .expect_add_share_with_provenance()
+            .returning(|_, _| Err("store is closed".into()));
+        let result = apply_share(TestBlockBuilder::new().build(), peer_id, &mock_chain).await;
         assert_eq!(
             result.unwrap_err().to_string(),
-            "Failed to add share, Error: Share block validation failed"
+            "Error adding share to chain: store is closed"
         );
     }
+
+    #[tokio::test]
+    async fn test_is_stale_share() {
+        let mut mock_chain = ChainHandle::default();
+        mock_chain
+            .expect_get_depth()
+            .with(mockall::predicate::eq(ShareBlockHash::from(
+                "00".repeat(32).as_str(),
+            )))
+            .returning(|_| Some(10));
+        mock_chain
+            .expect_get_depth()
+            .with(mockall::predicate::eq(ShareBlockHash::from(
+                "01".repeat(32).as_str(),
+            )))
+            .returning(|_| None);
+
+        let within_lag = TestBlockBuilder::new()
+            .prev_share_blockhash("00".repeat(32).as_str().into())
+            .build();
+        let unknown_prev = TestBlockBuilder::new()
+            .prev_share_blockhash("01".repeat(32).as_str().into())
+            .build();
+
+        assert!(!is_stale_share(&within_lag, &mock_chain, 10).await);
+        assert!(is_stale_share(&within_lag, &mock_chain, 9).await);
+        assert!(!is_stale_share(&unknown_prev, &mock_chain, 0).await);
+    }
+
+    #[test]
+    fn test_self_echoed_message_is_not_counted_as_received() {
+        let metrics = Metrics::new();
+        let local_peer_id = PeerId::random();
+        let message = |source| gossipsub::Message {
+            source: Some(source),
+            data: vec![1, 2, 3],
+            sequence_number: Some(0),
+            topic: TopicHash::from_raw("share"),
+        };
+
+        let echo = message(local_peer_id);
+        assert!(is_self_echo(&echo, &local_peer_id));
+        assert!(!record_received_gossip(&echo, &local_peer_id, &metrics));
+        assert!(!metrics.snapshot().topics.contains_key("share"));
+
+        let from_peer = message(PeerId::random());
+        assert!(!is_self_echo(&from_peer, &local_peer_id));
+        assert!(record_received_gossip(&from_peer, &local_peer_id, &metrics));
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.topics["share"].messages_received, 1);
+        assert_eq!(snapshot.topics["share"].bytes_received, 3);
+    }
 }
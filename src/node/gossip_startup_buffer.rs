@@ -0,0 +1,118 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::{Duration, Instant};
+
+/// Most messages buffered before a peer joins, later messages are dropped
+pub const MAX_STARTUP_GOSSIP_MESSAGES: usize = 1000;
+
+/// Holds share topic messages that failed to publish right after startup because no peer had joined
+/// our gossipsub mesh yet, so a fresh node doesn't lose its miners' first shares.
+/// Messages are only buffered until the startup window ends. They are published once a peer subscribes
+/// to the share topic, and dropped if the window ends before any peer does.
+#[derive(Debug)]
+pub struct GossipStartupBuffer {
+    until: Instant,
+    messages: Vec<Vec<u8>>,
+}
+
+impl GossipStartupBuffer {
+    pub fn new(window: Duration, now: Instant) -> Self {
+        Self {
+            until: now + window,
+            messages: Vec::new(),
+        }
+    }
+
+    /// Whether the startup window is still open at now
+    pub fn is_open(&self, now: Instant) -> bool {
+        now < self.until
+    }
+
+    /// Buffer a message that failed to publish. Returns false if the message was not buffered,
+    /// because the window has ended or the buffer is full.
+    pub fn push(&mut self, message: Vec<u8>, now: Instant) -> bool {
+        if !self.is_open(now) || self.messages.len() >= MAX_STARTUP_GOSSIP_MESSAGES {
+            return false;
+        }
+        self.messages.push(message);
+        true
+    }
+
+    /// Take the buffered messages to publish, in the order they were buffered
+    pub fn take(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.messages)
+    }
+
+    /// Drop the buffered messages once the window has ended, returning how many were dropped
+    pub fn expire(&mut self, now: Instant) -> usize {
+        if self.is_open(now) {
+            return 0;
+        }
+        self.take().len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_submitted_before_mesh_forms_is_published_once_peer_joins() {
+        let start = Instant::now();
+        let mut buffer = GossipStartupBuffer::new(Duration::from_secs(30), start);
+        let mut published = Vec::new();
+
+        // No peer yet, publishing fails and the share is buffered
+        assert!(buffer.push(b"share1".to_vec(), start));
+        assert!(buffer.push(b"share2".to_vec(), start + Duration::from_secs(1)));
+        assert!(published.is_empty());
+
+        // A peer subscribes to the share topic, so the buffered shares are published in order
+        published.extend(buffer.take());
+        assert_eq!(published, vec![b"share1".to_vec(), b"share2".to_vec()]);
+        assert!(buffer.is_empty());
+
+        // Nothing is buffered once the window ends
+        assert!(!buffer.push(b"share3".to_vec(), start + Duration::from_secs(30)));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_buffered_messages_expire_with_window_and_buffer_is_bounded() {
+        let start = Instant::now();
+        let mut buffer = GossipStartupBuffer::new(Duration::from_secs(30), start);
+        for _ in 0..MAX_STARTUP_GOSSIP_MESSAGES {
+            assert!(buffer.push(b"share".to_vec(), start));
+        }
+        assert!(!buffer.push(b"share".to_vec(), start));
+
+        assert_eq!(buffer.expire(start + Duration::from_secs(29)), 0);
+        assert_eq!(
+            buffer.expire(start + Duration::from_secs(30)),
+            MAX_STARTUP_GOSSIP_MESSAGES
+        );
+        assert!(buffer.is_empty());
+    }
+}
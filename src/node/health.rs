@@ -0,0 +1,219 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Window over which dropped command responses are counted
+pub const DROPPED_RESPONSE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Whether the node is keeping up with its callers and connected to enough peers to take traffic
+/// Getting any status back shows the node is alive, whatever its peer count.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum HealthStatus {
+    Healthy,
+    /// Callers are giving up on commands, a sign the node's event loop is overloaded
+    Degraded {
+        /// Command responses dropped in the last DROPPED_RESPONSE_WINDOW
+        dropped_responses: usize,
+    },
+    /// No share has been accepted for longer than network.max_share_gap_secs, miners may have disconnected
+    /// or the node may be partitioned from the pool
+    Stale {
+        secs_since_last_share: u64,
+    },
+    /// The node keeps up but has fewer peers connected than network.min_healthy_peers
+    UnderConnected {
+        connected_peers: usize,
+        min_healthy_peers: u32,
+    },
+}
+
+impl HealthStatus {
+    /// Whether the node is ready for production traffic, only when healthy
+    pub fn is_ready(&self) -> bool {
+        matches!(self, HealthStatus::Healthy)
+    }
+
+    /// Take the time since the last accepted share into account. An otherwise healthy node is stale once
+    /// the gap is longer than max_share_gap_secs, other statuses are kept. A node that has not accepted
+    /// a share yet is not stale, and a max_share_gap_secs of 0 never makes it stale.
+    pub fn with_last_share(
+        self,
+        since_last_share: Option<Duration>,
+        max_share_gap_secs: u64,
+    ) -> Self {
+        match (self, since_last_share) {
+            (HealthStatus::Healthy, Some(gap))
+                if max_share_gap_secs > 0 && gap.as_secs() > max_share_gap_secs =>
+            {
+                HealthStatus::Stale {
+                    secs_since_last_share: gap.as_secs(),
+                }
+            }
+            (status, _) => status,
+        }
+    }
+
+    /// Take the connected peers into account. An otherwise healthy node is under connected while fewer than
+    /// min_healthy_peers are connected, other statuses are kept.
+    pub fn with_peers(self, connected_peers: usize, min_healthy_peers: u32) -> Self {
+        match self {
+            HealthStatus::Healthy if connected_peers < min_healthy_peers as usize => {
+                HealthStatus::UnderConnected {
+                    connected_peers,
+                    min_healthy_peers,
+                }
+            }
+            status => status,
+        }
+    }
+}
+
+/// Counts command responses that could not be delivered because the caller dropped its receiver,
+/// usually after giving up waiting on a slow node. More than the configured number in DROPPED_RESPONSE_WINDOW
+/// marks the node degraded, until enough of them age out of the window.
+#[derive(Debug, Default)]
+pub struct DroppedResponses {
+    /// Times of the dropped responses in the window, oldest first
+    dropped_at: VecDeque<Instant>,
+    /// Whether the last count was past the limit, so the overload warning is logged once per episode
+    over_limit: bool,
+}
+
+impl DroppedResponses {
+    /// Record a dropped response at now, warning when this takes the count past max_per_window.
+    /// A max_per_window of 0 never reports the node degraded.
+    pub fn record(&mut self, now: Instant, max_per_window: u32) {
+        self.dropped_at.push_back(now);
+        let dropped = self.count(now);
+        if max_per_window > 0 && dropped > max_per_window as usize && !self.over_limit {
+            warn!(
+                "{} command responses dropped in the last {:?}, callers are giving up on an overloaded node",
+                dropped, DROPPED_RESPONSE_WINDOW
+            );
+            self.over_limit = true;
+        }
+    }
+
+    /// Health given the responses dropped in the window ending at now
+    pub fn status(&mut self, now: Instant, max_per_window: u32) -> HealthStatus {
+        let dropped = self.count(now);
+        self.over_limit = max_per_window > 0 && dropped > max_per_window as usize;
+        if self.over_limit {
+            HealthStatus::Degraded {
+                dropped_responses: dropped,
+            }
+        } else {
+            HealthStatus::Healthy
+        }
+    }
+
+    /// Drop the responses that aged out of the window ending at now and count the rest
+    fn count(&mut self, now: Instant) -> usize {
+        while let Some(oldest) = self.dropped_at.front() {
+            if now.duration_since(*oldest) < DROPPED_RESPONSE_WINDOW {
+                break;
+            }
+            self.dropped_at.pop_front();
+        }
+        self.dropped_at.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dropped_responses_past_limit_degrade_until_they_age_out() {
+        let start = Instant::now();
+        let mut dropped = DroppedResponses::default();
+        for _ in 0..3 {
+            dropped.record(start, 3);
+        }
+        assert_eq!(dropped.status(start, 3), HealthStatus::Healthy);
+
+        dropped.record(start + Duration::from_secs(1), 3);
+        assert_eq!(
+            dropped.status(start + Duration::from_secs(1), 3),
+            HealthStatus::Degraded {
+                dropped_responses: 4
+            }
+        );
+        // Without a limit the node is never degraded
+        assert_eq!(
+            dropped.status(start + Duration::from_secs(1), 0),
+            HealthStatus::Healthy
+        );
+
+        // The first three age out of the window
+        assert_eq!(
+            dropped.status(start + DROPPED_RESPONSE_WINDOW, 3),
+            HealthStatus::Healthy
+        );
+    }
+
+    #[test]
+    fn test_node_is_ready_only_with_min_healthy_peers_connected() {
+        let below = HealthStatus::Healthy.with_peers(1, 2);
+        assert_eq!(
+            below,
+            HealthStatus::UnderConnected {
+                connected_peers: 1,
+                min_healthy_peers: 2
+            }
+        );
+        assert!(!below.is_ready());
+
+        assert!(HealthStatus::Healthy.with_peers(2, 2).is_ready());
+        assert!(HealthStatus::Healthy.with_peers(3, 2).is_ready());
+        assert!(HealthStatus::Healthy.with_peers(0, 0).is_ready());
+
+        // An overloaded node stays degraded whatever its peers
+        let degraded = HealthStatus::Degraded {
+            dropped_responses: 4,
+        };
+        assert_eq!(degraded.clone().with_peers(3, 2), degraded);
+        assert!(!degraded.is_ready());
+    }
+
+    #[test]
+    fn test_long_gap_since_last_share_makes_node_stale() {
+        let gap = Some(Duration::from_secs(601));
+        assert_eq!(
+            HealthStatus::Healthy.with_last_share(gap, 600),
+            HealthStatus::Stale {
+                secs_since_last_share: 601
+            }
+        );
+        assert_eq!(
+            HealthStatus::Healthy.with_last_share(Some(Duration::from_secs(600)), 600),
+            HealthStatus::Healthy
+        );
+        // No share yet, or no limit, is never stale
+        assert_eq!(
+            HealthStatus::Healthy.with_last_share(None, 600),
+            HealthStatus::Healthy
+        );
+        assert_eq!(
+            HealthStatus::Healthy.with_last_share(gap, 0),
+            HealthStatus::Healthy
+        );
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Number of requests from each peer that are being handled
+/// The count is shared with the tasks handling the requests, which end their request by
+/// dropping the InflightRequest they were given.
+#[derive(Debug, Clone, Default)]
+pub struct InflightRequests {
+    counts: Arc<Mutex<HashMap<PeerId, usize>>>,
+}
+
+/// A request being handled, counted against its peer until dropped
+#[derive(Debug)]
+pub struct InflightRequest {
+    counts: Arc<Mutex<HashMap<PeerId, usize>>>,
+    peer_id: PeerId,
+}
+
+impl InflightRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a request from peer_id if it has fewer than max_per_peer requests being handled
+    /// Returns None when the peer is at its limit, other peers are not affected.
+    pub fn try_start(&self, peer_id: PeerId, max_per_peer: u32) -> Option<InflightRequest> {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(peer_id).or_insert(0);
+        if *count >= max_per_peer as usize {
+            return None;
+        }
+        *count += 1;
+        Some(InflightRequest {
+            counts: self.counts.clone(),
+            peer_id,
+        })
+    }
+
+    /// Number of requests from peer_id being handled
+    pub fn count(&self, peer_id: &PeerId) -> usize {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+impl Drop for InflightRequest {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.peer_id) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.peer_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_over_cap_is_throttled_while_other_peers_are_not() {
+        let inflight = InflightRequests::new();
+        let busy_peer = PeerId::random();
+        let other_peer = PeerId::random();
+
+        let first = inflight.try_start(busy_peer, 2).unwrap();
+        let _second = inflight.try_start(busy_peer, 2).unwrap();
+        assert!(inflight.try_start(busy_peer, 2).is_none());
+        assert_eq!(inflight.count(&busy_peer), 2);
+
+        let _other = inflight.try_start(other_peer, 2).unwrap();
+        assert_eq!(inflight.count(&other_peer), 1);
+
+        // Finishing a request makes room for the next one
+        drop(first);
+        assert_eq!(inflight.count(&busy_peer), 1);
+        assert!(inflight.try_start(busy_peer, 2).is_some());
+    }
+
+    #[test]
+    fn test_finished_requests_are_removed_from_counts() {
+        let inflight = InflightRequests::new();
+        let peer_id = PeerId::random();
+
+        let request = inflight.try_start(peer_id, 1).unwrap();
+        drop(request);
+        assert_eq!(inflight.count(&peer_id), 0);
+        assert!(inflight.counts.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use libp2p::core::transport::ListenerId;
+use libp2p::Multiaddr;
+use std::collections::HashMap;
+
+/// The listeners the node opened and the address each was asked to listen on,
+/// so the node can tell when it stops listening and gets no more inbound connections.
+#[derive(Debug, Default)]
+pub struct Listeners {
+    open: HashMap<ListenerId, Multiaddr>,
+}
+
+impl Listeners {
+    /// Remember a listener we opened
+    pub fn opened(&mut self, listener_id: ListenerId, address: Multiaddr) {
+        self.open.insert(listener_id, address);
+    }
+
+    /// Forget a listener that closed, returning the address it was listening on
+    pub fn closed(&mut self, listener_id: &ListenerId) -> Option<Multiaddr> {
+        self.open.remove(listener_id)
+    }
+
+    /// Whether any listener is still open
+    pub fn is_listening(&self) -> bool {
+        !self.open.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listener_closure_marks_node_not_listening() {
+        let mut listeners = Listeners::default();
+        assert!(!listeners.is_listening());
+        let ipv4 = ListenerId::next();
+        let ipv6 = ListenerId::next();
+        let ipv4_addr: Multiaddr = "/ip4/0.0.0.0/tcp/6884".parse().unwrap();
+        listeners.opened(ipv4, ipv4_addr.clone());
+        listeners.opened(ipv6, "/ip6/::/tcp/6884".parse().unwrap());
+        assert!(listeners.is_listening());
+
+        assert_eq!(listeners.closed(&ipv4), Some(ipv4_addr));
+        assert!(listeners.is_listening());
+        // A listener we don't know about closing changes nothing
+        assert_eq!(listeners.closed(&ListenerId::next()), None);
+
+        listeners.closed(&ipv6);
+        assert!(!listeners.is_listening());
+    }
+}
@@ -14,9 +14,16 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::shares::miner_message::{MinerWorkbase, UserWorkbase};
-use crate::shares::{ShareBlock, ShareBlockHash, ShareHeader};
-use bitcoin::Txid;
+use crate::shares::chain::{ChainCursor, ChainPage};
+use crate::shares::genesis::GENESIS_PUBLIC_KEY;
+use crate::shares::miner_message::{CoinbaseDelta, Gbt, MinerWorkbase, UserWorkbase};
+use crate::shares::{ShareBlock, ShareBlockHash, ShareHeader, StorageShareBlock};
+use crate::utils::serde_support::backend::{Bincode, Cbor, SerializationBackend};
+use crate::utils::serde_support::decimal::deserialize_decimal;
+use bitcoin::absolute::Time;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::{PublicKey, Txid};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 
@@ -33,33 +40,218 @@ pub enum Message {
     ShareBlock(ShareBlock),
     GetData(GetData),
     Workbase(MinerWorkbase),
+    /// A workbase differing from a stored base workbase only in its coinbase, sent instead of the full workbase.
+    /// The receiver rebuilds and stores the full workbase, and can't if it doesn't have the base.
+    WorkbaseUpdate {
+        base_workinfoid: u64,
+        coinbase_delta: CoinbaseDelta,
+    },
     UserWorkbase(UserWorkbase),
     Transaction(bitcoin::Transaction),
     MiningShare(ShareBlock),
+    /// Mining share stamped with the milliseconds since epoch its originating node gossiped it at.
+    /// Sent instead of MiningShare when propagation latency measurement is enabled.
+    TimedMiningShare {
+        share: ShareBlock,
+        origin_millis: u64,
+    },
+    /// Lightweight probe used to measure round trip times, carries a nonce echoed back in the Pong
+    Ping(u64),
+    /// Reply to a Ping, with the responder's clock in milliseconds since epoch so peers can estimate clock skew
+    Pong {
+        nonce: u64,
+        time_millis: u64,
+    },
+    /// Our chain tip, its cumulative difficulty and height, exchanged when a connection is established.
+    /// Only the node with less work syncs from the other.
+    ChainState {
+        tip: Option<ShareBlockHash>,
+        #[serde(deserialize_with = "deserialize_decimal")]
+        work: Decimal,
+        height: Option<u32>,
+    },
+    /// Request the page of the chain starting at a cursor, for bulk sync.
+    /// The requester pulls pages one at a time, following each page's next cursor until it is None.
+    GetChainFrom(ChainCursor),
+    /// Request the first page of the chain above the common ancestor with our locator, to start a bulk sync.
+    /// The following pages are pulled with GetChainFrom.
+    GetChainFromLocator(Vec<ShareBlockHash>),
+    /// Response to GetChainFrom and GetChainFromLocator, bounded in share count and serialized size
+    ChainPage(ChainPage),
+    /// Response to a request from a peer that already has max_inflight_requests_per_peer requests
+    /// being handled. The request was not handled and can be sent again later.
+    Busy,
+    /// Pool wide operational message, signed by an operator key and gossiped on the announcement topic
+    Announcement {
+        payload: String,
+        signature: Vec<u8>,
+    },
 }
 
 impl Message {
-    /// Serialize the message to CBOR bytes
+    /// Serialize the message with a serialization backend
+    pub fn serialize_with<B: SerializationBackend>(
+        &self,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        B::serialize(self)
+    }
+
+    /// Deserialize a message serialized with a serialization backend
+    pub fn deserialize_with<B: SerializationBackend>(
+        bytes: &[u8],
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        B::deserialize(bytes)
+    }
+
+    /// Serialize the message to CBOR bytes, the wire format
     pub fn cbor_serialize(&self) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut buf = Vec::new();
-        if let Err(e) = ciborium::ser::into_writer(&self, &mut buf) {
-            return Err(e.into());
-        }
-        Ok(buf)
+        self.serialize_with::<Cbor>()
+            .map_err(|e| -> Box<dyn Error> { e })
     }
 
     /// Deserialize a message from CBOR bytes
     pub fn cbor_deserialize(bytes: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
-        match ciborium::de::from_reader(bytes) {
-            Ok(msg) => Ok(msg),
-            Err(e) => Err(e.into()),
+        Self::deserialize_with::<Cbor>(bytes)
+    }
+
+    /// Serialize the message to bincode bytes, for messages kept on this node. Peers expect CBOR.
+    /// Workbase messages can't be deserialized from bincode, see Bincode.
+    pub fn bincode_serialize(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.serialize_with::<Bincode>()
+    }
+
+    /// Deserialize a message from bincode bytes
+    pub fn bincode_deserialize(bytes: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Self::deserialize_with::<Bincode>(bytes)
+    }
+
+    /// Check representative messages and a stored share against the encodings they had when the wire format
+    /// last changed, so a dependency bump that changes the encoding is caught at startup instead of silently
+    /// breaking the network. Each message is also decoded and encoded again, to catch a decoder mismatch.
+    /// Share hashes are computed from the CBOR encoded share, so the genesis hash is checked too.
+    pub fn self_test() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let public_key = GENESIS_PUBLIC_KEY.parse::<PublicKey>()?;
+        let share = ShareBlock::build_genesis_for_network(public_key, bitcoin::Network::Signet);
+        let blockhash = share.cached_blockhash.unwrap();
+        if blockhash != ShareBlockHash::from(SELF_TEST_GENESIS_HASH) {
+            return Err(format!(
+                "Signet genesis share hashes to {blockhash}, expected {SELF_TEST_GENESIS_HASH}"
+            )
+            .into());
         }
+
+        let messages = [
+            ("ShareBlock", Message::ShareBlock(share.clone())),
+            (
+                "ShareHeaders",
+                Message::ShareHeaders(vec![share.header.clone()]),
+            ),
+            ("Workbase", Message::Workbase(self_test_workbase()?)),
+            (
+                "Inventory",
+                Message::Inventory(InventoryMessage::BlockHashes(vec![blockhash])),
+            ),
+            (
+                "ChainState",
+                Message::ChainState {
+                    tip: Some(blockhash),
+                    work: share.header.miner_share.diff,
+                    height: Some(0),
+                },
+            ),
+        ];
+        for ((name, message), expected) in messages.into_iter().zip(SELF_TEST_MESSAGE_DIGESTS) {
+            let bytes = message
+                .cbor_serialize()
+                .map_err(|e| format!("{name} message failed to serialize: {e}"))?;
+            let digest = sha256::Hash::hash(&bytes).to_string();
+            if digest != expected {
+                return Err(format!(
+                    "{name} message encodes to digest {digest}, expected {expected}"
+                )
+                .into());
+            }
+            let reencoded = Message::cbor_deserialize(&bytes)
+                .map_err(|e| format!("{name} message failed to deserialize: {e}"))?
+                .cbor_serialize()
+                .map_err(|e| format!("{name} message failed to serialize again: {e}"))?;
+            if reencoded != bytes {
+                return Err(format!("{name} message changed in a serialization round trip").into());
+            }
+        }
+
+        let stored = StorageShareBlock::from(share.clone())
+            .cbor_serialize()
+            .map_err(|e| format!("Stored share failed to serialize: {e}"))?;
+        let mut loaded = StorageShareBlock::cbor_deserialize(&stored)
+            .map_err(|e| format!("Stored share failed to deserialize: {e}"))?
+            .into_share_block_with_transactions(share.transactions.clone());
+        loaded.compute_blockhash();
+        if loaded.header != share.header || loaded.cached_blockhash != Some(blockhash) {
+            return Err("Stored share changed in a serialization round trip".into());
+        }
+        Ok(())
     }
 }
 
+/// Hash of the signet genesis share, computed from its CBOR encoded header
+const SELF_TEST_GENESIS_HASH: &str =
+    "a93fc3ede1c185da86c399bdf1cbd36739ac956b3c0953acaf5f84f8782fd1d2";
+
+/// SHA256 of the CBOR encoding of each self test message, in the order Message::self_test builds them
+const SELF_TEST_MESSAGE_DIGESTS: [&str; 5] = [
+    "581f4ed5044f7f1f8511469dce7f8720fa73f5871885844b5d483bdf1a131bbf",
+    "a8c8b3e52ce063f3c67152132b315f6171d4fe338ec04491a673a5b9110099be",
+    "0d0089a9adf874591e13fc10d0945838c71684e5e5d88f8aa41b25d6facd055d",
+    "be1bee39fe737ef88827f2693e74215759b3e51f74d862f6f82732dd0a53bd65",
+    "5bba9a3be2373bb2665030df9e78fc06b9747c777f47d72748257b15e50239df",
+];
+
+/// A signet workbase with a coinbase paying a single output, built in code so the self test doesn't depend on
+/// files shipped alongside the binary
+fn self_test_workbase() -> Result<MinerWorkbase, Box<dyn Error + Send + Sync>> {
+    Ok(MinerWorkbase {
+        workinfoid: 7459044800742817807,
+        gbt: Gbt {
+            capabilities: vec!["proposal".to_string()],
+            version: 536870912,
+            rules: vec!["csv".to_string(), "!segwit".to_string(), "!signet".to_string(), "taproot".to_string()],
+            vbavailable: serde_json::json!({}),
+            vbrequired: 0,
+            previousblockhash: "00000000790ba17d9c06acf8749166014eb1499c8ea6dd598060dbec7eeae808".to_string(),
+            transactions: vec![],
+            coinbaseaux: serde_json::json!({}),
+            coinbasevalue: 5000000000,
+            longpollid: "00000000790ba17d9c06acf8749166014eb1499c8ea6dd598060dbec7eeae8084".to_string(),
+            target: "00000377ae000000000000000000000000000000000000000000000000000000".to_string(),
+            mintime: 1736686858,
+            mutable: vec!["time".to_string(), "transactions".to_string(), "prevblock".to_string()],
+            noncerange: "00000000ffffffff".to_string(),
+            sigoplimit: 80000,
+            sizelimit: 4000000,
+            weightlimit: 4000000,
+            curtime: Time::from_consensus(1736694495)?,
+            bits: "1e0377ae".to_string(),
+            height: 98,
+            signet_challenge: Some("51".to_string()),
+            default_witness_commitment: "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9".to_string(),
+            diff: 0.001126515290698186,
+            ntime: Time::from_consensus(0x6783dadf)?,
+            bbversion: "20000000".to_string(),
+            nbit: "1e0377ae".to_string(),
+        },
+        txns: vec![],
+        merkles: vec![],
+        coinb1: "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff2c017500045967af67041e05861c0c".to_string(),
+        coinb2: "0a636b706f6f6c0a2f7032706f6f6c76322fffffffff030011102401000000".to_string(),
+        coinb3: "00e1f50500000000160014a248cf2f99f449511b22bab1a3d001719f84cd090000000000000000266a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf900000000".to_string(),
+        header: "200000000005eae0c8ca9034d28bb1e42e8787146bac9f91c042cc0d616b001f00000000000000000000000000000000000000000000000000000000000000000000000067af67591e0377ae000000000000008000000000000000000000000000000000000000000000000000000000".to_string(),
+    })
+}
+
 /// The inventory message used to tell a peer what we have in our inventory.
 /// The message can be used to tell the peer about share headers, blocks, or transactions that this peer has.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum InventoryMessage {
     BlockHashes(Vec<ShareBlockHash>),
     TransactionHashes(Vec<Txid>),
@@ -77,6 +269,20 @@ mod tests {
     use super::*;
     use std::str::FromStr;
 
+    #[test]
+    fn test_self_test_round_trips_representative_messages() {
+        Message::self_test().unwrap();
+    }
+
+    #[test]
+    fn test_self_test_workbase_matches_test_data() {
+        let expected: MinerWorkbase = serde_json::from_str(include_str!(
+            "../../tests/test_data/simple_miner_workbase.json"
+        ))
+        .unwrap();
+        assert_eq!(self_test_workbase().unwrap(), expected);
+    }
+
     #[test]
     fn test_inventory_message_serde() {
         let have_shares = vec![
@@ -143,4 +349,75 @@ mod tests {
             _ => panic!("Expected Txid variant"),
         }
     }
+
+    #[test]
+    fn test_chain_state_message_serde() {
+        let tip: ShareBlockHash =
+            "0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5".into();
+        let msg = Message::ChainState {
+            tip: Some(tip),
+            work: Decimal::from_str("12345.678").unwrap(),
+            height: Some(42),
+        };
+
+        let serialized = msg.cbor_serialize().unwrap();
+        let deserialized = Message::cbor_deserialize(&serialized).unwrap();
+        match deserialized {
+            Message::ChainState {
+                tip: deserialized_tip,
+                work,
+                height,
+            } => {
+                assert_eq!(deserialized_tip, Some(tip));
+                assert_eq!(work, Decimal::from_str("12345.678").unwrap());
+                assert_eq!(height, Some(42));
+            }
+            _ => panic!("Expected ChainState variant"),
+        }
+    }
+
+    #[test]
+    fn test_messages_round_trip_through_bincode() {
+        let tip: ShareBlockHash =
+            "0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5".into();
+        let chain_state = Message::ChainState {
+            tip: Some(tip),
+            work: Decimal::from_str("12345.678").unwrap(),
+            height: Some(42),
+        };
+        let serialized = chain_state.bincode_serialize().unwrap();
+        match Message::bincode_deserialize(&serialized).unwrap() {
+            Message::ChainState {
+                tip: deserialized_tip,
+                work,
+                height,
+            } => {
+                assert_eq!(deserialized_tip, Some(tip));
+                assert_eq!(work, Decimal::from_str("12345.678").unwrap());
+                assert_eq!(height, Some(42));
+            }
+            _ => panic!("Expected ChainState variant"),
+        }
+
+        let genesis = ShareBlock::build_genesis_for_network(
+            GENESIS_PUBLIC_KEY.parse().unwrap(),
+            bitcoin::Network::Signet,
+        );
+        let share_message = Message::ShareBlock(genesis.clone());
+        let serialized = share_message.bincode_serialize().unwrap();
+        // Bincode doesn't encode field names, so it is smaller than the wire format
+        assert!(serialized.len() < share_message.cbor_serialize().unwrap().len());
+        match Message::bincode_deserialize(&serialized).unwrap() {
+            Message::ShareBlock(mut share) => {
+                share.compute_blockhash();
+                assert_eq!(share, genesis);
+            }
+            _ => panic!("Expected ShareBlock variant"),
+        }
+
+        // Workbases hold serde_json values, which bincode can't decode
+        let workbase = Message::Workbase(self_test_workbase().unwrap());
+        let serialized = workbase.bincode_serialize().unwrap();
+        assert!(Message::bincode_deserialize(&serialized).is_err());
+    }
 }
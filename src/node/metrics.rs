@@ -0,0 +1,558 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Upper bounds, in milliseconds, of the propagation latency histogram buckets.
+/// Latencies above the last bound are counted in a final overflow bucket.
+pub const PROPAGATION_LATENCY_BUCKETS_MS: [u64; 9] =
+    [10, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000];
+
+/// Latencies longer than this are assumed to come from a badly skewed clock and are not recorded.
+/// Matches the largest timestamp difference share validation tolerates.
+pub const MAX_PROPAGATION_LATENCY_MS: u64 = 60_000;
+
+/// Histogram of the time between a share being gossiped by its originating node and us accepting it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyHistogram {
+    /// Counts per bucket, the last entry counts latencies above the largest bucket bound
+    pub buckets: Vec<u64>,
+    /// Number of latencies recorded
+    pub count: u64,
+    /// Sum of all recorded latencies in milliseconds
+    pub sum_ms: u64,
+    /// Number of shares whose origin time was ahead of our clock, recorded with zero latency
+    pub clock_ahead: u64,
+    /// Number of shares dropped from the histogram for exceeding MAX_PROPAGATION_LATENCY_MS
+    pub discarded: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; PROPAGATION_LATENCY_BUCKETS_MS.len() + 1],
+            count: 0,
+            sum_ms: 0,
+            clock_ahead: 0,
+            discarded: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Record the latency between a share's origin time and the time we accepted it.
+    /// An origin time ahead of our clock can only be clock skew, so it is recorded as zero latency.
+    pub fn record(&mut self, origin_millis: u64, now_millis: u64) {
+        let latency_ms = match now_millis.checked_sub(origin_millis) {
+            Some(latency_ms) => latency_ms,
+            None => {
+                self.clock_ahead += 1;
+                0
+            }
+        };
+        if latency_ms > MAX_PROPAGATION_LATENCY_MS {
+            self.discarded += 1;
+            return;
+        }
+        let bucket = PROPAGATION_LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| latency_ms <= *bound)
+            .unwrap_or(PROPAGATION_LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += latency_ms;
+    }
+}
+
+/// Gossip message counts for a single topic
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TopicMetrics {
+    /// Messages received from peers on the topic
+    pub messages_received: u64,
+    /// Messages we published on the topic
+    pub messages_published: u64,
+    /// Payload bytes of the messages received on the topic
+    pub bytes_received: u64,
+    /// Payload bytes of the messages we published on the topic
+    pub bytes_published: u64,
+    /// Received messages that failed to decode, were rate limited or failed validation
+    pub rejects: u64,
+}
+
+/// A point in time copy of the node's metrics
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    pub propagation_latency: LatencyHistogram,
+    /// Gossip counts keyed by topic
+    pub topics: HashMap<String, TopicMetrics>,
+    /// Responses we sent to peers' requests
+    pub responses_sent: u64,
+    /// Requests from peers we failed to respond to
+    pub inbound_failures: u64,
+    /// Requests we sent that failed, including dial failures and timeouts
+    pub outbound_failures: u64,
+    /// Dials we started, to our dial peers and to peers a behaviour asked us to dial
+    pub dial_attempts: u64,
+}
+
+/// Node and chain state exported as gauges next to the metrics
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeGauges {
+    /// Peers with at least one connection established
+    pub peers: usize,
+    /// Height of the chain tip, None before the chain has any shares
+    pub chain_height: Option<u32>,
+    /// Total difficulty of the main chain
+    pub chain_work: f64,
+}
+
+impl MetricsSnapshot {
+    /// Render the metrics and gauges in the Prometheus text exposition format, ready to serve over HTTP.
+    /// Gossip counters are labelled by topic, the propagation latency is a histogram in milliseconds.
+    pub fn to_prometheus(&self, gauges: &NodeGauges) -> String {
+        let mut out = String::new();
+        write_metric(
+            &mut out,
+            "p2pool_peers",
+            "gauge",
+            "Connected peers",
+            &[(String::new(), gauges.peers as f64)],
+        );
+        if let Some(chain_height) = gauges.chain_height {
+            write_metric(
+                &mut out,
+                "p2pool_chain_height",
+                "gauge",
+                "Height of the chain tip",
+                &[(String::new(), chain_height as f64)],
+            );
+        }
+        write_metric(
+            &mut out,
+            "p2pool_chain_work",
+            "gauge",
+            "Total difficulty of the main chain",
+            &[(String::new(), gauges.chain_work)],
+        );
+        write_metric(
+            &mut out,
+            "p2pool_responses_sent_total",
+            "counter",
+            "Responses sent to peers' requests",
+            &[(String::new(), self.responses_sent as f64)],
+        );
+        write_metric(
+            &mut out,
+            "p2pool_inbound_failures_total",
+            "counter",
+            "Requests from peers we failed to respond to",
+            &[(String::new(), self.inbound_failures as f64)],
+        );
+        write_metric(
+            &mut out,
+            "p2pool_outbound_failures_total",
+            "counter",
+            "Requests we sent that failed",
+            &[(String::new(), self.outbound_failures as f64)],
+        );
+        write_metric(
+            &mut out,
+            "p2pool_dial_attempts_total",
+            "counter",
+            "Dials started to peers",
+            &[(String::new(), self.dial_attempts as f64)],
+        );
+
+        // Sort topics so the output is stable between scrapes
+        let mut topics: Vec<(&String, &TopicMetrics)> = self.topics.iter().collect();
+        topics.sort_by_key(|(topic, _)| *topic);
+        let per_topic = |value: fn(&TopicMetrics) -> u64| -> Vec<(String, f64)> {
+            topics
+                .iter()
+                .map(|(topic, metrics)| {
+                    (
+                        format!("{{topic=\"{}\"}}", escape_label_value(topic)),
+                        value(metrics) as f64,
+                    )
+                })
+                .collect()
+        };
+        write_metric(
+            &mut out,
+            "p2pool_gossip_messages_received_total",
+            "counter",
+            "Gossip messages received by topic",
+            &per_topic(|topic| topic.messages_received),
+        );
+        write_metric(
+            &mut out,
+            "p2pool_gossip_messages_published_total",
+            "counter",
+            "Gossip messages published by topic",
+            &per_topic(|topic| topic.messages_published),
+        );
+        write_metric(
+            &mut out,
+            "p2pool_gossip_bytes_received_total",
+            "counter",
+            "Gossip payload bytes received by topic",
+            &per_topic(|topic| topic.bytes_received),
+        );
+        write_metric(
+            &mut out,
+            "p2pool_gossip_bytes_published_total",
+            "counter",
+            "Gossip payload bytes published by topic",
+            &per_topic(|topic| topic.bytes_published),
+        );
+        write_metric(
+            &mut out,
+            "p2pool_gossip_rejects_total",
+            "counter",
+            "Gossip messages received and rejected by topic",
+            &per_topic(|topic| topic.rejects),
+        );
+
+        // Prometheus histogram buckets are cumulative, ours count each bucket on its own
+        let latency = &self.propagation_latency;
+        let mut cumulative = 0;
+        let mut samples = Vec::new();
+        for (bound, count) in PROPAGATION_LATENCY_BUCKETS_MS.iter().zip(&latency.buckets) {
+            cumulative += count;
+            samples.push((format!("_bucket{{le=\"{bound}\"}}"), cumulative as f64));
+        }
+        samples.push(("_bucket{le=\"+Inf\"}".to_string(), latency.count as f64));
+        samples.push(("_sum".to_string(), latency.sum_ms as f64));
+        samples.push(("_count".to_string(), latency.count as f64));
+        write_metric(
+            &mut out,
+            "p2pool_propagation_latency_ms",
+            "histogram",
+            "Time between a share being gossiped by its origin and us accepting it",
+            &samples,
+        );
+        out
+    }
+}
+
+/// Write a metric's HELP and TYPE lines and its samples, each a suffix with labels appended to the name and a value
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, f64)]) {
+    // Writing to a String can't fail
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (suffix, value) in samples {
+        let _ = writeln!(out, "{name}{suffix} {value}");
+    }
+}
+
+/// Escape a label value as the text exposition format requires
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Metrics recorded by the node, shared with the tasks handling gossip messages
+#[derive(Debug, Default)]
+pub struct Metrics {
+    propagation_latency: Mutex<LatencyHistogram>,
+    topics: Mutex<HashMap<String, TopicMetrics>>,
+    responses_sent: AtomicU64,
+    inbound_failures: AtomicU64,
+    outbound_failures: AtomicU64,
+    dial_attempts: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the propagation latency for a share we accepted
+    pub fn record_propagation_latency(&self, origin_millis: u64, now_millis: u64) {
+        self.propagation_latency
+            .lock()
+            .unwrap()
+            .record(origin_millis, now_millis);
+    }
+
+    /// Count a gossip message received on a topic
+    pub fn record_gossip_received(&self, topic: &str, bytes: usize) {
+        let mut topics = self.topics.lock().unwrap();
+        let topic = topics.entry(topic.to_string()).or_default();
+        topic.messages_received += 1;
+        topic.bytes_received += bytes as u64;
+    }
+
+    /// Count a gossip message we published on a topic
+    pub fn record_gossip_published(&self, topic: &str, bytes: usize) {
+        let mut topics = self.topics.lock().unwrap();
+        let topic = topics.entry(topic.to_string()).or_default();
+        topic.messages_published += 1;
+        topic.bytes_published += bytes as u64;
+    }
+
+    /// Count a gossip message received on a topic that we rejected
+    pub fn record_gossip_reject(&self, topic: &str) {
+        self.topics
+            .lock()
+            .unwrap()
+            .entry(topic.to_string())
+            .or_default()
+            .rejects += 1;
+    }
+
+    /// Count a response we sent to a peer's request
+    pub fn record_response_sent(&self) {
+        self.responses_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a peer's request we failed to respond to
+    pub fn record_inbound_failure(&self) {
+        self.inbound_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a request we sent that failed
+    pub fn record_outbound_failure(&self) {
+        self.outbound_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a dial we started
+    pub fn record_dial_attempt(&self) {
+        self.dial_attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            propagation_latency: self.propagation_latency.lock().unwrap().clone(),
+            topics: self.topics.lock().unwrap().clone(),
+            responses_sent: self.responses_sent.load(Ordering::Relaxed),
+            inbound_failures: self.inbound_failures.load(Ordering::Relaxed),
+            outbound_failures: self.outbound_failures.load(Ordering::Relaxed),
+            dial_attempts: self.dial_attempts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_places_latency_in_bucket() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.record(1_000, 1_010);
+        histogram.record(1_000, 1_300);
+        histogram.record(1_000, 21_000);
+
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.sum_ms, 10 + 300 + 20_000);
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[4], 1);
+        assert_eq!(histogram.buckets[PROPAGATION_LATENCY_BUCKETS_MS.len()], 1);
+    }
+
+    #[test]
+    fn test_record_origin_ahead_of_clock_counts_as_zero_latency() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.record(2_000, 1_000);
+
+        assert_eq!(histogram.clock_ahead, 1);
+        assert_eq!(histogram.count, 1);
+        assert_eq!(histogram.sum_ms, 0);
+        assert_eq!(histogram.buckets[0], 1);
+    }
+
+    #[test]
+    fn test_record_discards_implausible_latency() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.record(0, MAX_PROPAGATION_LATENCY_MS + 1);
+
+        assert_eq!(histogram.discarded, 1);
+        assert_eq!(histogram.count, 0);
+        assert!(histogram.buckets.iter().all(|count| *count == 0));
+    }
+
+    #[test]
+    fn test_metrics_snapshot() {
+        let metrics = Metrics::new();
+        metrics.record_propagation_latency(1_000, 1_050);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.propagation_latency.count, 1);
+        assert_eq!(snapshot.propagation_latency.buckets[1], 1);
+    }
+
+    #[test]
+    fn test_metrics_snapshot_request_response_counts() {
+        let metrics = Metrics::new();
+        metrics.record_response_sent();
+        metrics.record_response_sent();
+        metrics.record_inbound_failure();
+        metrics.record_outbound_failure();
+        metrics.record_dial_attempt();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.responses_sent, 2);
+        assert_eq!(snapshot.inbound_failures, 1);
+        assert_eq!(snapshot.outbound_failures, 1);
+        assert_eq!(snapshot.dial_attempts, 1);
+    }
+
+    #[test]
+    fn test_metrics_snapshot_counts_gossip_per_topic() {
+        let metrics = Metrics::new();
+        metrics.record_gossip_published("share", 100);
+        metrics.record_gossip_published("share", 50);
+        metrics.record_gossip_received("share", 80);
+        metrics.record_gossip_reject("share");
+        metrics.record_gossip_published("announcement", 20);
+        metrics.record_gossip_received("announcement", 30);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(
+            snapshot.topics["share"],
+            TopicMetrics {
+                messages_received: 1,
+                messages_published: 2,
+                bytes_received: 80,
+                bytes_published: 150,
+                rejects: 1,
+            }
+        );
+        assert_eq!(
+            snapshot.topics["announcement"],
+            TopicMetrics {
+                messages_received: 1,
+                messages_published: 1,
+                bytes_received: 30,
+                bytes_published: 20,
+                rejects: 0,
+            }
+        );
+    }
+
+    /// Check every line is a HELP or TYPE comment or a sample of the metric the last TYPE line declared,
+    /// returning the declared metric names
+    fn parse_prometheus_text(text: &str) -> Vec<String> {
+        let is_name = |name: &str| {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+        };
+        let mut declared: Vec<String> = Vec::new();
+        for line in text.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let mut parts = comment.splitn(3, ' ');
+                let keyword = parts.next().unwrap();
+                let name = parts.next().expect("comment without metric name");
+                let rest = parts.next().expect("comment without text");
+                assert!(is_name(name), "invalid metric name in {line}");
+                match keyword {
+                    "HELP" => {}
+                    "TYPE" => {
+                        assert!(["counter", "gauge", "histogram"].contains(&rest));
+                        declared.push(name.to_string());
+                    }
+                    _ => panic!("unexpected comment {line}"),
+                }
+                continue;
+            }
+            let (series, value) = line.rsplit_once(' ').expect("sample without value");
+            value.parse::<f64>().expect("sample value is not a number");
+            let name = match series.split_once('{') {
+                Some((name, labels)) => {
+                    let labels = labels.strip_suffix('}').expect("unterminated labels");
+                    let (label, label_value) = labels.split_once('=').expect("label without value");
+                    assert!(is_name(label), "invalid label name in {line}");
+                    assert!(label_value.starts_with('"') && label_value.ends_with('"'));
+                    name
+                }
+                None => series,
+            };
+            let family = declared.last().expect("sample before TYPE line");
+            assert!(
+                name == family
+                    || name
+                        .strip_prefix(family.as_str())
+                        .is_some_and(|suffix| { ["_bucket", "_sum", "_count"].contains(&suffix) }),
+                "sample {name} does not belong to {family}"
+            );
+        }
+        declared
+    }
+
+    #[test]
+    fn test_to_prometheus_renders_valid_exposition_text() {
+        let metrics = Metrics::new();
+        metrics.record_propagation_latency(1_000, 1_050);
+        metrics.record_propagation_latency(1_000, 1_700);
+        metrics.record_gossip_received("share", 80);
+        metrics.record_gossip_reject("share");
+        metrics.record_gossip_published("announcement", 20);
+        metrics.record_response_sent();
+        let gauges = NodeGauges {
+            peers: 3,
+            chain_height: Some(42),
+            chain_work: 1234.5,
+        };
+
+        let text = metrics.snapshot().to_prometheus(&gauges);
+        let names = parse_prometheus_text(&text);
+        assert_eq!(
+            names,
+            vec![
+                "p2pool_peers",
+                "p2pool_chain_height",
+                "p2pool_chain_work",
+                "p2pool_responses_sent_total",
+                "p2pool_inbound_failures_total",
+                "p2pool_outbound_failures_total",
+                "p2pool_dial_attempts_total",
+                "p2pool_gossip_messages_received_total",
+                "p2pool_gossip_messages_published_total",
+                "p2pool_gossip_bytes_received_total",
+                "p2pool_gossip_bytes_published_total",
+                "p2pool_gossip_rejects_total",
+                "p2pool_propagation_latency_ms",
+            ]
+        );
+        assert!(text.contains("p2pool_peers 3\n"));
+        assert!(text.contains("p2pool_chain_height 42\n"));
+        assert!(text.contains("p2pool_chain_work 1234.5\n"));
+        assert!(text.contains("p2pool_gossip_messages_received_total{topic=\"share\"} 1\n"));
+        assert!(text.contains("p2pool_gossip_rejects_total{topic=\"share\"} 1\n"));
+        assert!(text.contains("p2pool_gossip_messages_published_total{topic=\"announcement\"} 1\n"));
+        // Buckets are cumulative, the 50ms latency is in every bucket from 50ms up
+        assert!(text.contains("p2pool_propagation_latency_ms_bucket{le=\"10\"} 0\n"));
+        assert!(text.contains("p2pool_propagation_latency_ms_bucket{le=\"50\"} 1\n"));
+        assert!(text.contains("p2pool_propagation_latency_ms_bucket{le=\"1000\"} 2\n"));
+        assert!(text.contains("p2pool_propagation_latency_ms_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("p2pool_propagation_latency_ms_sum 750\n"));
+        assert!(text.contains("p2pool_propagation_latency_ms_count 2\n"));
+    }
+
+    #[test]
+    fn test_to_prometheus_escapes_label_values() {
+        assert_eq!(escape_label_value("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -15,37 +15,99 @@
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
 pub mod behaviour;
+pub mod compaction;
 pub mod request_response_handler;
 pub use crate::config::Config;
+use crate::config::{ConfigReload, NetworkConfig};
 pub mod actor;
+pub mod allow_list;
+pub mod announcement;
+pub mod audit;
+pub mod block_found;
+pub mod diagnostics;
+pub mod events;
+pub mod gossip_conformance;
 pub mod gossip_handler;
+pub mod gossip_startup_buffer;
+pub mod health;
+pub mod inflight;
+pub mod listeners;
 pub mod messages;
+pub mod metrics;
+pub mod observed_addresses;
 pub mod p2p_message_handlers;
+pub mod peer_stats;
+pub mod pruning;
 pub mod rate_limiter;
+pub mod reindex;
+pub mod security;
+pub mod share_subscriptions;
+pub mod share_validation;
+pub mod sync_sessions;
+pub mod watchdog;
 
 use crate::node::behaviour::request_response::RequestResponseEvent;
-use crate::node::messages::Message;
-use crate::node::p2p_message_handlers::senders::{send_blocks_inventory, send_getheaders};
+use crate::node::messages::{InventoryMessage, Message};
+use crate::node::p2p_message_handlers::receivers::{
+    handle_chain_page, handle_chain_state_response,
+};
+use crate::node::p2p_message_handlers::senders::{send_blocks_inventory, send_chain_state};
 #[mockall_double::double]
 use crate::shares::chain::actor::ChainHandle;
+use crate::shares::chain::snapshot::{ChainSnapshot, SnapshotError};
+use crate::shares::chain::{ChainStats, DeepReorg, Equivocation, OrphanSummary, Reorg};
 use crate::shares::receive_mining_message::start_receiving_mining_messages;
-use behaviour::{P2PoolBehaviour, P2PoolBehaviourEvent};
-use gossip_handler::handle_gossipsub_event;
+use crate::shares::validation::validate_proof_of_work;
+use crate::shares::{ShareBlock, ShareBlockHash};
+use crate::utils::backoff::Backoff;
+use crate::utils::clock::Clock;
+use crate::utils::log_level::LogLevelHandle;
+use crate::utils::rng::NodeRng;
+use allow_list::allowed_discoveries;
+use announcement::{handle_announcement, ANNOUNCEMENT_TOPIC};
+use behaviour::{P2PoolBehaviour, P2PoolBehaviourEvent, PROTOCOL_VERSION};
+use block_found::{forward_blocks_found, BlockFoundHandler, BlockFoundHandlers};
+use compaction::run_scheduled_compaction;
+use diagnostics::Diagnostics;
+use events::{EventSender, NodeEvent, SequencedEvent, EVENT_CHANNEL_CAPACITY};
+use gossip_conformance::{GossipAnomalyAction, TopicSubscriptions};
+use gossip_handler::{handle_gossipsub_event, record_received_gossip};
+use gossip_startup_buffer::GossipStartupBuffer;
+use health::{DroppedResponses, HealthStatus};
+use inflight::InflightRequests;
+use libp2p::core::transport::ListenerId;
 use libp2p::identify;
 use libp2p::mdns::Event as MdnsEvent;
 use libp2p::request_response::ResponseChannel;
 use libp2p::PeerId;
 use libp2p::{
     gossipsub,
-    kad::{Event as KademliaEvent, QueryResult},
-    swarm::SwarmEvent,
+    kad::{Event as KademliaEvent, GetClosestPeersError, QueryId, QueryResult},
+    swarm::{dial_opts::DialOpts, ConnectionId, DialError, SwarmEvent},
     Multiaddr, Swarm,
 };
+use listeners::Listeners;
+use metrics::{Metrics, MetricsSnapshot};
+use observed_addresses::ObservedAddresses;
+use peer_stats::{NetworkQuality, PeerBreakdown, PeerInfo, PeerOrigin, PeerStats};
+use pruning::run_disk_usage_pruner;
+use rand::rngs::StdRng;
 use rate_limiter::RateLimiter;
+use reindex::run_reindex;
 use request_response_handler::handle_request_response_event;
+use rust_decimal::Decimal;
+use security::{check_connection_security, SECURITY_PROTOCOL};
+use share_subscriptions::{ShareFilter, ShareSubscriptions};
+use share_validation::{run_share_validation, ShareJob, SHARE_VALIDATION_QUEUE_SIZE};
+use std::collections::HashMap;
 use std::error::Error;
-use std::time::Duration;
-use tokio::sync::mpsc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sync_sessions::SyncSessions;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
 use tracing::{debug, error, info, warn};
 
 pub struct SwarmResponseChannel<T> {
@@ -75,25 +137,245 @@ pub enum SwarmSend<C> {
     Response(C, Message),
 }
 
+/// How often the node checks whether it has been without peers for longer than the isolation grace period
+pub const ISOLATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Dial an address, tracking the dial so its outcome counts towards the dial failure rate and the peer
+/// is counted under origin once connected.
+/// The swarm only emits Dialing events for dials started by behaviours, not for our own dials, so the
+/// dial attempt is counted here.
+fn dial_address(
+    swarm: &mut Swarm<P2PoolBehaviour>,
+    peer_stats: &mut PeerStats,
+    metrics: &Metrics,
+    addr: Multiaddr,
+    origin: PeerOrigin,
+) -> Result<ConnectionId, DialError> {
+    debug!("Dialing {}", addr);
+    let opts = DialOpts::from(addr);
+    let connection_id = opts.connection_id();
+    metrics.record_dial_attempt();
+    swarm.dial(opts)?;
+    peer_stats.dial_started(connection_id, origin);
+    Ok(connection_id)
+}
+
+/// TCP transport config with the socket options from the network config
+pub fn tcp_config(network: &NetworkConfig) -> libp2p::tcp::Config {
+    libp2p::tcp::Config::default()
+        .nodelay(network.tcp_nodelay)
+        .listen_backlog(network.tcp_listen_backlog)
+}
+
+/// Address without its /p2p/ peer id, to match a dialed address against a configured one with or without it
+fn without_peer_id(addr: &Multiaddr) -> Multiaddr {
+    addr.iter()
+        .filter(|protocol| !matches!(protocol, libp2p::multiaddr::Protocol::P2p(_)))
+        .collect()
+}
+
+/// Listen on every address, failing only if none of them can be listened on
+/// On a dual stack listen one address family can be unavailable, we warn and carry on with the others.
+/// The listeners opened are recorded in listeners, so the node can tell when it stops listening.
+fn listen_on_all(
+    swarm: &mut Swarm<P2PoolBehaviour>,
+    listeners: &mut Listeners,
+    addrs: Vec<Multiaddr>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut errors = Vec::new();
+    let mut listening = false;
+    for addr in addrs {
+        match swarm.listen_on(addr.clone()) {
+            Ok(listener_id) => {
+                info!("Node listening on {}", addr);
+                listeners.opened(listener_id, addr);
+                listening = true;
+            }
+            Err(e) => {
+                warn!("Failed to listen on {}: {:?}", addr, e);
+                errors.push(format!("{}: {:?}", addr, e));
+            }
+        }
+    }
+    if !listening {
+        error!("Failed to listen on any address: {}", errors.join(", "));
+        return Err(format!("Failed to listen on {}", errors.join(", ")).into());
+    }
+    Ok(())
+}
+
+/// Publish reorgs of the main chain as node events
+fn forward_reorgs(mut reorg_rx: broadcast::Receiver<Reorg>, event_tx: EventSender) {
+    tokio::spawn(async move {
+        loop {
+            match reorg_rx.recv().await {
+                Ok(reorg) => {
+                    event_tx.send(NodeEvent::Reorg(reorg));
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Missed {} reorgs from the chain", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Publish reorgs refused by the chain for being too deep as node events
+fn forward_deep_reorgs(mut deep_reorg_rx: broadcast::Receiver<DeepReorg>, event_tx: EventSender) {
+    tokio::spawn(async move {
+        loop {
+            match deep_reorg_rx.recv().await {
+                Ok(deep_reorg) => {
+                    event_tx.send(NodeEvent::DeepReorgRejected(deep_reorg));
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Missed {} rejected deep reorgs from the chain", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Publish equivocations found by the chain as node events
+fn forward_equivocations(
+    mut equivocation_rx: broadcast::Receiver<Equivocation>,
+    event_tx: EventSender,
+) {
+    tokio::spawn(async move {
+        loop {
+            match equivocation_rx.recv().await {
+                Ok(equivocation) => {
+                    event_tx.send(NodeEvent::Equivocation(equivocation));
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Missed {} equivocations from the chain", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Read the chain snapshot at path and replace the chain with it if it has more work
+async fn load_snapshot(path: PathBuf, chain_handle: &ChainHandle) -> Result<(), SnapshotError> {
+    let bytes = tokio::fs::read(&path)
+        .await
+        .map_err(|e| SnapshotError::Read(format!("{}: {}", path.display(), e)))?;
+    let snapshot = ChainSnapshot::cbor_deserialize(&bytes)?;
+    chain_handle.load_snapshot(snapshot).await
+}
+
 /// Node is the main struct that represents the node
 struct Node {
     swarm: Swarm<P2PoolBehaviour>,
     swarm_tx: mpsc::Sender<SwarmSend<ResponseChannel<Message>>>,
     swarm_rx: mpsc::Receiver<SwarmSend<ResponseChannel<Message>>>,
     share_topic: gossipsub::IdentTopic,
+    announcement_topic: gossipsub::IdentTopic,
+    event_tx: EventSender,
+    metrics: Arc<Metrics>,
     chain_handle: ChainHandle,
     rate_limiter: RateLimiter,
-    config: Config,
+    /// Requests from each peer being handled, bounded by max_inflight_requests_per_peer
+    inflight_requests: InflightRequests,
+    /// Peers we are syncing from, bounded by max_sync_sessions
+    sync_sessions: SyncSessions,
+    peer_stats: PeerStats,
+    /// Listeners still open, the node gets no inbound connections once they have all closed
+    listeners: Listeners,
+    genesis_hash: ShareBlockHash,
+    /// Callers waiting on closest peer lookups they started, keyed by kademlia query id
+    closest_peers_queries: HashMap<QueryId, oneshot::Sender<Vec<PeerId>>>,
+    /// Cancellation flag and task of the reindex started last, only one reindex runs at a time
+    reindex: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+    /// Called with every share added to the chain that solves a bitcoin block
+    block_found_handlers: BlockFoundHandlers,
+    /// Shares added to the chain, sent on to the share subscribers they match
+    accepted_share_rx: broadcast::Receiver<ShareBlock>,
+    share_subscriptions: ShareSubscriptions,
+    /// Topics our peers told us they subscribed to, to flag gossip on topics they did not
+    topic_subscriptions: TopicSubscriptions,
+    /// Task pruning the store by disk usage, None when pruning is disabled
+    disk_usage_pruner: Option<JoinHandle<()>>,
+    /// Task compacting the store every compaction_interval_secs, None when scheduled compaction is disabled
+    scheduled_compaction: Option<JoinHandle<()>>,
+    /// When the last connected peer disconnected or the node started without peers, None while any peer is connected
+    isolated_since: Option<Instant>,
+    /// When to retry recovering from isolation, None until the first recovery after the grace period
+    isolation_retry_at: Option<Instant>,
+    /// Spreads out the recoveries from isolation while no dialed peer comes back
+    isolation_backoff: Backoff,
+    /// Earliest time the dial peers are re-dialed again after a failed kademlia bootstrap, None until one fails
+    bootstrap_retry_at: Option<Instant>,
+    /// Whether a bootstrap failed while re-dialing was backing off, the dial peers are re-dialed at bootstrap_retry_at
+    bootstrap_recovery_pending: bool,
+    /// Spreads out the re-dials after failed bootstraps until a bootstrap succeeds
+    bootstrap_backoff: Backoff,
+    /// When to retry a sync whose request failed, with the peer and the work it claimed
+    sync_retry: Option<(Instant, PeerId, Decimal)>,
+    /// Spreads out the sync retries until a chain page arrives
+    sync_backoff: Backoff,
+    /// Peers we connected to by dialing one of the dial_peers, with the dial peer address. Never evicted for being idle
+    dial_peer_ids: HashMap<PeerId, Multiaddr>,
+    /// Dials to dial_peers still in flight, with the dial peer address, so a dial peer isn't dialed twice at once
+    dialing_dial_peers: HashMap<ConnectionId, Multiaddr>,
+    /// Handle to change the log filter at runtime, None when logging was set up without one
+    log_level: Option<LogLevelHandle>,
+    /// Share gossip that failed to publish before any peer joined the share topic
+    gossip_startup_buffer: GossipStartupBuffer,
+    /// Gossiped shares queued for validation, valid shares are added to the chain in the order they were queued
+    share_validation_tx: mpsc::Sender<ShareJob>,
+    /// Peers whose gossiped shares were rejected for taking longer than the message processing timeout
+    timed_out_rx: mpsc::Receiver<PeerId>,
+    /// Command responses dropped because the caller stopped waiting, a sign of overload
+    dropped_responses: DroppedResponses,
+    /// Addresses peers observe us at, added to our external addresses once enough peers agree
+    observed_addresses: ObservedAddresses,
+    /// When the chain last accepted a share, None until it accepts one
+    last_share_at: Option<Instant>,
+    /// The config the node runs with, the single source of truth for tunables and the effective config.
+    /// Reloads replace the node's copy, so a handler holding a clone keeps the values it started with.
+    config: Arc<Config>,
+    /// Where the node reads the time from, the system clock outside of tests
+    clock: Arc<dyn Clock>,
+    /// Breaks ties between equally good peers to sync from
+    sync_rng: StdRng,
+    /// The node's identity keypair, also used to sign the inclusion proofs it builds
+    id_keys: libp2p::identity::Keypair,
 }
 
 impl Node {
     pub fn new(
         config: &Config,
         chain_handle: ChainHandle,
+        clock: Arc<dyn Clock>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let id_keys = libp2p::identity::Keypair::generate_ed25519();
+        // Check before building the swarm, so no peer traffic is accepted with a broken format
+        if config.network.serialization_self_test {
+            if let Err(e) = Message::self_test() {
+                error!("Serialization self test failed: {}", e);
+                return Err(format!("Serialization self test failed: {e}").into());
+            }
+            info!("Serialization self test passed");
+        }
+
+        if !config.network.has_discovery_path() {
+            let message = "No dial_peers configured and mdns is disabled, the node can't find peers and will only connect to peers that dial it";
+            if config.network.require_discovery {
+                error!("{}", message);
+                return Err(message.into());
+            }
+            warn!("{}", message);
+        }
+
+        // Components fork their rng in a fixed order, so a test seed reproduces every one of them
+        let mut rng = NodeRng::new(config.network.test_seed);
+        let id_keys = rng.identity();
 
-        let behavior = match P2PoolBehaviour::new(&id_keys, config) {
+        let genesis_hash = ShareBlock::genesis_hash_for_network(config.bitcoin.network);
+        let behavior = match P2PoolBehaviour::new(&id_keys, config, genesis_hash) {
             Ok(behavior) => behavior,
             Err(err) => {
                 error!("Failed to create P2PoolBehaviour: {}", err);
@@ -101,10 +383,10 @@ impl Node {
             }
         };
 
-        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(id_keys)
+        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(id_keys.clone())
             .with_tokio()
             .with_tcp(
-                libp2p::tcp::Config::default(),
+                tcp_config(&config.network),
                 libp2p::noise::Config::new,
                 libp2p::yamux::Config::default,
             )?
@@ -114,23 +396,10 @@ impl Node {
             })
             .build();
 
-        match config.network.listen_address.parse() {
-            Ok(addr) => match swarm.listen_on(addr) {
-                Ok(_) => {
-                    info!("Node listening on {}", config.network.listen_address);
-                }
-                Err(e) => {
-                    error!(
-                        "Failed to listen on {}: {}",
-                        config.network.listen_address, e
-                    );
-                    return Err(format!(
-                        "Failed to listen on {}: {}",
-                        config.network.listen_address, e
-                    )
-                    .into());
-                }
-            },
+        let mut peer_stats = PeerStats::new();
+        let mut listeners = Listeners::default();
+        match config.network.listen_multiaddrs() {
+            Ok(addrs) => listen_on_all(&mut swarm, &mut listeners, addrs)?,
             Err(e) => {
                 error!(
                     "Invalid listen address {}: {}",
@@ -144,13 +413,24 @@ impl Node {
             }
         }
 
+        let metrics = Arc::new(Metrics::new());
+        let mut dialing_dial_peers = HashMap::new();
         for peer_addr in &config.network.dial_peers {
             match peer_addr.parse::<Multiaddr>() {
                 Ok(remote) => {
-                    if let Err(e) = swarm.dial(remote) {
-                        debug!("Failed to dial {}: {}", peer_addr, e);
-                    } else {
-                        info!("Dialed {}", peer_addr);
+                    let address = without_peer_id(&remote);
+                    match dial_address(
+                        &mut swarm,
+                        &mut peer_stats,
+                        &metrics,
+                        remote,
+                        PeerOrigin::DialPeer,
+                    ) {
+                        Ok(connection_id) => {
+                            dialing_dial_peers.insert(connection_id, address);
+                            info!("Dialed {}", peer_addr);
+                        }
+                        Err(e) => debug!("Failed to dial {}: {}", peer_addr, e),
                     }
                 }
                 Err(e) => debug!("Invalid multiaddr {}: {}", peer_addr, e),
@@ -161,30 +441,133 @@ impl Node {
         if let Err(e) = swarm.behaviour_mut().gossipsub.subscribe(&share_topic) {
             error!("Failed to subscribe to share topic: {}", e);
         }
+        let announcement_topic = gossipsub::IdentTopic::new(ANNOUNCEMENT_TOPIC);
+        if let Err(e) = swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&announcement_topic)
+        {
+            error!("Failed to subscribe to announcement topic: {}", e);
+        }
+        let event_tx = EventSender::new(EVENT_CHANNEL_CAPACITY);
+        forward_equivocations(chain_handle.subscribe_equivocations(), event_tx.clone());
+        forward_deep_reorgs(chain_handle.subscribe_deep_reorgs(), event_tx.clone());
+        forward_reorgs(chain_handle.subscribe_reorgs(), event_tx.clone());
+        let block_found_handlers = BlockFoundHandlers::default();
+        forward_blocks_found(
+            chain_handle.subscribe_blocks_found(),
+            block_found_handlers.clone(),
+            event_tx.clone(),
+        );
+
+        let accepted_share_rx = chain_handle.subscribe_accepted_shares();
 
         let (swarm_tx, swarm_rx) = mpsc::channel(100);
 
-        if let Err(e) =
-            start_receiving_mining_messages(config, chain_handle.clone(), swarm_tx.clone())
-        {
+        let isolation_backoff = Backoff::new(config.backoff.clone(), rng.fork());
+
+        if config.network.observer {
+            info!("Running as an observer, not receiving shares from ckpool");
+        } else if let Err(e) = start_receiving_mining_messages(
+            config,
+            rng.fork(),
+            chain_handle.clone(),
+            swarm_tx.clone(),
+        ) {
             error!("Failed to start receiving shares: {}", e);
             return Err(e);
         }
+        let sync_rng = rng.fork();
+        let bootstrap_backoff = Backoff::new(config.backoff.clone(), rng.fork());
+        let sync_backoff = Backoff::new(config.backoff.clone(), rng.fork());
 
         let rate_limiter =
-            RateLimiter::new(Duration::from_secs(config.network.rate_limit_window_secs));
+            RateLimiter::new(Duration::from_secs(config.network.rate_limit_window_secs))
+                .with_clock(clock.clone());
+
+        let disk_usage_pruner = (config.store.max_disk_bytes > 0).then(|| {
+            tokio::spawn(run_disk_usage_pruner(
+                chain_handle.clone(),
+                config.store.clone(),
+            ))
+        });
+
+        let (share_validation_tx, share_validation_rx) = mpsc::channel(SHARE_VALIDATION_QUEUE_SIZE);
+        let (timed_out_tx, timed_out_rx) = mpsc::channel(SHARE_VALIDATION_QUEUE_SIZE);
+        tokio::spawn(run_share_validation(
+            share_validation_rx,
+            config.network.share_validation_concurrency as usize,
+            Duration::from_millis(config.network.message_processing_timeout),
+            chain_handle.clone(),
+            metrics.clone(),
+            timed_out_tx,
+            clock.clone(),
+            validate_proof_of_work,
+        ));
+
+        let scheduled_compaction = (config.store.compaction_interval_secs > 0).then(|| {
+            tokio::spawn(run_scheduled_compaction(
+                chain_handle.clone(),
+                Duration::from_secs(config.store.compaction_interval_secs),
+            ))
+        });
 
         Ok(Self {
             swarm,
             swarm_tx,
             swarm_rx,
             share_topic,
+            announcement_topic,
+            event_tx,
+            metrics,
             chain_handle,
             rate_limiter,
-            config: config.clone(),
+            inflight_requests: InflightRequests::new(),
+            sync_sessions: SyncSessions::new(config.network.max_sync_sessions as usize),
+            peer_stats,
+            listeners,
+            genesis_hash,
+            closest_peers_queries: HashMap::new(),
+            reindex: None,
+            block_found_handlers,
+            accepted_share_rx,
+            share_subscriptions: ShareSubscriptions::default(),
+            topic_subscriptions: TopicSubscriptions::default(),
+            disk_usage_pruner,
+            scheduled_compaction,
+            // A node starts without peers, if none connects within the grace period it recovers as if it lost them
+            isolated_since: Some(clock.instant()),
+            isolation_retry_at: None,
+            isolation_backoff,
+            bootstrap_retry_at: None,
+            bootstrap_recovery_pending: false,
+            bootstrap_backoff,
+            sync_retry: None,
+            sync_backoff,
+            dial_peer_ids: HashMap::new(),
+            dialing_dial_peers,
+            log_level: None,
+            gossip_startup_buffer: GossipStartupBuffer::new(
+                Duration::from_secs(config.network.gossip_startup_buffer_secs),
+                clock.instant(),
+            ),
+            share_validation_tx,
+            timed_out_rx,
+            dropped_responses: DroppedResponses::default(),
+            observed_addresses: ObservedAddresses::default(),
+            last_share_at: None,
+            config: Arc::new(config.clone()),
+            clock,
+            sync_rng,
+            id_keys,
         })
     }
 
+    /// The config the node runs with, including hot reloaded fields, for handlers to read tunables from
+    pub fn config(&self) -> Arc<Config> {
+        self.config.clone()
+    }
+
     /// Returns a Vec of peer IDs that are currently connected to this node
     #[allow(dead_code)]
     pub fn connected_peers(&self) -> Vec<libp2p::PeerId> {
@@ -196,9 +579,479 @@ impl Node {
         for peer_id in self.swarm.connected_peers().cloned().collect::<Vec<_>>() {
             self.swarm.disconnect_peer_id(peer_id).unwrap_or_default();
         }
+        if let Some(pruner) = self.disk_usage_pruner.take() {
+            pruner.abort();
+        }
+        if let Some(compaction) = self.scheduled_compaction.take() {
+            compaction.abort();
+        }
+        Ok(())
+    }
+
+    /// Publish a signed announcement on the announcement topic
+    pub fn publish_announcement(
+        &mut self,
+        payload: String,
+        signature: Vec<u8>,
+    ) -> Result<(), Box<dyn Error>> {
+        let buf = Message::Announcement { payload, signature }.cbor_serialize()?;
+        let bytes = buf.len();
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.announcement_topic.clone(), buf)?;
+        self.metrics
+            .record_gossip_published(ANNOUNCEMENT_TOPIC, bytes);
+        Ok(())
+    }
+
+    /// Publish a message on the share topic, counting it in the share topic's metrics
+    pub fn publish_share(&mut self, buf: Vec<u8>) -> Result<(), gossipsub::PublishError> {
+        let bytes = buf.len();
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.share_topic.clone(), buf)?;
+        self.metrics
+            .record_gossip_published(&self.share_topic.to_string(), bytes);
         Ok(())
     }
 
+    /// Publish a message on the share topic. During the startup window a message that fails because no peer
+    /// has joined the share topic yet is buffered and published once a peer subscribes.
+    pub fn gossip_share(&mut self, buf: Vec<u8>) -> Result<(), gossipsub::PublishError> {
+        let now = self.clock.instant();
+        if !self.gossip_startup_buffer.is_open(now) {
+            return self.publish_share(buf);
+        }
+        match self.publish_share(buf.clone()) {
+            Err(gossipsub::PublishError::InsufficientPeers) => {
+                if !self.gossip_startup_buffer.push(buf, now) {
+                    return Err(gossipsub::PublishError::InsufficientPeers);
+                }
+                debug!(
+                    "No peers on the share topic yet, buffered gossip until one joins, {} buffered",
+                    self.gossip_startup_buffer.len()
+                );
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Publish the share gossip buffered during startup, now that a peer joined the share topic
+    fn flush_gossip_startup_buffer(&mut self) {
+        let messages = self.gossip_startup_buffer.take();
+        if messages.is_empty() {
+            return;
+        }
+        info!(
+            "Peer joined the share topic, publishing {} buffered gossip messages",
+            messages.len()
+        );
+        for buf in messages {
+            if let Err(e) = self.publish_share(buf) {
+                error!("Error publishing buffered share: {}", e);
+            }
+        }
+    }
+
+    /// Drop the share gossip buffered during startup once the window ended without any peer joining
+    pub fn expire_gossip_startup_buffer(&mut self) {
+        let dropped = self.gossip_startup_buffer.expire(self.clock.instant());
+        if dropped > 0 {
+            warn!("No peer joined the share topic during startup, dropped {} buffered gossip messages", dropped);
+        }
+    }
+
+    /// Subscribe to events published by the node, numbered so missed events show up as a sequence gap
+    pub fn subscribe_events(&self) -> broadcast::Receiver<SequencedEvent> {
+        self.event_tx.subscribe()
+    }
+
+    /// Record a command response that couldn't be sent because the caller dropped its receiver
+    pub fn record_dropped_response(&mut self) {
+        self.dropped_responses.record(
+            self.clock.instant(),
+            self.config.network.max_dropped_responses_per_minute,
+        );
+    }
+
+    /// Whether the node keeps up with its callers, degraded once too many of them give up on commands,
+    /// has accepted a share recently and has at least min_healthy_peers connected
+    pub fn health(&mut self) -> HealthStatus {
+        self.dropped_responses
+            .status(
+                self.clock.instant(),
+                self.config.network.max_dropped_responses_per_minute,
+            )
+            .with_last_share(
+                self.time_since_last_share(),
+                self.config.network.max_share_gap_secs,
+            )
+            .with_peers(
+                self.swarm.connected_peers().count(),
+                self.config.network.min_healthy_peers,
+            )
+    }
+
+    /// Subscribe to the reorgs of the main chain only, straight from the chain
+    pub fn subscribe_reorgs(&self) -> broadcast::Receiver<Reorg> {
+        self.chain_handle.subscribe_reorgs()
+    }
+
+    /// Register a handler called with every share added to the chain that solves a bitcoin block
+    pub fn register_block_found_handler(&self, handler: Arc<dyn BlockFoundHandler>) {
+        self.block_found_handlers.register(handler);
+    }
+
+    /// Subscribe to accepted shares matching filter
+    pub fn subscribe_shares(&mut self, filter: ShareFilter) -> mpsc::Receiver<ShareBlock> {
+        self.share_subscriptions.subscribe(filter)
+    }
+
+    /// Time since the chain last accepted a share, None if it hasn't accepted one since the node started
+    pub fn time_since_last_share(&self) -> Option<Duration> {
+        self.last_share_at.map(|last_share_at| {
+            self.clock
+                .instant()
+                .saturating_duration_since(last_share_at)
+        })
+    }
+
+    /// Send a share received from the chain on to the share subscribers it matches
+    pub fn publish_accepted_share(
+        &mut self,
+        share: Result<ShareBlock, broadcast::error::RecvError>,
+    ) {
+        match share {
+            Ok(share) => {
+                self.last_share_at = Some(self.clock.instant());
+                self.share_subscriptions.publish(&share);
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                // The missed shares were still accepted
+                self.last_share_at = Some(self.clock.instant());
+                warn!("Share subscribers missed {} accepted shares", missed);
+            }
+            // The chain handle we hold keeps the channel open
+            Err(broadcast::error::RecvError::Closed) => {}
+        }
+    }
+
+    /// Apply the hot reloadable fields of a new config, dialing any newly added dial peers
+    pub fn reload_config(&mut self, new: Config) -> ConfigReload {
+        let added_peers: Vec<String> = new
+            .network
+            .dial_peers
+            .iter()
+            .filter(|peer| !self.config.network.dial_peers.contains(peer))
+            .cloned()
+            .collect();
+        let previous_log_level = self.config.logging.level.clone();
+        let mut reload = Arc::make_mut(&mut self.config).reload(&new);
+        if reload.applied.contains(&"logging.level") {
+            if let Err(e) = self.set_log_level(&self.config.logging.level) {
+                error!("Keeping log level {}: {}", previous_log_level, e);
+                Arc::make_mut(&mut self.config).logging.level = previous_log_level;
+                reload.applied.retain(|field| *field != "logging.level");
+                reload.requires_restart.push("logging.level");
+            }
+        }
+        self.swarm
+            .behaviour_mut()
+            .gate
+            .set_allowed_peers(&self.config.network.allowed_peers);
+        for peer_addr in added_peers {
+            match peer_addr.parse::<Multiaddr>() {
+                Ok(remote) => {
+                    if let Err(e) = self.dial_dial_peer(remote) {
+                        debug!("Failed to dial {}: {}", peer_addr, e);
+                    }
+                }
+                Err(e) => debug!("Invalid multiaddr {}: {}", peer_addr, e),
+            }
+        }
+        info!(
+            "Reloaded config, applied {:?}, requires restart {:?}",
+            reload.applied, reload.requires_restart
+        );
+        reload
+    }
+
+    /// Recover once no peer has been connected for the isolation grace period, by re-dialing the dial peers
+    /// and re-bootstrapping kademlia. Recovery is retried on the backoff schedule until a peer connects.
+    pub fn check_isolation(&mut self) {
+        let grace_period = Duration::from_secs(self.config.network.isolation_grace_period_secs);
+        if grace_period.is_zero() {
+            return;
+        }
+        let Some(since) = self.isolated_since else {
+            return;
+        };
+        let retry_at = self.isolation_retry_at.unwrap_or(since + grace_period);
+        if self.clock.instant() >= retry_at {
+            self.recover_from_isolation();
+            self.isolation_retry_at =
+                Some(self.clock.instant() + self.isolation_backoff.next_delay());
+        }
+    }
+
+    /// Disconnect peers that sent nothing but pings and pongs for peer_idle_evict_secs, freeing their
+    /// connection slots for more active peers. Peers we dialed from dial_peers and prioritized peers are kept.
+    pub fn evict_idle_peers(&mut self) {
+        let idle_for = Duration::from_secs(self.config.network.peer_idle_evict_secs);
+        if idle_for.is_zero() {
+            return;
+        }
+        for peer_id in self.peer_stats.idle_peers(idle_for, self.clock.instant()) {
+            if self.dial_peer_ids.contains_key(&peer_id) || self.peer_stats.priority(&peer_id) > 0 {
+                continue;
+            }
+            let reason = format!("idle for {}s", idle_for.as_secs());
+            info!("Disconnecting peer {}: {}", peer_id, reason);
+            self.peer_stats.record_disconnect(peer_id, reason);
+            // Stop tracking now, so the peer isn't evicted again before its connections close
+            self.peer_stats.remove_peer(&peer_id);
+            self.swarm.disconnect_peer_id(peer_id).unwrap_or_else(|e| {
+                error!("Failed to disconnect idle peer: {:?}", e);
+            });
+        }
+    }
+
+    /// Re-dial the dial peers we are neither connected to nor already dialing, returning how many were dialed
+    fn redial_dial_peers(&mut self) -> usize {
+        let mut dialed = 0;
+        for peer_addr in self.config.network.dial_peers.clone() {
+            match peer_addr.parse::<Multiaddr>() {
+                Ok(remote) => match self.dial_dial_peer(remote) {
+                    Ok(true) => dialed += 1,
+                    Ok(false) => debug!(
+                        "Not re-dialing {}, it is connected or being dialed",
+                        peer_addr
+                    ),
+                    Err(e) => debug!("Failed to re-dial {}: {}", peer_addr, e),
+                },
+                Err(e) => debug!("Invalid multiaddr {}: {}", peer_addr, e),
+            }
+        }
+        dialed
+    }
+
+    /// Dial a dial peer unless we are connected to it or a dial to it is in flight, returning whether it was dialed
+    fn dial_dial_peer(&mut self, remote: Multiaddr) -> Result<bool, DialError> {
+        let address = without_peer_id(&remote);
+        if self.dial_peer_ids.values().any(|dialed| *dialed == address)
+            || self
+                .dialing_dial_peers
+                .values()
+                .any(|dialing| *dialing == address)
+        {
+            return Ok(false);
+        }
+        let connection_id = dial_address(
+            &mut self.swarm,
+            &mut self.peer_stats,
+            &self.metrics,
+            remote,
+            PeerOrigin::DialPeer,
+        )?;
+        self.dialing_dial_peers.insert(connection_id, address);
+        Ok(true)
+    }
+
+    /// Start a kademlia bootstrap. Without any peer in the routing table the bootstrap can't start,
+    /// so we recover the same way as from a bootstrap query that failed.
+    pub fn bootstrap_kademlia(&mut self) {
+        match self.swarm.behaviour_mut().kademlia.bootstrap() {
+            Ok(query_id) => debug!("Started kademlia bootstrap {:?}", query_id),
+            Err(e) => {
+                warn!("Failed to start kademlia bootstrap: {}", e);
+                self.recover_from_bootstrap_failure();
+            }
+        }
+    }
+
+    /// Re-dial all dial peers after a failed bootstrap, so the routing table fills again once they connect
+    /// instead of staying empty. Re-dials follow the backoff schedule, a bootstrap failing before the next
+    /// re-dial is due leaves it to check_retries. Tells event subscribers the bootstrap failed.
+    fn recover_from_bootstrap_failure(&mut self) {
+        match self.bootstrap_retry_at {
+            Some(retry_at) if self.clock.instant() < retry_at => {
+                debug!("Kademlia bootstrap failed, re-dialing dial peers once the backoff is over");
+                self.bootstrap_recovery_pending = true;
+            }
+            _ => self.redial_after_bootstrap_failure(),
+        }
+    }
+
+    fn redial_after_bootstrap_failure(&mut self) {
+        let dialed = self.redial_dial_peers();
+        self.bootstrap_recovery_pending = false;
+        self.bootstrap_retry_at = Some(self.clock.instant() + self.bootstrap_backoff.next_delay());
+        warn!("Kademlia bootstrap failed, re-dialed {} dial peers", dialed);
+        self.event_tx.send(NodeEvent::BootstrapFailed { dialed });
+    }
+
+    /// Run the bootstrap recovery and sync retries whose backoff is over
+    pub fn check_retries(&mut self) {
+        let now = self.clock.instant();
+        if self.bootstrap_recovery_pending && self.bootstrap_retry_at.is_none_or(|at| now >= at) {
+            self.redial_after_bootstrap_failure();
+        }
+        if let Some((retry_at, peer_id, work)) = self.sync_retry {
+            if now >= retry_at {
+                self.sync_retry = None;
+                self.retry_sync(peer_id, work);
+            }
+        }
+    }
+
+    /// Schedule a retry on the backoff schedule for a sync whose request to peer_id failed
+    fn schedule_sync_retry(&mut self, peer_id: PeerId) {
+        let Some(work) = self
+            .peer_stats
+            .get(&peer_id)
+            .and_then(|info| info.chain_work)
+        else {
+            return;
+        };
+        let delay = self.sync_backoff.next_delay();
+        debug!("Retrying sync from peer {} in {:?}", peer_id, delay);
+        self.sync_retry = Some((self.clock.instant() + delay, peer_id, work));
+    }
+
+    /// Sync again towards the work peer_id claimed, from another peer claiming as much if it disconnected
+    fn retry_sync(&mut self, peer_id: PeerId, work: Decimal) {
+        let sync_peer = if self.peer_stats.get(&peer_id).is_some() {
+            Some(peer_id)
+        } else {
+            self.peer_stats.peers_with_work(work).into_iter().next()
+        };
+        match sync_peer {
+            Some(sync_peer) => self.sync_towards(sync_peer, work, None),
+            None => debug!(
+                "No connected peer claims work {}, dropping the sync retry",
+                work
+            ),
+        }
+    }
+
+    /// Re-dial all dial peers and re-bootstrap kademlia, telling event subscribers the node was isolated.
+    /// The re-dial counts as a bootstrap recovery, so a failing re-bootstrap doesn't re-dial again before its backoff.
+    fn recover_from_isolation(&mut self) {
+        let dialed = self.redial_dial_peers();
+        self.bootstrap_retry_at = Some(self.clock.instant() + self.bootstrap_backoff.next_delay());
+        if let Err(e) = self.swarm.behaviour_mut().kademlia.bootstrap() {
+            debug!("Failed to re-bootstrap kademlia: {}", e);
+        }
+        warn!(
+            "No peers connected for {}s, re-dialed {} dial peers",
+            self.config.network.isolation_grace_period_secs, dialed
+        );
+        self.event_tx.send(NodeEvent::Isolated { dialed });
+    }
+
+    /// Replace the log filter, failing if the filter is invalid or logging has no reload handle
+    pub fn set_log_level(&self, level: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(log_level) = &self.log_level else {
+            return Err("Logging was set up without a reload handle".into());
+        };
+        log_level.set_level(level)?;
+        info!("Log level set to {}", level);
+        Ok(())
+    }
+
+    /// The log filter currently applied, None when logging has no reload handle
+    pub fn log_level(&self) -> Option<String> {
+        self.log_level.as_ref().map(LogLevelHandle::level)
+    }
+
+    /// Start reindexing the store on its own task, progress is published as node events
+    pub fn start_reindex(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some((_, task)) = &self.reindex {
+            if !task.is_finished() {
+                return Err("Reindex already running".into());
+            }
+        }
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let task = tokio::spawn(run_reindex(
+            self.chain_handle.clone(),
+            self.event_tx.clone(),
+            cancelled.clone(),
+        ));
+        self.reindex = Some((cancelled, task));
+        Ok(())
+    }
+
+    /// Cancel the running reindex, returns false if no reindex was running
+    pub fn cancel_reindex(&mut self) -> bool {
+        match self.reindex.take() {
+            Some((cancelled, task)) if !task.is_finished() => {
+                cancelled.store(true, Ordering::SeqCst);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// A copy of the metrics recorded so far
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Stamp locally originated mining shares with the current time when measuring propagation latency
+    fn stamp_gossip_message(&self, message: Message) -> Message {
+        match message {
+            Message::MiningShare(share) if self.config.network.measure_propagation_latency => {
+                let origin_millis = self.clock.millis_since_epoch();
+                Message::TimedMiningShare {
+                    share,
+                    origin_millis,
+                }
+            }
+            message => message,
+        }
+    }
+
+    /// Snapshot the node's peers, sync sessions, ban list and metrics along with the chain state the caller fetched
+    pub fn diagnostics(&self, chain: Option<ChainStats>, orphans: OrphanSummary) -> Diagnostics {
+        Diagnostics::new(
+            &self.peer_stats,
+            &self.sync_sessions,
+            &self.swarm.behaviour().gate,
+            self.metrics(),
+            chain,
+            orphans,
+            self.clock.as_ref(),
+        )
+    }
+
+    /// Stats and supported protocols for a connected peer
+    pub fn peer_info(&self, peer_id: &PeerId) -> Option<PeerInfo> {
+        self.peer_stats.get(peer_id).cloned()
+    }
+
+    /// Number of connected peers by how we came to be connected to them
+    pub fn peer_breakdown(&self) -> PeerBreakdown {
+        self.peer_stats.peer_breakdown()
+    }
+
+    /// Summarise peer latencies and dial failures into a network quality report
+    pub fn network_quality(&self) -> NetworkQuality {
+        self.peer_stats.network_quality(
+            Duration::from_millis(self.config.network.latency_threshold_ms),
+            self.listeners.is_listening(),
+        )
+    }
+
+    /// The most recent inventory a connected peer sent us, None if it sent none or isn't connected
+    pub fn peer_inventory(&self, peer_id: &PeerId) -> Option<InventoryMessage> {
+        self.peer_stats
+            .get(peer_id)
+            .and_then(|info| info.last_inventory.clone())
+    }
+
     /// Send a message to a specific peer
     pub fn send_to_peer(
         &mut self,
@@ -213,6 +1066,72 @@ impl Node {
         Ok(())
     }
 
+    /// Sync towards the work a peer claimed in its chain state, from the best of the connected peers that claimed
+    /// at least that much work and didn't fail work verification. The peer is one of them, it is used when the
+    /// chain state has no tip to score the peers on. See PeerStats::select_sync_peer for the scoring.
+    fn sync_towards(&mut self, peer_id: PeerId, work: Decimal, tip: Option<ShareBlockHash>) {
+        self.peer_stats.record_chain_work(&peer_id, work);
+        let candidates: Vec<PeerId> = self
+            .peer_stats
+            .peers_with_work(work)
+            .into_iter()
+            .filter(|candidate| !self.sync_sessions.is_rejected(candidate))
+            .collect();
+        let sync_peer = tip
+            .and_then(|tip| {
+                self.peer_stats
+                    .select_sync_peer(&candidates, &tip, &mut self.sync_rng)
+            })
+            .unwrap_or(peer_id);
+        let sync_work = self
+            .peer_stats
+            .get(&sync_peer)
+            .and_then(|info| info.chain_work)
+            .unwrap_or(work);
+        if sync_peer != peer_id {
+            debug!(
+                "Peer {} claims work {}, syncing from better peer {} claiming {}",
+                peer_id, work, sync_peer, sync_work
+            );
+        }
+
+        let chain_handle = self.chain_handle.clone();
+        let swarm_tx = self.swarm_tx.clone();
+        let sync_sessions = self.sync_sessions.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_chain_state_response(
+                sync_peer,
+                sync_work,
+                chain_handle,
+                swarm_tx,
+                sync_sessions,
+            )
+            .await
+            {
+                error!("Failed to sync after chain state: {}", e);
+            }
+        });
+    }
+
+    /// Ping a peer to sample the round trip time to it
+    pub fn ping_peer(&mut self, peer_id: libp2p::PeerId) {
+        let nonce = self.peer_stats.next_ping_nonce();
+        let request_id = self
+            .swarm
+            .behaviour_mut()
+            .request_response
+            .send_request(&peer_id, Message::Ping(nonce));
+        self.peer_stats
+            .ping_sent(request_id, peer_id, self.clock.instant());
+    }
+
+    /// Ping all connected peers
+    pub fn ping_peers(&mut self) {
+        for peer_id in self.connected_peers() {
+            self.ping_peer(peer_id);
+        }
+    }
+
     /// Handle swarm events, these are events that are generated by the libp2p library
     pub async fn handle_swarm_event(
         &mut self,
@@ -223,12 +1142,58 @@ impl Node {
                 info!("Listening on {address:?}");
                 Ok(())
             }
+            SwarmEvent::Dialing {
+                peer_id,
+                connection_id,
+            } => {
+                // Our own dials don't emit Dialing, so this is a behaviour, mostly kademlia, dialing
+                debug!("Dialing {:?} on connection {}", peer_id, connection_id);
+                self.metrics.record_dial_attempt();
+                self.peer_stats.dial_started(connection_id, PeerOrigin::Dht);
+                Ok(())
+            }
             SwarmEvent::ConnectionEstablished {
-                peer_id, endpoint, ..
+                peer_id,
+                endpoint,
+                connection_id,
+                num_established,
+                ..
             } => {
+                // Defense in depth, every transport is built with Noise so this should never fail
+                if let Err(e) = check_connection_security(&endpoint) {
+                    error!("Closing connection {connection_id} to peer {peer_id}: {e}");
+                    if endpoint.is_dialer() {
+                        self.peer_stats.dial_finished(connection_id, false);
+                    }
+                    self.swarm.close_connection(connection_id);
+                    return Ok(());
+                }
+                debug!(
+                    "Connection {connection_id} to peer {peer_id} secured with {SECURITY_PROTOCOL}"
+                );
+                let origin = self.peer_stats.connection_origin(connection_id, &endpoint);
+                self.peer_stats.add_peer(peer_id, self.clock.instant());
+                self.peer_stats.set_origin(&peer_id, origin);
+                self.isolated_since = None;
+                self.isolation_retry_at = None;
+                self.isolation_backoff.reset();
+                if num_established.get() == 1 {
+                    self.ping_peer(peer_id);
+                    self.apply_mesh_priority(&peer_id);
+                }
                 match endpoint {
-                    libp2p::core::ConnectedPoint::Dialer { .. } => {
-                        if let Err(e) = send_getheaders(
+                    libp2p::core::ConnectedPoint::Dialer { address, .. } => {
+                        self.peer_stats.dial_finished(connection_id, true);
+                        self.dialing_dial_peers.remove(&connection_id);
+                        let address = without_peer_id(&address);
+                        if self.config.network.dial_peers.iter().any(|dial_peer| {
+                            dial_peer
+                                .parse::<Multiaddr>()
+                                .is_ok_and(|dial_peer| without_peer_id(&dial_peer) == address)
+                        }) {
+                            self.dial_peer_ids.insert(peer_id, address);
+                        }
+                        if let Err(e) = send_chain_state(
                             peer_id,
                             self.chain_handle.clone(),
                             self.swarm_tx.clone(),
@@ -249,9 +1214,23 @@ impl Node {
                 }
                 Ok(())
             }
-            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established,
+                ..
+            } => {
                 info!("Disconnected from peer: {peer_id}");
                 self.swarm.behaviour_mut().remove_peer(&peer_id);
+                if num_established == 0 {
+                    self.peer_stats.remove_peer(&peer_id);
+                    self.dial_peer_ids.remove(&peer_id);
+                    self.sync_sessions.finish(&peer_id);
+                    self.topic_subscriptions.remove_peer(&peer_id);
+                }
+                if self.swarm.connected_peers().next().is_none() && self.isolated_since.is_none() {
+                    warn!("Last peer disconnected, node is isolated");
+                    self.isolated_since = Some(self.clock.instant());
+                }
                 Ok(())
             }
             SwarmEvent::OutgoingConnectionError {
@@ -260,6 +1239,25 @@ impl Node {
                 connection_id,
             } => {
                 error!("Failed to connect to peer: {peer_id:?}, error: {error}, connection_id: {connection_id}");
+                self.peer_stats.dial_finished(connection_id, false);
+                self.dialing_dial_peers.remove(&connection_id);
+                Ok(())
+            }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                info!("No longer listening on {address:?}");
+                Ok(())
+            }
+            SwarmEvent::ListenerError { listener_id, error } => {
+                // Listener errors are not fatal, the listener stays open until it is closed
+                error!("Listener {listener_id:?} error: {error}");
+                Ok(())
+            }
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                addresses,
+                reason,
+            } => {
+                self.handle_listener_closed(listener_id, addresses, reason);
                 Ok(())
             }
             SwarmEvent::Behaviour(event) => match event {
@@ -283,8 +1281,52 @@ impl Node {
                         .await
                 }
             },
-            _ => Ok(()),
+            other => {
+                debug!("Ignoring swarm event: {other:?}");
+                Ok(())
+            }
+        }
+    }
+
+    /// Forget a closed listener and tell event subscribers, logging an error once no listener is left
+    fn handle_listener_closed(
+        &mut self,
+        listener_id: ListenerId,
+        addresses: Vec<Multiaddr>,
+        reason: Result<(), std::io::Error>,
+    ) {
+        let requested = self.listeners.closed(&listener_id);
+        let error = reason.err().map(|e| e.to_string());
+        warn!(
+            "Listener {:?} for {:?} closed on {:?}, error: {:?}",
+            listener_id, requested, addresses, error
+        );
+        if !self.listeners.is_listening() {
+            error!(
+                "Node is no longer listening on any address, it won't accept inbound connections"
+            );
+        }
+        self.event_tx.send(NodeEvent::ListenerClosed {
+            addresses,
+            error,
+            listening: self.listeners.is_listening(),
+        });
+    }
+
+    /// Check the genesis hash a peer advertised in its identify protocol version matches ours
+    fn check_genesis(&self, protocol_version: &str) -> Result<(), String> {
+        let peer_genesis = protocol_version
+            .strip_prefix(PROTOCOL_VERSION)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .unwrap_or(protocol_version);
+        let expected = self.genesis_hash.to_string();
+        if peer_genesis != expected {
+            return Err(format!(
+                "genesis mismatch: expected {} got {}",
+                expected, peer_genesis
+            ));
         }
+        Ok(())
     }
 
     /// Handle identify events, these are events that are generated by the identify protocol
@@ -292,15 +1334,34 @@ impl Node {
         match event {
             identify::Event::Received { peer_id, info } => {
                 info!(
-                    "Identified Peer {} with protocol version {}",
-                    peer_id, info.protocol_version
+                    "Identified Peer {} with protocol version {} and agent version {}",
+                    peer_id, info.protocol_version, info.agent_version
+                );
+                if let Err(reason) = self.check_genesis(&info.protocol_version) {
+                    warn!("Disconnecting peer {}: {}", peer_id, reason);
+                    self.peer_stats.record_disconnect(peer_id, reason);
+                    self.swarm.disconnect_peer_id(peer_id).unwrap_or_else(|e| {
+                        error!("Failed to disconnect peer on a different chain: {:?}", e);
+                    });
+                    return;
+                }
+                self.peer_stats.set_protocols(
+                    &peer_id,
+                    info.protocols.iter().map(|p| p.to_string()).collect(),
                 );
+                self.peer_stats
+                    .set_agent_version(&peer_id, info.agent_version.clone());
                 // Add the peer's advertised addresses to Kademlia
                 for addr in info.listen_addrs {
                     self.swarm
                         .behaviour_mut()
                         .add_address(peer_id, addr.clone());
                 }
+                // The swarm tells identify and Kademlia about the external address, so it is advertised
+                if let Some(addr) = self.observed_addresses.observe(info.observed_addr, peer_id) {
+                    info!("Confirmed external address {} observed by peers", addr);
+                    self.swarm.add_external_address(addr);
+                }
             }
             _ => {
                 debug!("Other identify event: {:?}", event);
@@ -313,11 +1374,20 @@ impl Node {
         match event {
             MdnsEvent::Discovered(discovered) => {
                 info!("Discovered peer: {:?}", discovered);
+                // Discovery doesn't bypass the allow-list, only allowed LAN peers are dialed
+                let discovered =
+                    allowed_discoveries(&self.config.network.allowed_peers, discovered);
                 for (peer_id, addr) in discovered {
                     // Check if we're not already connected to this peer
                     if !self.swarm.is_connected(&peer_id) {
                         // Try to dial the discovered peer
-                        match self.swarm.dial(addr.clone()) {
+                        match dial_address(
+                            &mut self.swarm,
+                            &mut self.peer_stats,
+                            &self.metrics,
+                            addr.clone(),
+                            PeerOrigin::Mdns,
+                        ) {
                             Ok(_) => {
                                 // Add the peer's address to Kademlia
                                 self.swarm.behaviour_mut().add_address(peer_id, addr);
@@ -343,12 +1413,33 @@ impl Node {
             } => {
                 info!("Routing updated for peer: {peer}, is_new_peer: {is_new_peer}, addresses: {addresses:?}, bucket_range: {bucket_range:?}, old_peer: {old_peer:?}");
             }
-            KademliaEvent::OutboundQueryProgressed { result, .. } => match result {
+            KademliaEvent::OutboundQueryProgressed {
+                id, result, step, ..
+            } => match result {
                 QueryResult::GetClosestPeers(Ok(ok)) => {
                     debug!("Got closest peers: {:?}", ok.peers);
+                    if step.last {
+                        self.closest_peers_found(id, ok.peers);
+                    }
                 }
                 QueryResult::GetClosestPeers(Err(err)) => {
                     debug!("Failed to get closest peers: {err}");
+                    // Report whatever peers we found before the query timed out
+                    let GetClosestPeersError::Timeout { peers, .. } = err;
+                    self.closest_peers_found(id, peers);
+                }
+                QueryResult::Bootstrap(Ok(ok)) => {
+                    debug!(
+                        "Bootstrap step with peer {}, {:?} remaining",
+                        ok.peer, ok.num_remaining
+                    );
+                    self.bootstrap_backoff.reset();
+                    self.bootstrap_retry_at = None;
+                    self.bootstrap_recovery_pending = false;
+                }
+                QueryResult::Bootstrap(Err(err)) => {
+                    warn!("Kademlia bootstrap failed: {err}");
+                    self.recover_from_bootstrap_failure();
                 }
                 _ => debug!("Other query result: {:?}", result),
             },
@@ -356,6 +1447,26 @@ impl Node {
         }
     }
 
+    /// Start a kademlia lookup for the peers closest to target, sending them to tx once the query completes
+    pub fn find_closest_peers(&mut self, target: PeerId, tx: oneshot::Sender<Vec<PeerId>>) {
+        let query_id = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .get_closest_peers(target);
+        self.closest_peers_queries.insert(query_id, tx);
+    }
+
+    /// Resolve a closest peers lookup started with find_closest_peers
+    /// Queries started by the behaviour itself have no caller waiting and are ignored.
+    fn closest_peers_found(&mut self, query_id: QueryId, peers: Vec<PeerId>) {
+        if let Some(tx) = self.closest_peers_queries.remove(&query_id) {
+            if tx.send(peers).is_err() {
+                error!("Failed to send closest peers response");
+            }
+        }
+    }
+
     /// Handle connection established events, these are events that are generated when a connection is established
     async fn handle_connection_established(&mut self, peer_id: libp2p::PeerId) {
         info!("Connection established with peer: {peer_id}");
@@ -372,12 +1483,62 @@ impl Node {
         &mut self,
         gossip_event: gossipsub::Event,
     ) -> Result<(), Box<dyn Error>> {
+        match &gossip_event {
+            gossipsub::Event::Subscribed { peer_id, topic } => {
+                self.topic_subscriptions.subscribed(*peer_id, topic.clone());
+                if *topic == self.share_topic.hash() {
+                    self.flush_gossip_startup_buffer();
+                }
+                return Ok(());
+            }
+            gossipsub::Event::Unsubscribed { peer_id, topic } => {
+                self.topic_subscriptions.unsubscribed(peer_id, topic);
+                return Ok(());
+            }
+            _ => {}
+        }
         if let gossipsub::Event::Message {
             propagation_source,
             message_id: _,
             message,
         } = &gossip_event
         {
+            let topic = message.topic.to_string();
+            if record_received_gossip(message, self.swarm.local_peer_id(), &self.metrics) {
+                self.peer_stats
+                    .record_activity(propagation_source, self.clock.instant());
+            } else {
+                debug!(
+                    "Gossip message on {} echoed back to us by peer {}",
+                    topic, propagation_source
+                );
+                if self.config.network.drop_gossip_echoes {
+                    return Ok(());
+                }
+            }
+            let expected_topics = [self.share_topic.hash(), self.announcement_topic.hash()];
+            if let Some(anomaly) = self.topic_subscriptions.check_message(
+                propagation_source,
+                &message.topic,
+                &expected_topics,
+            ) {
+                warn!(
+                    "Gossip anomaly from peer {}: {}",
+                    propagation_source, anomaly
+                );
+                match self.config.network.gossip_anomaly_action {
+                    GossipAnomalyAction::Log => {}
+                    GossipAnomalyAction::Drop => {
+                        self.metrics.record_gossip_reject(&topic);
+                        return Ok(());
+                    }
+                    GossipAnomalyAction::Disconnect => {
+                        self.metrics.record_gossip_reject(&topic);
+                        self.ban_peer(*propagation_source, anomaly.to_string());
+                        return Ok(());
+                    }
+                }
+            }
             match Message::cbor_deserialize(&message.data) {
                 Ok(deserialized_msg) => {
                     let message_type = Message::from(deserialized_msg);
@@ -394,6 +1555,7 @@ impl Node {
                         "Rate limit exceeded for peer {} with message type {:?}. Disconnecting.",
                         propagation_source, message_type
                     );
+                        self.metrics.record_gossip_reject(&topic);
                         self.swarm
                             .disconnect_peer_id(*propagation_source)
                             .unwrap_or_else(|e| {
@@ -402,9 +1564,40 @@ impl Node {
                         return Ok(());
                     }
 
+                    if let Message::Announcement { payload, signature } = message_type {
+                        handle_announcement(
+                            payload,
+                            &signature,
+                            &self.config().network.trusted_operator_keys,
+                            &self.event_tx,
+                        );
+                        return Ok(());
+                    }
+
+                    let share = match message_type {
+                        Message::MiningShare(share) => Some((share, None)),
+                        Message::TimedMiningShare {
+                            share,
+                            origin_millis,
+                        } => Some((share, Some(origin_millis))),
+                        _ => None,
+                    };
+                    if let Some((share, origin_millis)) = share {
+                        self.queue_share_validation(ShareJob {
+                            share,
+                            peer_id: *propagation_source,
+                            topic,
+                            origin_millis,
+                            max_gossip_lag: self.config.network.max_gossip_lag,
+                        });
+                        return Ok(());
+                    }
+
                     let chain_handle = self.chain_handle.clone();
+                    let metrics = self.metrics.clone();
                     tokio::spawn(async move {
                         if let Err(e) = handle_gossipsub_event(gossip_event, chain_handle).await {
+                            metrics.record_gossip_reject(&topic);
                             error!("Failed to handle gossipsub event: {}", e);
                         }
                     });
@@ -414,6 +1607,7 @@ impl Node {
                         "Failed to deserialize gossip message from {}: {}",
                         propagation_source, e
                     );
+                    self.metrics.record_gossip_reject(&topic);
                     return Err("Failed to deserialize message".into());
                 }
             }
@@ -421,47 +1615,300 @@ impl Node {
         Ok(())
     }
 
+    /// Set the priority of a peer for sync and gossip. Peers with a priority above 0 are preferred as sync
+    /// sources, never evicted for being idle and the last pruned from the gossipsub mesh.
+    pub fn set_peer_priority(&mut self, peer_id: PeerId, priority: u8) {
+        info!("Setting priority of peer {} to {}", peer_id, priority);
+        self.peer_stats.set_priority(peer_id, priority);
+        self.apply_mesh_priority(&peer_id);
+    }
+
+    /// Make the priority of a peer its gossipsub application score. The mesh keeps the best scored peers when it
+    /// prunes, so higher priority peers are pruned last. Only connected peers are scored, so this is applied again
+    /// when a peer connects.
+    fn apply_mesh_priority(&mut self, peer_id: &PeerId) {
+        let priority = self.peer_stats.priority(peer_id);
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .set_application_score(peer_id, priority as f64);
+    }
+
+    /// Disconnect a peer and refuse its connections until it is unbanned, returns false if it was already banned
+    pub fn ban_peer(&mut self, peer_id: PeerId, reason: String) -> bool {
+        warn!("Banning peer {}: {}", peer_id, reason);
+        let banned = self.swarm.behaviour_mut().gate.ban(peer_id);
+        if self.swarm.is_connected(&peer_id) {
+            self.peer_stats.record_disconnect(peer_id, reason);
+            self.swarm.disconnect_peer_id(peer_id).unwrap_or_else(|e| {
+                error!("Failed to disconnect banned peer: {:?}", e);
+            });
+        }
+        banned
+    }
+
+    /// Accept connections from a banned peer again, returns false if it was not banned
+    pub fn unban_peer(&mut self, peer_id: &PeerId) -> bool {
+        info!("Unbanning peer {}", peer_id);
+        self.swarm.behaviour_mut().gate.unban(peer_id)
+    }
+
+    /// Disconnect a peer whose gossiped share was rejected for taking too long to process
+    pub fn record_timed_out_message(&mut self, peer_id: PeerId) {
+        self.peer_stats.record_timed_out_message(&peer_id);
+        warn!(
+            "Disconnecting peer {}, its share took too long to validate",
+            peer_id
+        );
+        self.swarm.disconnect_peer_id(peer_id).unwrap_or_else(|e| {
+            error!("Failed to disconnect timed out peer: {:?}", e);
+        });
+    }
+
+    /// Queue a gossiped share for validation, dropping it if the queue is full
+    fn queue_share_validation(&self, job: ShareJob) {
+        if let Err(e) = self.share_validation_tx.try_send(job) {
+            let job = match e {
+                mpsc::error::TrySendError::Full(job) | mpsc::error::TrySendError::Closed(job) => {
+                    job
+                }
+            };
+            warn!(
+                "Dropping share {:?} from peer {}, the validation queue is full or closed",
+                job.share.cached_blockhash, job.peer_id
+            );
+            self.metrics.record_gossip_reject(&job.topic);
+        }
+    }
+
     /// Handle request-response events from the libp2p network
     async fn handle_request_response_event(
         &mut self,
         request_response_event: RequestResponseEvent<Message, Message>,
     ) -> Result<(), Box<dyn Error>> {
+        // Peers send inventory both unprompted and in response to our requests
+        if let RequestResponseEvent::Message {
+            peer,
+            message:
+                libp2p::request_response::Message::Request {
+                    request: Message::Inventory(inventory),
+                    ..
+                }
+                | libp2p::request_response::Message::Response {
+                    response: Message::Inventory(inventory),
+                    ..
+                },
+        } = &request_response_event
+        {
+            self.peer_stats.record_inventory(peer, inventory.clone());
+        }
+        if let RequestResponseEvent::Message {
+            peer,
+            message: libp2p::request_response::Message::Response { .. },
+        } = &request_response_event
+        {
+            self.peer_stats.record_response(peer);
+        }
+        // Pings and pongs keep flowing from peers that do nothing else, so they don't count as activity
         if let RequestResponseEvent::Message {
             peer,
             message:
                 libp2p::request_response::Message::Request {
-                    request_id: _,
-                    request,
-                    channel: _,
+                    request: message, ..
+                }
+                | libp2p::request_response::Message::Response {
+                    response: message, ..
                 },
         } = &request_response_event
         {
-            let message = Message::from(request.clone());
-            if !self
-                .rate_limiter
-                .check_rate_limit(&peer, message.clone(), &self.config.network)
-                .await
-            {
-                warn!(
-                    "Rate limit exceeded for peer {} with message type {:?}. Disconnecting.",
-                    peer, message
+            if !matches!(message, Message::Ping(_) | Message::Pong { .. }) {
+                self.peer_stats.record_activity(peer, self.clock.instant());
+            }
+        }
+        match &request_response_event {
+            RequestResponseEvent::Message {
+                message:
+                    libp2p::request_response::Message::Response {
+                        request_id,
+                        response: Message::Pong { time_millis, .. },
+                    },
+                ..
+            } => {
+                let now_millis = self.clock.millis_since_epoch();
+                self.peer_stats.pong_received(
+                    request_id,
+                    *time_millis,
+                    self.clock.instant(),
+                    now_millis,
                 );
-                self.swarm.disconnect_peer_id(*peer).unwrap_or_else(|e| {
-                    error!("Failed to disconnect rate-limited peer: {:?}", e);
+                if let Some(skew_ms) = self
+                    .peer_stats
+                    .clock_skew_beyond(self.config.network.max_clock_skew_ms)
+                {
+                    warn!(
+                        "Local clock is {} ms {} the median peer clock, more than the {} ms allowed. Valid shares will be rejected until the clock is fixed.",
+                        skew_ms.unsigned_abs(),
+                        if skew_ms > 0 { "ahead of" } else { "behind" },
+                        self.config.network.max_clock_skew_ms
+                    );
+                }
+            }
+            RequestResponseEvent::Message {
+                peer,
+                message:
+                    libp2p::request_response::Message::Response {
+                        response: Message::ChainState { tip, work, .. },
+                        ..
+                    },
+            } => {
+                self.sync_towards(*peer, *work, *tip);
+            }
+            RequestResponseEvent::Message {
+                peer,
+                message:
+                    libp2p::request_response::Message::Response {
+                        response: Message::ChainPage(page),
+                        ..
+                    },
+            } => {
+                self.sync_backoff.reset();
+                let peer = *peer;
+                let page = page.clone();
+                let chain_handle = self.chain_handle.clone();
+                let swarm_tx = self.swarm_tx.clone();
+                let sync_sessions = self.sync_sessions.clone();
+                let sync_min_height_offset = self.config.network.sync_min_height_offset;
+                tokio::spawn(async move {
+                    if let Err(e) = handle_chain_page(
+                        peer,
+                        page,
+                        chain_handle,
+                        swarm_tx,
+                        sync_sessions,
+                        sync_min_height_offset,
+                    )
+                    .await
+                    {
+                        error!("Failed to handle chain page from peer {}: {}", peer, e);
+                    }
                 });
-                return Ok(());
             }
-
-            let chain_handle = self.chain_handle.clone();
-            let swarm_tx = self.swarm_tx.clone();
-            let event_clone = request_response_event;
-            tokio::spawn(async move {
-                if let Err(e) =
-                    handle_request_response_event(event_clone, chain_handle, swarm_tx).await
+            RequestResponseEvent::Message {
+                peer,
+                message:
+                    libp2p::request_response::Message::Response {
+                        request_id,
+                        response: Message::Busy,
+                    },
+            } => {
+                info!(
+                    "Peer {} is busy and did not handle request {}",
+                    peer, request_id
+                );
+            }
+            RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                warn!("Request {} to peer {} failed: {}", request_id, peer, error);
+                self.peer_stats.request_failed(request_id);
+                if self.sync_sessions.finish(peer) {
+                    self.schedule_sync_retry(*peer);
+                }
+                self.peer_stats.record_request_failure(peer, true);
+                self.metrics.record_outbound_failure();
+            }
+            RequestResponseEvent::InboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                warn!(
+                    "Failed to respond to request {} from peer {}: {}",
+                    request_id, peer, error
+                );
+                self.peer_stats.record_request_failure(peer, false);
+                self.metrics.record_inbound_failure();
+            }
+            RequestResponseEvent::ResponseSent { peer, request_id } => {
+                debug!("Sent response to request {} from peer {}", request_id, peer);
+                self.metrics.record_response_sent();
+            }
+            RequestResponseEvent::Message {
+                peer,
+                message:
+                    libp2p::request_response::Message::Request {
+                        request_id: _,
+                        request,
+                        channel: _,
+                    },
+            } => {
+                let message = Message::from(request.clone());
+                if !self
+                    .rate_limiter
+                    .check_rate_limit(&peer, message.clone(), &self.config.network)
+                    .await
                 {
-                    error!("Failed to handle request-response event: {}", e);
+                    warn!(
+                        "Rate limit exceeded for peer {} with message type {:?}. Disconnecting.",
+                        peer, message
+                    );
+                    self.swarm.disconnect_peer_id(*peer).unwrap_or_else(|e| {
+                        error!("Failed to disconnect rate-limited peer: {:?}", e);
+                    });
+                    return Ok(());
                 }
-            });
+
+                let peer = *peer;
+                let Some(inflight_request) = self
+                    .inflight_requests
+                    .try_start(peer, self.config.network.max_inflight_requests_per_peer)
+                else {
+                    warn!(
+                        "Peer {} has {} requests in flight, responding busy",
+                        peer,
+                        self.inflight_requests.count(&peer)
+                    );
+                    if let RequestResponseEvent::Message {
+                        message: libp2p::request_response::Message::Request { channel, .. },
+                        ..
+                    } = request_response_event
+                    {
+                        if self
+                            .swarm
+                            .behaviour_mut()
+                            .request_response
+                            .send_response(channel, Message::Busy)
+                            .is_err()
+                        {
+                            debug!("Failed to send busy response to peer {}", peer);
+                        }
+                    }
+                    return Ok(());
+                };
+
+                // Peers send their chain state when they connect, we answer it and sync if it shows more work
+                if let Message::ChainState { tip, work, .. } = request {
+                    self.sync_towards(peer, *work, *tip);
+                }
+
+                let chain_handle = self.chain_handle.clone();
+                let swarm_tx = self.swarm_tx.clone();
+                let clock = self.clock.clone();
+                let event_clone = request_response_event;
+                tokio::spawn(async move {
+                    // The request counts against the peer until it has been handled
+                    let _inflight_request = inflight_request;
+                    if let Err(e) =
+                        handle_request_response_event(event_clone, chain_handle, swarm_tx, clock)
+                            .await
+                    {
+                        error!("Failed to handle request-response event: {}", e);
+                    }
+                });
+            }
+            _ => {}
         }
         Ok(())
     }
@@ -0,0 +1,141 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashSet;
+
+/// Distinct peers that must observe an address before it is confirmed as one of our external addresses
+pub const OBSERVATIONS_TO_CONFIRM: usize = 2;
+
+/// Most external addresses confirmed from observations, later ones are ignored
+pub const MAX_CONFIRMED_ADDRESSES: usize = 8;
+
+/// Most addresses waiting for enough observations, the oldest is dropped to make room
+pub const MAX_PENDING_ADDRESSES: usize = 32;
+
+/// Confirms the addresses peers observe us at, as reported in identify, before they are added to the swarm's
+/// external addresses and advertised to peers and Kademlia.
+/// A peer reports the address our connection to it came from, which for our outbound connections is often an
+/// ephemeral port nobody can dial, so an address is only confirmed once OBSERVATIONS_TO_CONFIRM peers report it.
+#[derive(Debug, Default)]
+pub struct ObservedAddresses {
+    /// Addresses seen by too few peers yet, with the peers that observed them, oldest first
+    pending: Vec<(Multiaddr, HashSet<PeerId>)>,
+    confirmed: Vec<Multiaddr>,
+}
+
+impl ObservedAddresses {
+    /// Record that peer_id observed us at addr. Returns the address when this observation confirms it,
+    /// None if it was already confirmed, needs more observations or MAX_CONFIRMED_ADDRESSES are confirmed.
+    pub fn observe(&mut self, addr: Multiaddr, peer_id: PeerId) -> Option<Multiaddr> {
+        if self.confirmed.contains(&addr) || self.confirmed.len() >= MAX_CONFIRMED_ADDRESSES {
+            return None;
+        }
+        let index = match self.pending.iter().position(|(a, _)| *a == addr) {
+            Some(index) => index,
+            None => {
+                if self.pending.len() >= MAX_PENDING_ADDRESSES {
+                    self.pending.remove(0);
+                }
+                self.pending.push((addr, HashSet::new()));
+                self.pending.len() - 1
+            }
+        };
+        let observers = &mut self.pending[index].1;
+        observers.insert(peer_id);
+        if observers.len() < OBSERVATIONS_TO_CONFIRM {
+            return None;
+        }
+        let (addr, _) = self.pending.remove(index);
+        self.confirmed.push(addr.clone());
+        Some(addr)
+    }
+
+    /// The addresses confirmed so far, in the order they were confirmed
+    pub fn confirmed(&self) -> &[Multiaddr] {
+        &self.confirmed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::behaviour::advertised_addresses::CappedIdentify;
+    use libp2p::identify;
+    use libp2p::identity::Keypair;
+    use libp2p::swarm::behaviour::ExternalAddrConfirmed;
+    use libp2p::swarm::{FromSwarm, NetworkBehaviour};
+
+    #[test]
+    fn test_observed_external_address_is_confirmed_and_advertised() {
+        let local_key = Keypair::generate_ed25519();
+        let mut identify = CappedIdentify::new(
+            identify::Behaviour::new(identify::Config::new(
+                "/p2pool/1.0.0".to_string(),
+                local_key.public(),
+            )),
+            3,
+        );
+        let mut observed = ObservedAddresses::default();
+        let addr: Multiaddr = "/ip4/203.0.113.7/tcp/6884".parse().unwrap();
+        let peer1 = PeerId::random();
+        let peer2 = PeerId::random();
+
+        // The same peer reporting the address again doesn't confirm it
+        assert_eq!(observed.observe(addr.clone(), peer1), None);
+        assert_eq!(observed.observe(addr.clone(), peer1), None);
+        assert!(observed.confirmed().is_empty());
+
+        // A second peer does, and it is confirmed only once
+        let confirmed = observed.observe(addr.clone(), peer2).unwrap();
+        assert_eq!(confirmed, addr);
+        assert_eq!(observed.observe(addr.clone(), PeerId::random()), None);
+        assert_eq!(observed.confirmed(), &[addr.clone()]);
+
+        // The node adds the confirmed address to the swarm, which reports it to identify to advertise
+        identify.on_swarm_event(FromSwarm::ExternalAddrConfirmed(ExternalAddrConfirmed {
+            addr: &confirmed,
+        }));
+        assert_eq!(identify.advertised_addresses(), vec![addr]);
+    }
+
+    #[test]
+    fn test_confirmed_and_pending_addresses_are_capped() {
+        let mut observed = ObservedAddresses::default();
+        let peers = [PeerId::random(), PeerId::random()];
+        for port in 0..MAX_CONFIRMED_ADDRESSES + 2 {
+            let addr: Multiaddr = format!("/ip4/203.0.113.7/tcp/{}", 7000 + port)
+                .parse()
+                .unwrap();
+            for peer_id in peers {
+                observed.observe(addr.clone(), peer_id);
+            }
+        }
+        assert_eq!(observed.confirmed().len(), MAX_CONFIRMED_ADDRESSES);
+
+        // Addresses seen once are dropped oldest first once too many are waiting
+        let mut observed = ObservedAddresses::default();
+        let first: Multiaddr = "/ip4/203.0.113.8/tcp/6000".parse().unwrap();
+        observed.observe(first.clone(), peers[0]);
+        for port in 0..MAX_PENDING_ADDRESSES {
+            let addr: Multiaddr = format!("/ip4/203.0.113.8/tcp/{}", 7000 + port)
+                .parse()
+                .unwrap();
+            observed.observe(addr, peers[0]);
+        }
+        assert_eq!(observed.observe(first, peers[1]), None);
+    }
+}
@@ -21,12 +21,18 @@ use crate::node::messages::{GetData, InventoryMessage, Message};
 use crate::node::SwarmSend;
 #[mockall_double::double]
 use crate::shares::chain::actor::ChainHandle;
+use crate::shares::store::WorkbaseOutcome;
 use crate::utils::time_provider::TimeProvider;
+use receivers::chain_page::{handle_get_chain_from, handle_get_chain_from_locator};
 use receivers::getblocks::handle_getblocks;
 use receivers::getheaders::handle_getheaders;
+use receivers::handle_chain_state_request;
 use receivers::share_blocks::handle_share_block;
 use receivers::share_headers::handle_share_headers;
+use receivers::workbase_update::handle_workbase_update;
+use senders::send_workbase;
 use std::error::Error;
+use std::time::UNIX_EPOCH;
 use tokio::sync::mpsc;
 use tracing::{error, info};
 
@@ -72,16 +78,37 @@ pub async fn handle_request<C: 'static>(
         }
         Message::Workbase(workbase) => {
             info!("Received workbase: {:?}", workbase);
-            if let Err(e) = chain_handle.add_workbase(workbase.clone()).await {
-                error!("Failed to store workbase: {}", e);
-                return Err(format!("Error storing workbase: {}", e).into());
+            match chain_handle.add_workbase(workbase.clone()).await {
+                Ok(WorkbaseOutcome::Added) => {}
+                // Already stored and gossiped when we first got it
+                Ok(WorkbaseOutcome::Existing) => return Ok(()),
+                Err(e) => {
+                    error!("Failed to store workbase: {}", e);
+                    return Err(format!("Error storing workbase: {}", e).into());
+                }
+            }
+            send_workbase(workbase, &chain_handle, swarm_tx).await
+        }
+        Message::WorkbaseUpdate {
+            base_workinfoid,
+            coinbase_delta,
+        } => {
+            match handle_workbase_update(base_workinfoid, coinbase_delta.clone(), &chain_handle)
+                .await?
+            {
+                WorkbaseOutcome::Added => {}
+                // Already stored and gossiped when we first got it
+                WorkbaseOutcome::Existing => return Ok(()),
             }
             if let Err(e) = swarm_tx
-                .send(SwarmSend::Gossip(Message::Workbase(workbase)))
+                .send(SwarmSend::Gossip(Message::WorkbaseUpdate {
+                    base_workinfoid,
+                    coinbase_delta,
+                }))
                 .await
             {
-                error!("Failed to send share: {}", e);
-                return Err("Error sending share to network".into());
+                error!("Failed to send workbase update: {}", e);
+                return Err("Error sending workbase update to network".into());
             }
             Ok(())
         }
@@ -132,6 +159,60 @@ pub async fn handle_request<C: 'static>(
             info!("Received mining share from ckpool: {:?}", share_block);
             Ok(())
         }
+        Message::TimedMiningShare { share, .. } => {
+            info!(
+                "Ignoring timed mining share sent as a request, timed shares are only gossiped: {:?}",
+                share.cached_blockhash
+            );
+            Ok(())
+        }
+        Message::Ping(nonce) => {
+            let time_millis = time_provider
+                .now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            if let Err(e) = swarm_tx
+                .send(SwarmSend::Response(
+                    response_channel,
+                    Message::Pong { nonce, time_millis },
+                ))
+                .await
+            {
+                error!("Failed to send pong: {}", e);
+                return Err("Error sending pong".into());
+            }
+            Ok(())
+        }
+        Message::Pong { .. } => {
+            info!("Received unsolicited pong");
+            Ok(())
+        }
+        Message::ChainState { tip, work, height } => {
+            info!(
+                "Received chain state from peer {}: tip {:?}, work {}, height {:?}",
+                peer, tip, work, height
+            );
+            handle_chain_state_request(chain_handle, response_channel, swarm_tx).await
+        }
+        Message::GetChainFrom(cursor) => {
+            handle_get_chain_from(cursor, chain_handle, response_channel, swarm_tx).await
+        }
+        Message::GetChainFromLocator(locator) => {
+            handle_get_chain_from_locator(locator, chain_handle, response_channel, swarm_tx).await
+        }
+        Message::ChainPage(_) => {
+            info!("Ignoring chain page sent as a request, chain pages are only sent as responses");
+            Ok(())
+        }
+        Message::Announcement { .. } => {
+            info!("Ignoring announcement sent as a request, announcements are only gossiped");
+            Ok(())
+        }
+        Message::Busy => {
+            info!("Received unsolicited busy response");
+            Ok(())
+        }
     }
 }
 
@@ -140,9 +221,12 @@ mod tests {
     use super::*;
     #[mockall_double::double]
     use crate::shares::chain::actor::ChainHandle;
+    use crate::shares::validation::pow_cache::PowCacheHandle;
     use crate::shares::ShareBlockHash;
     use crate::test_utils::simple_miner_workbase;
-    use crate::test_utils::{load_valid_workbases_userworkbases_and_shares, TestBlockBuilder};
+    use crate::test_utils::{
+        load_valid_workbases_userworkbases_and_shares, valid_share_block, TestBlockBuilder,
+    };
     use crate::utils::time_provider::TestTimeProvider;
     use mockall::predicate::*;
     use std::time::SystemTime;
@@ -155,25 +239,7 @@ mod tests {
         let (response_channel_tx, _response_channel_rx) = oneshot::channel::<Message>();
         let peer_id = libp2p::PeerId::random();
 
-        let (workbases, userworkbases, shares) = load_valid_workbases_userworkbases_and_shares();
-        let pubkey = "020202020202020202020202020202020202020202020202020202020202020202"
-            .parse::<bitcoin::PublicKey>()
-            .unwrap();
-        let share_header = crate::shares::miner_message::builders::build_share_header(
-            &workbases[0],
-            &shares[0],
-            &userworkbases[0],
-            pubkey,
-        )
-        .unwrap();
-
-        let share_block = crate::shares::miner_message::builders::build_share_block(
-            &workbases[0],
-            &userworkbases[0],
-            &shares[0],
-            share_header,
-        )
-        .unwrap();
+        let (share_block, workbase, user_workbase, miner_share) = valid_share_block();
 
         // Set up mock expectations
         chain_handle
@@ -187,14 +253,17 @@ mod tests {
         chain_handle
             .expect_get_workbase()
             .with(eq(7473434392883363843))
-            .returning(move |_| Some(workbases[0].clone()));
+            .returning(move |_| Some(workbase.clone()));
         chain_handle
             .expect_get_user_workbase()
             .with(eq(7473434392883363843))
-            .returning(move |_| Some(userworkbases[0].clone()));
+            .returning(move |_| Some(user_workbase.clone()));
+        chain_handle
+            .expect_pow_cache()
+            .returning(PowCacheHandle::default);
 
         let mut time_provider = TestTimeProvider(SystemTime::now());
-        time_provider.set_time(shares[0].ntime);
+        time_provider.set_time(miner_share.ntime);
 
         // Test handle_request directly without request_id
         let result = handle_request(
@@ -260,7 +329,7 @@ mod tests {
     #[tokio::test]
     async fn test_handle_request_workbase_success() {
         let peer_id = libp2p::PeerId::random();
-        let (swarm_tx, _swarm_rx) = mpsc::channel(32);
+        let (swarm_tx, mut swarm_rx) = mpsc::channel(32);
         let (response_channel_tx, _response_channel_rx) = oneshot::channel::<Message>();
         let mut chain_handle = ChainHandle::default();
 
@@ -270,7 +339,10 @@ mod tests {
         chain_handle
             .expect_add_workbase()
             .with(eq(workbase.clone()))
-            .returning(|_| Ok(()));
+            .returning(|_| Ok(WorkbaseOutcome::Added));
+        chain_handle
+            .expect_get_previous_workbase()
+            .returning(|_| None);
 
         chain_handle
             .expect_setup_share_for_chain()
@@ -278,6 +350,39 @@ mod tests {
 
         let time_provider = TestTimeProvider(SystemTime::now());
 
+        let result = handle_request(
+            peer_id,
+            Message::Workbase(workbase.clone()),
+            chain_handle,
+            response_channel_tx,
+            swarm_tx,
+            &time_provider,
+        )
+        .await;
+
+        assert!(result.is_ok());
+        match swarm_rx.try_recv() {
+            Ok(SwarmSend::Gossip(Message::Workbase(gossiped))) => assert_eq!(gossiped, workbase),
+            _ => panic!("Expected the new workbase to be gossiped"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_existing_workbase_is_not_gossiped_again() {
+        let peer_id = libp2p::PeerId::random();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel(32);
+        let (response_channel_tx, _response_channel_rx) = oneshot::channel::<Message>();
+        let mut chain_handle = ChainHandle::default();
+
+        let workbase = simple_miner_workbase();
+
+        chain_handle
+            .expect_add_workbase()
+            .with(eq(workbase.clone()))
+            .returning(|_| Ok(WorkbaseOutcome::Existing));
+
+        let time_provider = TestTimeProvider(SystemTime::now());
+
         let result = handle_request(
             peer_id,
             Message::Workbase(workbase),
@@ -289,6 +394,7 @@ mod tests {
         .await;
 
         assert!(result.is_ok());
+        assert!(swarm_rx.try_recv().is_err());
     }
 
     #[tokio::test]
@@ -580,6 +686,44 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_handle_request_ping_responds_with_pong() {
+        let peer_id = libp2p::PeerId::random();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel(32);
+        let response_channel = 1u32;
+        let chain_handle = ChainHandle::default();
+        let time_provider = TestTimeProvider(SystemTime::now());
+
+        let result = handle_request(
+            peer_id,
+            Message::Ping(42),
+            chain_handle,
+            response_channel,
+            swarm_tx,
+            &time_provider,
+        )
+        .await;
+
+        assert!(result.is_ok());
+
+        if let Some(SwarmSend::Response(channel, Message::Pong { nonce, time_millis })) =
+            swarm_rx.recv().await
+        {
+            assert_eq!(channel, response_channel);
+            assert_eq!(nonce, 42);
+            assert_eq!(
+                time_millis,
+                time_provider
+                    .now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64
+            );
+        } else {
+            panic!("Expected SwarmSend::Response with Pong message");
+        }
+    }
+
     #[tokio::test]
     async fn test_handle_request_get_data_for_block() {
         let peer_id = libp2p::PeerId::random();
@@ -0,0 +1,658 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::p2p_message_handlers::senders::send_get_chain_from;
+use crate::node::sync_sessions::SyncSessions;
+use crate::node::Message;
+use crate::node::SwarmSend;
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use crate::shares::chain::{ChainCursor, ChainPage};
+use crate::shares::store::ShareProvenance;
+use crate::shares::validation::{validate_contents, validate_declared_difficulty};
+use crate::shares::{ShareBlock, ShareBlockHash};
+use libp2p::PeerId;
+use std::error::Error;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Most shares served in one chain page
+const MAX_CHAIN_PAGE_SHARES: usize = 500;
+
+/// Most serialized share bytes served in one chain page, well below the request-response size limit
+const MAX_CHAIN_PAGE_BYTES: usize = 2 * 1024 * 1024;
+
+/// Number of shares sampled from each chain page a peer serves to verify its claimed work
+const VERIFICATION_SAMPLE_SIZE: usize = 4;
+
+/// Handle a GetChainFrom request from a peer syncing from us
+/// - respond with the page of the chain starting at the cursor
+/// - the page is bounded by MAX_CHAIN_PAGE_SHARES and MAX_CHAIN_PAGE_BYTES, and has the cursor to request the next page from
+pub async fn handle_get_chain_from<C: 'static>(
+    cursor: ChainCursor,
+    chain_handle: ChainHandle,
+    response_channel: C,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
+) -> Result<(), Box<dyn Error>> {
+    info!("Received get chain from: {:?}", cursor);
+    let page = chain_handle
+        .get_chain_page(cursor, MAX_CHAIN_PAGE_SHARES, MAX_CHAIN_PAGE_BYTES)
+        .await;
+    swarm_tx
+        .send(SwarmSend::Response(
+            response_channel,
+            Message::ChainPage(page),
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Handle a GetChainFromLocator request from a peer starting to sync from us
+/// - respond with the first page of the chain above the highest share of the locator on our main chain
+/// - the page is bounded like the pages served for GetChainFrom, the peer pulls the rest with GetChainFrom
+pub async fn handle_get_chain_from_locator<C: 'static>(
+    locator: Vec<ShareBlockHash>,
+    chain_handle: ChainHandle,
+    response_channel: C,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
+) -> Result<(), Box<dyn Error>> {
+    info!("Received get chain from locator: {:?}", locator);
+    let page = chain_handle
+        .get_chain_page_from_locator(locator, MAX_CHAIN_PAGE_SHARES, MAX_CHAIN_PAGE_BYTES)
+        .await;
+    swarm_tx
+        .send(SwarmSend::Response(
+            response_channel,
+            Message::ChainPage(page),
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Handle a ChainPage response from a peer we are syncing from
+/// - add the shares we don't have yet to the chain, in the order served, so parents come first
+/// - request the next page if there is one, otherwise finish the sync session
+///
+/// The session is finished if a share fails to add or the next cursor doesn't move forward,
+/// so a misbehaving peer can't keep us syncing forever.
+///
+/// Before any share from a page is added, a sample of the page is verified to back the work the peer claimed.
+/// A peer failing verification is rejected and not used for sync again, so a peer lying about its work can't
+/// feed us a bogus chain.
+///
+/// A page whose first share we don't have is more than sync_min_height_offset below our chain tip finishes the
+/// session before anything is verified, so a peer serving a backlog of ancient shares can't make us validate
+/// them. 0 syncs pages at any height.
+pub async fn handle_chain_page<C: 'static>(
+    peer_id: PeerId,
+    page: ChainPage,
+    chain_handle: ChainHandle,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
+    sync_sessions: SyncSessions,
+    sync_min_height_offset: u32,
+) -> Result<(), Box<dyn Error>> {
+    let Some(cursor) = sync_sessions.cursor(&peer_id) else {
+        info!(
+            "Ignoring chain page from peer {} we are not syncing from",
+            peer_id
+        );
+        return Ok(());
+    };
+    info!(
+        "Received chain page of {} shares from peer {} for {:?}",
+        page.shares.len(),
+        peer_id,
+        cursor
+    );
+    if let Some((height, tip_height)) =
+        ancient_page_height(&page.shares, &chain_handle, sync_min_height_offset).await
+    {
+        sync_sessions.finish(&peer_id);
+        return Err(format!(
+            "Peer {} served shares from height {}, more than {} below our tip at {}",
+            peer_id, height, sync_min_height_offset, tip_height
+        )
+        .into());
+    }
+    match verify_sample(&page.shares, &chain_handle).await {
+        Ok(()) => {}
+        Err(SampleError::Invalid(e)) => {
+            warn!("Peer {} failed chain work verification: {}", peer_id, e);
+            sync_sessions.reject(&peer_id);
+            return Err(format!("Peer {} failed chain work verification: {}", peer_id, e).into());
+        }
+        Err(SampleError::MissingWorkbase(workinfoid)) => {
+            // Not the peer's fault, the shares mined on it can't be added either
+            sync_sessions.finish(&peer_id);
+            return Err(format!(
+                "Missing workbase {} to verify chain page from peer {}",
+                workinfoid, peer_id
+            )
+            .into());
+        }
+    }
+    for mut share in page.shares {
+        share.compute_blockhash();
+        if chain_handle
+            .get_share(share.cached_blockhash.unwrap())
+            .await
+            .is_some()
+        {
+            continue;
+        }
+        if let Err(e) = add_synced_share(share, peer_id, &chain_handle).await {
+            sync_sessions.finish(&peer_id);
+            return Err(format!("Failed to add share from chain page: {}", e).into());
+        }
+    }
+    match page.next {
+        Some(next) if (next.height, next.offset) > (cursor.height, cursor.offset) => {
+            if let Err(e) = send_get_chain_from(peer_id, next, swarm_tx, &sync_sessions).await {
+                sync_sessions.finish(&peer_id);
+                return Err(e);
+            }
+        }
+        Some(next) => {
+            sync_sessions.finish(&peer_id);
+            return Err(format!(
+                "Peer {} sent next cursor {:?} that doesn't move past {:?}",
+                peer_id, next, cursor
+            )
+            .into());
+        }
+        None => {
+            if sync_sessions.finish(&peer_id) {
+                debug!("Sync session with peer {} finished", peer_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The height of the first share of the page we don't have and our tip height, when the share is more than
+/// offset below our tip. The share's height is its parent's plus one, a share whose parent we don't have fails
+/// to add anyway. None if the page is recent enough or offset is 0.
+async fn ancient_page_height(
+    shares: &[ShareBlock],
+    chain_handle: &ChainHandle,
+    offset: u32,
+) -> Option<(u32, u32)> {
+    if offset == 0 {
+        return None;
+    }
+    let tip_height = chain_handle.get_tip_height().await?;
+    for share in shares {
+        let mut share = share.clone();
+        share.compute_blockhash();
+        if chain_handle
+            .get_share(share.cached_blockhash.unwrap())
+            .await
+            .is_some()
+        {
+            continue;
+        }
+        let prev_share_blockhash = share.header.prev_share_blockhash?;
+        let parent_height = chain_handle
+            .get_share_heights(vec![prev_share_blockhash])
+            .await
+            .into_iter()
+            .next()
+            .flatten()?;
+        let height = parent_height + 1;
+        return (height.saturating_add(offset) < tip_height).then_some((height, tip_height));
+    }
+    None
+}
+
+/// Validate a share synced from a peer and store it, recording the peer that served it.
+/// Synced shares are historical, so unlike gossiped shares their timestamp is not checked to be recent.
+async fn add_synced_share(
+    share: ShareBlock,
+    peer_id: PeerId,
+    chain_handle: &ChainHandle,
+) -> Result<(), Box<dyn Error>> {
+    validate_contents(&share, chain_handle)
+        .await
+        .map_err(|e| format!("Share validation failed: {}", e))?;
+    chain_handle
+        .add_share_with_provenance(share, ShareProvenance::Peer(peer_id))
+        .await
+        .map_err(|e| format!("Error adding share to chain: {}", e))?;
+    Ok(())
+}
+
+/// Why a sample of a chain page failed verification
+#[derive(Debug, PartialEq)]
+enum SampleError {
+    /// A sampled share's proof of work doesn't back the difficulty it declares
+    Invalid(String),
+    /// The workbases to rebuild a sampled share's header are not stored, by workinfoid
+    MissingWorkbase(u64),
+}
+
+/// Verify the proof of work of up to VERIFICATION_SAMPLE_SIZE shares spread evenly over the page backs their
+/// declared difficulty, always including the last share, the closest to the tip the peer advertised
+async fn verify_sample(
+    shares: &[ShareBlock],
+    chain_handle: &ChainHandle,
+) -> Result<(), SampleError> {
+    if shares.is_empty() {
+        return Ok(());
+    }
+    let last = shares.len() - 1;
+    let step = last.div_ceil(VERIFICATION_SAMPLE_SIZE - 1).max(1);
+    for index in (0..last).step_by(step).chain(std::iter::once(last)) {
+        let share = &shares[index];
+        let workinfoid = share.header.miner_share.workinfoid;
+        let (Some(workbase), Some(user_workbase)) = (
+            chain_handle.get_workbase(workinfoid).await,
+            chain_handle.get_user_workbase(workinfoid).await,
+        ) else {
+            return Err(SampleError::MissingWorkbase(workinfoid));
+        };
+        validate_declared_difficulty(share, &workbase, &user_workbase)
+            .map_err(SampleError::Invalid)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::p2p_message_handlers::receivers::chain_state::handle_chain_state_response;
+    use crate::shares::miner_message::{MinerWorkbase, UserWorkbase};
+    use crate::shares::validation::pow_cache::PowCacheHandle;
+    use crate::test_utils::{valid_share_block, TestBlockBuilder};
+    use mockall::predicate::*;
+    use rust_decimal_macros::dec;
+
+    /// Serve the workbases the sampled shares were mined on from the chain handle mock
+    fn expect_workbases(
+        chain_handle: &mut ChainHandle,
+        workbase: MinerWorkbase,
+        user_workbase: UserWorkbase,
+    ) {
+        chain_handle
+            .expect_get_workbase()
+            .returning(move |_| Some(workbase.clone()));
+        chain_handle
+            .expect_get_user_workbase()
+            .returning(move |_| Some(user_workbase.clone()));
+    }
+
+    #[tokio::test]
+    async fn test_sample_is_verified_against_the_rebuilt_header() {
+        let (share, workbase, user_workbase, _) = valid_share_block();
+        let mut chain_handle = ChainHandle::default();
+        expect_workbases(&mut chain_handle, workbase, user_workbase);
+        assert_eq!(
+            verify_sample(std::slice::from_ref(&share), &chain_handle).await,
+            Ok(())
+        );
+
+        // A declared hash low enough for any difficulty doesn't count, the header is hashed again
+        let mut forged = share.clone();
+        forged.header.miner_share.hash =
+            "0000000000000000000000000000000000000000000000000000000000000001"
+                .parse()
+                .unwrap();
+        forged.header.miner_share.diff = dec!(1000000.0);
+        assert!(matches!(
+            verify_sample(&[forged], &chain_handle).await,
+            Err(SampleError::Invalid(_))
+        ));
+
+        // Without the workbases the share can't be verified, which is not the peer's fault
+        let mut chain_handle = ChainHandle::default();
+        chain_handle.expect_get_workbase().returning(|_| None);
+        chain_handle.expect_get_user_workbase().returning(|_| None);
+        assert_eq!(
+            verify_sample(std::slice::from_ref(&share), &chain_handle).await,
+            Err(SampleError::MissingWorkbase(
+                share.header.miner_share.workinfoid
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_get_chain_from_responds_with_bounded_page() {
+        let mut chain_handle = ChainHandle::default();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel(1);
+        let cursor = ChainCursor {
+            height: 10,
+            offset: 0,
+        };
+        let share = TestBlockBuilder::new()
+            .blockhash("0000000000000000000000000000000000000000000000000000000000000001")
+            .build();
+        let next = ChainCursor {
+            height: 11,
+            offset: 0,
+        };
+        let served = share.clone();
+        chain_handle
+            .expect_get_chain_page()
+            .with(
+                eq(cursor),
+                eq(MAX_CHAIN_PAGE_SHARES),
+                eq(MAX_CHAIN_PAGE_BYTES),
+            )
+            .times(1)
+            .returning(move |_, _, _| ChainPage {
+                shares: vec![served.clone()],
+                next: Some(next),
+            });
+
+        handle_get_chain_from(cursor, chain_handle, 1u32, swarm_tx)
+            .await
+            .unwrap();
+
+        match swarm_rx.recv().await {
+            Some(SwarmSend::Response(1, Message::ChainPage(page))) => {
+                assert_eq!(page.shares, vec![share]);
+                assert_eq!(page.next, Some(next));
+            }
+            _ => panic!("Expected a ChainPage response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_get_chain_from_locator_responds_with_page_above_fork() {
+        let mut chain_handle = ChainHandle::default();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel(1);
+        let locator: Vec<ShareBlockHash> =
+            vec!["0000000000000000000000000000000000000000000000000000000000000002".into()];
+        let share = TestBlockBuilder::new()
+            .blockhash("0000000000000000000000000000000000000000000000000000000000000003")
+            .build();
+        let served = share.clone();
+        chain_handle
+            .expect_get_chain_page_from_locator()
+            .with(
+                eq(locator.clone()),
+                eq(MAX_CHAIN_PAGE_SHARES),
+                eq(MAX_CHAIN_PAGE_BYTES),
+            )
+            .times(1)
+            .returning(move |_, _, _| ChainPage {
+                shares: vec![served.clone()],
+                next: None,
+            });
+
+        handle_get_chain_from_locator(locator, chain_handle, 1u32, swarm_tx)
+            .await
+            .unwrap();
+
+        match swarm_rx.recv().await {
+            Some(SwarmSend::Response(1, Message::ChainPage(page))) => {
+                assert_eq!(page.shares, vec![share]);
+                assert_eq!(page.next, None);
+            }
+            _ => panic!("Expected a ChainPage response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_page_requests_next_page_until_exhausted() {
+        let mut chain_handle = ChainHandle::default();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+        let peer_id = PeerId::random();
+        let sync_sessions = SyncSessions::new(1);
+        let (share, workbase, user_workbase, _) = valid_share_block();
+        // The share is already stored, so it is skipped without validation
+        let stored = share.clone();
+        chain_handle
+            .expect_get_share()
+            .returning(move |_| Some(stored.clone()));
+        expect_workbases(&mut chain_handle, workbase.clone(), user_workbase.clone());
+
+        assert!(sync_sessions.start(peer_id).await);
+        let first = ChainCursor {
+            height: 0,
+            offset: 0,
+        };
+        send_get_chain_from(peer_id, first, swarm_tx.clone(), &sync_sessions)
+            .await
+            .unwrap();
+        assert!(matches!(
+            swarm_rx.recv().await,
+            Some(SwarmSend::Request(_, Message::GetChainFrom(cursor))) if cursor == first
+        ));
+
+        // A page with a next cursor moves the session's cursor and requests the next page
+        let next = ChainCursor {
+            height: 1,
+            offset: 0,
+        };
+        let page = ChainPage {
+            shares: vec![share.clone()],
+            next: Some(next),
+        };
+        handle_chain_page(
+            peer_id,
+            page,
+            chain_handle,
+            swarm_tx.clone(),
+            sync_sessions.clone(),
+            0,
+        )
+        .await
+        .unwrap();
+        match swarm_rx.recv().await {
+            Some(SwarmSend::Request(sent_peer_id, Message::GetChainFrom(cursor))) => {
+                assert_eq!(sent_peer_id, peer_id);
+                assert_eq!(cursor, next);
+            }
+            _ => panic!("Expected a GetChainFrom request"),
+        }
+        assert_eq!(sync_sessions.cursor(&peer_id), Some(next));
+
+        // The last page is verified too and finishes the session
+        let mut chain_handle = ChainHandle::default();
+        let stored = share.clone();
+        chain_handle
+            .expect_get_share()
+            .returning(move |_| Some(stored.clone()));
+        expect_workbases(&mut chain_handle, workbase, user_workbase);
+        let page = ChainPage {
+            shares: vec![share],
+            next: None,
+        };
+        handle_chain_page(
+            peer_id,
+            page,
+            chain_handle,
+            swarm_tx,
+            sync_sessions.clone(),
+            0,
+        )
+        .await
+        .unwrap();
+        assert!(!sync_sessions.is_active(&peer_id));
+        assert!(swarm_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chain_page_adds_historical_shares_from_the_peer() {
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+        let peer_id = PeerId::random();
+        let sync_sessions = SyncSessions::new(1);
+        // The fixture share was mined long ago, only gossiped shares have to be recent
+        let (share, workbase, user_workbase, _) = valid_share_block();
+        let mut chain_handle = ChainHandle::default();
+        chain_handle.expect_get_share().returning(|_| None);
+        expect_workbases(&mut chain_handle, workbase, user_workbase);
+        chain_handle
+            .expect_pow_cache()
+            .returning(PowCacheHandle::default);
+        chain_handle
+            .expect_add_share_with_provenance()
+            .with(eq(share.clone()), eq(ShareProvenance::Peer(peer_id)))
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        assert!(sync_sessions.start(peer_id).await);
+        let first = ChainCursor {
+            height: 0,
+            offset: 0,
+        };
+        send_get_chain_from(peer_id, first, swarm_tx.clone(), &sync_sessions)
+            .await
+            .unwrap();
+        swarm_rx.recv().await.unwrap();
+
+        let page = ChainPage {
+            shares: vec![share],
+            next: None,
+        };
+        handle_chain_page(
+            peer_id,
+            page,
+            chain_handle,
+            swarm_tx,
+            sync_sessions.clone(),
+            0,
+        )
+        .await
+        .unwrap();
+        assert!(!sync_sessions.is_active(&peer_id));
+    }
+
+    #[tokio::test]
+    async fn test_chain_page_far_below_tip_is_not_verified_or_added() {
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+        let peer_id = PeerId::random();
+        let sync_sessions = SyncSessions::new(1);
+        let parent: ShareBlockHash =
+            "0000000000000000000000000000000000000000000000000000000000000001".into();
+        let ancient = TestBlockBuilder::new()
+            .blockhash("0000000000000000000000000000000000000000000000000000000000000002")
+            .prev_share_blockhash(parent)
+            .build();
+
+        // The page forks off at height 11 with our tip at 2000, no workbase is looked up and nothing is added
+        let mut chain_handle = ChainHandle::default();
+        chain_handle
+            .expect_get_tip_height()
+            .returning(|| Some(2000));
+        chain_handle.expect_get_share().returning(|_| None);
+        chain_handle
+            .expect_get_share_heights()
+            .with(eq(vec![parent]))
+            .returning(|_| vec![Some(10)]);
+        chain_handle.expect_get_workbase().never();
+        chain_handle.expect_add_share_with_provenance().never();
+
+        assert!(sync_sessions.start(peer_id).await);
+        let first = ChainCursor {
+            height: 0,
+            offset: 0,
+        };
+        send_get_chain_from(peer_id, first, swarm_tx.clone(), &sync_sessions)
+            .await
+            .unwrap();
+        swarm_rx.recv().await.unwrap();
+
+        let page = ChainPage {
+            shares: vec![ancient],
+            next: Some(ChainCursor {
+                height: 12,
+                offset: 0,
+            }),
+        };
+        let result = handle_chain_page(
+            peer_id,
+            page,
+            chain_handle,
+            swarm_tx,
+            sync_sessions.clone(),
+            1000,
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(!sync_sessions.is_active(&peer_id));
+        assert!(!sync_sessions.is_rejected(&peer_id));
+        assert!(swarm_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_peer_claiming_false_work_fails_verification_and_is_not_synced_from() {
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(2);
+        let peer_id = PeerId::random();
+        let sync_sessions = SyncSessions::new(1);
+
+        // The peer claims more work than ours, so we start syncing from it
+        let mut chain_handle = ChainHandle::default();
+        chain_handle
+            .expect_get_total_difficulty()
+            .returning(|| dec!(10.0));
+        chain_handle.expect_build_locator().returning(Vec::new);
+        handle_chain_state_response(
+            peer_id,
+            dec!(1000000.0),
+            chain_handle,
+            swarm_tx.clone(),
+            sync_sessions.clone(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            swarm_rx.recv().await,
+            Some(SwarmSend::Request(_, Message::GetChainFromLocator(_)))
+        ));
+
+        // The page's last share declares a difficulty its proof of work doesn't meet
+        let (honest, workbase, user_workbase, _) = valid_share_block();
+        let mut inflated = honest.clone();
+        inflated.header.miner_share.diff = dec!(1000000.0);
+        let page = ChainPage {
+            shares: vec![honest, inflated],
+            next: Some(ChainCursor {
+                height: 3,
+                offset: 0,
+            }),
+        };
+        // Only the workbases to verify the sample are looked up, nothing is added
+        let mut chain_handle = ChainHandle::default();
+        expect_workbases(&mut chain_handle, workbase, user_workbase);
+        let result = handle_chain_page(
+            peer_id,
+            page,
+            chain_handle,
+            swarm_tx.clone(),
+            sync_sessions.clone(),
+            0,
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(!sync_sessions.is_active(&peer_id));
+        assert!(sync_sessions.is_rejected(&peer_id));
+        assert!(swarm_rx.try_recv().is_err());
+
+        // The peer's claimed work is no longer trusted, so it isn't compared with ours
+        handle_chain_state_response(
+            peer_id,
+            dec!(1000000.0),
+            ChainHandle::default(),
+            swarm_tx,
+            sync_sessions.clone(),
+        )
+        .await
+        .unwrap();
+        assert!(!sync_sessions.is_active(&peer_id));
+        assert!(swarm_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::p2p_message_handlers::senders::chain_state::local_chain_state;
+use crate::node::p2p_message_handlers::senders::send_get_chain_from_locator;
+use crate::node::sync_sessions::SyncSessions;
+use crate::node::SwarmSend;
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use libp2p::PeerId;
+use rust_decimal::Decimal;
+use std::error::Error;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// Handle a ChainState request from a peer that just connected to us
+/// Respond with our own chain state so the peer can decide if it needs to sync from us.
+/// The node picks the peer to sync from if the peer has more work than us, see handle_chain_state_response.
+pub async fn handle_chain_state_request<C: 'static>(
+    chain_handle: ChainHandle,
+    response_channel: C,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
+) -> Result<(), Box<dyn Error>> {
+    let chain_state = local_chain_state(&chain_handle).await;
+    if let Err(e) = swarm_tx
+        .send(SwarmSend::Response(response_channel, chain_state))
+        .await
+    {
+        error!("Failed to send chain state response: {}", e);
+        return Err(format!("Failed to send chain state response: {}", e).into());
+    }
+    Ok(())
+}
+
+/// Sync after a chain state a peer sent us, as a request or in response to ours, showed more work than we have.
+/// The node picks peer_id among the peers claiming at least that work, so it need not be the peer that sent it.
+/// We pull the chain from the peer once a sync session is free.
+pub async fn handle_chain_state_response<C: 'static>(
+    peer_id: PeerId,
+    peer_work: Decimal,
+    chain_handle: ChainHandle,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
+    sync_sessions: SyncSessions,
+) -> Result<(), Box<dyn Error>> {
+    sync_if_peer_has_more_work(peer_id, peer_work, chain_handle, swarm_tx, sync_sessions).await
+}
+
+/// Only the node with less work syncs, so two connected nodes don't both fetch from each other
+/// Chain pages are pulled from above the common ancestor of our locator and the peer's main chain, so a heavier
+/// fork branching below our tip is fetched and reorgs in. The session started here ends when the peer has served its last page, see SyncSessions.
+/// Peers that failed verification of their claimed work are not synced from, whatever work they claim.
+async fn sync_if_peer_has_more_work<C: 'static>(
+    peer_id: PeerId,
+    peer_work: Decimal,
+    chain_handle: ChainHandle,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
+    sync_sessions: SyncSessions,
+) -> Result<(), Box<dyn Error>> {
+    if sync_sessions.is_rejected(&peer_id) {
+        info!(
+            "Peer {} failed chain work verification, not syncing from peer",
+            peer_id
+        );
+        return Ok(());
+    }
+    let local_work = chain_handle.get_total_difficulty().await;
+    if peer_work <= local_work {
+        info!(
+            "Peer {} has work {}, not more than our {}, not syncing",
+            peer_id, peer_work, local_work
+        );
+        return Ok(());
+    }
+    if !sync_sessions.start(peer_id).await {
+        info!("Already syncing from peer {}", peer_id);
+        return Ok(());
+    }
+    info!(
+        "Peer {} has work {}, more than our {}, syncing from peer",
+        peer_id, peer_work, local_work
+    );
+    let locator = chain_handle.build_locator().await;
+    if let Err(e) = send_get_chain_from_locator(peer_id, locator, swarm_tx, &sync_sessions).await {
+        sync_sessions.finish(&peer_id);
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Message;
+    use crate::shares::ShareBlockHash;
+    use rust_decimal_macros::dec;
+    use std::time::Duration;
+
+    /// Chain handle mock for a node with the given total difficulty
+    fn chain_handle_with_work(work: Decimal) -> ChainHandle {
+        let mut chain_handle = ChainHandle::default();
+        let tip: ShareBlockHash =
+            "0000000000000000000000000000000000000000000000000000000000000001".into();
+        chain_handle
+            .expect_get_chain_tip()
+            .returning(move || Some(tip));
+        chain_handle
+            .expect_get_total_difficulty()
+            .returning(move || work);
+        chain_handle
+            .expect_build_locator()
+            .returning(move || vec![tip]);
+        chain_handle
+    }
+
+    #[tokio::test]
+    async fn test_request_is_answered_with_our_chain_state() {
+        let chain_handle = chain_handle_with_work(dec!(10.0));
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(2);
+
+        handle_chain_state_request(chain_handle, 1, swarm_tx)
+            .await
+            .unwrap();
+
+        match swarm_rx.recv().await {
+            Some(SwarmSend::Response(1, Message::ChainState { work, .. })) => {
+                assert_eq!(work, dec!(10.0));
+            }
+            _ => panic!("Expected a ChainState response"),
+        }
+        // Syncing is left to the node, which picks the peer to sync from
+        assert!(swarm_rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_response_from_peer_with_more_work_syncs_from_peer() {
+        let chain_handle = chain_handle_with_work(dec!(10.0));
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+        let peer_id = PeerId::random();
+
+        handle_chain_state_response(
+            peer_id,
+            dec!(20.0),
+            chain_handle,
+            swarm_tx,
+            SyncSessions::new(1),
+        )
+        .await
+        .unwrap();
+
+        match swarm_rx.recv().await {
+            Some(SwarmSend::Request(sent_peer_id, Message::GetChainFromLocator(locator))) => {
+                assert_eq!(sent_peer_id, peer_id);
+                assert_eq!(
+                    locator,
+                    vec![ShareBlockHash::from(
+                        "0000000000000000000000000000000000000000000000000000000000000001"
+                    )]
+                );
+            }
+            _ => panic!("Expected a GetChainFromLocator request"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_from_peer_with_equal_or_less_work_does_not_sync() {
+        for peer_work in [dec!(10.0), dec!(5.0)] {
+            let chain_handle = chain_handle_with_work(dec!(10.0));
+            let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+
+            handle_chain_state_response(
+                PeerId::random(),
+                peer_work,
+                chain_handle,
+                swarm_tx,
+                SyncSessions::new(1),
+            )
+            .await
+            .unwrap();
+
+            assert!(swarm_rx.recv().await.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_one_sync_session_at_a_time_with_limit_of_one() {
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(8);
+        let sync_sessions = SyncSessions::new(1);
+        let peers = [PeerId::random(), PeerId::random(), PeerId::random()];
+
+        // Three peers offer more work than we have
+        for peer_id in peers {
+            let chain_handle = chain_handle_with_work(dec!(10.0));
+            let swarm_tx = swarm_tx.clone();
+            let sync_sessions = sync_sessions.clone();
+            tokio::spawn(async move {
+                handle_chain_state_response(
+                    peer_id,
+                    dec!(20.0),
+                    chain_handle,
+                    swarm_tx,
+                    sync_sessions,
+                )
+                .await
+                .unwrap();
+            });
+        }
+
+        let mut synced = Vec::new();
+        for _ in peers {
+            let peer_id = match swarm_rx.recv().await {
+                Some(SwarmSend::Request(peer_id, Message::GetChainFromLocator(_))) => peer_id,
+                _ => panic!("Expected a GetChainFromLocator request"),
+            };
+            assert_eq!(sync_sessions.active(), 1);
+            // No other peer is synced from until this session ends
+            assert!(
+                tokio::time::timeout(Duration::from_millis(50), swarm_rx.recv())
+                    .await
+                    .is_err()
+            );
+            assert!(sync_sessions.finish(&peer_id));
+            synced.push(peer_id);
+        }
+        synced.sort();
+        let mut expected = peers.to_vec();
+        expected.sort();
+        assert_eq!(synced, expected);
+    }
+}
@@ -19,6 +19,8 @@ use crate::node::Message;
 use crate::node::SwarmSend;
 #[mockall_double::double]
 use crate::shares::chain::actor::ChainHandle;
+use crate::shares::ShareBlockHash;
+use std::collections::HashSet;
 use std::error::Error;
 use tokio::sync::mpsc;
 use tracing::info;
@@ -32,11 +34,13 @@ use tracing::info;
 /// - Send the data to the peer via the swarm_tx channel
 /// - We send one message for each found object. See block and tx messages.
 ///   Note: At the moment, we only support sending blockhashes as inventory.
+/// - Shares more than sync_min_height_offset below our chain tip are not requested, 0 requests all of them.
 pub async fn handle_inventory<C: Clone + 'static>(
     inventory: Vec<InventoryMessage>,
     chain_handle: ChainHandle,
     swarm_tx: mpsc::Sender<SwarmSend<C>>,
     response_channel: C,
+    sync_min_height_offset: u32,
 ) -> Result<(), Box<dyn Error>> {
     info!("Received inventory update: {:?}", inventory);
 
@@ -47,6 +51,13 @@ pub async fn handle_inventory<C: Clone + 'static>(
 
                 // Check which blocks we're missing and request them
                 let missing_blocks = chain_handle.get_missing_blockhashes(&locator).await;
+                let missing_blocks = recent_blockhashes(
+                    &locator,
+                    missing_blocks,
+                    &chain_handle,
+                    sync_min_height_offset,
+                )
+                .await;
 
                 // Request missing blocks from the peer
                 if !missing_blocks.is_empty() {
@@ -77,6 +88,71 @@ pub async fn handle_inventory<C: Clone + 'static>(
     Ok(())
 }
 
+/// Keep the missing shares that are at most offset shares below our chain tip.
+/// Block hash inventories list shares in chain order, each building on the one before, as getblocks responses do.
+/// So the shares we have anchor the heights of the missing shares around them. Missing shares that no share
+/// we have anchors can't be placed, they are dropped too so a flood of unrelated shares can't get past the filter.
+async fn recent_blockhashes(
+    locator: &[ShareBlockHash],
+    missing: Vec<ShareBlockHash>,
+    chain_handle: &ChainHandle,
+    offset: u32,
+) -> Vec<ShareBlockHash> {
+    if offset == 0 || missing.is_empty() {
+        return missing;
+    }
+    let Some(tip_height) = chain_handle.get_tip_height().await else {
+        return missing;
+    };
+    let min_height = tip_height.saturating_sub(offset);
+    let missing_set: HashSet<ShareBlockHash> = missing.iter().copied().collect();
+
+    let known: Vec<ShareBlockHash> = locator
+        .iter()
+        .filter(|blockhash| !missing_set.contains(blockhash))
+        .copied()
+        .collect();
+    let mut known_heights = chain_handle.get_share_heights(known).await.into_iter();
+    let mut heights: Vec<Option<u32>> = locator
+        .iter()
+        .map(|blockhash| {
+            if missing_set.contains(blockhash) {
+                None
+            } else {
+                known_heights.next().flatten()
+            }
+        })
+        .collect();
+    for i in 1..heights.len() {
+        if heights[i].is_none() {
+            heights[i] = heights[i - 1].map(|height| height + 1);
+        }
+    }
+    for i in (0..heights.len().saturating_sub(1)).rev() {
+        if heights[i].is_none() {
+            heights[i] = heights[i + 1].and_then(|height| height.checked_sub(1));
+        }
+    }
+
+    let recent: Vec<ShareBlockHash> = locator
+        .iter()
+        .zip(heights)
+        .filter(|(blockhash, height)| {
+            missing_set.contains(blockhash) && height.is_some_and(|height| height >= min_height)
+        })
+        .map(|(blockhash, _)| *blockhash)
+        .collect();
+    if recent.len() < missing.len() {
+        info!(
+            "Ignoring {} advertised shares more than {} below our tip at height {}, or not connected to our chain",
+            missing.len() - recent.len(),
+            offset,
+            tip_height
+        );
+    }
+    recent
+}
+
 #[cfg(test)]
 mod tests {
     use crate::node::messages::{GetData, InventoryMessage};
@@ -116,8 +192,14 @@ mod tests {
 
         // Execute
         let inventory = vec![InventoryMessage::BlockHashes(locator)];
-        let result =
-            handle_inventory(inventory, chain_handle, swarm_tx, response_channel.clone()).await;
+        let result = handle_inventory(
+            inventory,
+            chain_handle,
+            swarm_tx,
+            response_channel.clone(),
+            0,
+        )
+        .await;
 
         // Verify
         assert!(result.is_ok(), "handle_inventory should return Ok");
@@ -147,4 +229,67 @@ mod tests {
             "No more messages should have been sent"
         );
     }
+
+    #[tokio::test]
+    async fn test_handle_inventory_skips_ancient_shares() {
+        let mut chain_handle = ChainHandle::default();
+        let hash = |n: u32| ShareBlockHash::from(format!("{:064x}", n).as_str());
+        // Shares 2 and 5 are ours, at heights 5 and 95, the others are missing
+        let locator = vec![hash(1), hash(2), hash(3), hash(4), hash(5), hash(6)];
+        let missing_blocks = vec![hash(1), hash(3), hash(4), hash(6)];
+
+        chain_handle
+            .expect_get_missing_blockhashes()
+            .with(eq(locator.clone()))
+            .returning(move |_| missing_blocks.clone());
+        chain_handle.expect_get_tip_height().returning(|| Some(100));
+        // The heights of the shares we have are looked up in one request
+        chain_handle
+            .expect_get_share_heights()
+            .with(eq(vec![hash(2), hash(5)]))
+            .times(1)
+            .returning(|_| vec![Some(5), Some(95)]);
+
+        let (swarm_tx, mut swarm_rx) = mpsc::channel(10);
+        let inventory = vec![InventoryMessage::BlockHashes(locator)];
+        handle_inventory(inventory, chain_handle, swarm_tx, "peer".to_string(), 10)
+            .await
+            .unwrap();
+
+        // Shares at heights 4, 6 and 7 are more than 10 below the tip, only the share at height 96 is requested
+        match swarm_rx.recv().await.unwrap() {
+            SwarmSend::Response(_, Message::GetData(GetData::Block(requested))) => {
+                assert_eq!(requested, hash(6));
+            }
+            _ => panic!("Expected GetData::Block message for the recent share"),
+        }
+        assert!(swarm_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_inventory_skips_shares_not_connected_to_our_chain() {
+        let mut chain_handle = ChainHandle::default();
+        let hash = |n: u32| ShareBlockHash::from(format!("{:064x}", n).as_str());
+        let locator = vec![hash(1), hash(2), hash(3)];
+        let missing_blocks = locator.clone();
+
+        chain_handle
+            .expect_get_missing_blockhashes()
+            .with(eq(locator.clone()))
+            .returning(move |_| missing_blocks.clone());
+        chain_handle.expect_get_tip_height().returning(|| Some(100));
+        chain_handle
+            .expect_get_share_heights()
+            .with(eq(Vec::<ShareBlockHash>::new()))
+            .returning(|_| vec![]);
+
+        let (swarm_tx, mut swarm_rx) = mpsc::channel(10);
+        let inventory = vec![InventoryMessage::BlockHashes(locator)];
+        handle_inventory(inventory, chain_handle, swarm_tx, "peer".to_string(), 10)
+            .await
+            .unwrap();
+
+        // None of the shares can be placed below or above our tip, so none are requested
+        assert!(swarm_rx.try_recv().is_err());
+    }
 }
@@ -14,14 +14,20 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod chain_page;
+pub mod chain_state;
 pub mod getblocks;
 pub mod getheaders;
 pub mod inventory;
 pub mod share_blocks;
 pub mod share_headers;
+pub mod workbase_update;
 
+pub use chain_page::{handle_chain_page, handle_get_chain_from, handle_get_chain_from_locator};
+pub use chain_state::{handle_chain_state_request, handle_chain_state_response};
 pub use getblocks::handle_getblocks;
 pub use getheaders::handle_getheaders;
 pub use inventory::handle_inventory;
 pub use share_blocks::handle_share_block;
 pub use share_headers::handle_share_headers;
+pub use workbase_update::handle_workbase_update;
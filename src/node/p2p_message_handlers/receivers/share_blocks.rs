@@ -50,7 +50,8 @@ pub async fn handle_share_block(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_utils::{load_valid_workbases_userworkbases_and_shares, TestBlockBuilder};
+    use crate::shares::validation::pow_cache::PowCacheHandle;
+    use crate::test_utils::{valid_share_block, TestBlockBuilder};
     use crate::utils::time_provider::TestTimeProvider;
     use mockall::predicate::*;
     use std::time::SystemTime;
@@ -58,26 +59,7 @@ mod tests {
     #[tokio::test]
     async fn test_handle_share_block_success() {
         let mut chain_handle = ChainHandle::default();
-        let (workbases, userworkbases, shares) = load_valid_workbases_userworkbases_and_shares();
-
-        let pubkey = "020202020202020202020202020202020202020202020202020202020202020202"
-            .parse::<bitcoin::PublicKey>()
-            .unwrap();
-        let share_header = crate::shares::miner_message::builders::build_share_header(
-            &workbases[0],
-            &shares[0],
-            &userworkbases[0],
-            pubkey,
-        )
-        .unwrap();
-
-        let share_block = crate::shares::miner_message::builders::build_share_block(
-            &workbases[0],
-            &userworkbases[0],
-            &shares[0],
-            share_header,
-        )
-        .unwrap();
+        let (share_block, workbase, user_workbase, miner_share) = valid_share_block();
 
         // Set up mock expectations
         chain_handle
@@ -88,15 +70,18 @@ mod tests {
         chain_handle
             .expect_get_workbase()
             .with(eq(7473434392883363843))
-            .returning(move |_| Some(workbases[0].clone()));
+            .returning(move |_| Some(workbase.clone()));
 
         chain_handle
             .expect_get_user_workbase()
             .with(eq(7473434392883363843))
-            .returning(move |_| Some(userworkbases[0].clone()));
+            .returning(move |_| Some(user_workbase.clone()));
+        chain_handle
+            .expect_pow_cache()
+            .returning(PowCacheHandle::default);
 
         let mut time_provider = TestTimeProvider(SystemTime::now());
-        time_provider.set_time(shares[0].ntime);
+        time_provider.set_time(miner_share.ntime);
 
         let result = handle_share_block(share_block, chain_handle, &time_provider).await;
         assert!(result.is_ok());
@@ -129,26 +114,7 @@ mod tests {
     #[tokio::test]
     async fn test_handle_share_block_add_share_error() {
         let mut chain_handle = ChainHandle::default();
-        let (workbases, userworkbases, shares) = load_valid_workbases_userworkbases_and_shares();
-
-        let pubkey = "020202020202020202020202020202020202020202020202020202020202020202"
-            .parse::<bitcoin::PublicKey>()
-            .unwrap();
-        let share_header = crate::shares::miner_message::builders::build_share_header(
-            &workbases[0],
-            &shares[0],
-            &userworkbases[0],
-            pubkey,
-        )
-        .unwrap();
-
-        let share_block = crate::shares::miner_message::builders::build_share_block(
-            &workbases[0],
-            &userworkbases[0],
-            &shares[0],
-            share_header,
-        )
-        .unwrap();
+        let (share_block, workbase, user_workbase, miner_share) = valid_share_block();
 
         // Set up mock expectations
         chain_handle
@@ -159,15 +125,18 @@ mod tests {
         chain_handle
             .expect_get_workbase()
             .with(eq(7473434392883363843))
-            .returning(move |_| Some(workbases[0].clone()));
+            .returning(move |_| Some(workbase.clone()));
 
         chain_handle
             .expect_get_user_workbase()
             .with(eq(7473434392883363843))
-            .returning(move |_| Some(userworkbases[0].clone()));
+            .returning(move |_| Some(user_workbase.clone()));
+        chain_handle
+            .expect_pow_cache()
+            .returning(PowCacheHandle::default);
 
         let mut time_provider = TestTimeProvider(SystemTime::now());
-        time_provider.set_time(shares[0].ntime);
+        time_provider.set_time(miner_share.ntime);
 
         let result = handle_share_block(share_block, chain_handle, &time_provider).await;
         assert!(result.is_err());
@@ -0,0 +1,94 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use crate::shares::miner_message::CoinbaseDelta;
+use crate::shares::store::WorkbaseOutcome;
+use std::error::Error;
+use tracing::{error, info};
+
+/// Handle a WorkbaseUpdate from a peer
+/// - rebuild the full workbase from the stored base workbase and the coinbase delta
+/// - store the full workbase, so shares mined on it validate like shares on any other workbase
+///
+/// Fails if we don't have the base workbase, the peer has to send the full workbase instead.
+pub async fn handle_workbase_update(
+    base_workinfoid: u64,
+    coinbase_delta: CoinbaseDelta,
+    chain_handle: &ChainHandle,
+) -> Result<WorkbaseOutcome, Box<dyn Error>> {
+    info!(
+        "Received workbase update {} on base workbase {}",
+        coinbase_delta.workinfoid, base_workinfoid
+    );
+    let Some(base) = chain_handle.get_workbase(base_workinfoid).await else {
+        error!(
+            "Missing base workbase {} for workbase update {}",
+            base_workinfoid, coinbase_delta.workinfoid
+        );
+        return Err(format!("Missing base workbase {}", base_workinfoid).into());
+    };
+    let workbase = base.apply_coinbase_delta(&coinbase_delta);
+    match chain_handle.add_workbase(workbase).await {
+        Ok(outcome) => Ok(outcome),
+        Err(e) => {
+            error!("Failed to store workbase update: {}", e);
+            Err(format!("Error storing workbase update: {}", e).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::simple_miner_workbase;
+    use mockall::predicate::*;
+
+    #[tokio::test]
+    async fn test_workbase_update_stores_rebuilt_workbase() {
+        let mut chain_handle = ChainHandle::default();
+        let base = simple_miner_workbase();
+        let mut refreshed = base.clone();
+        refreshed.workinfoid = base.workinfoid + 1;
+        refreshed.coinb2 = "ffff".to_string();
+        let delta = refreshed.coinbase_delta(&base).unwrap();
+
+        let stored = base.clone();
+        chain_handle
+            .expect_get_workbase()
+            .with(eq(base.workinfoid))
+            .returning(move |_| Some(stored.clone()));
+        chain_handle.expect_get_workbase().returning(|_| None);
+        chain_handle
+            .expect_add_workbase()
+            .with(eq(refreshed))
+            .times(1)
+            .returning(|_| Ok(WorkbaseOutcome::Added));
+
+        let outcome = handle_workbase_update(base.workinfoid, delta.clone(), &chain_handle)
+            .await
+            .unwrap();
+        assert_eq!(outcome, WorkbaseOutcome::Added);
+
+        // Without the base the update can't be rebuilt
+        assert!(
+            handle_workbase_update(base.workinfoid + 7, delta, &chain_handle)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,75 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::sync_sessions::SyncSessions;
+use crate::node::Message;
+use crate::node::SwarmSend;
+use crate::shares::chain::ChainCursor;
+use crate::shares::ShareBlockHash;
+use libp2p::PeerId;
+use std::error::Error;
+use tokio::sync::mpsc;
+use tracing::error;
+
+/// Request the chain page starting at cursor from a peer we are syncing from
+/// The cursor is recorded in the peer's sync session, so the response can be matched to the session.
+pub async fn send_get_chain_from<C: 'static>(
+    peer_id: PeerId,
+    cursor: ChainCursor,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
+    sync_sessions: &SyncSessions,
+) -> Result<(), Box<dyn Error>> {
+    if !sync_sessions.set_cursor(&peer_id, cursor) {
+        return Err(format!("No sync session with peer {}", peer_id).into());
+    }
+    if let Err(e) = swarm_tx
+        .send(SwarmSend::Request(peer_id, Message::GetChainFrom(cursor)))
+        .await
+    {
+        error!("Failed to send get chain from request: {}", e);
+        return Err(format!("Failed to send get chain from request: {}", e).into());
+    }
+    Ok(())
+}
+
+/// Request the first chain page above the common ancestor of our locator from a peer we start syncing from
+/// The peer picks the height the page starts at, so the session's cursor is set to the start of the chain and any
+/// next cursor the peer sends moves past it.
+pub async fn send_get_chain_from_locator<C: 'static>(
+    peer_id: PeerId,
+    locator: Vec<ShareBlockHash>,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
+    sync_sessions: &SyncSessions,
+) -> Result<(), Box<dyn Error>> {
+    let start = ChainCursor {
+        height: 0,
+        offset: 0,
+    };
+    if !sync_sessions.set_cursor(&peer_id, start) {
+        return Err(format!("No sync session with peer {}", peer_id).into());
+    }
+    if let Err(e) = swarm_tx
+        .send(SwarmSend::Request(
+            peer_id,
+            Message::GetChainFromLocator(locator),
+        ))
+        .await
+    {
+        error!("Failed to send get chain from locator request: {}", e);
+        return Err(format!("Failed to send get chain from locator request: {}", e).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::Message;
+use crate::node::SwarmSend;
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use libp2p::PeerId;
+use std::error::Error;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// Build a ChainState message describing our chain tip, total difficulty and tip height
+pub async fn local_chain_state(chain_handle: &ChainHandle) -> Message {
+    Message::ChainState {
+        tip: chain_handle.get_chain_tip().await,
+        work: chain_handle.get_total_difficulty().await,
+        height: chain_handle.get_tip_height().await,
+    }
+}
+
+/// Send our chain state to a peer we have connected to
+/// The peer responds with its own chain state, and whichever node has less work syncs from the other.
+pub async fn send_chain_state<C: 'static>(
+    peer_id: PeerId,
+    chain_handle: ChainHandle,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
+) -> Result<(), Box<dyn Error>> {
+    info!("Sending chain state to peer: {}", peer_id);
+    let chain_state = local_chain_state(&chain_handle).await;
+    if let Err(e) = swarm_tx
+        .send(SwarmSend::Request(peer_id, chain_state))
+        .await
+    {
+        error!("Failed to send chain state request: {}", e);
+        return Err(format!("Failed to send chain state request: {}", e).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shares::ShareBlockHash;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn test_send_chain_state() {
+        let mut chain_handle = ChainHandle::default();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+        let peer_id = PeerId::random();
+        let tip: ShareBlockHash =
+            "0000000000000000000000000000000000000000000000000000000000000001".into();
+
+        chain_handle
+            .expect_get_chain_tip()
+            .returning(move || Some(tip));
+        chain_handle
+            .expect_get_total_difficulty()
+            .returning(|| dec!(42.0));
+        chain_handle.expect_get_tip_height().returning(|| Some(7));
+
+        let result = send_chain_state(peer_id, chain_handle, swarm_tx).await;
+        assert!(result.is_ok());
+
+        match swarm_rx.recv().await {
+            Some(SwarmSend::Request(
+                sent_peer_id,
+                Message::ChainState {
+                    tip: sent_tip,
+                    work,
+                    height,
+                },
+            )) => {
+                assert_eq!(sent_peer_id, peer_id);
+                assert_eq!(sent_tip, Some(tip));
+                assert_eq!(work, dec!(42.0));
+                assert_eq!(height, Some(7));
+            }
+            _ => panic!("Expected a ChainState request"),
+        }
+    }
+}
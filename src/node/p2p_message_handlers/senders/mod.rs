@@ -14,8 +14,14 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod chain_page;
+pub mod chain_state;
 pub mod getheaders;
 pub mod inventory;
+pub mod workbase;
 
+pub use chain_page::{send_get_chain_from, send_get_chain_from_locator};
+pub use chain_state::send_chain_state;
 pub use getheaders::send_getheaders;
 pub use inventory::send_blocks_inventory;
+pub use workbase::send_workbase;
@@ -0,0 +1,138 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::Message;
+use crate::node::SwarmSend;
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use crate::shares::miner_message::MinerWorkbase;
+use std::error::Error;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// Build the gossip message for a workbase we just stored
+/// When ckpool only refreshed the coinbase since the previous workbase, send the coinbase delta on it
+/// instead of the full workbase. Peers without the previous workbase fall back to fetching the full one.
+pub async fn workbase_message(workbase: MinerWorkbase, chain_handle: &ChainHandle) -> Message {
+    let delta = chain_handle
+        .get_previous_workbase(workbase.workinfoid)
+        .await
+        .and_then(|base| Some((base.workinfoid, workbase.coinbase_delta(&base)?)));
+    match delta {
+        Some((base_workinfoid, coinbase_delta)) => {
+            info!(
+                "Gossiping workbase {} as an update on workbase {}",
+                workbase.workinfoid, base_workinfoid
+            );
+            Message::WorkbaseUpdate {
+                base_workinfoid,
+                coinbase_delta,
+            }
+        }
+        None => Message::Workbase(workbase),
+    }
+}
+
+/// Gossip a workbase we just stored, as a coinbase delta when possible. See workbase_message.
+pub async fn send_workbase<C: 'static>(
+    workbase: MinerWorkbase,
+    chain_handle: &ChainHandle,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
+) -> Result<(), Box<dyn Error>> {
+    let message = workbase_message(workbase, chain_handle).await;
+    if let Err(e) = swarm_tx.send(SwarmSend::Gossip(message)).await {
+        error!("Failed to send workbase: {}", e);
+        return Err("Error sending workbase to network".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::simple_miner_workbase;
+    use mockall::predicate::*;
+
+    #[tokio::test]
+    async fn test_coinbase_refresh_is_gossiped_as_an_update() {
+        let mut chain_handle = ChainHandle::default();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+        let base = simple_miner_workbase();
+        let mut refreshed = base.clone();
+        refreshed.workinfoid = base.workinfoid + 1;
+        refreshed.coinb2 = "ffff".to_string();
+
+        let stored = base.clone();
+        chain_handle
+            .expect_get_previous_workbase()
+            .with(eq(refreshed.workinfoid))
+            .returning(move |_| Some(stored.clone()));
+
+        send_workbase(refreshed.clone(), &chain_handle, swarm_tx)
+            .await
+            .unwrap();
+
+        match swarm_rx.recv().await {
+            Some(SwarmSend::Gossip(Message::WorkbaseUpdate {
+                base_workinfoid,
+                coinbase_delta,
+            })) => {
+                assert_eq!(base_workinfoid, base.workinfoid);
+                assert_eq!(base.apply_coinbase_delta(&coinbase_delta), refreshed);
+            }
+            _ => panic!("Expected a gossiped WorkbaseUpdate"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_new_template_is_gossiped_in_full() {
+        let mut chain_handle = ChainHandle::default();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(2);
+        let base = simple_miner_workbase();
+        let mut new_template = base.clone();
+        new_template.workinfoid = base.workinfoid + 1;
+        new_template.gbt.height += 1;
+
+        chain_handle
+            .expect_get_previous_workbase()
+            .with(eq(new_template.workinfoid))
+            .returning(move |_| Some(base.clone()));
+        chain_handle
+            .expect_get_previous_workbase()
+            .returning(|_| None);
+
+        send_workbase(new_template.clone(), &chain_handle, swarm_tx.clone())
+            .await
+            .unwrap();
+        match swarm_rx.recv().await {
+            Some(SwarmSend::Gossip(Message::Workbase(workbase))) => {
+                assert_eq!(workbase, new_template)
+            }
+            _ => panic!("Expected a gossiped Workbase"),
+        }
+
+        // Without a previous workbase there is nothing to send a delta on
+        let mut first = new_template.clone();
+        first.workinfoid = 1;
+        send_workbase(first.clone(), &chain_handle, swarm_tx)
+            .await
+            .unwrap();
+        match swarm_rx.recv().await {
+            Some(SwarmSend::Gossip(Message::Workbase(workbase))) => assert_eq!(workbase, first),
+            _ => panic!("Expected a gossiped Workbase"),
+        }
+    }
+}
@@ -0,0 +1,990 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::messages::InventoryMessage;
+use crate::shares::ShareBlockHash;
+use libp2p::core::ConnectedPoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response::OutboundRequestId;
+use libp2p::swarm::ConnectionId;
+use libp2p::PeerId;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Interval at which we ping connected peers to sample round trip times
+pub const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of round trip samples kept per peer
+const MAX_RTT_SAMPLES: usize = 10;
+
+/// Number of recent dial outcomes used to compute the dial failure rate
+const MAX_DIAL_OUTCOMES: usize = 100;
+
+/// Number of recent disconnect reasons we remember
+const MAX_DISCONNECT_REASONS: usize = 100;
+
+/// Round trip time assumed for peers we have no ping samples for yet
+const UNKNOWN_PEER_RTT: Duration = Duration::from_secs(1);
+
+/// Success rate assumed for peers we have not had a response or failure from yet
+const UNKNOWN_PEER_SUCCESS_RATE: f64 = 0.5;
+
+/// Score added for a peer that advertised the share in its last inventory
+const ADVERTISED_SHARE_SCORE: f64 = 1.0;
+
+/// Score taken off a peer for each of its messages we gave up processing after the processing timeout
+const TIMED_OUT_MESSAGE_PENALTY: f64 = 0.5;
+
+/// How we came to be connected to a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerOrigin {
+    /// We dialed one of the configured dial_peers
+    DialPeer,
+    /// We dialed a peer discovered on the local network with mdns
+    Mdns,
+    /// A behaviour dialed the peer, kademlia connecting to peers it learnt of from the DHT
+    Dht,
+    /// The peer connected to us
+    Inbound,
+    /// The connection goes through a relay circuit, whichever side opened it
+    Relay,
+}
+
+/// Number of connected peers by how we came to be connected to them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerBreakdown {
+    pub dial_peers: usize,
+    pub mdns: usize,
+    pub dht: usize,
+    pub inbound: usize,
+    pub relay: usize,
+}
+
+/// Statistics we track for each connected peer
+#[derive(Debug, Clone, Default)]
+pub struct PeerInfo {
+    /// Most recent ping round trip times, oldest first
+    pub rtt_samples: VecDeque<Duration>,
+    /// Protocols the peer told us it supports in its identify info, empty until identified
+    pub protocols: Vec<String>,
+    /// Software and version the peer advertised in its identify info, None until identified
+    pub agent_version: Option<String>,
+    /// The most recent inventory the peer sent us, shows whether the peer is behind or on a fork
+    pub last_inventory: Option<InventoryMessage>,
+    /// Total work the peer claimed in the last chain state it sent us, None until it sent one
+    pub chain_work: Option<Decimal>,
+    /// Responses the peer sent to our requests
+    pub responses_received: u64,
+    /// Requests we sent the peer that failed
+    pub outbound_failures: u64,
+    /// Requests from the peer we failed to respond to
+    pub inbound_failures: u64,
+    /// Messages from the peer we gave up processing after the message processing timeout
+    pub timed_out_messages: u64,
+    /// The peer's clock minus ours in milliseconds, estimated from the latest pong. Positive when the peer is ahead.
+    pub clock_offset_ms: Option<i64>,
+    /// When the peer connected or last sent us a message other than a ping or pong
+    pub last_activity: Option<Instant>,
+    /// How the first connection with the peer was made, None until it is recorded
+    pub origin: Option<PeerOrigin>,
+}
+
+impl PeerInfo {
+    /// Average of the recorded round trip times, None if no samples yet
+    pub fn average_rtt(&self) -> Option<Duration> {
+        if self.rtt_samples.is_empty() {
+            return None;
+        }
+        let total: Duration = self.rtt_samples.iter().sum();
+        Some(total / self.rtt_samples.len() as u32)
+    }
+
+    /// Fraction of our requests to the peer that got a response, None if none completed yet
+    pub fn success_rate(&self) -> Option<f64> {
+        let completed = self.responses_received + self.outbound_failures;
+        if completed == 0 {
+            return None;
+        }
+        Some(self.responses_received as f64 / completed as f64)
+    }
+
+    /// Whether the share is in the last inventory the peer sent us
+    pub fn advertised(&self, blockhash: &ShareBlockHash) -> bool {
+        matches!(
+            &self.last_inventory,
+            Some(InventoryMessage::BlockHashes(blockhashes)) if blockhashes.contains(blockhash)
+        )
+    }
+
+    /// How good a peer is to request a share from, higher is better.
+    /// Sums a latency score between 0 and 1, the request success rate, and a bonus if the peer advertised the share,
+    /// less a penalty for each message of the peer that timed out processing.
+    /// Peers without ping samples or request history get middling latency and success scores.
+    pub fn sync_score(&self, blockhash: &ShareBlockHash) -> f64 {
+        let rtt = self.average_rtt().unwrap_or(UNKNOWN_PEER_RTT);
+        let latency_score = 1.0 / (1.0 + rtt.as_secs_f64());
+        let success_rate = self.success_rate().unwrap_or(UNKNOWN_PEER_SUCCESS_RATE);
+        let advertised_score = if self.advertised(blockhash) {
+            ADVERTISED_SHARE_SCORE
+        } else {
+            0.0
+        };
+        latency_score + success_rate + advertised_score
+            - TIMED_OUT_MESSAGE_PENALTY * self.timed_out_messages as f64
+    }
+}
+
+/// Summary of the network health as seen from this node
+/// Round trip times only come from Ping/Pong exchanges, so they are not skewed by peers
+/// doing chain lookups or serialising large responses for other request types.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkQuality {
+    /// Median of the per peer average ping round trip times
+    pub median_rtt: Option<Duration>,
+    /// 95th percentile of the per peer average ping round trip times
+    pub p95_rtt: Option<Duration>,
+    /// Number of peers with average ping round trip time above the latency threshold
+    pub peers_above_threshold: usize,
+    /// Fraction of recent outbound dials that failed, between 0.0 and 1.0
+    /// Each dial is counted once, when its connection is established or its dial errors.
+    pub dial_failure_rate: f64,
+    /// Whether any of our listeners is still open, a node that isn't listening gets no inbound connections
+    pub listening: bool,
+    /// How far our clock is ahead of the median peer clock in milliseconds, negative when behind.
+    /// None until a peer has answered a ping.
+    pub clock_skew_ms: Option<i64>,
+}
+
+/// Tracks per peer statistics used for network quality reporting
+#[derive(Debug, Default)]
+pub struct PeerStats {
+    peers: HashMap<PeerId, PeerInfo>,
+    pending_pings: HashMap<OutboundRequestId, (PeerId, Instant)>,
+    next_ping_nonce: u64,
+    /// Dials in progress and why each was started
+    pending_dials: HashMap<ConnectionId, PeerOrigin>,
+    dial_outcomes: VecDeque<bool>,
+    disconnect_reasons: VecDeque<(PeerId, String)>,
+    /// Priorities operators set for peers, kept when the peer disconnects. Peers not in here have priority 0
+    priorities: HashMap<PeerId, u8>,
+}
+
+impl PeerStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the stats for a peer, if we have any
+    pub fn get(&self, peer_id: &PeerId) -> Option<&PeerInfo> {
+        self.peers.get(peer_id)
+    }
+
+    /// The peers we are tracking and their stats
+    pub fn peers(&self) -> impl Iterator<Item = (&PeerId, &PeerInfo)> {
+        self.peers.iter()
+    }
+
+    /// Start tracking a peer, called when a connection is established at now
+    pub fn add_peer(&mut self, peer_id: PeerId, now: Instant) {
+        self.peers.entry(peer_id).or_insert_with(|| PeerInfo {
+            last_activity: Some(now),
+            ..Default::default()
+        });
+    }
+
+    /// Record that a peer sent us a message other than a ping or pong at now
+    pub fn record_activity(&mut self, peer_id: &PeerId, now: Instant) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.last_activity = Some(now);
+        }
+    }
+
+    /// Peers that have not sent us anything but pings and pongs for idle_for since connecting
+    pub fn idle_peers(&self, idle_for: Duration, now: Instant) -> Vec<PeerId> {
+        self.peers
+            .iter()
+            .filter(|(_, info)| {
+                info.last_activity.is_some_and(|last_activity| {
+                    now.saturating_duration_since(last_activity) >= idle_for
+                })
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+
+    /// Set the priority of a peer, higher priority peers are preferred for sync. 0 is the default priority.
+    pub fn set_priority(&mut self, peer_id: PeerId, priority: u8) {
+        if priority == 0 {
+            self.priorities.remove(&peer_id);
+        } else {
+            self.priorities.insert(peer_id, priority);
+        }
+    }
+
+    /// The priority set for a peer, 0 if none was set
+    pub fn priority(&self, peer_id: &PeerId) -> u8 {
+        self.priorities.get(peer_id).copied().unwrap_or_default()
+    }
+
+    /// Stop tracking a peer, called when the last connection to the peer closes
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+        self.pending_pings.retain(|_, (peer, _)| peer != peer_id);
+    }
+
+    /// Record the agent version a connected peer advertised in identify
+    pub fn set_agent_version(&mut self, peer_id: &PeerId, agent_version: String) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.agent_version = Some(agent_version);
+        }
+    }
+
+    /// Record the protocols a connected peer supports, as learned from identify
+    pub fn set_protocols(&mut self, peer_id: &PeerId, protocols: Vec<String>) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.protocols = protocols;
+        }
+    }
+
+    /// How a newly established connection was made. Call before dial_finished, which forgets the dial.
+    /// Outbound connections we didn't see dialed are counted as dialed by a behaviour.
+    pub fn connection_origin(
+        &self,
+        connection_id: ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) -> PeerOrigin {
+        let address = endpoint.get_remote_address();
+        if address
+            .iter()
+            .any(|protocol| matches!(protocol, Protocol::P2pCircuit))
+        {
+            return PeerOrigin::Relay;
+        }
+        if endpoint.is_listener() {
+            return PeerOrigin::Inbound;
+        }
+        self.pending_dials
+            .get(&connection_id)
+            .copied()
+            .unwrap_or(PeerOrigin::Dht)
+    }
+
+    /// Record how we came to be connected to a peer, later connections with the peer don't change it
+    pub fn set_origin(&mut self, peer_id: &PeerId, origin: PeerOrigin) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.origin.get_or_insert(origin);
+        }
+    }
+
+    /// Count the connected peers by how we came to be connected to them
+    pub fn peer_breakdown(&self) -> PeerBreakdown {
+        let mut breakdown = PeerBreakdown::default();
+        for origin in self.peers.values().filter_map(|info| info.origin) {
+            match origin {
+                PeerOrigin::DialPeer => breakdown.dial_peers += 1,
+                PeerOrigin::Mdns => breakdown.mdns += 1,
+                PeerOrigin::Dht => breakdown.dht += 1,
+                PeerOrigin::Inbound => breakdown.inbound += 1,
+                PeerOrigin::Relay => breakdown.relay += 1,
+            }
+        }
+        breakdown
+    }
+
+    /// Record the latest inventory a connected peer sent us, replacing the previous one
+    pub fn record_inventory(&mut self, peer_id: &PeerId, inventory: InventoryMessage) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.last_inventory = Some(inventory);
+        }
+    }
+
+    /// Record the total work a connected peer claimed in its latest chain state
+    pub fn record_chain_work(&mut self, peer_id: &PeerId, work: Decimal) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.chain_work = Some(work);
+        }
+    }
+
+    /// Connected peers that claimed at least work in their latest chain state, the peers a sync to that work can use
+    pub fn peers_with_work(&self, work: Decimal) -> Vec<PeerId> {
+        self.peers
+            .iter()
+            .filter(|(_, info)| info.chain_work.is_some_and(|chain_work| chain_work >= work))
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+
+    /// Nonce for the next ping we send, peers echo it back in their pong
+    pub fn next_ping_nonce(&mut self) -> u64 {
+        self.next_ping_nonce = self.next_ping_nonce.wrapping_add(1);
+        self.next_ping_nonce
+    }
+
+    /// Remember when a ping was sent, so we can measure the round trip time on pong
+    pub fn ping_sent(&mut self, request_id: OutboundRequestId, peer_id: PeerId, now: Instant) {
+        self.pending_pings.insert(request_id, (peer_id, now));
+    }
+
+    /// Record the round trip time for a ping we received a pong for, and the peer's clock offset.
+    /// The peer read its clock about half a round trip before we received the pong at now, now_millis
+    /// in wall clock time.
+    pub fn pong_received(
+        &mut self,
+        request_id: &OutboundRequestId,
+        peer_time_millis: u64,
+        now: Instant,
+        now_millis: u64,
+    ) {
+        if let Some((peer_id, sent_at)) = self.pending_pings.remove(request_id) {
+            let rtt = now.saturating_duration_since(sent_at);
+            self.record_rtt(peer_id, rtt);
+            let peer_read_at = now_millis.saturating_sub(rtt.as_millis() as u64 / 2);
+            self.record_clock_offset(peer_id, peer_time_millis as i64 - peer_read_at as i64);
+        }
+    }
+
+    /// Record a connected peer's clock minus ours, in milliseconds
+    pub fn record_clock_offset(&mut self, peer_id: PeerId, offset_ms: i64) {
+        if let Some(info) = self.peers.get_mut(&peer_id) {
+            info.clock_offset_ms = Some(offset_ms);
+        }
+    }
+
+    /// How far our clock is ahead of the median peer clock in milliseconds, None without any peer clock offsets.
+    /// Using the median means a single peer with a wrong clock doesn't make ours look skewed.
+    pub fn clock_skew_ms(&self) -> Option<i64> {
+        let mut offsets: Vec<i64> = self
+            .peers
+            .values()
+            .filter_map(|info| info.clock_offset_ms)
+            .collect();
+        if offsets.is_empty() {
+            return None;
+        }
+        offsets.sort_unstable();
+        Some(-offsets[(offsets.len() - 1) / 2])
+    }
+
+    /// Our clock skew if it is more than max_clock_skew_ms either way, None if within it or max_clock_skew_ms is 0
+    pub fn clock_skew_beyond(&self, max_clock_skew_ms: u64) -> Option<i64> {
+        if max_clock_skew_ms == 0 {
+            return None;
+        }
+        self.clock_skew_ms()
+            .filter(|skew| skew.unsigned_abs() > max_clock_skew_ms)
+    }
+
+    /// Forget about a request that failed, no round trip time is recorded
+    pub fn request_failed(&mut self, request_id: &OutboundRequestId) {
+        self.pending_pings.remove(request_id);
+    }
+
+    /// Count a response a connected peer sent to one of our requests
+    pub fn record_response(&mut self, peer_id: &PeerId) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.responses_received += 1;
+        }
+    }
+
+    /// Count a failed request to or from a connected peer
+    pub fn record_request_failure(&mut self, peer_id: &PeerId, outbound: bool) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            if outbound {
+                info.outbound_failures += 1;
+            } else {
+                info.inbound_failures += 1;
+            }
+        }
+    }
+
+    /// Count a message from a connected peer we gave up processing after the message processing timeout
+    pub fn record_timed_out_message(&mut self, peer_id: &PeerId) {
+        if let Some(info) = self.peers.get_mut(peer_id) {
+            info.timed_out_messages += 1;
+        }
+    }
+
+    /// Record a round trip time sample for a peer, keeping only the most recent samples
+    pub fn record_rtt(&mut self, peer_id: PeerId, rtt: Duration) {
+        let info = self.peers.entry(peer_id).or_default();
+        if info.rtt_samples.len() >= MAX_RTT_SAMPLES {
+            info.rtt_samples.pop_front();
+        }
+        info.rtt_samples.push_back(rtt);
+    }
+
+    /// Remember an outbound dial and why it was started, called when the swarm starts dialing
+    pub fn dial_started(&mut self, connection_id: ConnectionId, origin: PeerOrigin) {
+        self.pending_dials.insert(connection_id, origin);
+    }
+
+    /// Record the outcome of a dial we saw start, other connections are ignored
+    pub fn dial_finished(&mut self, connection_id: ConnectionId, success: bool) {
+        if self.pending_dials.remove(&connection_id).is_none() {
+            return;
+        }
+        if self.dial_outcomes.len() >= MAX_DIAL_OUTCOMES {
+            self.dial_outcomes.pop_front();
+        }
+        self.dial_outcomes.push_back(success);
+    }
+
+    /// Record why we disconnected a peer, kept after the peer is removed
+    pub fn record_disconnect(&mut self, peer_id: PeerId, reason: String) {
+        if self.disconnect_reasons.len() >= MAX_DISCONNECT_REASONS {
+            self.disconnect_reasons.pop_front();
+        }
+        self.disconnect_reasons.push_back((peer_id, reason));
+    }
+
+    /// The most recent reason we disconnected a peer, if we did
+    #[allow(dead_code)]
+    pub fn disconnect_reason(&self, peer_id: &PeerId) -> Option<&str> {
+        self.disconnect_reasons
+            .iter()
+            .rev()
+            .find(|(peer, _)| peer == peer_id)
+            .map(|(_, reason)| reason.as_str())
+    }
+
+    /// Pick the candidate to request a missing share from, the one with the highest sync score among the
+    /// candidates with the highest priority. Candidates we have no stats for are scored as unknown peers.
+    /// Ties are broken with rng, so requests spread over equally good peers, e.g. freshly connected ones.
+    pub fn select_sync_peer(
+        &self,
+        candidates: &[PeerId],
+        blockhash: &ShareBlockHash,
+        rng: &mut impl Rng,
+    ) -> Option<PeerId> {
+        let unknown = PeerInfo::default();
+        let top_priority = candidates
+            .iter()
+            .map(|candidate| self.priority(candidate))
+            .max()?;
+        let mut best_score = f64::NEG_INFINITY;
+        let mut best = Vec::new();
+        for candidate in candidates
+            .iter()
+            .filter(|candidate| self.priority(candidate) == top_priority)
+        {
+            let score = self
+                .peers
+                .get(candidate)
+                .unwrap_or(&unknown)
+                .sync_score(blockhash);
+            if score > best_score {
+                best_score = score;
+                best.clear();
+            }
+            if score == best_score {
+                best.push(*candidate);
+            }
+        }
+        best.choose(rng).copied()
+    }
+
+    /// Aggregate the per peer stats into a network quality summary, listening is whether the node has a listener open
+    pub fn network_quality(&self, latency_threshold: Duration, listening: bool) -> NetworkQuality {
+        let mut rtts: Vec<Duration> = self
+            .peers
+            .values()
+            .filter_map(PeerInfo::average_rtt)
+            .collect();
+        rtts.sort();
+
+        let failures = self.dial_outcomes.iter().filter(|ok| !**ok).count();
+        let dial_failure_rate = if self.dial_outcomes.is_empty() {
+            0.0
+        } else {
+            failures as f64 / self.dial_outcomes.len() as f64
+        };
+
+        NetworkQuality {
+            median_rtt: percentile(&rtts, 50),
+            p95_rtt: percentile(&rtts, 95),
+            peers_above_threshold: rtts.iter().filter(|rtt| **rtt > latency_threshold).count(),
+            dial_failure_rate,
+            listening,
+            clock_skew_ms: self.clock_skew_ms(),
+        }
+    }
+}
+
+/// Nearest rank percentile over sorted values
+fn percentile(sorted: &[Duration], percentile: usize) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percentile * sorted.len()).div_ceil(100);
+    Some(sorted[rank.saturating_sub(1)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::rng::NodeRng;
+    use rust_decimal_macros::dec;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_idle_peers_excludes_recently_active_peers() {
+        let mut stats = PeerStats::new();
+        let idle = PeerId::random();
+        let active = PeerId::random();
+        stats.add_peer(idle, Instant::now());
+        stats.add_peer(active, Instant::now());
+        let connected_at = stats.get(&idle).unwrap().last_activity.unwrap();
+        let idle_for = Duration::from_secs(600);
+
+        assert!(stats
+            .idle_peers(idle_for, connected_at + Duration::from_secs(599))
+            .is_empty());
+
+        stats.record_activity(&active, connected_at + Duration::from_secs(300));
+        assert_eq!(
+            stats.idle_peers(idle_for, connected_at + Duration::from_secs(600)),
+            vec![idle]
+        );
+        assert_eq!(
+            stats
+                .idle_peers(idle_for, connected_at + Duration::from_secs(900))
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_network_quality_percentiles() {
+        let mut stats = PeerStats::new();
+        for ms in 1..=20u64 {
+            stats.record_rtt(PeerId::random(), Duration::from_millis(ms * 10));
+        }
+        for success in [true, true, true, false] {
+            let connection_id = ConnectionId::new_unchecked(stats.dial_outcomes.len());
+            stats.dial_started(connection_id, PeerOrigin::DialPeer);
+            stats.dial_finished(connection_id, success);
+        }
+
+        let quality = stats.network_quality(Duration::from_millis(150), false);
+        assert_eq!(quality.median_rtt, Some(Duration::from_millis(100)));
+        assert_eq!(quality.p95_rtt, Some(Duration::from_millis(190)));
+        assert_eq!(quality.peers_above_threshold, 5);
+        assert_eq!(quality.dial_failure_rate, 0.25);
+    }
+
+    #[test]
+    fn test_network_quality_uses_average_rtt_per_peer() {
+        let mut stats = PeerStats::new();
+        let peer_id = PeerId::random();
+        stats.record_rtt(peer_id, Duration::from_millis(100));
+        stats.record_rtt(peer_id, Duration::from_millis(300));
+
+        let quality = stats.network_quality(Duration::from_millis(500), false);
+        assert_eq!(quality.median_rtt, Some(Duration::from_millis(200)));
+        assert_eq!(quality.p95_rtt, Some(Duration::from_millis(200)));
+        assert_eq!(quality.peers_above_threshold, 0);
+    }
+
+    #[test]
+    fn test_network_quality_without_peers() {
+        let stats = PeerStats::new();
+        let quality = stats.network_quality(Duration::from_millis(500), false);
+        assert_eq!(quality.median_rtt, None);
+        assert_eq!(quality.p95_rtt, None);
+        assert_eq!(quality.peers_above_threshold, 0);
+        assert_eq!(quality.dial_failure_rate, 0.0);
+        assert_eq!(quality.clock_skew_ms, None);
+    }
+
+    #[test]
+    fn test_request_outcomes_counted_for_connected_peers() {
+        let mut stats = PeerStats::new();
+        let peer_id = PeerId::random();
+        stats.add_peer(peer_id, Instant::now());
+        stats.record_response(&peer_id);
+        stats.record_response(&peer_id);
+        stats.record_request_failure(&peer_id, true);
+        stats.record_request_failure(&peer_id, false);
+        stats.record_request_failure(&peer_id, false);
+
+        let info = stats.get(&peer_id).unwrap();
+        assert_eq!(info.responses_received, 2);
+        assert_eq!(info.outbound_failures, 1);
+        assert_eq!(info.inbound_failures, 2);
+
+        // A message that timed out processing lowers the peer's sync score
+        let blockhash: ShareBlockHash =
+            "0000000000000000000000000000000000000000000000000000000000000001".into();
+        let score = stats.get(&peer_id).unwrap().sync_score(&blockhash);
+        stats.record_timed_out_message(&peer_id);
+        let info = stats.get(&peer_id).unwrap();
+        assert_eq!(info.timed_out_messages, 1);
+        assert!(info.sync_score(&blockhash) < score);
+
+        // Peers we are not connected to are not tracked
+        let unknown = PeerId::random();
+        stats.record_request_failure(&unknown, true);
+        assert!(stats.get(&unknown).is_none());
+    }
+
+    #[test]
+    fn test_select_sync_peer_prefers_fast_reliable_advertising_peer() {
+        let mut stats = PeerStats::new();
+        let mut rng = NodeRng::new(Some(1)).fork();
+        let blockhash: ShareBlockHash =
+            "0000000000000000000000000000000000000000000000000000000000000001".into();
+
+        // Fast but fails most requests
+        let unreliable = PeerId::random();
+        stats.add_peer(unreliable, Instant::now());
+        stats.record_rtt(unreliable, Duration::from_millis(20));
+        stats.record_response(&unreliable);
+        for _ in 0..3 {
+            stats.record_request_failure(&unreliable, true);
+        }
+        // Reliable but slow
+        let slow = PeerId::random();
+        stats.add_peer(slow, Instant::now());
+        stats.record_rtt(slow, Duration::from_secs(2));
+        stats.record_response(&slow);
+        // Fast and reliable
+        let good = PeerId::random();
+        stats.add_peer(good, Instant::now());
+        stats.record_rtt(good, Duration::from_millis(50));
+        stats.record_response(&good);
+        stats.record_response(&good);
+
+        let candidates = [unreliable, slow, good, PeerId::random()];
+        assert_eq!(
+            stats.select_sync_peer(&candidates, &blockhash, &mut rng),
+            Some(good)
+        );
+
+        // Advertising the share outweighs the latency difference
+        stats.record_inventory(&slow, InventoryMessage::BlockHashes(vec![blockhash]));
+        assert_eq!(
+            stats.select_sync_peer(&candidates, &blockhash, &mut rng),
+            Some(slow)
+        );
+        let other: ShareBlockHash =
+            "0000000000000000000000000000000000000000000000000000000000000002".into();
+        assert_eq!(
+            stats.select_sync_peer(&candidates, &other, &mut rng),
+            Some(good)
+        );
+
+        assert_eq!(stats.select_sync_peer(&[], &blockhash, &mut rng), None);
+    }
+
+    #[test]
+    fn test_peers_with_work_lists_connected_peers_claiming_enough_work() {
+        let mut stats = PeerStats::new();
+        let behind = PeerId::random();
+        let ahead = PeerId::random();
+        let silent = PeerId::random();
+        for peer_id in [behind, ahead, silent] {
+            stats.add_peer(peer_id, Instant::now());
+        }
+        stats.record_chain_work(&behind, dec!(5));
+        stats.record_chain_work(&ahead, dec!(10));
+        // Peers we are not connected to are not recorded
+        stats.record_chain_work(&PeerId::random(), dec!(20));
+
+        assert_eq!(stats.peers_with_work(dec!(10)), vec![ahead]);
+        let mut with_work = stats.peers_with_work(dec!(5));
+        with_work.sort();
+        let mut expected = vec![behind, ahead];
+        expected.sort();
+        assert_eq!(with_work, expected);
+        assert!(stats.peers_with_work(dec!(11)).is_empty());
+    }
+
+    #[test]
+    fn test_select_sync_peer_prefers_high_priority_peer() {
+        let mut stats = PeerStats::new();
+        let mut rng = NodeRng::new(Some(1)).fork();
+        let blockhash: ShareBlockHash =
+            "0000000000000000000000000000000000000000000000000000000000000001".into();
+
+        // The default priority peer is faster, more reliable and advertised the share
+        let default_peer = PeerId::random();
+        stats.add_peer(default_peer, Instant::now());
+        stats.record_rtt(default_peer, Duration::from_millis(20));
+        stats.record_response(&default_peer);
+        stats.record_inventory(
+            &default_peer,
+            InventoryMessage::BlockHashes(vec![blockhash]),
+        );
+        let infra_peer = PeerId::random();
+        stats.add_peer(infra_peer, Instant::now());
+        stats.record_rtt(infra_peer, Duration::from_secs(1));
+
+        let candidates = [default_peer, infra_peer];
+        assert_eq!(
+            stats.select_sync_peer(&candidates, &blockhash, &mut rng),
+            Some(default_peer)
+        );
+
+        stats.set_priority(infra_peer, 10);
+        assert_eq!(stats.priority(&infra_peer), 10);
+        assert_eq!(
+            stats.select_sync_peer(&candidates, &blockhash, &mut rng),
+            Some(infra_peer)
+        );
+        // The priority is kept across reconnects
+        stats.remove_peer(&infra_peer);
+        stats.add_peer(infra_peer, Instant::now());
+        assert_eq!(stats.priority(&infra_peer), 10);
+
+        // Back to the default priority, the better scored peer wins again
+        stats.set_priority(infra_peer, 0);
+        assert_eq!(
+            stats.select_sync_peer(&candidates, &blockhash, &mut rng),
+            Some(default_peer)
+        );
+    }
+
+    #[test]
+    fn test_select_sync_peer_with_same_seed_is_reproducible() {
+        let mut stats = PeerStats::new();
+        let blockhash: ShareBlockHash =
+            "0000000000000000000000000000000000000000000000000000000000000001".into();
+        // Peers we know nothing about tie
+        let candidates: Vec<PeerId> = (0..5).map(|_| PeerId::random()).collect();
+        for candidate in &candidates {
+            stats.add_peer(*candidate, Instant::now());
+        }
+
+        let selections = |seed| {
+            let mut rng = NodeRng::new(Some(seed)).fork();
+            (0..20)
+                .map(|_| {
+                    stats
+                        .select_sync_peer(&candidates, &blockhash, &mut rng)
+                        .unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = selections(42);
+        assert_eq!(first, selections(42));
+        // Ties are spread over the candidates rather than always going to the same one
+        assert!(first.iter().collect::<HashSet<_>>().len() > 1);
+        assert_ne!(first, selections(43));
+    }
+
+    #[test]
+    fn test_skewed_local_clock_is_detected() {
+        let mut stats = PeerStats::new();
+        assert_eq!(stats.clock_skew_ms(), None);
+        assert_eq!(stats.clock_skew_beyond(1_000), None);
+
+        // Our clock is five minutes behind all three peers, give or take network jitter
+        for offset_ms in [300_000, 300_200, 299_900] {
+            let peer_id = PeerId::random();
+            stats.add_peer(peer_id, Instant::now());
+            stats.record_clock_offset(peer_id, offset_ms);
+        }
+        assert_eq!(stats.clock_skew_ms(), Some(-300_000));
+        assert_eq!(stats.clock_skew_beyond(1_000), Some(-300_000));
+        assert_eq!(
+            stats
+                .network_quality(Duration::from_millis(500), false)
+                .clock_skew_ms,
+            Some(-300_000)
+        );
+        // A threshold of 0 disables the check
+        assert_eq!(stats.clock_skew_beyond(0), None);
+    }
+
+    #[test]
+    fn test_single_skewed_peer_does_not_skew_estimate() {
+        let mut stats = PeerStats::new();
+        for offset_ms in [20, -15, 600_000] {
+            let peer_id = PeerId::random();
+            stats.add_peer(peer_id, Instant::now());
+            stats.record_clock_offset(peer_id, offset_ms);
+        }
+        assert_eq!(stats.clock_skew_ms(), Some(-20));
+        assert_eq!(stats.clock_skew_beyond(1_000), None);
+    }
+
+    #[test]
+    fn test_remove_peer_drops_samples() {
+        let mut stats = PeerStats::new();
+        let peer_id = PeerId::random();
+        stats.record_rtt(peer_id, Duration::from_millis(100));
+        stats.remove_peer(&peer_id);
+        assert!(stats.get(&peer_id).is_none());
+        assert_eq!(
+            stats
+                .network_quality(Duration::from_millis(50), false)
+                .median_rtt,
+            None
+        );
+    }
+
+    #[test]
+    fn test_dial_outcomes_only_count_tracked_dials() {
+        let mut stats = PeerStats::new();
+        let dialed = ConnectionId::new_unchecked(1);
+        stats.dial_started(dialed, PeerOrigin::DialPeer);
+        stats.dial_finished(dialed, true);
+        // Finishing the same dial twice, or a dial we never saw start, is not counted
+        stats.dial_finished(dialed, false);
+        stats.dial_finished(ConnectionId::new_unchecked(2), false);
+
+        let quality = stats.network_quality(Duration::from_millis(500), false);
+        assert_eq!(quality.dial_failure_rate, 0.0);
+    }
+
+    #[test]
+    fn test_disconnect_reason_survives_peer_removal() {
+        let mut stats = PeerStats::new();
+        let peer_id = PeerId::random();
+        stats.add_peer(peer_id, Instant::now());
+        stats.record_disconnect(peer_id, "genesis mismatch: expected a got b".to_string());
+        stats.remove_peer(&peer_id);
+
+        assert_eq!(
+            stats.disconnect_reason(&peer_id),
+            Some("genesis mismatch: expected a got b")
+        );
+        assert_eq!(stats.disconnect_reason(&PeerId::random()), None);
+    }
+
+    #[test]
+    fn test_record_inventory_keeps_latest_for_connected_peer() {
+        let mut stats = PeerStats::new();
+        let peer = PeerId::random();
+        let first = InventoryMessage::BlockHashes(vec![
+            "0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5".into(),
+        ]);
+        let second = InventoryMessage::BlockHashes(vec![
+            "0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb6".into(),
+        ]);
+
+        // Not tracked until connected
+        stats.record_inventory(&peer, first.clone());
+        assert!(stats.get(&peer).is_none());
+
+        stats.add_peer(peer, Instant::now());
+        stats.record_inventory(&peer, first);
+        stats.record_inventory(&peer, second.clone());
+        assert_eq!(stats.get(&peer).unwrap().last_inventory, Some(second));
+
+        stats.remove_peer(&peer);
+        assert!(stats.get(&peer).is_none());
+    }
+
+    #[test]
+    fn test_peer_breakdown_counts_peers_by_origin() {
+        let mut stats = PeerStats::new();
+        let dialer = |address: &str| ConnectedPoint::Dialer {
+            address: address.parse().unwrap(),
+            role_override: libp2p::core::Endpoint::Dialer,
+        };
+        let listener = |send_back_addr: &str| ConnectedPoint::Listener {
+            local_addr: "/ip4/0.0.0.0/tcp/6884".parse().unwrap(),
+            send_back_addr: send_back_addr.parse().unwrap(),
+        };
+        let connections = [
+            (
+                1,
+                Some(PeerOrigin::DialPeer),
+                dialer("/ip4/10.0.0.1/tcp/6884"),
+            ),
+            (
+                2,
+                Some(PeerOrigin::DialPeer),
+                dialer("/ip4/10.0.0.2/tcp/6884"),
+            ),
+            (
+                3,
+                Some(PeerOrigin::Mdns),
+                dialer("/ip4/192.168.1.5/tcp/6884"),
+            ),
+            // Dialed by kademlia, we only see these dials in Dialing events
+            (4, None, dialer("/ip4/10.0.0.4/tcp/6884")),
+            (5, None, listener("/ip4/10.0.0.5/tcp/40000")),
+            (6, None, listener("/ip4/10.0.0.6/tcp/40000")),
+            (7, None, listener("/ip4/10.0.0.7/tcp/40000")),
+            (
+                8,
+                Some(PeerOrigin::DialPeer),
+                dialer("/ip4/198.51.100.1/tcp/4001/p2p-circuit"),
+            ),
+        ];
+        for (id, origin, endpoint) in connections {
+            let connection_id = ConnectionId::new_unchecked(id);
+            if let Some(origin) = origin {
+                stats.dial_started(connection_id, origin);
+            }
+            let peer = PeerId::random();
+            let origin = stats.connection_origin(connection_id, &endpoint);
+            stats.add_peer(peer, Instant::now());
+            stats.set_origin(&peer, origin);
+            stats.dial_finished(connection_id, true);
+        }
+
+        assert_eq!(
+            stats.peer_breakdown(),
+            PeerBreakdown {
+                dial_peers: 2,
+                mdns: 1,
+                dht: 1,
+                inbound: 3,
+                relay: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_protocols_for_connected_peer() {
+        let mut stats = PeerStats::new();
+        let peer = PeerId::random();
+        stats.add_peer(peer, Instant::now());
+
+        let protocols = vec!["/ipfs/id/1.0.0".to_string(), "/p2pool/1.0.0".to_string()];
+        stats.set_protocols(&peer, protocols.clone());
+        assert_eq!(stats.get(&peer).unwrap().protocols, protocols);
+
+        // Identify info arriving after the peer is gone does not bring it back
+        let gone = PeerId::random();
+        stats.set_protocols(&gone, protocols);
+        assert!(stats.get(&gone).is_none());
+    }
+
+    #[test]
+    fn test_set_agent_version_for_connected_peer() {
+        let mut stats = PeerStats::new();
+        let peer = PeerId::random();
+        stats.add_peer(peer, Instant::now());
+        assert_eq!(stats.get(&peer).unwrap().agent_version, None);
+
+        stats.set_agent_version(&peer, "p2poolv2/0.1.0".to_string());
+        assert_eq!(
+            stats.get(&peer).unwrap().agent_version.as_deref(),
+            Some("p2poolv2/0.1.0")
+        );
+
+        let gone = PeerId::random();
+        stats.set_agent_version(&gone, "p2poolv2/0.1.0".to_string());
+        assert!(stats.get(&gone).is_none());
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+use crate::config::StoreConfig;
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use std::time::Duration;
+use tracing::info;
+
+/// Check the store's disk usage every prune_check_interval_secs, pruning the oldest shares once it
+/// goes over max_disk_bytes until it is back under prune_low_water_bytes.
+/// Checks run one after another, so a slow prune delays the next check instead of overlapping it.
+pub async fn run_disk_usage_pruner(chain_handle: ChainHandle, store: StoreConfig) {
+    let mut check_interval =
+        tokio::time::interval(Duration::from_secs(store.prune_check_interval_secs));
+    info!(
+        "Pruning the store when it uses more than {} bytes",
+        store.max_disk_bytes
+    );
+    loop {
+        check_interval.tick().await;
+        if let Some(report) = chain_handle
+            .prune_to_disk_usage(store.max_disk_bytes, store.prune_low_water_bytes)
+            .await
+        {
+            if report.shares_pruned > 0 {
+                info!(
+                    "Pruned {} shares, store disk usage went from {} to {} bytes",
+                    report.shares_pruned, report.bytes_before, report.bytes_after
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shares::chain::PruneReport;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pruner_checks_usage_every_interval() {
+        let mut chain_handle = ChainHandle::default();
+        chain_handle
+            .expect_prune_to_disk_usage()
+            .withf(|max_disk_bytes, low_water_bytes| {
+                *max_disk_bytes == 1000 && *low_water_bytes == 800
+            })
+            .times(3)
+            .returning(|_, _| {
+                Some(PruneReport {
+                    bytes_before: 1200,
+                    bytes_after: 700,
+                    shares_pruned: 5,
+                })
+            });
+        let store = StoreConfig {
+            path: "unused".to_string(),
+            max_disk_bytes: 1000,
+            prune_low_water_bytes: 800,
+            prune_check_interval_secs: 60,
+            compaction_interval_secs: 0,
+            max_workbases: 0,
+        };
+
+        let pruner = tokio::spawn(run_disk_usage_pruner(chain_handle, store));
+        // The first check runs straight away, then once per interval
+        tokio::time::sleep(Duration::from_secs(150)).await;
+        pruner.abort();
+        let _ = pruner.await;
+    }
+}
@@ -16,8 +16,10 @@
 
 use crate::config::NetworkConfig;
 use crate::node::messages::Message;
+use crate::utils::clock::{Clock, SystemClock};
 use libp2p::PeerId;
 use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tracing::warn;
@@ -38,9 +40,7 @@ impl RecentCounter {
         }
     }
 
-    fn increment(&mut self, window: Duration) -> bool {
-        let now = Instant::now();
-
+    fn increment(&mut self, window: Duration, now: Instant) -> bool {
         // Remove timestamps outside the current window
         while let Some(front) = self.timestamps.front() {
             if now.duration_since(*front) > window {
@@ -54,9 +54,7 @@ impl RecentCounter {
         true
     }
 
-    fn count(&mut self, window: Duration) -> usize {
-        let now = Instant::now();
-
+    fn count(&mut self, window: Duration, now: Instant) -> usize {
         // Prune old entries
         while let Some(front) = self.timestamps.front() {
             if now.duration_since(*front) > window {
@@ -71,10 +69,11 @@ impl RecentCounter {
 }
 
 /// Rate limiter to prevent DoS attacks by limiting the number of messages
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct RateLimiter {
     limits: Mutex<HashMap<PeerId, HashMap<MessageType, RecentCounter>>>,
     window: Duration,
+    clock: Arc<dyn Clock>,
 }
 
 /// Enum representing message types for rate limiting
@@ -91,6 +90,13 @@ pub enum MessageType {
     ShareHeaders,
     ShareBlock,
     GetData,
+    Ping,
+    Pong,
+    ChainState,
+    GetChainFrom,
+    ChainPage,
+    Announcement,
+    Busy,
 }
 
 impl RateLimiter {
@@ -98,15 +104,22 @@ impl RateLimiter {
         Self {
             limits: Mutex::new(HashMap::new()),
             window,
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Read the time messages arrive at from clock instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     // Get the message type from a Message
     fn get_message_type(message: &Message) -> MessageType {
         match message {
-            Message::Workbase(_) => MessageType::Workbase,
+            Message::Workbase(_) | Message::WorkbaseUpdate { .. } => MessageType::Workbase,
             Message::UserWorkbase(_) => MessageType::UserWorkbase,
-            Message::MiningShare(_) => MessageType::MiningShare,
+            Message::MiningShare(_) | Message::TimedMiningShare { .. } => MessageType::MiningShare,
             Message::Inventory(_) => MessageType::Inventory,
             Message::Transaction(_) => MessageType::Transaction,
             Message::NotFound(_) => MessageType::NotFound,
@@ -115,6 +128,13 @@ impl RateLimiter {
             Message::ShareHeaders(_) => MessageType::ShareHeaders,
             Message::ShareBlock(_) => MessageType::ShareBlock,
             Message::GetData(_) => MessageType::GetData,
+            Message::Ping(_) => MessageType::Ping,
+            Message::Pong { .. } => MessageType::Pong,
+            Message::ChainState { .. } => MessageType::ChainState,
+            Message::GetChainFrom(_) | Message::GetChainFromLocator(_) => MessageType::GetChainFrom,
+            Message::ChainPage(_) => MessageType::ChainPage,
+            Message::Announcement { .. } => MessageType::Announcement,
+            Message::Busy => MessageType::Busy,
         }
     }
 
@@ -126,9 +146,11 @@ impl RateLimiter {
         config: &NetworkConfig,
     ) -> bool {
         let max_allowed = match message {
-            Message::Workbase(_) => config.max_workbase_per_second,
+            Message::Workbase(_) | Message::WorkbaseUpdate { .. } => config.max_workbase_per_second,
             Message::UserWorkbase(_) => config.max_userworkbase_per_second,
-            Message::MiningShare(_) => config.max_miningshare_per_second,
+            Message::MiningShare(_) | Message::TimedMiningShare { .. } => {
+                config.max_miningshare_per_second
+            }
             Message::Inventory(_) => config.max_inventory_per_second,
             Message::Transaction(_) => config.max_transaction_per_second,
             _ => return true,
@@ -143,7 +165,8 @@ impl RateLimiter {
             .entry(message_type)
             .or_insert_with(RecentCounter::new);
 
-        let current_count = counter.count(self.window);
+        let now = self.clock.instant();
+        let current_count = counter.count(self.window, now);
         if current_count >= max_allowed as usize {
             warn!(
                 "Rate limit exceeded for peer {} and message type {:?}",
@@ -151,7 +174,7 @@ impl RateLimiter {
             );
             false
         } else {
-            counter.increment(self.window);
+            counter.increment(self.window, now);
             true
         }
     }
@@ -160,26 +183,59 @@ impl RateLimiter {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::NetworkConfig;
+    use crate::config::{NetworkConfig, DEFAULT_AGENT_VERSION};
+    use crate::node::gossip_conformance::GossipAnomalyAction;
     use crate::shares::miner_message::{Gbt, MinerWorkbase};
     use libp2p::PeerId;
 
     fn test_config() -> NetworkConfig {
         NetworkConfig {
             listen_address: "".to_string(),
+            enable_ipv4: true,
+            enable_ipv6: true,
             dial_peers: vec![],
             enable_mdns: false,
+            require_discovery: false,
             max_pending_incoming: 0,
             max_pending_outgoing: 0,
             max_established_incoming: 0,
             max_established_outgoing: 0,
             max_established_per_peer: 0,
+            tcp_nodelay: false,
+            tcp_listen_backlog: 1024,
             max_workbase_per_second: 2,
             max_userworkbase_per_second: 1,
             max_miningshare_per_second: 100,
             max_inventory_per_second: 100,
             max_transaction_per_second: 100,
             rate_limit_window_secs: 1,
+            latency_threshold_ms: 500,
+            max_clock_skew_ms: 30_000,
+            auto_gossip: false,
+            observer: false,
+            watchdog_timeout_secs: 0,
+            max_dropped_responses_per_minute: 0,
+            min_healthy_peers: 0,
+            max_share_gap_secs: 0,
+            isolation_grace_period_secs: 0,
+            gossip_startup_buffer_secs: 0,
+            peer_idle_evict_secs: 0,
+            max_sync_sessions: 4,
+            max_advertised_addresses: 8,
+            share_validation_concurrency: 4,
+            message_processing_timeout: 0,
+            max_gossip_lag: 10,
+            sync_min_height_offset: 1000,
+            trusted_operator_keys: vec![],
+            allowed_peers: vec![],
+            gossip_anomaly_action: GossipAnomalyAction::Log,
+            drop_gossip_echoes: true,
+            measure_propagation_latency: false,
+            max_inflight_requests_per_peer: 8,
+            serialization_self_test: true,
+            allow_unsafe_ops: false,
+            agent_version: DEFAULT_AGENT_VERSION.to_string(),
+            test_seed: None,
         }
     }
 
@@ -286,4 +342,44 @@ mod tests {
                 .await
         );
     }
+
+    #[tokio::test]
+    async fn test_mock_clock_evicts_messages_once_window_passes() {
+        use crate::utils::clock::MockClock;
+        use std::time::SystemTime;
+
+        let config = test_config();
+        let clock = MockClock::new(SystemTime::now());
+        let limiter = RateLimiter::new(Duration::from_secs(60)).with_clock(Arc::new(clock.clone()));
+        let peer_id = PeerId::random();
+
+        for _ in 0..2 {
+            assert!(
+                limiter
+                    .check_rate_limit(&peer_id, create_test_workbase(), &config)
+                    .await
+            );
+        }
+        assert!(
+            !limiter
+                .check_rate_limit(&peer_id, create_test_workbase(), &config)
+                .await
+        );
+
+        // Still within the window, nothing is evicted
+        clock.advance(Duration::from_secs(60));
+        assert!(
+            !limiter
+                .check_rate_limit(&peer_id, create_test_workbase(), &config)
+                .await
+        );
+
+        // Just past the window both messages are evicted, without waiting on the real clock
+        clock.advance(Duration::from_millis(1));
+        assert!(
+            limiter
+                .check_rate_limit(&peer_id, create_test_workbase(), &config)
+                .await
+        );
+    }
 }
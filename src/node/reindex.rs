@@ -0,0 +1,121 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+use crate::node::events::{EventSender, NodeEvent};
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::info;
+
+/// Reindex the store one height at a time, from genesis up to the current chain tip.
+/// Each height is a separate request to the chain actor, so other chain requests are served in between.
+/// Progress is published after every height, and the reindex stops before the next height once cancelled.
+pub async fn run_reindex(
+    chain_handle: ChainHandle,
+    event_tx: EventSender,
+    cancelled: Arc<AtomicBool>,
+) {
+    let total = match chain_handle.get_tip_height().await {
+        Some(tip_height) => tip_height + 1,
+        None => 0,
+    };
+    info!("Starting reindex of {} heights", total);
+    for height in 0..total {
+        if cancelled.load(Ordering::SeqCst) {
+            info!("Reindex cancelled after {} of {} heights", height, total);
+            return;
+        }
+        chain_handle.reindex_height(height).await;
+        event_tx.send(NodeEvent::ReindexProgress {
+            done: height + 1,
+            total,
+        });
+    }
+    info!("Reindex of {} heights finished", total);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::events::{SequencedEvent, EVENT_CHANNEL_CAPACITY};
+    use tokio::sync::broadcast;
+
+    fn progress_events(event_rx: &mut broadcast::Receiver<SequencedEvent>) -> Vec<(u32, u32)> {
+        let mut progress = Vec::new();
+        while let Ok(sequenced) = event_rx.try_recv() {
+            if let NodeEvent::ReindexProgress { done, total } = sequenced.event {
+                progress.push((done, total));
+            }
+        }
+        progress
+    }
+
+    #[tokio::test]
+    async fn test_run_reindex_reports_progress_for_every_height() {
+        let mut chain_handle = ChainHandle::default();
+        chain_handle.expect_get_tip_height().returning(|| Some(2));
+        chain_handle
+            .expect_reindex_height()
+            .times(3)
+            .returning(|_| 1);
+        let event_tx = EventSender::new(EVENT_CHANNEL_CAPACITY);
+        let mut event_rx = event_tx.subscribe();
+
+        run_reindex(chain_handle, event_tx, Arc::new(AtomicBool::new(false))).await;
+
+        assert_eq!(progress_events(&mut event_rx), vec![(1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[tokio::test]
+    async fn test_run_reindex_stops_once_cancelled() {
+        let mut chain_handle = ChainHandle::default();
+        chain_handle.expect_get_tip_height().returning(|| Some(9));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancel = cancelled.clone();
+        // Cancel while the third height is being reindexed
+        chain_handle
+            .expect_reindex_height()
+            .times(3)
+            .returning(move |height| {
+                if height == 2 {
+                    cancel.store(true, Ordering::SeqCst);
+                }
+                1
+            });
+        let event_tx = EventSender::new(EVENT_CHANNEL_CAPACITY);
+        let mut event_rx = event_tx.subscribe();
+
+        run_reindex(chain_handle, event_tx, cancelled).await;
+
+        assert_eq!(
+            progress_events(&mut event_rx),
+            vec![(1, 10), (2, 10), (3, 10)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_reindex_empty_chain() {
+        let mut chain_handle = ChainHandle::default();
+        chain_handle.expect_get_tip_height().returning(|| None);
+        chain_handle.expect_reindex_height().never();
+        let event_tx = EventSender::new(EVENT_CHANNEL_CAPACITY);
+        let mut event_rx = event_tx.subscribe();
+
+        run_reindex(chain_handle, event_tx, Arc::new(AtomicBool::new(false))).await;
+
+        assert!(progress_events(&mut event_rx).is_empty());
+    }
+}
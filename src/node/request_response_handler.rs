@@ -20,9 +20,10 @@ use crate::node::p2p_message_handlers::handle_request;
 use crate::node::SwarmSend;
 #[mockall_double::double]
 use crate::shares::chain::actor::ChainHandle;
-use crate::utils::time_provider::SystemTimeProvider;
+use crate::utils::clock::Clock;
 use libp2p::request_response::ResponseChannel;
 use std::error::Error;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 use tracing::{debug, error, info};
 
@@ -32,6 +33,7 @@ pub async fn handle_request_response_event(
     event: RequestResponseEvent<Message, Message>,
     chain_handle: ChainHandle,
     swarm_tx: mpsc::Sender<SwarmSend<ResponseChannel<Message>>>,
+    clock: Arc<dyn Clock>,
 ) -> Result<(), Box<dyn Error>> {
     info!("Request-response event: {:?}", event);
     match event {
@@ -45,14 +47,13 @@ pub async fn handle_request_response_event(
                 },
         } => {
             debug!("Received request from peer: {}", peer);
-            let time_provider = SystemTimeProvider {};
             if let Err(e) = handle_request::<ResponseChannel<Message>>(
                 peer,
                 request,
                 chain_handle,
                 response_channel,
                 swarm_tx,
-                &time_provider,
+                &clock,
             )
             .await
             {
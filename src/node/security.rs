@@ -0,0 +1,94 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use libp2p::core::ConnectedPoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+
+/// The security protocol the swarm upgrades every connection with
+pub const SECURITY_PROTOCOL: &str = "/noise";
+
+/// The security protocol connections over this address are upgraded with, None if the swarm has no secured
+/// transport for it. The swarm only has a TCP transport, upgraded with Noise when it is built in Node::new.
+/// If a transport is ever added without Noise, this has to be updated before its connections are accepted.
+pub fn security_protocol(address: &Multiaddr) -> Option<&'static str> {
+    let mut protocols = address.iter();
+    let tcp = matches!(
+        (protocols.next(), protocols.next()),
+        (
+            Some(Protocol::Ip4(_) | Protocol::Ip6(_)),
+            Some(Protocol::Tcp(_))
+        )
+    );
+    (tcp && protocols.all(|protocol| matches!(protocol, Protocol::P2p(_))))
+        .then_some(SECURITY_PROTOCOL)
+}
+
+/// Check a connection was upgraded with SECURITY_PROTOCOL, going by the remote address of its endpoint
+pub fn check_connection_security(endpoint: &ConnectedPoint) -> Result<(), String> {
+    let address = endpoint.get_remote_address();
+    match security_protocol(address) {
+        Some(SECURITY_PROTOCOL) => Ok(()),
+        Some(protocol) => Err(format!(
+            "connection over {address} used {protocol}, expected {SECURITY_PROTOCOL}"
+        )),
+        None => Err(format!(
+            "connection over {address} used a transport not secured with {SECURITY_PROTOCOL}"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::core::Endpoint;
+
+    #[test]
+    fn test_noise_connections_pass_security_check() {
+        let dialer = ConnectedPoint::Dialer {
+            address: "/ip4/127.0.0.1/tcp/6884".parse().unwrap(),
+            role_override: Endpoint::Dialer,
+        };
+        assert_eq!(check_connection_security(&dialer), Ok(()));
+
+        let listener = ConnectedPoint::Listener {
+            local_addr: "/ip6/::1/tcp/6884".parse().unwrap(),
+            send_back_addr: "/ip6/::1/tcp/52714".parse().unwrap(),
+        };
+        assert_eq!(check_connection_security(&listener), Ok(()));
+
+        let with_peer_id: Multiaddr =
+            format!("/ip4/127.0.0.1/tcp/6884/p2p/{}", libp2p::PeerId::random())
+                .parse()
+                .unwrap();
+        assert_eq!(security_protocol(&with_peer_id), Some(SECURITY_PROTOCOL));
+    }
+
+    #[test]
+    fn test_unsecured_transports_fail_security_check() {
+        for address in [
+            "/memory/1234",
+            "/ip4/127.0.0.1/udp/6884/quic-v1",
+            "/ip4/127.0.0.1/tcp/6884/ws",
+        ] {
+            let dialer = ConnectedPoint::Dialer {
+                address: address.parse().unwrap(),
+                role_override: Endpoint::Dialer,
+            };
+            assert!(check_connection_security(&dialer).is_err(), "{address}");
+        }
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::shares::ShareBlock;
+use bitcoin::PublicKey;
+use rust_decimal::Decimal;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// Number of matching shares buffered for each share subscriber, a subscriber that falls
+/// further behind misses shares
+pub const SHARE_SUBSCRIPTION_CAPACITY: usize = 64;
+
+/// Which accepted shares a share subscriber receives, an empty filter matches all shares
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShareFilter {
+    /// Only shares from this miner
+    pub miner_pubkey: Option<PublicKey>,
+    /// Only shares with at least this difficulty
+    pub min_difficulty: Option<Decimal>,
+}
+
+#[allow(dead_code)]
+impl ShareFilter {
+    pub fn with_miner_pubkey(mut self, miner_pubkey: PublicKey) -> Self {
+        self.miner_pubkey = Some(miner_pubkey);
+        self
+    }
+
+    pub fn with_min_difficulty(mut self, min_difficulty: Decimal) -> Self {
+        self.min_difficulty = Some(min_difficulty);
+        self
+    }
+
+    pub fn matches(&self, share: &ShareBlock) -> bool {
+        self.miner_pubkey
+            .is_none_or(|miner_pubkey| share.header.miner_pubkey == miner_pubkey)
+            && self
+                .min_difficulty
+                .is_none_or(|min_difficulty| share.header.miner_share.diff >= min_difficulty)
+    }
+}
+
+/// Share subscribers and their filters, shares are filtered here before they are sent so
+/// subscribers only receive the shares they asked for
+#[derive(Default)]
+pub struct ShareSubscriptions {
+    subscribers: Vec<(ShareFilter, mpsc::Sender<ShareBlock>)>,
+}
+
+#[allow(dead_code)]
+impl ShareSubscriptions {
+    /// Add a subscriber receiving the accepted shares matching filter
+    pub fn subscribe(&mut self, filter: ShareFilter) -> mpsc::Receiver<ShareBlock> {
+        let (tx, rx) = mpsc::channel(SHARE_SUBSCRIPTION_CAPACITY);
+        self.subscribers.push((filter, tx));
+        rx
+    }
+
+    /// Send an accepted share to the subscribers it matches, dropping subscribers that have gone
+    pub fn publish(&mut self, share: &ShareBlock) {
+        self.subscribers.retain(|(filter, tx)| {
+            if tx.is_closed() {
+                return false;
+            }
+            if !filter.matches(share) {
+                return true;
+            }
+            match tx.try_send(share.clone()) {
+                Ok(()) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    warn!(
+                        "Share subscriber is falling behind, dropped share {:?}",
+                        share.cached_blockhash
+                    );
+                    true
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestBlockBuilder;
+    use rust_decimal_macros::dec;
+
+    const MINER1: &str = "020202020202020202020202020202020202020202020202020202020202020202";
+    const MINER2: &str = "020202020202020202020202020202020202020202020202020202020202020203";
+
+    fn share(miner_pubkey: &str, diff: Decimal) -> ShareBlock {
+        TestBlockBuilder::new()
+            .miner_pubkey(miner_pubkey)
+            .diff(diff)
+            .build()
+    }
+
+    #[test]
+    fn test_filter_matches_miner_and_min_difficulty() {
+        assert!(ShareFilter::default().matches(&share(MINER1, dec!(1.0))));
+
+        let filter = ShareFilter::default()
+            .with_miner_pubkey(MINER1.parse().unwrap())
+            .with_min_difficulty(dec!(5.0));
+        assert!(filter.matches(&share(MINER1, dec!(5.0))));
+        assert!(!filter.matches(&share(MINER1, dec!(4.0))));
+        assert!(!filter.matches(&share(MINER2, dec!(10.0))));
+    }
+
+    #[test]
+    fn test_publish_sends_only_matching_shares() {
+        let mut subscriptions = ShareSubscriptions::default();
+        let mut all_rx = subscriptions.subscribe(ShareFilter::default());
+        let mut miner1_rx = subscriptions
+            .subscribe(ShareFilter::default().with_miner_pubkey(MINER1.parse().unwrap()));
+
+        subscriptions.publish(&share(MINER1, dec!(1.0)));
+        subscriptions.publish(&share(MINER2, dec!(1.0)));
+
+        assert_eq!(
+            miner1_rx.try_recv().unwrap().header.miner_pubkey,
+            MINER1.parse().unwrap()
+        );
+        assert!(miner1_rx.try_recv().is_err());
+        assert_eq!(
+            all_rx.try_recv().unwrap().header.miner_pubkey,
+            MINER1.parse().unwrap()
+        );
+        assert_eq!(
+            all_rx.try_recv().unwrap().header.miner_pubkey,
+            MINER2.parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_publish_drops_closed_subscribers() {
+        let mut subscriptions = ShareSubscriptions::default();
+        let kept_rx = subscriptions.subscribe(ShareFilter::default());
+        drop(subscriptions.subscribe(ShareFilter::default()));
+
+        subscriptions.publish(&share(MINER1, dec!(1.0)));
+        assert_eq!(subscriptions.len(), 1);
+        drop(kept_rx);
+        subscriptions.publish(&share(MINER1, dec!(1.0)));
+        assert!(subscriptions.is_empty());
+    }
+}
@@ -0,0 +1,405 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::gossip_handler::{apply_share, is_stale_share, ShareValidation};
+use crate::node::metrics::Metrics;
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use crate::shares::miner_message::{MinerWorkbase, UserWorkbase};
+use crate::shares::validation::pow_cache::PowCacheHandle;
+use crate::shares::validation::{fetch_share_workbases, validate_timestamp};
+use crate::shares::ShareBlock;
+use crate::utils::clock::Clock;
+use futures::stream::{self, StreamExt};
+use libp2p::PeerId;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// Gossiped shares waiting for validation, shares arriving when the queue is full are dropped
+pub const SHARE_VALIDATION_QUEUE_SIZE: usize = 1024;
+
+/// A gossiped share waiting to be validated and added to the chain
+#[derive(Debug)]
+pub struct ShareJob {
+    pub share: ShareBlock,
+    pub peer_id: PeerId,
+    /// The gossip topic the share arrived on, rejects are counted against it
+    pub topic: String,
+    /// When the originating node gossiped the share, for timed shares
+    pub origin_millis: Option<u64>,
+    /// The max_gossip_lag in force when the share arrived
+    pub max_gossip_lag: u32,
+}
+
+/// Run validate on up to concurrency jobs at once, each on its own task, and pass the results to apply
+/// one at a time in the order the jobs arrived. A slow job holds back the results of the jobs after it.
+/// Returns once the jobs channel is closed and every job has been applied.
+pub async fn validate_in_order<J, R, V, VF, A, AF>(
+    jobs: mpsc::Receiver<J>,
+    concurrency: usize,
+    validate: V,
+    mut apply: A,
+) where
+    V: Fn(J) -> VF,
+    VF: Future<Output = R> + Send + 'static,
+    R: Send + 'static,
+    A: FnMut(R) -> AF,
+    AF: Future<Output = ()>,
+{
+    let jobs = stream::unfold(jobs, |mut jobs| async move {
+        jobs.recv().await.map(|job| (job, jobs))
+    });
+    // buffered only pulls a job once fewer than concurrency are running, and yields them in input order
+    let mut results = pin!(jobs
+        .map(|job| tokio::spawn(validate(job)))
+        .buffered(concurrency.max(1)));
+    while let Some(result) = results.next().await {
+        match result {
+            Ok(result) => apply(result).await,
+            Err(e) => error!("Share validation task failed: {}", e),
+        }
+    }
+}
+
+/// Checks a share's proof of work against the workbases it was mined on, see validation::validate_proof_of_work
+pub type ProofOfWorkCheck =
+    fn(&ShareBlock, &MinerWorkbase, &UserWorkbase, &PowCacheHandle) -> Result<(), String>;
+
+/// Run the processing of a message, giving up on it once it takes longer than timeout.
+/// Returns None if it timed out, a zero timeout never gives up. The timeout can only fire when the processing
+/// awaits, so computation has to run on a blocking task for the timeout to bound it.
+pub async fn within_processing_timeout<F: Future>(
+    timeout: Duration,
+    processing: F,
+) -> Option<F::Output> {
+    if timeout.is_zero() {
+        return Some(processing.await);
+    }
+    tokio::time::timeout(timeout, processing).await.ok()
+}
+
+/// Validate a gossiped share, checking its proof of work with check_proof_of_work on a blocking task.
+/// Returns None if the proof of work check took longer than timeout. The check keeps running on its blocking
+/// thread, but the share is rejected without waiting for it. The lookups on the chain aren't timed, a slow chain
+/// is our own problem and not the fault of the peer that sent the share.
+async fn validate_share_job(
+    job: &ShareJob,
+    chain_handle: &ChainHandle,
+    clock: &Arc<dyn Clock>,
+    timeout: Duration,
+    check_proof_of_work: ProofOfWorkCheck,
+) -> Option<ShareValidation> {
+    if is_stale_share(&job.share, chain_handle, job.max_gossip_lag).await {
+        return Some(ShareValidation::Stale);
+    }
+    if let Err(e) = validate_timestamp(&job.share, clock).await {
+        return Some(ShareValidation::Invalid(format!(
+            "Share timestamp validation failed: {}",
+            e
+        )));
+    }
+    let (workbase, userworkbase) = match fetch_share_workbases(&job.share, chain_handle).await {
+        Ok(workbases) => workbases,
+        Err(e) => return Some(ShareValidation::Invalid(e.to_string())),
+    };
+    let share = job.share.clone();
+    let pow_cache = chain_handle.pow_cache();
+    let check = tokio::task::spawn_blocking(move || {
+        check_proof_of_work(&share, &workbase, &userworkbase, &pow_cache)
+    });
+    match within_processing_timeout(timeout, check).await? {
+        Ok(Ok(())) => Some(ShareValidation::Valid),
+        Ok(Err(reason)) => Some(ShareValidation::Invalid(reason)),
+        Err(e) => Some(ShareValidation::Invalid(format!(
+            "Proof of work check failed: {}",
+            e
+        ))),
+    }
+}
+
+/// Validate gossiped shares concurrently and add the valid ones to the chain in the order they arrived,
+/// so the CPU heavy checks don't hold up the node's event loop.
+/// A share whose proof of work check takes longer than timeout is rejected and its peer sent on timed_out_tx.
+/// Adding a share to the chain is given the same timeout, but the peer isn't blamed when the chain is slow.
+/// Share timestamps and propagation latency are measured against clock, the node's clock.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_share_validation(
+    jobs: mpsc::Receiver<ShareJob>,
+    concurrency: usize,
+    timeout: Duration,
+    chain_handle: ChainHandle,
+    metrics: Arc<Metrics>,
+    timed_out_tx: mpsc::Sender<PeerId>,
+    clock: Arc<dyn Clock>,
+    check_proof_of_work: ProofOfWorkCheck,
+) {
+    let validate = {
+        let chain_handle = chain_handle.clone();
+        let clock = clock.clone();
+        move |job: ShareJob| {
+            let chain_handle = chain_handle.clone();
+            let clock = clock.clone();
+            async move {
+                let validation =
+                    validate_share_job(&job, &chain_handle, &clock, timeout, check_proof_of_work)
+                        .await;
+                (job, validation)
+            }
+        }
+    };
+    let apply = |(job, validation): (ShareJob, Option<ShareValidation>)| {
+        let chain_handle = chain_handle.clone();
+        let metrics = metrics.clone();
+        let timed_out_tx = timed_out_tx.clone();
+        let clock = clock.clone();
+        async move {
+            let Some(validation) = validation else {
+                warn!(
+                    "Rejecting share {:?} from peer {}, validation took longer than {:?}",
+                    job.share.cached_blockhash, job.peer_id, timeout
+                );
+                metrics.record_gossip_reject(&job.topic);
+                if timed_out_tx.try_send(job.peer_id).is_err() {
+                    warn!("Failed to report timed out share from peer {}", job.peer_id);
+                }
+                return;
+            };
+            apply_validated_share(job, validation, &chain_handle, &metrics, &*clock, timeout).await
+        }
+    };
+    validate_in_order(jobs, concurrency, validate, apply).await;
+}
+
+/// Add a share that passed validation to the chain and record its propagation latency if it was timed.
+/// Stops waiting for the chain after timeout, so a slow chain doesn't hold back the shares behind this one.
+async fn apply_validated_share(
+    job: ShareJob,
+    validation: ShareValidation,
+    chain_handle: &ChainHandle,
+    metrics: &Metrics,
+    clock: &dyn Clock,
+    timeout: Duration,
+) {
+    match validation {
+        ShareValidation::Valid => {}
+        ShareValidation::Stale => {
+            info!(
+                "Discarding stale share {:?} from peer: {}",
+                job.share.cached_blockhash, job.peer_id
+            );
+            return;
+        }
+        ShareValidation::Invalid(reason) => {
+            error!("Share block validation failed: {}", reason);
+            metrics.record_gossip_reject(&job.topic);
+            return;
+        }
+    }
+    let blockhash = job.share.cached_blockhash;
+    match within_processing_timeout(timeout, apply_share(job.share, job.peer_id, chain_handle))
+        .await
+    {
+        Some(Ok(())) => {}
+        Some(Err(e)) => {
+            error!("Failed to add share: {}", e);
+            metrics.record_gossip_reject(&job.topic);
+            return;
+        }
+        None => {
+            warn!(
+                "Stopped waiting for the chain to add share {:?} from peer {} after {:?}",
+                blockhash, job.peer_id, timeout
+            );
+            return;
+        }
+    }
+    if let Some(origin_millis) = job.origin_millis {
+        metrics.record_propagation_latency(origin_millis, clock.millis_since_epoch());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TestBlockBuilder, TestMinerWorkbaseBuilder, TestUserWorkbaseBuilder};
+    use crate::utils::clock::MockClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[tokio::test]
+    async fn test_validation_runs_concurrently_and_insertions_stay_ordered() {
+        let (jobs_tx, jobs_rx) = mpsc::channel(16);
+        // Earlier jobs take longer, so they finish validating after the jobs behind them
+        for job in 0..8u64 {
+            jobs_tx.send(job).await.unwrap();
+        }
+        drop(jobs_tx);
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let validate = {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            move |job: u64| {
+                let running = running.clone();
+                let max_running = max_running.clone();
+                async move {
+                    let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now_running, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(80 - job * 10)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    job
+                }
+            }
+        };
+        let inserted = Arc::new(Mutex::new(Vec::new()));
+        let apply = {
+            let inserted = inserted.clone();
+            move |job: u64| {
+                let inserted = inserted.clone();
+                async move { inserted.lock().unwrap().push(job) }
+            }
+        };
+
+        validate_in_order(jobs_rx, 4, validate, apply).await;
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 4);
+        assert_eq!(*inserted.lock().unwrap(), (0..8).collect::<Vec<u64>>());
+    }
+
+    #[tokio::test]
+    async fn test_slow_validation_times_out_without_holding_back_later_jobs() {
+        let (jobs_tx, jobs_rx) = mpsc::channel(16);
+        for job in 0..4u64 {
+            jobs_tx.send(job).await.unwrap();
+        }
+        drop(jobs_tx);
+
+        // Job 1 is pathological and would take far longer than the timeout to validate
+        let validate = |job: u64| async move {
+            let took = if job == 1 {
+                Duration::from_secs(60)
+            } else {
+                Duration::from_millis(10)
+            };
+            let validation = within_processing_timeout(Duration::from_millis(100), async move {
+                tokio::time::sleep(took).await;
+            })
+            .await;
+            (job, validation.is_some())
+        };
+        let applied = Arc::new(Mutex::new(Vec::new()));
+        let apply = {
+            let applied = applied.clone();
+            move |result: (u64, bool)| {
+                let applied = applied.clone();
+                async move { applied.lock().unwrap().push(result) }
+            }
+        };
+
+        let started = std::time::Instant::now();
+        validate_in_order(jobs_rx, 2, validate, apply).await;
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            *applied.lock().unwrap(),
+            vec![(0, true), (1, false), (2, true), (3, true)]
+        );
+        // A zero timeout never gives up
+        assert_eq!(
+            within_processing_timeout(Duration::ZERO, async { 7 }).await,
+            Some(7)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_share_validation_reports_the_peer_of_a_share_with_slow_proof_of_work() {
+        let share = TestBlockBuilder::new()
+            .blockhash("0000000000000000000000000000000000000000000000000000000000000001")
+            .build();
+        let mined_at = UNIX_EPOCH
+            + Duration::from_secs(share.header.miner_share.ntime.to_consensus_u32() as u64);
+
+        /// A mock chain with the workbases the share needs, cloned with the same expectations
+        fn chain_with_workbases() -> ChainHandle {
+            let mut chain_handle = ChainHandle::default();
+            chain_handle
+                .expect_get_workbase()
+                .returning(|_| Some(TestMinerWorkbaseBuilder::new().build()));
+            chain_handle
+                .expect_get_user_workbase()
+                .returning(|_| Some(TestUserWorkbaseBuilder::new().build()));
+            chain_handle
+                .expect_pow_cache()
+                .returning(PowCacheHandle::default);
+            // The share is rejected, so it is never added to the chain
+            chain_handle.expect_add_share_with_provenance().never();
+            chain_handle.expect_clone().returning(chain_with_workbases);
+            chain_handle
+        }
+
+        fn slow_proof_of_work(
+            _: &ShareBlock,
+            _: &MinerWorkbase,
+            _: &UserWorkbase,
+            _: &PowCacheHandle,
+        ) -> Result<(), String> {
+            std::thread::sleep(Duration::from_secs(2));
+            Ok(())
+        }
+
+        let peer_id = PeerId::random();
+        let (jobs_tx, jobs_rx) = mpsc::channel(1);
+        let (timed_out_tx, mut timed_out_rx) = mpsc::channel(1);
+        let metrics = Arc::new(Metrics::new());
+        let validation = tokio::spawn(run_share_validation(
+            jobs_rx,
+            1,
+            Duration::from_millis(100),
+            chain_with_workbases(),
+            metrics.clone(),
+            timed_out_tx,
+            Arc::new(MockClock::new(mined_at)),
+            slow_proof_of_work,
+        ));
+        jobs_tx
+            .send(ShareJob {
+                share,
+                peer_id,
+                topic: "share".to_string(),
+                origin_millis: None,
+                max_gossip_lag: 10,
+            })
+            .await
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        let timed_out = tokio::time::timeout(Duration::from_secs(1), timed_out_rx.recv())
+            .await
+            .expect("The slow share should time out before its proof of work check finishes");
+        assert_eq!(timed_out, Some(peer_id));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(metrics.snapshot().topics["share"].rejects, 1);
+
+        drop(jobs_tx);
+        validation.await.unwrap();
+    }
+}
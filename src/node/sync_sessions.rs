@@ -0,0 +1,194 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::shares::chain::ChainCursor;
+use libp2p::PeerId;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits the number of peers we sync from at once, so startup sync uses predictable resources
+/// A session starts when we decide to sync from a peer with more work and ends when the peer has served
+/// its last chain page, a request fails or the peer disconnects.
+/// Peers waiting for a session are served in the order they arrived as sessions end.
+/// A sample of every page a peer serves is verified, peers that fail verification are rejected and not synced
+/// from again. The most recent MAX_REJECTED_PEERS rejected peers are remembered.
+#[derive(Debug, Clone)]
+pub struct SyncSessions {
+    semaphore: Arc<Semaphore>,
+    active: Arc<Mutex<HashMap<PeerId, Session>>>,
+    /// Rejected peers, oldest first
+    rejected: Arc<Mutex<VecDeque<PeerId>>>,
+}
+
+/// Most rejected peers remembered, the oldest rejection is forgotten when another peer is rejected
+pub const MAX_REJECTED_PEERS: usize = 1024;
+
+#[derive(Debug)]
+struct Session {
+    _permit: OwnedSemaphorePermit,
+    /// Where the next chain page is requested from, None until the first page is requested
+    cursor: Option<ChainCursor>,
+}
+
+impl SyncSessions {
+    pub fn new(max_sessions: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_sessions)),
+            active: Arc::new(Mutex::new(HashMap::new())),
+            rejected: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Wait for a free session and start it for peer_id
+    /// Returns false if a session with the peer is already running, we only sync once from each peer at a time.
+    pub async fn start(&self, peer_id: PeerId) -> bool {
+        if self.is_active(&peer_id) {
+            return false;
+        }
+        // We never close the semaphore, so acquiring only waits
+        let Ok(permit) = self.semaphore.clone().acquire_owned().await else {
+            return false;
+        };
+        let mut active = self.active.lock().unwrap();
+        if active.contains_key(&peer_id) {
+            return false;
+        }
+        active.insert(
+            peer_id,
+            Session {
+                _permit: permit,
+                cursor: None,
+            },
+        );
+        true
+    }
+
+    /// Record the cursor of the chain page last requested from peer_id. Returns false if no session is running.
+    pub fn set_cursor(&self, peer_id: &PeerId, cursor: ChainCursor) -> bool {
+        match self.active.lock().unwrap().get_mut(peer_id) {
+            Some(session) => {
+                session.cursor = Some(cursor);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The cursor of the chain page last requested from peer_id, None if no session is running or no page was requested
+    pub fn cursor(&self, peer_id: &PeerId) -> Option<ChainCursor> {
+        self.active
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .and_then(|session| session.cursor)
+    }
+
+    /// End the session with a peer that failed verification and don't sync from it again
+    pub fn reject(&self, peer_id: &PeerId) {
+        {
+            let mut rejected = self.rejected.lock().unwrap();
+            if !rejected.contains(peer_id) {
+                if rejected.len() >= MAX_REJECTED_PEERS {
+                    rejected.pop_front();
+                }
+                rejected.push_back(*peer_id);
+            }
+        }
+        self.finish(peer_id);
+    }
+
+    /// Whether the peer failed verification and isn't used for sync
+    pub fn is_rejected(&self, peer_id: &PeerId) -> bool {
+        self.rejected.lock().unwrap().contains(peer_id)
+    }
+
+    /// End the session with peer_id, letting the next waiting peer start. Returns false if none was running.
+    pub fn finish(&self, peer_id: &PeerId) -> bool {
+        self.active.lock().unwrap().remove(peer_id).is_some()
+    }
+
+    /// Whether a session with peer_id is running
+    pub fn is_active(&self, peer_id: &PeerId) -> bool {
+        self.active.lock().unwrap().contains_key(peer_id)
+    }
+
+    /// The peers with a running session and the cursor of the chain page last requested from each
+    pub fn sessions(&self) -> Vec<(PeerId, Option<ChainCursor>)> {
+        self.active
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer_id, session)| (*peer_id, session.cursor))
+            .collect()
+    }
+
+    /// Number of sessions running
+    pub fn active(&self) -> usize {
+        self.active.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_waiting_peer_starts_once_session_finishes() {
+        let sessions = SyncSessions::new(1);
+        let first = PeerId::random();
+        let second = PeerId::random();
+
+        assert!(sessions.start(first).await);
+        // A second session with the same peer is refused without waiting
+        assert!(!sessions.start(first).await);
+
+        let waiting = tokio::spawn({
+            let sessions = sessions.clone();
+            async move { sessions.start(second).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        assert_eq!(sessions.active(), 1);
+
+        assert!(sessions.finish(&first));
+        assert!(waiting.await.unwrap());
+        assert!(sessions.is_active(&second));
+        assert!(!sessions.finish(&first));
+    }
+
+    #[test]
+    fn test_rejected_peers_are_bounded_forgetting_the_oldest() {
+        let sessions = SyncSessions::new(1);
+        let first = PeerId::random();
+        sessions.reject(&first);
+        sessions.reject(&first);
+        assert!(sessions.is_rejected(&first));
+
+        for _ in 1..MAX_REJECTED_PEERS {
+            sessions.reject(&PeerId::random());
+        }
+        assert!(sessions.is_rejected(&first));
+        assert_eq!(sessions.rejected.lock().unwrap().len(), MAX_REJECTED_PEERS);
+
+        let last = PeerId::random();
+        sessions.reject(&last);
+        assert!(!sessions.is_rejected(&first));
+        assert!(sessions.is_rejected(&last));
+        assert_eq!(sessions.rejected.lock().unwrap().len(), MAX_REJECTED_PEERS);
+    }
+}
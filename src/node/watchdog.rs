@@ -0,0 +1,164 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::utils::clock::Clock;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::error;
+
+/// Tracks the last time the node's event loop went round
+/// The node actor beats the watchdog on every pass of its loop. Its ping and isolation timers keep an idle
+/// loop going round, so only a loop stuck inside a handler stops beating.
+#[derive(Clone)]
+pub struct Watchdog {
+    timeout: Option<Duration>,
+    last_beat: Arc<Mutex<Instant>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Watchdog {
+    /// Create a watchdog that expires after timeout_secs without a beat on clock, 0 disables it
+    pub fn new(timeout_secs: u64, clock: Arc<dyn Clock>) -> Self {
+        let timeout = match timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+        Self {
+            timeout,
+            last_beat: Arc::new(Mutex::new(clock.instant())),
+            clock,
+        }
+    }
+
+    /// The configured timeout, None if the watchdog is disabled
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Record that the loop went round, pushing the deadline out by the timeout
+    pub fn beat(&self) {
+        *self.last_beat.lock().unwrap() = self.clock.instant();
+    }
+
+    /// Resolves once the timeout passes without a beat, never resolves if the watchdog is disabled
+    pub async fn stalled(&self) {
+        let Some(timeout) = self.timeout else {
+            return std::future::pending().await;
+        };
+        loop {
+            let deadline = *self.last_beat.lock().unwrap() + timeout;
+            let now = self.clock.instant();
+            if now >= deadline {
+                return;
+            }
+            tokio::time::sleep(deadline - now).await;
+        }
+    }
+}
+
+/// Run the event loop in its own task and abort it if the watchdog sees it stall.
+/// Returns once the loop returns or is aborted.
+pub async fn supervise(watchdog: Watchdog, event_loop: impl Future<Output = ()> + Send + 'static) {
+    let mut task = tokio::spawn(event_loop);
+    tokio::select! {
+        _ = &mut task => {},
+        _ = watchdog.stalled() => {
+            error!(
+                "Node event loop made no progress in {:?}, stopping node",
+                watchdog.timeout().unwrap_or_default()
+            );
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    use tokio::time::Instant;
+
+    /// Clock reading tokio's time, so paused tests run the watchdog without waiting
+    #[derive(Debug)]
+    struct TokioClock;
+
+    impl Clock for TokioClock {
+        fn instant(&self) -> std::time::Instant {
+            Instant::now().into_std()
+        }
+
+        fn system_time(&self) -> SystemTime {
+            SystemTime::now()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_expires_without_beats() {
+        let watchdog = Watchdog::new(5, Arc::new(TokioClock));
+        let start = Instant::now();
+        watchdog.stalled().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_beat_pushes_deadline_out() {
+        let watchdog = Watchdog::new(5, Arc::new(TokioClock));
+        tokio::time::advance(Duration::from_secs(4)).await;
+        watchdog.beat();
+        let result = tokio::time::timeout(Duration::from_secs(4), watchdog.stalled()).await;
+        assert!(result.is_err());
+        watchdog.stalled().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_disabled_never_expires() {
+        let watchdog = Watchdog::new(0, Arc::new(TokioClock));
+        assert!(watchdog.timeout().is_none());
+        let result = tokio::time::timeout(Duration::from_secs(3600), watchdog.stalled()).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervise_keeps_a_loop_that_goes_round() {
+        let watchdog = Watchdog::new(5, Arc::new(TokioClock));
+        let beating = watchdog.clone();
+        let event_loop = async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                beating.beat();
+            }
+        };
+        let result =
+            tokio::time::timeout(Duration::from_secs(60), supervise(watchdog, event_loop)).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_supervise_aborts_a_stuck_loop() {
+        let watchdog = Watchdog::new(5, Arc::new(TokioClock));
+        let beating = watchdog.clone();
+        let event_loop = async move {
+            beating.beat();
+            // Stuck in a handler that never returns
+            std::future::pending::<()>().await;
+        };
+        let start = Instant::now();
+        supervise(watchdog, event_loop).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+}
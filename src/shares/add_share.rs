@@ -0,0 +1,448 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::node::messages::Message;
+use crate::node::SwarmSend;
+#[mockall_double::double]
+use crate::shares::chain::actor::ChainHandle;
+use crate::shares::store::ShareProvenance;
+use crate::shares::validation;
+use crate::shares::{ShareBlock, ShareError};
+use crate::utils::time_provider::TimeProvider;
+use std::error::Error;
+use std::fmt;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// The effect of adding a share to the chain
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddShareOutcome {
+    /// The share was added and is the new tip of the main chain
+    AcceptedMain,
+    /// The share was added but is not on the main chain, it can be referenced as an uncle
+    AcceptedUncle,
+    /// The share is already in the chain, nothing was changed
+    Duplicate,
+    /// The share was not added
+    Rejected(AddShareError),
+}
+
+/// Reasons a share is rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddShareError {
+    /// The share has no blockhash, so it can't be stored
+    MissingBlockhash,
+    /// The share failed validation
+    Invalid(String),
+    /// The share's coinbase doesn't pay the miner to a well formed address, so the share can't be paid out
+    InvalidMinerAddress(String),
+    /// The share was valid but the chain failed to store it
+    Store(String),
+    /// The node is an observer and doesn't take local shares
+    Observer,
+}
+
+impl fmt::Display for AddShareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddShareError::MissingBlockhash => write!(f, "Share has no blockhash"),
+            AddShareError::Invalid(reason) => write!(f, "Invalid share: {}", reason),
+            AddShareError::InvalidMinerAddress(reason) => {
+                write!(f, "Share can't be paid out: {}", reason)
+            }
+            AddShareError::Store(reason) => write!(f, "Failed to store share: {}", reason),
+            AddShareError::Observer => {
+                write!(f, "Node is an observer, local shares are not accepted")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AddShareError {}
+
+/// Validate and add a locally produced share to the chain, reporting what happened to it
+/// Shares we already have are reported as duplicates without being validated again.
+pub async fn add_local_share(
+    share: ShareBlock,
+    chain_handle: &ChainHandle,
+    time_provider: &impl TimeProvider,
+) -> AddShareOutcome {
+    let blockhash = match share.cached_blockhash {
+        Some(blockhash) => blockhash,
+        None => return AddShareOutcome::Rejected(AddShareError::MissingBlockhash),
+    };
+    if chain_handle.get_share(blockhash).await.is_some() {
+        info!("Share {} is already in the chain", blockhash);
+        return AddShareOutcome::Duplicate;
+    }
+    if let Err(e) = validation::validate(&share, chain_handle, time_provider).await {
+        error!("Share {} validation failed: {}", blockhash, e);
+        return AddShareOutcome::Rejected(AddShareError::Invalid(e.to_string()));
+    }
+    if let Err(e) = chain_handle
+        .add_share_with_provenance(share, ShareProvenance::Local)
+        .await
+    {
+        error!("Failed to add share {} to chain: {}", blockhash, e);
+        return match e.downcast_ref::<ShareError>() {
+            Some(share_error) => AddShareOutcome::Rejected(AddShareError::InvalidMinerAddress(
+                share_error.to_string(),
+            )),
+            None => AddShareOutcome::Rejected(AddShareError::Store(e.to_string())),
+        };
+    }
+    if chain_handle.get_chain_tip().await == Some(blockhash) {
+        AddShareOutcome::AcceptedMain
+    } else {
+        AddShareOutcome::AcceptedUncle
+    }
+}
+
+/// Add a batch of locally produced shares, reporting the outcome for each share in input order
+/// Shares are added one after another, so a share can build on an earlier share in the same batch.
+/// A rejected share does not stop the rest of the batch from being added.
+pub async fn add_local_share_batch(
+    shares: Vec<ShareBlock>,
+    chain_handle: &ChainHandle,
+    time_provider: &impl TimeProvider,
+) -> Vec<AddShareOutcome> {
+    let mut outcomes = Vec::with_capacity(shares.len());
+    for share in shares {
+        outcomes.push(add_local_share(share, chain_handle, time_provider).await);
+    }
+    outcomes
+}
+
+/// Add a locally produced share and gossip it once accepted, unless gossip is false.
+/// Without gossip only the validation and store writes are done, which is what throughput benchmarks measure.
+pub async fn add_local_share_with_gossip<C>(
+    share: ShareBlock,
+    chain_handle: &ChainHandle,
+    time_provider: &impl TimeProvider,
+    gossip: bool,
+    swarm_tx: &mpsc::Sender<SwarmSend<C>>,
+) -> AddShareOutcome {
+    let outcome = add_local_share(share.clone(), chain_handle, time_provider).await;
+    if gossip {
+        let _ = gossip_accepted_share(&outcome, share, swarm_tx);
+    }
+    outcome
+}
+
+/// Gossip a local share once it has been accepted into the chain
+/// Only used for shares produced locally, shares received from the network are forwarded by gossipsub.
+/// We use try_send as this is called from the node's event loop, which is also the receiver of swarm_tx.
+pub fn gossip_accepted_share<C>(
+    outcome: &AddShareOutcome,
+    share: ShareBlock,
+    swarm_tx: &mpsc::Sender<SwarmSend<C>>,
+) -> Result<(), Box<dyn Error>> {
+    match outcome {
+        AddShareOutcome::AcceptedMain | AddShareOutcome::AcceptedUncle => {
+            if let Err(e) = swarm_tx.try_send(SwarmSend::Gossip(Message::MiningShare(share))) {
+                error!("Failed to gossip local share: {}", e);
+                return Err("Error gossiping local share".into());
+            }
+            Ok(())
+        }
+        AddShareOutcome::Duplicate | AddShareOutcome::Rejected(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shares::validation::pow_cache::PowCacheHandle;
+    use crate::shares::ShareBlockHash;
+    use crate::test_utils::valid_share_block;
+    use crate::utils::time_provider::TestTimeProvider;
+    use mockall::predicate::*;
+    use std::time::SystemTime;
+
+    /// Build a valid share along with a chain handle mock that can validate it
+    fn valid_share_and_chain_handle() -> (ShareBlock, ChainHandle, TestTimeProvider) {
+        let (share_block, workbase, user_workbase, miner_share) = valid_share_block();
+
+        let mut chain_handle = ChainHandle::default();
+        chain_handle
+            .expect_get_share()
+            .with(eq(share_block.cached_blockhash.unwrap()))
+            .returning(|_| None);
+        chain_handle
+            .expect_get_workbase()
+            .with(eq(7473434392883363843))
+            .returning(move |_| Some(workbase.clone()));
+        chain_handle
+            .expect_get_user_workbase()
+            .with(eq(7473434392883363843))
+            .returning(move |_| Some(user_workbase.clone()));
+        chain_handle
+            .expect_pow_cache()
+            .returning(PowCacheHandle::default);
+
+        let mut time_provider = TestTimeProvider(SystemTime::now());
+        time_provider.set_time(miner_share.ntime);
+
+        (share_block, chain_handle, time_provider)
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_accepted_main() {
+        let (share_block, mut chain_handle, time_provider) = valid_share_and_chain_handle();
+        let blockhash = share_block.cached_blockhash.unwrap();
+        chain_handle
+            .expect_add_share_with_provenance()
+            .with(eq(share_block.clone()), eq(ShareProvenance::Local))
+            .returning(|_, _| Ok(()));
+        chain_handle
+            .expect_get_chain_tip()
+            .returning(move || Some(blockhash));
+
+        let outcome = add_local_share(share_block, &chain_handle, &time_provider).await;
+        assert_eq!(outcome, AddShareOutcome::AcceptedMain);
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_accepted_uncle() {
+        let (share_block, mut chain_handle, time_provider) = valid_share_and_chain_handle();
+        chain_handle
+            .expect_add_share_with_provenance()
+            .with(eq(share_block.clone()), eq(ShareProvenance::Local))
+            .returning(|_, _| Ok(()));
+        chain_handle.expect_get_chain_tip().returning(|| {
+            Some("0000000000000000000000000000000000000000000000000000000000000001".into())
+        });
+
+        let outcome = add_local_share(share_block, &chain_handle, &time_provider).await;
+        assert_eq!(outcome, AddShareOutcome::AcceptedUncle);
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_duplicate() {
+        let (share_block, _, time_provider) = valid_share_and_chain_handle();
+        let mut chain_handle = ChainHandle::default();
+        let existing = share_block.clone();
+        chain_handle
+            .expect_get_share()
+            .with(eq(share_block.cached_blockhash.unwrap()))
+            .returning(move |_| Some(existing.clone()));
+        chain_handle.expect_add_share_with_provenance().never();
+
+        let outcome = add_local_share(share_block, &chain_handle, &time_provider).await;
+        assert_eq!(outcome, AddShareOutcome::Duplicate);
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_rejected_invalid() {
+        let (share_block, chain_handle, _) = valid_share_and_chain_handle();
+        // The share timestamp is far in the past compared to now
+        let time_provider = TestTimeProvider(SystemTime::now());
+
+        let outcome = add_local_share(share_block, &chain_handle, &time_provider).await;
+        match outcome {
+            AddShareOutcome::Rejected(AddShareError::Invalid(reason)) => {
+                assert!(reason.starts_with("Share timestamp validation failed"));
+            }
+            _ => panic!("Expected invalid share rejection, got {:?}", outcome),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_rejected_store_error() {
+        let (share_block, mut chain_handle, time_provider) = valid_share_and_chain_handle();
+        chain_handle
+            .expect_add_share_with_provenance()
+            .returning(|_, _| Err("Failed to add share".into()));
+
+        let outcome = add_local_share(share_block, &chain_handle, &time_provider).await;
+        assert_eq!(
+            outcome,
+            AddShareOutcome::Rejected(AddShareError::Store("Failed to add share".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_rejected_invalid_miner_address() {
+        let (share_block, mut chain_handle, time_provider) = valid_share_and_chain_handle();
+        chain_handle
+            .expect_add_share_with_provenance()
+            .returning(|_, _| Err(ShareError::MissingCoinbase.into()));
+
+        let outcome = add_local_share(share_block, &chain_handle, &time_provider).await;
+        assert_eq!(
+            outcome,
+            AddShareOutcome::Rejected(AddShareError::InvalidMinerAddress(
+                ShareError::MissingCoinbase.to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_rejected_without_blockhash() {
+        let (mut share_block, chain_handle, time_provider) = valid_share_and_chain_handle();
+        share_block.cached_blockhash = None;
+
+        let outcome = add_local_share(share_block, &chain_handle, &time_provider).await;
+        assert_eq!(
+            outcome,
+            AddShareOutcome::Rejected(AddShareError::MissingBlockhash)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_batch_reports_outcomes_in_order() {
+        let (share_block, mut chain_handle, time_provider) = valid_share_and_chain_handle();
+        let blockhash = share_block.cached_blockhash.unwrap();
+        chain_handle
+            .expect_add_share_with_provenance()
+            .with(eq(share_block.clone()), eq(ShareProvenance::Local))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        chain_handle
+            .expect_get_chain_tip()
+            .returning(move || Some(blockhash));
+
+        let mut without_blockhash = share_block.clone();
+        without_blockhash.cached_blockhash = None;
+
+        let existing_hash: ShareBlockHash =
+            "0000000000000000000000000000000000000000000000000000000000000001".into();
+        let mut existing = share_block.clone();
+        existing.cached_blockhash = Some(existing_hash);
+        let stored = existing.clone();
+        chain_handle
+            .expect_get_share()
+            .with(eq(existing_hash))
+            .returning(move |_| Some(stored.clone()));
+
+        let outcomes = add_local_share_batch(
+            vec![without_blockhash, share_block, existing],
+            &chain_handle,
+            &time_provider,
+        )
+        .await;
+        assert_eq!(
+            outcomes,
+            vec![
+                AddShareOutcome::Rejected(AddShareError::MissingBlockhash),
+                AddShareOutcome::AcceptedMain,
+                AddShareOutcome::Duplicate,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_batch_empty() {
+        let (_, chain_handle, time_provider) = valid_share_and_chain_handle();
+        let outcomes = add_local_share_batch(vec![], &chain_handle, &time_provider).await;
+        assert!(outcomes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_without_gossip_stores_many_shares() {
+        let (share_block, mut chain_handle, time_provider) = valid_share_and_chain_handle();
+        let blockhash = share_block.cached_blockhash.unwrap();
+        let count = 500;
+        chain_handle
+            .expect_add_share_with_provenance()
+            .with(eq(share_block.clone()), eq(ShareProvenance::Local))
+            .times(count)
+            .returning(|_, _| Ok(()));
+        chain_handle
+            .expect_get_chain_tip()
+            .returning(move || Some(blockhash));
+        // Room for a single message is enough to catch any gossip attempt
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+
+        let started = std::time::Instant::now();
+        for _ in 0..count {
+            let outcome = add_local_share_with_gossip(
+                share_block.clone(),
+                &chain_handle,
+                &time_provider,
+                false,
+                &swarm_tx,
+            )
+            .await;
+            assert_eq!(outcome, AddShareOutcome::AcceptedMain);
+        }
+        info!("Added {} shares in {:?}", count, started.elapsed());
+
+        assert!(swarm_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_add_local_share_with_gossip_publishes_accepted_share() {
+        let (share_block, mut chain_handle, time_provider) = valid_share_and_chain_handle();
+        let blockhash = share_block.cached_blockhash.unwrap();
+        chain_handle
+            .expect_add_share_with_provenance()
+            .returning(|_, _| Ok(()));
+        chain_handle
+            .expect_get_chain_tip()
+            .returning(move || Some(blockhash));
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+
+        let outcome = add_local_share_with_gossip(
+            share_block.clone(),
+            &chain_handle,
+            &time_provider,
+            true,
+            &swarm_tx,
+        )
+        .await;
+        assert_eq!(outcome, AddShareOutcome::AcceptedMain);
+
+        match swarm_rx.try_recv() {
+            Ok(SwarmSend::Gossip(Message::MiningShare(share))) => assert_eq!(share, share_block),
+            _ => panic!("Expected SwarmSend::Gossip with MiningShare message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gossip_accepted_share_publishes_share() {
+        let (share_block, _, _) = valid_share_and_chain_handle();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+
+        let result = gossip_accepted_share(
+            &AddShareOutcome::AcceptedMain,
+            share_block.clone(),
+            &swarm_tx,
+        );
+        assert!(result.is_ok());
+
+        match swarm_rx.try_recv() {
+            Ok(SwarmSend::Gossip(Message::MiningShare(share))) => assert_eq!(share, share_block),
+            _ => panic!("Expected SwarmSend::Gossip with MiningShare message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gossip_accepted_share_skips_duplicate_and_rejected() {
+        let (share_block, _, _) = valid_share_and_chain_handle();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel::<SwarmSend<u32>>(1);
+
+        gossip_accepted_share(&AddShareOutcome::Duplicate, share_block.clone(), &swarm_tx).unwrap();
+        gossip_accepted_share(
+            &AddShareOutcome::Rejected(AddShareError::MissingBlockhash),
+            share_block,
+            &swarm_tx,
+        )
+        .unwrap();
+
+        assert!(swarm_rx.try_recv().is_err());
+    }
+}
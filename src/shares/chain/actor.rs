@@ -14,15 +14,28 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
-use super::chain::Chain;
+use super::chain::{
+    BlockCandidate, Chain, ChainCursor, ChainPage, ChainStats, DeepReorg, Equivocation,
+    OrphanSummary, PruneReport, PurgeReport, Reorg, ShareStatus,
+};
+use super::dag::DagSnapshot;
+use super::payout::{PayoutProof, PayoutReport};
+use super::snapshot::{ChainSnapshot, SnapshotError};
+use crate::config::{ChainConfig, StoreConfig};
 use crate::shares::miner_message::{MinerWorkbase, UserWorkbase};
-use crate::shares::store::Store;
+use crate::shares::store::{
+    ShareProvenance, Store, StoreBenchmark, WorkbaseOutcome, WorkbaseRange,
+};
+use crate::shares::validation::pow_cache::PowCacheHandle;
 use crate::shares::{ShareBlock, ShareBlockHash, ShareHeader};
+use crate::utils::clock::Clock;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
 use tracing::{debug, error};
 
 #[derive(Debug)]
@@ -32,10 +45,16 @@ pub enum ChainMessage {
     Reorg(ShareBlock, Decimal),
     IsConfirmed(ShareBlock),
     AddShare(ShareBlock),
+    AddShareWithProvenance(ShareBlock, ShareProvenance),
+    GetShareProvenance(ShareBlockHash),
+    GetShareStatus(ShareBlockHash),
+    GetChainStats,
     StoreWorkbase(MinerWorkbase),
     StoreUserWorkbase(UserWorkbase),
     GetWorkbase(u64),
+    GetPreviousWorkbase(u64),
     GetWorkbases(Vec<u64>),
+    ListWorkbases(WorkbaseRange, usize, usize),
     GetUserWorkbase(u64),
     GetUserWorkbases(Vec<u64>),
     GetShare(ShareBlockHash),
@@ -43,12 +62,36 @@ pub enum ChainMessage {
     GetShareHeaders(Vec<ShareBlockHash>),
     GetTotalDifficulty,
     GetChainTip,
+    GetTipHeight,
+    GetShareHeights(Vec<ShareBlockHash>),
     GetChainTipAndUncles,
     GetDepth(ShareBlockHash),
+    GetPath(ShareBlockHash, ShareBlockHash),
     GetHeadersForLocator(Vec<ShareBlockHash>, ShareBlockHash, usize),
     GetBlockhashesForLocator(Vec<ShareBlockHash>, ShareBlockHash, usize),
+    GetChainPage(ChainCursor, usize, usize),
+    GetChainPageFromLocator(Vec<ShareBlockHash>, usize, usize),
     BuildLocator,
     GetMissingBlockhashes(Vec<ShareBlockHash>),
+    GetSharesByMiner(bitcoin::Address),
+    ReindexHeight(u32),
+    DrainOrphans,
+    GetOrphanSummary,
+    GetMissingAncestors(ShareBlockHash),
+    GetDagSnapshot(u32),
+    ComputePayouts,
+    GetPayoutReport(usize, u64),
+    GetInclusionProof(ShareBlockHash),
+    VerifyInclusionProof(PayoutProof),
+    EstimateHashrate(Duration, u64),
+    GetShareHistogram(Duration, usize, u64),
+    LoadSnapshot(ChainSnapshot),
+    ReopenStore,
+    SetTip(ShareBlockHash),
+    CompactStore,
+    BenchmarkStore(usize),
+    PruneToDiskUsage(u64, u64),
+    PurgePeerShares(libp2p::PeerId),
 }
 
 #[derive(Debug)]
@@ -60,7 +103,7 @@ pub enum ChainResponse {
     ReorgResult(Result<(), Box<dyn Error + Send + Sync>>),
     IsConfirmedResult(bool),
     AddShareResult(Result<(), Box<dyn Error + Send + Sync>>),
-    StoreWorkbaseResult(Result<(), Box<dyn Error + Send + Sync>>),
+    StoreWorkbaseResult(Result<WorkbaseOutcome, Box<dyn Error + Send + Sync>>),
     StoreUserWorkbaseResult(Result<(), Box<dyn Error + Send + Sync>>),
     GetWorkbaseResult(Option<MinerWorkbase>),
     GetWorkbasesResult(Vec<MinerWorkbase>),
@@ -70,12 +113,38 @@ pub enum ChainResponse {
     GetSharesAtHeightResult(HashMap<ShareBlockHash, ShareBlock>),
     GetShareHeadersResult(Vec<ShareHeader>),
     ChainTip(Option<ShareBlockHash>),
+    TipHeight(Option<u32>),
+    ShareHeights(Vec<Option<u32>>),
     ChainTipAndUncles(Option<ShareBlockHash>, HashSet<ShareBlockHash>),
     Depth(Option<usize>),
+    Path(Option<Vec<ShareBlockHash>>),
     GetHeadersForLocatorResult(Vec<ShareHeader>),
     BuildLocatorResult(Vec<ShareBlockHash>),
     GetBlockhashesForLocatorResult(Vec<ShareBlockHash>),
+    ChainPage(ChainPage),
     GetMissingBlockhashesResult(Vec<ShareBlockHash>),
+    GetSharesByMinerResult(Vec<ShareBlockHash>),
+    ReindexHeightResult(usize),
+    DrainOrphansResult(usize),
+    OrphanSummary(OrphanSummary),
+    MissingAncestors(Vec<ShareBlockHash>),
+    DagSnapshot(DagSnapshot),
+    Payouts(HashMap<bitcoin::Address, u64>),
+    PayoutReport(PayoutReport),
+    InclusionProof(Option<PayoutProof>),
+    InclusionProofVerified(Result<(), String>),
+    Hashrate(f64),
+    ShareHistogram(Vec<(u64, u64)>),
+    LoadSnapshotResult(Result<(), SnapshotError>),
+    ReopenStoreResult(Result<(), Box<dyn Error + Send + Sync>>),
+    SetTipResult(Result<(), Box<dyn Error + Send + Sync>>),
+    CompactStoreResult(u64, u64),
+    StoreBenchmarkResult(Result<StoreBenchmark, Box<dyn Error + Send + Sync>>),
+    ShareProvenance(Option<ShareProvenance>),
+    ShareStatus(ShareStatus),
+    ChainStats(ChainStats),
+    PruneReport(PruneReport),
+    PurgeReport(PurgeReport),
 }
 
 pub struct ChainActor {
@@ -130,6 +199,44 @@ impl ChainActor {
                         error!("Failed to send add_share response: {}", e);
                     }
                 }
+                ChainMessage::AddShareWithProvenance(share_block, provenance) => {
+                    let result = self
+                        .chain
+                        .add_share_with_provenance(share_block, provenance);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::AddShareResult(result))
+                        .await
+                    {
+                        error!("Failed to send add_share response: {}", e);
+                    }
+                }
+                ChainMessage::GetShareStatus(blockhash) => {
+                    let result = self.chain.get_share_status(&blockhash);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::ShareStatus(result))
+                        .await
+                    {
+                        error!("Failed to send get_share_status response: {}", e);
+                    }
+                }
+                ChainMessage::GetChainStats => {
+                    let result = self.chain.get_chain_stats();
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::ChainStats(result))
+                        .await
+                    {
+                        error!("Failed to send get_chain_stats response: {}", e);
+                    }
+                }
+                ChainMessage::GetShareProvenance(blockhash) => {
+                    let result = self.chain.get_share_provenance(&blockhash);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::ShareProvenance(result))
+                        .await
+                    {
+                        error!("Failed to send get_share_provenance response: {}", e);
+                    }
+                }
                 ChainMessage::StoreWorkbase(workbase) => {
                     let result = self.chain.add_workbase(workbase);
                     if let Err(e) = response_sender
@@ -148,6 +255,15 @@ impl ChainActor {
                         error!("Failed to send get_workbase response: {}", e);
                     }
                 }
+                ChainMessage::GetPreviousWorkbase(workinfoid) => {
+                    let result = self.chain.get_previous_workbase(workinfoid);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::GetWorkbaseResult(result))
+                        .await
+                    {
+                        error!("Failed to send get_previous_workbase response: {}", e);
+                    }
+                }
                 ChainMessage::GetWorkbases(workinfoids) => {
                     let result = self.chain.get_workbases(&workinfoids);
                     if let Err(e) = response_sender
@@ -157,6 +273,15 @@ impl ChainActor {
                         error!("Failed to send get_workbases response: {}", e);
                     }
                 }
+                ChainMessage::ListWorkbases(range, offset, limit) => {
+                    let result = self.chain.list_workbases(&range, offset, limit);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::GetWorkbasesResult(result))
+                        .await
+                    {
+                        error!("Failed to send list_workbases response: {}", e);
+                    }
+                }
                 ChainMessage::GetShare(share_hash) => {
                     let result: Option<ShareBlock> = self.chain.get_share(&share_hash);
                     if let Err(e) = response_sender
@@ -199,6 +324,24 @@ impl ChainActor {
                         error!("Failed to send get_chain_tip response: {}", e);
                     }
                 }
+                ChainMessage::GetTipHeight => {
+                    let result = self.chain.get_tip_height();
+                    if let Err(e) = response_sender.send(ChainResponse::TipHeight(result)).await {
+                        error!("Failed to send get_tip_height response: {}", e);
+                    }
+                }
+                ChainMessage::GetShareHeights(blockhashes) => {
+                    let result = blockhashes
+                        .iter()
+                        .map(|blockhash| self.chain.get_share_height(blockhash))
+                        .collect();
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::ShareHeights(result))
+                        .await
+                    {
+                        error!("Failed to send get_share_heights response: {}", e);
+                    }
+                }
                 ChainMessage::GetChainTipAndUncles => {
                     let (chain_tip, uncles) = self.chain.get_chain_tip_and_uncles();
                     if let Err(e) = response_sender
@@ -208,6 +351,12 @@ impl ChainActor {
                         error!("Failed to send get_chain_tip_and_uncles response: {}", e);
                     }
                 }
+                ChainMessage::GetPath(from, to) => {
+                    let result = self.chain.get_path(&from, &to);
+                    if let Err(e) = response_sender.send(ChainResponse::Path(result)).await {
+                        error!("Failed to send get_path response: {}", e);
+                    }
+                }
                 ChainMessage::GetDepth(blockhash) => {
                     let result = self.chain.get_depth(&blockhash);
                     if let Err(e) = response_sender.send(ChainResponse::Depth(result)).await {
@@ -269,6 +418,20 @@ impl ChainActor {
                         error!("Failed to send get_blockhashes_for_locator response: {}", e);
                     }
                 }
+                ChainMessage::GetChainPage(cursor, max_shares, max_bytes) => {
+                    let page = self.chain.get_chain_page(cursor, max_shares, max_bytes);
+                    if let Err(e) = response_sender.send(ChainResponse::ChainPage(page)).await {
+                        error!("Failed to send get_chain_page response: {}", e);
+                    }
+                }
+                ChainMessage::GetChainPageFromLocator(locator, max_shares, max_bytes) => {
+                    let page = self
+                        .chain
+                        .get_chain_page_from_locator(&locator, max_shares, max_bytes);
+                    if let Err(e) = response_sender.send(ChainResponse::ChainPage(page)).await {
+                        error!("Failed to send get_chain_page_from_locator response: {}", e);
+                    }
+                }
                 ChainMessage::BuildLocator => {
                     let result = self.chain.build_locator();
                     let result = match result {
@@ -294,6 +457,194 @@ impl ChainActor {
                         error!("Failed to send get_missing_blockhashes response: {}", e);
                     }
                 }
+                ChainMessage::GetSharesByMiner(address) => {
+                    let result = self.chain.get_shares_by_miner(&address);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::GetSharesByMinerResult(result))
+                        .await
+                    {
+                        error!("Failed to send get_shares_by_miner response: {}", e);
+                    }
+                }
+                ChainMessage::ReindexHeight(height) => {
+                    let result = self.chain.reindex_height(height);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::ReindexHeightResult(result))
+                        .await
+                    {
+                        error!("Failed to send reindex_height response: {}", e);
+                    }
+                }
+                ChainMessage::DrainOrphans => {
+                    let result = self.chain.drain_orphans();
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::DrainOrphansResult(result))
+                        .await
+                    {
+                        error!("Failed to send drain_orphans response: {}", e);
+                    }
+                }
+                ChainMessage::GetMissingAncestors(blockhash) => {
+                    let result = self.chain.get_missing_ancestors(&blockhash);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::MissingAncestors(result))
+                        .await
+                    {
+                        error!("Failed to send get_missing_ancestors response: {}", e);
+                    }
+                }
+                ChainMessage::GetOrphanSummary => {
+                    let result = self.chain.get_orphan_summary();
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::OrphanSummary(result))
+                        .await
+                    {
+                        error!("Failed to send get_orphan_summary response: {}", e);
+                    }
+                }
+                ChainMessage::GetDagSnapshot(depth) => {
+                    let result = self.chain.get_dag_snapshot(depth);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::DagSnapshot(result))
+                        .await
+                    {
+                        error!("Failed to send get_dag_snapshot response: {}", e);
+                    }
+                }
+                ChainMessage::ComputePayouts => {
+                    let result = self.chain.compute_payouts();
+                    if let Err(e) = response_sender.send(ChainResponse::Payouts(result)).await {
+                        error!("Failed to send compute_payouts response: {}", e);
+                    }
+                }
+                ChainMessage::GetPayoutReport(window, total_reward) => {
+                    let result = self.chain.payout_report(window, total_reward);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::PayoutReport(result))
+                        .await
+                    {
+                        error!("Failed to send payout_report response: {}", e);
+                    }
+                }
+                ChainMessage::GetInclusionProof(block_hash) => {
+                    let result = self.chain.inclusion_proof(block_hash);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::InclusionProof(result))
+                        .await
+                    {
+                        error!("Failed to send inclusion_proof response: {}", e);
+                    }
+                }
+                ChainMessage::VerifyInclusionProof(proof) => {
+                    let result = self.chain.verify_inclusion_proof(&proof);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::InclusionProofVerified(result))
+                        .await
+                    {
+                        error!("Failed to send verify_inclusion_proof response: {}", e);
+                    }
+                }
+                ChainMessage::EstimateHashrate(window, now) => {
+                    let result = self.chain.estimate_hashrate(window, now);
+                    if let Err(e) = response_sender.send(ChainResponse::Hashrate(result)).await {
+                        error!("Failed to send estimate_hashrate response: {}", e);
+                    }
+                }
+                ChainMessage::GetShareHistogram(bucket, count, now) => {
+                    let result = self.chain.share_histogram(bucket, count, now);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::ShareHistogram(result))
+                        .await
+                    {
+                        error!("Failed to send share_histogram response: {}", e);
+                    }
+                }
+                ChainMessage::PruneToDiskUsage(max_disk_bytes, low_water_bytes) => {
+                    let result = self
+                        .chain
+                        .prune_to_disk_usage(max_disk_bytes, low_water_bytes);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::PruneReport(result))
+                        .await
+                    {
+                        error!("Failed to send prune_to_disk_usage response: {}", e);
+                    }
+                }
+                ChainMessage::PurgePeerShares(peer_id) => {
+                    let result = self.chain.purge_peer_shares(peer_id);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::PurgeReport(result))
+                        .await
+                    {
+                        error!("Failed to send purge_peer_shares response: {}", e);
+                    }
+                }
+                ChainMessage::CompactStore => {
+                    // Compacting a large store takes minutes, it runs on the blocking pool so the actor keeps
+                    // serving chain requests in the meantime
+                    let maintenance = self.chain.store_maintenance();
+                    tokio::spawn(async move {
+                        match tokio::task::spawn_blocking(move || maintenance.compact()).await {
+                            Ok((bytes_before, bytes_after)) => {
+                                if let Err(e) = response_sender
+                                    .send(ChainResponse::CompactStoreResult(
+                                        bytes_before,
+                                        bytes_after,
+                                    ))
+                                    .await
+                                {
+                                    error!("Failed to send compact_store response: {}", e);
+                                }
+                            }
+                            Err(e) => error!("Store compaction task failed: {}", e),
+                        }
+                    });
+                }
+                ChainMessage::BenchmarkStore(ops) => {
+                    // Like compaction, the benchmark runs on the blocking pool so it doesn't hold up the actor
+                    let maintenance = self.chain.store_maintenance();
+                    tokio::spawn(async move {
+                        match tokio::task::spawn_blocking(move || maintenance.benchmark(ops)).await
+                        {
+                            Ok(result) => {
+                                if let Err(e) = response_sender
+                                    .send(ChainResponse::StoreBenchmarkResult(result))
+                                    .await
+                                {
+                                    error!("Failed to send benchmark_store response: {}", e);
+                                }
+                            }
+                            Err(e) => error!("Store benchmark task failed: {}", e),
+                        }
+                    });
+                }
+                ChainMessage::SetTip(blockhash) => {
+                    let result = self.chain.set_tip(blockhash);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::SetTipResult(result))
+                        .await
+                    {
+                        error!("Failed to send set_tip response: {}", e);
+                    }
+                }
+                ChainMessage::ReopenStore => {
+                    let result = self.chain.reopen_store();
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::ReopenStoreResult(result))
+                        .await
+                    {
+                        error!("Failed to send reopen_store response: {}", e);
+                    }
+                }
+                ChainMessage::LoadSnapshot(snapshot) => {
+                    let result = self.chain.load_snapshot(snapshot);
+                    if let Err(e) = response_sender
+                        .send(ChainResponse::LoadSnapshotResult(result))
+                        .await
+                    {
+                        error!("Failed to send load_snapshot response: {}", e);
+                    }
+                }
             }
         }
     }
@@ -302,17 +653,105 @@ impl ChainActor {
 #[derive(Clone)]
 pub struct ChainHandle {
     sender: mpsc::Sender<(ChainMessage, mpsc::Sender<ChainResponse>)>,
+    equivocation_tx: broadcast::Sender<Equivocation>,
+    deep_reorg_tx: broadcast::Sender<DeepReorg>,
+    reorg_tx: broadcast::Sender<Reorg>,
+    accepted_share_tx: broadcast::Sender<ShareBlock>,
+    block_found_tx: broadcast::Sender<BlockCandidate>,
+    pow_cache: PowCacheHandle,
 }
 
 #[allow(dead_code)]
 impl ChainHandle {
     pub fn new(store_path: String) -> Self {
+        let store = Store::new(store_path.clone()).unwrap();
+        Self::spawn(store_path, Chain::new(store))
+    }
+
+    /// Create a ChainHandle whose chain reads the time from clock, the same clock the node is given
+    pub fn new_with_clock(store_path: String, clock: Arc<dyn Clock>) -> Self {
+        let store = Store::new(store_path.clone()).unwrap();
+        Self::spawn(store_path, Chain::new(store).with_clock(clock))
+    }
+
+    /// Create a ChainHandle with the side branch limit, reorg depth limit, checkpoints and payout policy from the chain config,
+    /// and the workbase limit from the store config, paying out to addresses on network
+    pub fn new_with_config(
+        store_config: StoreConfig,
+        chain_config: ChainConfig,
+        network: bitcoin::Network,
+    ) -> Self {
+        let store_path = store_config.path;
+        let store = Store::new(store_path.clone()).unwrap();
+        let chain = Chain::new(store)
+            .with_max_side_branches(chain_config.max_side_branches)
+            .with_max_workbases(store_config.max_workbases)
+            .with_payout_policy(chain_config.payout_policy, chain_config.payout_window)
+            .with_max_reorg_depth(chain_config.max_reorg_depth)
+            .with_checkpoints(chain_config.checkpoints)
+            .with_stable_tip(
+                chain_config.stable_tip_window_secs,
+                chain_config.stable_tip_max_reorgs,
+                chain_config.stable_tip_min_unchanged_secs,
+            )
+            .with_retargeting(
+                chain_config.retarget_window,
+                chain_config.target_share_interval_secs,
+            )
+            .with_network(network);
+        Self::spawn(store_path, chain)
+    }
+
+    fn spawn(store_path: String, chain: Chain) -> Self {
         tracing::info!("Creating ChainHandle with store_path: {}", store_path);
         let (sender, receiver) = mpsc::channel(1);
-        let store = Store::new(store_path).unwrap();
-        let mut chain_actor = ChainActor::new(Chain::new(store), receiver);
+        let equivocation_tx = chain.equivocation_sender();
+        let deep_reorg_tx = chain.deep_reorg_sender();
+        let reorg_tx = chain.reorg_sender();
+        let accepted_share_tx = chain.accepted_share_sender();
+        let block_found_tx = chain.block_found_sender();
+        let pow_cache = chain.pow_cache();
+        let mut chain_actor = ChainActor::new(chain, receiver);
         tokio::spawn(async move { chain_actor.run().await });
-        Self { sender }
+        Self {
+            sender,
+            equivocation_tx,
+            deep_reorg_tx,
+            reorg_tx,
+            accepted_share_tx,
+            block_found_tx,
+            pow_cache,
+        }
+    }
+
+    /// The chain's proof of work cache, validation checks it before hashing a share
+    pub fn pow_cache(&self) -> PowCacheHandle {
+        self.pow_cache.clone()
+    }
+
+    /// Subscribe to equivocations found when adding shares to the chain
+    pub fn subscribe_equivocations(&self) -> broadcast::Receiver<Equivocation> {
+        self.equivocation_tx.subscribe()
+    }
+
+    /// Subscribe to reorgs the chain refused for being deeper than the configured limit
+    pub fn subscribe_deep_reorgs(&self) -> broadcast::Receiver<DeepReorg> {
+        self.deep_reorg_tx.subscribe()
+    }
+
+    /// Subscribe to reorgs of the main chain, with the shares orphaned and promoted and their miners
+    pub fn subscribe_reorgs(&self) -> broadcast::Receiver<Reorg> {
+        self.reorg_tx.subscribe()
+    }
+
+    /// Subscribe to shares that solve a bitcoin block, with the block to submit
+    pub fn subscribe_blocks_found(&self) -> broadcast::Receiver<BlockCandidate> {
+        self.block_found_tx.subscribe()
+    }
+
+    /// Subscribe to shares as they are added to the chain
+    pub fn subscribe_accepted_shares(&self) -> broadcast::Receiver<ShareBlock> {
+        self.accepted_share_tx.subscribe()
     }
 
     pub async fn get_tips(&self) -> HashSet<ShareBlockHash> {
@@ -396,6 +835,82 @@ impl ChainHandle {
         }
     }
 
+    /// Add a share and record where it first reached us, for forensic analysis
+    pub async fn add_share_with_provenance(
+        &self,
+        share_block: ShareBlock,
+        provenance: ShareProvenance,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((
+                ChainMessage::AddShareWithProvenance(share_block, provenance),
+                response_sender,
+            ))
+            .await
+        {
+            error!("Failed to send AddShareWithProvenance message: {}", e);
+            return Err(e.into());
+        }
+
+        match response_receiver.recv().await {
+            Some(ChainResponse::AddShareResult(result)) => result,
+            _ => Err("Failed to receive add_share result".into()),
+        }
+    }
+
+    /// Where a share first reached us, None if unknown or added without provenance
+    pub async fn get_share_provenance(&self, blockhash: ShareBlockHash) -> Option<ShareProvenance> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::GetShareProvenance(blockhash), response_sender))
+            .await
+        {
+            error!("Failed to send GetShareProvenance message: {}", e);
+            return None;
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::ShareProvenance(result)) => result,
+            _ => None,
+        }
+    }
+
+    /// Where a share stands in the chain, Unknown if the chain actor did not respond
+    pub async fn get_share_status(&self, blockhash: ShareBlockHash) -> ShareStatus {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::GetShareStatus(blockhash), response_sender))
+            .await
+        {
+            error!("Failed to send GetShareStatus message: {}", e);
+            return ShareStatus::Unknown;
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::ShareStatus(result)) => result,
+            _ => ShareStatus::Unknown,
+        }
+    }
+
+    /// The chain tip and whether it is stable enough to pay out on
+    pub async fn get_chain_stats(&self) -> Result<ChainStats, Box<dyn Error + Send + Sync>> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::GetChainStats, response_sender))
+            .await
+        {
+            error!("Failed to send GetChainStats message: {}", e);
+            return Err("chain is not running".into());
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::ChainStats(stats)) => Ok(stats),
+            _ => Err("no response from chain to get chain stats".into()),
+        }
+    }
+
     pub async fn get_share(&self, share_hash: ShareBlockHash) -> Option<ShareBlock> {
         let (response_sender, mut response_receiver) = mpsc::channel(1);
         if let Err(e) = self
@@ -449,7 +964,7 @@ impl ChainHandle {
     pub async fn add_workbase(
         &self,
         workbase: MinerWorkbase,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ) -> Result<WorkbaseOutcome, Box<dyn Error + Send + Sync>> {
         tracing::debug!("Adding workbase to chain: {:?}", workbase.workinfoid);
         let (response_sender, mut response_receiver) = mpsc::channel(1);
         if let Err(e) = self
@@ -502,6 +1017,22 @@ impl ChainHandle {
         }
     }
 
+    /// Get the workbase stored before the one with workinfoid, to gossip a workbase as a coinbase delta on it
+    pub async fn get_previous_workbase(&self, workinfoid: u64) -> Option<MinerWorkbase> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        self.sender
+            .send((
+                ChainMessage::GetPreviousWorkbase(workinfoid),
+                response_sender,
+            ))
+            .await
+            .unwrap();
+        match response_receiver.recv().await {
+            Some(ChainResponse::GetWorkbaseResult(result)) => result,
+            _ => None,
+        }
+    }
+
     pub async fn get_workbases(&self, workinfoids: &[u64]) -> Vec<MinerWorkbase> {
         let (response_sender, mut response_receiver) = mpsc::channel(1);
         self.sender
@@ -517,6 +1048,31 @@ impl ChainHandle {
         }
     }
 
+    /// List workbases in a height or time range, skipping offset of them and returning at most limit
+    pub async fn list_workbases(
+        &self,
+        range: WorkbaseRange,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<MinerWorkbase> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((
+                ChainMessage::ListWorkbases(range, offset, limit),
+                response_sender,
+            ))
+            .await
+        {
+            error!("Failed to send ListWorkbases message: {}", e);
+            return vec![];
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::GetWorkbasesResult(result)) => result,
+            _ => vec![],
+        }
+    }
+
     pub async fn get_user_workbase(&self, workinfoid: u64) -> Option<UserWorkbase> {
         let (response_sender, mut response_receiver) = mpsc::channel(1);
         self.sender
@@ -568,29 +1124,81 @@ impl ChainHandle {
         }
     }
 
-    pub async fn get_chain_tip_and_uncles(
-        &self,
-    ) -> (Option<ShareBlockHash>, HashSet<ShareBlockHash>) {
+    pub async fn get_tip_height(&self) -> Option<u32> {
         let (response_sender, mut response_receiver) = mpsc::channel(1);
         self.sender
-            .send((ChainMessage::GetChainTipAndUncles, response_sender))
+            .send((ChainMessage::GetTipHeight, response_sender))
             .await
             .unwrap();
         match response_receiver.recv().await {
-            Some(ChainResponse::ChainTipAndUncles(chain_tip, uncles)) => (chain_tip, uncles),
-            _ => (None, HashSet::new()),
+            Some(ChainResponse::TipHeight(result)) => result,
+            _ => None,
         }
     }
 
-    pub async fn get_depth(&self, blockhash: ShareBlockHash) -> Option<usize> {
+    /// Heights of stored shares in one request, in the order given, None for shares not in the store
+    pub async fn get_share_heights(&self, blockhashes: Vec<ShareBlockHash>) -> Vec<Option<u32>> {
+        let len = blockhashes.len();
         let (response_sender, mut response_receiver) = mpsc::channel(1);
-        self.sender
-            .send((ChainMessage::GetDepth(blockhash), response_sender))
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::GetShareHeights(blockhashes), response_sender))
             .await
-            .unwrap();
+        {
+            error!("Failed to send GetShareHeights message: {}", e);
+            return vec![None; len];
+        }
         match response_receiver.recv().await {
-            Some(ChainResponse::Depth(result)) => result,
-            _ => None,
+            Some(ChainResponse::ShareHeights(result)) => result,
+            _ => vec![None; len],
+        }
+    }
+
+    pub async fn get_chain_tip_and_uncles(
+        &self,
+    ) -> (Option<ShareBlockHash>, HashSet<ShareBlockHash>) {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        self.sender
+            .send((ChainMessage::GetChainTipAndUncles, response_sender))
+            .await
+            .unwrap();
+        match response_receiver.recv().await {
+            Some(ChainResponse::ChainTipAndUncles(chain_tip, uncles)) => (chain_tip, uncles),
+            _ => (None, HashSet::new()),
+        }
+    }
+
+    pub async fn get_depth(&self, blockhash: ShareBlockHash) -> Option<usize> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        self.sender
+            .send((ChainMessage::GetDepth(blockhash), response_sender))
+            .await
+            .unwrap();
+        match response_receiver.recv().await {
+            Some(ChainResponse::Depth(result)) => result,
+            _ => None,
+        }
+    }
+
+    /// The path from one share back to the closest common ancestor with another and forward to it,
+    /// None if either share is not found or they have no common ancestor
+    pub async fn get_path(
+        &self,
+        from: ShareBlockHash,
+        to: ShareBlockHash,
+    ) -> Option<Vec<ShareBlockHash>> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::GetPath(from, to), response_sender))
+            .await
+        {
+            error!("Failed to send GetPath message: {}", e);
+            return None;
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::Path(result)) => result,
+            _ => None,
         }
     }
 
@@ -651,6 +1259,65 @@ impl ChainHandle {
         }
     }
 
+    /// Get a page of the chain starting at cursor, bounded by max_shares shares and max_bytes serialized bytes
+    pub async fn get_chain_page(
+        &self,
+        cursor: ChainCursor,
+        max_shares: usize,
+        max_bytes: usize,
+    ) -> ChainPage {
+        let empty = ChainPage {
+            shares: vec![],
+            next: None,
+        };
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((
+                ChainMessage::GetChainPage(cursor, max_shares, max_bytes),
+                response_sender,
+            ))
+            .await
+        {
+            error!("Failed to send GetChainPage message: {}", e);
+            return empty;
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::ChainPage(page)) => page,
+            _ => empty,
+        }
+    }
+
+    /// Get the first page of the chain above the common ancestor with a peer's locator,
+    /// bounded by max_shares shares and max_bytes serialized bytes
+    pub async fn get_chain_page_from_locator(
+        &self,
+        locator: Vec<ShareBlockHash>,
+        max_shares: usize,
+        max_bytes: usize,
+    ) -> ChainPage {
+        let empty = ChainPage {
+            shares: vec![],
+            next: None,
+        };
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((
+                ChainMessage::GetChainPageFromLocator(locator, max_shares, max_bytes),
+                response_sender,
+            ))
+            .await
+        {
+            error!("Failed to send GetChainPageFromLocator message: {}", e);
+            return empty;
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::ChainPage(page)) => page,
+            _ => empty,
+        }
+    }
+
     pub async fn build_locator(&self) -> Vec<ShareBlockHash> {
         let (response_sender, mut response_receiver) = mpsc::channel(1);
         self.sender
@@ -680,6 +1347,366 @@ impl ChainHandle {
             _ => vec![],
         }
     }
+
+    pub async fn get_shares_by_miner(&self, address: bitcoin::Address) -> Vec<ShareBlockHash> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::GetSharesByMiner(address), response_sender))
+            .await
+        {
+            error!("Failed to send GetSharesByMiner message: {}", e);
+            return vec![];
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::GetSharesByMinerResult(result)) => result,
+            _ => vec![],
+        }
+    }
+
+    /// Rebuild the store indexes for the shares at a height, returning the number of shares reindexed
+    pub async fn reindex_height(&self, height: u32) -> usize {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::ReindexHeight(height), response_sender))
+            .await
+        {
+            error!("Failed to send ReindexHeight message: {}", e);
+            return 0;
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::ReindexHeightResult(result)) => result,
+            _ => 0,
+        }
+    }
+
+    /// Connect orphan shares whose parent has since been stored, returning the number connected
+    pub async fn drain_orphans(&self) -> usize {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::DrainOrphans, response_sender))
+            .await
+        {
+            error!("Failed to send DrainOrphans message: {}", e);
+            return 0;
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::DrainOrphansResult(result)) => result,
+            _ => 0,
+        }
+    }
+
+    /// Count the orphan shares and list the parents they are waiting for
+    pub async fn get_orphan_summary(&self) -> OrphanSummary {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::GetOrphanSummary, response_sender))
+            .await
+        {
+            error!("Failed to send GetOrphanSummary message: {}", e);
+            return OrphanSummary::default();
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::OrphanSummary(result)) => result,
+            _ => OrphanSummary::default(),
+        }
+    }
+
+    /// The deepest missing ancestor an orphan is waiting for, empty if it is not an orphan or nothing is missing
+    pub async fn get_missing_ancestors(&self, blockhash: ShareBlockHash) -> Vec<ShareBlockHash> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((
+                ChainMessage::GetMissingAncestors(blockhash),
+                response_sender,
+            ))
+            .await
+        {
+            error!("Failed to send GetMissingAncestors message: {}", e);
+            return Vec::new();
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::MissingAncestors(result)) => result,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Get the shares at the most recent `depth` heights with the links between them
+    pub async fn get_dag_snapshot(&self, depth: u32) -> DagSnapshot {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::GetDagSnapshot(depth), response_sender))
+            .await
+        {
+            error!("Failed to send GetDagSnapshot message: {}", e);
+            return DagSnapshot::default();
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::DagSnapshot(result)) => result,
+            _ => DagSnapshot::default(),
+        }
+    }
+
+    /// Each miner's part of a reward under the configured payout policy, in parts of PAYOUT_SCALE
+    pub async fn compute_payouts(&self) -> HashMap<bitcoin::Address, u64> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::ComputePayouts, response_sender))
+            .await
+        {
+            error!("Failed to send ComputePayouts message: {}", e);
+            return HashMap::new();
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::Payouts(result)) => result,
+            _ => HashMap::new(),
+        }
+    }
+
+    /// Split total_reward, in satoshis, between the miners of the window most recent main chain shares
+    /// Empty, with no reward, if the chain actor did not respond.
+    pub async fn payout_report(&self, window: usize, total_reward: u64) -> PayoutReport {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((
+                ChainMessage::GetPayoutReport(window, total_reward),
+                response_sender,
+            ))
+            .await
+        {
+            error!("Failed to send GetPayoutReport message: {}", e);
+            return PayoutReport::default();
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::PayoutReport(result)) => result,
+            _ => PayoutReport::default(),
+        }
+    }
+
+    /// Proof of the shares the reward for the bitcoin block solved by share block_hash is split over
+    pub async fn inclusion_proof(&self, block_hash: ShareBlockHash) -> Option<PayoutProof> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::GetInclusionProof(block_hash), response_sender))
+            .await
+        {
+            error!("Failed to send GetInclusionProof message: {}", e);
+            return None;
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::InclusionProof(result)) => result,
+            _ => None,
+        }
+    }
+
+    /// Check a proof's signature and that its shares match the ones in our store
+    pub async fn verify_inclusion_proof(&self, proof: PayoutProof) -> Result<(), String> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::VerifyInclusionProof(proof), response_sender))
+            .await
+        {
+            error!("Failed to send VerifyInclusionProof message: {}", e);
+            return Err("chain actor is not running".to_string());
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::InclusionProofVerified(result)) => result,
+            _ => Err("no response from the chain actor".to_string()),
+        }
+    }
+
+    /// Estimated hashes per second from the difficulty of the shares found in the window ending at now,
+    /// in seconds since epoch. Zero if the chain actor did not respond.
+    pub async fn estimate_hashrate(&self, window: Duration, now: u64) -> f64 {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::EstimateHashrate(window, now), response_sender))
+            .await
+        {
+            error!("Failed to send EstimateHashrate message: {}", e);
+            return 0.0;
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::Hashrate(result)) => result,
+            _ => 0.0,
+        }
+    }
+
+    /// Number of shares found in each of the count most recent buckets of the given length ending at now,
+    /// in seconds since epoch, oldest first. Empty if the chain actor did not respond.
+    pub async fn share_histogram(
+        &self,
+        bucket: Duration,
+        count: usize,
+        now: u64,
+    ) -> Vec<(u64, u64)> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((
+                ChainMessage::GetShareHistogram(bucket, count, now),
+                response_sender,
+            ))
+            .await
+        {
+            error!("Failed to send GetShareHistogram message: {}", e);
+            return Vec::new();
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::ShareHistogram(result)) => result,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Prune the oldest shares if the store uses more than max_disk_bytes, until it is under low_water_bytes
+    /// Returns None if the chain actor did not respond.
+    pub async fn prune_to_disk_usage(
+        &self,
+        max_disk_bytes: u64,
+        low_water_bytes: u64,
+    ) -> Option<PruneReport> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((
+                ChainMessage::PruneToDiskUsage(max_disk_bytes, low_water_bytes),
+                response_sender,
+            ))
+            .await
+        {
+            error!("Failed to send PruneToDiskUsage message: {}", e);
+            return None;
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::PruneReport(result)) => Some(result),
+            _ => None,
+        }
+    }
+
+    /// Close and reopen the store, deriving the chain tip again from the reopened store
+    pub async fn reopen_store(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::ReopenStore, response_sender))
+            .await
+        {
+            error!("Failed to send ReopenStore message: {}", e);
+            return Err("chain is not running".into());
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::ReopenStoreResult(result)) => result,
+            _ => Err("no response from chain to reopen store".into()),
+        }
+    }
+
+    /// Forcibly make a stored share the chain tip, for testing and emergency recovery only
+    pub async fn set_tip(
+        &self,
+        blockhash: ShareBlockHash,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::SetTip(blockhash), response_sender))
+            .await
+        {
+            error!("Failed to send SetTip message: {}", e);
+            return Err("chain is not running".into());
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::SetTipResult(result)) => result,
+            _ => Err("no response from chain to set tip".into()),
+        }
+    }
+
+    /// Compact the whole store, returning its disk usage in bytes before and after compaction
+    pub async fn compact_store(&self) -> Result<(u64, u64), Box<dyn Error + Send + Sync>> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::CompactStore, response_sender))
+            .await
+        {
+            error!("Failed to send CompactStore message: {}", e);
+            return Err("chain is not running".into());
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::CompactStoreResult(bytes_before, bytes_after)) => {
+                Ok((bytes_before, bytes_after))
+            }
+            _ => Err("no response from chain to compact store".into()),
+        }
+    }
+
+    /// Benchmark the store's disk with ops synthetic entries, which are deleted afterwards
+    pub async fn benchmark_store(
+        &self,
+        ops: usize,
+    ) -> Result<StoreBenchmark, Box<dyn Error + Send + Sync>> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::BenchmarkStore(ops), response_sender))
+            .await
+        {
+            error!("Failed to send BenchmarkStore message: {}", e);
+            return Err("chain is not running".into());
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::StoreBenchmarkResult(result)) => result,
+            _ => Err("no response from chain to benchmark store".into()),
+        }
+    }
+
+    /// Delete the shares that first reached us from a peer, keeping the ones the chain depends on
+    pub async fn purge_peer_shares(
+        &self,
+        peer_id: libp2p::PeerId,
+    ) -> Result<PurgeReport, Box<dyn Error + Send + Sync>> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::PurgePeerShares(peer_id), response_sender))
+            .await
+        {
+            error!("Failed to send PurgePeerShares message: {}", e);
+            return Err("chain is not running".into());
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::PurgeReport(report)) => Ok(report),
+            _ => Err("no response from chain to purge peer shares".into()),
+        }
+    }
+
+    /// Replace the chain with a snapshot if it is valid and has more work than the chain
+    pub async fn load_snapshot(&self, snapshot: ChainSnapshot) -> Result<(), SnapshotError> {
+        let (response_sender, mut response_receiver) = mpsc::channel(1);
+        if let Err(e) = self
+            .sender
+            .send((ChainMessage::LoadSnapshot(snapshot), response_sender))
+            .await
+        {
+            error!("Failed to send LoadSnapshot message: {}", e);
+            return Err(SnapshotError::Read("chain is not running".to_string()));
+        }
+        match response_receiver.recv().await {
+            Some(ChainResponse::LoadSnapshotResult(result)) => result,
+            _ => Err(SnapshotError::Read(
+                "no response from chain to load snapshot".to_string(),
+            )),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -689,15 +1716,34 @@ use mockall::mock;
 mock! {
     pub ChainHandle {
         pub fn new(store_path: String) -> Self;
+        pub fn new_with_clock(store_path: String, clock: Arc<dyn Clock>) -> Self;
+        pub fn new_with_config(store_config: StoreConfig, chain_config: ChainConfig, network: bitcoin::Network) -> Self;
+        pub fn subscribe_equivocations(&self) -> broadcast::Receiver<Equivocation>;
+        pub fn subscribe_deep_reorgs(&self) -> broadcast::Receiver<DeepReorg>;
+        pub fn subscribe_reorgs(&self) -> broadcast::Receiver<Reorg>;
+        pub fn subscribe_accepted_shares(&self) -> broadcast::Receiver<ShareBlock>;
+        pub fn subscribe_blocks_found(&self) -> broadcast::Receiver<BlockCandidate>;
+        pub fn pow_cache(&self) -> PowCacheHandle;
         pub async fn get_tips(&self) -> HashSet<ShareBlockHash>;
         pub async fn reorg(&self, share_block: ShareBlock, total_difficulty_upto_prev_share_blockhash: Decimal) -> Result<(), Box<dyn Error + Send + Sync>>;
         pub async fn is_confirmed(&self, share_block: ShareBlock) -> Result<bool, Box<dyn Error + Send + Sync>>;
         pub async fn add_share(&self, share_block: ShareBlock) -> Result<(), Box<dyn Error + Send + Sync>>;
-        pub async fn add_workbase(&self, workbase: MinerWorkbase) -> Result<(), Box<dyn Error + Send + Sync>>;
+        pub async fn add_share_with_provenance(&self, share_block: ShareBlock, provenance: ShareProvenance) -> Result<(), Box<dyn Error + Send + Sync>>;
+        pub async fn get_share_provenance(&self, blockhash: ShareBlockHash) -> Option<ShareProvenance>;
+        pub async fn get_share_status(&self, blockhash: ShareBlockHash) -> ShareStatus;
+        pub async fn get_chain_stats(&self) -> Result<ChainStats, Box<dyn Error + Send + Sync>>;
+        pub async fn prune_to_disk_usage(&self, max_disk_bytes: u64, low_water_bytes: u64) -> Option<PruneReport>;
+        pub async fn add_workbase(&self, workbase: MinerWorkbase) -> Result<WorkbaseOutcome, Box<dyn Error + Send + Sync>>;
         pub async fn get_workbase(&self, workinfoid: u64) -> Option<MinerWorkbase>;
+        pub async fn get_previous_workbase(&self, workinfoid: u64) -> Option<MinerWorkbase>;
+        pub async fn list_workbases(&self, range: WorkbaseRange, offset: usize, limit: usize) -> Vec<MinerWorkbase>;
+        pub async fn get_total_difficulty(&self) -> Decimal;
         pub async fn get_chain_tip(&self) -> Option<ShareBlockHash>;
+        pub async fn get_tip_height(&self) -> Option<u32>;
+        pub async fn get_share_heights(&self, blockhashes: Vec<ShareBlockHash>) -> Vec<Option<u32>>;
         pub async fn get_chain_tip_and_uncles(&self) -> (Option<ShareBlockHash>, HashSet<ShareBlockHash>);
         pub async fn get_depth(&self, blockhash: ShareBlockHash) -> Option<usize>;
+        pub async fn get_path(&self, from: ShareBlockHash, to: ShareBlockHash) -> Option<Vec<ShareBlockHash>>;
         pub async fn setup_share_for_chain(&self, share_block: ShareBlock) -> ShareBlock;
         pub async fn add_user_workbase(&self, user_workbase: UserWorkbase) -> Result<(), Box<dyn Error + Send + Sync>>;
         pub async fn get_user_workbase(&self, workinfoid: u64) -> Option<UserWorkbase>;
@@ -706,8 +1752,28 @@ mock! {
         pub async fn get_share_headers(&self, share_hashes: Vec<ShareBlockHash>) -> Vec<ShareHeader>;
         pub async fn get_headers_for_locator(&self, block_hashes: Vec<ShareBlockHash>, stop_block_hash: ShareBlockHash, max_headers: usize) -> Vec<ShareHeader>;
         pub async fn get_blockhashes_for_locator(&self, locator: Vec<ShareBlockHash>, stop_block_hash: ShareBlockHash, max_blockhashes: usize) -> Vec<ShareBlockHash>;
+        pub async fn get_chain_page(&self, cursor: ChainCursor, max_shares: usize, max_bytes: usize) -> ChainPage;
+        pub async fn get_chain_page_from_locator(&self, locator: Vec<ShareBlockHash>, max_shares: usize, max_bytes: usize) -> ChainPage;
         pub async fn build_locator(&self) -> Vec<ShareBlockHash>;
         pub async fn get_missing_blockhashes(&self, blockhashes: &[ShareBlockHash]) -> Vec<ShareBlockHash>;
+        pub async fn get_shares_by_miner(&self, address: bitcoin::Address) -> Vec<ShareBlockHash>;
+        pub async fn reindex_height(&self, height: u32) -> usize;
+        pub async fn drain_orphans(&self) -> usize;
+        pub async fn get_orphan_summary(&self) -> OrphanSummary;
+        pub async fn get_missing_ancestors(&self, blockhash: ShareBlockHash) -> Vec<ShareBlockHash>;
+        pub async fn get_dag_snapshot(&self, depth: u32) -> DagSnapshot;
+        pub async fn compute_payouts(&self) -> HashMap<bitcoin::Address, u64>;
+        pub async fn payout_report(&self, window: usize, total_reward: u64) -> PayoutReport;
+        pub async fn inclusion_proof(&self, block_hash: ShareBlockHash) -> Option<PayoutProof>;
+        pub async fn verify_inclusion_proof(&self, proof: PayoutProof) -> Result<(), String>;
+        pub async fn estimate_hashrate(&self, window: Duration, now: u64) -> f64;
+        pub async fn share_histogram(&self, bucket: Duration, count: usize, now: u64) -> Vec<(u64, u64)>;
+        pub async fn load_snapshot(&self, snapshot: ChainSnapshot) -> Result<(), SnapshotError>;
+        pub async fn reopen_store(&self) -> Result<(), Box<dyn Error + Send + Sync>>;
+        pub async fn set_tip(&self, blockhash: ShareBlockHash) -> Result<(), Box<dyn Error + Send + Sync>>;
+        pub async fn compact_store(&self) -> Result<(u64, u64), Box<dyn Error + Send + Sync>>;
+        pub async fn benchmark_store(&self, ops: usize) -> Result<StoreBenchmark, Box<dyn Error + Send + Sync>>;
+        pub async fn purge_peer_shares(&self, peer_id: libp2p::PeerId) -> Result<PurgeReport, Box<dyn Error + Send + Sync>>;
     }
 
     impl Clone for ChainHandle {
@@ -14,18 +14,204 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+use super::dag::{DagEdge, DagEdgeKind, DagNode, DagSnapshot};
+use super::payout::{
+    scale_weights, PayoutPolicy, PayoutProof, PayoutReport, DEFAULT_PAYOUT_WINDOW,
+};
+use super::snapshot::{ChainSnapshot, SnapshotError};
+use crate::shares::miner_message::builders::build_bitcoin_block;
 use crate::shares::miner_message::{MinerWorkbase, UserWorkbase};
+use crate::shares::store::{
+    ShareProvenance, Store, StoreMaintenance, WorkbaseOutcome, WorkbaseRange,
+};
+use crate::shares::validation::pow_cache::PowCacheHandle;
+use crate::shares::validation::MAX_TIME_DIFF;
 use crate::shares::ShareBlockHash;
-use crate::shares::{store::Store, ShareBlock, ShareHeader};
+use crate::shares::{ShareBlock, ShareHeader};
+use crate::utils::clock::{Clock, SystemClock};
+use crate::utils::time_provider::TimeProvider;
+use bitcoin::PublicKey;
+use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::error::Error;
-use tracing::{error, info};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, warn};
+
+/// Number of equivocations buffered for each subscriber
+pub const EQUIVOCATION_CHANNEL_CAPACITY: usize = 64;
+
+/// Number of accepted shares buffered for each subscriber
+pub const ACCEPTED_SHARE_CHANNEL_CAPACITY: usize = 256;
+
+/// Number of rejected deep reorgs buffered for each subscriber
+pub const DEEP_REORG_CHANNEL_CAPACITY: usize = 16;
+
+/// A reorg we refused because it would have replaced more main chain shares than max_reorg_depth
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeepReorg {
+    /// The main chain tip we kept
+    pub chain_tip: ShareBlockHash,
+    /// The share with more work whose branch forks too far below the chain tip
+    pub rejected: ShareBlockHash,
+    /// Number of main chain shares the reorg would have replaced, counted up to max_reorg_depth + 1
+    pub depth: usize,
+}
+
+/// A share the chain must pass through at a height, agreed on out of band.
+/// Shares conflicting with a checkpoint are rejected and reorgs can't rewrite history below the highest one reached.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Checkpoint {
+    pub height: u32,
+    pub blockhash: ShareBlockHash,
+}
+
+/// Position to serve the next page of the chain from, in a paginated sync.
+/// Shares at a height are served in blockhash order, offset counts the ones already served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainCursor {
+    pub height: u32,
+    pub offset: u32,
+}
+
+/// Shares served in one page of a paginated sync, in height order so parents come before their children.
+/// next is the cursor to request the following page from, None once the page reaches the chain tip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainPage {
+    pub shares: Vec<ShareBlock>,
+    pub next: Option<ChainCursor>,
+}
+
+/// Number of reorgs buffered for each subscriber
+pub const REORG_CHANNEL_CAPACITY: usize = 64;
+
+/// The main chain switched to a branch that doesn't build on the old chain tip
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reorg {
+    pub old_tip: ShareBlockHash,
+    pub new_tip: ShareBlockHash,
+    /// Shares that were on the main chain and are now orphaned, from the old tip back to the fork
+    pub orphaned: Vec<ShareBlockHash>,
+    /// Shares that moved from a side branch onto the main chain, from the new tip back to the fork
+    pub promoted: Vec<ShareBlockHash>,
+    /// Payout addresses of the miners of the orphaned and promoted shares, whose credits change
+    pub affected_miners: HashSet<bitcoin::Address>,
+}
+
+/// Number of solved blocks buffered for each subscriber
+pub const BLOCK_FOUND_CHANNEL_CAPACITY: usize = 16;
+
+/// A share that meets the bitcoin network target and not only the share target,
+/// with what is needed to submit the block it solved to bitcoind
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockCandidate {
+    pub share_blockhash: ShareBlockHash,
+    pub share: ShareBlock,
+    /// The workbase the share was mined on
+    pub workbase: MinerWorkbase,
+    /// The coinbase of the solved block, paying out the pool
+    pub coinbase: bitcoin::Transaction,
+    /// The solved bitcoin block, ready to submit
+    pub block: bitcoin::Block,
+}
+
+/// A miner produced two different shares on the same parent, and so at the same height
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equivocation {
+    pub miner_pubkey: PublicKey,
+    pub height: u32,
+    pub prev_share_blockhash: Option<ShareBlockHash>,
+    /// The share we already had from the miner
+    pub accepted: ShareBlockHash,
+    /// The new share conflicting with it
+    pub conflicting: ShareBlockHash,
+}
 
 /// The minimum number of shares that must be on the chain for a share to be considered confirmed
 const MIN_CONFIRMATION_DEPTH: usize = 100;
 
+/// Number of heights deleted between disk usage checks while pruning
+const PRUNE_BATCH_HEIGHTS: u32 = 16;
+
+/// What a disk usage check did
+#[derive(Debug, Clone, PartialEq)]
+pub struct PruneReport {
+    /// Store disk usage when the check started
+    pub bytes_before: u64,
+    /// Store disk usage once pruning stopped, the same as bytes_before if nothing was pruned
+    pub bytes_after: u64,
+    /// Number of shares deleted
+    pub shares_pruned: usize,
+}
+
+/// What purging the shares a peer delivered did
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PurgeReport {
+    /// Shares deleted from the store
+    pub removed: Vec<ShareBlockHash>,
+    /// Shares kept because other shares build on them or reference them as uncles, or they are the chain tip
+    pub kept: Vec<ShareBlockHash>,
+}
+
+/// Where a share stands in the chain, for learning the outcome of a share after submitting it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareStatus {
+    /// On the main chain, from genesis up to the chain tip
+    MainChain,
+    /// Off the main chain, on a side branch we still track
+    SideBranch,
+    /// Off the main chain, referenced as an uncle by a main chain share
+    Uncle,
+    /// Stored, but on no branch we track, e.g. a dropped side branch
+    Orphan,
+    /// Not in the store
+    Unknown,
+}
+
+/// The chain tip and how settled it is, for services like payouts that should wait out reorgs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainStats {
+    pub chain_tip: Option<ShareBlockHash>,
+    pub height: Option<u32>,
+    pub total_difficulty: Decimal,
+    /// Seconds since the chain tip last changed, by extending the main chain or by a reorg
+    pub tip_unchanged_secs: u64,
+    /// Reorgs in the last stable_tip_window_secs
+    pub recent_reorgs: usize,
+    /// Whether the tip is stable, see Chain::is_tip_stable
+    pub tip_stable: bool,
+}
+
+/// Shares stored at height 0 because their parent was missing when they arrived
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OrphanSummary {
+    /// Number of orphan shares
+    pub count: usize,
+    /// Orphans whose parent has since been stored on the chain, Chain::drain_orphans reconnects them
+    pub connectable: usize,
+    /// The parents we are still missing, each listed once
+    pub missing_parents: Vec<ShareBlockHash>,
+}
+
+/// Seconds over which reorgs are counted when no stability window is configured
+pub const DEFAULT_STABLE_TIP_WINDOW_SECS: u64 = 120;
+
+/// Number of side branch tips tracked when no limit is configured
+pub const DEFAULT_MAX_SIDE_BRANCHES: usize = 16;
+
+/// Seconds between shares retargeting aims for when no interval is configured
+pub const DEFAULT_TARGET_SHARE_INTERVAL_SECS: u64 = 10;
+
+/// How far a share's difficulty may be from the retargeted difficulty, as a fraction of the retargeted difficulty
+pub const RETARGET_TOLERANCE: Decimal = dec!(0.1);
+
+/// Most a single retarget scales difficulty up or down by, like bitcoin's difficulty adjustment
+pub const MAX_RETARGET_FACTOR: u64 = 4;
+
 /// A datastructure representing the main share chain
 /// The share chain reorgs when a share is found that has a higher total PoW than the current tip
 pub struct Chain {
@@ -39,42 +225,281 @@ pub struct Chain {
     pub tips: HashSet<ShareBlockHash>,
     /// Total difficulty up to the tip
     pub total_difficulty: Decimal,
+    /// Maximum number of tips tracked besides the chain tip, bounds the fork state an attacker can create
+    pub max_side_branches: usize,
+    /// Workbases stored before the oldest ones no share was mined on are evicted, 0 keeps them all
+    pub max_workbases: usize,
+    /// How shares in the payout window are weighted
+    pub payout_policy: PayoutPolicy,
+    /// Number of main chain shares, counting back from the tip, that share a reward
+    pub payout_window: usize,
+    /// Network the miner payout addresses are for
+    pub network: bitcoin::Network,
+    /// Maximum number of main chain shares a reorg may replace, 0 for no limit
+    pub max_reorg_depth: usize,
+    /// Share the chain must pass through at each checkpoint height
+    pub checkpoints: BTreeMap<u32, ShareBlockHash>,
+    /// Seconds over which reorgs are counted to decide if the tip is stable, 0 never counts a reorg
+    pub stable_tip_window_secs: u64,
+    /// Maximum number of reorgs in the stability window for the tip to be stable
+    pub stable_tip_max_reorgs: usize,
+    /// Seconds the tip must have stayed unchanged for it to be stable
+    pub stable_tip_min_unchanged_secs: u64,
+    /// Number of previous shares the expected difficulty of a new share is retargeted over, 0 disables retargeting
+    pub retarget_window: usize,
+    /// Seconds between shares the retargeted difficulty aims for
+    pub target_share_interval_secs: u64,
+    /// Seconds since epoch when the chain tip last changed
+    tip_changed_at: u64,
+    /// Seconds since epoch of the reorgs in the stability window, oldest first
+    reorg_times: VecDeque<u64>,
+    /// Clock used to time tip changes and reorgs, the node's clock
+    clock: Arc<dyn Clock>,
+    /// Equivocations found while adding shares are sent here
+    equivocation_tx: broadcast::Sender<Equivocation>,
+    /// Reorgs refused for being deeper than max_reorg_depth are sent here
+    deep_reorg_tx: broadcast::Sender<DeepReorg>,
+    /// Reorgs the main chain went through are sent here
+    reorg_tx: broadcast::Sender<Reorg>,
+    /// Shares are sent here once they are added to the chain
+    accepted_share_tx: broadcast::Sender<ShareBlock>,
+    /// Shares that solve a bitcoin block are sent here once they are added to the chain
+    block_found_tx: broadcast::Sender<BlockCandidate>,
+    /// Proof of work results of the shares validated for this chain, shared with the chain handle
+    pow_cache: PowCacheHandle,
 }
 
 #[allow(dead_code)]
 impl Chain {
     pub fn new(store: Store) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
         Self {
             tips: HashSet::new(),
             total_difficulty: dec!(0.0),
             store,
             chain_tip: None,
             genesis_block_hash: None,
+            max_side_branches: DEFAULT_MAX_SIDE_BRANCHES,
+            max_workbases: 0,
+            payout_policy: PayoutPolicy::default(),
+            payout_window: DEFAULT_PAYOUT_WINDOW,
+            network: bitcoin::Network::Signet,
+            max_reorg_depth: 0,
+            checkpoints: BTreeMap::new(),
+            stable_tip_window_secs: DEFAULT_STABLE_TIP_WINDOW_SECS,
+            stable_tip_max_reorgs: 0,
+            stable_tip_min_unchanged_secs: 0,
+            retarget_window: 0,
+            target_share_interval_secs: DEFAULT_TARGET_SHARE_INTERVAL_SECS,
+            tip_changed_at: clock.seconds_since_epoch(),
+            reorg_times: VecDeque::new(),
+            clock,
+            equivocation_tx: broadcast::channel(EQUIVOCATION_CHANNEL_CAPACITY).0,
+            deep_reorg_tx: broadcast::channel(DEEP_REORG_CHANNEL_CAPACITY).0,
+            reorg_tx: broadcast::channel(REORG_CHANNEL_CAPACITY).0,
+            accepted_share_tx: broadcast::channel(ACCEPTED_SHARE_CHANNEL_CAPACITY).0,
+            block_found_tx: broadcast::channel(BLOCK_FOUND_CHANNEL_CAPACITY).0,
+            pow_cache: PowCacheHandle::default(),
         }
     }
 
+    pub fn with_max_side_branches(mut self, max_side_branches: usize) -> Self {
+        self.max_side_branches = max_side_branches;
+        self
+    }
+
+    pub fn with_max_workbases(mut self, max_workbases: usize) -> Self {
+        self.max_workbases = max_workbases;
+        self
+    }
+
+    pub fn with_payout_policy(mut self, payout_policy: PayoutPolicy, payout_window: usize) -> Self {
+        self.payout_policy = payout_policy;
+        self.payout_window = payout_window;
+        self
+    }
+
+    pub fn with_network(mut self, network: bitcoin::Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    pub fn with_max_reorg_depth(mut self, max_reorg_depth: usize) -> Self {
+        self.max_reorg_depth = max_reorg_depth;
+        self
+    }
+
+    pub fn with_checkpoints(mut self, checkpoints: Vec<Checkpoint>) -> Self {
+        self.checkpoints = checkpoints
+            .into_iter()
+            .map(|checkpoint| (checkpoint.height, checkpoint.blockhash))
+            .collect();
+        self
+    }
+
+    pub fn with_stable_tip(
+        mut self,
+        window_secs: u64,
+        max_reorgs: usize,
+        min_unchanged_secs: u64,
+    ) -> Self {
+        self.stable_tip_window_secs = window_secs;
+        self.stable_tip_max_reorgs = max_reorgs;
+        self.stable_tip_min_unchanged_secs = min_unchanged_secs;
+        self
+    }
+
+    pub fn with_retargeting(mut self, window: usize, target_share_interval_secs: u64) -> Self {
+        self.retarget_window = window;
+        self.target_share_interval_secs = target_share_interval_secs;
+        self
+    }
+
+    /// Read the time from clock instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.tip_changed_at = clock.seconds_since_epoch();
+        self.clock = clock;
+        self
+    }
+
+    /// Proof of work cache for validating shares before they are added to the chain
+    pub fn pow_cache(&self) -> PowCacheHandle {
+        self.pow_cache.clone()
+    }
+
+    /// Sender for equivocations found while adding shares, subscribe to it to receive them
+    pub fn equivocation_sender(&self) -> broadcast::Sender<Equivocation> {
+        self.equivocation_tx.clone()
+    }
+
+    /// Sender for reorgs refused for being too deep, subscribe to it to receive them
+    pub fn deep_reorg_sender(&self) -> broadcast::Sender<DeepReorg> {
+        self.deep_reorg_tx.clone()
+    }
+
+    /// Sender for reorgs the main chain went through, subscribe to it to receive them
+    pub fn reorg_sender(&self) -> broadcast::Sender<Reorg> {
+        self.reorg_tx.clone()
+    }
+
+    /// Sender for shares that solve a bitcoin block, subscribe to it to receive them
+    pub fn block_found_sender(&self) -> broadcast::Sender<BlockCandidate> {
+        self.block_found_tx.clone()
+    }
+
+    /// Sender for shares added to the chain, subscribe to it to receive them
+    pub fn accepted_share_sender(&self) -> broadcast::Sender<ShareBlock> {
+        self.accepted_share_tx.clone()
+    }
+
+    /// Find shares we already have from the same miner on the same parent as the new share
+    fn find_equivocations(&self, share: &ShareBlock, height: u32) -> Vec<Equivocation> {
+        let blockhash = share.cached_blockhash.unwrap();
+        let mut equivocations: Vec<Equivocation> = self
+            .store
+            .get_shares_at_height(height)
+            .into_iter()
+            .filter(|(existing_hash, existing)| {
+                *existing_hash != blockhash
+                    && existing.header.prev_share_blockhash == share.header.prev_share_blockhash
+                    && existing.header.miner_pubkey == share.header.miner_pubkey
+            })
+            .map(|(existing_hash, _)| Equivocation {
+                miner_pubkey: share.header.miner_pubkey,
+                height,
+                prev_share_blockhash: share.header.prev_share_blockhash,
+                accepted: existing_hash,
+                conflicting: blockhash,
+            })
+            .collect();
+        equivocations.sort_by_key(|equivocation| equivocation.accepted.to_string());
+        equivocations
+    }
+
     /// Add a share to the chain and update the tips and total difficulty
+    /// Shares equivocating with a share we already have from the same miner are still added,
+    /// and the equivocation is sent to equivocation subscribers.
+    /// Shares at a checkpoint height other than the checkpoint share are rejected.
     pub fn add_share(&mut self, share: ShareBlock) -> Result<(), Box<dyn Error + Send + Sync>> {
         info!("Adding share to chain: {:?}", share);
 
-        if self.tips.is_empty() {
-            self.genesis_block_hash = share.cached_blockhash;
-        }
         let blockhash = share.cached_blockhash.unwrap();
         let prev_share_blockhash = share.header.prev_share_blockhash;
         let share_difficulty = share.header.miner_share.diff;
 
-        let height = match self.get_height_for_prevhash(prev_share_blockhash) {
+        // A share we can't pay out would skew the accounting of every share in its payout window
+        if let Err(e) = share.miner_address(self.network) {
+            warn!("Rejecting share {:?}: {}", blockhash, e);
+            return Err(e.into());
+        }
+
+        let prev_height = self.get_height_for_prevhash(prev_share_blockhash);
+        let height = match prev_height {
             Some(prev_height) => prev_height + 1,
             None => 0, // If there's no previous height, this is height 0
         };
+        // A share whose parent we don't have yet is put at height 0, its real height is unknown
+        let height_known = prev_share_blockhash.is_none() || prev_height.is_some();
+        if let Some(expected) = self.checkpoints.get(&height).filter(|_| height_known) {
+            if *expected != blockhash {
+                error!(
+                    "Rejecting share {:?} at height {}, it conflicts with checkpoint {:?}",
+                    blockhash, height, expected
+                );
+                return Err(format!(
+                    "Share {} conflicts with checkpoint {} at height {}",
+                    blockhash, expected, height
+                )
+                .into());
+            }
+        }
+        // The expected difficulty depends on the shares before this one, unknown for an orphan
+        if let Some(expected) = self
+            .expected_difficulty(prev_share_blockhash)
+            .filter(|_| height_known)
+        {
+            if (share_difficulty - expected).abs() > expected * RETARGET_TOLERANCE {
+                warn!(
+                    "Rejecting share {:?} with difficulty {}, expected {}",
+                    blockhash, share_difficulty, expected
+                );
+                return Err(format!(
+                    "Share {} has difficulty {}, expected {} within {}",
+                    blockhash, share_difficulty, expected, RETARGET_TOLERANCE
+                )
+                .into());
+            }
+        }
+
+        if self.tips.is_empty() {
+            self.genesis_block_hash = share.cached_blockhash;
+        }
+        for equivocation in self.find_equivocations(&share, height) {
+            warn!(
+                "Miner {} equivocated at height {}: {:?} conflicts with {:?}",
+                equivocation.miner_pubkey, height, equivocation.conflicting, equivocation.accepted
+            );
+            // Sending fails only when there are no subscribers
+            let _ = self.equivocation_tx.send(equivocation);
+        }
         // save to share to store for all cases
         tracing::debug!(
             "Adding share to store: {:?} at height: {}",
             share.cached_blockhash,
             height
         );
-        self.store.add_share(share.clone(), height);
+        self.store.add_share(share.clone(), height)?;
+        // Sending fails only when there are no subscribers
+        let _ = self.accepted_share_tx.send(share.clone());
+        if let Some(candidate) = self.find_block_candidate(blockhash, &share) {
+            info!(
+                "Share {:?} solved bitcoin block {}",
+                blockhash,
+                candidate.block.block_hash()
+            );
+            // Sending fails only when there are no subscribers
+            let _ = self.block_found_tx.send(candidate);
+        }
 
         // handle new chain by setting tip and total difficulty
         if self.tips.is_empty() {
@@ -82,6 +507,7 @@ impl Chain {
             self.tips.insert(blockhash);
             self.total_difficulty = share_difficulty;
             self.chain_tip = Some(blockhash);
+            self.tip_changed_at = self.clock.seconds_since_epoch();
             return Ok(());
         }
 
@@ -102,22 +528,344 @@ impl Chain {
         if let Some(prev_share_blockhash) = prev_share_blockhash {
             tracing::info!("Checking for reorgs at share: {:?}", prev_share_blockhash);
             let chain_upto_prev_share_blockhash = self.store.get_chain_upto(&prev_share_blockhash);
-            let total_difficulty_upto_prev_share_blockhash = chain_upto_prev_share_blockhash
-                .iter()
-                .map(|share| share.header.miner_share.diff)
-                .sum::<Decimal>();
+            // The walk back stops at the pruned heights, add the work of the pruned shares below them
+            let total_difficulty_upto_prev_share_blockhash = self.store.pruned_work()
+                + chain_upto_prev_share_blockhash
+                    .iter()
+                    .map(|share| share.header.miner_share.diff)
+                    .sum::<Decimal>();
             if total_difficulty_upto_prev_share_blockhash + share_difficulty > self.total_difficulty
             {
+                if let Some(deep_reorg) =
+                    self.find_deep_reorg(blockhash, &chain_upto_prev_share_blockhash)
+                {
+                    error!(
+                        "Refusing reorg to share {:?}, it would replace {} main chain shares below tip {:?}, more than the {} allowed. The share is kept as a side branch, investigate before following it.",
+                        blockhash, deep_reorg.depth, deep_reorg.chain_tip, self.max_reorg_depth
+                    );
+                    // Sending fails only when there are no subscribers
+                    let _ = self.deep_reorg_tx.send(deep_reorg);
+                    self.prune_side_branches();
+                    return Ok(());
+                }
+                if let Some(checkpoint_height) = self.find_checkpoint_violation(
+                    blockhash,
+                    height,
+                    &chain_upto_prev_share_blockhash,
+                ) {
+                    error!(
+                        "Refusing reorg to share {:?}, its branch doesn't pass through the checkpoint at height {}. The share is kept as a side branch.",
+                        blockhash, checkpoint_height
+                    );
+                    self.prune_side_branches();
+                    return Ok(());
+                }
+                let old_tip = self.chain_tip;
                 let reorg_result = self.reorg(share, total_difficulty_upto_prev_share_blockhash);
                 if reorg_result.is_err() {
                     error!("Failed to reorg chain for share: {:?}", blockhash);
                     return Err(reorg_result.err().unwrap());
                 }
+                // Extending the chain tip moves the tip without orphaning anything
+                if let Some(old_tip) = old_tip.filter(|tip| *tip != prev_share_blockhash) {
+                    let reorg =
+                        self.find_reorg(old_tip, blockhash, &chain_upto_prev_share_blockhash);
+                    info!(
+                        "Reorged from {:?} to {:?}, orphaned {} shares and promoted {}",
+                        old_tip,
+                        blockhash,
+                        reorg.orphaned.len(),
+                        reorg.promoted.len()
+                    );
+                    self.record_reorg();
+                    // Sending fails only when there are no subscribers
+                    let _ = self.reorg_tx.send(reorg);
+                }
             }
         }
+        self.prune_side_branches();
         Ok(())
     }
 
+    /// Rebuild the bitcoin block a share was mined for and check it against the network target in the
+    /// workbase, which is much harder than the share target. None if the share doesn't solve a block,
+    /// or its workbases aren't stored so the block can't be built.
+    fn find_block_candidate(
+        &self,
+        blockhash: ShareBlockHash,
+        share: &ShareBlock,
+    ) -> Option<BlockCandidate> {
+        let workinfoid = share.header.miner_share.workinfoid;
+        let workbase = self.store.get_workbase(workinfoid)?;
+        let user_workbase = self.store.get_user_workbase(workinfoid)?;
+        let block =
+            build_bitcoin_block(&workbase, &user_workbase, &share.header.miner_share).ok()?;
+        block.header.validate_pow(block.header.target()).ok()?;
+        Some(BlockCandidate {
+            share_blockhash: blockhash,
+            share: share.clone(),
+            workbase,
+            coinbase: block.txdata.first()?.clone(),
+            block,
+        })
+    }
+
+    /// Check if switching to a branch would replace more than max_reorg_depth main chain shares.
+    /// The depth is the number of shares from the chain tip back to where the branch forks off.
+    /// Only the last max_reorg_depth + 1 main chain shares are walked, a fork below them is too deep whatever its height.
+    fn find_deep_reorg(
+        &self,
+        blockhash: ShareBlockHash,
+        branch_upto_prev: &[ShareBlock],
+    ) -> Option<DeepReorg> {
+        if self.max_reorg_depth == 0 {
+            return None;
+        }
+        let chain_tip = self.chain_tip?;
+        let branch: HashSet<ShareBlockHash> = branch_upto_prev
+            .iter()
+            .filter_map(|share| share.cached_blockhash)
+            .collect();
+        let mut depth = 0;
+        let mut current = Some(chain_tip);
+        while let Some(main) = current {
+            if branch.contains(&main) || depth > self.max_reorg_depth {
+                break;
+            }
+            depth += 1;
+            current = self
+                .store
+                .get_share(&main)
+                .and_then(|share| share.header.prev_share_blockhash);
+        }
+        (depth > self.max_reorg_depth).then_some(DeepReorg {
+            chain_tip,
+            rejected: blockhash,
+            depth,
+        })
+    }
+
+    /// Check if switching to a branch would rewrite history below the highest checkpoint the main chain or
+    /// the branch has reached, returning the checkpoint height if the branch doesn't pass through it.
+    /// branch_upto_prev is the new share's chain from its parent back to genesis.
+    fn find_checkpoint_violation(
+        &self,
+        blockhash: ShareBlockHash,
+        height: u32,
+        branch_upto_prev: &[ShareBlock],
+    ) -> Option<u32> {
+        let tip_height = self
+            .chain_tip
+            .and_then(|tip| self.get_share_height(&tip))
+            .unwrap_or(0);
+        let (checkpoint_height, checkpoint) = self
+            .checkpoints
+            .range(..=height.max(tip_height))
+            .next_back()?;
+        if *checkpoint == blockhash {
+            return None;
+        }
+        let passes = *checkpoint_height < height
+            && branch_upto_prev
+                .iter()
+                .any(|share| share.cached_blockhash == Some(*checkpoint));
+        (!passes).then_some(*checkpoint_height)
+    }
+
+    /// Find the shares a reorg from old_tip to new_tip moved off and onto the main chain, and their miners.
+    /// branch_upto_prev is the new tip's chain from its parent back to genesis.
+    fn find_reorg(
+        &self,
+        old_tip: ShareBlockHash,
+        new_tip: ShareBlockHash,
+        branch_upto_prev: &[ShareBlock],
+    ) -> Reorg {
+        let old_chain = self.store.get_chain_upto(&old_tip);
+        let old_chain_hashes: HashSet<ShareBlockHash> = old_chain
+            .iter()
+            .filter_map(|share| share.cached_blockhash)
+            .collect();
+        let branch_hashes: HashSet<ShareBlockHash> = branch_upto_prev
+            .iter()
+            .filter_map(|share| share.cached_blockhash)
+            .collect();
+        let mut affected_miners = HashSet::new();
+        let mut orphaned = Vec::new();
+        for share in old_chain
+            .iter()
+            .take_while(|share| !branch_hashes.contains(&share.cached_blockhash.unwrap()))
+        {
+            orphaned.push(share.cached_blockhash.unwrap());
+            affected_miners.insert(share.header.miner_pubkey);
+        }
+        let mut promoted = vec![new_tip];
+        if let Some(new_share) = self.store.get_share(&new_tip) {
+            affected_miners.insert(new_share.header.miner_pubkey);
+        }
+        for share in branch_upto_prev
+            .iter()
+            .take_while(|share| !old_chain_hashes.contains(&share.cached_blockhash.unwrap()))
+        {
+            promoted.push(share.cached_blockhash.unwrap());
+            affected_miners.insert(share.header.miner_pubkey);
+        }
+        Reorg {
+            old_tip,
+            new_tip,
+            orphaned,
+            promoted,
+            affected_miners: affected_miners
+                .into_iter()
+                .map(|miner_pubkey| bitcoin::Address::p2pkh(miner_pubkey, self.network))
+                .collect(),
+        }
+    }
+
+    /// Drop the lowest work side branch tips once there are more than max_side_branches of them
+    /// Only the tips are forgotten, the shares stay in the store. The main chain tip is never dropped.
+    fn prune_side_branches(&mut self) {
+        let side_tips: Vec<ShareBlockHash> = self
+            .tips
+            .iter()
+            .filter(|tip| Some(**tip) != self.chain_tip)
+            .copied()
+            .collect();
+        if side_tips.len() <= self.max_side_branches {
+            return;
+        }
+        // Only walk the branches to compute their work once we know we have to drop some
+        let mut side_tips = self.side_branch_work(side_tips);
+        // Lowest work first, ties broken by blockhash so pruning is deterministic
+        side_tips.sort_by(|(work_a, hash_a), (work_b, hash_b)| {
+            work_a
+                .cmp(work_b)
+                .then_with(|| hash_a.to_string().cmp(&hash_b.to_string()))
+        });
+        let excess = side_tips.len() - self.max_side_branches;
+        for (work, tip) in side_tips.into_iter().take(excess) {
+            info!(
+                "Dropping side branch tip {:?} with work {} above the prune horizon",
+                tip, work
+            );
+            self.tips.remove(&tip);
+        }
+    }
+
+    /// Work of each side branch above the prune horizon, the lowest height any of them forks off the main chain.
+    /// Below the horizon every branch is the main chain, so this orders the branches the same as their total work
+    /// while walking back no further than the horizon.
+    fn side_branch_work(&self, side_tips: Vec<ShareBlockHash>) -> Vec<(Decimal, ShareBlockHash)> {
+        let main_height = match self.chain_tip.and_then(|tip| self.get_share_height(&tip)) {
+            Some(height) => height,
+            None => {
+                return side_tips
+                    .into_iter()
+                    .map(|tip| (self.get_total_difficulty_upto(&tip), tip))
+                    .collect()
+            }
+        };
+        // Main chain shares walked back from the tip only as far as the branches need, the share at index i
+        // is at main_height - i
+        let mut main_chain: Vec<ShareBlock> = Vec::new();
+        let mut next_main = self.chain_tip;
+        let mut branches = Vec::with_capacity(side_tips.len());
+        for tip in side_tips {
+            let mut height = self.get_share_height(&tip).unwrap_or_default();
+            let mut current = Some(tip);
+            let mut work = Decimal::ZERO;
+            while let Some(blockhash) = current {
+                if height <= main_height {
+                    let index = (main_height - height) as usize;
+                    while main_chain.len() <= index {
+                        match next_main.and_then(|main| self.store.get_share(&main)) {
+                            Some(share) => {
+                                next_main = share.header.prev_share_blockhash;
+                                main_chain.push(share);
+                            }
+                            None => break,
+                        }
+                    }
+                    if main_chain
+                        .get(index)
+                        .is_some_and(|share| share.cached_blockhash == Some(blockhash))
+                    {
+                        break;
+                    }
+                }
+                let Some(share) = self.store.get_share(&blockhash) else {
+                    break;
+                };
+                work += share.header.miner_share.diff;
+                current = share.header.prev_share_blockhash;
+                height = height.saturating_sub(1);
+            }
+            branches.push((tip, height, work));
+        }
+        let horizon = branches
+            .iter()
+            .map(|(_, fork_height, _)| *fork_height)
+            .min()
+            .unwrap_or_default();
+        branches
+            .into_iter()
+            .map(|(tip, fork_height, work)| {
+                // Add the main chain work between the horizon and where this branch forks off
+                let main_work: Decimal = main_chain
+                    .iter()
+                    .skip(main_height.saturating_sub(fork_height) as usize)
+                    .take(fork_height.saturating_sub(horizon) as usize)
+                    .map(|share| share.header.miner_share.diff)
+                    .sum();
+                (work + main_work, tip)
+            })
+            .collect()
+    }
+
+    /// Total difficulty of the chain from genesis up to and including blockhash, including the pruned shares
+    fn get_total_difficulty_upto(&self, blockhash: &ShareBlockHash) -> Decimal {
+        self.store.pruned_work()
+            + self
+                .store
+                .get_chain_upto(blockhash)
+                .iter()
+                .map(|share| share.header.miner_share.diff)
+                .sum::<Decimal>()
+    }
+
+    /// The difficulty expected of a share building on prev_share_blockhash, retargeted so shares arrive every
+    /// target_share_interval_secs. The average difficulty of the retarget_window shares up to the parent is scaled by
+    /// how much faster or slower than the target they arrived, by at most MAX_RETARGET_FACTOR either way.
+    /// None when retargeting is disabled or the parent has fewer than retarget_window shares before it.
+    pub fn expected_difficulty(
+        &self,
+        prev_share_blockhash: Option<ShareBlockHash>,
+    ) -> Option<Decimal> {
+        if self.retarget_window == 0 || self.target_share_interval_secs == 0 {
+            return None;
+        }
+        // The window's timespan runs from the share before the window to the parent
+        let mut shares = Vec::with_capacity(self.retarget_window + 1);
+        let mut current = prev_share_blockhash;
+        while shares.len() <= self.retarget_window {
+            let share = self.store.get_share(&current?)?;
+            current = share.header.prev_share_blockhash;
+            shares.push(share);
+        }
+        let ntime = |share: &ShareBlock| share.header.miner_share.ntime.to_consensus_u32() as u64;
+        let timespan = ntime(&shares[0]).saturating_sub(ntime(&shares[self.retarget_window]));
+        let target_timespan = self.retarget_window as u64 * self.target_share_interval_secs;
+        let timespan = timespan.clamp(
+            (target_timespan / MAX_RETARGET_FACTOR).max(1),
+            target_timespan * MAX_RETARGET_FACTOR,
+        );
+        let average = shares[..self.retarget_window]
+            .iter()
+            .map(|share| share.header.miner_share.diff)
+            .sum::<Decimal>()
+            / Decimal::from(self.retarget_window);
+        Some(average * Decimal::from(target_timespan) / Decimal::from(timespan))
+    }
+
     /// Get height for the previous blockhash
     fn get_height_for_prevhash(&mut self, hash: Option<ShareBlockHash>) -> Option<u32> {
         match hash {
@@ -157,9 +905,54 @@ impl Chain {
         self.total_difficulty =
             total_difficulty_upto_prev_share_blockhash + share.header.miner_share.diff;
         self.chain_tip = share.cached_blockhash;
+        self.tip_changed_at = self.clock.seconds_since_epoch();
         Ok(())
     }
 
+    /// Remember when a reorg happened, forgetting reorgs that fell out of the stability window
+    fn record_reorg(&mut self) {
+        let now = self.clock.seconds_since_epoch();
+        self.reorg_times.push_back(now);
+        while let Some(oldest) = self.reorg_times.front() {
+            if now.saturating_sub(*oldest) < self.stable_tip_window_secs {
+                break;
+            }
+            self.reorg_times.pop_front();
+        }
+    }
+
+    /// Number of reorgs in the stability window ending at now
+    fn recent_reorgs(&self, now: u64) -> usize {
+        self.reorg_times
+            .iter()
+            .filter(|time| now.saturating_sub(**time) < self.stable_tip_window_secs)
+            .count()
+    }
+
+    /// True when there is a chain tip, it stayed unchanged for stable_tip_min_unchanged_secs and the
+    /// chain went through at most stable_tip_max_reorgs reorgs in the last stable_tip_window_secs.
+    /// A payout service should wait for this before paying out on the tip, so a run of reorgs doesn't
+    /// get it to pay out on a tip that is about to be replaced.
+    pub fn is_tip_stable(&self) -> bool {
+        let now = self.clock.seconds_since_epoch();
+        self.chain_tip.is_some()
+            && now.saturating_sub(self.tip_changed_at) >= self.stable_tip_min_unchanged_secs
+            && self.recent_reorgs(now) <= self.stable_tip_max_reorgs
+    }
+
+    /// The chain tip, its height and work, and how long ago and how often it changed
+    pub fn get_chain_stats(&self) -> ChainStats {
+        let now = self.clock.seconds_since_epoch();
+        ChainStats {
+            chain_tip: self.chain_tip,
+            height: self.get_tip_height(),
+            total_difficulty: self.total_difficulty,
+            tip_unchanged_secs: now.saturating_sub(self.tip_changed_at),
+            recent_reorgs: self.recent_reorgs(now),
+            tip_stable: self.is_tip_stable(),
+        }
+    }
+
     /// Check if a share is confirmed according to the minimum confirmation depth
     pub fn is_confirmed(&self, share: ShareBlock) -> bool {
         if share.header.prev_share_blockhash.is_none() {
@@ -171,16 +964,30 @@ impl Chain {
             > MIN_CONFIRMATION_DEPTH
     }
 
-    /// Add a workbase to the chain
+    /// Add a workbase to the chain, reporting whether it was new or already stored.
+    /// A conflicting workbase with the same workinfoid but different contents is an error.
     pub fn add_workbase(
         &mut self,
         workbase: MinerWorkbase,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        if let Err(e) = self.store.add_workbase(workbase) {
-            error!("Failed to add workbase to store: {}", e);
-            return Err("Error adding workbase to store".into());
+    ) -> Result<WorkbaseOutcome, Box<dyn Error + Send + Sync>> {
+        match self.store.add_workbase(workbase) {
+            Ok(outcome) => {
+                if outcome == WorkbaseOutcome::Added && self.max_workbases > 0 {
+                    match self.store.evict_workbases(self.max_workbases) {
+                        Ok(evicted) if !evicted.is_empty() => {
+                            debug!("Evicted workbases {:?}", evicted);
+                        }
+                        Ok(_) => {}
+                        Err(e) => error!("Failed to evict workbases: {}", e),
+                    }
+                }
+                Ok(outcome)
+            }
+            Err(e) => {
+                error!("Failed to add workbase to store: {}", e);
+                Err(format!("Error adding workbase to store: {}", e).into())
+            }
         }
-        Ok(())
     }
 
     /// Add a user workbase to the chain
@@ -241,6 +1048,81 @@ impl Chain {
             .get_blockhashes_for_locator(locator, stop_block_hash, max_blockhashes)
     }
 
+    /// Get a page of the chain starting at cursor, with at most max_shares shares and max_bytes of serialized shares.
+    /// All shares stored at each height are served, including uncles and side branches.
+    /// A page always has at least one share, so a share larger than max_bytes doesn't stall the sync.
+    pub fn get_chain_page(
+        &self,
+        cursor: ChainCursor,
+        max_shares: usize,
+        max_bytes: usize,
+    ) -> ChainPage {
+        let Some(tip_height) = self.get_tip_height() else {
+            return ChainPage {
+                shares: vec![],
+                next: None,
+            };
+        };
+        let mut shares = Vec::new();
+        let mut bytes = 0;
+        let mut height = cursor.height;
+        let mut offset = cursor.offset as usize;
+        while height <= tip_height {
+            let mut at_height: Vec<(ShareBlockHash, ShareBlock)> =
+                self.get_shares_at_height(height).into_iter().collect();
+            at_height.sort_by_key(|(blockhash, _)| blockhash.to_string());
+            for (_, share) in at_height.into_iter().skip(offset) {
+                let size = serialized_size(&share);
+                if !shares.is_empty() && (shares.len() >= max_shares || bytes + size > max_bytes) {
+                    return ChainPage {
+                        shares,
+                        next: Some(ChainCursor {
+                            height,
+                            offset: offset as u32,
+                        }),
+                    };
+                }
+                bytes += size;
+                shares.push(share);
+                offset += 1;
+            }
+            height += 1;
+            offset = 0;
+        }
+        ChainPage { shares, next: None }
+    }
+
+    /// Get the first page of the chain above the common ancestor with a peer's locator, so a syncing peer on a
+    /// fork that branches below its tip fetches our branch from the fork point. Served from genesis if no share
+    /// of the locator is on our main chain.
+    pub fn get_chain_page_from_locator(
+        &self,
+        locator: &[ShareBlockHash],
+        max_shares: usize,
+        max_bytes: usize,
+    ) -> ChainPage {
+        let height = self
+            .locator_fork_height(locator)
+            .map_or(0, |fork_height| fork_height + 1);
+        self.get_chain_page(ChainCursor { height, offset: 0 }, max_shares, max_bytes)
+    }
+
+    /// Height of the highest share of the locator on our main chain, None if none of them is
+    fn locator_fork_height(&self, locator: &[ShareBlockHash]) -> Option<u32> {
+        let locator: HashSet<&ShareBlockHash> = locator.iter().collect();
+        let mut current = self.chain_tip;
+        while let Some(blockhash) = current {
+            if locator.contains(&blockhash) {
+                return self.get_share_height(&blockhash);
+            }
+            current = self
+                .store
+                .get_share(&blockhash)
+                .and_then(|share| share.header.prev_share_blockhash);
+        }
+        None
+    }
+
     /// Get the height of the chain tip
     pub fn get_tip_height(&self) -> Option<u32> {
         match self.chain_tip {
@@ -311,11 +1193,26 @@ impl Chain {
         self.store.get_workbase(workinfoid)
     }
 
+    /// Get the workbase stored before the one with workinfoid, see Store::get_previous_workbase
+    pub fn get_previous_workbase(&self, workinfoid: u64) -> Option<MinerWorkbase> {
+        self.store.get_previous_workbase(workinfoid)
+    }
+
     /// Get workbases from the chain given a list of workinfoids
     pub fn get_workbases(&self, workinfoids: &[u64]) -> Vec<MinerWorkbase> {
         self.store.get_workbases(workinfoids)
     }
 
+    /// List workbases in a height or time range, skipping offset of them and returning at most limit
+    pub fn list_workbases(
+        &self,
+        range: &WorkbaseRange,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<MinerWorkbase> {
+        self.store.list_workbases(range, offset, limit)
+    }
+
     /// Get a user workbase from the chain given a workinfoid
     pub fn get_user_workbase(&self, workinfoid: u64) -> Option<UserWorkbase> {
         self.store.get_user_workbase(workinfoid)
@@ -340,64 +1237,841 @@ impl Chain {
         (self.chain_tip, uncles)
     }
 
-    /// Check which blockhashes from the provided list are missing from the chain
-    /// Returns a vector of blockhashes that are not present in the chain
-    pub fn get_missing_blockhashes(&self, blockhashes: &[ShareBlockHash]) -> Vec<ShareBlockHash> {
-        self.store.get_missing_blockhashes(blockhashes)
+    /// Add a share and record where it first reached us, if it is added
+    pub fn add_share_with_provenance(
+        &mut self,
+        share: ShareBlock,
+        provenance: ShareProvenance,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let blockhash = share.cached_blockhash;
+        self.add_share(share)?;
+        if let Some(blockhash) = blockhash {
+            self.store.set_share_provenance(&blockhash, provenance);
+        }
+        Ok(())
     }
 
-    /// Get the depth of a blockhash from chain tip
-    /// Returns None if blockhash is not found in chain
-    /// Returns 0 if blockhash is the chain tip
-    pub fn get_depth(&self, blockhash: &ShareBlockHash) -> Option<usize> {
-        // If chain tip is None, return None
-        let tip = self.chain_tip?;
-
-        // If blockhash is chain tip, return 0
-        if tip == *blockhash {
-            return Some(0);
+    /// Where a share stands in the chain. A share both on a side branch and referenced as an uncle is an Uncle.
+    pub fn get_share_status(&self, blockhash: &ShareBlockHash) -> ShareStatus {
+        let Some(height) = self.get_share_height(blockhash) else {
+            return ShareStatus::Unknown;
+        };
+        // Walk the main chain down to the share's height, main chain shares referencing uncles are above them
+        let mut current = self.chain_tip;
+        while let Some(main_chain_blockhash) = current {
+            if main_chain_blockhash == *blockhash {
+                return ShareStatus::MainChain;
+            }
+            let Some(share) = self.store.get_share(&main_chain_blockhash) else {
+                break;
+            };
+            if share.header.uncles.contains(blockhash) {
+                return ShareStatus::Uncle;
+            }
+            match self.get_share_height(&main_chain_blockhash) {
+                Some(main_chain_height) if main_chain_height > height => {}
+                _ => break,
+            }
+            current = share.header.prev_share_blockhash;
         }
-
-        // Get chain from tip to given blockhash
-        let chain = self.store.get_chain_upto(blockhash);
-        // If chain is empty, blockhash not found
-        if chain.is_empty() {
-            return None;
+        let on_side_branch = self
+            .tips
+            .iter()
+            .filter(|tip| Some(**tip) != self.chain_tip)
+            .any(|tip| self.is_ancestor_or_self(blockhash, height, *tip));
+        if on_side_branch {
+            ShareStatus::SideBranch
+        } else {
+            ShareStatus::Orphan
         }
+    }
 
-        // Return length of chain minus 1 (since chain includes the blockhash)
-        Some(chain.len())
+    /// Height of a stored share, None if it is not in the store
+    pub fn get_share_height(&self, blockhash: &ShareBlockHash) -> Option<u32> {
+        self.store
+            .get_block_metadata(blockhash)
+            .and_then(|metadata| metadata.height)
     }
-}
 
-#[cfg(test)]
-mod chain_tests {
-    use super::*;
-    use crate::test_utils::random_hex_string;
-    use crate::test_utils::TestBlockBuilder;
-    use std::collections::HashSet;
-    use tempfile::tempdir;
+    /// Whether the share at height is the tip or one of the tip's ancestors
+    fn is_ancestor_or_self(
+        &self,
+        blockhash: &ShareBlockHash,
+        height: u32,
+        tip: ShareBlockHash,
+    ) -> bool {
+        let mut current = Some(tip);
+        while let Some(ancestor) = current {
+            if ancestor == *blockhash {
+                return true;
+            }
+            match self.get_share_height(&ancestor) {
+                Some(ancestor_height) if ancestor_height > height => {}
+                _ => return false,
+            }
+            current = self
+                .store
+                .get_share(&ancestor)
+                .and_then(|share| share.header.prev_share_blockhash);
+        }
+        false
+    }
 
-    #[test_log::test(test)]
-    /// Setup a test chain with 3 shares on the main chain, where shares 2 and 3 have two uncles each
-    fn test_chain_add_shares() {
-        let temp_dir = tempdir().unwrap();
-        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
-        let mut chain = Chain::new(store);
+    /// Where a share first reached us, None if it was added without provenance
+    pub fn get_share_provenance(&self, blockhash: &ShareBlockHash) -> Option<ShareProvenance> {
+        self.store.get_share_provenance(blockhash)
+    }
 
-        // Create initial share (1)
-        let share1 = TestBlockBuilder::new()
-            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
-            .miner_pubkey("020202020202020202020202020202020202020202020202020202020202020202")
-            .build();
+    /// Delete the shares that first reached us from a peer, as long as the chain stays connected.
+    /// Shares with children, shares referenced as uncles and the chain tip are kept and reported.
+    /// Shares are visited highest first, so a peer's shares building only on each other are all removed.
+    pub fn purge_peer_shares(&mut self, peer_id: libp2p::PeerId) -> PurgeReport {
+        let mut candidates: Vec<(u32, ShareBlockHash)> = self
+            .store
+            .get_shares_by_provenance(ShareProvenance::Peer(peer_id))
+            .into_iter()
+            .map(|blockhash| {
+                let height = self
+                    .store
+                    .get_block_metadata(&blockhash)
+                    .and_then(|metadata| metadata.height)
+                    .unwrap_or_default();
+                (height, blockhash)
+            })
+            .collect();
+        candidates.sort_by(|(height_a, hash_a), (height_b, hash_b)| {
+            height_b
+                .cmp(height_a)
+                .then_with(|| hash_a.to_string().cmp(&hash_b.to_string()))
+        });
 
-        chain.add_share(share1.clone()).unwrap();
+        let lowest_height = candidates.last().map(|(height, _)| *height);
+        let referenced_uncles: HashSet<ShareBlockHash> =
+            match (lowest_height, self.get_tip_height()) {
+                (Some(lowest_height), Some(tip_height)) => (lowest_height..=tip_height)
+                    .flat_map(|height| self.store.get_shares_at_height(height).into_values())
+                    .flat_map(|share| share.header.uncles)
+                    .collect(),
+                _ => HashSet::new(),
+            };
 
-        let mut expected_tips = HashSet::new();
-        expected_tips.insert(share1.cached_blockhash.unwrap());
-        assert_eq!(chain.tips, expected_tips);
-        assert_eq!(chain.total_difficulty, dec!(1.0));
-        assert_eq!(chain.chain_tip, Some(share1.cached_blockhash.unwrap()));
+        // Candidates go from the highest, so a share's children from the same peer are purged before it is checked.
+        // The shares are only collected here and deleted together in one batch.
+        let mut report = PurgeReport::default();
+        let mut removed = HashSet::new();
+        let mut parents = Vec::new();
+        for (_, blockhash) in candidates {
+            if Some(blockhash) == self.chain_tip
+                || referenced_uncles.contains(&blockhash)
+                || self
+                    .store
+                    .get_children_blockhashes(&blockhash)
+                    .iter()
+                    .any(|child| !removed.contains(child))
+            {
+                report.kept.push(blockhash);
+                continue;
+            }
+            if let Some(prev_share_blockhash) = self
+                .store
+                .get_share(&blockhash)
+                .and_then(|share| share.header.prev_share_blockhash)
+            {
+                parents.push(prev_share_blockhash);
+            }
+            removed.insert(blockhash);
+            report.removed.push(blockhash);
+        }
+        if !report.removed.is_empty() {
+            if let Err(e) = self.store.delete_leaf_shares(&report.removed) {
+                error!("Failed to purge shares from peer {}: {}", peer_id, e);
+                report.removed.clear();
+                return report;
+            }
+        }
+        for blockhash in &report.removed {
+            self.tips.remove(blockhash);
+        }
+        // A parent left without children is the tip of its branch again
+        for parent in parents {
+            if !removed.contains(&parent) && self.store.get_children_blockhashes(&parent).is_empty()
+            {
+                self.tips.insert(parent);
+            }
+        }
+        info!(
+            "Purged {} shares from peer {}, kept {} the chain depends on",
+            report.removed.len(),
+            peer_id,
+            report.kept.len()
+        );
+        report
+    }
+
+    /// Prune the oldest shares once the store uses more than max_disk_bytes, until it is under low_water_bytes.
+    /// Shares that are not confirmed yet or in the payout window are never pruned, so usage can stay above
+    /// the low water mark.
+    pub fn prune_to_disk_usage(
+        &mut self,
+        max_disk_bytes: u64,
+        low_water_bytes: u64,
+    ) -> PruneReport {
+        let bytes_before = self.store.disk_usage_bytes();
+        let mut report = PruneReport {
+            bytes_before,
+            bytes_after: bytes_before,
+            shares_pruned: 0,
+        };
+        if bytes_before <= max_disk_bytes {
+            return report;
+        }
+        let keep_heights = MIN_CONFIRMATION_DEPTH.max(self.payout_window) as u32;
+        let prunable_below = match self.get_tip_height() {
+            Some(tip_height) => (tip_height + 1).saturating_sub(keep_heights),
+            None => 0,
+        };
+
+        while report.bytes_after > low_water_bytes {
+            let height = match self.store.lowest_unpruned_height() {
+                Some(height) if height < prunable_below => height,
+                _ => break,
+            };
+            let end = (height + PRUNE_BATCH_HEIGHTS).min(prunable_below);
+            let pruned = match self.store.prune_heights(height..end) {
+                Ok(pruned) => pruned,
+                Err(e) => {
+                    error!("Failed to prune heights {}..{}: {}", height, end, e);
+                    break;
+                }
+            };
+            for blockhash in &pruned {
+                self.tips.remove(blockhash);
+            }
+            report.shares_pruned += pruned.len();
+            report.bytes_after = self.store.disk_usage_bytes();
+        }
+        if report.bytes_after > low_water_bytes {
+            warn!(
+                "Store uses {} bytes after pruning {} shares, more than the {} bytes low water mark. The rest of the shares are too recent to prune.",
+                report.bytes_after, report.shares_pruned, low_water_bytes
+            );
+        }
+        report
+    }
+
+    /// Compact the whole store, returning its disk usage before and after in bytes
+    pub fn compact_store(&self) -> (u64, u64) {
+        self.store.maintenance().compact()
+    }
+
+    /// Handle to run maintenance on the store, like compaction, without holding the chain
+    pub fn store_maintenance(&self) -> StoreMaintenance {
+        self.store.maintenance()
+    }
+
+    /// Get the blockhashes of all shares attributed to a miner payout address
+    pub fn get_shares_by_miner(&self, address: &bitcoin::Address) -> Vec<ShareBlockHash> {
+        self.store.get_shares_by_miner(address)
+    }
+
+    /// Count the orphans and list the parents they are waiting for
+    pub fn get_orphan_summary(&self) -> OrphanSummary {
+        let orphans: HashMap<ShareBlockHash, ShareBlockHash> = self
+            .store
+            .get_shares_at_height(0)
+            .into_iter()
+            .filter_map(|(blockhash, share)| {
+                share
+                    .header
+                    .prev_share_blockhash
+                    .map(|prev| (blockhash, prev))
+            })
+            .collect();
+        let mut summary = OrphanSummary {
+            count: orphans.len(),
+            ..Default::default()
+        };
+        for prev in orphans.values() {
+            if self.store.get_share(prev).is_none() {
+                if !summary.missing_parents.contains(prev) {
+                    summary.missing_parents.push(*prev);
+                }
+            } else if !orphans.contains_key(prev) {
+                summary.connectable += 1;
+            }
+        }
+        summary
+    }
+
+    /// The shares missing to connect an orphan, found by walking back from it through the orphans it builds on
+    /// to the first parent not in the store, the deepest missing ancestor. Uncles are validated to be stored
+    /// before a share is added, so only a parent can be missing. Empty if the share is unknown, is not an
+    /// orphan, or builds on orphans that can already be reconnected.
+    pub fn get_missing_ancestors(&self, blockhash: &ShareBlockHash) -> Vec<ShareBlockHash> {
+        let mut visited = HashSet::new();
+        let mut current = *blockhash;
+        while visited.insert(current) {
+            // Orphans are stored at height 0, a share at any other height is connected to the chain
+            if self.get_share_height(&current) != Some(0) {
+                return Vec::new();
+            }
+            let Some(prev) = self
+                .store
+                .get_share(&current)
+                .and_then(|share| share.header.prev_share_blockhash)
+            else {
+                return Vec::new();
+            };
+            if self.store.get_share(&prev).is_none() {
+                return vec![prev];
+            }
+            current = prev;
+        }
+        Vec::new()
+    }
+
+    /// Reconnect orphans, shares stored at height 0 because their parent was missing when they arrived, whose
+    /// parent has since been stored. Each is added again at its real height, along with the shares built on it,
+    /// and may become the chain tip. Returns the number of shares reconnected.
+    pub fn drain_orphans(&mut self) -> usize {
+        let orphans: Vec<ShareBlock> = self
+            .store
+            .get_shares_at_height(0)
+            .into_values()
+            .filter(|share| {
+                share
+                    .header
+                    .prev_share_blockhash
+                    .is_some_and(|prev| self.store.get_share(&prev).is_some())
+            })
+            .collect();
+        let orphan_blockhashes: HashSet<ShareBlockHash> = orphans
+            .iter()
+            .map(|share| share.cached_blockhash.unwrap())
+            .collect();
+        // Orphans building on another orphan are reconnected along with their parent
+        let mut pending: VecDeque<ShareBlockHash> = orphans
+            .iter()
+            .filter(|share| {
+                !share
+                    .header
+                    .prev_share_blockhash
+                    .is_some_and(|prev| orphan_blockhashes.contains(&prev))
+            })
+            .map(|share| share.cached_blockhash.unwrap())
+            .collect();
+        let mut connected = 0;
+        while let Some(blockhash) = pending.pop_front() {
+            let Some(share) = self.store.get_share(&blockhash) else {
+                continue;
+            };
+            let old_height = self.get_share_height(&blockhash);
+            if let Err(e) = self.add_share(share) {
+                warn!("Failed to reconnect orphan share {:?}: {}", blockhash, e);
+                continue;
+            }
+            if let Some(old_height) = old_height
+                .filter(|old_height| Some(*old_height) != self.get_share_height(&blockhash))
+            {
+                if let Err(e) = self
+                    .store
+                    .remove_height_to_blockhash(&blockhash, old_height)
+                {
+                    warn!(
+                        "Failed to remove reconnected share {:?} from height {}: {}",
+                        blockhash, old_height, e
+                    );
+                }
+            }
+            connected += 1;
+            pending.extend(self.store.get_children_blockhashes(&blockhash));
+        }
+        info!("Reconnected {} orphan shares", connected);
+        connected
+    }
+
+    /// Rebuild the store indexes for the shares at a height, returning the number of shares reindexed
+    pub fn reindex_height(&mut self, height: u32) -> usize {
+        self.store
+            .reindex_miner_shares_at_height(height)
+            .unwrap_or_else(|e| {
+                error!("Failed to reindex height {}: {}", height, e);
+                0
+            })
+    }
+
+    /// The main chain shares that share a reward, the payout_window most recent ones, starting from the tip
+    pub fn payout_window(&self) -> Vec<ShareBlock> {
+        self.main_chain_window(self.payout_window)
+    }
+
+    /// The len most recent main chain shares, starting from the tip
+    fn main_chain_window(&self, len: usize) -> Vec<ShareBlock> {
+        match self.chain_tip {
+            Some(tip) => self
+                .window_from(tip, len)
+                .into_iter()
+                .map(|(_, share)| share)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Up to len shares and their blockhashes, starting from blockhash and following parents back
+    fn window_from(
+        &self,
+        blockhash: ShareBlockHash,
+        len: usize,
+    ) -> Vec<(ShareBlockHash, ShareBlock)> {
+        let mut window = Vec::new();
+        let mut current = Some(blockhash);
+        while let Some(blockhash) = current {
+            if window.len() >= len {
+                break;
+            }
+            match self.store.get_share(&blockhash) {
+                Some(share) => {
+                    current = share.header.prev_share_blockhash;
+                    window.push((blockhash, share));
+                }
+                None => break,
+            }
+        }
+        window
+    }
+
+    /// Proof of the shares the reward for the bitcoin block solved by share block_hash is split over,
+    /// with their cumulative work. None if the share is not in the store.
+    pub fn inclusion_proof(&self, block_hash: ShareBlockHash) -> Option<PayoutProof> {
+        let window = self.window_from(block_hash, self.payout_window);
+        if window.is_empty() {
+            return None;
+        }
+        Some(PayoutProof::new(block_hash, self.payout_policy, &window))
+    }
+
+    /// Check a proof's signature and arithmetic, then that its policy and entries match the proof built from the
+    /// shares in our store, so a proof for shares we don't have or with altered miners or work is rejected.
+    pub fn verify_inclusion_proof(&self, proof: &PayoutProof) -> Result<(), String> {
+        proof.verify()?;
+        let expected = self
+            .inclusion_proof(proof.block_hash)
+            .ok_or_else(|| format!("share {} is not in the store", proof.block_hash))?;
+        if proof.policy != expected.policy {
+            return Err(format!(
+                "proof uses payout policy {:?}, the chain uses {:?}",
+                proof.policy, expected.policy
+            ));
+        }
+        if proof.entries.len() != expected.entries.len() {
+            return Err(format!(
+                "proof has {} shares, the payout window of block {} has {}",
+                proof.entries.len(),
+                proof.block_hash,
+                expected.entries.len()
+            ));
+        }
+        match proof
+            .entries
+            .iter()
+            .zip(&expected.entries)
+            .find(|(entry, stored)| entry != stored)
+        {
+            Some((entry, _)) => Err(format!(
+                "share {} in the proof does not match the store",
+                entry.blockhash
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Number of stored shares found in each of the count most recent buckets of the given length, from the
+    /// share time index. Buckets are aligned to multiples of their length in seconds since epoch, the last
+    /// one holds now. Returns the start time of each bucket with its count, oldest first.
+    pub fn share_histogram(&self, bucket: Duration, count: usize, now: u64) -> Vec<(u64, u64)> {
+        let bucket_secs = bucket.as_secs().clamp(1, u32::MAX as u64);
+        let last = now - now % bucket_secs;
+        let first = last.saturating_sub(bucket_secs.saturating_mul(count.saturating_sub(1) as u64));
+        let first = match u32::try_from(first) {
+            Ok(first) => first,
+            Err(_) => return Vec::new(),
+        };
+        self.store
+            .count_shares_by_time(first, bucket_secs as u32, count)
+            .into_iter()
+            .map(|(start, shares)| (start as u64, shares))
+            .collect()
+    }
+
+    /// Estimated hashes per second behind the shares found in the window ending at now, in seconds since epoch.
+    /// Sums the difficulty of main chain shares and their uncles with a time in the window, walking back from
+    /// the tip, and divides the expected hashes, 2^32 per unit of difficulty, by the window length.
+    /// Share times are not monotonic along the chain, so shares outside the window are skipped rather than ending
+    /// the walk. A share's time is within MAX_TIME_DIFF of when it was found, so the walk ends at the first share
+    /// more than twice that before the window, all its ancestors were found before the window started.
+    pub fn estimate_hashrate(&self, window: Duration, now: u64) -> f64 {
+        if window.is_zero() {
+            return 0.0;
+        }
+        let since = now.saturating_sub(window.as_secs());
+        let ntime = |share: &ShareBlock| share.header.miner_share.ntime.to_consensus_u32() as u64;
+        let in_window = |share: &ShareBlock| ntime(share) >= since;
+
+        let mut counted = HashSet::new();
+        let mut difficulty = Decimal::ZERO;
+        let mut current = self.chain_tip;
+        while let Some(blockhash) = current {
+            let share = match self.store.get_share(&blockhash) {
+                Some(share) if ntime(&share) + 2 * MAX_TIME_DIFF >= since => share,
+                _ => break,
+            };
+            if in_window(&share) && counted.insert(blockhash) {
+                difficulty += share.header.miner_share.diff;
+            }
+            for uncle in share.header.uncles.iter() {
+                if let Some(uncle_share) = self.store.get_share(uncle) {
+                    if in_window(&uncle_share) && counted.insert(*uncle) {
+                        difficulty += uncle_share.header.miner_share.diff;
+                    }
+                }
+            }
+            current = share.header.prev_share_blockhash;
+        }
+        difficulty.to_f64().unwrap_or(0.0) * 2f64.powi(32) / window.as_secs_f64()
+    }
+
+    /// Each miner's part of a reward under the configured payout policy, in parts of PAYOUT_SCALE
+    pub fn compute_payouts(&self) -> HashMap<bitcoin::Address, u64> {
+        let weights = self
+            .payout_policy
+            .miner_weights(&self.payout_window())
+            .into_iter()
+            .map(|(miner_pubkey, weight)| {
+                (bitcoin::Address::p2pkh(miner_pubkey, self.network), weight)
+            })
+            .collect();
+        scale_weights(weights)
+    }
+
+    /// Split total_reward, in satoshis, between the miners of the window most recent main chain shares under
+    /// the configured payout policy, with each miner's share count and weight
+    pub fn payout_report(&self, window: usize, total_reward: u64) -> PayoutReport {
+        PayoutReport::new(
+            self.payout_policy,
+            &self.main_chain_window(window),
+            self.network,
+            total_reward,
+        )
+    }
+
+    /// Replace the chain with a chain snapshot if it is valid, has the same genesis and more work
+    /// Snapshot shares missing from the store are added to it, then the tips, chain tip and total
+    /// difficulty are replaced in one step. Workbases and shares already stored are kept.
+    pub fn load_snapshot(&mut self, snapshot: ChainSnapshot) -> Result<(), SnapshotError> {
+        let loaded = snapshot.validate()?;
+        if let Some(genesis) = self.genesis_block_hash {
+            if genesis != loaded.genesis {
+                return Err(SnapshotError::Invalid(format!(
+                    "snapshot genesis {} is not the chain genesis {}",
+                    loaded.genesis, genesis
+                )));
+            }
+        }
+        if loaded.total_difficulty <= self.total_difficulty {
+            return Err(SnapshotError::NotHeavier {
+                snapshot: loaded.total_difficulty,
+                current: self.total_difficulty,
+            });
+        }
+        // The missing shares go to the store in one atomic batch instead of a write per share
+        let missing: Vec<(ShareBlock, u32)> = snapshot
+            .shares
+            .into_iter()
+            .zip(loaded.heights)
+            .filter(|(share, _)| {
+                self.store
+                    .get_share(&share.cached_blockhash.unwrap())
+                    .is_none()
+            })
+            .collect();
+        self.store
+            .add_shares(missing)
+            .map_err(|e| SnapshotError::Store(e.to_string()))?;
+        info!(
+            "Loaded chain snapshot with tip {:?} and work {}",
+            loaded.chain_tip, loaded.total_difficulty
+        );
+        self.genesis_block_hash = Some(loaded.genesis);
+        self.tips = loaded.tips;
+        self.chain_tip = Some(loaded.chain_tip);
+        self.total_difficulty = loaded.total_difficulty;
+        self.tip_changed_at = self.clock.seconds_since_epoch();
+        self.prune_side_branches();
+        Ok(())
+    }
+
+    /// Forcibly make a stored share the chain tip, recomputing the total difficulty from its chain.
+    /// Meant for testing reorg handling and emergency recovery only, the share doesn't need the most work.
+    /// Tips are leaves, so this fails if the share is not in the store or has children.
+    /// Moving the tip off the main chain is published as a reorg, like any other reorg.
+    pub fn set_tip(
+        &mut self,
+        blockhash: ShareBlockHash,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(share) = self.store.get_share(&blockhash) else {
+            return Err(format!("Share {} is not in the store", blockhash).into());
+        };
+        if !self.store.get_children_blockhashes(&blockhash).is_empty() {
+            return Err(format!(
+                "Share {} has children, only a leaf can be the chain tip",
+                blockhash
+            )
+            .into());
+        }
+        warn!(
+            "Forcibly setting chain tip from {:?} to {}",
+            self.chain_tip, blockhash
+        );
+        let old_tip = self.chain_tip;
+        self.total_difficulty = self.get_total_difficulty_upto(&blockhash);
+        self.chain_tip = Some(blockhash);
+        self.tips.insert(blockhash);
+        if self.genesis_block_hash.is_none() {
+            self.genesis_block_hash = Some(self.store.get_genesis_blockhash());
+        }
+        self.tip_changed_at = self.clock.seconds_since_epoch();
+        if let Some(old_tip) = old_tip.filter(|tip| *tip != blockhash) {
+            let branch_upto_prev = share
+                .header
+                .prev_share_blockhash
+                .map(|prev| self.store.get_chain_upto(&prev))
+                .unwrap_or_default();
+            let reorg = self.find_reorg(old_tip, blockhash, &branch_upto_prev);
+            self.record_reorg();
+            // Sending fails only when there are no subscribers
+            let _ = self.reorg_tx.send(reorg);
+        }
+        Ok(())
+    }
+
+    /// Close and reopen the store at the same path, then derive the chain tip again from the reopened store.
+    /// Tips the reopened store no longer has are dropped, and the remaining tip with the most work becomes the chain tip.
+    pub fn reopen_store(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.store.reopen()?;
+        self.tips.retain(|tip| self.store.get_share(tip).is_some());
+        let best = self
+            .tips
+            .iter()
+            .map(|tip| (self.get_total_difficulty_upto(tip), *tip))
+            .max_by(|(work_a, hash_a), (work_b, hash_b)| {
+                work_a
+                    .cmp(work_b)
+                    .then_with(|| hash_b.to_string().cmp(&hash_a.to_string()))
+            });
+        match best {
+            Some((total_difficulty, chain_tip)) => {
+                self.chain_tip = Some(chain_tip);
+                self.total_difficulty = total_difficulty;
+                self.genesis_block_hash = Some(self.store.get_genesis_blockhash());
+            }
+            None => {
+                self.chain_tip = None;
+                self.total_difficulty = dec!(0.0);
+                self.genesis_block_hash = None;
+            }
+        }
+        info!(
+            "Reopened store, chain tip is {:?} with work {}",
+            self.chain_tip, self.total_difficulty
+        );
+        Ok(())
+    }
+
+    /// Snapshot of the shares at the most recent `depth` heights, with their parent and uncle links
+    /// The snapshot ends at the highest tip, which can be a side branch tip above the main chain tip.
+    pub fn get_dag_snapshot(&self, depth: u32) -> DagSnapshot {
+        let top_height = self
+            .tips
+            .iter()
+            .filter_map(|tip| self.store.get_block_metadata(tip).and_then(|md| md.height))
+            .max();
+        let top_height = match top_height {
+            Some(top_height) if depth > 0 => top_height,
+            _ => {
+                return DagSnapshot {
+                    chain_tip: self.chain_tip,
+                    ..Default::default()
+                }
+            }
+        };
+        let bottom_height = top_height.saturating_sub(depth - 1);
+
+        let mut main_chain = HashSet::new();
+        let mut current = self.chain_tip;
+        while let Some(blockhash) = current {
+            let share = match self.store.get_share(&blockhash) {
+                Some(share) => share,
+                None => break,
+            };
+            let height = self
+                .store
+                .get_block_metadata(&blockhash)
+                .and_then(|md| md.height);
+            if height.is_some_and(|height| height < bottom_height) {
+                break;
+            }
+            main_chain.insert(blockhash);
+            current = share.header.prev_share_blockhash;
+        }
+
+        let mut shares = Vec::new();
+        for height in bottom_height..=top_height {
+            let mut at_height: Vec<(ShareBlockHash, ShareBlock)> = self
+                .store
+                .get_shares_at_height(height)
+                .into_iter()
+                .collect();
+            at_height.sort_by_key(|(blockhash, _)| blockhash.to_string());
+            shares.extend(at_height.into_iter().map(|(_, share)| (height, share)));
+        }
+        let in_snapshot: HashSet<ShareBlockHash> = shares
+            .iter()
+            .filter_map(|(_, share)| share.cached_blockhash)
+            .collect();
+
+        let mut snapshot = DagSnapshot {
+            chain_tip: self.chain_tip,
+            ..Default::default()
+        };
+        for (height, share) in shares {
+            let blockhash = share.cached_blockhash.unwrap();
+            if let Some(prev) = share.header.prev_share_blockhash {
+                if in_snapshot.contains(&prev) {
+                    snapshot.edges.push(DagEdge {
+                        from: blockhash,
+                        to: prev,
+                        kind: DagEdgeKind::Parent,
+                    });
+                }
+            }
+            for uncle in &share.header.uncles {
+                if in_snapshot.contains(uncle) {
+                    snapshot.edges.push(DagEdge {
+                        from: blockhash,
+                        to: *uncle,
+                        kind: DagEdgeKind::Uncle,
+                    });
+                }
+            }
+            snapshot.nodes.push(DagNode {
+                blockhash,
+                height,
+                miner_pubkey: share.header.miner_pubkey,
+                work: share.header.miner_share.diff,
+                main_chain: main_chain.contains(&blockhash),
+            });
+        }
+        snapshot
+    }
+
+    /// Check which blockhashes from the provided list are missing from the chain
+    /// Returns a vector of blockhashes that are not present in the chain
+    pub fn get_missing_blockhashes(&self, blockhashes: &[ShareBlockHash]) -> Vec<ShareBlockHash> {
+        self.store.get_missing_blockhashes(blockhashes)
+    }
+
+    /// Get the depth of a blockhash below the chain tip, the tip height less the share's height.
+    /// Shares off the main chain are measured by their height too, shares as high as the tip or higher have depth 0.
+    /// Returns None if there is no chain tip or the blockhash is not found in the chain
+    pub fn get_depth(&self, blockhash: &ShareBlockHash) -> Option<usize> {
+        let tip_height = self.get_tip_height()?;
+        let height = self.get_share_height(blockhash)?;
+        Some(tip_height.saturating_sub(height) as usize)
+    }
+
+    /// The path between two shares through their closest common ancestor, following parent links:
+    /// from `from` back to the ancestor, then forward to `to`, both ends included.
+    /// Returns None if either share is not found or the shares have no common ancestor.
+    pub fn get_path(
+        &self,
+        from: &ShareBlockHash,
+        to: &ShareBlockHash,
+    ) -> Option<Vec<ShareBlockHash>> {
+        let parent = |blockhash: &ShareBlockHash| {
+            self.store
+                .get_share(blockhash)
+                .and_then(|share| share.header.prev_share_blockhash)
+        };
+        let mut from_height = self.get_share_height(from)?;
+        let mut to_height = self.get_share_height(to)?;
+        let (mut from_side, mut to_side) = (*from, *to);
+        let mut backwards = vec![from_side];
+        let mut forwards = vec![to_side];
+
+        // Walk the higher share down to the height of the lower one, then both down until they meet
+        while from_height > to_height {
+            from_side = parent(&from_side)?;
+            backwards.push(from_side);
+            from_height -= 1;
+        }
+        while to_height > from_height {
+            to_side = parent(&to_side)?;
+            forwards.push(to_side);
+            to_height -= 1;
+        }
+        while from_side != to_side {
+            from_side = parent(&from_side)?;
+            to_side = parent(&to_side)?;
+            backwards.push(from_side);
+            forwards.push(to_side);
+        }
+
+        // The common ancestor ends both walks
+        forwards.pop();
+        backwards.extend(forwards.into_iter().rev());
+        Some(backwards)
+    }
+}
+
+/// Size of a share serialized as CBOR, as sent over the network
+fn serialized_size(share: &ShareBlock) -> usize {
+    let mut buf = Vec::new();
+    match ciborium::ser::into_writer(share, &mut buf) {
+        Ok(()) => buf.len(),
+        Err(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod chain_tests {
+    use super::*;
+    use crate::test_utils::random_hex_string;
+    use crate::test_utils::TestBlockBuilder;
+    use crate::utils::clock::MockClock;
+    use std::collections::HashSet;
+    use std::time::UNIX_EPOCH;
+    use tempfile::tempdir;
+
+    #[test_log::test(test)]
+    /// Setup a test chain with 3 shares on the main chain, where shares 2 and 3 have two uncles each
+    fn test_chain_add_shares() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        // Create initial share (1)
+        let share1 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .miner_pubkey("020202020202020202020202020202020202020202020202020202020202020202")
+            .build();
+
+        chain.add_share(share1.clone()).unwrap();
+
+        let mut expected_tips = HashSet::new();
+        expected_tips.insert(share1.cached_blockhash.unwrap());
+        assert_eq!(chain.tips, expected_tips);
+        assert_eq!(chain.total_difficulty, dec!(1.0));
+        assert_eq!(chain.chain_tip, Some(share1.cached_blockhash.unwrap()));
 
         // Create uncles for share2
         let uncle1_share2 = TestBlockBuilder::new()
@@ -593,6 +2267,46 @@ mod chain_tests {
         }
     }
 
+    #[test]
+    fn test_workbases_beyond_cap_are_evicted_unless_a_share_references_them() {
+        use crate::test_utils::TestMinerWorkbaseBuilder;
+
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store).with_max_workbases(3);
+        // The oldest workbase has a share mined on it
+        chain
+            .add_share(
+                TestBlockBuilder::new()
+                    .blockhash(format!("{:064x}", 1).as_str())
+                    .workinfoid(1)
+                    .build(),
+            )
+            .unwrap();
+
+        for workinfoid in 1..=5 {
+            let workbase = TestMinerWorkbaseBuilder::new()
+                .workinfoid(workinfoid)
+                .build();
+            assert_eq!(
+                chain.add_workbase(workbase).unwrap(),
+                WorkbaseOutcome::Added
+            );
+        }
+
+        // 2 and 3 were evicted oldest first, 1 is kept for the share mined on it
+        assert_eq!(chain.store.get_workinfoids(), vec![1, 4, 5]);
+        assert!(chain.store.get_workbase(2).is_none());
+        assert!(chain.store.get_workbase(1).is_some());
+        let listed: Vec<u64> = chain
+            .store
+            .list_workbases(&WorkbaseRange::Height(0..=u32::MAX), 0, 10)
+            .iter()
+            .map(|workbase| workbase.workinfoid)
+            .collect();
+        assert_eq!(listed, vec![1, 4, 5]);
+    }
+
     #[test]
     fn test_add_workbase() {
         use crate::shares::miner_message::CkPoolMessage;
@@ -621,9 +2335,26 @@ mod chain_tests {
 
         // Add workbase and verify it succeeds
         let result = chain.add_workbase(workbase.clone());
-        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), WorkbaseOutcome::Added);
 
         // Verify workbase was stored by checking it matches what we stored
+        assert_eq!(
+            chain.get_workbase(workbase.workinfoid),
+            Some(workbase.clone())
+        );
+
+        // An identical workbase is reported as existing
+        let result = chain.add_workbase(workbase.clone());
+        assert_eq!(result.unwrap(), WorkbaseOutcome::Existing);
+
+        // A different workbase with the same workinfoid is refused and the stored one kept
+        let mut conflicting = workbase.clone();
+        conflicting.coinb1 = format!("{}00", conflicting.coinb1);
+        let result = chain.add_workbase(conflicting);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Conflicting workbase"));
         assert_eq!(chain.get_workbase(workbase.workinfoid), Some(workbase));
     }
 
@@ -675,6 +2406,43 @@ mod chain_tests {
         assert_eq!(chain.get_depth(&non_existent_hash), None);
     }
 
+    #[test]
+    fn test_get_depth_measures_distance_below_tip_on_long_chain() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        let mut blockhashes = Vec::new();
+        let mut prev = None;
+        for i in 0..15 {
+            let mut builder = TestBlockBuilder::new()
+                .blockhash(&format!("{:064x}", i + 1))
+                .workinfoid(7452731920372203525 + i as u64);
+            if let Some(prev) = prev {
+                builder = builder.prev_share_blockhash(prev);
+            }
+            let share = builder.build();
+            prev = share.cached_blockhash;
+            blockhashes.push(share.cached_blockhash.unwrap());
+            chain.add_share(share).unwrap();
+        }
+
+        // Depth is counted from the tip, however long the chain below it
+        assert_eq!(chain.get_depth(&blockhashes[14]), Some(0));
+        assert_eq!(chain.get_depth(&blockhashes[13]), Some(1));
+        assert_eq!(chain.get_depth(&blockhashes[4]), Some(10));
+        assert_eq!(chain.get_depth(&blockhashes[0]), Some(14));
+
+        // An uncle off the main chain is measured by its height
+        let uncle = TestBlockBuilder::new()
+            .blockhash(&format!("{:064x}", 100))
+            .prev_share_blockhash(blockhashes[12])
+            .workinfoid(7452731920372203600)
+            .build();
+        chain.add_share(uncle.clone()).unwrap();
+        assert_eq!(chain.get_depth(&uncle.cached_blockhash.unwrap()), Some(1));
+    }
+
     #[test]
     fn test_get_headers_for_locator() {
         let temp_dir = tempdir().unwrap();
@@ -858,4 +2626,1650 @@ mod chain_tests {
         assert_eq!(locator[12], blocks[8].cached_blockhash.unwrap());
         assert_eq!(locator[13], blocks[0].cached_blockhash.unwrap());
     }
+
+    #[test]
+    fn test_side_branches_beyond_cap_drop_lowest_work() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store).with_max_side_branches(2);
+
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+        chain.add_share(genesis.clone()).unwrap();
+
+        let main_share = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 2).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .diff(dec!(10.0))
+            .build();
+        chain.add_share(main_share.clone()).unwrap();
+        let main_tip = main_share.cached_blockhash.unwrap();
+
+        // Four competing branches off genesis, each with less work than the main chain
+        let mut side_shares = Vec::new();
+        for i in 1..=4 {
+            let side_share = TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", 10 + i).as_str())
+                .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+                .diff(Decimal::from(i))
+                .build();
+            chain.add_share(side_share.clone()).unwrap();
+            side_shares.push(side_share);
+        }
+
+        let expected_tips: HashSet<ShareBlockHash> = [
+            main_tip,
+            side_shares[2].cached_blockhash.unwrap(),
+            side_shares[3].cached_blockhash.unwrap(),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(chain.tips, expected_tips);
+        assert_eq!(chain.chain_tip, Some(main_tip));
+        assert_eq!(chain.total_difficulty, dec!(11.0));
+
+        // Dropped branches are only forgotten as tips, the shares are still stored
+        assert!(chain
+            .get_share(&side_shares[0].cached_blockhash.unwrap())
+            .is_some());
+    }
+
+    #[test]
+    fn test_side_branches_forking_at_different_heights_compare_total_work() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store).with_max_side_branches(2);
+
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+        chain.add_share(genesis.clone()).unwrap();
+        let mut main_chain = vec![genesis];
+        for (i, diff) in [dec!(1.0), dec!(1.0), dec!(10.0)].into_iter().enumerate() {
+            let share = TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", 2 + i).as_str())
+                .prev_share_blockhash(main_chain.last().unwrap().cached_blockhash.unwrap())
+                .diff(diff)
+                .build();
+            chain.add_share(share.clone()).unwrap();
+            main_chain.push(share);
+        }
+
+        // Work of their own shares alone would drop the branch off the second main share,
+        // counting the main chain shares below where each forks off drops the branch off genesis.
+        let branch = |blockhash: u64, parent: &ShareBlock, diff: Decimal| {
+            TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", blockhash).as_str())
+                .prev_share_blockhash(parent.cached_blockhash.unwrap())
+                .diff(diff)
+                .build()
+        };
+        let off_genesis = branch(11, &main_chain[0], dec!(3.0));
+        let off_second = branch(12, &main_chain[2], dec!(2.0));
+        let off_first = branch(13, &main_chain[1], dec!(2.5));
+        chain.add_share(off_genesis.clone()).unwrap();
+        chain.add_share(off_second.clone()).unwrap();
+        chain.add_share(off_first.clone()).unwrap();
+
+        let expected_tips: HashSet<ShareBlockHash> = [
+            main_chain[3].cached_blockhash.unwrap(),
+            off_second.cached_blockhash.unwrap(),
+            off_first.cached_blockhash.unwrap(),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(chain.tips, expected_tips);
+    }
+
+    #[test]
+    fn test_conflicting_shares_from_one_miner_are_equivocation() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+        let mut equivocation_rx = chain.equivocation_sender().subscribe();
+
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+        chain.add_share(genesis.clone()).unwrap();
+
+        let miner = "020202020202020202020202020202020202020202020202020202020202020202";
+        let other_miner = "020202020202020202020202020202020202020202020202020202020202020203";
+        let first = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 2).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .miner_pubkey(miner)
+            .build();
+        let other_miners_share = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 3).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .miner_pubkey(other_miner)
+            .build();
+        chain.add_share(first.clone()).unwrap();
+        chain.add_share(other_miners_share).unwrap();
+        // Re-adding the same share is not a conflict
+        chain.add_share(first.clone()).unwrap();
+        assert!(equivocation_rx.try_recv().is_err());
+
+        let conflicting = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 4).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .miner_pubkey(miner)
+            .build();
+        chain.add_share(conflicting.clone()).unwrap();
+
+        assert_eq!(
+            equivocation_rx.try_recv().unwrap(),
+            Equivocation {
+                miner_pubkey: miner.parse().unwrap(),
+                height: 1,
+                prev_share_blockhash: genesis.cached_blockhash,
+                accepted: first.cached_blockhash.unwrap(),
+                conflicting: conflicting.cached_blockhash.unwrap(),
+            }
+        );
+        assert!(equivocation_rx.try_recv().is_err());
+        // The conflicting share is still stored
+        assert!(chain
+            .get_share(&conflicting.cached_blockhash.unwrap())
+            .is_some());
+    }
+
+    #[test]
+    fn test_frequent_reorgs_keep_tip_unstable_until_they_settle() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let start = 1_700_000_000;
+        let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(start));
+        let mut chain = Chain::new(store)
+            .with_stable_tip(60, 1, 20)
+            .with_clock(Arc::new(clock.clone()));
+        let set_now = |_: &mut Chain, secs: u64| {
+            clock.set_system_time(UNIX_EPOCH + Duration::from_secs(start + secs));
+        };
+        let share = |hash: u32, prev: u32, diff: Decimal| {
+            TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", hash).as_str())
+                .prev_share_blockhash(format!("{:064x}", prev).as_str().into())
+                .diff(diff)
+                .build()
+        };
+
+        assert!(!chain.is_tip_stable());
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+        chain.add_share(genesis).unwrap();
+        chain.add_share(share(2, 1, dec!(1.0))).unwrap();
+        // A new tip has to stay unchanged for a while first
+        assert!(!chain.is_tip_stable());
+        set_now(&mut chain, 20);
+        assert!(chain.is_tip_stable());
+
+        // One reorg is tolerated
+        chain.add_share(share(3, 1, dec!(2.0))).unwrap();
+        assert_eq!(chain.chain_tip, Some(format!("{:064x}", 3).as_str().into()));
+        assert!(!chain.is_tip_stable());
+        set_now(&mut chain, 40);
+        assert!(chain.is_tip_stable());
+
+        // The two branches keep overtaking each other
+        set_now(&mut chain, 50);
+        chain.add_share(share(4, 2, dec!(2.0))).unwrap();
+        assert!(!chain.is_tip_stable());
+        set_now(&mut chain, 60);
+        chain.add_share(share(5, 3, dec!(2.0))).unwrap();
+        assert_eq!(chain.get_chain_stats().recent_reorgs, 3);
+        assert!(!chain.is_tip_stable());
+
+        // Extending the tip doesn't count as a reorg, the reorgs at 50 and 60 are still in the window
+        set_now(&mut chain, 90);
+        chain.add_share(share(6, 5, dec!(1.0))).unwrap();
+        let stats = chain.get_chain_stats();
+        assert_eq!(stats.recent_reorgs, 2);
+        assert_eq!(stats.tip_unchanged_secs, 0);
+        assert!(!stats.tip_stable);
+
+        // The tip staying unchanged isn't enough while the reorgs are still in the window
+        set_now(&mut chain, 105);
+        assert_eq!(chain.get_chain_stats().recent_reorgs, 2);
+        assert!(!chain.is_tip_stable());
+
+        // Once the reorgs fall out of the window the tip is stable again
+        set_now(&mut chain, 121);
+        let stats = chain.get_chain_stats();
+        assert_eq!(
+            stats,
+            ChainStats {
+                chain_tip: Some(format!("{:064x}", 6).as_str().into()),
+                height: Some(3),
+                total_difficulty: chain.total_difficulty,
+                tip_unchanged_secs: 31,
+                recent_reorgs: 0,
+                tip_stable: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_path_through_fork() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+        let hash = |n: u32| ShareBlockHash::from(format!("{:064x}", n).as_str());
+        let share = |n: u32, prev: u32| {
+            TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", n).as_str())
+                .prev_share_blockhash(hash(prev))
+                .diff(dec!(1.0))
+                .build()
+        };
+
+        // 1 - 2 - 3 - 4
+        //      \
+        //       5 - 6
+        chain
+            .add_share(
+                TestBlockBuilder::new()
+                    .blockhash(format!("{:064x}", 1).as_str())
+                    .build(),
+            )
+            .unwrap();
+        for (n, prev) in [(2, 1), (3, 2), (4, 3), (5, 2), (6, 5)] {
+            chain.add_share(share(n, prev)).unwrap();
+        }
+
+        assert_eq!(
+            chain.get_path(&hash(4), &hash(6)),
+            Some(vec![hash(4), hash(3), hash(2), hash(5), hash(6)])
+        );
+        assert_eq!(
+            chain.get_path(&hash(6), &hash(3)),
+            Some(vec![hash(6), hash(5), hash(2), hash(3)])
+        );
+        // A share and its ancestor
+        assert_eq!(
+            chain.get_path(&hash(4), &hash(1)),
+            Some(vec![hash(4), hash(3), hash(2), hash(1)])
+        );
+        assert_eq!(
+            chain.get_path(&hash(1), &hash(3)),
+            Some(vec![hash(1), hash(2), hash(3)])
+        );
+        assert_eq!(chain.get_path(&hash(3), &hash(3)), Some(vec![hash(3)]));
+
+        // A share on a chain of its own shares no ancestor with the chain
+        chain
+            .store
+            .add_share(
+                TestBlockBuilder::new()
+                    .blockhash(format!("{:064x}", 7).as_str())
+                    .build(),
+                0,
+            )
+            .unwrap();
+        chain.store.add_share(share(8, 7), 1).unwrap();
+        assert_eq!(chain.get_path(&hash(4), &hash(8)), None);
+        assert_eq!(chain.get_path(&hash(4), &hash(9)), None);
+    }
+
+    #[test]
+    fn test_share_conflicting_with_checkpoint_is_rejected() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let hash = |n: u32| ShareBlockHash::from(format!("{:064x}", n).as_str());
+        let mut chain = Chain::new(store).with_checkpoints(vec![
+            Checkpoint {
+                height: 1,
+                blockhash: hash(2),
+            },
+            Checkpoint {
+                height: 3,
+                blockhash: hash(4),
+            },
+        ]);
+        let share = |n: u32, prev: u32, diff: Decimal| {
+            TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", n).as_str())
+                .prev_share_blockhash(hash(prev))
+                .diff(diff)
+                .build()
+        };
+
+        // 1 - 2 - 3 - 4 - 5, passing through both checkpoints
+        chain
+            .add_share(
+                TestBlockBuilder::new()
+                    .blockhash(format!("{:064x}", 1).as_str())
+                    .diff(dec!(1.0))
+                    .build(),
+            )
+            .unwrap();
+        for (n, prev) in [(2, 1), (3, 2), (4, 3), (5, 4)] {
+            chain.add_share(share(n, prev, dec!(1.0))).unwrap();
+        }
+        assert_eq!(chain.chain_tip, Some(hash(5)));
+
+        // A different share at a checkpoint height is rejected and not stored
+        assert!(chain.add_share(share(6, 3, dec!(1.0))).is_err());
+        assert!(chain.get_share(&hash(6)).is_none());
+        assert!(chain.add_share(share(7, 1, dec!(1.0))).is_err());
+        assert!(chain.get_share(&hash(7)).is_none());
+
+        // A branch forking below the highest checkpoint doesn't become the main chain, even with more work
+        chain.add_share(share(9, 2, dec!(10.0))).unwrap();
+        assert_eq!(chain.chain_tip, Some(hash(5)));
+        assert!(chain.get_share(&hash(9)).is_some());
+
+        // Building on the checkpoints still extends the chain
+        chain.add_share(share(10, 5, dec!(1.0))).unwrap();
+        assert_eq!(chain.chain_tip, Some(hash(10)));
+    }
+
+    #[test]
+    fn test_share_with_malformed_miner_address_is_rejected() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        let mut share = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .build();
+        share.transactions[0].output[0].script_pubkey =
+            bitcoin::ScriptBuf::new_op_return(b"not an address");
+        let error = chain.add_share(share.clone()).unwrap_err();
+        assert!(error.downcast_ref::<crate::shares::ShareError>().is_some());
+        assert!(chain.get_share(&share.cached_blockhash.unwrap()).is_none());
+        assert_eq!(chain.chain_tip, None);
+
+        let share = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .build();
+        chain.add_share(share).unwrap();
+        assert!(chain.chain_tip.is_some());
+    }
+
+    #[test]
+    fn test_drain_orphans_connects_orphans_whose_parent_arrived() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+        let hash = |n: u32| ShareBlockHash::from(format!("{:064x}", n).as_str());
+        let share = |n: u32, prev: u32| {
+            TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", n).as_str())
+                .prev_share_blockhash(hash(prev))
+                .build()
+        };
+
+        chain
+            .add_share(
+                TestBlockBuilder::new()
+                    .blockhash(format!("{:064x}", 1).as_str())
+                    .build(),
+            )
+            .unwrap();
+        chain.add_share(share(2, 1)).unwrap();
+        // 4 builds on the missing 3 and 5 builds on 4, 6 builds on 9 which never arrives
+        chain.add_share(share(4, 3)).unwrap();
+        chain.add_share(share(5, 4)).unwrap();
+        chain.add_share(share(6, 9)).unwrap();
+        assert_eq!(chain.get_share_height(&hash(4)), Some(0));
+        assert_eq!(chain.drain_orphans(), 0);
+        let summary = chain.get_orphan_summary();
+        assert_eq!((summary.count, summary.connectable), (3, 0));
+        assert_eq!(
+            summary.missing_parents.into_iter().collect::<HashSet<_>>(),
+            HashSet::from([hash(3), hash(9)])
+        );
+
+        // The missing parent is fetched out of band
+        chain.add_share(share(3, 2)).unwrap();
+        assert_eq!(chain.chain_tip, Some(hash(3)));
+        assert_eq!(chain.get_orphan_summary().connectable, 1);
+
+        assert_eq!(chain.drain_orphans(), 2);
+        assert_eq!(chain.get_share_height(&hash(4)), Some(3));
+        assert_eq!(chain.get_share_height(&hash(5)), Some(4));
+        assert_eq!(chain.chain_tip, Some(hash(5)));
+        assert_eq!(chain.get_tip_height(), Some(4));
+        let at_height_zero: HashSet<ShareBlockHash> =
+            chain.get_shares_at_height(0).into_keys().collect();
+        assert_eq!(at_height_zero, HashSet::from([hash(1), hash(6)]));
+        assert_eq!(chain.drain_orphans(), 0);
+        assert_eq!(
+            chain.get_orphan_summary(),
+            OrphanSummary {
+                count: 1,
+                connectable: 0,
+                missing_parents: vec![hash(9)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_ancestors_of_orphan_chain_is_deepest_missing_parent() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+        let hash = |n: u32| ShareBlockHash::from(format!("{:064x}", n).as_str());
+        let share = |n: u32, prev: u32| {
+            TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", n).as_str())
+                .prev_share_blockhash(hash(prev))
+                .build()
+        };
+
+        chain
+            .add_share(
+                TestBlockBuilder::new()
+                    .blockhash(format!("{:064x}", 1).as_str())
+                    .build(),
+            )
+            .unwrap();
+        chain.add_share(share(2, 1)).unwrap();
+        // 5 builds on 4 which builds on the missing 3
+        chain.add_share(share(4, 3)).unwrap();
+        chain.add_share(share(5, 4)).unwrap();
+
+        assert_eq!(chain.get_missing_ancestors(&hash(5)), vec![hash(3)]);
+        assert_eq!(chain.get_missing_ancestors(&hash(4)), vec![hash(3)]);
+        // Connected and unknown shares are missing nothing
+        assert!(chain.get_missing_ancestors(&hash(2)).is_empty());
+        assert!(chain.get_missing_ancestors(&hash(1)).is_empty());
+        assert!(chain.get_missing_ancestors(&hash(7)).is_empty());
+
+        // Once 3 arrives the orphans can be reconnected, nothing is missing
+        chain.add_share(share(3, 2)).unwrap();
+        assert!(chain.get_missing_ancestors(&hash(5)).is_empty());
+    }
+
+    #[test]
+    fn test_sync_multi_page_chain_between_chains() {
+        let server_dir = tempdir().unwrap();
+        let client_dir = tempdir().unwrap();
+        let mut server =
+            Chain::new(Store::new(server_dir.path().to_str().unwrap().to_string()).unwrap());
+        let mut client =
+            Chain::new(Store::new(client_dir.path().to_str().unwrap().to_string()).unwrap());
+        let hash = |n: u32| ShareBlockHash::from(format!("{:064x}", n).as_str());
+
+        // 1 - 2 - ... - 7 on the main chain, with a side share 8 at height 2
+        server
+            .add_share(
+                TestBlockBuilder::new()
+                    .blockhash(format!("{:064x}", 1).as_str())
+                    .build(),
+            )
+            .unwrap();
+        for (n, prev) in [(2, 1), (3, 2), (4, 3), (5, 4), (6, 5), (7, 6), (8, 2)] {
+            server
+                .add_share(
+                    TestBlockBuilder::new()
+                        .blockhash(format!("{:064x}", n).as_str())
+                        .prev_share_blockhash(hash(prev))
+                        .build(),
+                )
+                .unwrap();
+        }
+
+        // Pull pages of at most 3 shares until the server has no more
+        let mut cursor = ChainCursor {
+            height: 0,
+            offset: 0,
+        };
+        let mut pages = 0;
+        loop {
+            let page = server.get_chain_page(cursor, 3, usize::MAX);
+            assert!(!page.shares.is_empty() && page.shares.len() <= 3);
+            pages += 1;
+            for share in page.shares {
+                client.add_share(share).unwrap();
+            }
+            match page.next {
+                Some(next) => cursor = next,
+                None => break,
+            }
+        }
+        assert_eq!(pages, 3);
+        assert_eq!(client.chain_tip, Some(hash(7)));
+        assert_eq!(client.get_total_difficulty(), server.get_total_difficulty());
+        assert!(client.get_share(&hash(8)).is_some());
+
+        // The byte bound splits pages within a height, but each page still has a share
+        let page = server.get_chain_page(
+            ChainCursor {
+                height: 2,
+                offset: 0,
+            },
+            100,
+            1,
+        );
+        assert_eq!(page.shares.len(), 1);
+        assert_eq!(
+            page.next,
+            Some(ChainCursor {
+                height: 2,
+                offset: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_chain_page_from_locator_starts_above_the_fork_point() {
+        let server_dir = tempdir().unwrap();
+        let client_dir = tempdir().unwrap();
+        let mut server =
+            Chain::new(Store::new(server_dir.path().to_str().unwrap().to_string()).unwrap());
+        let mut client =
+            Chain::new(Store::new(client_dir.path().to_str().unwrap().to_string()).unwrap());
+        let hash = |n: u32| ShareBlockHash::from(format!("{:064x}", n).as_str());
+        let share = |n: u32, prev: u32| {
+            TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", n).as_str())
+                .prev_share_blockhash(hash(prev))
+                .build()
+        };
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+
+        // The server has 1 - 2 - 3 - 4 - 5 - 6, the client forked off at 2 with 1 - 2 - 9 - 10
+        server.add_share(genesis.clone()).unwrap();
+        for (n, prev) in [(2, 1), (3, 2), (4, 3), (5, 4), (6, 5)] {
+            server.add_share(share(n, prev)).unwrap();
+        }
+        client.add_share(genesis).unwrap();
+        for (n, prev) in [(2, 1), (9, 2), (10, 9)] {
+            client.add_share(share(n, prev)).unwrap();
+        }
+
+        // The page starts at the height above 2, not at the client's tip height
+        let page =
+            server.get_chain_page_from_locator(&client.build_locator().unwrap(), 100, usize::MAX);
+        let served: Vec<ShareBlockHash> = page
+            .shares
+            .iter()
+            .map(|share| share.cached_blockhash.unwrap())
+            .collect();
+        assert_eq!(served, vec![hash(3), hash(4), hash(5), hash(6)]);
+        assert_eq!(page.next, None);
+
+        // The heavier fork reorgs in on the client
+        for share in page.shares {
+            client.add_share(share).unwrap();
+        }
+        assert_eq!(client.chain_tip, Some(hash(6)));
+
+        // A locator sharing nothing with our main chain is served from genesis
+        let page = server.get_chain_page_from_locator(&[hash(9), hash(10)], 100, usize::MAX);
+        assert_eq!(page.shares.len(), 6);
+    }
+
+    #[test]
+    fn test_set_tip_to_stored_share() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+        let hash = |n: u32| ShareBlockHash::from(format!("{:064x}", n).as_str());
+        let share = |n: u32, prev: u32| {
+            TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", n).as_str())
+                .prev_share_blockhash(hash(prev))
+                .diff(dec!(1.0))
+                .build()
+        };
+
+        // 1 - 2 - 3 - 4
+        //      \
+        //       5
+        chain
+            .add_share(
+                TestBlockBuilder::new()
+                    .blockhash(format!("{:064x}", 1).as_str())
+                    .diff(dec!(1.0))
+                    .build(),
+            )
+            .unwrap();
+        for (n, prev) in [(2, 1), (3, 2), (4, 3), (5, 2)] {
+            chain.add_share(share(n, prev)).unwrap();
+        }
+        assert_eq!(chain.chain_tip, Some(hash(4)));
+        let mut reorg_rx = chain.reorg_sender().subscribe();
+
+        // The side share has less work, it only becomes the tip when forced
+        chain.set_tip(hash(5)).unwrap();
+        assert_eq!(chain.chain_tip, Some(hash(5)));
+        assert_eq!(chain.total_difficulty, dec!(3.0));
+        assert!(chain.tips.contains(&hash(5)));
+        let reorg = reorg_rx.try_recv().unwrap();
+        assert_eq!((reorg.old_tip, reorg.new_tip), (hash(4), hash(5)));
+        assert_eq!(reorg.orphaned, vec![hash(4), hash(3)]);
+        assert_eq!(reorg.promoted, vec![hash(5)]);
+
+        // A share that is not stored or is not a leaf is refused and the tip kept
+        assert!(chain.set_tip(hash(9)).is_err());
+        assert!(chain.set_tip(hash(3)).is_err());
+        assert_eq!(chain.chain_tip, Some(hash(5)));
+        assert!(!chain.tips.contains(&hash(3)));
+        assert!(reorg_rx.try_recv().is_err());
+
+        chain.set_tip(hash(4)).unwrap();
+        assert_eq!(chain.chain_tip, Some(hash(4)));
+        assert_eq!(chain.total_difficulty, dec!(4.0));
+        assert_eq!(reorg_rx.try_recv().unwrap().orphaned, vec![hash(5)]);
+    }
+
+    #[test]
+    fn test_reorg_deeper_than_limit_is_rejected() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store).with_max_reorg_depth(2);
+        let mut deep_reorg_rx = chain.deep_reorg_sender().subscribe();
+
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+        chain.add_share(genesis.clone()).unwrap();
+
+        // Main chain of three shares on top of genesis
+        let mut prev = genesis.cached_blockhash.unwrap();
+        for i in 2..=4 {
+            let share = TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", i).as_str())
+                .prev_share_blockhash(prev)
+                .diff(dec!(1.0))
+                .build();
+            chain.add_share(share.clone()).unwrap();
+            prev = share.cached_blockhash.unwrap();
+        }
+        let main_tip = prev;
+        assert_eq!(chain.chain_tip, Some(main_tip));
+
+        // A heavier branch forking off genesis would replace all three main chain shares
+        let deep_fork = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 10).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .diff(dec!(10.0))
+            .build();
+        chain.add_share(deep_fork.clone()).unwrap();
+
+        assert_eq!(chain.chain_tip, Some(main_tip));
+        assert_eq!(
+            deep_reorg_rx.try_recv().unwrap(),
+            DeepReorg {
+                chain_tip: main_tip,
+                rejected: deep_fork.cached_blockhash.unwrap(),
+                depth: 3,
+            }
+        );
+        // The share is kept as a side branch
+        assert!(chain.tips.contains(&deep_fork.cached_blockhash.unwrap()));
+
+        // A heavier branch forking two shares below the tip is within the limit
+        let shallow_fork = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 11).as_str())
+            .prev_share_blockhash(format!("{:064x}", 2).as_str().into())
+            .diff(dec!(20.0))
+            .build();
+        chain.add_share(shallow_fork.clone()).unwrap();
+        assert_eq!(chain.chain_tip, shallow_fork.cached_blockhash);
+        assert!(deep_reorg_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_share_meeting_network_target_is_reported_as_block_found() {
+        use crate::test_utils::valid_share_block;
+
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+        let mut block_found_rx = chain.block_found_sender().subscribe();
+
+        // A share whose workbase isn't stored can't solve a block
+        chain.add_share(TestBlockBuilder::new().build()).unwrap();
+        assert!(block_found_rx.try_recv().is_err());
+
+        // The test share has difficulty 31, the signet network difficulty in its workbase is 0.001
+        let (share, workbase, user_workbase, _) = valid_share_block();
+        chain.add_workbase(workbase.clone()).unwrap();
+        chain.add_user_workbase(user_workbase).unwrap();
+        chain.add_share(share.clone()).unwrap();
+
+        let candidate = block_found_rx.try_recv().unwrap();
+        assert_eq!(candidate.share_blockhash, share.cached_blockhash.unwrap());
+        assert_eq!(candidate.share, share);
+        assert_eq!(candidate.workbase, workbase);
+        assert!(candidate.coinbase.is_coinbase());
+        assert_eq!(candidate.block.txdata[0], candidate.coinbase);
+        assert!(candidate
+            .block
+            .header
+            .validate_pow(candidate.block.header.target())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_reorg_reports_orphaned_and_promoted_shares_and_affected_miners() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+        let mut reorg_rx = chain.reorg_sender().subscribe();
+
+        let miners = [
+            "020202020202020202020202020202020202020202020202020202020202020202",
+            "020202020202020202020202020202020202020202020202020202020202020203",
+            "020202020202020202020202020202020202020202020202020202020202020204",
+            "020202020202020202020202020202020202020202020202020202020202020205",
+        ];
+        let share = |i: u32, prev: Option<&ShareBlock>, miner: &str, diff: Decimal| {
+            let mut builder = TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", i).as_str())
+                .miner_pubkey(miner)
+                .diff(diff);
+            if let Some(prev) = prev {
+                builder = builder.prev_share_blockhash(prev.cached_blockhash.unwrap());
+            }
+            builder.build()
+        };
+        let genesis = share(1, None, miners[0], dec!(1.0));
+        let main1 = share(2, Some(&genesis), miners[1], dec!(1.0));
+        let main2 = share(3, Some(&main1), miners[2], dec!(1.0));
+        let side1 = share(4, Some(&genesis), miners[3], dec!(1.0));
+        let side2 = share(5, Some(&side1), miners[3], dec!(5.0));
+
+        // Extending the tip and adding a lighter side branch are not reorgs
+        for share in [&genesis, &main1, &main2, &side1] {
+            chain.add_share(share.clone()).unwrap();
+        }
+        assert_eq!(chain.chain_tip, main2.cached_blockhash);
+        assert!(reorg_rx.try_recv().is_err());
+
+        chain.add_share(side2.clone()).unwrap();
+        assert_eq!(chain.chain_tip, side2.cached_blockhash);
+
+        let reorg = reorg_rx.try_recv().unwrap();
+        assert_eq!(reorg.old_tip, main2.cached_blockhash.unwrap());
+        assert_eq!(reorg.new_tip, side2.cached_blockhash.unwrap());
+        assert_eq!(
+            reorg.orphaned,
+            vec![
+                main2.cached_blockhash.unwrap(),
+                main1.cached_blockhash.unwrap()
+            ]
+        );
+        assert_eq!(
+            reorg.promoted,
+            vec![
+                side2.cached_blockhash.unwrap(),
+                side1.cached_blockhash.unwrap()
+            ]
+        );
+        // The genesis miner's share stays on the main chain, so its credit doesn't change
+        let expected: HashSet<bitcoin::Address> = miners[1..]
+            .iter()
+            .map(|miner| {
+                bitcoin::Address::p2pkh(miner.parse::<PublicKey>().unwrap(), chain.network)
+            })
+            .collect();
+        assert_eq!(reorg.affected_miners, expected);
+        assert!(reorg_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_reopen_store_keeps_shares_and_chain_tip() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+        chain.add_share(genesis.clone()).unwrap();
+        let main_share = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 2).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .diff(dec!(10.0))
+            .build();
+        chain.add_share(main_share.clone()).unwrap();
+        let side_share = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 3).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .diff(dec!(1.0))
+            .build();
+        chain.add_share(side_share.clone()).unwrap();
+        let total_difficulty = chain.total_difficulty;
+
+        // Forget the in-memory tip, reopening derives it again from the store
+        chain.chain_tip = side_share.cached_blockhash;
+        chain.reopen_store().unwrap();
+
+        assert_eq!(chain.chain_tip, main_share.cached_blockhash);
+        assert_eq!(chain.total_difficulty, total_difficulty);
+        assert_eq!(chain.genesis_block_hash, genesis.cached_blockhash);
+        assert_eq!(chain.get_tip_height(), Some(1));
+        assert_eq!(
+            chain.get_share(&main_share.cached_blockhash.unwrap()),
+            Some(main_share.clone())
+        );
+        assert!(chain
+            .get_share(&side_share.cached_blockhash.unwrap())
+            .is_some());
+
+        // Writes keep working on the reopened store
+        let next_share = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 4).as_str())
+            .prev_share_blockhash(main_share.cached_blockhash.unwrap())
+            .build();
+        chain.add_share(next_share.clone()).unwrap();
+        assert_eq!(chain.chain_tip, next_share.cached_blockhash);
+    }
+
+    #[test]
+    fn test_get_dag_snapshot_over_fork() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+        let main1 = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 2).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .miner_pubkey("020202020202020202020202020202020202020202020202020202020202020202")
+            .diff(dec!(10.0))
+            .build();
+        let side1 = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 3).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .miner_pubkey("020202020202020202020202020202020202020202020202020202020202020203")
+            .diff(dec!(1.0))
+            .build();
+        let main2 = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 4).as_str())
+            .prev_share_blockhash(main1.cached_blockhash.unwrap())
+            .uncles(vec![side1.cached_blockhash.unwrap()])
+            .diff(dec!(10.0))
+            .build();
+        for share in [&genesis, &main1, &side1, &main2] {
+            chain.add_share(share.clone()).unwrap();
+        }
+        let [genesis_hash, main1_hash, side1_hash, main2_hash] =
+            [&genesis, &main1, &side1, &main2].map(|share| share.cached_blockhash.unwrap());
+
+        let snapshot = chain.get_dag_snapshot(3);
+        assert_eq!(snapshot.chain_tip, Some(main2_hash));
+        let nodes: Vec<(ShareBlockHash, u32, bool)> = snapshot
+            .nodes
+            .iter()
+            .map(|node| (node.blockhash, node.height, node.main_chain))
+            .collect();
+        // Nodes at the same height are ordered by blockhash
+        let mut expected_nodes = vec![
+            (genesis_hash, 0, true),
+            (main1_hash, 1, true),
+            (side1_hash, 1, false),
+            (main2_hash, 2, true),
+        ];
+        expected_nodes.sort_by_key(|(blockhash, height, _)| (*height, blockhash.to_string()));
+        assert_eq!(nodes, expected_nodes);
+        let side_node = snapshot
+            .nodes
+            .iter()
+            .find(|node| node.blockhash == side1_hash)
+            .unwrap();
+        assert_eq!(side_node.miner_pubkey, side1.header.miner_pubkey);
+        assert_eq!(side_node.work, dec!(1.0));
+        let edges: HashSet<(ShareBlockHash, ShareBlockHash, DagEdgeKind)> = snapshot
+            .edges
+            .iter()
+            .map(|edge| (edge.from, edge.to, edge.kind))
+            .collect();
+        assert_eq!(snapshot.edges.len(), 4);
+        assert_eq!(
+            edges,
+            [
+                (main1_hash, genesis_hash, DagEdgeKind::Parent),
+                (side1_hash, genesis_hash, DagEdgeKind::Parent),
+                (main2_hash, main1_hash, DagEdgeKind::Parent),
+                (main2_hash, side1_hash, DagEdgeKind::Uncle),
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        // A shallower snapshot leaves out genesis and the links to it
+        let snapshot = chain.get_dag_snapshot(2);
+        assert_eq!(snapshot.nodes.len(), 3);
+        assert_eq!(snapshot.edges.len(), 2);
+        assert!(snapshot.edges.iter().all(|edge| edge.from == main2_hash));
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let deserialized: DagSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, snapshot);
+
+        assert!(chain.get_dag_snapshot(0).nodes.is_empty());
+    }
+
+    #[test]
+    fn test_compute_payouts_with_pplns_and_equal_policies() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store).with_payout_policy(PayoutPolicy::Pplns, 2);
+
+        let miner1 = "020202020202020202020202020202020202020202020202020202020202020202";
+        let miner2 = "020202020202020202020202020202020202020202020202020202020202020203";
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .miner_pubkey(miner2)
+            .diff(dec!(100.0))
+            .build();
+        let main1 = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 2).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .miner_pubkey(miner1)
+            .diff(dec!(3.0))
+            .build();
+        let side1 = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 3).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .miner_pubkey(miner2)
+            .diff(dec!(1.0))
+            .build();
+        let main2 = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 4).as_str())
+            .prev_share_blockhash(main1.cached_blockhash.unwrap())
+            .miner_pubkey(miner2)
+            .diff(dec!(1.0))
+            .build();
+        for share in [&genesis, &main1, &side1, &main2] {
+            chain.add_share(share.clone()).unwrap();
+        }
+
+        // The window holds the two most recent main chain shares, not genesis or the side share
+        let window = chain.payout_window();
+        assert_eq!(
+            window
+                .iter()
+                .map(|share| share.cached_blockhash.unwrap())
+                .collect::<Vec<_>>(),
+            vec![
+                main2.cached_blockhash.unwrap(),
+                main1.cached_blockhash.unwrap()
+            ]
+        );
+
+        let address1 = bitcoin::Address::p2pkh(main1.header.miner_pubkey, bitcoin::Network::Signet);
+        let address2 = bitcoin::Address::p2pkh(main2.header.miner_pubkey, bitcoin::Network::Signet);
+
+        let payouts = chain.compute_payouts();
+        assert_eq!(payouts.len(), 2);
+        assert_eq!(payouts[&address1], 75_000_000);
+        assert_eq!(payouts[&address2], 25_000_000);
+
+        let chain = chain.with_payout_policy(PayoutPolicy::Equal, 2);
+        let payouts = chain.compute_payouts();
+        assert_eq!(payouts[&address1], 50_000_000);
+        assert_eq!(payouts[&address2], 50_000_000);
+    }
+
+    #[test]
+    fn test_payout_report_splits_reward_over_window_between_two_miners() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store).with_payout_policy(PayoutPolicy::Pplns, 10);
+
+        let miner1 = "020202020202020202020202020202020202020202020202020202020202020202";
+        let miner2 = "020202020202020202020202020202020202020202020202020202020202020203";
+        let mut prev = None;
+        for (n, (miner, diff)) in [
+            (miner2, dec!(100.0)),
+            (miner1, dec!(3.0)),
+            (miner2, dec!(1.0)),
+            (miner1, dec!(3.0)),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut builder = TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", n + 1).as_str())
+                .miner_pubkey(miner)
+                .diff(diff);
+            if let Some(prev) = prev {
+                builder = builder.prev_share_blockhash(prev);
+            }
+            let share = builder.build();
+            prev = share.cached_blockhash;
+            chain.add_share(share).unwrap();
+        }
+
+        // The window of three leaves out genesis, miner1 has weight 6 over two shares and miner2 1 over one
+        let report = chain.payout_report(3, 1000);
+        assert_eq!(report.policy, PayoutPolicy::Pplns);
+        assert_eq!(report.share_count, 3);
+        assert_eq!(report.miners.len(), 2);
+
+        let address1 = bitcoin::Address::p2pkh(
+            miner1.parse::<bitcoin::PublicKey>().unwrap(),
+            bitcoin::Network::Signet,
+        );
+        let address2 = bitcoin::Address::p2pkh(
+            miner2.parse::<bitcoin::PublicKey>().unwrap(),
+            bitcoin::Network::Signet,
+        );
+        assert_eq!(report.miners[0].address, address1);
+        assert_eq!(report.miners[0].share_count, 2);
+        assert_eq!(report.miners[0].weight, dec!(6.0));
+        assert_eq!(report.miners[1].address, address2);
+        assert_eq!(report.miners[1].share_count, 1);
+        assert_eq!(report.miners[1].weight, dec!(1.0));
+
+        // 6/7 and 1/7 of 1000 round down to 857 and 142, the satoshi left over goes to the heaviest miner
+        assert_eq!(report.miners[0].reward, 858);
+        assert_eq!(report.miners[1].reward, 142);
+        assert_eq!(
+            report.miners.iter().map(|miner| miner.reward).sum::<u64>(),
+            report.total_reward
+        );
+    }
+
+    #[test]
+    fn test_inclusion_proof_covers_payout_window_of_solved_share() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store).with_payout_policy(PayoutPolicy::Pplns, 3);
+
+        let miner1 = "020202020202020202020202020202020202020202020202020202020202020202";
+        let miner2 = "020202020202020202020202020202020202020202020202020202020202020203";
+        let mut shares: Vec<ShareBlock> = Vec::new();
+        for (i, (miner, diff)) in [
+            (miner1, dec!(1.0)),
+            (miner2, dec!(2.0)),
+            (miner1, dec!(3.0)),
+            (miner2, dec!(4.0)),
+            (miner1, dec!(5.0)),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut builder = TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", i + 1).as_str())
+                .miner_pubkey(miner)
+                .diff(diff);
+            if let Some(prev) = shares.last() {
+                builder = builder.prev_share_blockhash(prev.cached_blockhash.unwrap());
+            }
+            let share = builder.build();
+            chain.add_share(share.clone()).unwrap();
+            shares.push(share);
+        }
+
+        // The fourth share solved a block, its window is itself and the two shares before it
+        let solved = shares[3].cached_blockhash.unwrap();
+        let proof = chain.inclusion_proof(solved).unwrap();
+        assert_eq!(proof.block_hash, solved);
+        assert_eq!(proof.policy, PayoutPolicy::Pplns);
+        assert_eq!(
+            proof
+                .entries
+                .iter()
+                .map(|entry| (entry.blockhash, entry.diff, entry.cumulative_work))
+                .collect::<Vec<_>>(),
+            vec![
+                (solved, dec!(4.0), dec!(4.0)),
+                (shares[2].cached_blockhash.unwrap(), dec!(3.0), dec!(7.0)),
+                (shares[1].cached_blockhash.unwrap(), dec!(2.0), dec!(9.0)),
+            ]
+        );
+        assert_eq!(proof.entries[0].miner_pubkey, shares[3].header.miner_pubkey);
+        assert_eq!(proof.total_work(), dec!(9.0));
+
+        // An unsigned proof is rejected, a signed one checks out against the store
+        assert!(proof.verify().is_err());
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let mut proof = proof;
+        proof.sign(&keypair).unwrap();
+        assert_eq!(proof.signer(), Some(keypair.public()));
+        proof.verify().unwrap();
+        chain.verify_inclusion_proof(&proof).unwrap();
+
+        // The proof survives a serialization round trip for audit
+        let json = serde_json::to_string(&proof).unwrap();
+        assert_eq!(serde_json::from_str::<PayoutProof>(&json).unwrap(), proof);
+
+        let mut tampered = proof.clone();
+        tampered.entries[1].diff = dec!(30.0);
+        assert!(tampered.verify().is_err());
+
+        // Re-signing a proof does not make shares that differ from the store pass
+        let mut forged = proof.clone();
+        forged.entries[1].miner_pubkey = shares[3].header.miner_pubkey;
+        forged.sign(&keypair).unwrap();
+        forged.verify().unwrap();
+        assert!(chain.verify_inclusion_proof(&forged).is_err());
+
+        let mut truncated = proof.clone();
+        truncated.entries.pop();
+        truncated.sign(&keypair).unwrap();
+        assert!(chain.verify_inclusion_proof(&truncated).is_err());
+
+        assert!(chain
+            .inclusion_proof(format!("{:064x}", 99).as_str().into())
+            .is_none());
+    }
+
+    #[test]
+    fn test_estimate_hashrate_from_shares_in_window() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        let now: u64 = 1_735_000_000;
+        assert_eq!(chain.estimate_hashrate(Duration::from_secs(600), now), 0.0);
+
+        // The first share is older than the window and is not counted. The third has an ntime just before the
+        // window, later than its parent's, and is skipped without hiding the parent in the window.
+        let mut shares: Vec<ShareBlock> = Vec::new();
+        for (i, (age, diff)) in [
+            (1_000, dec!(100.0)),
+            (500, dec!(1.0)),
+            (620, dec!(10.0)),
+            (300, dec!(2.0)),
+            (100, dec!(3.0)),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut builder = TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", i + 1).as_str())
+                .diff(diff);
+            if let Some(prev) = shares.last() {
+                builder = builder.prev_share_blockhash(prev.cached_blockhash.unwrap());
+            }
+            let mut share = builder.build();
+            share.header.miner_share.ntime =
+                bitcoin::absolute::Time::from_consensus((now - age) as u32).unwrap();
+            chain.add_share(share.clone()).unwrap();
+            shares.push(share);
+        }
+
+        let expected = 6.0 * 2f64.powi(32) / 600.0;
+        let estimate = chain.estimate_hashrate(Duration::from_secs(600), now);
+        assert!((estimate - expected).abs() < 1e-6 * expected);
+
+        // A wider window takes in the old shares too
+        let expected = 116.0 * 2f64.powi(32) / 2_000.0;
+        let estimate = chain.estimate_hashrate(Duration::from_secs(2_000), now);
+        assert!((estimate - expected).abs() < 1e-6 * expected);
+    }
+
+    #[test]
+    fn test_share_histogram_counts_shares_per_time_bucket() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        let now: u64 = 1_735_000_050;
+        let bucket = Duration::from_secs(100);
+        assert_eq!(
+            chain.share_histogram(bucket, 3, now),
+            vec![(1_734_999_800, 0), (1_734_999_900, 0), (1_735_000_000, 0)]
+        );
+
+        // Shares before the first bucket and after the last are not counted, the middle bucket stays empty
+        let mut shares: Vec<ShareBlock> = Vec::new();
+        for (i, ntime) in [
+            1_734_999_700u32,
+            1_734_999_850,
+            1_734_999_899,
+            1_735_000_000,
+            1_735_000_050,
+            1_735_000_200,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut builder = TestBlockBuilder::new().blockhash(format!("{:064x}", i + 1).as_str());
+            if let Some(prev) = shares.last() {
+                builder = builder.prev_share_blockhash(prev.cached_blockhash.unwrap());
+            }
+            let mut share = builder.build();
+            share.header.miner_share.ntime =
+                bitcoin::absolute::Time::from_consensus(ntime).unwrap();
+            chain.add_share(share.clone()).unwrap();
+            shares.push(share);
+        }
+
+        assert_eq!(
+            chain.share_histogram(bucket, 3, now),
+            vec![(1_734_999_800, 2), (1_734_999_900, 0), (1_735_000_000, 2)]
+        );
+        // A single wide bucket holds all shares in the current bucket, including one with an ntime after now
+        assert_eq!(
+            chain.share_histogram(Duration::from_secs(1_000), 1, now),
+            vec![(1_735_000_000, 3)]
+        );
+    }
+
+    #[test]
+    fn test_difficulty_retargets_to_shares_arriving_fast_or_slow() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store).with_retargeting(4, 10);
+
+        let start: u32 = 1_735_000_000;
+        let share = |n: u64, prev: Option<&ShareBlock>, ntime: u32, diff: Decimal| {
+            let mut builder = TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", n).as_str())
+                .diff(diff);
+            if let Some(prev) = prev {
+                builder = builder.prev_share_blockhash(prev.cached_blockhash.unwrap());
+            }
+            let mut share = builder.build();
+            share.header.miner_share.ntime =
+                bitcoin::absolute::Time::from_consensus(ntime).unwrap();
+            share
+        };
+
+        // Until the window fills there is nothing to retarget from, any difficulty is accepted
+        let mut shares: Vec<ShareBlock> = Vec::new();
+        for n in 0..5u32 {
+            let next = share(n as u64 + 1, shares.last(), start + n * 5, dec!(1.0));
+            assert_eq!(
+                chain.expected_difficulty(next.header.prev_share_blockhash),
+                None
+            );
+            chain.add_share(next.clone()).unwrap();
+            shares.push(next);
+        }
+
+        // Shares arrived every 5 seconds, twice as fast as the 10 second target, so difficulty doubles
+        let tip = shares.last().unwrap().clone();
+        assert_eq!(
+            chain.expected_difficulty(tip.cached_blockhash),
+            Some(dec!(2.0))
+        );
+        let unchanged = share(100, Some(&tip), start + 25, dec!(1.0));
+        assert!(chain.add_share(unchanged).is_err());
+        let retargeted = share(6, Some(&tip), start + 25, dec!(2.05));
+        chain.add_share(retargeted.clone()).unwrap();
+        assert_eq!(chain.chain_tip, retargeted.cached_blockhash);
+        shares.push(retargeted);
+
+        // Shares then arrive every 40 seconds, slower than the target, so difficulty comes down
+        for n in 7..=10u32 {
+            let prev = shares.last().unwrap();
+            let expected = chain.expected_difficulty(prev.cached_blockhash).unwrap();
+            let next = share(n as u64, Some(prev), start + 25 + (n - 6) * 40, expected);
+            chain.add_share(next.clone()).unwrap();
+            shares.push(next);
+        }
+        let tip = shares.last().unwrap();
+        let expected = chain.expected_difficulty(tip.cached_blockhash).unwrap();
+        assert!(expected < dec!(1.0));
+        let too_hard = share(200, Some(tip), start + 225, dec!(2.0));
+        assert!(chain.add_share(too_hard).is_err());
+    }
+
+    #[test]
+    fn test_load_snapshot_replaces_chain_only_when_heavier() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        let share = |n: u64, prev: Option<&ShareBlock>, diff: Decimal| {
+            let mut builder = TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", n).as_str())
+                .diff(diff);
+            if let Some(prev) = prev {
+                builder = builder.prev_share_blockhash(prev.cached_blockhash.unwrap());
+            }
+            builder.build()
+        };
+        let genesis = share(1, None, dec!(1.0));
+        let current = share(2, Some(&genesis), dec!(3.0));
+        chain.add_share(genesis.clone()).unwrap();
+        chain.add_share(current.clone()).unwrap();
+
+        // A snapshot with the same work as the chain is rejected
+        let equal = ChainSnapshot {
+            shares: vec![genesis.clone(), share(3, Some(&genesis), dec!(3.0))],
+        };
+        assert_eq!(
+            chain.load_snapshot(equal),
+            Err(SnapshotError::NotHeavier {
+                snapshot: dec!(4.0),
+                current: dec!(4.0)
+            })
+        );
+
+        let other_genesis = share(4, None, dec!(10.0));
+        let other_chain = ChainSnapshot {
+            shares: vec![other_genesis],
+        };
+        assert!(matches!(
+            chain.load_snapshot(other_chain),
+            Err(SnapshotError::Invalid(_))
+        ));
+        assert_eq!(chain.chain_tip, current.cached_blockhash);
+
+        let heavier1 = share(5, Some(&genesis), dec!(2.0));
+        let heavier2 = share(6, Some(&heavier1), dec!(2.0));
+        let heavier = ChainSnapshot {
+            shares: vec![genesis.clone(), heavier1.clone(), heavier2.clone()],
+        };
+        chain.load_snapshot(heavier).unwrap();
+        assert_eq!(chain.chain_tip, heavier2.cached_blockhash);
+        assert_eq!(chain.total_difficulty, dec!(5.0));
+        assert_eq!(
+            chain.tips,
+            HashSet::from([heavier2.cached_blockhash.unwrap()])
+        );
+        assert_eq!(chain.get_tip_height(), Some(2));
+        assert_eq!(
+            chain.get_share(&heavier1.cached_blockhash.unwrap()),
+            Some(heavier1)
+        );
+    }
+
+    #[test]
+    fn test_gossiped_share_records_source_peer() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+        chain.add_share(genesis.clone()).unwrap();
+        let gossiped = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 2).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .build();
+        let local = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 3).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .build();
+
+        let source = libp2p::PeerId::random();
+        chain
+            .add_share_with_provenance(gossiped.clone(), ShareProvenance::Peer(source))
+            .unwrap();
+        // A later delivery by another peer keeps the first source
+        chain
+            .add_share_with_provenance(
+                gossiped.clone(),
+                ShareProvenance::Peer(libp2p::PeerId::random()),
+            )
+            .unwrap();
+        chain
+            .add_share_with_provenance(local.clone(), ShareProvenance::Local)
+            .unwrap();
+
+        assert_eq!(
+            chain.get_share_provenance(&gossiped.cached_blockhash.unwrap()),
+            Some(ShareProvenance::Peer(source))
+        );
+        assert_eq!(
+            chain.get_share_provenance(&local.cached_blockhash.unwrap()),
+            Some(ShareProvenance::Local)
+        );
+        assert_eq!(
+            chain.get_share_provenance(&genesis.cached_blockhash.unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_get_share_status_for_each_state() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store).with_max_side_branches(1);
+
+        let share = |i: u64, prev: &ShareBlock| {
+            TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", i).as_str())
+                .prev_share_blockhash(prev.cached_blockhash.unwrap())
+        };
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+        // Main chain genesis <- 2 <- 3 <- 4, with 5 an uncle of 4, side branch genesis <- 6 <- 7
+        // and side branch genesis <- 8, dropped once 7 makes 6 <- 7 the heavier side branch
+        let share2 = share(2, &genesis).build();
+        let share3 = share(3, &share2).build();
+        let share5 = share(5, &share2).build();
+        let share4 = share(4, &share3)
+            .uncles(vec![share5.cached_blockhash.unwrap()])
+            .build();
+        let share6 = share(6, &genesis).build();
+        let share8 = share(8, &genesis).build();
+        let share7 = share(7, &share6).build();
+        for share in [
+            &genesis, &share2, &share3, &share5, &share4, &share6, &share8, &share7,
+        ] {
+            chain.add_share(share.clone()).unwrap();
+        }
+        assert_eq!(chain.chain_tip, share4.cached_blockhash);
+        assert!(!chain.tips.contains(&share8.cached_blockhash.unwrap()));
+
+        let status = |share: &ShareBlock| chain.get_share_status(&share.cached_blockhash.unwrap());
+        assert_eq!(status(&genesis), ShareStatus::MainChain);
+        assert_eq!(status(&share2), ShareStatus::MainChain);
+        assert_eq!(status(&share4), ShareStatus::MainChain);
+        assert_eq!(status(&share5), ShareStatus::Uncle);
+        assert_eq!(status(&share6), ShareStatus::SideBranch);
+        assert_eq!(status(&share7), ShareStatus::SideBranch);
+        assert_eq!(status(&share8), ShareStatus::Orphan);
+        assert_eq!(
+            chain.get_share_status(&format!("{:064x}", 9).as_str().into()),
+            ShareStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn test_purge_peer_shares_keeps_shares_the_chain_depends_on() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        let banned = libp2p::PeerId::random();
+        let other = libp2p::PeerId::random();
+        let share = |i: u64, prev: &ShareBlock| {
+            TestBlockBuilder::new()
+                .blockhash(format!("{:064x}", i).as_str())
+                .prev_share_blockhash(prev.cached_blockhash.unwrap())
+        };
+
+        let genesis = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 1).as_str())
+            .build();
+        chain.add_share(genesis.clone()).unwrap();
+        // Main chain genesis <- 2 <- 3 <- 4 <- 8, with 7 an uncle of 8 and a side branch 2 <- 5 <- 6
+        let share2 = share(2, &genesis).build();
+        let share3 = share(3, &share2).build();
+        let share4 = share(4, &share3).build();
+        let share5 = share(5, &share2).build();
+        let share6 = share(6, &share5).build();
+        let share7 = share(7, &share3).build();
+        let share8 = share(8, &share4)
+            .uncles(vec![share7.cached_blockhash.unwrap()])
+            .build();
+        for (share, provenance) in [
+            (&share2, ShareProvenance::Peer(banned)),
+            (&share3, ShareProvenance::Peer(other)),
+            (&share4, ShareProvenance::Local),
+            (&share5, ShareProvenance::Peer(banned)),
+            (&share6, ShareProvenance::Peer(banned)),
+            (&share7, ShareProvenance::Peer(banned)),
+            (&share8, ShareProvenance::Local),
+        ] {
+            chain
+                .add_share_with_provenance(share.clone(), provenance)
+                .unwrap();
+        }
+        assert_eq!(chain.chain_tip, share8.cached_blockhash);
+
+        let report = chain.purge_peer_shares(banned);
+
+        // The side branch is removed, the main chain share and the uncle are kept
+        assert_eq!(
+            report.removed,
+            vec![
+                share6.cached_blockhash.unwrap(),
+                share5.cached_blockhash.unwrap()
+            ]
+        );
+        assert_eq!(
+            report.kept,
+            vec![
+                share7.cached_blockhash.unwrap(),
+                share2.cached_blockhash.unwrap()
+            ]
+        );
+        assert!(chain.get_share(&share5.cached_blockhash.unwrap()).is_none());
+        assert!(chain.get_share(&share6.cached_blockhash.unwrap()).is_none());
+        assert!(chain.get_share(&share2.cached_blockhash.unwrap()).is_some());
+        assert!(chain.get_share(&share3.cached_blockhash.unwrap()).is_some());
+        assert_eq!(
+            chain.store.get_blockhashes_for_height(2),
+            vec![share3.cached_blockhash.unwrap()]
+        );
+        assert_eq!(
+            chain
+                .store
+                .get_children_blockhashes(&share2.cached_blockhash.unwrap()),
+            vec![share3.cached_blockhash.unwrap()]
+        );
+        assert!(!chain.tips.contains(&share5.cached_blockhash.unwrap()));
+        assert!(!chain.tips.contains(&share6.cached_blockhash.unwrap()));
+        assert_eq!(chain.chain_tip, share8.cached_blockhash);
+
+        // Shares from other sources are untouched
+        assert!(chain.purge_peer_shares(other).removed.is_empty());
+        assert_eq!(
+            chain.get_share_provenance(&share3.cached_blockhash.unwrap()),
+            Some(ShareProvenance::Peer(other))
+        );
+    }
+
+    #[test]
+    fn test_prune_to_disk_usage_deletes_oldest_shares() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store).with_payout_policy(PayoutPolicy::Pplns, 10);
+
+        let mut shares = Vec::new();
+        let mut prev_share_blockhash = None;
+        for i in 0..300 {
+            let mut builder = TestBlockBuilder::new().blockhash(format!("{:064x}", i + 1).as_str());
+            if let Some(prev) = prev_share_blockhash {
+                builder = builder.prev_share_blockhash(prev);
+            }
+            let share = builder.build();
+            prev_share_blockhash = share.cached_blockhash;
+            chain.add_share(share.clone()).unwrap();
+            shares.push(share);
+        }
+        let full = chain.store.disk_usage_bytes();
+        assert!(full > 0);
+
+        // Under the threshold nothing is pruned
+        let report = chain.prune_to_disk_usage(full, full / 2);
+        assert_eq!(report.shares_pruned, 0);
+        assert!(chain
+            .get_share(&shares[0].cached_blockhash.unwrap())
+            .is_some());
+
+        let low_water = full * 3 / 4;
+        let report = chain.prune_to_disk_usage(full - 1, low_water);
+        assert!(report.shares_pruned > 0);
+        assert!(report.bytes_after < report.bytes_before);
+        assert!(report.bytes_after <= low_water);
+        assert!(chain.store.disk_usage_bytes() <= low_water);
+
+        // The oldest shares are gone, the heights kept are the ones after them
+        let pruned = report.shares_pruned;
+        assert!(chain
+            .get_share(&shares[0].cached_blockhash.unwrap())
+            .is_none());
+        assert!(chain
+            .get_share(&shares[pruned - 1].cached_blockhash.unwrap())
+            .is_none());
+        assert!(chain
+            .get_share(&shares[pruned].cached_blockhash.unwrap())
+            .is_some());
+        assert_eq!(chain.store.pruned_below(), pruned as u32);
+        assert_eq!(
+            chain.store.get_genesis_blockhash(),
+            shares[pruned].cached_blockhash.unwrap()
+        );
+        assert_eq!(chain.get_tip_height(), Some(299));
+
+        // Confirmation depth is never pruned, even when the store stays over the low water mark
+        let report = chain.prune_to_disk_usage(0, 0);
+        assert_eq!(chain.store.pruned_below(), 200);
+        assert_eq!(pruned + report.shares_pruned, 200);
+        assert!(chain
+            .get_share(&shares[200].cached_blockhash.unwrap())
+            .is_some());
+        let pruned_work: Decimal = shares[..200]
+            .iter()
+            .map(|share| share.header.miner_share.diff)
+            .sum();
+        assert_eq!(chain.store.pruned_work(), pruned_work);
+
+        // A share extending the tip still moves it after the pruning
+        let total_difficulty = chain.total_difficulty;
+        let next = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 301).as_str())
+            .prev_share_blockhash(shares[299].cached_blockhash.unwrap())
+            .build();
+        chain.add_share(next.clone()).unwrap();
+        assert_eq!(chain.chain_tip, next.cached_blockhash);
+        assert_eq!(chain.get_tip_height(), Some(300));
+        assert_eq!(
+            chain.total_difficulty,
+            total_difficulty + next.header.miner_share.diff
+        );
+    }
+
+    #[test]
+    fn test_compact_store_after_deletions_keeps_serving_shares() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let mut chain = Chain::new(store);
+
+        let mut shares = Vec::new();
+        let mut prev_share_blockhash = None;
+        for i in 0..100 {
+            let mut builder = TestBlockBuilder::new().blockhash(format!("{:064x}", i + 1).as_str());
+            if let Some(prev) = prev_share_blockhash {
+                builder = builder.prev_share_blockhash(prev);
+            }
+            let share = builder.build();
+            prev_share_blockhash = share.cached_blockhash;
+            chain.add_share(share.clone()).unwrap();
+            shares.push(share);
+        }
+        let deleted = chain.store.prune_heights(0..40).unwrap();
+        assert_eq!(deleted.len(), 40);
+
+        let (bytes_before, bytes_after) = chain.compact_store();
+        assert!(bytes_after <= bytes_before);
+
+        for share in &shares[..40] {
+            assert!(chain.get_share(&share.cached_blockhash.unwrap()).is_none());
+        }
+        for share in &shares[40..] {
+            assert_eq!(
+                chain.get_share(&share.cached_blockhash.unwrap()),
+                Some(share.clone())
+            );
+        }
+        assert_eq!(chain.chain_tip, shares[99].cached_blockhash);
+        assert_eq!(chain.get_tip_height(), Some(99));
+    }
 }
@@ -0,0 +1,55 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+use crate::shares::ShareBlockHash;
+use bitcoin::PublicKey;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// A share in a DAG snapshot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DagNode {
+    pub blockhash: ShareBlockHash,
+    pub height: u32,
+    pub miner_pubkey: PublicKey,
+    /// Difficulty of the share, the work it adds to its chain
+    pub work: Decimal,
+    /// True if the share is on the main chain, false if it is on a side branch
+    pub main_chain: bool,
+}
+
+/// How a share refers to an earlier share
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DagEdgeKind {
+    Parent,
+    Uncle,
+}
+
+/// A link from a share to the earlier share it builds on or includes as an uncle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DagEdge {
+    pub from: ShareBlockHash,
+    pub to: ShareBlockHash,
+    pub kind: DagEdgeKind,
+}
+
+/// The shares at the most recent heights of the chain, including side branches, and the links between them.
+/// Nodes are ordered by height and then blockhash. Links to shares below the snapshot are left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DagSnapshot {
+    pub chain_tip: Option<ShareBlockHash>,
+    pub nodes: Vec<DagNode>,
+    pub edges: Vec<DagEdge>,
+}
@@ -16,3 +16,11 @@
 
 pub mod actor;
 mod chain;
+pub mod dag;
+pub mod payout;
+pub mod snapshot;
+
+pub use chain::{
+    BlockCandidate, ChainCursor, ChainPage, ChainStats, Checkpoint, DeepReorg, Equivocation,
+    OrphanSummary, PruneReport, PurgeReport, Reorg, ShareStatus,
+};
@@ -0,0 +1,332 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+use crate::shares::{ShareBlock, ShareBlockHash};
+use libp2p::identity::{Keypair, PublicKey};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Payouts are the parts of PAYOUT_SCALE each miner earns, i.e. satoshis per bitcoin of reward
+pub const PAYOUT_SCALE: u64 = 100_000_000;
+
+/// Default number of main chain shares, counting back from the tip, that share a reward
+pub const DEFAULT_PAYOUT_WINDOW: usize = 1000;
+
+/// How the shares in the payout window are weighted when splitting a reward between miners
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayoutPolicy {
+    /// Pay per last N shares, each share weighted by its difficulty
+    #[default]
+    Pplns,
+    /// Each share in the window counts the same, whatever its difficulty
+    Equal,
+}
+
+impl PayoutPolicy {
+    /// The weight a single share adds to its miner
+    pub fn share_weight(&self, share: &ShareBlock) -> Decimal {
+        match self {
+            PayoutPolicy::Pplns => share.header.miner_share.diff,
+            PayoutPolicy::Equal => Decimal::ONE,
+        }
+    }
+
+    /// Total weight of each miner pubkey over the shares in the window
+    pub fn miner_weights(&self, window: &[ShareBlock]) -> HashMap<bitcoin::PublicKey, Decimal> {
+        let mut weights = HashMap::new();
+        for share in window {
+            *weights
+                .entry(share.header.miner_pubkey)
+                .or_insert(Decimal::ZERO) += self.share_weight(share);
+        }
+        weights
+    }
+}
+
+/// A share in a payout proof, with the work of the window shares up to and including it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PayoutProofEntry {
+    pub blockhash: ShareBlockHash,
+    pub miner_pubkey: bitcoin::PublicKey,
+    pub diff: Decimal,
+    pub cumulative_work: Decimal,
+}
+
+/// The shares the reward for a solved block is split over, starting from the share that solved it and
+/// going back along its parents. Auditors can recompute each miner's part from the entries and policy.
+/// The node that built the proof signs it with its identity key, so it can be checked after it is passed on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PayoutProof {
+    pub block_hash: ShareBlockHash,
+    pub policy: PayoutPolicy,
+    pub entries: Vec<PayoutProofEntry>,
+    /// Protobuf encoded public key of the node that signed the proof, empty until signed
+    #[serde(default)]
+    pub signer: Vec<u8>,
+    /// Signature over the block hash, policy and entries, empty until signed
+    #[serde(default)]
+    pub signature: Vec<u8>,
+}
+
+impl PayoutProof {
+    /// Build the proof for the window of shares and their blockhashes, ordered from the share that solved the block
+    pub fn new(
+        block_hash: ShareBlockHash,
+        policy: PayoutPolicy,
+        window: &[(ShareBlockHash, ShareBlock)],
+    ) -> Self {
+        let mut cumulative_work = Decimal::ZERO;
+        let entries = window
+            .iter()
+            .map(|(blockhash, share)| {
+                cumulative_work += share.header.miner_share.diff;
+                PayoutProofEntry {
+                    blockhash: *blockhash,
+                    miner_pubkey: share.header.miner_pubkey,
+                    diff: share.header.miner_share.diff,
+                    cumulative_work,
+                }
+            })
+            .collect();
+        Self {
+            block_hash,
+            policy,
+            entries,
+            signer: Vec::new(),
+            signature: Vec::new(),
+        }
+    }
+
+    /// CBOR encoding of the signed fields
+    fn signed_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(&(&self.block_hash, &self.policy, &self.entries), &mut buf)
+            .map_err(|e| format!("failed to encode proof: {e}"))?;
+        Ok(buf)
+    }
+
+    /// Sign the proof with the node's identity keypair
+    pub fn sign(&mut self, keypair: &Keypair) -> Result<(), String> {
+        self.signature = keypair
+            .sign(&self.signed_bytes()?)
+            .map_err(|e| format!("failed to sign proof: {e}"))?;
+        self.signer = keypair.public().encode_protobuf();
+        Ok(())
+    }
+
+    /// The public key of the node that signed the proof, None if it is unsigned or the key does not decode
+    pub fn signer(&self) -> Option<PublicKey> {
+        PublicKey::try_decode_protobuf(&self.signer).ok()
+    }
+
+    /// Total work of the shares in the window
+    pub fn total_work(&self) -> Decimal {
+        self.entries
+            .last()
+            .map_or(Decimal::ZERO, |entry| entry.cumulative_work)
+    }
+
+    /// Check the proof is signed by its signer, starts at the solved block and each cumulative work adds the
+    /// entry's difficulty. The chain checks the entries against its own shares with verify_inclusion_proof.
+    pub fn verify(&self) -> Result<(), String> {
+        let signer = self
+            .signer()
+            .ok_or_else(|| format!("proof for block {} is not signed", self.block_hash))?;
+        if !signer.verify(&self.signed_bytes()?, &self.signature) {
+            return Err(format!(
+                "invalid signature on proof for block {}",
+                self.block_hash
+            ));
+        }
+        match self.entries.first() {
+            Some(first) if first.blockhash == self.block_hash => {}
+            _ => return Err(format!("proof does not start at block {}", self.block_hash)),
+        }
+        let mut cumulative_work = Decimal::ZERO;
+        for entry in &self.entries {
+            cumulative_work += entry.diff;
+            if entry.cumulative_work != cumulative_work {
+                return Err(format!(
+                    "cumulative work {} of share {} should be {}",
+                    entry.cumulative_work, entry.blockhash, cumulative_work
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A miner's share count, weight and reward in a payout report
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinerPayout {
+    pub address: bitcoin::Address,
+    pub share_count: usize,
+    pub weight: Decimal,
+    /// Reward in satoshis
+    pub reward: u64,
+}
+
+/// How a block reward is split between the miners of a window of shares, for a payout engine to pay out
+/// Miners are ordered by weight, heaviest first. Rewards are rounded down and the satoshis left over go to
+/// the heaviest miner, so the rewards always add up to the total reward.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PayoutReport {
+    pub policy: PayoutPolicy,
+    /// Reward split between the miners, in satoshis
+    pub total_reward: u64,
+    /// Number of shares in the window
+    pub share_count: usize,
+    pub miners: Vec<MinerPayout>,
+}
+
+impl PayoutReport {
+    /// Split total_reward between the miners of the shares in the window under the policy
+    pub fn new(
+        policy: PayoutPolicy,
+        window: &[ShareBlock],
+        network: bitcoin::Network,
+        total_reward: u64,
+    ) -> Self {
+        let mut by_miner: HashMap<bitcoin::PublicKey, (usize, Decimal)> = HashMap::new();
+        for share in window {
+            let (share_count, weight) = by_miner
+                .entry(share.header.miner_pubkey)
+                .or_insert((0, Decimal::ZERO));
+            *share_count += 1;
+            *weight += policy.share_weight(share);
+        }
+        let total_weight: Decimal = by_miner.values().map(|(_, weight)| *weight).sum();
+
+        let mut miners: Vec<MinerPayout> = by_miner
+            .into_iter()
+            .map(|(miner_pubkey, (share_count, weight))| {
+                let reward = if total_weight.is_zero() {
+                    0
+                } else {
+                    (weight * Decimal::from(total_reward) / total_weight)
+                        .floor()
+                        .to_u64()
+                        .unwrap_or(0)
+                };
+                MinerPayout {
+                    address: bitcoin::Address::p2pkh(miner_pubkey, network),
+                    share_count,
+                    weight,
+                    reward,
+                }
+            })
+            .collect();
+        miners.sort_by(|a, b| {
+            b.weight
+                .cmp(&a.weight)
+                .then_with(|| a.address.to_string().cmp(&b.address.to_string()))
+        });
+        let distributed: u64 = miners.iter().map(|miner| miner.reward).sum();
+        if let Some(heaviest) = miners.first_mut() {
+            heaviest.reward += total_reward.saturating_sub(distributed);
+        }
+
+        Self {
+            policy,
+            total_reward,
+            share_count: window.len(),
+            miners,
+        }
+    }
+}
+
+/// Split PAYOUT_SCALE between miners in proportion to their weights
+/// Amounts are rounded down, so the total can be a few units short of PAYOUT_SCALE.
+pub fn scale_weights<K: Eq + Hash>(weights: HashMap<K, Decimal>) -> HashMap<K, u64> {
+    let total: Decimal = weights.values().sum();
+    if total.is_zero() {
+        return HashMap::new();
+    }
+    weights
+        .into_iter()
+        .map(|(miner, weight)| {
+            let amount = (weight * Decimal::from(PAYOUT_SCALE) / total)
+                .floor()
+                .to_u64()
+                .unwrap_or(0);
+            (miner, amount)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestBlockBuilder;
+    use rust_decimal_macros::dec;
+
+    const MINER1: &str = "020202020202020202020202020202020202020202020202020202020202020202";
+    const MINER2: &str = "020202020202020202020202020202020202020202020202020202020202020203";
+
+    fn window() -> Vec<ShareBlock> {
+        vec![
+            TestBlockBuilder::new()
+                .miner_pubkey(MINER1)
+                .diff(dec!(3.0))
+                .build(),
+            TestBlockBuilder::new()
+                .miner_pubkey(MINER1)
+                .diff(dec!(3.0))
+                .build(),
+            TestBlockBuilder::new()
+                .miner_pubkey(MINER2)
+                .diff(dec!(2.0))
+                .build(),
+        ]
+    }
+
+    #[test]
+    fn test_pplns_weights_shares_by_difficulty() {
+        let weights = PayoutPolicy::Pplns.miner_weights(&window());
+        assert_eq!(weights[&MINER1.parse().unwrap()], dec!(6.0));
+        assert_eq!(weights[&MINER2.parse().unwrap()], dec!(2.0));
+
+        let payouts = scale_weights(weights);
+        assert_eq!(payouts[&MINER1.parse().unwrap()], 75_000_000);
+        assert_eq!(payouts[&MINER2.parse().unwrap()], 25_000_000);
+    }
+
+    #[test]
+    fn test_equal_weights_every_share_the_same() {
+        let weights = PayoutPolicy::Equal.miner_weights(&window());
+        assert_eq!(weights[&MINER1.parse().unwrap()], dec!(2));
+        assert_eq!(weights[&MINER2.parse().unwrap()], dec!(1));
+
+        let payouts = scale_weights(weights);
+        assert_eq!(payouts[&MINER1.parse().unwrap()], 66_666_666);
+        assert_eq!(payouts[&MINER2.parse().unwrap()], 33_333_333);
+    }
+
+    #[test]
+    fn test_scale_weights_empty_window() {
+        let weights = PayoutPolicy::Pplns.miner_weights(&[]);
+        assert!(scale_weights(weights).is_empty());
+    }
+
+    #[test]
+    fn test_payout_policy_deserializes_lowercase() {
+        let policy: PayoutPolicy = serde_json::from_str("\"equal\"").unwrap();
+        assert_eq!(policy, PayoutPolicy::Equal);
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::shares::{ShareBlock, ShareBlockHash};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+/// Why a chain snapshot was not loaded
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Failed to read snapshot: {0}")]
+    Read(String),
+    #[error("Invalid snapshot: {0}")]
+    Invalid(String),
+    #[error("Snapshot work {snapshot} is not more than the current chain work {current}")]
+    NotHeavier { snapshot: Decimal, current: Decimal },
+    #[error("Failed to store snapshot shares: {0}")]
+    Store(String),
+}
+
+/// A chain export, the shares of a share chain starting from its genesis share
+/// Every share comes after its parent and uncles.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainSnapshot {
+    pub shares: Vec<ShareBlock>,
+}
+
+/// The chain state described by a valid snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotChain {
+    pub genesis: ShareBlockHash,
+    /// The tip with the most work, the first one found when tips have equal work
+    pub chain_tip: ShareBlockHash,
+    pub tips: HashSet<ShareBlockHash>,
+    pub total_difficulty: Decimal,
+    /// Height of each share, in snapshot order
+    pub heights: Vec<u32>,
+}
+
+#[allow(dead_code)]
+impl ChainSnapshot {
+    /// Serialize the snapshot to CBOR bytes
+    pub fn cbor_serialize(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(&self, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Deserialize a snapshot from CBOR bytes, computing the blockhash of each share
+    pub fn cbor_deserialize(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut snapshot: Self =
+            ciborium::de::from_reader(bytes).map_err(|e| SnapshotError::Read(e.to_string()))?;
+        for share in snapshot.shares.iter_mut() {
+            share.compute_blockhash();
+        }
+        Ok(snapshot)
+    }
+
+    /// Check the shares form a chain from a single genesis share and work out its tips and work
+    pub fn validate(&self) -> Result<SnapshotChain, SnapshotError> {
+        let genesis = match self.shares.first() {
+            Some(share) if share.header.prev_share_blockhash.is_none() => {
+                share.cached_blockhash.unwrap()
+            }
+            Some(share) => {
+                return Err(SnapshotError::Invalid(format!(
+                    "first share {} is not a genesis share",
+                    share.cached_blockhash.unwrap()
+                )))
+            }
+            None => return Err(SnapshotError::Invalid("snapshot has no shares".to_string())),
+        };
+
+        // Height and total difficulty up to each share seen so far
+        let mut seen: HashMap<ShareBlockHash, (u32, Decimal)> = HashMap::new();
+        let mut tips = HashSet::new();
+        let mut heights = Vec::with_capacity(self.shares.len());
+        let mut chain_tip = genesis;
+        let mut total_difficulty = Decimal::MIN;
+        for share in &self.shares {
+            let blockhash = share.cached_blockhash.unwrap();
+            if seen.contains_key(&blockhash) {
+                return Err(SnapshotError::Invalid(format!(
+                    "share {} appears more than once",
+                    blockhash
+                )));
+            }
+            let (height, work) = match share.header.prev_share_blockhash {
+                None if blockhash == genesis => (0, share.header.miner_share.diff),
+                None => {
+                    return Err(SnapshotError::Invalid(format!(
+                        "share {} has no parent, only the first share can be a genesis share",
+                        blockhash
+                    )))
+                }
+                Some(prev) => match seen.get(&prev) {
+                    Some((prev_height, prev_work)) => {
+                        (prev_height + 1, prev_work + share.header.miner_share.diff)
+                    }
+                    None => {
+                        return Err(SnapshotError::Invalid(format!(
+                            "share {} comes before its parent {}",
+                            blockhash, prev
+                        )))
+                    }
+                },
+            };
+            for uncle in &share.header.uncles {
+                if !seen.contains_key(uncle) {
+                    return Err(SnapshotError::Invalid(format!(
+                        "share {} comes before its uncle {}",
+                        blockhash, uncle
+                    )));
+                }
+                tips.remove(uncle);
+            }
+            if let Some(prev) = share.header.prev_share_blockhash {
+                tips.remove(&prev);
+            }
+            tips.insert(blockhash);
+            if work > total_difficulty {
+                chain_tip = blockhash;
+                total_difficulty = work;
+            }
+            seen.insert(blockhash, (height, work));
+            heights.push(height);
+        }
+        Ok(SnapshotChain {
+            genesis,
+            chain_tip,
+            tips,
+            total_difficulty,
+            heights,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestBlockBuilder;
+    use rust_decimal_macros::dec;
+
+    fn share(n: u64, prev: Option<&ShareBlock>, diff: Decimal) -> ShareBlock {
+        let mut builder = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", n).as_str())
+            .diff(diff);
+        if let Some(prev) = prev {
+            builder = builder.prev_share_blockhash(prev.cached_blockhash.unwrap());
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn test_validate_finds_tip_with_most_work() {
+        let genesis = share(1, None, dec!(1.0));
+        let light = share(2, Some(&genesis), dec!(1.0));
+        let heavy = share(3, Some(&genesis), dec!(5.0));
+        let snapshot = ChainSnapshot {
+            shares: vec![genesis.clone(), light.clone(), heavy.clone()],
+        };
+
+        let chain = snapshot.validate().unwrap();
+        assert_eq!(chain.genesis, genesis.cached_blockhash.unwrap());
+        assert_eq!(chain.chain_tip, heavy.cached_blockhash.unwrap());
+        assert_eq!(chain.total_difficulty, dec!(6.0));
+        assert_eq!(chain.heights, vec![0, 1, 1]);
+        assert_eq!(
+            chain.tips,
+            HashSet::from([
+                light.cached_blockhash.unwrap(),
+                heavy.cached_blockhash.unwrap()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_broken_snapshots() {
+        let genesis = share(1, None, dec!(1.0));
+        let child = share(2, Some(&genesis), dec!(1.0));
+
+        let invalid = |shares: Vec<ShareBlock>| match (ChainSnapshot { shares }).validate() {
+            Err(SnapshotError::Invalid(reason)) => reason,
+            other => panic!("Expected an invalid snapshot, got {:?}", other),
+        };
+        assert_eq!(invalid(vec![]), "snapshot has no shares");
+        assert!(invalid(vec![child.clone(), genesis.clone()]).contains("is not a genesis share"));
+        assert!(invalid(vec![genesis.clone(), genesis.clone()]).contains("more than once"));
+        assert!(invalid(vec![genesis.clone(), share(3, None, dec!(1.0))])
+            .contains("only the first share can be a genesis share"));
+
+        let orphan = share(4, Some(&share(5, None, dec!(1.0))), dec!(1.0));
+        assert!(invalid(vec![genesis.clone(), orphan]).contains("comes before its parent"));
+
+        let with_unknown_uncle = TestBlockBuilder::new()
+            .blockhash(format!("{:064x}", 6).as_str())
+            .prev_share_blockhash(genesis.cached_blockhash.unwrap())
+            .uncles(vec![child.cached_blockhash.unwrap()])
+            .build();
+        assert!(invalid(vec![genesis, with_unknown_uncle]).contains("comes before its uncle"));
+    }
+
+    #[test]
+    fn test_cbor_round_trip_recomputes_blockhashes() {
+        let mut genesis = share(1, None, dec!(1.0));
+        genesis.compute_blockhash();
+        let snapshot = ChainSnapshot {
+            shares: vec![genesis],
+        };
+
+        let decoded = ChainSnapshot::cbor_deserialize(&snapshot.cbor_serialize().unwrap()).unwrap();
+        assert_eq!(decoded, snapshot);
+        assert!(matches!(
+            ChainSnapshot::cbor_deserialize(&[0xff]),
+            Err(SnapshotError::Read(_))
+        ));
+    }
+}
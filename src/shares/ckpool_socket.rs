@@ -15,19 +15,14 @@
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
 use crate::config::CkPoolConfig;
+use crate::utils::backoff::Backoff;
 use mockall::automock;
 use serde_json::Value;
 use std::error::Error;
 use std::thread;
-use std::time::Duration;
 use tracing::{debug, info};
 use zmq;
 
-/// The delay between reconnect attempts
-const RECONNECT_DELAY: u64 = 1;
-/// The maximum delay between reconnect attempts
-const MAX_RECONNECT_DELAY: u64 = 60;
-
 /// Trait for zmq socket operations. We implement this for the zmq::Socket type and mock it for testing
 #[automock]
 pub(crate) trait ZMQSocketTrait {
@@ -59,6 +54,7 @@ pub(crate) trait CkPoolSocketTrait {
 /// Concrete implementation of the CkPoolSocket trait
 pub(crate) struct CkPoolSocket<S: ZMQSocketTrait> {
     config: CkPoolConfig,
+    backoff: Backoff,
     socket: S,
 }
 
@@ -69,17 +65,24 @@ pub(crate) fn create_zmq_socket() -> Result<zmq::Socket, Box<dyn Error>> {
 }
 
 impl CkPoolSocket<zmq::Socket> {
-    pub(crate) fn new(config: CkPoolConfig, socket: zmq::Socket) -> Result<Self, Box<dyn Error>> {
-        Ok(CkPoolSocket { config, socket })
+    pub(crate) fn new(
+        config: CkPoolConfig,
+        backoff: Backoff,
+        socket: zmq::Socket,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(CkPoolSocket {
+            config,
+            backoff,
+            socket,
+        })
     }
 }
 
 impl<S: ZMQSocketTrait> CkPoolSocketTrait for CkPoolSocket<S> {
     /// Connect to the ckpool socket
-    /// We use exponential backoff with a maximum delay of 60 seconds
+    /// Failed attempts are retried on the configured backoff schedule
     fn connect(&self) -> Result<(), zmq::Error> {
-        let mut retry_delay = Duration::from_secs(RECONNECT_DELAY);
-        let max_delay = Duration::from_secs(MAX_RECONNECT_DELAY);
+        let mut backoff = self.backoff.clone();
         let endpoint = format!("tcp://{}:{}", self.config.host, self.config.port);
 
         loop {
@@ -89,14 +92,12 @@ impl<S: ZMQSocketTrait> CkPoolSocketTrait for CkPoolSocket<S> {
                     break;
                 }
                 Err(e) => {
+                    let retry_delay = backoff.next_delay();
                     info!(
                         "Failed to connect to ckpool at {}:{}: {}. Retrying in {:?}...",
                         self.config.host, self.config.port, e, retry_delay
                     );
                     thread::sleep(retry_delay);
-
-                    // Exponential backoff with a maximum delay
-                    retry_delay = std::cmp::min(retry_delay * 2, max_delay);
                 }
             }
         }
@@ -149,11 +150,27 @@ pub(crate) fn start_receiving_from_ckpool<S: ZMQSocketTrait>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::BackoffConfig;
     use mockall::predicate::eq;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
+    use std::time::Duration;
     use tokio::sync::mpsc;
 
+    fn test_backoff() -> Backoff {
+        Backoff::new(
+            BackoffConfig {
+                base_millis: 10,
+                cap_millis: 100,
+                multiplier: 2.0,
+                jitter: 0.5,
+            },
+            StdRng::seed_from_u64(7),
+        )
+    }
+
     #[tokio::test]
     async fn test_receive_valid_json() {
         let (tx, mut rx) = mpsc::channel(100);
@@ -227,6 +244,7 @@ mod tests {
         // Create a CkPoolSocket with the mock
         let ckpool_socket = CkPoolSocket {
             config,
+            backoff: test_backoff(),
             socket: mock_zmq_socket,
         };
 
@@ -273,6 +291,7 @@ mod tests {
         // Create a CkPoolSocket with the mock
         let ckpool_socket = CkPoolSocket {
             config,
+            backoff: test_backoff(),
             socket: mock_zmq_socket,
         };
 
@@ -325,6 +344,7 @@ mod tests {
         // Create a CkPoolSocket with the mock
         let ckpool_socket = CkPoolSocket {
             config,
+            backoff: test_backoff(),
             socket: mock_zmq_socket,
         };
 
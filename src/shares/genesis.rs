@@ -18,6 +18,10 @@ use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use std::error::Error;
 
+/// Public key used in the coinbase of the genesis share block on all networks
+pub const GENESIS_PUBLIC_KEY: &str =
+    "02ac493f2130ca56cb5c3a559860cef9a84f90b5a85dfe4ec6e6067eeee17f4d2d";
+
 /// Genesis block data
 pub struct GenesisData {
     pub workinfoid: u64,
@@ -14,10 +14,12 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::node::p2p_message_handlers::senders::send_workbase;
 use crate::node::SwarmSend;
 #[mockall_double::double]
 use crate::shares::chain::actor::ChainHandle;
 use crate::shares::miner_message::CkPoolMessage;
+use crate::shares::store::WorkbaseOutcome;
 use crate::shares::ShareBlock;
 use bitcoin::PublicKey;
 use std::error::Error;
@@ -28,11 +30,12 @@ use tracing::{error, info};
 /// For now the message can be a share or a GBT workbase
 /// We store the received message in the node's database
 /// we assume it is valid and add it to the chain.
+/// New workbases are gossiped to peers, as a coinbase delta when only the coinbase changed.
 /// TODO: Send INV message for the share _or_ push this immediately to the network
-pub async fn handle_mining_message<C>(
+pub async fn handle_mining_message<C: 'static>(
     mining_message: CkPoolMessage,
     chain_handle: ChainHandle,
-    _swarm_tx: mpsc::Sender<SwarmSend<C>>,
+    swarm_tx: mpsc::Sender<SwarmSend<C>>,
     miner_pubkey: PublicKey,
 ) -> Result<(), Box<dyn Error>> {
     match mining_message {
@@ -54,9 +57,15 @@ pub async fn handle_mining_message<C>(
                 "Mining message workbase received: {:?}",
                 workbase.workinfoid
             );
-            if let Err(e) = chain_handle.add_workbase(workbase).await {
-                error!("Failed to add workbase: {}", e);
-                return Err("Error adding workbase".into());
+            match chain_handle.add_workbase(workbase.clone()).await {
+                Ok(WorkbaseOutcome::Added) => {
+                    send_workbase(workbase, &chain_handle, swarm_tx).await?;
+                }
+                Ok(WorkbaseOutcome::Existing) => {}
+                Err(e) => {
+                    error!("Failed to add workbase: {}", e);
+                    return Err("Error adding workbase".into());
+                }
             }
         }
         CkPoolMessage::UserWorkbase(userworkbase) => {
@@ -77,7 +86,8 @@ pub async fn handle_mining_message<C>(
 mod tests {
     use super::*;
     use crate::node::messages::Message;
-    use crate::test_utils::simple_miner_share;
+    use crate::test_utils::{simple_miner_share, simple_miner_workbase};
+    use mockall::predicate::*;
     use rust_decimal_macros::dec;
 
     #[tokio::test]
@@ -171,4 +181,47 @@ mod tests {
             "Error adding share to chain"
         );
     }
+
+    #[tokio::test]
+    async fn test_handle_mining_message_gossips_coinbase_refresh_as_update() {
+        let miner_pubkey = "020202020202020202020202020202020202020202020202020202020202020202"
+            .parse()
+            .unwrap();
+        let mut mock_chain = ChainHandle::default();
+        let (swarm_tx, mut swarm_rx) = mpsc::channel(1);
+        let base = simple_miner_workbase();
+        let mut refreshed = base.clone();
+        refreshed.workinfoid = base.workinfoid + 1;
+        refreshed.coinb2 = "ffff".to_string();
+
+        mock_chain
+            .expect_add_workbase()
+            .with(eq(refreshed.clone()))
+            .times(1)
+            .returning(|_| Ok(WorkbaseOutcome::Added));
+        let stored = base.clone();
+        mock_chain
+            .expect_get_previous_workbase()
+            .returning(move |_| Some(stored.clone()));
+
+        let result = handle_mining_message::<mpsc::Sender<Message>>(
+            CkPoolMessage::Workbase(refreshed.clone()),
+            mock_chain,
+            swarm_tx,
+            miner_pubkey,
+        )
+        .await;
+
+        assert!(result.is_ok());
+        match swarm_rx.try_recv() {
+            Ok(SwarmSend::Gossip(Message::WorkbaseUpdate {
+                base_workinfoid,
+                coinbase_delta,
+            })) => {
+                assert_eq!(base_workinfoid, base.workinfoid);
+                assert_eq!(base.apply_coinbase_delta(&coinbase_delta), refreshed);
+            }
+            _ => panic!("Expected the refreshed workbase to be gossiped as an update"),
+        }
+    }
 }
@@ -17,6 +17,7 @@
 pub mod builders;
 
 use crate::shares::genesis;
+use crate::utils::serde_support::decimal::deserialize_decimal;
 use crate::utils::serde_support::time::{deserialize_time, serialize_time};
 use bitcoin::absolute::Time;
 use bitcoin::BlockHash;
@@ -45,7 +46,9 @@ pub struct MinerShare {
         deserialize_with = "deserialize_time"
     )]
     pub ntime: Time,
+    #[serde(deserialize_with = "deserialize_decimal")]
     pub diff: Decimal,
+    #[serde(deserialize_with = "deserialize_decimal")]
     pub sdiff: Decimal,
     pub hash: BlockHash,
     #[serde(skip)]
@@ -99,22 +102,7 @@ impl MinerShare {
         workbase: &MinerWorkbase,
         user_workbase: &UserWorkbase,
     ) -> Result<bool, String> {
-        let coinbase = builders::build_coinbase_from_share(user_workbase, self)
-            .map_err(|e| format!("Failed to build coinbase: {}", e))?;
-        let coinbase_txid = coinbase.compute_txid();
-        let txids = workbase
-            .txns
-            .iter()
-            .map(|tx| bitcoin::Txid::from_str(&tx.txid))
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| format!("Failed to parse txid: {}", e))?;
-        let mut all_txids = vec![coinbase_txid];
-        all_txids.extend(&txids);
-
-        let merkle_root = builders::compute_merkle_root_from_txids(&all_txids)
-            .ok_or_else(|| "Failed to compute merkle root".to_string())?;
-        let header = builders::build_bitcoin_header(workbase, self, merkle_root)
-            .map_err(|e| format!("Failed to build header: {}", e))?;
+        let header = self.rebuild_header(workbase, user_workbase)?;
         let block = builders::build_bitcoin_block(workbase, user_workbase, self)
             .map_err(|e| format!("Failed to build block: {}", e))?;
 
@@ -138,6 +126,40 @@ impl MinerShare {
 
         Ok(true)
     }
+
+    /// Hash of the bitcoin block header rebuilt from the workbases and this share.
+    /// Unlike the hash field, which the share only declares, this is the work the share actually did.
+    pub fn header_hash(
+        &self,
+        workbase: &MinerWorkbase,
+        user_workbase: &UserWorkbase,
+    ) -> Result<BlockHash, String> {
+        Ok(self.rebuild_header(workbase, user_workbase)?.block_hash())
+    }
+
+    /// Rebuild the bitcoin block header the share was mined on, with the coinbase from the user workbase
+    fn rebuild_header(
+        &self,
+        workbase: &MinerWorkbase,
+        user_workbase: &UserWorkbase,
+    ) -> Result<bitcoin::block::Header, String> {
+        let coinbase = builders::build_coinbase_from_share(user_workbase, self)
+            .map_err(|e| format!("Failed to build coinbase: {}", e))?;
+        let coinbase_txid = coinbase.compute_txid();
+        let txids = workbase
+            .txns
+            .iter()
+            .map(|tx| bitcoin::Txid::from_str(&tx.txid))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Failed to parse txid: {}", e))?;
+        let mut all_txids = vec![coinbase_txid];
+        all_txids.extend(&txids);
+
+        let merkle_root = builders::compute_merkle_root_from_txids(&all_txids)
+            .ok_or_else(|| "Failed to compute merkle root".to_string())?;
+        builders::build_bitcoin_header(workbase, self, merkle_root)
+            .map_err(|e| format!("Failed to build header: {}", e))
+    }
 }
 
 #[cfg(test)]
@@ -175,6 +197,41 @@ pub struct MinerWorkbase {
     pub header: String,
 }
 
+/// The fields of a workbase that change when ckpool only refreshes the coinbase.
+/// Sent with the workinfoid of a base workbase in place of the full workbase, the receiver rebuilds
+/// the full workbase from the base it already stores.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct CoinbaseDelta {
+    pub workinfoid: u64,
+    pub coinb1: String,
+    pub coinb2: String,
+    pub coinb3: String,
+}
+
+impl MinerWorkbase {
+    /// The delta rebuilding this workbase from base, None if they differ in more than the coinbase
+    pub fn coinbase_delta(&self, base: &MinerWorkbase) -> Option<CoinbaseDelta> {
+        let delta = CoinbaseDelta {
+            workinfoid: self.workinfoid,
+            coinb1: self.coinb1.clone(),
+            coinb2: self.coinb2.clone(),
+            coinb3: self.coinb3.clone(),
+        };
+        (base.apply_coinbase_delta(&delta) == *self).then_some(delta)
+    }
+
+    /// The full workbase with this workbase's template and the delta's workinfoid and coinbase
+    pub fn apply_coinbase_delta(&self, delta: &CoinbaseDelta) -> MinerWorkbase {
+        MinerWorkbase {
+            workinfoid: delta.workinfoid,
+            coinb1: delta.coinb1.clone(),
+            coinb2: delta.coinb2.clone(),
+            coinb3: delta.coinb3.clone(),
+            ..self.clone()
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(into = "(String, String, String, String, Vec<String>, String, String, String, bool)")]
 #[serde(from = "(String, String, String, String, Vec<String>, String, String, String, bool)")]
@@ -305,7 +362,26 @@ pub struct Gbt {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::simple_miner_workbase;
     use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_rebuild_workbase_from_base_and_coinbase_delta() {
+        let base = simple_miner_workbase();
+        let mut refreshed = base.clone();
+        refreshed.workinfoid = base.workinfoid + 1;
+        refreshed.coinb1 = format!("{}00", base.coinb1);
+        refreshed.coinb2 = "ffff".to_string();
+
+        let delta = refreshed.coinbase_delta(&base).unwrap();
+        assert_eq!(delta.workinfoid, refreshed.workinfoid);
+        assert_eq!(base.apply_coinbase_delta(&delta), refreshed);
+
+        // A new template can't be sent as a delta
+        let mut new_template = refreshed.clone();
+        new_template.gbt.height += 1;
+        assert_eq!(new_template.coinbase_delta(&base), None);
+    }
     #[test]
     fn test_build_genesis_miner_share() {
         let genesis_data = genesis::genesis_data(bitcoin::Network::Signet).unwrap();
@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod add_share;
 pub mod chain;
 pub mod ckpool_socket;
 pub mod genesis;
@@ -24,10 +25,12 @@ pub mod store;
 pub mod transactions;
 pub mod validation;
 use crate::shares::miner_message::MinerShare;
+use crate::utils::serde_support::backend::{Bincode, Cbor, SerializationBackend};
 use bitcoin::TxMerkleNode;
-use bitcoin::{BlockHash, PublicKey, Transaction};
+use bitcoin::{BlockHash, PublicKey, ScriptBuf, Transaction};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
+use std::fmt;
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 /// Header for the ShareBlock
@@ -105,6 +108,14 @@ impl ShareBlockBuilder {
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug, Hash, Copy)]
 pub struct ShareBlockHash(BlockHash);
 
+impl ShareBlockHash {
+    /// Parse a blockhash from the bytes it is stored as, None if there are not exactly 32 bytes
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        use bitcoin::hashes::Hash;
+        BlockHash::from_slice(bytes).ok().map(ShareBlockHash)
+    }
+}
+
 impl From<&str> for ShareBlockHash {
     fn from(s: &str) -> ShareBlockHash {
         ShareBlockHash(s.parse().expect("Invalid block hash string"))
@@ -155,6 +166,28 @@ pub struct ShareBlock {
     pub cached_blockhash: Option<ShareBlockHash>,
 }
 
+/// Reasons the contents of a share are malformed
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShareError {
+    /// The share has no coinbase transaction with an output paying the miner
+    MissingCoinbase,
+    /// The coinbase output paying the miner is not to a standard address
+    InvalidMinerAddress(String),
+}
+
+impl fmt::Display for ShareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShareError::MissingCoinbase => write!(f, "Share has no coinbase output"),
+            ShareError::InvalidMinerAddress(reason) => {
+                write!(f, "Invalid miner address: {}", reason)
+            }
+        }
+    }
+}
+
+impl Error for ShareError {}
+
 impl ShareBlock {
     pub fn new(
         miner_share: MinerShare,
@@ -187,12 +220,67 @@ impl ShareBlock {
         block
     }
 
+    /// Payout script for the miner of this share
+    /// Matches the P2PKH coinbase output we build for the miner pubkey, and is what we index shares by.
+    pub fn miner_script_pubkey(&self) -> ScriptBuf {
+        ScriptBuf::new_p2pkh(&self.header.miner_pubkey.pubkey_hash())
+    }
+
+    /// Payout address of the miner, parsed from the first output of the coinbase, which pays the miner's reward
+    /// Fails if the share has no coinbase output, or the output doesn't pay to a standard address for the network.
+    pub fn miner_address(&self, network: bitcoin::Network) -> Result<bitcoin::Address, ShareError> {
+        let output = self
+            .transactions
+            .first()
+            .filter(|transaction| transaction.is_coinbase())
+            .and_then(|coinbase| coinbase.output.first())
+            .ok_or(ShareError::MissingCoinbase)?;
+        bitcoin::Address::from_script(&output.script_pubkey, network)
+            .map_err(|e| ShareError::InvalidMinerAddress(e.to_string()))
+    }
+
     pub fn compute_blockhash(&mut self) {
         let mut serialized = Vec::new();
         ciborium::ser::into_writer(&self, &mut serialized).unwrap();
         self.cached_blockhash = Some(ShareBlockHash(bitcoin::hashes::Hash::hash(&serialized)));
     }
 
+    /// Serialize the share with a serialization backend, the cached blockhash is not serialized
+    pub fn serialize_with<B: SerializationBackend>(
+        &self,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        B::serialize(self)
+    }
+
+    /// Deserialize a share serialized with a serialization backend and compute its blockhash
+    pub fn deserialize_with<B: SerializationBackend>(
+        bytes: &[u8],
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut share: ShareBlock = B::deserialize(bytes)?;
+        share.compute_blockhash();
+        Ok(share)
+    }
+
+    /// Serialize the share to CBOR bytes, the encoding its blockhash is computed from
+    pub fn cbor_serialize(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.serialize_with::<Cbor>()
+    }
+
+    /// Deserialize a share from CBOR bytes
+    pub fn cbor_deserialize(bytes: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Self::deserialize_with::<Cbor>(bytes)
+    }
+
+    /// Serialize the share to bincode bytes, smaller and faster than CBOR for shares kept on this node
+    pub fn bincode_serialize(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.serialize_with::<Bincode>()
+    }
+
+    /// Deserialize a share from bincode bytes
+    pub fn bincode_deserialize(bytes: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Self::deserialize_with::<Bincode>(bytes)
+    }
+
     /// Build a genesis share block for a given network
     /// The bitcoin blockhash is hardcoded, so are the coinbase, nonce2, nonce, ntime, diff, sdiff
     /// The workinfoid and clientid are 0 for genesis block on all networks
@@ -205,6 +293,20 @@ impl ShareBlock {
         ShareBlock::build_genesis(&genesis_data, public_key, network)
     }
 
+    /// Hash identifying the share chain for a network, exchanged with peers on connect
+    /// Networks without share chain genesis data use the bitcoin genesis block hash instead.
+    pub fn genesis_hash_for_network(network: bitcoin::Network) -> ShareBlockHash {
+        match genesis::genesis_data(network) {
+            Ok(genesis_data) => {
+                let public_key = genesis::GENESIS_PUBLIC_KEY.parse::<PublicKey>().unwrap();
+                ShareBlock::build_genesis(&genesis_data, public_key, network)
+                    .cached_blockhash
+                    .unwrap()
+            }
+            Err(_) => ShareBlockHash(bitcoin::constants::genesis_block(network).block_hash()),
+        }
+    }
+
     fn build_genesis(
         genesis_data: &genesis::GenesisData,
         public_key: PublicKey,
@@ -279,11 +381,31 @@ mod tests {
     use super::*;
     use crate::node::messages::Message;
     use crate::test_utils::simple_miner_share;
-    use crate::test_utils::TestBlockBuilder;
+    use crate::test_utils::{valid_share_block, TestBlockBuilder};
+    use crate::utils::serde_support::backend::Bincode;
     use bitcoin::absolute::Time;
     use rust_decimal_macros::dec;
     use std::collections::HashSet;
 
+    #[test]
+    fn test_share_round_trips_through_cbor_and_bincode() {
+        let (share, _, _, _) = valid_share_block();
+
+        let cbor = share.cbor_serialize().unwrap();
+        let bincode = share.bincode_serialize().unwrap();
+        assert_eq!(ShareBlock::cbor_deserialize(&cbor).unwrap(), share);
+        assert_eq!(ShareBlock::bincode_deserialize(&bincode).unwrap(), share);
+        assert_eq!(
+            ShareBlock::deserialize_with::<Bincode>(&bincode).unwrap(),
+            share
+        );
+        assert!(bincode.len() < cbor.len());
+
+        // Bincode bytes are not CBOR, the backends can't read each other's encoding
+        assert!(ShareBlock::cbor_deserialize(&bincode).is_err());
+        assert!(ShareBlock::bincode_deserialize(&cbor).is_err());
+    }
+
     #[test]
     fn test_build_genesis_share_header() {
         let bitcoin_blockhash = "000000000822bbfaf34d53fc43d0c1382054d3aafe31893020c315db8b0a19f9"
@@ -527,6 +649,25 @@ mod tests {
         assert!(hash_set.contains(&hash2));
     }
 
+    #[test]
+    fn test_genesis_hash_for_network() {
+        let public_key = genesis::GENESIS_PUBLIC_KEY.parse().unwrap();
+        let signet_genesis =
+            ShareBlock::build_genesis_for_network(public_key, bitcoin::Network::Signet);
+        assert_eq!(
+            ShareBlock::genesis_hash_for_network(bitcoin::Network::Signet),
+            signet_genesis.cached_blockhash.unwrap()
+        );
+        assert_ne!(
+            ShareBlock::genesis_hash_for_network(bitcoin::Network::Signet),
+            ShareBlock::genesis_hash_for_network(bitcoin::Network::Testnet4)
+        );
+        assert_eq!(
+            ShareBlock::genesis_hash_for_network(bitcoin::Network::Regtest),
+            "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"
+        );
+    }
+
     #[test]
     fn test_share_block_hash_display() {
         let hash: ShareBlockHash =
@@ -538,4 +679,43 @@ mod tests {
             "0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5"
         );
     }
+
+    #[test]
+    fn test_miner_address_from_coinbase() {
+        let share = TestBlockBuilder::new().build();
+        let pubkey = "020202020202020202020202020202020202020202020202020202020202020202"
+            .parse::<PublicKey>()
+            .unwrap();
+        assert_eq!(
+            share.miner_address(bitcoin::Network::Regtest).unwrap(),
+            bitcoin::Address::p2pkh(pubkey, bitcoin::Network::Regtest)
+        );
+    }
+
+    #[test]
+    fn test_miner_address_malformed() {
+        // The coinbase pays to a script that isn't a standard address
+        let mut share = TestBlockBuilder::new().build();
+        share.transactions[0].output[0].script_pubkey = ScriptBuf::new_op_return(b"not an address");
+        assert!(matches!(
+            share.miner_address(bitcoin::Network::Regtest),
+            Err(ShareError::InvalidMinerAddress(_))
+        ));
+
+        // The first transaction isn't a coinbase
+        let mut share = TestBlockBuilder::new().build();
+        share.transactions[0].input[0].previous_output.vout = 0;
+        assert_eq!(
+            share.miner_address(bitcoin::Network::Regtest),
+            Err(ShareError::MissingCoinbase)
+        );
+
+        // There are no transactions
+        let mut share = TestBlockBuilder::new().build();
+        share.transactions.clear();
+        assert_eq!(
+            share.miner_address(bitcoin::Network::Regtest),
+            Err(ShareError::MissingCoinbase)
+        );
+    }
 }
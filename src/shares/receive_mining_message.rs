@@ -20,10 +20,12 @@ use crate::shares::chain::actor::ChainHandle;
 use crate::shares::ckpool_socket::CkPoolSocketTrait;
 use crate::shares::handle_mining_message::handle_mining_message;
 use crate::shares::miner_message::CkPoolMessage;
+use crate::utils::backoff::Backoff;
 use crate::{
     node::SwarmSend,
     shares::ckpool_socket::{create_zmq_socket, start_receiving_from_ckpool, CkPoolSocket},
 };
+use rand::rngs::StdRng;
 use std::error::Error;
 use std::thread;
 use tokio::sync::mpsc;
@@ -32,15 +34,18 @@ use tracing::{error, info};
 /// Receives messages from ckpool and sends them to the node asynchronously
 /// Each new message received starts a new tokio task
 /// TODO: Add limits to how many concurrent tasks are run
+/// Connecting to ckpool is retried on the configured backoff schedule, with jitter drawn from rng.
 pub fn start_receiving_mining_messages<C: Send + 'static>(
     config: &Config,
+    rng: StdRng,
     chain_handle: ChainHandle,
     swarm_tx: mpsc::Sender<SwarmSend<C>>,
 ) -> Result<(), Box<dyn Error>> {
     let (mining_message_tx, mut mining_message_rx) =
         tokio::sync::mpsc::channel::<serde_json::Value>(100);
     let socket = create_zmq_socket()?;
-    let ckpool_socket = CkPoolSocket::new(config.ckpool.clone(), socket)?;
+    let backoff = Backoff::new(config.backoff.clone(), rng);
+    let ckpool_socket = CkPoolSocket::new(config.ckpool.clone(), backoff, socket)?;
     ckpool_socket.connect()?;
     thread::spawn(move || {
         if let Err(e) = start_receiving_from_ckpool(ckpool_socket, mining_message_tx) {
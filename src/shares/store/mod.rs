@@ -15,13 +15,16 @@
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
 use crate::node::messages::Message;
+use crate::shares::genesis::GENESIS_PUBLIC_KEY;
 use crate::shares::miner_message::{MinerWorkbase, UserWorkbase};
 use crate::shares::{ShareBlock, ShareHeader, StorageShareBlock};
 use bitcoin::Transaction;
 use rocksdb::{ColumnFamilyDescriptor, Options as RocksDbOptions, DB};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::error::Error;
+use std::sync::Arc;
 use tracing::debug;
 
 use super::ShareBlockHash;
@@ -45,6 +48,185 @@ pub struct BlockMetadata {
     pub is_confirmed: bool,
 }
 
+/// Where a share first reached this node. Only kept for forensic analysis, never used for consensus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareProvenance {
+    /// Submitted by our own miners
+    Local,
+    /// Delivered by this peer
+    Peer(libp2p::PeerId),
+}
+
+/// The effect of adding a workbase to the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkbaseOutcome {
+    /// The workbase was new and has been stored
+    Added,
+    /// An identical workbase was already stored, nothing was written
+    Existing,
+}
+
+/// Why a write to the store was refused or failed
+#[derive(Debug, thiserror::Error)]
+pub enum StoreWriteError {
+    #[error("Store is read only after a failed reopen, reopen it again before writing")]
+    ReadOnly,
+    #[error("Failed to write to store: {0}")]
+    RocksDb(#[from] rocksdb::Error),
+}
+
+/// Sentinel stored for local shares, peer ids are multihashes so can't collide with it
+const LOCAL_PROVENANCE: &[u8] = b"local";
+
+impl ShareProvenance {
+    fn to_bytes(self) -> Vec<u8> {
+        match self {
+            ShareProvenance::Local => LOCAL_PROVENANCE.to_vec(),
+            ShareProvenance::Peer(peer_id) => peer_id.to_bytes(),
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes == LOCAL_PROVENANCE {
+            return Some(ShareProvenance::Local);
+        }
+        libp2p::PeerId::from_bytes(bytes)
+            .ok()
+            .map(ShareProvenance::Peer)
+    }
+}
+
+/// Column families the store opens, all of them count towards the disk usage
+const COLUMN_FAMILIES: [&str; 14] = [
+    "block",
+    "block_txids",
+    "inputs",
+    "outputs",
+    "tx",
+    "workbase",
+    "workbase_index",
+    "user_workbase",
+    "block_index",
+    "block_height",
+    "miner_shares",
+    "share_provenance",
+    "share_time",
+    "workbase_shares",
+];
+
+/// Key in the block_height column family of the lowest height that has not been pruned.
+/// Heights are 4 byte keys, so it can't collide with them.
+const PRUNED_BELOW_KEY: &[u8] = b"pruned_below";
+
+/// Key in the block_height column family of the total difficulty of the pruned shares the remaining chain builds on.
+const PRUNED_WORK_KEY: &[u8] = b"pruned_work";
+
+/// Range of workbases to list, by the block height or the template time (curtime) of their block template.
+/// Both bounds are inclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkbaseRange {
+    Height(std::ops::RangeInclusive<u32>),
+    Time(std::ops::RangeInclusive<u32>),
+}
+
+impl WorkbaseRange {
+    /// Key prefix of the index the range is looked up in
+    fn index_prefix(&self) -> u8 {
+        match self {
+            WorkbaseRange::Height(_) => b'h',
+            WorkbaseRange::Time(_) => b't',
+        }
+    }
+
+    fn bounds(&self) -> (u32, u32) {
+        match self {
+            WorkbaseRange::Height(range) | WorkbaseRange::Time(range) => {
+                (*range.start(), *range.end())
+            }
+        }
+    }
+}
+
+/// Prefix of the keys the store benchmark writes to the default column family, which holds no store data
+const BENCHMARK_KEY_PREFIX: &[u8] = b"benchmark:";
+/// The first key past every key starting with BENCHMARK_KEY_PREFIX, bounding the benchmark's compaction
+const BENCHMARK_KEY_END: &[u8] = b"benchmark;";
+
+/// Throughput and latency of writing, reading and deleting synthetic entries through the store's database.
+/// Latencies are per entry, in microseconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreBenchmark {
+    pub ops: usize,
+    /// Size of each entry written, a serialized share
+    pub entry_bytes: usize,
+    pub writes_per_sec: f64,
+    pub reads_per_sec: f64,
+    pub write_p50_micros: u64,
+    pub write_p99_micros: u64,
+    pub read_p50_micros: u64,
+    pub read_p99_micros: u64,
+}
+
+/// Latency at percentile of latencies sorted in ascending order, 0 if there are none
+fn percentile_micros(sorted: &[u64], percentile: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    sorted[(sorted.len() - 1) * percentile / 100]
+}
+
+/// Per entry latencies in microseconds of the benchmark's writes and reads, and the time each phase took
+struct BenchmarkTimings {
+    write_micros: Vec<u64>,
+    writes_elapsed: std::time::Duration,
+    read_micros: Vec<u64>,
+    reads_elapsed: std::time::Duration,
+}
+
+/// Operations per second for ops operations taking elapsed in total
+fn ops_per_sec(ops: usize, elapsed: std::time::Duration) -> f64 {
+    ops as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+/// Key in the share_time column family, ordered by the share's ntime and then its blockhash
+fn share_time_key(ntime: u32, blockhash: &ShareBlockHash) -> Vec<u8> {
+    [ntime.to_be_bytes().as_slice(), blockhash.as_ref()].concat()
+}
+
+/// Key in the workbase_shares column family, grouping the shares mined on a workbase under its workinfoid
+fn workbase_share_key(workinfoid: u64, blockhash: &ShareBlockHash) -> Vec<u8> {
+    [workinfoid.to_be_bytes().as_slice(), blockhash.as_ref()].concat()
+}
+
+/// Key in the workbase_index column family, ordered by the indexed value and then the workinfoid
+fn workbase_index_key(prefix: u8, value: u32, workinfoid: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(13);
+    key.push(prefix);
+    key.extend_from_slice(&value.to_be_bytes());
+    key.extend_from_slice(&workinfoid.to_be_bytes());
+    key
+}
+
+/// The workbase_index keys of a workbase, by its block height and by its template time
+fn workbase_index_keys(workbase: &MinerWorkbase) -> [Vec<u8>; 2] {
+    [
+        workbase_index_key(b'h', workbase.gbt.height, workbase.workinfoid),
+        workbase_index_key(
+            b't',
+            workbase.gbt.curtime.to_consensus_u32(),
+            workbase.workinfoid,
+        ),
+    ]
+}
+
+/// Index lists collected in memory while adding shares, keyed by parent, height and miner payout script
+#[derive(Default)]
+struct PendingIndexes {
+    children: HashMap<ShareBlockHash, Vec<ShareBlockHash>>,
+    heights: HashMap<u32, Vec<ShareBlockHash>>,
+    miner_shares: HashMap<bitcoin::ScriptBuf, Vec<ShareBlockHash>>,
+}
+
 /// A store for share blocks.
 /// RocksDB as is used as the underlying database.
 /// We use column families to store different types of data, so that compactions are independent for each type.
@@ -53,10 +235,137 @@ pub struct BlockMetadata {
 /// - block_txids: txids for a block, to get transactions for a block. A tx can appear in multiple blocks.
 /// - inputs: inputs for a transaction, to get inputs for a tx.
 /// - outputs: outputs for a transaction, to get outputs for a tx. These can be marked as spent. So these are updated.
+/// - miner_shares: blockhashes of shares for a miner payout script, to get shares for a miner.
+/// - share_provenance: where a share first reached this node, local or the peer that delivered it.
+/// - workbase_index: workinfoids of workbases keyed by their block height and by their template time.
+/// - share_time: blockhashes of shares keyed by their ntime, to count shares found in a time range.
+/// - workbase_shares: blockhashes of shares keyed by the workinfoid they were mined on, to keep referenced workbases.
 #[allow(dead_code)]
 pub struct Store {
     path: String,
-    db: DB,
+    /// Shared with the maintenance tasks running off the chain actor, see StoreMaintenance
+    db: Arc<DB>,
+    /// Workinfoids of the stored workbases, loaded on open so eviction doesn't scan the workbase column family
+    workinfoids: BTreeSet<u64>,
+    /// Set while a failed reopen left the read only handle in place, writes are refused until a reopen succeeds
+    read_only: bool,
+}
+
+/// Handle to the store's database for maintenance that can run for minutes, like compaction and benchmarks. It is
+/// moved to a blocking thread so the chain actor keeps serving requests while the maintenance runs.
+#[derive(Clone)]
+pub struct StoreMaintenance {
+    db: Arc<DB>,
+}
+
+impl StoreMaintenance {
+    /// Compact the whole store, returning its disk usage before and after in bytes
+    pub fn compact(&self) -> (u64, u64) {
+        let bytes_before = disk_usage_bytes(&self.db);
+        compact(&self.db);
+        (bytes_before, disk_usage_bytes(&self.db))
+    }
+
+    /// Benchmark the disk by writing and reading back ops synthetic entries the size of a share, one at a time.
+    /// The entries go to the default column family, away from the store data, and are deleted afterwards.
+    pub fn benchmark(&self, ops: usize) -> Result<StoreBenchmark, Box<dyn Error + Send + Sync>> {
+        let public_key = GENESIS_PUBLIC_KEY.parse::<bitcoin::PublicKey>()?;
+        let share = ShareBlock::build_genesis_for_network(public_key, bitcoin::Network::Signet);
+        let entry = StorageShareBlock::from(share)
+            .cbor_serialize()
+            .map_err(|e| e.to_string())?;
+        let keys: Vec<Vec<u8>> = (0..ops)
+            .map(|i| [BENCHMARK_KEY_PREFIX, (i as u64).to_be_bytes().as_slice()].concat())
+            .collect();
+
+        // Delete the entries whether or not timing them succeeded, so a failed benchmark leaves none behind
+        let timed = self.time_benchmark_entries(&keys, &entry);
+        let mut batch = rocksdb::WriteBatch::default();
+        for key in &keys {
+            batch.delete(key);
+        }
+        self.db.write(batch)?;
+        self.db
+            .compact_range(Some(BENCHMARK_KEY_PREFIX), Some(BENCHMARK_KEY_END));
+        let BenchmarkTimings {
+            mut write_micros,
+            writes_elapsed,
+            mut read_micros,
+            reads_elapsed,
+        } = timed?;
+
+        write_micros.sort_unstable();
+        read_micros.sort_unstable();
+        Ok(StoreBenchmark {
+            ops,
+            entry_bytes: entry.len(),
+            writes_per_sec: ops_per_sec(ops, writes_elapsed),
+            reads_per_sec: ops_per_sec(ops, reads_elapsed),
+            write_p50_micros: percentile_micros(&write_micros, 50),
+            write_p99_micros: percentile_micros(&write_micros, 99),
+            read_p50_micros: percentile_micros(&read_micros, 50),
+            read_p99_micros: percentile_micros(&read_micros, 99),
+        })
+    }
+
+    /// Write each key with entry then read it back, timing each write and read
+    fn time_benchmark_entries(
+        &self,
+        keys: &[Vec<u8>],
+        entry: &[u8],
+    ) -> Result<BenchmarkTimings, Box<dyn Error + Send + Sync>> {
+        let mut write_micros = Vec::with_capacity(keys.len());
+        let writes_started = std::time::Instant::now();
+        for key in keys {
+            let started = std::time::Instant::now();
+            self.db.put(key, entry)?;
+            write_micros.push(started.elapsed().as_micros() as u64);
+        }
+        let writes_elapsed = writes_started.elapsed();
+        // Flush so the reads are served from disk rather than the memtable
+        self.db.flush()?;
+
+        let mut read_micros = Vec::with_capacity(keys.len());
+        let reads_started = std::time::Instant::now();
+        for key in keys {
+            let started = std::time::Instant::now();
+            match self.db.get(key)? {
+                Some(read) if read == entry => {}
+                _ => return Err("Benchmark entry read back differs from the one written".into()),
+            }
+            read_micros.push(started.elapsed().as_micros() as u64);
+        }
+        Ok(BenchmarkTimings {
+            write_micros,
+            writes_elapsed,
+            read_micros,
+            reads_elapsed: reads_started.elapsed(),
+        })
+    }
+}
+
+/// Total size of the SST files of all column families, after flushing the memtables to them
+fn disk_usage_bytes(db: &DB) -> u64 {
+    COLUMN_FAMILIES
+        .iter()
+        .filter_map(|name| db.cf_handle(name))
+        .map(|column_family| {
+            if let Err(e) = db.flush_cf(column_family) {
+                tracing::error!("Failed to flush column family: {:?}", e);
+            }
+            db.property_int_value_cf(column_family, "rocksdb.total-sst-files-size")
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+        })
+        .sum()
+}
+
+/// Compact all column families, dropping deleted entries from disk
+fn compact(db: &DB) {
+    for column_family in COLUMN_FAMILIES.iter().filter_map(|name| db.cf_handle(name)) {
+        db.compact_range_cf(column_family, None::<&[u8]>, None::<&[u8]>);
+    }
 }
 
 /// A rocksdb based store for share blocks.
@@ -65,91 +374,297 @@ pub struct Store {
 impl Store {
     /// Create a new share store
     pub fn new(path: String) -> Result<Self, Box<dyn Error>> {
+        let db = Arc::new(Self::open_db(&path, RocksDbOptions::default())?);
+        let mut store = Self {
+            path,
+            db,
+            workinfoids: BTreeSet::new(),
+            read_only: false,
+        };
+        store.backfill_workbase_index();
+        store.backfill_workbase_shares();
+        store.backfill_share_time();
+        store.workinfoids = store.scan_workinfoids();
+        Ok(store)
+    }
+
+    /// Index the stored shares by the workinfoid they were mined on if the index is empty.
+    /// Like the workbase index, an empty index next to stored shares means the store predates it.
+    fn backfill_workbase_shares(&self) {
+        let block_cf = self.db.cf_handle("block").unwrap();
+        let workbase_shares_cf = self.db.cf_handle("workbase_shares").unwrap();
+        if self
+            .db
+            .iterator_cf(workbase_shares_cf, rocksdb::IteratorMode::Start)
+            .next()
+            .is_some()
+        {
+            return;
+        }
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut indexed = 0;
+        for (key, value) in self
+            .db
+            .iterator_cf(block_cf, rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .filter(|(key, _)| !key.ends_with(b"_md"))
+        {
+            let Some(blockhash) = ShareBlockHash::from_bytes(&key) else {
+                continue;
+            };
+            if let Ok(share) = StorageShareBlock::cbor_deserialize(&value) {
+                batch.put_cf(
+                    workbase_shares_cf,
+                    workbase_share_key(share.header.miner_share.workinfoid, &blockhash),
+                    [],
+                );
+                indexed += 1;
+            }
+        }
+        if indexed > 0 {
+            tracing::info!(
+                "Backfilling the workbase shares index with {} shares",
+                indexed
+            );
+            self.db.write(batch).unwrap();
+        }
+    }
+
+    /// Index the stored shares by their ntime if the share time index is empty.
+    /// Shares stored before the index existed would otherwise be missing from the share histogram.
+    fn backfill_share_time(&self) {
+        let block_cf = self.db.cf_handle("block").unwrap();
+        let share_time_cf = self.db.cf_handle("share_time").unwrap();
+        if self
+            .db
+            .iterator_cf(share_time_cf, rocksdb::IteratorMode::Start)
+            .next()
+            .is_some()
+        {
+            return;
+        }
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut indexed = 0;
+        for (key, value) in self
+            .db
+            .iterator_cf(block_cf, rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .filter(|(key, _)| !key.ends_with(b"_md"))
+        {
+            let Some(blockhash) = ShareBlockHash::from_bytes(&key) else {
+                continue;
+            };
+            if let Ok(share) = StorageShareBlock::cbor_deserialize(&value) {
+                batch.put_cf(
+                    share_time_cf,
+                    share_time_key(
+                        share.header.miner_share.ntime.to_consensus_u32(),
+                        &blockhash,
+                    ),
+                    [],
+                );
+                indexed += 1;
+            }
+        }
+        if indexed > 0 {
+            tracing::info!("Backfilling the share time index with {} shares", indexed);
+            self.db.write(batch).unwrap();
+        }
+    }
+
+    /// Index the stored workbases by height and time if the workbase index is empty.
+    /// Stores written before the index existed have workbases but no index, the index is written with every
+    /// workbase since, so an empty index next to stored workbases means the store needs the backfill.
+    fn backfill_workbase_index(&self) {
+        let workbase_cf = self.db.cf_handle("workbase").unwrap();
+        let workbase_index_cf = self.db.cf_handle("workbase_index").unwrap();
+        if self
+            .db
+            .iterator_cf(workbase_index_cf, rocksdb::IteratorMode::Start)
+            .next()
+            .is_some()
+        {
+            return;
+        }
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut indexed = 0;
+        for (_, value) in self
+            .db
+            .iterator_cf(workbase_cf, rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+        {
+            if let Ok(Message::Workbase(workbase)) = Message::cbor_deserialize(&value) {
+                for key in workbase_index_keys(&workbase) {
+                    batch.put_cf(workbase_index_cf, key, b"");
+                }
+                indexed += 1;
+            }
+        }
+        if indexed > 0 {
+            tracing::info!("Backfilling the workbase index with {} workbases", indexed);
+            self.db.write(batch).unwrap();
+        }
+    }
+
+    /// Open the RocksDB database at path with all the store's column families
+    fn open_db(path: &str, mut db_options: RocksDbOptions) -> Result<DB, rocksdb::Error> {
         // for now we use default options for all column families, we can tweak this later based on performance testing
-        let block_cf = ColumnFamilyDescriptor::new("block", RocksDbOptions::default());
-        let block_txids_cf = ColumnFamilyDescriptor::new("block_txids", RocksDbOptions::default());
-        let inputs_cf = ColumnFamilyDescriptor::new("inputs", RocksDbOptions::default());
-        let outputs_cf = ColumnFamilyDescriptor::new("outputs", RocksDbOptions::default());
-        let tx_cf = ColumnFamilyDescriptor::new("tx", RocksDbOptions::default());
-        let workbase_cf = ColumnFamilyDescriptor::new("workbase", RocksDbOptions::default());
-        let block_index_cf = ColumnFamilyDescriptor::new("block_index", RocksDbOptions::default());
-        let block_height_cf =
-            ColumnFamilyDescriptor::new("block_height", RocksDbOptions::default());
-        let user_workbase_cf =
-            ColumnFamilyDescriptor::new("user_workbase", RocksDbOptions::default());
+        let column_families = COLUMN_FAMILIES
+            .iter()
+            .map(|name| ColumnFamilyDescriptor::new(*name, RocksDbOptions::default()))
+            .collect::<Vec<_>>();
 
         // for the db too, we use default options for now
-        let mut db_options = RocksDbOptions::default();
         db_options.create_missing_column_families(true);
         db_options.create_if_missing(true);
-        let db = DB::open_cf_descriptors(
-            &db_options,
-            path.clone(),
-            vec![
-                block_cf,
-                block_txids_cf,
-                inputs_cf,
-                outputs_cf,
-                tx_cf,
-                workbase_cf,
-                user_workbase_cf,
-                block_index_cf,
-                block_height_cf,
-            ],
-        )
-        .unwrap();
-        Ok(Self { path, db })
+        DB::open_cf_descriptors(&db_options, path, column_families)
+    }
+
+    /// Open the RocksDB database at path read only, with all the store's column families.
+    /// A read only open doesn't take the directory lock, so it works while another handle has the database open.
+    fn open_db_read_only(path: &str) -> Result<DB, rocksdb::Error> {
+        let column_families = COLUMN_FAMILIES
+            .iter()
+            .map(|name| ColumnFamilyDescriptor::new(*name, RocksDbOptions::default()))
+            .collect::<Vec<_>>();
+        DB::open_cf_descriptors_read_only(&RocksDbOptions::default(), path, column_families, false)
+    }
+
+    /// Flush, close and open the database again at the same path, dropping any in-memory state RocksDB had.
+    /// The database is first opened read only to check it can be opened, if that fails the current handle is kept
+    /// and the error returned. RocksDB locks its directory, so the read only handle stands in while the old handle
+    /// is closed. If opening for writes still fails, the read only handle stays in place so reads keep being served
+    /// from disk, writes return StoreWriteError::ReadOnly, and the error is returned for the operator to reopen again
+    /// or restart.
+    pub fn reopen(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // A maintenance task holding the handle would keep the directory locked and the open for writes would fail
+        if Arc::strong_count(&self.db) > 1 {
+            return Err("Store maintenance is running, reopen the store once it completes".into());
+        }
+        if let Err(e) = self.db.flush() {
+            tracing::error!("Failed to flush store before reopening: {}", e);
+        }
+        let read_only = Arc::new(Self::open_db_read_only(&self.path)?);
+        drop(std::mem::replace(&mut self.db, read_only));
+        self.read_only = true;
+        self.db = Arc::new(Self::open_db(&self.path, RocksDbOptions::default())?);
+        self.read_only = false;
+        Ok(())
+    }
+
+    /// Write the batch atomically, refused while the store is read only
+    fn write(&self, batch: rocksdb::WriteBatch) -> Result<(), StoreWriteError> {
+        if self.read_only {
+            return Err(StoreWriteError::ReadOnly);
+        }
+        self.db.write(batch)?;
+        Ok(())
     }
 
     /// Add a share to the store
     /// We use StorageShareBlock to serialize the share so that we do not store transactions serialized with the block.
     /// Transactions are stored separately. All writes are done in a single atomic batch.
-    pub fn add_share(&mut self, share: ShareBlock, height: u32) {
-        debug!(
-            "Adding share to store with {} txs: {:?}",
-            share.transactions.len(),
-            share.cached_blockhash
-        );
-        let blockhash = share.cached_blockhash.unwrap();
+    pub fn add_share(&mut self, share: ShareBlock, height: u32) -> Result<(), StoreWriteError> {
+        self.add_shares(vec![(share, height)])
+    }
 
+    /// Add shares with their heights to the store in a single atomic batch.
+    /// The children, height and miner index lists are collected in memory before they are written,
+    /// a batch can't see the lists earlier puts in it wrote.
+    pub fn add_shares(&mut self, shares: Vec<(ShareBlock, u32)>) -> Result<(), StoreWriteError> {
         // Create a new write batch
         let mut batch = rocksdb::WriteBatch::default();
+        let mut indexes = PendingIndexes::default();
 
-        // Store transactions and get their metadata
-        let txs_metadata = self.store_txs(&share.transactions, &mut batch);
+        for (share, height) in shares {
+            debug!(
+                "Adding share to store with {} txs: {:?}",
+                share.transactions.len(),
+                share.cached_blockhash
+            );
+            let blockhash = share.cached_blockhash.unwrap();
 
-        let txids = txs_metadata.iter().map(|t| t.txid).collect();
-        // Store block -> txids index
-        self.store_txids_to_block_index(&blockhash, &txids, &mut batch);
+            // Store transactions and get their metadata
+            let txs_metadata = self.store_txs(&share.transactions, &mut batch);
 
-        if let Err(e) =
-            self.update_block_index(&share.header.prev_share_blockhash, &blockhash, &mut batch)
-        {
-            tracing::error!("Failed to update block index: {:?}", e);
-            return;
-        }
+            let txids = txs_metadata.iter().map(|t| t.txid).collect();
+            // Store block -> txids index
+            self.store_txids_to_block_index(&blockhash, &txids, &mut batch);
 
-        self.set_height_to_blockhash(&blockhash, height, &mut batch);
-        self.set_block_height_in_metadata(&blockhash, Some(height), Some(&mut batch))
-            .unwrap();
+            if let Some(prev_blockhash) = share.header.prev_share_blockhash {
+                self.update_block_index(&prev_blockhash, &blockhash, &mut indexes);
+            }
 
-        // Add the share block itself
-        let storage_share_block: StorageShareBlock = share.into();
-        let block_cf = self.db.cf_handle("block").unwrap();
-        batch.put_cf::<&[u8], Vec<u8>>(
-            block_cf,
-            blockhash.as_ref(),
-            storage_share_block.cbor_serialize().unwrap(),
-        );
+            self.set_height_to_blockhash(&blockhash, height, &mut indexes);
+            self.set_block_height_in_metadata(&blockhash, Some(height), Some(&mut batch))
+                .unwrap();
+
+            self.add_share_to_miner_index(&share.miner_script_pubkey(), &blockhash, &mut indexes);
+
+            let share_time_cf = self.db.cf_handle("share_time").unwrap();
+            batch.put_cf(
+                share_time_cf,
+                share_time_key(
+                    share.header.miner_share.ntime.to_consensus_u32(),
+                    &blockhash,
+                ),
+                [],
+            );
+            let workbase_shares_cf = self.db.cf_handle("workbase_shares").unwrap();
+            batch.put_cf(
+                workbase_shares_cf,
+                workbase_share_key(share.header.miner_share.workinfoid, &blockhash),
+                [],
+            );
+
+            // Add the share block itself
+            let storage_share_block: StorageShareBlock = share.into();
+            let block_cf = self.db.cf_handle("block").unwrap();
+            batch.put_cf::<&[u8], Vec<u8>>(
+                block_cf,
+                blockhash.as_ref(),
+                storage_share_block.cbor_serialize().unwrap(),
+            );
+        }
+
+        self.put_pending_indexes(indexes, &mut batch);
 
         // Write the entire batch atomically
-        self.db.write(batch).unwrap();
+        self.write(batch)
+    }
+
+    /// Put the index lists collected while adding shares into the batch
+    fn put_pending_indexes(&self, indexes: PendingIndexes, batch: &mut rocksdb::WriteBatch) {
+        let block_index_cf = self.db.cf_handle("block_index").unwrap();
+        for (prev_blockhash, children) in indexes.children {
+            let mut prev_blockhash_bytes = prev_blockhash.as_ref().to_vec();
+            prev_blockhash_bytes.extend_from_slice(b"_bi");
+            let mut serialized = Vec::new();
+            ciborium::ser::into_writer(&children, &mut serialized).unwrap();
+            batch.put_cf::<&[u8], Vec<u8>>(
+                block_index_cf,
+                prev_blockhash_bytes.as_ref(),
+                serialized,
+            );
+        }
+        let block_height_cf = self.db.cf_handle("block_height").unwrap();
+        for (height, blockhashes) in indexes.heights {
+            let mut serialized = Vec::new();
+            ciborium::ser::into_writer(&blockhashes, &mut serialized).unwrap();
+            batch.put_cf(block_height_cf, height.to_be_bytes(), serialized);
+        }
+        let miner_shares_cf = self.db.cf_handle("miner_shares").unwrap();
+        for (script_pubkey, blockhashes) in indexes.miner_shares {
+            let mut serialized = Vec::new();
+            ciborium::ser::into_writer(&blockhashes, &mut serialized).unwrap();
+            batch.put_cf(miner_shares_cf, script_pubkey.as_bytes(), serialized);
+        }
     }
 
     /// Load children BlockHashes for a blockhash from the block index
     /// These are tracked in a separate index in rocksdb as relations from
     /// blockhash -> next blockhashes
-    fn get_children_blockhashes(&self, blockhash: &ShareBlockHash) -> Vec<ShareBlockHash> {
+    pub fn get_children_blockhashes(&self, blockhash: &ShareBlockHash) -> Vec<ShareBlockHash> {
         let block_index_cf = self.db.cf_handle("block_index").unwrap();
         let mut blockhash_bytes = blockhash.as_ref().to_vec();
         blockhash_bytes.extend_from_slice(b"_bi");
@@ -171,42 +686,19 @@ impl Store {
     /// We store the next blockhashes for a block in a separate column family
     fn update_block_index(
         &self,
-        prev_blockhash: &Option<ShareBlockHash>,
+        prev_blockhash: &ShareBlockHash,
         next_blockhash: &ShareBlockHash,
-        batch: &mut rocksdb::WriteBatch,
-    ) -> Result<(), Box<dyn Error>> {
-        if prev_blockhash.is_none() {
-            return Ok(());
+        indexes: &mut PendingIndexes,
+    ) {
+        let existing_children = indexes
+            .children
+            .entry(*prev_blockhash)
+            .or_insert_with(|| self.get_children_blockhashes(prev_blockhash));
+
+        // Add the new prev blockhash to the set, a share added again is listed once
+        if !existing_children.contains(next_blockhash) {
+            existing_children.push(*next_blockhash);
         }
-
-        let prev_blockhash = prev_blockhash.unwrap();
-
-        let block_index_cf = self.db.cf_handle("block_index").unwrap();
-        let mut prev_blockhash_bytes = prev_blockhash.as_ref().to_vec();
-        prev_blockhash_bytes.extend_from_slice(b"_bi");
-
-        let mut existing_children = self.get_children_blockhashes(&prev_blockhash);
-
-        // Add the new prev blockhash to the set
-        existing_children.push(*next_blockhash);
-
-        // Serialize the updated set
-        let mut serialized_children = Vec::new();
-        match ciborium::ser::into_writer(
-            &existing_children.into_iter().collect::<Vec<_>>(),
-            &mut serialized_children,
-        ) {
-            Ok(_) => (),
-            Err(e) => return Err(Box::new(e)),
-        };
-
-        // Store the updated set
-        batch.put_cf::<&[u8], Vec<u8>>(
-            block_index_cf,
-            prev_blockhash_bytes.as_ref(),
-            serialized_children,
-        );
-        Ok(())
     }
 
     /// Store transactions in the store
@@ -305,19 +797,153 @@ impl Store {
         }
     }
 
-    /// Add a workbase to the store
-    pub fn add_workbase(&mut self, workbase: MinerWorkbase) -> Result<(), Box<dyn Error>> {
+    /// Add a workbase to the store.
+    /// A workbase identical to the stored one with the same workinfoid is not written again.
+    /// A workbase with the same workinfoid but different contents is an error and the stored one is kept.
+    pub fn add_workbase(
+        &mut self,
+        workbase: MinerWorkbase,
+    ) -> Result<WorkbaseOutcome, Box<dyn Error>> {
         let workbase_key = format!("workbase:{}", workbase.workinfoid);
         debug!("Adding workbase to store: {:?}", workbase_key);
         let workbase_cf = self.db.cf_handle("workbase").unwrap();
+        let workbase_index_cf = self.db.cf_handle("workbase_index").unwrap();
+        let workinfoid = workbase.workinfoid;
+        let serialized = Message::Workbase(workbase.clone())
+            .cbor_serialize()
+            .unwrap();
+        if let Some(existing) = self
+            .db
+            .get_cf::<&[u8]>(workbase_cf, workbase_key.as_bytes())
+            .unwrap()
+        {
+            if existing == serialized {
+                debug!("Workbase already in store: {:?}", workbase_key);
+                return Ok(WorkbaseOutcome::Existing);
+            }
+            return Err(format!(
+                "Conflicting workbase {} is already stored with different contents",
+                workinfoid
+            )
+            .into());
+        }
+        let mut batch = rocksdb::WriteBatch::default();
+        for key in workbase_index_keys(&workbase) {
+            batch.put_cf(workbase_index_cf, key, b"");
+        }
+        batch.put_cf(workbase_cf, workbase_key.as_bytes(), serialized);
+        self.write(batch)?;
+        self.workinfoids.insert(workinfoid);
+        Ok(WorkbaseOutcome::Added)
+    }
+
+    /// List workbases in the range, ordered by height or time and then workinfoid.
+    /// Skips the first offset workbases in the range and returns at most limit of them, to page through large ranges.
+    pub fn list_workbases(
+        &self,
+        range: &WorkbaseRange,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<MinerWorkbase> {
+        let workbase_index_cf = self.db.cf_handle("workbase_index").unwrap();
+        let prefix = range.index_prefix();
+        let (start, end) = range.bounds();
+        let start_key = workbase_index_key(prefix, start, 0);
+        let end_key = workbase_index_key(prefix, end, u64::MAX);
+        let workinfoids: Vec<u64> = self
+            .db
+            .iterator_cf(
+                workbase_index_cf,
+                rocksdb::IteratorMode::From(&start_key, rocksdb::Direction::Forward),
+            )
+            .filter_map(Result::ok)
+            .take_while(|(key, _)| key.as_ref() <= end_key.as_slice())
+            .skip(offset)
+            .take(limit)
+            .filter_map(|(key, _)| {
+                let workinfoid: [u8; 8] = key[5..].try_into().ok()?;
+                Some(u64::from_be_bytes(workinfoid))
+            })
+            .collect();
+        self.get_workbases(&workinfoids)
+    }
+
+    /// Workinfoids of all stored workbases, lowest first
+    pub fn get_workinfoids(&self) -> Vec<u64> {
+        self.workinfoids.iter().copied().collect()
+    }
+
+    /// The stored workbase with the highest workinfoid below workinfoid, the one ckpool sent before it
+    pub fn get_previous_workbase(&self, workinfoid: u64) -> Option<MinerWorkbase> {
+        let previous = self.workinfoids.range(..workinfoid).next_back()?;
+        self.get_workbase(*previous)
+    }
+
+    /// Read the workinfoids of all stored workbases from the workbase column family, lowest first
+    fn scan_workinfoids(&self) -> BTreeSet<u64> {
+        let workbase_cf = self.db.cf_handle("workbase").unwrap();
+        // Keys are strings, so they don't iterate in numeric order, the set sorts them
         self.db
-            .put_cf(
-                workbase_cf,
-                workbase_key.as_bytes(),
-                Message::Workbase(workbase).cbor_serialize().unwrap(),
+            .iterator_cf(workbase_cf, rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .filter_map(|(key, _)| {
+                std::str::from_utf8(&key)
+                    .ok()?
+                    .strip_prefix("workbase:")?
+                    .parse()
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Whether a stored share was mined on the workbase, a seek to the workinfoid in the workbase_shares index
+    fn is_workbase_referenced(&self, workinfoid: u64) -> bool {
+        let workbase_shares_cf = self.db.cf_handle("workbase_shares").unwrap();
+        let prefix = workinfoid.to_be_bytes();
+        self.db
+            .iterator_cf(
+                workbase_shares_cf,
+                rocksdb::IteratorMode::From(&prefix, rocksdb::Direction::Forward),
             )
-            .unwrap();
-        Ok(())
+            .next()
+            .and_then(Result::ok)
+            .is_some_and(|(key, _)| key.starts_with(&prefix))
+    }
+
+    /// Evict the oldest workbases, lowest workinfoid first, until at most max_workbases are stored.
+    /// Workbases a stored share was mined on are kept, so more than max_workbases can remain.
+    /// Their user workbases are evicted with them. Returns the evicted workinfoids.
+    pub fn evict_workbases(&mut self, max_workbases: usize) -> Result<Vec<u64>, StoreWriteError> {
+        let excess = self.workinfoids.len().saturating_sub(max_workbases);
+        if excess == 0 {
+            return Ok(Vec::new());
+        }
+        let evicted: Vec<u64> = self
+            .workinfoids
+            .iter()
+            .copied()
+            .filter(|workinfoid| !self.is_workbase_referenced(*workinfoid))
+            .take(excess)
+            .collect();
+
+        let workbase_cf = self.db.cf_handle("workbase").unwrap();
+        let workbase_index_cf = self.db.cf_handle("workbase_index").unwrap();
+        let user_workbase_cf = self.db.cf_handle("user_workbase").unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        for workbase in self.get_workbases(&evicted) {
+            for key in workbase_index_keys(&workbase) {
+                batch.delete_cf(workbase_index_cf, key);
+            }
+        }
+        for workinfoid in &evicted {
+            batch.delete_cf(workbase_cf, format!("workbase:{}", workinfoid));
+            batch.delete_cf(user_workbase_cf, format!("user_workbase:{}", workinfoid));
+        }
+        self.write(batch)?;
+        for workinfoid in &evicted {
+            self.workinfoids.remove(workinfoid);
+        }
+        Ok(evicted)
     }
 
     /// Add a user workbase to the store
@@ -325,15 +951,15 @@ impl Store {
         let user_workbase_key = format!("user_workbase:{}", user_workbase.workinfoid);
         debug!("Adding user workbase to store: {:?}", user_workbase_key);
         let user_workbase_cf = self.db.cf_handle("user_workbase").unwrap();
-        self.db
-            .put_cf(
-                user_workbase_cf,
-                user_workbase_key.as_bytes(),
-                Message::UserWorkbase(user_workbase)
-                    .cbor_serialize()
-                    .unwrap(),
-            )
-            .unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put_cf(
+            user_workbase_cf,
+            user_workbase_key.as_bytes(),
+            Message::UserWorkbase(user_workbase)
+                .cbor_serialize()
+                .unwrap(),
+        );
+        self.write(batch)?;
         Ok(())
     }
 
@@ -356,9 +982,9 @@ impl Store {
         tx_metadata.spent_by = spent_by;
         let mut serialized = Vec::new();
         ciborium::ser::into_writer(&tx_metadata, &mut serialized).unwrap();
-        self.db
-            .put_cf::<&[u8], Vec<u8>>(tx_cf, txid.as_ref(), serialized)
-            .unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put_cf::<&[u8], Vec<u8>>(tx_cf, txid.as_ref(), serialized);
+        self.write(batch)?;
         Ok(())
     }
 
@@ -565,9 +1191,10 @@ impl Store {
     }
 
     /// Get the genesis blockhash, as the first blockhash in the chain
-    /// Assume there is no uncle at height 0
+    /// Once the chain has been pruned, this is the first blockhash at the lowest height we still have.
+    /// Assume there is no uncle at that height
     pub fn get_genesis_blockhash(&self) -> ShareBlockHash {
-        self.get_blockhashes_for_height(0)[0]
+        self.get_blockhashes_for_height(self.pruned_below())[0]
     }
 
     /// Get blockhashes to satisfy the locator query.
@@ -798,50 +1425,479 @@ impl Store {
 
     /// Set the height for the blockhash, storing it in a vector of blockhashes for that height
     /// We are fine with Vector instead of HashSet as we are not going to have a lot of blockhashes at the same height
-    pub fn set_height_to_blockhash(
-        &mut self,
+    fn set_height_to_blockhash(
+        &self,
         blockhash: &ShareBlockHash,
         height: u32,
-        batch: &mut rocksdb::WriteBatch,
+        indexes: &mut PendingIndexes,
     ) {
+        // Get any existing blockhashes for this height
+        let blockhashes = indexes
+            .heights
+            .entry(height)
+            .or_insert_with(|| self.get_blockhashes_for_height(height));
+
+        // Add the new blockhash if not already present
+        if !blockhashes.contains(blockhash) {
+            blockhashes.push(*blockhash);
+        }
+    }
+
+    /// Remove the blockhash from the blockhashes stored for a height, used when a share moves to another height
+    pub fn remove_height_to_blockhash(
+        &mut self,
+        blockhash: &ShareBlockHash,
+        height: u32,
+    ) -> Result<(), StoreWriteError> {
         let column_family = self.db.cf_handle("block_height").unwrap();
-        // Convert height to big-endian bytes - this is our key
-        let height_bytes = height.to_be_bytes();
+        let mut blockhashes = self.get_blockhashes_for_height(height);
+        if !blockhashes.contains(blockhash) {
+            return Ok(());
+        }
+        blockhashes.retain(|at_height| at_height != blockhash);
+        let mut serialized = Vec::new();
+        ciborium::ser::into_writer(&blockhashes, &mut serialized).unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        batch.put_cf(column_family, height.to_be_bytes(), serialized);
+        self.write(batch)
+    }
 
-        // Get any existing blockhashes for this height
-        let mut blockhashes: Vec<ShareBlockHash> = match self
+    /// Add the blockhash to the shares indexed by the miner's payout script
+    fn add_share_to_miner_index(
+        &self,
+        script_pubkey: &bitcoin::Script,
+        blockhash: &ShareBlockHash,
+        indexes: &mut PendingIndexes,
+    ) {
+        let blockhashes = indexes
+            .miner_shares
+            .entry(script_pubkey.to_owned())
+            .or_insert_with(|| self.get_shares_for_script(script_pubkey));
+        if !blockhashes.contains(blockhash) {
+            blockhashes.push(*blockhash);
+        }
+    }
+
+    /// Get the blockhashes of shares paying out to a script, in the order they were added
+    fn get_shares_for_script(&self, script_pubkey: &bitcoin::Script) -> Vec<ShareBlockHash> {
+        let column_family = self.db.cf_handle("miner_shares").unwrap();
+        match self
             .db
-            .get_cf::<&[u8]>(column_family, height_bytes.as_ref())
+            .get_cf::<&[u8]>(column_family, script_pubkey.as_bytes())
         {
             Ok(Some(existing)) => ciborium::de::from_reader(&existing[..]).unwrap_or_default(),
             Ok(None) | Err(_) => Vec::new(),
-        };
+        }
+    }
 
-        // Add the new blockhash if not already present
-        if !blockhashes.contains(blockhash) {
-            blockhashes.push(*blockhash);
+    /// Record where a share first reached us. Later deliveries of the same share keep the first record.
+    pub fn set_share_provenance(&self, blockhash: &ShareBlockHash, provenance: ShareProvenance) {
+        if self.get_share_provenance(blockhash).is_some() {
+            return;
+        }
+        let column_family = self.db.cf_handle("share_provenance").unwrap();
+        if let Err(e) = self
+            .db
+            .put_cf(column_family, blockhash.as_ref(), provenance.to_bytes())
+        {
+            tracing::error!("Failed to store share provenance: {:?}", e);
+        }
+    }
+
+    /// Where a share first reached us, None for shares added without provenance, e.g. during sync
+    pub fn get_share_provenance(&self, blockhash: &ShareBlockHash) -> Option<ShareProvenance> {
+        let column_family = self.db.cf_handle("share_provenance").unwrap();
+        match self.db.get_cf::<&[u8]>(column_family, blockhash.as_ref()) {
+            Ok(Some(bytes)) => ShareProvenance::from_bytes(&bytes),
+            Ok(None) | Err(_) => None,
+        }
+    }
+
+    /// Get the blockhashes of all shares that first reached us from a source
+    pub fn get_shares_by_provenance(&self, provenance: ShareProvenance) -> Vec<ShareBlockHash> {
+        let column_family = self.db.cf_handle("share_provenance").unwrap();
+        let provenance = provenance.to_bytes();
+        self.db
+            .iterator_cf(column_family, rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .filter(|(_, value)| value.as_ref() == provenance.as_slice())
+            .filter_map(|(key, _)| ShareBlockHash::from_bytes(&key))
+            .collect()
+    }
+
+    /// Rebuild the miner, share time and workbase shares index entries for the shares at a height, returning the number of shares indexed
+    /// Blockhashes already in the index are kept, so reindexing a height more than once is harmless.
+    pub fn reindex_miner_shares_at_height(
+        &mut self,
+        height: u32,
+    ) -> Result<usize, StoreWriteError> {
+        let mut shares: Vec<(ShareBlockHash, ShareBlock)> =
+            self.get_shares_at_height(height).into_iter().collect();
+        // Shares at a height have no order of their own, sort them so the index is deterministic
+        shares.sort_by_key(|(blockhash, _)| blockhash.to_string());
+        // Collect per script first, a batch can't see the index entries earlier puts in it wrote
+        let mut by_script: HashMap<bitcoin::ScriptBuf, Vec<ShareBlockHash>> = HashMap::new();
+        for (blockhash, share) in &shares {
+            let script_pubkey = share.miner_script_pubkey();
+            let blockhashes = by_script
+                .entry(script_pubkey.clone())
+                .or_insert_with(|| self.get_shares_for_script(&script_pubkey));
+            if !blockhashes.contains(blockhash) {
+                blockhashes.push(*blockhash);
+            }
+        }
+        let column_family = self.db.cf_handle("miner_shares").unwrap();
+        let share_time_cf = self.db.cf_handle("share_time").unwrap();
+        let workbase_shares_cf = self.db.cf_handle("workbase_shares").unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        for (blockhash, share) in &shares {
+            batch.put_cf(
+                share_time_cf,
+                share_time_key(share.header.miner_share.ntime.to_consensus_u32(), blockhash),
+                [],
+            );
+            batch.put_cf(
+                workbase_shares_cf,
+                workbase_share_key(share.header.miner_share.workinfoid, blockhash),
+                [],
+            );
+        }
+        for (script_pubkey, blockhashes) in by_script {
+            let mut serialized = Vec::new();
+            ciborium::ser::into_writer(&blockhashes, &mut serialized).unwrap();
+            batch.put_cf(column_family, script_pubkey.as_bytes(), serialized);
+        }
+        self.write(batch)?;
+        Ok(shares.len())
+    }
+
+    /// Count the stored shares with an ntime in each of count consecutive buckets of bucket_secs seconds,
+    /// the first starting at start. Returns the start time of each bucket with its count, oldest first.
+    pub fn count_shares_by_time(
+        &self,
+        start: u32,
+        bucket_secs: u32,
+        count: usize,
+    ) -> Vec<(u32, u64)> {
+        let bucket_secs = bucket_secs.max(1);
+        let mut buckets: Vec<(u32, u64)> = (0..count)
+            .map_while(|i| {
+                let offset = u32::try_from(i).ok()?.checked_mul(bucket_secs)?;
+                Some((start.checked_add(offset)?, 0))
+            })
+            .collect();
+        let share_time_cf = self.db.cf_handle("share_time").unwrap();
+        let start_key = start.to_be_bytes();
+        for (key, _) in self
+            .db
+            .iterator_cf(
+                share_time_cf,
+                rocksdb::IteratorMode::From(&start_key, rocksdb::Direction::Forward),
+            )
+            .filter_map(Result::ok)
+        {
+            let ntime = match key[..4].try_into() {
+                Ok(ntime) => u32::from_be_bytes(ntime),
+                Err(_) => continue,
+            };
+            let index = ((ntime - start) / bucket_secs) as usize;
+            match buckets.get_mut(index) {
+                Some((_, shares)) => *shares += 1,
+                None => break,
+            }
+        }
+        buckets
+    }
+
+    /// Get the blockhashes of all shares attributed to a miner payout address
+    pub fn get_shares_by_miner(&self, address: &bitcoin::Address) -> Vec<ShareBlockHash> {
+        self.get_shares_for_script(&address.script_pubkey())
+    }
+
+    /// Get the blockhashes for a specific height
+    pub fn get_blockhashes_for_height(&self, height: u32) -> Vec<ShareBlockHash> {
+        let column_family = self.db.cf_handle("block_height").unwrap();
+        let height_bytes = height.to_be_bytes();
+        match self
+            .db
+            .get_cf::<&[u8]>(column_family, height_bytes.as_ref())
+        {
+            Ok(Some(blockhashes)) => {
+                ciborium::de::from_reader(&blockhashes[..]).unwrap_or_default()
+            }
+            Ok(None) | Err(_) => vec![],
+        }
+    }
+
+    /// Lowest height that has not been pruned, 0 if the store was never pruned
+    pub fn pruned_below(&self) -> u32 {
+        let column_family = self.db.cf_handle("block_height").unwrap();
+        match self.db.get_cf::<&[u8]>(column_family, PRUNED_BELOW_KEY) {
+            Ok(Some(bytes)) => bytes
+                .as_slice()
+                .try_into()
+                .map(u32::from_be_bytes)
+                .unwrap_or_default(),
+            Ok(None) | Err(_) => 0,
+        }
+    }
+
+    /// Total difficulty of the pruned shares the remaining shares build on, 0 if the store was never pruned.
+    /// The chain walked back from a share stops at the pruned heights, so its total difficulty adds this.
+    pub fn pruned_work(&self) -> Decimal {
+        let column_family = self.db.cf_handle("block_height").unwrap();
+        match self.db.get_cf::<&[u8]>(column_family, PRUNED_WORK_KEY) {
+            Ok(Some(bytes)) => bytes
+                .as_slice()
+                .try_into()
+                .map(Decimal::deserialize)
+                .unwrap_or_default(),
+            Ok(None) | Err(_) => Decimal::ZERO,
+        }
+    }
+
+    /// Lowest height with shares at or above pruned_below, None if no heights are left.
+    /// Heights can be missing below the tip, for shares loaded from a snapshot or deleted by a purge, so this
+    /// seeks to the lowest height key instead of stepping up from pruned_below.
+    pub fn lowest_unpruned_height(&self) -> Option<u32> {
+        let block_height_cf = self.db.cf_handle("block_height").unwrap();
+        let start_key = self.pruned_below().to_be_bytes();
+        self.db
+            .iterator_cf(
+                block_height_cf,
+                rocksdb::IteratorMode::From(&start_key, rocksdb::Direction::Forward),
+            )
+            .filter_map(Result::ok)
+            .find_map(|(key, _)| key.as_ref().try_into().ok().map(u32::from_be_bytes))
+    }
+
+    /// Size of the store's SST files across all column families
+    /// Memtables are flushed first, so recent writes are counted.
+    pub fn disk_usage_bytes(&self) -> u64 {
+        disk_usage_bytes(&self.db)
+    }
+
+    /// Delete the shares at the heights, with their indexes and the transactions no remaining share includes.
+    /// The difficulty of the deleted shares the remaining shares build on is added to pruned_work.
+    /// Returns the blockhashes deleted. The column families are compacted afterwards, so the space is reclaimed.
+    pub fn prune_heights(
+        &mut self,
+        heights: std::ops::Range<u32>,
+    ) -> Result<Vec<ShareBlockHash>, StoreWriteError> {
+        let mut batch = rocksdb::WriteBatch::default();
+        let block_height_cf = self.db.cf_handle("block_height").unwrap();
+        let mut pruned_by_height = Vec::new();
+        for height in heights.clone() {
+            pruned_by_height.push(self.get_blockhashes_for_height(height));
+            batch.delete_cf(block_height_cf, height.to_be_bytes());
+        }
+        let pruned: Vec<ShareBlockHash> = pruned_by_height.iter().flatten().copied().collect();
+
+        let pruned_work = self.pruned_work() + self.work_built_on(&pruned_by_height, &pruned);
+        batch.put_cf(block_height_cf, PRUNED_WORK_KEY, pruned_work.serialize());
+        self.delete_share_entries(&pruned, &mut batch);
+        if heights.end > self.pruned_below() {
+            batch.put_cf(block_height_cf, PRUNED_BELOW_KEY, heights.end.to_be_bytes());
+        }
+        self.write(batch)?;
+        self.compact();
+        Ok(pruned)
+    }
+
+    /// Total difficulty of the shares about to be pruned that a share left in the store builds on.
+    /// Walks the pruned shares from the highest height down, a share counts when one of its children is left in
+    /// the store or counts itself. Side shares no remaining share builds on add nothing.
+    fn work_built_on(
+        &self,
+        pruned_by_height: &[Vec<ShareBlockHash>],
+        pruned: &[ShareBlockHash],
+    ) -> Decimal {
+        let pruned: HashSet<&ShareBlockHash> = pruned.iter().collect();
+        let mut built_on: HashSet<ShareBlockHash> = HashSet::new();
+        let mut work = Decimal::ZERO;
+        for blockhash in pruned_by_height.iter().rev().flatten() {
+            let has_remaining_descendant = self
+                .get_children_blockhashes(blockhash)
+                .iter()
+                .any(|child| !pruned.contains(child) || built_on.contains(child));
+            if !has_remaining_descendant {
+                continue;
+            }
+            if let Some(share) = self.get_share(blockhash) {
+                work += share.header.miner_share.diff;
+            }
+            built_on.insert(*blockhash);
+        }
+        work
+    }
 
-            // Serialize the updated vector of blockhashes
+    /// Delete shares no other share builds on, with their indexes and the transactions no remaining share includes.
+    /// The shares are removed from their height's blockhashes and from their parent's children.
+    /// Callers have to check the shares have no children, deleting a share with children disconnects them.
+    pub fn delete_leaf_shares(
+        &mut self,
+        blockhashes: &[ShareBlockHash],
+    ) -> Result<(), StoreWriteError> {
+        let mut batch = rocksdb::WriteBatch::default();
+        let mut heights: HashMap<u32, Vec<ShareBlockHash>> = HashMap::new();
+        let mut children: HashMap<ShareBlockHash, Vec<ShareBlockHash>> = HashMap::new();
+        for blockhash in blockhashes {
+            if let Some(height) = self
+                .get_block_metadata(blockhash)
+                .and_then(|metadata| metadata.height)
+            {
+                heights
+                    .entry(height)
+                    .or_insert_with(|| self.get_blockhashes_for_height(height))
+                    .retain(|at_height| at_height != blockhash);
+            }
+            if let Some(prev_share_blockhash) = self
+                .get_share(blockhash)
+                .and_then(|share| share.header.prev_share_blockhash)
+            {
+                children
+                    .entry(prev_share_blockhash)
+                    .or_insert_with(|| self.get_children_blockhashes(&prev_share_blockhash))
+                    .retain(|child| child != blockhash);
+            }
+        }
+        self.delete_share_entries(blockhashes, &mut batch);
+
+        let block_height_cf = self.db.cf_handle("block_height").unwrap();
+        for (height, blockhashes) in heights {
             let mut serialized = Vec::new();
             ciborium::ser::into_writer(&blockhashes, &mut serialized).unwrap();
+            batch.put_cf(block_height_cf, height.to_be_bytes(), serialized);
+        }
+        let block_index_cf = self.db.cf_handle("block_index").unwrap();
+        // Parents deleted along with their children already have their index entry deleted
+        for (blockhash, children) in children
+            .into_iter()
+            .filter(|(blockhash, _)| !blockhashes.contains(blockhash))
+        {
+            let key = [blockhash.as_ref(), b"_bi".as_slice()].concat();
+            if children.is_empty() {
+                batch.delete_cf(block_index_cf, key);
+            } else {
+                let mut serialized = Vec::new();
+                ciborium::ser::into_writer(&children, &mut serialized).unwrap();
+                batch.put_cf(block_index_cf, key, serialized);
+            }
+        }
+        self.write(batch)
+    }
+
+    /// Add deletes for the shares, their metadata, indexes, provenance and the transactions only they include
+    fn delete_share_entries(
+        &self,
+        blockhashes: &[ShareBlockHash],
+        batch: &mut rocksdb::WriteBatch,
+    ) {
+        let deleted_txids_keys: HashSet<Vec<u8>> = blockhashes
+            .iter()
+            .map(|blockhash| [blockhash.as_ref(), b"_txids".as_slice()].concat())
+            .collect();
+        let kept_txids = self.txids_included_except(&deleted_txids_keys);
+
+        let block_cf = self.db.cf_handle("block").unwrap();
+        let block_txids_cf = self.db.cf_handle("block_txids").unwrap();
+        let block_index_cf = self.db.cf_handle("block_index").unwrap();
+        let share_provenance_cf = self.db.cf_handle("share_provenance").unwrap();
+        let share_time_cf = self.db.cf_handle("share_time").unwrap();
+        let workbase_shares_cf = self.db.cf_handle("workbase_shares").unwrap();
+        let mut miner_indexes: HashMap<bitcoin::ScriptBuf, Vec<ShareBlockHash>> = HashMap::new();
+        for blockhash in blockhashes {
+            if let Some(share) = self.get_share(blockhash) {
+                let script_pubkey = share.miner_script_pubkey();
+                miner_indexes
+                    .entry(script_pubkey.clone())
+                    .or_insert_with(|| self.get_shares_for_script(&script_pubkey))
+                    .retain(|indexed| indexed != blockhash);
+                batch.delete_cf(
+                    share_time_cf,
+                    share_time_key(share.header.miner_share.ntime.to_consensus_u32(), blockhash),
+                );
+                batch.delete_cf(
+                    workbase_shares_cf,
+                    workbase_share_key(share.header.miner_share.workinfoid, blockhash),
+                );
+            }
+            for txid in self.get_txids_for_blockhash(blockhash) {
+                if !kept_txids.contains(&txid) {
+                    self.delete_tx(&txid, batch);
+                }
+            }
+            let blockhash_bytes = blockhash.as_ref();
+            batch.delete_cf(block_cf, blockhash_bytes);
+            batch.delete_cf(block_cf, [blockhash_bytes, b"_md".as_slice()].concat());
+            batch.delete_cf(
+                block_txids_cf,
+                [blockhash_bytes, b"_txids".as_slice()].concat(),
+            );
+            batch.delete_cf(
+                block_index_cf,
+                [blockhash_bytes, b"_bi".as_slice()].concat(),
+            );
+            batch.delete_cf(share_provenance_cf, blockhash_bytes);
+        }
+
+        let miner_shares_cf = self.db.cf_handle("miner_shares").unwrap();
+        for (script_pubkey, blockhashes) in miner_indexes {
+            if blockhashes.is_empty() {
+                batch.delete_cf(miner_shares_cf, script_pubkey.as_bytes());
+            } else {
+                let mut serialized = Vec::new();
+                ciborium::ser::into_writer(&blockhashes, &mut serialized).unwrap();
+                batch.put_cf(miner_shares_cf, script_pubkey.as_bytes(), serialized);
+            }
+        }
+    }
+
+    /// Txids included by the shares, skipping the block_txids entries with the given keys
+    fn txids_included_except(&self, skipped_keys: &HashSet<Vec<u8>>) -> HashSet<bitcoin::Txid> {
+        let block_txids_cf = self.db.cf_handle("block_txids").unwrap();
+        self.db
+            .iterator_cf(block_txids_cf, rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .filter(|(key, _)| !skipped_keys.contains(key.as_ref()))
+            .flat_map(|(_, value)| {
+                ciborium::de::from_reader::<Vec<bitcoin::Txid>, _>(value.as_ref())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
 
-            // Store the updated vector
-            batch.put_cf(column_family, height_bytes, serialized);
+    /// Add deletes for a transaction's metadata, inputs and outputs to the batch
+    fn delete_tx(&self, txid: &bitcoin::Txid, batch: &mut rocksdb::WriteBatch) {
+        let Some(metadata) = self.get_tx_metadata(txid) else {
+            return;
+        };
+        let inputs_cf = self.db.cf_handle("inputs").unwrap();
+        for i in 0..metadata.input_count {
+            batch.delete_cf(inputs_cf, format!("{}:{}", txid, i));
+        }
+        let outputs_cf = self.db.cf_handle("outputs").unwrap();
+        for i in 0..metadata.output_count {
+            batch.delete_cf(outputs_cf, format!("{}:{}", txid, i));
         }
+        let tx_cf = self.db.cf_handle("tx").unwrap();
+        batch.delete_cf::<&[u8]>(tx_cf, txid.as_ref());
     }
 
-    /// Get the blockhashes for a specific height
-    pub fn get_blockhashes_for_height(&self, height: u32) -> Vec<ShareBlockHash> {
-        let column_family = self.db.cf_handle("block_height").unwrap();
-        let height_bytes = height.to_be_bytes();
-        match self
-            .db
-            .get_cf::<&[u8]>(column_family, height_bytes.as_ref())
-        {
-            Ok(Some(blockhashes)) => {
-                ciborium::de::from_reader(&blockhashes[..]).unwrap_or_default()
-            }
-            Ok(None) | Err(_) => vec![],
+    /// Benchmark the disk with ops synthetic entries, see StoreMaintenance::benchmark
+    pub fn benchmark(&self, ops: usize) -> Result<StoreBenchmark, Box<dyn Error + Send + Sync>> {
+        self.maintenance().benchmark(ops)
+    }
+
+    /// Compact all column families, dropping deleted entries from disk
+    pub fn compact(&self) {
+        compact(&self.db);
+    }
+
+    /// Handle for maintenance to run on the database off the chain actor
+    pub fn maintenance(&self) -> StoreMaintenance {
+        StoreMaintenance {
+            db: self.db.clone(),
         }
     }
 
@@ -985,6 +2041,226 @@ mod tests {
     use std::collections::HashSet;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_benchmark_reports_throughput_and_cleans_up() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let share = TestBlockBuilder::new().build();
+        let blockhash = share.cached_blockhash.unwrap();
+        store.add_share(share.clone(), 0).unwrap();
+
+        let benchmark = store.benchmark(50).unwrap();
+
+        assert_eq!(benchmark.ops, 50);
+        assert!(benchmark.entry_bytes > 0);
+        assert!(benchmark.writes_per_sec > 0.0);
+        assert!(benchmark.reads_per_sec > 0.0);
+        assert!(benchmark.write_p50_micros <= benchmark.write_p99_micros);
+        assert!(benchmark.read_p50_micros <= benchmark.read_p99_micros);
+        // The synthetic entries are gone and the store data untouched
+        assert_eq!(
+            store
+                .db
+                .iterator(rocksdb::IteratorMode::Start)
+                .filter_map(Result::ok)
+                .count(),
+            0
+        );
+        assert_eq!(store.get_share(&blockhash), Some(share));
+    }
+
+    #[test]
+    fn test_benchmark_key_end_bounds_only_benchmark_keys() {
+        let last_key = [BENCHMARK_KEY_PREFIX, u64::MAX.to_be_bytes().as_slice()].concat();
+        assert!(last_key.as_slice() < BENCHMARK_KEY_END);
+        assert!(!BENCHMARK_KEY_END.starts_with(BENCHMARK_KEY_PREFIX));
+    }
+
+    #[test]
+    fn test_workbase_index_is_backfilled_on_open() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let mut store = Store::new(path.clone()).unwrap();
+        for workinfoid in 1..=3 {
+            let mut workbase = TestMinerWorkbaseBuilder::new()
+                .workinfoid(workinfoid)
+                .build();
+            workbase.gbt.height = 100 + workinfoid as u32;
+            store.add_workbase(workbase).unwrap();
+        }
+
+        // A store written before the index existed has the workbases without their index entries
+        let workbase_index_cf = store.db.cf_handle("workbase_index").unwrap();
+        let keys: Vec<Box<[u8]>> = store
+            .db
+            .iterator_cf(workbase_index_cf, rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            store.db.delete_cf(workbase_index_cf, key).unwrap();
+        }
+        assert!(store
+            .list_workbases(&WorkbaseRange::Height(0..=u32::MAX), 0, 10)
+            .is_empty());
+        drop(store);
+
+        let store = Store::new(path).unwrap();
+        let listed: Vec<u64> = store
+            .list_workbases(&WorkbaseRange::Height(102..=103), 0, 10)
+            .iter()
+            .map(|workbase| workbase.workinfoid)
+            .collect();
+        assert_eq!(listed, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_workbase_shares_index_is_backfilled_on_open_and_keeps_referenced_workbases() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let mut store = Store::new(path.clone()).unwrap();
+        for workinfoid in 1..=3 {
+            store
+                .add_workbase(
+                    TestMinerWorkbaseBuilder::new()
+                        .workinfoid(workinfoid)
+                        .build(),
+                )
+                .unwrap();
+        }
+        let share = TestBlockBuilder::new()
+            .blockhash("0000000000000000000000000000000000000000000000000000000000000001")
+            .workinfoid(1)
+            .build();
+        store.add_share(share, 0).unwrap();
+
+        // A store written before the index existed has the share without its index entry
+        let workbase_shares_cf = store.db.cf_handle("workbase_shares").unwrap();
+        let keys: Vec<Box<[u8]>> = store
+            .db
+            .iterator_cf(workbase_shares_cf, rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys.len(), 1);
+        for key in keys {
+            store.db.delete_cf(workbase_shares_cf, key).unwrap();
+        }
+        assert!(!store.is_workbase_referenced(1));
+        drop(store);
+
+        // The workinfoids are loaded and the index backfilled on open, so the referenced workbase is kept
+        let mut store = Store::new(path).unwrap();
+        assert_eq!(store.get_workinfoids(), vec![1, 2, 3]);
+        assert!(store.is_workbase_referenced(1));
+        assert_eq!(store.evict_workbases(1).unwrap(), vec![2, 3]);
+        assert_eq!(store.get_workinfoids(), vec![1]);
+    }
+
+    #[test]
+    fn test_share_time_index_is_backfilled_on_open() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap().to_string();
+        let mut store = Store::new(path.clone()).unwrap();
+        let share = TestBlockBuilder::new()
+            .blockhash("0000000000000000000000000000000000000000000000000000000000000001")
+            .build();
+        let ntime = share.header.miner_share.ntime.to_consensus_u32();
+        store.add_share(share, 0).unwrap();
+        assert_eq!(store.count_shares_by_time(ntime, 60, 1), vec![(ntime, 1)]);
+
+        // A store written before the index existed has the share without its index entry
+        let share_time_cf = store.db.cf_handle("share_time").unwrap();
+        let keys: Vec<Box<[u8]>> = store
+            .db
+            .iterator_cf(share_time_cf, rocksdb::IteratorMode::Start)
+            .filter_map(Result::ok)
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys.len(), 1);
+        for key in keys {
+            store.db.delete_cf(share_time_cf, key).unwrap();
+        }
+        assert_eq!(store.count_shares_by_time(ntime, 60, 1), vec![(ntime, 0)]);
+        drop(store);
+
+        let store = Store::new(path).unwrap();
+        assert_eq!(store.count_shares_by_time(ntime, 60, 1), vec![(ntime, 1)]);
+    }
+
+    #[test]
+    fn test_get_previous_workbase() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        for workinfoid in [5, 2, 9] {
+            store
+                .add_workbase(
+                    TestMinerWorkbaseBuilder::new()
+                        .workinfoid(workinfoid)
+                        .build(),
+                )
+                .unwrap();
+        }
+
+        assert_eq!(store.get_previous_workbase(9).unwrap().workinfoid, 5);
+        assert_eq!(store.get_previous_workbase(5).unwrap().workinfoid, 2);
+        assert_eq!(store.get_previous_workbase(7).unwrap().workinfoid, 5);
+        assert!(store.get_previous_workbase(2).is_none());
+    }
+
+    #[test]
+    fn test_list_workbases_by_height_and_time() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+
+        // Stored out of order, with two workbases for height 102
+        let mut workbases = Vec::new();
+        for (workinfoid, height, curtime) in [
+            (5, 104, 1_700_000_400),
+            (2, 101, 1_700_000_100),
+            (4, 102, 1_700_000_250),
+            (3, 102, 1_700_000_200),
+            (1, 100, 1_700_000_000),
+        ] {
+            let mut workbase = TestMinerWorkbaseBuilder::new()
+                .workinfoid(workinfoid)
+                .build();
+            workbase.gbt.height = height;
+            workbase.gbt.curtime = bitcoin::absolute::Time::from_consensus(curtime).unwrap();
+            store.add_workbase(workbase.clone()).unwrap();
+            workbases.push(workbase);
+        }
+        let workinfoids = |listed: Vec<MinerWorkbase>| -> Vec<u64> {
+            listed.iter().map(|workbase| workbase.workinfoid).collect()
+        };
+
+        assert_eq!(
+            workinfoids(store.list_workbases(&WorkbaseRange::Height(101..=102), 0, 10)),
+            vec![2, 3, 4]
+        );
+        assert_eq!(
+            store.list_workbases(&WorkbaseRange::Height(104..=104), 0, 10),
+            vec![workbases[0].clone()]
+        );
+        assert_eq!(
+            workinfoids(store.list_workbases(
+                &WorkbaseRange::Time(1_700_000_100..=1_700_000_400),
+                0,
+                10
+            )),
+            vec![2, 3, 4, 5]
+        );
+        assert!(store
+            .list_workbases(&WorkbaseRange::Height(105..=200), 0, 10)
+            .is_empty());
+
+        // Paging through the whole range
+        let range = WorkbaseRange::Height(0..=u32::MAX);
+        assert_eq!(workinfoids(store.list_workbases(&range, 0, 2)), vec![1, 2]);
+        assert_eq!(workinfoids(store.list_workbases(&range, 2, 2)), vec![3, 4]);
+        assert_eq!(workinfoids(store.list_workbases(&range, 4, 2)), vec![5]);
+    }
+
     #[test_log::test(test)]
     fn test_chain_with_uncles() {
         let temp_dir = tempdir().unwrap();
@@ -1065,13 +2341,13 @@ mod tests {
             .build();
 
         // Add all shares to store
-        store.add_share(share1.clone(), 0);
-        store.add_share(uncle1_share2.clone(), 1);
-        store.add_share(uncle2_share2.clone(), 1);
-        store.add_share(share2.clone(), 1);
-        store.add_share(uncle1_share3.clone(), 2);
-        store.add_share(uncle2_share3.clone(), 2);
-        store.add_share(share3.clone(), 2);
+        store.add_share(share1.clone(), 0).unwrap();
+        store.add_share(uncle1_share2.clone(), 1).unwrap();
+        store.add_share(uncle2_share2.clone(), 1).unwrap();
+        store.add_share(share2.clone(), 1).unwrap();
+        store.add_share(uncle1_share3.clone(), 2).unwrap();
+        store.add_share(uncle2_share3.clone(), 2).unwrap();
+        store.add_share(share3.clone(), 2).unwrap();
 
         // Get chain up to share3
         let chain = store.get_chain_upto(&share3.cached_blockhash.unwrap());
@@ -1410,7 +2686,7 @@ mod tests {
             .build();
 
         // Store the share block
-        store.add_share(share.clone(), 0);
+        store.add_share(share.clone(), 0).unwrap();
         assert_eq!(share.transactions.len(), 3);
 
         // Retrieve transactions for the block hash
@@ -1563,7 +2839,7 @@ mod tests {
             .build();
 
         // Add share to store
-        store.add_share(share.clone(), 0);
+        store.add_share(share.clone(), 0).unwrap();
 
         // Get share header from store
         let read_share = store.get_share(&share.cached_blockhash.unwrap()).unwrap();
@@ -1667,11 +2943,11 @@ mod tests {
             .build();
 
         // Add all shares to store
-        store.add_share(share1.clone(), 0);
-        store.add_share(uncle1_share2.clone(), 1);
-        store.add_share(uncle2_share2.clone(), 1);
-        store.add_share(share2.clone(), 1);
-        store.add_share(share3.clone(), 2);
+        store.add_share(share1.clone(), 0).unwrap();
+        store.add_share(uncle1_share2.clone(), 1).unwrap();
+        store.add_share(uncle2_share2.clone(), 1).unwrap();
+        store.add_share(share2.clone(), 1).unwrap();
+        store.add_share(share3.clone(), 2).unwrap();
 
         // Verify children of share1
         let children_share1 = store.get_children_blockhashes(&share1.cached_blockhash.unwrap());
@@ -1700,6 +2976,191 @@ mod tests {
         assert!(children_uncle2_share2.is_empty());
     }
 
+    #[test]
+    fn test_get_shares_by_miner() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+
+        let miner1 = "020202020202020202020202020202020202020202020202020202020202020202";
+        let miner2 = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+        let share1 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .miner_pubkey(miner1)
+            .build();
+        let share2 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb6")
+            .prev_share_blockhash(share1.cached_blockhash.unwrap())
+            .miner_pubkey(miner2)
+            .build();
+        let share3 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb7")
+            .prev_share_blockhash(share2.cached_blockhash.unwrap())
+            .miner_pubkey(miner1)
+            .build();
+
+        store.add_share(share1.clone(), 0).unwrap();
+        store.add_share(share2.clone(), 1).unwrap();
+        store.add_share(share3.clone(), 2).unwrap();
+
+        let address1 = bitcoin::Address::p2pkh(
+            miner1.parse::<bitcoin::PublicKey>().unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let address2 = bitcoin::Address::p2pkh(
+            miner2.parse::<bitcoin::PublicKey>().unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let unknown_address = bitcoin::Address::p2pkh(
+            "03a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f90"
+                .parse::<bitcoin::PublicKey>()
+                .unwrap(),
+            bitcoin::Network::Regtest,
+        );
+
+        assert_eq!(
+            store.get_shares_by_miner(&address1),
+            vec![
+                share1.cached_blockhash.unwrap(),
+                share3.cached_blockhash.unwrap()
+            ]
+        );
+        assert_eq!(
+            store.get_shares_by_miner(&address2),
+            vec![share2.cached_blockhash.unwrap()]
+        );
+        assert!(store.get_shares_by_miner(&unknown_address).is_empty());
+    }
+
+    #[test]
+    fn test_add_shares_keeps_index_entries_for_shares_in_one_batch() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+
+        let miner = "020202020202020202020202020202020202020202020202020202020202020202";
+        let share1 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .miner_pubkey(miner)
+            .build();
+        let share2 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb6")
+            .prev_share_blockhash(share1.cached_blockhash.unwrap())
+            .miner_pubkey(miner)
+            .build();
+        let share3 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb7")
+            .prev_share_blockhash(share1.cached_blockhash.unwrap())
+            .miner_pubkey(miner)
+            .build();
+
+        store
+            .add_shares(vec![
+                (share1.clone(), 0),
+                (share2.clone(), 1),
+                (share3.clone(), 1),
+            ])
+            .unwrap();
+
+        let address = bitcoin::Address::p2pkh(
+            miner.parse::<bitcoin::PublicKey>().unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        assert_eq!(
+            store.get_shares_by_miner(&address),
+            vec![
+                share1.cached_blockhash.unwrap(),
+                share2.cached_blockhash.unwrap(),
+                share3.cached_blockhash.unwrap()
+            ]
+        );
+        assert_eq!(
+            store.get_children_blockhashes(&share1.cached_blockhash.unwrap()),
+            vec![
+                share2.cached_blockhash.unwrap(),
+                share3.cached_blockhash.unwrap()
+            ]
+        );
+        assert_eq!(
+            store.get_blockhashes_for_height(1),
+            vec![
+                share2.cached_blockhash.unwrap(),
+                share3.cached_blockhash.unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reindex_miner_shares_at_height_restores_index() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+
+        let miner = "020202020202020202020202020202020202020202020202020202020202020202";
+        let share1 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .miner_pubkey(miner)
+            .build();
+        let share2 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb6")
+            .miner_pubkey(miner)
+            .build();
+        store.add_share(share1.clone(), 0).unwrap();
+        store.add_share(share2.clone(), 0).unwrap();
+
+        let address = bitcoin::Address::p2pkh(
+            miner.parse::<bitcoin::PublicKey>().unwrap(),
+            bitcoin::Network::Regtest,
+        );
+        let column_family = store.db.cf_handle("miner_shares").unwrap();
+        store
+            .db
+            .delete_cf(column_family, address.script_pubkey().as_bytes())
+            .unwrap();
+        assert!(store.get_shares_by_miner(&address).is_empty());
+
+        assert_eq!(store.reindex_miner_shares_at_height(0).unwrap(), 2);
+        // Reindexing again doesn't duplicate entries
+        assert_eq!(store.reindex_miner_shares_at_height(0).unwrap(), 2);
+
+        let mut expected = vec![
+            share1.cached_blockhash.unwrap(),
+            share2.cached_blockhash.unwrap(),
+        ];
+        expected.sort_by_key(|blockhash| blockhash.to_string());
+        assert_eq!(store.get_shares_by_miner(&address), expected);
+        assert_eq!(store.reindex_miner_shares_at_height(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_share_provenance_keeps_first_delivery() {
+        let temp_dir = tempdir().unwrap();
+        let store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+
+        let gossiped: ShareBlockHash =
+            "0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5".into();
+        let local: ShareBlockHash =
+            "0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb6".into();
+        let first_peer = libp2p::PeerId::random();
+
+        store.set_share_provenance(&gossiped, ShareProvenance::Peer(first_peer));
+        store.set_share_provenance(&gossiped, ShareProvenance::Peer(libp2p::PeerId::random()));
+        store.set_share_provenance(&local, ShareProvenance::Local);
+
+        assert_eq!(
+            store.get_share_provenance(&gossiped),
+            Some(ShareProvenance::Peer(first_peer))
+        );
+        assert_eq!(
+            store.get_share_provenance(&local),
+            Some(ShareProvenance::Local)
+        );
+        assert_eq!(
+            store.get_share_provenance(
+                &"0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb7".into()
+            ),
+            None
+        );
+    }
+
     #[test]
     fn test_get_descendants() {
         let temp_dir = tempdir().unwrap();
@@ -1757,11 +3218,11 @@ mod tests {
             .build();
 
         // Add all shares to store
-        store.add_share(share1.clone(), 0);
-        store.add_share(uncle1_share2.clone(), 1);
-        store.add_share(uncle2_share2.clone(), 1);
-        store.add_share(share2.clone(), 1);
-        store.add_share(share3.clone(), 2);
+        store.add_share(share1.clone(), 0).unwrap();
+        store.add_share(uncle1_share2.clone(), 1).unwrap();
+        store.add_share(uncle2_share2.clone(), 1).unwrap();
+        store.add_share(share2.clone(), 1).unwrap();
+        store.add_share(share3.clone(), 2).unwrap();
 
         // Verify descendants of share1
         let descendants_share1 = store.get_descendants(
@@ -1833,7 +3294,7 @@ mod tests {
                     .prev_share_blockhash(store.get_blockhashes_for_height(height as u32 - 1)[0]);
             }
             let block = builder.build();
-            store.add_share(block, height as u32);
+            store.add_share(block, height as u32).unwrap();
         }
 
         let stop_block = store.get_blockhashes_for_height(2)[0];
@@ -1876,7 +3337,7 @@ mod tests {
             }
             let block = builder.build();
             blocks.push(block.clone());
-            store.add_share(block, height as u32);
+            store.add_share(block, height as u32).unwrap();
         }
 
         locator.push(blocks[0].cached_blockhash.unwrap()); // locator = tip
@@ -1916,7 +3377,7 @@ mod tests {
             }
             let block = builder.build();
             blocks.push(block.clone());
-            store.add_share(block, height as u32);
+            store.add_share(block, height as u32).unwrap();
         }
 
         let stop_block = store.get_blockhashes_for_height(2)[0];
@@ -1945,7 +3406,7 @@ mod tests {
             .build();
 
         // Add share to store
-        store.add_share(share.clone(), 0);
+        store.add_share(share.clone(), 0).unwrap();
         let blockhash = share.cached_blockhash.unwrap();
 
         // Initially, block should not be valid or confirmed
@@ -2015,8 +3476,8 @@ mod tests {
             .build();
 
         // Add shares to store
-        store.add_share(share1.clone(), 0);
-        store.add_share(share2.clone(), 1);
+        store.add_share(share1.clone(), 0).unwrap();
+        store.add_share(share2.clone(), 1).unwrap();
 
         let blockhash1 = share1.cached_blockhash.unwrap();
         let blockhash2 = share2.cached_blockhash.unwrap();
@@ -2045,6 +3506,107 @@ mod tests {
         assert!(!updated_metadata2.is_confirmed);
     }
 
+    #[test]
+    fn test_failed_reopen_keeps_serving_the_store_on_disk() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let share = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .build();
+        store.add_share(share.clone(), 0).unwrap();
+
+        // Without its CURRENT file the database can't be opened again, the open handle still works
+        std::fs::remove_file(temp_dir.path().join("CURRENT")).unwrap();
+        assert!(store.reopen().is_err());
+        assert_eq!(
+            store.get_share(&share.cached_blockhash.unwrap()),
+            Some(share)
+        );
+    }
+
+    #[test]
+    fn test_read_only_store_refuses_writes_until_reopened() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let share = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .build();
+
+        // As left by a reopen that opened the database read only but failed to open it for writes
+        store.read_only = true;
+        assert!(matches!(
+            store.add_share(share.clone(), 0),
+            Err(StoreWriteError::ReadOnly)
+        ));
+        assert!(store
+            .add_user_workbase(TestUserWorkbaseBuilder::new().build())
+            .is_err());
+        assert_eq!(store.get_share(&share.cached_blockhash.unwrap()), None);
+
+        store.reopen().unwrap();
+        store.add_share(share.clone(), 0).unwrap();
+        assert_eq!(
+            store.get_share(&share.cached_blockhash.unwrap()),
+            Some(share)
+        );
+    }
+
+    #[test]
+    fn test_maintenance_compacts_off_the_store_and_blocks_reopen_while_held() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        let share = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .build();
+        store.add_share(share.clone(), 0).unwrap();
+
+        let maintenance = store.maintenance();
+        let compaction = std::thread::spawn(move || maintenance.compact());
+        assert_eq!(
+            store.get_share(&share.cached_blockhash.unwrap()),
+            Some(share.clone())
+        );
+        let (bytes_before, bytes_after) = compaction.join().unwrap();
+        assert!(bytes_before > 0);
+        assert!(bytes_after > 0);
+
+        let maintenance = store.maintenance();
+        assert!(store.reopen().is_err());
+        drop(maintenance);
+        store.reopen().unwrap();
+        assert_eq!(
+            store.get_share(&share.cached_blockhash.unwrap()),
+            Some(share)
+        );
+    }
+
+    #[test]
+    fn test_lowest_unpruned_height_skips_missing_heights() {
+        let temp_dir = tempdir().unwrap();
+        let mut store = Store::new(temp_dir.path().to_str().unwrap().to_string()).unwrap();
+        assert_eq!(store.lowest_unpruned_height(), None);
+
+        let share1 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .build();
+        let share2 = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb6")
+            .prev_share_blockhash(share1.cached_blockhash.unwrap())
+            .build();
+        store.add_share(share1.clone(), 500).unwrap();
+        store.add_share(share2.clone(), 900).unwrap();
+        assert_eq!(store.lowest_unpruned_height(), Some(500));
+
+        assert_eq!(
+            store.prune_heights(500..501).unwrap(),
+            vec![share1.cached_blockhash.unwrap()]
+        );
+        assert_eq!(store.lowest_unpruned_height(), Some(900));
+
+        store.prune_heights(900..901).unwrap();
+        assert_eq!(store.lowest_unpruned_height(), None);
+    }
+
     #[test]
     fn test_set_and_get_block_height_in_metadata() {
         let temp_dir = tempdir().unwrap();
@@ -2061,7 +3623,7 @@ mod tests {
         let blockhash = share.cached_blockhash.unwrap();
 
         // Add share to store without setting height in metadata
-        store.add_share(share.clone(), 0);
+        store.add_share(share.clone(), 0).unwrap();
 
         // Height should be set during add_share
         let metadata = store.get_block_metadata(&blockhash).unwrap();
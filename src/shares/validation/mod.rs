@@ -15,11 +15,15 @@
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
 mod bitcoin_block_validation;
+pub mod pow_cache;
 
 #[mockall_double::double]
 use crate::shares::chain::actor::ChainHandle;
+use crate::shares::miner_message::{MinerWorkbase, UserWorkbase};
+use crate::shares::validation::pow_cache::PowCacheHandle;
 use crate::shares::ShareBlock;
 use crate::utils::time_provider::TimeProvider;
+use rust_decimal::prelude::ToPrimitive;
 use std::error::Error;
 
 pub const MAX_UNCLES: usize = 3;
@@ -40,6 +44,26 @@ pub async fn validate(
     if let Err(e) = validate_timestamp(share, time_provider).await {
         return Err(format!("Share timestamp validation failed: {}", e).into());
     }
+    validate_contents(share, chain_handle).await
+}
+
+/// Validate everything but the share timestamp, the checks a share has to pass whenever it was received.
+/// The timestamp is only checked to be recent on arrival, stored shares are revalidated with this.
+pub async fn validate_contents(
+    share: &ShareBlock,
+    chain_handle: &ChainHandle,
+) -> Result<(), Box<dyn Error>> {
+    let (workbase, userworkbase) = fetch_share_workbases(share, chain_handle).await?;
+    validate_proof_of_work(share, &workbase, &userworkbase, &chain_handle.pow_cache())?;
+    Ok(())
+}
+
+/// Check the share's parents are stored and fetch the workbases it was mined on, the lookups on the chain
+/// validating the share's contents needs
+pub async fn fetch_share_workbases(
+    share: &ShareBlock,
+    chain_handle: &ChainHandle,
+) -> Result<(MinerWorkbase, UserWorkbase), Box<dyn Error>> {
     if let Err(e) = validate_prev_share_blockhash(share, chain_handle).await {
         return Err(format!("Share prev_share_blockhash validation failed: {}", e).into());
     }
@@ -66,15 +90,27 @@ pub async fn validate(
         )
         .into());
     }
-    if let Err(e) = share
-        .header
-        .miner_share
-        .validate(&workbase.unwrap(), &userworkbase.unwrap())
-    {
-        return Err(format!("Share validation failed: {}", e).into());
-    }
+    Ok((workbase.unwrap(), userworkbase.unwrap()))
+}
 
-    Ok(())
+/// Validate the share's proof of work against the workbases it was mined on, unless the result is cached.
+/// Rebuilding and hashing the bitcoin block is the expensive part of validation, it is done once per share.
+/// Nothing is looked up on the chain, so this can run on a blocking thread.
+pub fn validate_proof_of_work(
+    share: &ShareBlock,
+    workbase: &MinerWorkbase,
+    userworkbase: &UserWorkbase,
+    pow_cache: &PowCacheHandle,
+) -> Result<(), String> {
+    pow_cache
+        .validate(share, || {
+            share
+                .header
+                .miner_share
+                .validate(workbase, userworkbase)
+                .map(|_| ())
+        })
+        .map_err(|e| format!("Share validation failed: {}", e))
 }
 
 /// Validate prev_share_blockhash is in store or block is genesis
@@ -113,6 +149,43 @@ pub async fn validate_uncles(
     Ok(())
 }
 
+/// Validate the share's proof of work meets the difficulty the share declares.
+/// A chain's work is the sum of the difficulties its shares declare, so this checks a share's contribution to
+/// claimed work is backed by real work: the header is rebuilt from the workbases and hashed, and must hash to the
+/// hash the share declares.
+pub fn validate_declared_difficulty(
+    share: &ShareBlock,
+    workbase: &MinerWorkbase,
+    user_workbase: &UserWorkbase,
+) -> Result<(), String> {
+    use bitcoin::hashes::Hash;
+
+    let hash = share
+        .header
+        .miner_share
+        .header_hash(workbase, user_workbase)?;
+    if hash != share.header.miner_share.hash {
+        return Err(format!(
+            "Header hashes to {}, not the declared hash {}",
+            hash, share.header.miner_share.hash
+        ));
+    }
+    let hash_target = bitcoin::Target::from_le_bytes(hash.to_byte_array());
+    let declared = share
+        .header
+        .miner_share
+        .diff
+        .to_f64()
+        .ok_or_else(|| format!("Invalid difficulty {}", share.header.miner_share.diff))?;
+    if hash_target.difficulty_float() < declared {
+        return Err(format!(
+            "Hash {} doesn't meet declared difficulty {}",
+            share.header.miner_share.hash, share.header.miner_share.diff
+        ));
+    }
+    Ok(())
+}
+
 /// Validate the share timestamp is within the last 60 seconds
 pub async fn validate_timestamp(
     share: &ShareBlock,
@@ -142,9 +215,10 @@ pub async fn validate_timestamp(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::shares::validation::pow_cache::PowCacheHandle;
     use crate::shares::{PublicKey, ShareBlockHash};
-    use crate::test_utils::load_valid_workbases_userworkbases_and_shares;
     use crate::test_utils::simple_miner_share;
+    use crate::test_utils::valid_share_block;
     use crate::test_utils::TestBlockBuilder;
     use crate::utils::time_provider::TestTimeProvider;
     use std::time::SystemTime;
@@ -366,25 +440,7 @@ mod tests {
     async fn test_validate_share() {
         let mut chain_handle = ChainHandle::default();
 
-        let (workbases, userworkbases, shares) = load_valid_workbases_userworkbases_and_shares();
-        let pubkey = "020202020202020202020202020202020202020202020202020202020202020202"
-            .parse::<bitcoin::PublicKey>()
-            .unwrap();
-        let share_header = crate::shares::miner_message::builders::build_share_header(
-            &workbases[0],
-            &shares[0],
-            &userworkbases[0],
-            pubkey,
-        )
-        .unwrap();
-
-        let share_block = crate::shares::miner_message::builders::build_share_block(
-            &workbases[0],
-            &userworkbases[0],
-            &shares[0],
-            share_header,
-        )
-        .unwrap();
+        let (share_block, workbase, user_workbase, miner_share) = valid_share_block();
 
         // Set up mock expectations
         chain_handle
@@ -398,14 +454,17 @@ mod tests {
         chain_handle
             .expect_get_workbase()
             .with(mockall::predicate::eq(7473434392883363843))
-            .returning(move |_| Some(workbases[0].clone()));
+            .returning(move |_| Some(workbase.clone()));
         chain_handle
             .expect_get_user_workbase()
             .with(mockall::predicate::eq(7473434392883363843))
-            .returning(move |_| Some(userworkbases[0].clone()));
+            .returning(move |_| Some(user_workbase.clone()));
+        chain_handle
+            .expect_pow_cache()
+            .returning(PowCacheHandle::default);
 
         let mut time_provider = TestTimeProvider(SystemTime::now());
-        time_provider.set_time(shares[0].ntime);
+        time_provider.set_time(miner_share.ntime);
 
         // Test handle_request directly without request_id
         let result = validate(&share_block, &chain_handle, &time_provider).await;
@@ -0,0 +1,233 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::shares::miner_message::MinerShare;
+use crate::shares::ShareBlock;
+use bitcoin::hashes::{sha256, Hash};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Number of shares whose proof of work result is cached, the oldest result is evicted once full
+pub const POW_CACHE_CAPACITY: usize = 4096;
+
+/// Key a proof of work result is cached under, the digest of the miner share.
+/// The proof of work is checked against the miner share and the workbases looked up by its workinfoid,
+/// so the same miner share always gets the same result. The digest is computed from the share contents,
+/// so shares decoded from gossip, which have no cached blockhash, find the result too.
+pub type PowCacheKey = sha256::Hash;
+
+/// The key the proof of work result of a miner share is cached under
+pub fn pow_cache_key(miner_share: &MinerShare) -> PowCacheKey {
+    let mut serialized = Vec::new();
+    ciborium::ser::into_writer(miner_share, &mut serialized).unwrap();
+    sha256::Hash::hash(&serialized)
+}
+
+/// Bounded cache of proof of work validation results, keyed by the digest of the miner share
+#[derive(Debug)]
+pub struct PowCache {
+    capacity: usize,
+    results: HashMap<PowCacheKey, Result<(), String>>,
+    /// Keys in the order they were cached, oldest first
+    order: VecDeque<PowCacheKey>,
+}
+
+impl PowCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            results: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn get(&self, key: &PowCacheKey) -> Option<Result<(), String>> {
+        self.results.get(key).cloned()
+    }
+
+    /// Cache the result for a share, evicting the oldest result when the cache is full
+    pub fn insert(&mut self, key: PowCacheKey, result: Result<(), String>) {
+        if self.capacity == 0 || self.results.contains_key(&key) {
+            return;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.results.remove(&oldest);
+            }
+        }
+        self.order.push_back(key);
+        self.results.insert(key, result);
+    }
+
+    /// The cached result for a share, running validate and caching its result on a miss
+    pub fn get_or_validate(
+        &mut self,
+        key: PowCacheKey,
+        validate: impl FnOnce() -> Result<(), String>,
+    ) -> Result<(), String> {
+        if let Some(result) = self.get(&key) {
+            return result;
+        }
+        let result = validate();
+        self.insert(key, result.clone());
+        result
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+}
+
+/// Proof of work cache owned by the chain and shared by the clones of its handle, so duplicate shares from gossip
+/// and shares validated again on a reorg don't rebuild and hash the bitcoin block again
+#[derive(Debug, Clone)]
+pub struct PowCacheHandle {
+    cache: Arc<Mutex<PowCache>>,
+}
+
+impl Default for PowCacheHandle {
+    fn default() -> Self {
+        Self::new(POW_CACHE_CAPACITY)
+    }
+}
+
+impl PowCacheHandle {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(PowCache::new(capacity))),
+        }
+    }
+
+    /// Validate the proof of work of a share with validate, unless the result for its miner share is cached.
+    /// The lock isn't held while validating, so shares are validated concurrently.
+    pub fn validate(
+        &self,
+        share: &ShareBlock,
+        validate: impl FnOnce() -> Result<(), String>,
+    ) -> Result<(), String> {
+        let key = pow_cache_key(&share.header.miner_share);
+        if let Some(result) = self.cache.lock().unwrap().get(&key) {
+            return result;
+        }
+        let result = validate();
+        self.cache.lock().unwrap().insert(key, result.clone());
+        result
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.lock().unwrap().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::messages::Message;
+    use crate::test_utils::TestBlockBuilder;
+    use std::cell::Cell;
+
+    fn key(i: usize) -> PowCacheKey {
+        sha256::Hash::hash(&i.to_be_bytes())
+    }
+
+    #[test]
+    fn test_pow_is_computed_once_per_unique_share() {
+        let mut cache = PowCache::new(POW_CACHE_CAPACITY);
+        let hashes = Cell::new(0);
+        let validate = || {
+            hashes.set(hashes.get() + 1);
+            Ok(())
+        };
+
+        // Each share is seen five times, as gossip duplicates and reorg re-validation would
+        for _ in 0..5 {
+            for i in 0..100 {
+                assert_eq!(cache.get_or_validate(key(i), validate), Ok(()));
+            }
+        }
+        assert_eq!(hashes.get(), 100);
+        assert_eq!(cache.len(), 100);
+    }
+
+    #[test]
+    fn test_failed_pow_is_cached() {
+        let mut cache = PowCache::new(4);
+        let hashes = Cell::new(0);
+        for _ in 0..3 {
+            let result = cache.get_or_validate(key(1), || {
+                hashes.set(hashes.get() + 1);
+                Err("Invalid proof of work".to_string())
+            });
+            assert_eq!(result, Err("Invalid proof of work".to_string()));
+        }
+        assert_eq!(hashes.get(), 1);
+    }
+
+    #[test]
+    fn test_cache_evicts_oldest_result_when_full() {
+        let mut cache = PowCache::new(2);
+        cache.insert(key(1), Ok(()));
+        cache.insert(key(2), Ok(()));
+        cache.insert(key(3), Ok(()));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&key(1)).is_none());
+        assert!(cache.get(&key(2)).is_some());
+        assert!(cache.get(&key(3)).is_some());
+    }
+
+    #[test]
+    fn test_gossiped_copy_of_a_share_hits_the_cache() {
+        let cache = PowCacheHandle::new(POW_CACHE_CAPACITY);
+        let hashes = Cell::new(0);
+        let validate = || {
+            hashes.set(hashes.get() + 1);
+            Ok(())
+        };
+        let share = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5")
+            .build();
+        assert_eq!(cache.validate(&share, validate), Ok(()));
+
+        // A share decoded from gossip carries no cached blockhash and still finds the result
+        let encoded = Message::MiningShare(share.clone())
+            .cbor_serialize()
+            .unwrap();
+        let gossiped = match Message::cbor_deserialize(&encoded).unwrap() {
+            Message::MiningShare(gossiped) => gossiped,
+            _ => panic!("Expected a mining share"),
+        };
+        assert_eq!(cache.validate(&gossiped, validate), Ok(()));
+        assert_eq!(hashes.get(), 1);
+
+        // Clones of the handle share the cache, a different miner share is validated
+        let other = TestBlockBuilder::new()
+            .blockhash("0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb6")
+            .clientid(2)
+            .build();
+        assert_eq!(cache.clone().validate(&other, validate), Ok(()));
+        assert_eq!(hashes.get(), 2);
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+#[cfg(test)]
+use crate::shares::miner_message::builders::{build_share_block, build_share_header};
 #[cfg(test)]
 use crate::shares::miner_message::Gbt;
 #[cfg(test)]
@@ -109,40 +111,9 @@ pub fn test_coinbase_transaction() -> bitcoin::Transaction {
 }
 
 #[cfg(test)]
-pub fn load_valid_workbases_userworkbases_and_shares(
-) -> (Vec<MinerWorkbase>, Vec<UserWorkbase>, Vec<MinerShare>) {
-    let workbases_str = include_str!("../../tests/test_data/validation/workbases.json");
-    let shares_str = include_str!("../../tests/test_data/validation/shares.json");
-    let userworkbases_str = include_str!("../../tests/test_data/validation/userworkbases.json");
-    let workbases: Vec<CkPoolMessage> = serde_json::from_str(&workbases_str).unwrap();
-    let shares: Vec<CkPoolMessage> = serde_json::from_str(&shares_str).unwrap();
-    let userworkbases: Vec<CkPoolMessage> = serde_json::from_str(&userworkbases_str).unwrap();
-    let workbases = workbases
-        .into_iter()
-        .filter_map(|msg| match msg {
-            CkPoolMessage::Workbase(w) => Some(w),
-            _ => None,
-        })
-        .collect::<Vec<MinerWorkbase>>();
-
-    let userworkbases = userworkbases
-        .into_iter()
-        .filter_map(|msg| match msg {
-            CkPoolMessage::UserWorkbase(w) => Some(w),
-            _ => None,
-        })
-        .collect::<Vec<UserWorkbase>>();
-
-    let shares = shares
-        .into_iter()
-        .filter_map(|msg| match msg {
-            CkPoolMessage::Share(s) => Some(s),
-            _ => None,
-        })
-        .collect::<Vec<MinerShare>>();
-
-    (workbases, userworkbases, shares)
-}
+mod validation_data;
+#[cfg(test)]
+pub use validation_data::{load_valid_workbases_userworkbases_and_shares, valid_share_block};
 
 #[cfg(test)]
 #[derive(Debug, Clone)]
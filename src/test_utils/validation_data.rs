@@ -0,0 +1,77 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+//! The validation test data and a valid share built from it. The integration tests include this file in
+//! tests/common too, so it only uses names its parent module imports.
+
+use super::{
+    build_share_block, build_share_header, CkPoolMessage, MinerShare, MinerWorkbase, ShareBlock,
+    UserWorkbase,
+};
+
+pub fn load_valid_workbases_userworkbases_and_shares(
+) -> (Vec<MinerWorkbase>, Vec<UserWorkbase>, Vec<MinerShare>) {
+    let workbases_str = include_str!("../../tests/test_data/validation/workbases.json");
+    let shares_str = include_str!("../../tests/test_data/validation/shares.json");
+    let userworkbases_str = include_str!("../../tests/test_data/validation/userworkbases.json");
+    let workbases: Vec<CkPoolMessage> = serde_json::from_str(&workbases_str).unwrap();
+    let shares: Vec<CkPoolMessage> = serde_json::from_str(&shares_str).unwrap();
+    let userworkbases: Vec<CkPoolMessage> = serde_json::from_str(&userworkbases_str).unwrap();
+    let workbases = workbases
+        .into_iter()
+        .filter_map(|msg| match msg {
+            CkPoolMessage::Workbase(w) => Some(w),
+            _ => None,
+        })
+        .collect::<Vec<MinerWorkbase>>();
+
+    let userworkbases = userworkbases
+        .into_iter()
+        .filter_map(|msg| match msg {
+            CkPoolMessage::UserWorkbase(w) => Some(w),
+            _ => None,
+        })
+        .collect::<Vec<UserWorkbase>>();
+
+    let shares = shares
+        .into_iter()
+        .filter_map(|msg| match msg {
+            CkPoolMessage::Share(s) => Some(s),
+            _ => None,
+        })
+        .collect::<Vec<MinerShare>>();
+
+    (workbases, userworkbases, shares)
+}
+
+/// A valid share built from the first workbase, user workbase and miner share of the validation test data,
+/// returned along with them. The miner share's ntime is when the share was mined, validation only accepts the
+/// share as recent around that time.
+pub fn valid_share_block() -> (ShareBlock, MinerWorkbase, UserWorkbase, MinerShare) {
+    let (workbases, userworkbases, shares) = load_valid_workbases_userworkbases_and_shares();
+    let pubkey = "020202020202020202020202020202020202020202020202020202020202020202"
+        .parse()
+        .unwrap();
+    let header = build_share_header(&workbases[0], &shares[0], &userworkbases[0], pubkey).unwrap();
+    let share_block =
+        build_share_block(&workbases[0], &userworkbases[0], &shares[0], header).unwrap();
+    (
+        share_block,
+        workbases[0].clone(),
+        userworkbases[0].clone(),
+        shares[0].clone(),
+    )
+}
@@ -0,0 +1,145 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::config::BackoffConfig;
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::time::Duration;
+
+/// Exponential backoff schedule for retrying a failing operation, e.g. dialing a peer.
+/// The delay starts at the base, grows by the multiplier after every retry and is capped.
+/// Each delay is shortened by a random fraction of up to jitter of itself, so nodes retrying
+/// after the same failure, e.g. a shared bootstrap peer restarting, spread their retries out.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    config: BackoffConfig,
+    attempt: u32,
+    /// Source of the jitter, forked from the node's rng so seeded runs retry on the same schedule
+    rng: StdRng,
+}
+
+impl Backoff {
+    pub fn new(config: BackoffConfig, rng: StdRng) -> Self {
+        Self {
+            config,
+            attempt: 0,
+            rng,
+        }
+    }
+
+    /// The delay to wait before the next retry, growing the delay for the retry after it
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = jittered_delay(&self.config, self.attempt, &mut self.rng);
+        self.attempt = self.attempt.saturating_add(1);
+        delay
+    }
+
+    /// Start the schedule from the base delay again, e.g. once the operation succeeds
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// Number of delays handed out since the schedule was last reset
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// The delay before retry number attempt, counting from 0, with jitter drawn from rng.
+    /// Always between (1 - jitter) times the capped delay and the capped delay.
+    pub fn delay_for_attempt(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        jittered_delay(&self.config, attempt, rng)
+    }
+}
+
+/// The delay before retry number attempt, shortened by a fraction of up to jitter drawn from rng
+fn jittered_delay(config: &BackoffConfig, attempt: u32, rng: &mut impl Rng) -> Duration {
+    let capped = capped_delay_millis(config, attempt);
+    let jitter = config.jitter.clamp(0.0, 1.0);
+    let shortened_by = if jitter > 0.0 {
+        rng.gen_range(0.0..=jitter)
+    } else {
+        0.0
+    };
+    Duration::from_millis((capped * (1.0 - shortened_by)) as u64)
+}
+
+/// The delay before retry number attempt without jitter, in milliseconds
+fn capped_delay_millis(config: &BackoffConfig, attempt: u32) -> f64 {
+    let cap = config.cap_millis as f64;
+    let growth = config
+        .multiplier
+        .max(1.0)
+        .powi(attempt.min(i32::MAX as u32) as i32);
+    // Large attempts overflow to infinity, which the cap brings back down
+    (config.base_millis as f64 * growth).min(cap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn config(jitter: f64) -> BackoffConfig {
+        BackoffConfig {
+            base_millis: 100,
+            cap_millis: 1_000,
+            multiplier: 2.0,
+            jitter,
+        }
+    }
+
+    #[test]
+    fn test_delays_grow_by_multiplier_up_to_cap_without_jitter() {
+        let mut backoff = Backoff::new(config(0.0), StdRng::seed_from_u64(7));
+        let delays: Vec<u64> = (0..6)
+            .map(|_| backoff.next_delay().as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1_000, 1_000]);
+        assert_eq!(backoff.attempt(), 6);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_jittered_delays_stay_within_bounds() {
+        let backoff = Backoff::new(config(0.5), StdRng::seed_from_u64(7));
+        let mut rng = StdRng::seed_from_u64(7);
+        for attempt in 0..20 {
+            let capped = capped_delay_millis(&backoff.config, attempt) as u64;
+            for _ in 0..50 {
+                let delay = backoff.delay_for_attempt(attempt, &mut rng).as_millis() as u64;
+                assert!(delay <= capped, "{delay} above {capped}");
+                assert!(delay >= capped / 2, "{delay} below {}", capped / 2);
+            }
+        }
+        // Attempts far past the cap don't overflow
+        assert!(backoff.delay_for_attempt(u32::MAX, &mut rng) <= Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn test_jitter_randomises_delays() {
+        let backoff = Backoff::new(config(0.5), StdRng::seed_from_u64(7));
+        let mut rng = StdRng::seed_from_u64(7);
+        let delays: std::collections::HashSet<Duration> = (0..20)
+            .map(|_| backoff.delay_for_attempt(3, &mut rng))
+            .collect();
+        assert!(
+            delays.len() > 1,
+            "Expected different delays, got {delays:?}"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::utils::time_provider::TimeProvider;
+use bitcoin::absolute::Time;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Source of the monotonic and wall clock time the node reads, so tests can control time instead of
+/// waiting on it. Every clone of a MockClock shares the same time, so the node, its rate limiter and the
+/// test all see time advance together.
+/// Every clock is a TimeProvider, the chain and share validation read the same clock as the node.
+pub trait Clock: Debug + Send + Sync {
+    /// Monotonic time, for timeouts, windows and eviction
+    fn instant(&self) -> Instant;
+
+    /// Wall clock time, for timestamps shared with peers
+    fn system_time(&self) -> SystemTime;
+
+    /// Set the wall clock time, ignored by clocks reading the real time
+    fn set_system_time(&self, _time: SystemTime) {}
+
+    fn millis_since_epoch(&self) -> u64 {
+        self.system_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn instant(&self) -> Instant {
+        (**self).instant()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        (**self).system_time()
+    }
+
+    fn set_system_time(&self, time: SystemTime) {
+        (**self).set_system_time(time)
+    }
+}
+
+impl<C: Clock + ?Sized> TimeProvider for C {
+    fn now(&self) -> SystemTime {
+        self.system_time()
+    }
+
+    fn set_time(&mut self, time: Time) {
+        self.set_system_time(UNIX_EPOCH + Duration::from_secs(time.to_consensus_u32() as u64));
+    }
+
+    fn seconds_since_epoch(&self) -> u64 {
+        self.system_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// Clock reading the real time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock that only moves when a test advances it
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<(Instant, SystemTime)>>,
+}
+
+impl MockClock {
+    /// A clock starting at the given wall clock time
+    pub fn new(system_time: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new((Instant::now(), system_time))),
+        }
+    }
+
+    /// Move both the monotonic and the wall clock time forward
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += by;
+        now.1 += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn instant(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+
+    /// Set the wall clock time only, monotonic time can't go back
+    fn set_system_time(&self, time: SystemTime) {
+        self.now.lock().unwrap().1 = time;
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// The reloadable filter layer installed at logging setup, paired with a LogLevelHandle to change it
+pub type LogFilterLayer = reload::Layer<EnvFilter, Registry>;
+
+/// Handle to query and change the log filter while the node is running
+#[derive(Clone)]
+pub struct LogLevelHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// The filter directives currently applied, EnvFilter doesn't give them back
+    level: Arc<Mutex<String>>,
+}
+
+impl LogLevelHandle {
+    /// Build a reloadable filter layer for level and the handle to change it later
+    pub fn new(level: &str) -> Result<(LogFilterLayer, Self), Box<dyn Error + Send + Sync>> {
+        let filter = EnvFilter::try_new(level)?;
+        let (layer, handle) = reload::Layer::new(filter);
+        Ok((
+            layer,
+            Self {
+                handle,
+                level: Arc::new(Mutex::new(level.to_string())),
+            },
+        ))
+    }
+
+    /// The filter directives currently applied
+    pub fn level(&self) -> String {
+        self.level.lock().unwrap().clone()
+    }
+
+    /// Replace the log filter with level, an invalid filter is rejected and the current one kept
+    pub fn set_level(&self, level: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let filter = EnvFilter::try_new(level)?;
+        let mut current = self.level.lock().unwrap();
+        self.handle.reload(filter)?;
+        *current = level.to_string();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Level;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_set_level_reloads_filter() {
+        let (layer, handle) = LogLevelHandle::new("info").unwrap();
+        let subscriber = Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(!tracing::enabled!(Level::DEBUG));
+
+            handle.set_level("debug").unwrap();
+            assert_eq!(handle.level(), "debug");
+            assert!(tracing::enabled!(Level::DEBUG));
+
+            assert!(handle.set_level("p2poolv2=loud").is_err());
+            assert_eq!(handle.level(), "debug");
+            assert!(tracing::enabled!(Level::DEBUG));
+        });
+    }
+
+    #[test]
+    fn test_new_rejects_invalid_level() {
+        assert!(LogLevelHandle::new("p2poolv2=loud").is_err());
+    }
+}
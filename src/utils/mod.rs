@@ -14,5 +14,9 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod backoff;
+pub mod clock;
+pub mod log_level;
+pub mod rng;
 pub mod serde_support;
 pub mod time_provider;
@@ -0,0 +1,104 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use libp2p::identity::Keypair;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Root of a node's internal randomness, e.g. the node identity, backoff jitter and sync peer tie breaking.
+/// Seeded from test_seed when one is configured, so multi node tests are reproducible, and from OS entropy otherwise.
+/// Each component gets its own rng forked from the root, so components don't draw from each other's sequence.
+#[derive(Debug)]
+pub struct NodeRng {
+    rng: StdRng,
+    seeded: bool,
+}
+
+impl NodeRng {
+    pub fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => Self {
+                rng: StdRng::seed_from_u64(seed),
+                seeded: true,
+            },
+            None => Self {
+                rng: StdRng::from_entropy(),
+                seeded: false,
+            },
+        }
+    }
+
+    /// A new rng for a component, determined by the seed and the number of forks made before it
+    pub fn fork(&mut self) -> StdRng {
+        StdRng::seed_from_u64(self.rng.gen())
+    }
+
+    /// The libp2p identity of the node. Derived from the seed when one is configured, so a seeded node has the
+    /// same peer id on every run. Generated by libp2p otherwise.
+    pub fn identity(&mut self) -> Keypair {
+        let mut secret: [u8; 32] = self.fork().gen();
+        if !self.seeded {
+            return Keypair::generate_ed25519();
+        }
+        Keypair::ed25519_from_bytes(&mut secret)
+            .expect("any 32 bytes are a valid ed25519 secret key")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_forks_same_sequences() {
+        let mut first = NodeRng::new(Some(42));
+        let mut second = NodeRng::new(Some(42));
+        for _ in 0..3 {
+            let a: Vec<u64> = first
+                .fork()
+                .sample_iter(rand::distributions::Standard)
+                .take(8)
+                .collect();
+            let b: Vec<u64> = second
+                .fork()
+                .sample_iter(rand::distributions::Standard)
+                .take(8)
+                .collect();
+            assert_eq!(a, b);
+        }
+
+        let mut other = NodeRng::new(Some(43));
+        assert_ne!(
+            NodeRng::new(Some(42)).fork().gen::<u64>(),
+            other.fork().gen::<u64>()
+        );
+    }
+
+    #[test]
+    fn test_same_seed_derives_same_identity() {
+        let peer_id = |seed| NodeRng::new(seed).identity().public().to_peer_id();
+        assert_eq!(peer_id(Some(42)), peer_id(Some(42)));
+        assert_ne!(peer_id(Some(42)), peer_id(Some(43)));
+        assert_ne!(peer_id(None), peer_id(None));
+
+        // The identity takes the first fork, components forking after it are still reproducible
+        let mut first = NodeRng::new(Some(42));
+        let mut second = NodeRng::new(Some(42));
+        first.identity();
+        second.identity();
+        assert_eq!(first.fork().gen::<u64>(), second.fork().gen::<u64>());
+    }
+}
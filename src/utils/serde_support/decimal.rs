@@ -0,0 +1,36 @@
+// Copyright (C) 2024, 2025 P2Poolv2 Developers (see AUTHORS)
+//
+//  This file is part of P2Poolv2
+//
+// P2Poolv2 is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// P2Poolv2 is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer};
+use std::str::FromStr;
+
+/// Deserialize a Decimal serialized as a string by binary formats, CBOR and bincode.
+/// Decimal's own Deserialize asks the format what it holds, which bincode can't answer. Human readable formats
+/// like the JSON ckpool sends keep using it, so numbers are still accepted there.
+pub fn deserialize_decimal<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    if deserializer.is_human_readable() {
+        return <Decimal as Deserialize>::deserialize(deserializer);
+    }
+    let decimal_str = String::deserialize(deserializer)?;
+    Decimal::from_str(&decimal_str)
+        .or_else(|_| Decimal::from_scientific(&decimal_str))
+        .map_err(|e| D::Error::custom(format!("Invalid decimal: {}", e)))
+}
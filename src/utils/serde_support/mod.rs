@@ -14,4 +14,7 @@
 // You should have received a copy of the GNU General Public License along with
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod backend;
+pub mod bincode;
+pub mod decimal;
 pub mod time;
@@ -18,6 +18,7 @@ use bitcoin::absolute::Time;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Trait to get current system time, allowing for mocking in tests
+/// Every Clock is a TimeProvider, production code reads the time from the node's clock.
 #[allow(dead_code)]
 pub trait TimeProvider {
     fn now(&self) -> SystemTime;
@@ -25,24 +26,6 @@ pub trait TimeProvider {
     fn seconds_since_epoch(&self) -> u64;
 }
 
-/// Default implementation that uses actual system time
-#[derive(Clone, Debug)]
-pub struct SystemTimeProvider;
-
-impl TimeProvider for SystemTimeProvider {
-    fn now(&self) -> SystemTime {
-        SystemTime::now()
-    }
-
-    fn set_time(&mut self, _time: Time) {
-        // No-op for production provider
-    }
-
-    fn seconds_since_epoch(&self) -> u64 {
-        self.now().duration_since(UNIX_EPOCH).unwrap().as_secs()
-    }
-}
-
 /// Mock time provider for testing
 #[derive(Clone, Debug)]
 pub struct TestTimeProvider(pub SystemTime);
@@ -65,6 +48,8 @@ impl TimeProvider for TestTimeProvider {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::utils::clock::{Clock, MockClock, SystemClock};
+    use std::sync::Arc;
     use std::time::{Duration, UNIX_EPOCH};
 
     #[test]
@@ -75,8 +60,22 @@ mod test {
     }
 
     #[test]
-    fn test_system_time_provider() {
-        let provider = SystemTimeProvider;
+    fn test_shared_mock_clock_is_a_time_provider() {
+        let clock = MockClock::new(UNIX_EPOCH + Duration::from_secs(1000));
+        let mut shared: Arc<dyn Clock> = Arc::new(clock.clone());
+        assert_eq!(shared.seconds_since_epoch(), 1000);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(shared.seconds_since_epoch(), 1005);
+
+        // Setting the time through the provider moves every clone of the clock
+        shared.set_time(Time::from_consensus(1653195600).unwrap());
+        assert_eq!(clock.seconds_since_epoch(), 1653195600);
+    }
+
+    #[test]
+    fn test_system_clock_time_provider() {
+        let provider = SystemClock;
 
         // Get current time from provider
         let provider_time = provider.now();
@@ -93,7 +92,7 @@ mod test {
         assert!(seconds > 1704067200);
 
         // Test set_time is no-op
-        let mut provider = SystemTimeProvider;
+        let mut provider = SystemClock;
         let before = provider.now();
         provider.set_time(Time::from_consensus(1653195600).unwrap()); // Value picked from rust-bitcoin docs
         let after = provider.now();
@@ -15,9 +15,15 @@
 // P2Poolv2. If not, see <https://www.gnu.org/licenses/>.
 
 use p2poolv2::config::{
-    BitcoinConfig, CkPoolConfig, Config, LoggingConfig, MinerConfig, NetworkConfig, StoreConfig,
+    BackoffConfig, BitcoinConfig, ChainConfig, CkPoolConfig, Config, GossipsubConfig,
+    LoggingConfig, MinerConfig, NetworkConfig, StoreConfig, DEFAULT_AGENT_VERSION,
 };
-use p2poolv2::shares::miner_message::MinerWorkbase;
+use p2poolv2::node::gossip_conformance::GossipAnomalyAction;
+use p2poolv2::shares::chain::payout::PayoutPolicy;
+use p2poolv2::shares::miner_message::builders::{build_share_block, build_share_header};
+use p2poolv2::shares::miner_message::{CkPoolMessage, MinerShare, MinerWorkbase, UserWorkbase};
+use p2poolv2::shares::ShareBlock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[cfg(test)]
 /// Build a default test configuration with test values that can be replaced later by each test
@@ -28,19 +34,61 @@ pub fn default_test_config() -> Config {
     Config {
         network: NetworkConfig {
             listen_address: "/ip4/127.0.0.1/tcp/6891".to_string(),
+            enable_ipv4: true,
+            enable_ipv6: true,
             dial_peers: vec![],
             enable_mdns: false,
+            require_discovery: false,
             max_pending_incoming: 10,
             max_pending_outgoing: 10,
             max_established_incoming: 50,
             max_established_outgoing: 50,
             max_established_per_peer: 3,
+            tcp_nodelay: true,
+            tcp_listen_backlog: 1024,
             max_workbase_per_second: 10,
             max_userworkbase_per_second: 10,
             max_miningshare_per_second: 100,
             max_inventory_per_second: 100,
             max_transaction_per_second: 100,
             rate_limit_window_secs: 1,
+            latency_threshold_ms: 500,
+            max_clock_skew_ms: 30_000,
+            auto_gossip: false,
+            observer: false,
+            watchdog_timeout_secs: 0,
+            max_dropped_responses_per_minute: 0,
+            min_healthy_peers: 0,
+            max_share_gap_secs: 0,
+            isolation_grace_period_secs: 0,
+            gossip_startup_buffer_secs: 0,
+            peer_idle_evict_secs: 0,
+            max_sync_sessions: 4,
+            max_advertised_addresses: 8,
+            share_validation_concurrency: 4,
+            message_processing_timeout: 5_000,
+            max_gossip_lag: 10,
+            sync_min_height_offset: 1000,
+            trusted_operator_keys: vec![],
+            allowed_peers: vec![],
+            gossip_anomaly_action: GossipAnomalyAction::Log,
+            drop_gossip_echoes: true,
+            measure_propagation_latency: false,
+            max_inflight_requests_per_peer: 8,
+            serialization_self_test: true,
+            allow_unsafe_ops: false,
+            agent_version: DEFAULT_AGENT_VERSION.to_string(),
+            test_seed: None,
+        },
+        gossipsub: GossipsubConfig {
+            flood_publish: true,
+            fanout_ttl_secs: 60,
+        },
+        backoff: BackoffConfig {
+            base_millis: 1000,
+            cap_millis: 60000,
+            multiplier: 2.0,
+            jitter: 0.5,
         },
         bitcoin: BitcoinConfig {
             network: bitcoin::Network::Regtest,
@@ -50,6 +98,23 @@ pub fn default_test_config() -> Config {
         },
         store: StoreConfig {
             path: "test_chain.db".to_string(),
+            max_disk_bytes: 0,
+            prune_low_water_bytes: 0,
+            prune_check_interval_secs: 60,
+            compaction_interval_secs: 0,
+            max_workbases: 0,
+        },
+        chain: ChainConfig {
+            max_side_branches: 16,
+            payout_policy: PayoutPolicy::Pplns,
+            payout_window: 1000,
+            max_reorg_depth: 100,
+            stable_tip_window_secs: 120,
+            stable_tip_max_reorgs: 0,
+            stable_tip_min_unchanged_secs: 0,
+            retarget_window: 0,
+            target_share_interval_secs: 10,
+            checkpoints: vec![],
         },
         ckpool: CkPoolConfig {
             host: "127.0.0.1".to_string(),
@@ -74,3 +139,17 @@ pub fn simple_miner_workbase() -> MinerWorkbase {
     let json_str = include_str!("../../tests/test_data/simple_miner_workbase.json");
     serde_json::from_str(json_str).unwrap()
 }
+
+#[allow(dead_code)]
+#[path = "../../src/test_utils/validation_data.rs"]
+mod validation_data;
+
+/// A share mined on the validation test data, with the workbase and user workbase it needs to validate
+/// and the time it was mined at, so nodes with a clock set to that time accept it as recent.
+#[allow(dead_code)]
+#[cfg(test)]
+pub fn valid_share_block() -> (ShareBlock, MinerWorkbase, UserWorkbase, SystemTime) {
+    let (share_block, workbase, user_workbase, miner_share) = validation_data::valid_share_block();
+    let mined_at = UNIX_EPOCH + Duration::from_secs(miner_share.ntime.to_consensus_u32() as u64);
+    (share_block, workbase, user_workbase, mined_at)
+}
@@ -103,3 +103,1953 @@ async fn test_three_nodes_connectivity() {
         .await
         .expect("Failed to shutdown node 3");
 }
+
+#[tokio::test]
+async fn test_network_quality_reports_ping_rtt_and_dial_failures() {
+    let config1 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6887".to_string())
+        .with_store_path("test_chain_quality_1.db".to_string());
+    // Dial one live peer and one address nobody listens on
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6888".to_string())
+        .with_store_path("test_chain_quality_2.db".to_string())
+        .with_dial_peers(vec![
+            "/ip4/127.0.0.1/tcp/6887".to_string(),
+            "/ip4/127.0.0.1/tcp/6889".to_string(),
+        ]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let quality = node2_handle
+        .get_network_quality()
+        .await
+        .expect("Failed to get network quality from node 2");
+
+    // Node 2 pings node 1 as soon as the connection is established
+    assert!(quality.median_rtt.is_some(), "Expected a ping round trip");
+    assert!(quality.p95_rtt.is_some(), "Expected a ping round trip");
+    assert_eq!(quality.peers_above_threshold, 0);
+    assert_eq!(quality.dial_failure_rate, 0.5);
+
+    node1_handle
+        .shutdown()
+        .await
+        .expect("Failed to shutdown node 1");
+    node2_handle
+        .shutdown()
+        .await
+        .expect("Failed to shutdown node 2");
+}
+
+#[tokio::test]
+async fn test_nodes_with_different_genesis_disconnect() {
+    let config1 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6895".to_string())
+        .with_store_path("test_chain_genesis_1.db".to_string());
+    let mut config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6896".to_string())
+        .with_store_path("test_chain_genesis_2.db".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6895".to_string()]);
+    config2.bitcoin.network = bitcoin::Network::Signet;
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let peers1 = node1_handle
+        .get_peers()
+        .await
+        .expect("Failed to get peers from node 1");
+    let peers2 = node2_handle
+        .get_peers()
+        .await
+        .expect("Failed to get peers from node 2");
+
+    assert!(peers1.is_empty(), "Node 1 should disconnect node 2");
+    assert!(peers2.is_empty(), "Node 2 should disconnect node 1");
+
+    node1_handle
+        .shutdown()
+        .await
+        .expect("Failed to shutdown node 1");
+    node2_handle
+        .shutdown()
+        .await
+        .expect("Failed to shutdown node 2");
+}
+
+#[tokio::test]
+async fn test_watchdog_keeps_idle_node_running() {
+    // An isolated node with no commands sent has nothing to process after it starts listening
+    let config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6897".to_string())
+        .with_watchdog_timeout_secs(3);
+
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, stop_rx) = NodeHandle::new(config, chain_handle)
+        .await
+        .expect("Failed to create node");
+
+    let stopped = tokio::time::timeout(Duration::from_secs(6), stop_rx).await;
+    assert!(
+        stopped.is_err(),
+        "Watchdog should not stop an idle node whose loop keeps going round"
+    );
+    assert!(node_handle.get_peers().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_find_closest_peers() {
+    let config1 = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6898".to_string());
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6899".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6898".to_string()]);
+    let config3 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6900".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6898".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let temp_dir3 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+    let chain_handle3 = ChainHandle::new(temp_dir3.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    let (node3_handle, _stop_rx3) = NodeHandle::new(config3, chain_handle3)
+        .await
+        .expect("Failed to create node 3");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let peers2 = node2_handle.get_peers().await.unwrap();
+    assert_eq!(peers2.len(), 1, "Node 2 should be connected to node 1");
+    let target = libp2p::PeerId::random();
+    let closest = tokio::time::timeout(
+        Duration::from_secs(10),
+        node2_handle.find_closest_peers(target),
+    )
+    .await
+    .expect("Closest peers lookup should complete")
+    .expect("Failed to find closest peers");
+    // Node 1 answers the lookup and tells node 2 about node 3
+    assert!(
+        closest.contains(&peers2[0]),
+        "Closest peers should include node 1"
+    );
+    assert_eq!(
+        closest.len(),
+        2,
+        "Closest peers should include node 1 and node 3"
+    );
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+    node3_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_peer_info_records_identified_protocols() {
+    let config1 = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6901".to_string());
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6902".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6901".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let peers2 = node2_handle.get_peers().await.unwrap();
+    assert_eq!(peers2.len(), 1, "Node 2 should be connected to node 1");
+
+    let peer_info = node2_handle
+        .get_peer_info(peers2[0])
+        .await
+        .expect("Failed to get peer info")
+        .expect("Node 1 should be tracked by node 2");
+    for protocol in ["/ipfs/id/1.0.0", "/ipfs/kad/1.0.0", "/p2pool/1.1.0"] {
+        assert!(
+            peer_info.protocols.iter().any(|p| p == protocol),
+            "Expected {} in {:?}",
+            protocol,
+            peer_info.protocols
+        );
+    }
+
+    let unknown = node2_handle
+        .get_peer_info(libp2p::PeerId::random())
+        .await
+        .unwrap();
+    assert!(unknown.is_none());
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_peer_info_records_configured_agent_version() {
+    let config1 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6922".to_string())
+        .with_agent_version("p2poolv2/test-deployment".to_string());
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6923".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6922".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let peers2 = node2_handle.get_peers().await.unwrap();
+    assert_eq!(peers2.len(), 1, "Node 2 should be connected to node 1");
+
+    let peer_info = node2_handle
+        .get_peer_info(peers2[0])
+        .await
+        .expect("Failed to get peer info")
+        .expect("Node 1 should be tracked by node 2");
+    assert_eq!(
+        peer_info.agent_version.as_deref(),
+        Some("p2poolv2/test-deployment")
+    );
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_trusted_announcement_is_delivered_to_subscribers() {
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use p2poolv2::node::announcement::sign_announcement;
+    use p2poolv2::node::events::NodeEvent;
+
+    let secret_key = SecretKey::from_slice(&[7; 32]).unwrap();
+    let operator_key = bitcoin::PublicKey::new(secret_key.public_key(&Secp256k1::signing_only()));
+
+    let config1 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6903".to_string())
+        .with_trusted_operator_keys(vec![operator_key]);
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6904".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6903".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    let mut events = node1_handle.subscribe_events().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    // Give gossipsub time to exchange topic subscriptions
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    let payload = "mandatory upgrade at block 1000".to_string();
+    let signature = sign_announcement(&payload, &secret_key);
+    node2_handle
+        .publish_announcement(payload.clone(), signature)
+        .await
+        .expect("Failed to publish announcement");
+
+    let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("Announcement should be delivered")
+        .unwrap();
+    assert_eq!(
+        event.event,
+        NodeEvent::Announcement {
+            payload,
+            signer: operator_key
+        }
+    );
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reload_config_applies_hot_fields_at_runtime() {
+    let config1 = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6905".to_string());
+    let config2 = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6906".to_string());
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1.clone(), chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    let (_node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(node1_handle.get_peers().await.unwrap().is_empty());
+
+    let reloaded = config1
+        .clone()
+        .with_max_gossip_lag(3)
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6906".to_string()])
+        .with_listen_address("/ip4/127.0.0.1/tcp/6907".to_string());
+    let reload = node1_handle.reload_config(reloaded.clone()).await.unwrap();
+    assert_eq!(
+        reload.applied,
+        vec!["network.dial_peers", "network.max_gossip_lag"]
+    );
+    assert_eq!(reload.requires_restart, vec!["network.listen_address"]);
+
+    // The newly added dial peer is dialed without a restart
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(node1_handle.get_peers().await.unwrap().len(), 1);
+
+    // Applied values are kept, only the listen address still differs
+    let reload = node1_handle.reload_config(reloaded).await.unwrap();
+    assert!(reload.applied.is_empty());
+    assert_eq!(reload.requires_restart, vec!["network.listen_address"]);
+
+    node1_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_node_exposes_the_config_it_was_built_with() {
+    let config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6930".to_string())
+        .with_max_gossip_lag(11);
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config.clone(), chain_handle)
+        .await
+        .expect("Failed to create node");
+
+    let effective = node_handle.get_effective_config().await.unwrap();
+    assert_eq!(effective, config.redacted());
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_announcement_handler_sees_reloaded_trusted_operator_keys() {
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use p2poolv2::node::announcement::sign_announcement;
+    use p2poolv2::node::events::NodeEvent;
+
+    let secret_key = SecretKey::from_slice(&[9; 32]).unwrap();
+    let operator_key = bitcoin::PublicKey::new(secret_key.public_key(&Secp256k1::signing_only()));
+
+    let config1 = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6955".to_string());
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6956".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6955".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1.clone(), chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    let mut events = node1_handle.subscribe_events().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    // Give gossipsub time to exchange topic subscriptions
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    // The operator key is not trusted yet, so the announcement is not surfaced
+    let payload = "before reload".to_string();
+    let signature = sign_announcement(&payload, &secret_key);
+    node2_handle
+        .publish_announcement(payload, signature)
+        .await
+        .expect("Failed to publish announcement");
+    let before = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            if let NodeEvent::Announcement { .. } = events.recv().await.unwrap().event {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(before.is_err());
+
+    let reload = node1_handle
+        .reload_config(config1.with_trusted_operator_keys(vec![operator_key]))
+        .await
+        .unwrap();
+    assert_eq!(reload.applied, vec!["network.trusted_operator_keys"]);
+
+    // The gossip handler reads the reloaded keys and now surfaces the announcement
+    let payload = "after reload".to_string();
+    let signature = sign_announcement(&payload, &secret_key);
+    node2_handle
+        .publish_announcement(payload.clone(), signature)
+        .await
+        .expect("Failed to publish announcement");
+    let event = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let event = events.recv().await.unwrap().event;
+            if let NodeEvent::Announcement { .. } = event {
+                return event;
+            }
+        }
+    })
+    .await
+    .expect("Announcement should be delivered after the reload");
+    assert_eq!(
+        event,
+        NodeEvent::Announcement {
+            payload,
+            signer: operator_key
+        }
+    );
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_effective_config_reflects_overrides_and_reloads() {
+    use p2poolv2::config::REDACTED;
+
+    let config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6924".to_string())
+        .with_max_gossip_lag(7)
+        .with_bitcoin_password("hunter2".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config.clone(), chain_handle)
+        .await
+        .expect("Failed to create node");
+
+    let effective = node_handle.get_effective_config().await.unwrap();
+    assert_eq!(effective.network.max_gossip_lag, 7);
+    assert_eq!(effective.bitcoin.password, REDACTED);
+    assert_eq!(effective.network, config.network);
+
+    // Hot reloaded fields show up, fields needing a restart keep their running value
+    node_handle
+        .reload_config(
+            config
+                .clone()
+                .with_max_gossip_lag(3)
+                .with_listen_address("/ip4/127.0.0.1/tcp/6925".to_string()),
+        )
+        .await
+        .unwrap();
+    let effective = node_handle.get_effective_config().await.unwrap();
+    assert_eq!(effective.network.max_gossip_lag, 3);
+    assert_eq!(effective.network.listen_address, "/ip4/127.0.0.1/tcp/6924");
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reindex_reports_progress_through_event_stream() {
+    use p2poolv2::node::events::NodeEvent;
+    use p2poolv2::shares::genesis::GENESIS_PUBLIC_KEY;
+    use p2poolv2::shares::ShareBlock;
+
+    let config = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6908".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let genesis = ShareBlock::build_genesis_for_network(
+        GENESIS_PUBLIC_KEY.parse().unwrap(),
+        bitcoin::Network::Signet,
+    );
+    chain_handle.add_share(genesis).await.unwrap();
+
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle)
+        .await
+        .expect("Failed to create node");
+    let mut events = node_handle.subscribe_events().await.unwrap();
+
+    node_handle.start_reindex().await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("Reindex progress should be published")
+        .unwrap();
+    assert_eq!(
+        event.event,
+        NodeEvent::ReindexProgress { done: 1, total: 1 }
+    );
+    let first_sequence = event.sequence;
+
+    // The finished reindex can't be cancelled, and a new one can be started
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!node_handle.cancel_reindex().await.unwrap());
+    node_handle.start_reindex().await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("Reindex progress should be published")
+        .unwrap();
+    assert_eq!(
+        event.event,
+        NodeEvent::ReindexProgress { done: 1, total: 1 }
+    );
+    assert_eq!(event.sequence, first_sequence + 1);
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_nodes_connect_over_ipv6_loopback() {
+    let config1 = default_test_config().with_listen_address("/ip6/::1/tcp/6909".to_string());
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6910".to_string())
+        .with_dial_peers(vec!["/ip6/::1/tcp/6909".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    assert_eq!(node1_handle.get_peers().await.unwrap().len(), 1);
+    assert_eq!(node2_handle.get_peers().await.unwrap().len(), 1);
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_wildcard_listen_continues_when_one_family_fails_to_bind() {
+    // Hold the IPv4 wildcard port so only the IPv6 listener can bind
+    let _ipv4_listener = std::net::TcpListener::bind("0.0.0.0:6911").unwrap();
+
+    let config1 = default_test_config().with_listen_address("/ip4/0.0.0.0/tcp/6911".to_string());
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6912".to_string())
+        .with_dial_peers(vec!["/ip6/::1/tcp/6911".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Node should start with only the IPv6 listener");
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    assert_eq!(node1_handle.get_peers().await.unwrap().len(), 1);
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_filtered_share_subscriber_only_receives_matching_shares() {
+    use futures::StreamExt;
+    use p2poolv2::node::share_subscriptions::ShareFilter;
+    use p2poolv2::shares::genesis::GENESIS_PUBLIC_KEY;
+    use p2poolv2::shares::{ShareBlock, ShareBlockHash};
+    use rust_decimal_macros::dec;
+
+    let miner1: bitcoin::PublicKey =
+        "020202020202020202020202020202020202020202020202020202020202020202"
+            .parse()
+            .unwrap();
+    let miner2: bitcoin::PublicKey =
+        "020202020202020202020202020202020202020202020202020202020202020203"
+            .parse()
+            .unwrap();
+    let config = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6913".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle.clone())
+        .await
+        .expect("Failed to create node");
+
+    let mut miner1_shares = node_handle
+        .subscribe_shares(
+            ShareFilter::default()
+                .with_miner_pubkey(miner1)
+                .with_min_difficulty(dec!(5.0)),
+        )
+        .await
+        .unwrap();
+
+    let genesis = ShareBlock::build_genesis_for_network(
+        GENESIS_PUBLIC_KEY.parse().unwrap(),
+        bitcoin::Network::Signet,
+    );
+    let child = |n: u64, prev: &ShareBlock, miner_pubkey, diff| {
+        let mut share = prev.clone();
+        share.header.prev_share_blockhash = prev.cached_blockhash;
+        share.header.miner_pubkey = miner_pubkey;
+        share.header.miner_share.diff = diff;
+        share.cached_blockhash = Some(ShareBlockHash::from(format!("{:064x}", n).as_str()));
+        share
+    };
+    let low_difficulty = child(1, &genesis, miner1, dec!(1.0));
+    let from_miner2 = child(2, &low_difficulty, miner2, dec!(10.0));
+    let from_miner1 = child(3, &from_miner2, miner1, dec!(10.0));
+    for share in [&genesis, &low_difficulty, &from_miner2, &from_miner1] {
+        chain_handle.add_share(share.clone()).await.unwrap();
+    }
+
+    // Genesis, the low difficulty share and the share from miner2 are filtered out
+    let share = tokio::time::timeout(Duration::from_secs(5), miner1_shares.next())
+        .await
+        .expect("Matching share should be streamed")
+        .unwrap();
+    assert_eq!(share.cached_blockhash, from_miner1.cached_blockhash);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), miner1_shares.next())
+            .await
+            .is_err()
+    );
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reorg_subscriber_receives_each_reorg_once() {
+    use p2poolv2::shares::genesis::GENESIS_PUBLIC_KEY;
+    use p2poolv2::shares::{ShareBlock, ShareBlockHash};
+    use rust_decimal_macros::dec;
+    use std::collections::HashSet;
+
+    let config = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6931".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle.clone())
+        .await
+        .expect("Failed to create node");
+    let mut reorgs = node_handle.subscribe_reorgs().await.unwrap();
+
+    let genesis = ShareBlock::build_genesis_for_network(
+        GENESIS_PUBLIC_KEY.parse().unwrap(),
+        bitcoin::Network::Signet,
+    );
+    let child = |n: u64, prev: &ShareBlock, diff| {
+        let mut share = prev.clone();
+        share.header.prev_share_blockhash = prev.cached_blockhash;
+        share.header.miner_share.diff = diff;
+        share.cached_blockhash = Some(ShareBlockHash::from(format!("{:064x}", n).as_str()));
+        share
+    };
+    // Extending the chain isn't a reorg, the heavier fork from genesis is
+    let main = child(1, &genesis, dec!(1.0));
+    let fork = child(2, &genesis, dec!(5.0));
+    for share in [&genesis, &main, &fork] {
+        chain_handle.add_share(share.clone()).await.unwrap();
+    }
+
+    let reorg = tokio::time::timeout(Duration::from_secs(5), reorgs.recv())
+        .await
+        .expect("Reorg should be delivered")
+        .unwrap();
+    assert_eq!(reorg.old_tip, main.cached_blockhash.unwrap());
+    assert_eq!(reorg.new_tip, fork.cached_blockhash.unwrap());
+    assert_eq!(reorg.orphaned, vec![main.cached_blockhash.unwrap()]);
+    assert_eq!(reorg.promoted, vec![fork.cached_blockhash.unwrap()]);
+    assert_eq!(
+        reorg.affected_miners,
+        HashSet::from([bitcoin::Address::p2pkh(
+            genesis.header.miner_pubkey,
+            bitcoin::Network::Signet
+        )])
+    );
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), reorgs.recv())
+            .await
+            .is_err()
+    );
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_load_heavier_snapshot_updates_chain_tip() {
+    use p2poolv2::shares::chain::snapshot::{ChainSnapshot, SnapshotError};
+    use p2poolv2::shares::genesis::GENESIS_PUBLIC_KEY;
+    use p2poolv2::shares::ShareBlock;
+    use rust_decimal_macros::dec;
+
+    let config = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6914".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().join("store").display().to_string());
+    let genesis = ShareBlock::build_genesis_for_network(
+        GENESIS_PUBLIC_KEY.parse().unwrap(),
+        bitcoin::Network::Signet,
+    );
+    let child = |prev: &ShareBlock, diff| {
+        let mut share = prev.clone();
+        share.header.prev_share_blockhash = prev.cached_blockhash;
+        share.header.miner_share.diff = diff;
+        share.compute_blockhash();
+        share
+    };
+    let current = child(&genesis, dec!(1.0));
+    chain_handle.add_share(genesis.clone()).await.unwrap();
+    chain_handle.add_share(current.clone()).await.unwrap();
+
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle.clone())
+        .await
+        .expect("Failed to create node");
+
+    let snapshot1 = child(&genesis, dec!(2.0));
+    let snapshot2 = child(&snapshot1, dec!(2.0));
+    let heavier_path = temp_dir.path().join("heavier.snapshot");
+    let heavier = ChainSnapshot {
+        shares: vec![genesis.clone(), snapshot1, snapshot2.clone()],
+    };
+    std::fs::write(&heavier_path, heavier.cbor_serialize().unwrap()).unwrap();
+
+    node_handle
+        .load_snapshot(heavier_path.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        chain_handle.get_chain_tip().await,
+        snapshot2.cached_blockhash
+    );
+    assert_eq!(chain_handle.get_tip_height().await, Some(2));
+
+    // Loading the same snapshot again is rejected, as it isn't heavier than the chain now
+    let err = node_handle.load_snapshot(heavier_path).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<SnapshotError>(),
+        Some(SnapshotError::NotHeavier { .. })
+    ));
+    let err = node_handle
+        .load_snapshot(temp_dir.path().join("missing.snapshot"))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<SnapshotError>(),
+        Some(SnapshotError::Read(_))
+    ));
+    assert_eq!(
+        chain_handle.get_chain_tip().await,
+        snapshot2.cached_blockhash
+    );
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_peer_inventory_is_retrievable_after_peer_sends_it() {
+    use p2poolv2::node::messages::{InventoryMessage, Message};
+
+    let config1 = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6915".to_string());
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6916".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6915".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let peers1 = node1_handle.get_peers().await.unwrap();
+    assert_eq!(peers1.len(), 1, "Node 1 should be connected to node 2");
+    let peers2 = node2_handle.get_peers().await.unwrap();
+    assert_eq!(peers2.len(), 1, "Node 2 should be connected to node 1");
+    assert_eq!(
+        node1_handle.get_peer_inventory(peers1[0]).await.unwrap(),
+        None
+    );
+
+    let inventory = InventoryMessage::BlockHashes(vec![
+        "0000000086704a35f17580d06f76d4c02d2b1f68774800675fb45f0411205bb5".into(),
+    ]);
+    node2_handle
+        .send_to_peer(peers2[0], Message::Inventory(inventory.clone()))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    assert_eq!(
+        node1_handle.get_peer_inventory(peers1[0]).await.unwrap(),
+        Some(inventory)
+    );
+    assert_eq!(
+        node1_handle
+            .get_peer_inventory(libp2p::PeerId::random())
+            .await
+            .unwrap(),
+        None
+    );
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_outbound_failure_is_counted_in_metrics() {
+    use p2poolv2::node::messages::{InventoryMessage, Message};
+
+    let config = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6917".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle)
+        .await
+        .expect("Failed to create node");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(
+        node_handle.get_metrics().await.unwrap().outbound_failures,
+        0
+    );
+
+    // We know no address for a random peer, so the request fails to dial
+    node_handle
+        .send_to_peer(
+            libp2p::PeerId::random(),
+            Message::Inventory(InventoryMessage::BlockHashes(vec![])),
+        )
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let metrics = node_handle.get_metrics().await.unwrap();
+    assert_eq!(metrics.outbound_failures, 1);
+    assert_eq!(metrics.inbound_failures, 0);
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reopen_store_keeps_reads_working() {
+    use p2poolv2::shares::genesis::GENESIS_PUBLIC_KEY;
+    use p2poolv2::shares::ShareBlock;
+
+    let config = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6918".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().join("store").display().to_string());
+    let genesis = ShareBlock::build_genesis_for_network(
+        GENESIS_PUBLIC_KEY.parse().unwrap(),
+        bitcoin::Network::Signet,
+    );
+    chain_handle.add_share(genesis.clone()).await.unwrap();
+
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle.clone())
+        .await
+        .expect("Failed to create node");
+
+    node_handle.reopen_store().await.unwrap();
+
+    assert_eq!(
+        chain_handle
+            .get_share(genesis.cached_blockhash.unwrap())
+            .await,
+        Some(genesis.clone())
+    );
+    assert_eq!(chain_handle.get_chain_tip().await, genesis.cached_blockhash);
+    // The swarm kept running while the store was reopened
+    assert!(node_handle.get_peers().await.unwrap().is_empty());
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_isolated_node_redials_dial_peers() {
+    use p2poolv2::node::events::NodeEvent;
+
+    let config1 = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6919".to_string());
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6920".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6919".to_string()])
+        .with_isolation_grace_period_secs(1);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(node2_handle.get_peers().await.unwrap().len(), 1);
+
+    let mut events = node2_handle.subscribe_events().await.unwrap();
+    // Shutting down node 1 disconnects node 2's last peer
+    node1_handle.shutdown().await.unwrap();
+
+    let event = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let NodeEvent::Isolated { dialed } = events.recv().await.unwrap().event {
+                return dialed;
+            }
+        }
+    })
+    .await
+    .expect("Node 2 should recover from isolation");
+    assert_eq!(event, 1);
+    assert!(node2_handle.get_peers().await.unwrap().is_empty());
+
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_node_without_peers_since_startup_recovers_from_isolation() {
+    use p2poolv2::node::events::NodeEvent;
+
+    // Nothing listens on the dial peer's address, so no peer ever connects
+    let config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6942".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6943".to_string()])
+        .with_isolation_grace_period_secs(1);
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle)
+        .await
+        .expect("Failed to create node");
+    let mut events = node_handle.subscribe_events().await.unwrap();
+
+    let dialed = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let NodeEvent::Isolated { dialed } = events.recv().await.unwrap().event {
+                return dialed;
+            }
+        }
+    })
+    .await
+    .expect("Node should recover from isolation without ever having a peer");
+    assert_eq!(dialed, 1);
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_set_log_level_at_runtime() {
+    use p2poolv2::utils::log_level::LogLevelHandle;
+
+    let config = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6921".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    // Hold on to the layer, the handle can only reload a filter layer that is still alive
+    let (_layer, log_level) = LogLevelHandle::new("info").unwrap();
+    let (node_handle, _stop_rx) =
+        NodeHandle::new_with_log_level(config, chain_handle, log_level.clone())
+            .await
+            .expect("Failed to create node");
+
+    assert_eq!(
+        node_handle.get_log_level().await.unwrap(),
+        Some("info".to_string())
+    );
+    node_handle
+        .set_log_level("info,p2poolv2::node=debug".to_string())
+        .await
+        .unwrap();
+    assert_eq!(log_level.level(), "info,p2poolv2::node=debug");
+    assert!(node_handle
+        .set_log_level("p2poolv2=loud".to_string())
+        .await
+        .is_err());
+    assert_eq!(
+        node_handle.get_log_level().await.unwrap(),
+        Some("info,p2poolv2::node=debug".to_string())
+    );
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reload_config_applies_log_level_through_reload_handle() {
+    use p2poolv2::utils::log_level::LogLevelHandle;
+
+    let config = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6941".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    // Hold on to the layer, the handle can only reload a filter layer that is still alive
+    let (_layer, log_level) = LogLevelHandle::new(&config.logging.level).unwrap();
+    let (node_handle, _stop_rx) =
+        NodeHandle::new_with_log_level(config.clone(), chain_handle, log_level.clone())
+            .await
+            .expect("Failed to create node");
+
+    let mut reloaded = config.clone();
+    reloaded.logging.level = "info,p2poolv2::node=trace".to_string();
+    let reload = node_handle.reload_config(reloaded).await.unwrap();
+    assert_eq!(reload.applied, vec!["logging.level"]);
+    assert!(reload.requires_restart.is_empty());
+    assert_eq!(log_level.level(), "info,p2poolv2::node=trace");
+    assert_eq!(
+        node_handle
+            .get_effective_config()
+            .await
+            .unwrap()
+            .logging
+            .level,
+        "info,p2poolv2::node=trace"
+    );
+
+    // A filter the logger can't parse keeps the current level
+    let mut invalid = config.clone();
+    invalid.logging.level = "p2poolv2=loud".to_string();
+    let reload = node_handle.reload_config(invalid).await.unwrap();
+    assert!(reload.applied.is_empty());
+    assert_eq!(reload.requires_restart, vec!["logging.level"]);
+    assert_eq!(log_level.level(), "info,p2poolv2::node=trace");
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_observer_refuses_local_shares_and_keeps_received_shares() {
+    use common::valid_share_block;
+    use p2poolv2::shares::add_share::{AddShareError, AddShareOutcome};
+    use p2poolv2::shares::store::ShareProvenance;
+    use p2poolv2::utils::clock::MockClock;
+    use std::sync::Arc;
+
+    // Both nodes read a clock set to when the share was mined, so they take it as recent
+    let (share, workbase, user_workbase, mined_at) = valid_share_block();
+    let blockhash = share.cached_blockhash.unwrap();
+    let clock = MockClock::new(mined_at);
+
+    let producer_config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6926".to_string())
+        .with_auto_gossip(true);
+    let observer_config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6957".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6926".to_string()])
+        .with_auto_gossip(true)
+        .with_observer(true);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let producer_chain = ChainHandle::new_with_clock(
+        temp_dir1.path().to_str().unwrap().to_string(),
+        Arc::new(clock.clone()),
+    );
+    let observer_chain = ChainHandle::new_with_clock(
+        temp_dir2.path().to_str().unwrap().to_string(),
+        Arc::new(clock.clone()),
+    );
+    for chain_handle in [&producer_chain, &observer_chain] {
+        chain_handle.add_workbase(workbase.clone()).await.unwrap();
+        chain_handle
+            .add_user_workbase(user_workbase.clone())
+            .await
+            .unwrap();
+    }
+
+    let (producer, _stop_rx1) =
+        NodeHandle::new_with_clock(producer_config, producer_chain, Arc::new(clock.clone()))
+            .await
+            .expect("Failed to create producer node");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (observer, _stop_rx2) = NodeHandle::new_with_clock(
+        observer_config,
+        observer_chain.clone(),
+        Arc::new(clock.clone()),
+    )
+    .await
+    .expect("Failed to create observer node");
+    // Give gossipsub time to exchange topic subscriptions
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let producer_id = observer.get_peers().await.unwrap()[0];
+
+    // The observer refuses the share on every local submission path
+    let refused = AddShareOutcome::Rejected(AddShareError::Observer);
+    assert_eq!(observer.add_share(share.clone()).await.unwrap(), refused);
+    assert_eq!(
+        observer
+            .add_share_local(share.clone(), false)
+            .await
+            .unwrap(),
+        refused
+    );
+    assert_eq!(
+        observer.add_share_batch(vec![share.clone()]).await.unwrap(),
+        vec![refused]
+    );
+    assert_eq!(observer_chain.get_share(blockhash).await, None);
+
+    // The same share mined by the producer reaches the observer over gossip and is stored
+    assert_eq!(
+        producer.add_share(share).await.unwrap(),
+        AddShareOutcome::AcceptedMain
+    );
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while observer_chain.get_share(blockhash).await.is_none() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("Gossiped share should be stored by the observer");
+    assert_eq!(observer_chain.get_chain_tip().await, Some(blockhash));
+    assert_eq!(
+        observer.get_share_provenance(blockhash).await.unwrap(),
+        Some(ShareProvenance::Peer(producer_id))
+    );
+
+    producer.shutdown().await.unwrap();
+    observer.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_gossiped_share_propagation_latency_is_recorded() {
+    use common::valid_share_block;
+    use p2poolv2::shares::add_share::AddShareOutcome;
+    use p2poolv2::utils::clock::MockClock;
+    use std::sync::Arc;
+
+    // The receiver's clock is 250ms ahead of the origin's when the share arrives
+    let (share, workbase, user_workbase, mined_at) = valid_share_block();
+    let blockhash = share.cached_blockhash.unwrap();
+    let origin_clock = MockClock::new(mined_at);
+    let receiver_clock = MockClock::new(mined_at + Duration::from_millis(250));
+
+    let origin_config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6958".to_string())
+        .with_auto_gossip(true)
+        .with_measure_propagation_latency(true);
+    let receiver_config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6959".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6958".to_string()])
+        .with_measure_propagation_latency(true);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let origin_chain = ChainHandle::new_with_clock(
+        temp_dir1.path().to_str().unwrap().to_string(),
+        Arc::new(origin_clock.clone()),
+    );
+    let receiver_chain = ChainHandle::new_with_clock(
+        temp_dir2.path().to_str().unwrap().to_string(),
+        Arc::new(receiver_clock.clone()),
+    );
+    for chain_handle in [&origin_chain, &receiver_chain] {
+        chain_handle.add_workbase(workbase.clone()).await.unwrap();
+        chain_handle
+            .add_user_workbase(user_workbase.clone())
+            .await
+            .unwrap();
+    }
+
+    let (origin, _stop_rx1) =
+        NodeHandle::new_with_clock(origin_config, origin_chain, Arc::new(origin_clock))
+            .await
+            .expect("Failed to create origin node");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (receiver, _stop_rx2) = NodeHandle::new_with_clock(
+        receiver_config,
+        receiver_chain.clone(),
+        Arc::new(receiver_clock),
+    )
+    .await
+    .expect("Failed to create receiving node");
+    // Give gossipsub time to exchange topic subscriptions
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    assert_eq!(
+        origin.add_share(share).await.unwrap(),
+        AddShareOutcome::AcceptedMain
+    );
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while receiver_chain.get_share(blockhash).await.is_none() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("Gossiped share should be accepted by the receiving node");
+
+    let latency = receiver.get_metrics().await.unwrap().propagation_latency;
+    assert_eq!(latency.count, 1);
+    assert_eq!(latency.sum_ms, 250);
+    assert_eq!(latency.clock_ahead, 0);
+    // The origin doesn't record latency for its own share
+    assert_eq!(
+        origin
+            .get_metrics()
+            .await
+            .unwrap()
+            .propagation_latency
+            .count,
+        0
+    );
+
+    origin.shutdown().await.unwrap();
+    receiver.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_set_tip_is_refused_without_unsafe_ops() {
+    use p2poolv2::shares::genesis::GENESIS_PUBLIC_KEY;
+    use p2poolv2::shares::ShareBlock;
+
+    let config = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6927".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let genesis = ShareBlock::build_genesis_for_network(
+        GENESIS_PUBLIC_KEY.parse().unwrap(),
+        bitcoin::Network::Signet,
+    );
+
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle.clone())
+        .await
+        .expect("Failed to create node");
+
+    chain_handle.add_share(genesis.clone()).await.unwrap();
+    let err = node_handle
+        .set_tip(genesis.cached_blockhash.unwrap())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("allow_unsafe_ops"));
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_bootstrap_with_empty_routing_table_redials_dial_peers() {
+    use p2poolv2::node::events::NodeEvent;
+
+    // Nothing listens on the dial peer, so the routing table stays empty
+    let config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6928".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6929".to_string()]);
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle)
+        .await
+        .expect("Failed to create node");
+    let mut events = node_handle.subscribe_events().await.unwrap();
+    // Let the dial from startup fail first, a dial peer isn't re-dialed while a dial to it is in flight
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    node_handle.bootstrap_kademlia().await.unwrap();
+
+    let dialed = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let NodeEvent::BootstrapFailed { dialed } = events.recv().await.unwrap().event {
+                return dialed;
+            }
+        }
+    })
+    .await
+    .expect("Bootstrap failure should re-dial the dial peers");
+    assert_eq!(dialed, 1);
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_repeated_bootstrap_failures_redial_on_the_backoff_schedule() {
+    use p2poolv2::config::BackoffConfig;
+    use p2poolv2::node::events::NodeEvent;
+
+    let config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6944".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6945".to_string()])
+        .with_backoff(BackoffConfig {
+            base_millis: 60_000,
+            cap_millis: 60_000,
+            multiplier: 1.0,
+            jitter: 0.0,
+        });
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle)
+        .await
+        .expect("Failed to create node");
+    let mut events = node_handle.subscribe_events().await.unwrap();
+
+    // The first failure re-dials at once, the second falls within the backoff and re-dials later
+    node_handle.bootstrap_kademlia().await.unwrap();
+    node_handle.bootstrap_kademlia().await.unwrap();
+
+    let mut failures = 0;
+    let _ = tokio::time::timeout(Duration::from_secs(3), async {
+        loop {
+            if let NodeEvent::BootstrapFailed { .. } = events.recv().await.unwrap().event {
+                failures += 1;
+            }
+        }
+    })
+    .await;
+    assert_eq!(failures, 1);
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_repeatedly_dropped_responses_degrade_health() {
+    use p2poolv2::node::health::HealthStatus;
+
+    let config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6932".to_string())
+        .with_max_dropped_responses_per_minute(3);
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle)
+        .await
+        .expect("Failed to create node");
+    assert_eq!(
+        node_handle.get_health().await.unwrap(),
+        HealthStatus::Healthy
+    );
+
+    // Each command is sent, then the caller gives up before the node responds
+    for _ in 0..4 {
+        assert!(
+            tokio::time::timeout(Duration::ZERO, node_handle.get_metrics())
+                .await
+                .is_err()
+        );
+    }
+    assert_eq!(
+        node_handle.get_health().await.unwrap(),
+        HealthStatus::Degraded {
+            dropped_responses: 4
+        }
+    );
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_dialing_a_peer_counts_a_dial_attempt() {
+    // Nothing listens on the dial peer's port, the attempt is counted even though the dial fails
+    let config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6933".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6934".to_string()]);
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle)
+        .await
+        .expect("Failed to create node");
+
+    let metrics = node_handle.get_metrics().await.unwrap();
+    assert!(metrics.dial_attempts >= 1);
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_time_since_last_share_is_tracked_after_adding_a_share() {
+    use p2poolv2::shares::genesis::GENESIS_PUBLIC_KEY;
+    use p2poolv2::shares::ShareBlock;
+
+    let config = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6935".to_string());
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle.clone())
+        .await
+        .expect("Failed to create node");
+    assert_eq!(node_handle.time_since_last_share().await.unwrap(), None);
+
+    let genesis = ShareBlock::build_genesis_for_network(
+        GENESIS_PUBLIC_KEY.parse().unwrap(),
+        bitcoin::Network::Signet,
+    );
+    chain_handle.add_share(genesis).await.unwrap();
+
+    // The node learns of the accepted share from the chain in the background
+    let elapsed = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(elapsed) = node_handle.time_since_last_share().await.unwrap() {
+                return elapsed;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("Accepted share should be tracked");
+    assert!(elapsed < Duration::from_secs(5));
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_nodes_connect_with_custom_tcp_socket_options() {
+    let config1 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6936".to_string())
+        .with_tcp_nodelay(true)
+        .with_tcp_listen_backlog(16);
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6937".to_string())
+        .with_tcp_nodelay(false)
+        .with_tcp_listen_backlog(1)
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6936".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    assert_eq!(node1_handle.get_peers().await.unwrap().len(), 1);
+    assert_eq!(node2_handle.get_peers().await.unwrap().len(), 1);
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_node_without_discovery_path_fails_startup_when_discovery_is_required() {
+    let config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6938".to_string())
+        .with_dial_peers(vec![])
+        .with_enable_mdns(false)
+        .with_require_discovery(true);
+    assert!(!config.network.has_discovery_path());
+
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    assert!(NodeHandle::new(config.clone(), chain_handle).await.is_err());
+
+    // Without require_discovery the node only warns and starts
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) =
+        NodeHandle::new(config.with_require_discovery(false), chain_handle)
+            .await
+            .expect("Failed to create node");
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_set_peer_priority_for_connected_peer() {
+    let config1 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6939".to_string())
+        .with_peer_idle_evict_secs(2);
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6940".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6939".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let peers = node1_handle.get_peers().await.unwrap();
+    assert_eq!(peers.len(), 1);
+    node1_handle.set_peer_priority(peers[0], 10).await.unwrap();
+
+    // The prioritized peer is not evicted for being idle
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    assert_eq!(node1_handle.get_peers().await.unwrap(), peers);
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_high_priority_peer_is_chosen_for_sync_over_default_peer() {
+    use p2poolv2::node::messages::Message;
+    use p2poolv2::shares::genesis::GENESIS_PUBLIC_KEY;
+    use p2poolv2::shares::ShareBlock;
+    use rust_decimal_macros::dec;
+
+    let genesis = ShareBlock::build_genesis_for_network(
+        GENESIS_PUBLIC_KEY.parse().unwrap(),
+        bitcoin::Network::Signet,
+    );
+    let child = |prev: &ShareBlock, diff| {
+        let mut share = prev.clone();
+        share.header.prev_share_blockhash = prev.cached_blockhash;
+        share.header.miner_share.diff = diff;
+        share.compute_blockhash();
+        share
+    };
+    let heavy = child(&genesis, dec!(2.0));
+    let light = child(&genesis, dec!(0.5));
+
+    let syncing_config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6960".to_string())
+        .with_allow_unsafe_ops(true);
+    let peer_config = |port: u16| {
+        default_test_config()
+            .with_listen_address(format!("/ip4/127.0.0.1/tcp/{}", port))
+            .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6960".to_string()])
+    };
+
+    // Both peers have the heavy chain, the syncing node has it too so connecting doesn't start a sync
+    let temp_dirs = [tempdir().unwrap(), tempdir().unwrap(), tempdir().unwrap()];
+    let chains: Vec<ChainHandle> = temp_dirs
+        .iter()
+        .map(|dir| ChainHandle::new(dir.path().to_str().unwrap().to_string()))
+        .collect();
+    for chain_handle in &chains {
+        chain_handle.add_share(genesis.clone()).await.unwrap();
+        chain_handle.add_share(heavy.clone()).await.unwrap();
+    }
+    chains[0].add_share(light.clone()).await.unwrap();
+
+    let (syncing, _stop_rx1) = NodeHandle::new(syncing_config, chains[0].clone())
+        .await
+        .expect("Failed to create syncing node");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (priority_peer, _stop_rx2) = NodeHandle::new(peer_config(6961), chains[1].clone())
+        .await
+        .expect("Failed to create priority peer");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let priority_peer_id = syncing.get_peers().await.unwrap()[0];
+    let (default_peer, _stop_rx3) = NodeHandle::new(peer_config(6962), chains[2].clone())
+        .await
+        .expect("Failed to create default peer");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(syncing.get_peers().await.unwrap().len(), 2);
+    let syncing_id = default_peer
+        .get_peers()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|peer_id| *peer_id != priority_peer_id)
+        .unwrap();
+
+    syncing
+        .set_peer_priority(priority_peer_id, 10)
+        .await
+        .unwrap();
+    // Fall back to the light tip, so both peers now have more work than the syncing node
+    syncing
+        .set_tip(light.cached_blockhash.unwrap())
+        .await
+        .unwrap();
+    let priority_responses = priority_peer.get_metrics().await.unwrap().responses_sent;
+    let default_responses = default_peer.get_metrics().await.unwrap().responses_sent;
+
+    // The default peer sends its chain state, but the syncing node requests the chain from the priority peer
+    default_peer
+        .send_to_peer(
+            syncing_id,
+            Message::ChainState {
+                tip: heavy.cached_blockhash,
+                work: dec!(3.0),
+                height: Some(1),
+            },
+        )
+        .await
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while priority_peer.get_metrics().await.unwrap().responses_sent == priority_responses {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("The priority peer should be asked for the chain");
+    assert_eq!(
+        default_peer.get_metrics().await.unwrap().responses_sent,
+        default_responses
+    );
+
+    syncing.shutdown().await.unwrap();
+    priority_peer.shutdown().await.unwrap();
+    default_peer.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_node_syncs_a_multi_page_chain_of_historical_shares_from_a_peer() {
+    use common::valid_share_block;
+    use p2poolv2::shares::ShareBlock;
+
+    // The fixture share was mined long ago, far outside the drift allowed for gossiped shares
+    let (root, workbase, user_workbase, _) = valid_share_block();
+    let child = |prev: &ShareBlock| {
+        let mut share = prev.clone();
+        share.header.prev_share_blockhash = prev.cached_blockhash;
+        share.compute_blockhash();
+        share
+    };
+    // More shares than fit in one chain page
+    let mut shares = vec![root];
+    for _ in 0..510 {
+        let share = child(shares.last().unwrap());
+        shares.push(share);
+    }
+
+    let serving_config =
+        default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6963".to_string());
+    let syncing_config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6964".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6963".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let serving_chain = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let syncing_chain = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+    for chain_handle in [&serving_chain, &syncing_chain] {
+        chain_handle.add_workbase(workbase.clone()).await.unwrap();
+        chain_handle
+            .add_user_workbase(user_workbase.clone())
+            .await
+            .unwrap();
+    }
+    syncing_chain.add_share(shares[0].clone()).await.unwrap();
+    for share in &shares {
+        serving_chain.add_share(share.clone()).await.unwrap();
+    }
+    let tip = shares.last().unwrap().cached_blockhash;
+    assert_eq!(serving_chain.get_chain_tip().await, tip);
+
+    let (serving, _stop_rx1) = NodeHandle::new(serving_config, serving_chain)
+        .await
+        .expect("Failed to create serving node");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (syncing, _stop_rx2) = NodeHandle::new(syncing_config, syncing_chain.clone())
+        .await
+        .expect("Failed to create syncing node");
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while syncing_chain.get_chain_tip().await != tip {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("The syncing node should sync every page of the peer's chain");
+    assert_eq!(syncing_chain.get_tip_height().await, Some(510));
+
+    serving.shutdown().await.unwrap();
+    syncing.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_share_batch_larger_than_the_swarm_channel_is_gossiped_in_full() {
+    use common::valid_share_block;
+    use p2poolv2::shares::add_share::AddShareOutcome;
+    use p2poolv2::shares::ShareBlock;
+    use p2poolv2::utils::clock::MockClock;
+    use std::sync::Arc;
+
+    let (root, workbase, user_workbase, mined_at) = valid_share_block();
+    let clock = MockClock::new(mined_at);
+    let child = |prev: &ShareBlock| {
+        let mut share = prev.clone();
+        share.header.prev_share_blockhash = prev.cached_blockhash;
+        share.compute_blockhash();
+        share
+    };
+    // More shares than the 100 messages the swarm channel holds
+    let mut shares = vec![root];
+    for _ in 0..149 {
+        let share = child(shares.last().unwrap());
+        shares.push(share);
+    }
+
+    let origin_config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6965".to_string())
+        .with_auto_gossip(true);
+    let receiver_config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6966".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6965".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let origin_chain = ChainHandle::new_with_clock(
+        temp_dir1.path().to_str().unwrap().to_string(),
+        Arc::new(clock.clone()),
+    );
+    origin_chain.add_workbase(workbase).await.unwrap();
+    origin_chain.add_user_workbase(user_workbase).await.unwrap();
+    let receiver_chain = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (origin, _stop_rx1) =
+        NodeHandle::new_with_clock(origin_config, origin_chain, Arc::new(clock))
+            .await
+            .expect("Failed to create origin node");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let (receiver, _stop_rx2) = NodeHandle::new(receiver_config, receiver_chain)
+        .await
+        .expect("Failed to create receiving node");
+    // Give gossipsub time to exchange topic subscriptions
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    let outcomes = origin.add_share_batch(shares.clone()).await.unwrap();
+    assert_eq!(outcomes, vec![AddShareOutcome::AcceptedMain; shares.len()]);
+    let published: u64 = origin
+        .get_metrics()
+        .await
+        .unwrap()
+        .topics
+        .values()
+        .map(|topic| topic.messages_published)
+        .sum();
+    assert_eq!(published, shares.len() as u64);
+
+    origin.shutdown().await.unwrap();
+    receiver.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_gossip_sent_before_any_peer_joins_is_published_once_one_does() {
+    use common::simple_miner_workbase;
+    use p2poolv2::node::messages::Message;
+
+    let config1 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6946".to_string())
+        .with_gossip_startup_buffer_secs(30);
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6947".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6946".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+
+    // Nobody is on the share topic yet, the workbase is buffered instead of failing to publish
+    let workbase = simple_miner_workbase();
+    node1_handle
+        .send_gossip(Message::Workbase(workbase.clone()))
+        .await
+        .expect("Gossip should be buffered during startup");
+
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2.clone())
+        .await
+        .expect("Failed to create node 2");
+
+    // Node 2 joining the share topic flushes the buffer, and node 2 stores the gossiped workbase
+    let received = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Some(received) = chain_handle2.get_workbase(workbase.workinfoid).await {
+                return received;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("Buffered workbase should be published once node 2 joins");
+    assert_eq!(received, workbase);
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_bootstrap_failure_does_not_redial_a_dial_peer_still_being_dialed() {
+    use p2poolv2::node::events::NodeEvent;
+
+    // Accepts the connection but never answers the handshake, so the dial from startup stays in flight
+    let _listener = std::net::TcpListener::bind("127.0.0.1:6948").unwrap();
+    let config = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6949".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6948".to_string()]);
+    let temp_dir = tempdir().unwrap();
+    let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+    let (node_handle, _stop_rx) = NodeHandle::new(config, chain_handle)
+        .await
+        .expect("Failed to create node");
+    let mut events = node_handle.subscribe_events().await.unwrap();
+
+    node_handle.bootstrap_kademlia().await.unwrap();
+
+    let dialed = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let NodeEvent::BootstrapFailed { dialed } = events.recv().await.unwrap().event {
+                return dialed;
+            }
+        }
+    })
+    .await
+    .expect("Bootstrap should fail with an empty routing table");
+    assert_eq!(dialed, 0);
+    assert_eq!(node_handle.get_metrics().await.unwrap().dial_attempts, 1);
+
+    node_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_idle_peer_is_evicted_while_active_peer_is_kept() {
+    use p2poolv2::node::messages::Message;
+    use rust_decimal::Decimal;
+
+    let config1 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6950".to_string())
+        .with_peer_idle_evict_secs(2);
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6951".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6950".to_string()]);
+    let config3 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6952".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6950".to_string()]);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let temp_dir3 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+    let chain_handle3 = ChainHandle::new(temp_dir3.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    let (node3_handle, _stop_rx3) = NodeHandle::new(config3, chain_handle3)
+        .await
+        .expect("Failed to create node 3");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(node1_handle.get_peers().await.unwrap().len(), 2);
+
+    // Node 3 keeps sending requests to node 1, node 2 stays silent
+    let node1_peer_id = node3_handle.get_peers().await.unwrap()[0];
+    for _ in 0..10 {
+        node3_handle
+            .send_to_peer(
+                node1_peer_id,
+                Message::ChainState {
+                    tip: None,
+                    work: Decimal::ZERO,
+                    height: None,
+                },
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    assert_eq!(node1_handle.get_peers().await.unwrap().len(), 1);
+    assert!(node2_handle.get_peers().await.unwrap().is_empty());
+    assert_eq!(node3_handle.get_peers().await.unwrap(), vec![node1_peer_id]);
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+    node3_handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_banned_peer_is_disconnected_and_refused_until_unbanned() {
+    let config1 = default_test_config().with_listen_address("/ip4/127.0.0.1/tcp/6953".to_string());
+    // Node 2 re-dials node 1 every time it finds itself without peers
+    let config2 = default_test_config()
+        .with_listen_address("/ip4/127.0.0.1/tcp/6954".to_string())
+        .with_dial_peers(vec!["/ip4/127.0.0.1/tcp/6953".to_string()])
+        .with_isolation_grace_period_secs(1);
+
+    let temp_dir1 = tempdir().unwrap();
+    let temp_dir2 = tempdir().unwrap();
+    let chain_handle1 = ChainHandle::new(temp_dir1.path().to_str().unwrap().to_string());
+    let chain_handle2 = ChainHandle::new(temp_dir2.path().to_str().unwrap().to_string());
+
+    let (node1_handle, _stop_rx1) = NodeHandle::new(config1, chain_handle1)
+        .await
+        .expect("Failed to create node 1");
+    let (node2_handle, _stop_rx2) = NodeHandle::new(config2, chain_handle2)
+        .await
+        .expect("Failed to create node 2");
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let peers = node1_handle.get_peers().await.unwrap();
+    assert_eq!(peers.len(), 1);
+    let node2_peer_id = peers[0];
+
+    assert!(node1_handle.ban_peer(node2_peer_id).await.unwrap());
+    assert!(!node1_handle.ban_peer(node2_peer_id).await.unwrap());
+    assert_eq!(
+        node1_handle.diagnostics().await.unwrap().banned_peers,
+        vec![node2_peer_id.to_string()]
+    );
+
+    // Node 2's re-dials are refused while it is banned
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert!(node1_handle.get_peers().await.unwrap().is_empty());
+    assert!(node2_handle.get_peers().await.unwrap().is_empty());
+
+    assert!(node1_handle.unban_peer(node2_peer_id).await.unwrap());
+    assert!(!node1_handle.unban_peer(node2_peer_id).await.unwrap());
+    assert!(node1_handle
+        .diagnostics()
+        .await
+        .unwrap()
+        .banned_peers
+        .is_empty());
+
+    node1_handle.shutdown().await.unwrap();
+    node2_handle.shutdown().await.unwrap();
+}
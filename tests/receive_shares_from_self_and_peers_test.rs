@@ -24,7 +24,8 @@ mod self_and_peer_messages_tests {
     use p2poolv2::shares::chain::actor::ChainHandle;
     use p2poolv2::shares::miner_message::CkPoolMessage;
     use p2poolv2::shares::ShareBlock;
-    use p2poolv2::utils::time_provider::{SystemTimeProvider, TestTimeProvider, TimeProvider};
+    use p2poolv2::utils::clock::SystemClock;
+    use p2poolv2::utils::time_provider::{TestTimeProvider, TimeProvider};
     use std::fs;
     use std::time::{Duration, SystemTime};
     use tempfile::tempdir;
@@ -193,7 +194,7 @@ mod self_and_peer_messages_tests {
         let peer_id = libp2p::PeerId::random();
         let (swarm_tx, mut _swarm_rx) = mpsc::channel(100);
         let workbase = simple_miner_workbase();
-        let time_provider = SystemTimeProvider {};
+        let time_provider = SystemClock;
 
         // sending two workbase messages quickly, the second one should be rate-limited.
         let result1 = handle_request(
@@ -240,4 +241,41 @@ mod self_and_peer_messages_tests {
             .await
             .expect("Failed to shutdown node");
     }
+
+    #[tokio::test]
+    async fn test_shares_on_a_chain_longer_than_max_gossip_lag_are_only_stale_when_far_below_tip() {
+        use p2poolv2::node::gossip_handler::is_stale_share;
+        use p2poolv2::shares::genesis::GENESIS_PUBLIC_KEY;
+
+        let max_gossip_lag = 10;
+        let temp_dir = tempdir().unwrap();
+        let chain_handle = ChainHandle::new(temp_dir.path().to_str().unwrap().to_string());
+        let child = |prev: &ShareBlock, nonce: u32| {
+            let mut share = prev.clone();
+            share.header.prev_share_blockhash = prev.cached_blockhash;
+            share.header.miner_share.nonce = format!("{:08x}", nonce);
+            share.compute_blockhash();
+            share
+        };
+
+        // A main chain of 15 shares, longer than max_gossip_lag
+        let mut chain = vec![ShareBlock::build_genesis_for_network(
+            GENESIS_PUBLIC_KEY.parse().unwrap(),
+            bitcoin::Network::Signet,
+        )];
+        for nonce in 1..15 {
+            chain.push(child(chain.last().unwrap(), nonce));
+        }
+        for share in &chain {
+            chain_handle.add_share(share.clone()).await.unwrap();
+        }
+        assert_eq!(chain_handle.get_tip_height().await, Some(14));
+
+        // Shares extending the tip, uncles and race losers near the tip are not stale
+        for prev in [&chain[14], &chain[13], &chain[4]] {
+            assert!(!is_stale_share(&child(prev, 100), &chain_handle, max_gossip_lag).await);
+        }
+        // A share building more than max_gossip_lag shares below the tip is stale
+        assert!(is_stale_share(&child(&chain[3], 100), &chain_handle, max_gossip_lag).await);
+    }
 }